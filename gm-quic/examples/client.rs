@@ -1,12 +1,12 @@
 use std::{fs::File, io::BufReader, net::SocketAddr, path::PathBuf, sync::Arc};
 
 use clap::Parser;
-use quic::QuicClient;
+use gm_quic::QuicClient;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 /// cargo run --example client -- \
 ///     --domain quit.test.net \
-///     --root quic/examples/keychain/root/rootCA-ECC.crt \
+///     --root gm-quic/examples/keychain/root/rootCA-ECC.crt \
 ///     --addr 127.0.0.1:4433
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -61,7 +61,8 @@ async fn run(args: Arguments) -> Result<(), Box<dyn std::error::Error>> {
         .without_cert()
         .with_keylog(args.keylog)
         .with_alpn([b"hq-29".as_ref()].iter().map(|s| s.to_vec()))
-        .build();
+        .build()
+        .expect("Failed to build the client");
 
     let quic_conn = client.connect(args.domain, args.addr).unwrap();
     let mut counter = 0;
@@ -81,7 +82,7 @@ async fn run(args: Arguments) -> Result<(), Box<dyn std::error::Error>> {
         }
 
         if content == "exit" || content == "quit" {
-            quic_conn.close("Client close the connection");
+            quic_conn.close(0, "Client close the connection");
             break;
         }
 
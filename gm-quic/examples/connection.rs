@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use quic::QuicClient;
+use gm_quic::QuicClient;
 use rustls::{client::WebPkiServerVerifier, pki_types::CertificateDer};
 
 #[tokio::main]
@@ -29,7 +29,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .prefer_versions([0x00000001u32])
     .with_webpki_verifier(verifier)
     .without_cert()
-    .build();
+    .build()?;
 
     let _conn = client
         .connect("localhost", "127.0.0.1:5000".parse().unwrap())
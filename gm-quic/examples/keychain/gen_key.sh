@@ -22,7 +22,7 @@ openssl x509 -req \
   -extfile openssl.cnf -extensions v3_req \
   -in quic-test-net.csr \
   -CA rootCA-ECC.crt -CAkey rootCA-ECC.key -CAcreateserial \
-  -out quic-test-net-ECC.crt -days 365 -sha384
+  -out quic-test-net-ECC.crt -days 2500 -sha384
 
 # view info in quic-test-net-ECC.crt
 openssl x509 -in quic-test-net-ECC.crt -text -noout
@@ -1,11 +1,11 @@
 use std::{net::SocketAddr, path::PathBuf};
 
 use clap::Parser;
-use quic::QuicServer;
+use gm_quic::QuicServer;
 
 // cargo run --example server -- \
-//      --cert quic/examples/keychain/quic.test.net/quic-test-net-ECC.crt \
-//      --key  quic/examples/keychain/quic.test.net/quic-test-net-ECC.key \
+//      --cert gm-quic/examples/keychain/quic.test.net/quic-test-net-ECC.crt \
+//      --key  gm-quic/examples/keychain/quic.test.net/quic-test-net-ECC.key \
 //      --bind 127.0.0.1:4433
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -44,7 +44,7 @@ async fn run(options: Opt) -> Result<(), Box<dyn std::error::Error>> {
         .with_supported_versions([0x00000001u32])
         .without_cert_verifier()
         .with_single_cert(options.cert, options.key)
-        .listen();
+        .listen()?;
 
     while let Ok((_conn, addr)) = server.accept().await {
         log::trace!("New connection from {}", addr);
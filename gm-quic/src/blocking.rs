@@ -0,0 +1,342 @@
+//! A minimal synchronous client, for scripts and command line tools that don't want to
+//! run an async runtime themselves.
+//!
+//! It's a thin wrapper over the async API: the client owns a small runtime, or enters an
+//! existing one through its [`Handle`], and blocks the calling thread on the async calls.
+//! The errors are the same as the async API, the typed errors like [`ConnectError`] and
+//! [`ReadTimedOut`] can be downcast from the returned [`io::Error`]s.
+//!
+//! ```no_run
+//! use std::io::{Read, Write};
+//!
+//! use gm_quic::{blocking::Client, QuicClient};
+//! # fn demo(config: QuicClient) -> std::io::Result<()> {
+//! let client = Client::connect("127.0.0.1:4433".parse().unwrap(), "localhost", config)?;
+//! let mut stream = client.open_bi_stream()?;
+//! stream.set_timeout(Some(std::time::Duration::from_secs(5)));
+//! stream.write_all(b"GET /README.md\r\n")?;
+//! stream.finish()?;
+//! let mut reply = Vec::new();
+//! stream.read_to_end(&mut reply)?;
+//! client.close(0, "done")?;
+//! # Ok(()) }
+//! ```
+//!
+//! [`ConnectError`]: crate::ConnectError
+//! [`ReadTimedOut`]: crate::ReadTimedOut
+use std::{
+    borrow::Cow,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use qrecovery::{recv::Reader, send::Writer};
+use quic::{QuicClient, QuicConnection};
+use qunreliable::{DatagramReader, DatagramWriter};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::{self, Handle},
+};
+
+use crate::FlushMode;
+
+/// The runtime the blocking calls are driven by, either owned by the client or entered
+/// through a handle.
+///
+/// The worker threads of a runtime don't survive a fork, the child process can't use
+/// the runtime of its parent, nor shut it down, the calls fail there instead of hanging.
+struct Runtime {
+    flavor: Option<Flavor>,
+    pid: u32,
+}
+
+enum Flavor {
+    Owned(runtime::Runtime),
+    Entered(Handle),
+}
+
+impl Runtime {
+    fn owned() -> io::Result<Self> {
+        // 连接的收包、确认、重传等后台任务，在两次阻塞调用之间也要继续运行，
+        // 而current_thread的运行时只在block_on期间才被驱动，所以用一个工作线程的运行时
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("gm-quic-blocking")
+            .enable_all()
+            .build()?;
+        Ok(Self::new(Flavor::Owned(runtime)))
+    }
+
+    fn entered(handle: Handle) -> Self {
+        Self::new(Flavor::Entered(handle))
+    }
+
+    fn new(flavor: Flavor) -> Self {
+        Self {
+            flavor: Some(flavor),
+            pid: std::process::id(),
+        }
+    }
+
+    fn handle(&self) -> io::Result<&Handle> {
+        if std::process::id() != self.pid {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the runtime of the client doesn't survive a fork, connect again in the child process",
+            ));
+        }
+        match self.flavor.as_ref().expect("only taken when dropped") {
+            Flavor::Owned(runtime) => Ok(runtime.handle()),
+            Flavor::Entered(handle) => Ok(handle),
+        }
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> io::Result<F::Output> {
+        let handle = self.handle()?;
+        // 在异步上下文中阻塞会使运行时panic，返回错误而不是panic
+        if Handle::try_current().is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the blocking client can't be used within an async runtime",
+            ));
+        }
+        Ok(handle.block_on(future))
+    }
+
+    /// Block on the future until it completes, or the timeout elapses, which is an error
+    /// of kind [`io::ErrorKind::TimedOut`].
+    fn block_on_timeout<T>(
+        &self,
+        timeout: Option<Duration>,
+        future: impl Future<Output = io::Result<T>>,
+    ) -> io::Result<T> {
+        self.block_on(async move {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, future)
+                    .await
+                    .map_err(|elapsed| io::Error::new(io::ErrorKind::TimedOut, elapsed))?,
+                None => future.await,
+            }
+        })?
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if std::process::id() != self.pid {
+            // 子进程里没有工作线程，关闭运行时会一直等下去，只好泄露
+            std::mem::forget(self.flavor.take());
+        }
+    }
+}
+
+/// A synchronous QUIC client connected to one server.
+///
+/// The connection is closed by [`Client::close`], the background tasks of the connection
+/// keep running until the client and all of its streams are dropped.
+pub struct Client {
+    connection: QuicConnection,
+    datagram_writer: DatagramWriter,
+    datagram_reader: Mutex<Option<DatagramReader>>,
+    timeout: Option<Duration>,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    /// Connect to the server at `addr` with the built `config`, on a small runtime owned
+    /// by the client.
+    ///
+    /// Return once the handshake made progress far enough to open streams, or fails
+    /// with the reason, see [`ConnectError`].
+    ///
+    /// [`ConnectError`]: crate::ConnectError
+    pub fn connect(
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+        config: QuicClient,
+    ) -> io::Result<Self> {
+        Self::connect_on(Runtime::owned()?, addr, server_name, config)
+    }
+
+    /// Same as [`Client::connect`], but the connection runs on the existing runtime of
+    /// the `handle`, which must be a multi-thread runtime, a current-thread runtime is
+    /// only driven by its own thread.
+    ///
+    /// The methods of the client must still be called outside of the runtime.
+    pub fn connect_with_handle(
+        handle: Handle,
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+        config: QuicClient,
+    ) -> io::Result<Self> {
+        Self::connect_on(Runtime::entered(handle), addr, server_name, config)
+    }
+
+    fn connect_on(
+        runtime: Runtime,
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+        config: QuicClient,
+    ) -> io::Result<Self> {
+        let connection = {
+            // 连接的后台任务要在这个运行时里启动
+            let _enter = runtime.handle()?.enter();
+            config.connect(server_name, addr)?
+        };
+        // 对方的传输参数到了，握手才算有了进展，数据报的大小上限也才确定
+        let datagram_writer = match runtime.block_on(connection.datagram_writer())? {
+            Ok(writer) => writer,
+            Err(error) => {
+                return Err(match connection.stats().connect_error {
+                    Some(connect_error) => io::Error::new(error.kind(), connect_error),
+                    None => error,
+                })
+            }
+        };
+        Ok(Self {
+            connection,
+            datagram_writer,
+            datagram_reader: Mutex::new(None),
+            timeout: None,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Set the timeout of opening the streams and receiving the datagrams, None means no
+    /// timeout, which is the default. The streams have their own timeouts, see
+    /// [`BlockingStream::set_timeout`].
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Open a bidirectional stream, blocking while the peer doesn't allow more streams.
+    pub fn open_bi_stream(&self) -> io::Result<BlockingStream> {
+        let (reader, writer) = self
+            .runtime
+            .block_on_timeout(self.timeout, self.connection.open_bi_stream())?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "stream id exhausted"))?;
+        Ok(BlockingStream {
+            reader: Some(reader),
+            writer: Some(writer),
+            timeout: None,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// Send a datagram, which must fit in the max_datagram_frame_size of the server.
+    pub fn send_datagram(&self, data: &[u8]) -> io::Result<()> {
+        self.datagram_writer.send(data)
+    }
+
+    /// Receive a datagram into `buf`, truncated if `buf` is too small, and return its size.
+    pub fn recv_datagram(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.datagram_reader.lock().unwrap();
+        if reader.is_none() {
+            *reader = Some(self.connection.datagrams()?.reader()?);
+        }
+        let reader = reader.as_mut().unwrap();
+        self.runtime
+            .block_on_timeout(self.timeout, reader.recv_into(buf))
+    }
+
+    /// Close the connection after the data of all streams, and the queued datagrams are
+    /// sent, waiting at most the timeout set by [`Client::set_timeout`].
+    ///
+    /// The connection is closed even if the timeout elapses, the error is returned then.
+    /// The `error_code` and the `reason` are of the application protocol, see
+    /// [`QuicConnection::close`](crate::QuicConnection).
+    pub fn close(self, error_code: u64, reason: impl Into<Cow<'static, str>>) -> io::Result<()> {
+        let flushed = self
+            .runtime
+            .block_on_timeout(self.timeout, self.connection.flushed(FlushMode::SentOnce));
+        self.connection.close(error_code, reason);
+        flushed
+    }
+}
+
+/// A bidirectional stream of the [`Client`], read and written synchronously.
+///
+/// The stream is reset if it's dropped before [`BlockingStream::finish`] succeeds, and
+/// the peer is told to stop sending if not all of its data has been read.
+pub struct BlockingStream {
+    reader: Option<Reader>,
+    writer: Option<Writer>,
+    timeout: Option<Duration>,
+    runtime: Arc<Runtime>,
+}
+
+impl BlockingStream {
+    /// Set the timeout of the subsequent reads and writes, None means no timeout, which is
+    /// the default.
+    ///
+    /// A read fails with [`ReadTimedOut`] if no new data becomes readable within the
+    /// timeout, see [`StreamReader::set_read_timeout`]. A write or flush fails with an
+    /// error of kind [`io::ErrorKind::TimedOut`] if it doesn't complete within the
+    /// timeout, the data already accepted by the stream is still sent.
+    ///
+    /// [`ReadTimedOut`]: crate::ReadTimedOut
+    /// [`StreamReader::set_read_timeout`]: crate::StreamReader::set_read_timeout
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        if let Some(reader) = self.reader.as_mut() {
+            reader.set_read_timeout(timeout);
+        }
+    }
+
+    /// Finish the sending side of the stream, blocking until the peer received all the
+    /// data, or the timeout elapses.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let timeout = self.timeout;
+        let runtime = self.runtime.clone();
+        let writer = self.writer_mut()?;
+        runtime.block_on_timeout(timeout, writer.shutdown())
+    }
+
+    fn writer_mut(&mut self) -> io::Result<&mut Writer> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stream is closed"))
+    }
+}
+
+impl io::Read for BlockingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = self
+            .reader
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stream is closed"))?;
+        // 读超时由流自己的计时器负责，错误中带有ReadTimedOut
+        self.runtime.block_on(reader.read(buf))?
+    }
+}
+
+impl io::Write for BlockingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let timeout = self.timeout;
+        let runtime = self.runtime.clone();
+        let writer = self.writer_mut()?;
+        runtime.block_on_timeout(timeout, writer.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let timeout = self.timeout;
+        let runtime = self.runtime.clone();
+        let writer = self.writer_mut()?;
+        runtime.block_on_timeout(timeout, writer.flush())
+    }
+}
+
+impl Drop for BlockingStream {
+    fn drop(&mut self) {
+        // 已经结束的流，取消与停止都不会有任何作用
+        if let Some(writer) = self.writer.take() {
+            writer.reset(0);
+        }
+        if let Some(reader) = self.reader.take() {
+            reader.stop(0);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! The public API of gm-quic.
+//!
+//! Applications should depend on this crate only. The types re-exported here are
+//! the stable surface, their names won't change across refactors of the internal
+//! crates. Anything not re-exported, including the internal crates themselves,
+//! is implementation detail and may change at any time.
+//!
+//! ```no_run
+//! use gm_quic::{QuicClient, StreamReader, StreamWriter};
+//! # async fn demo(client: QuicClient) -> std::io::Result<()> {
+//! let connection = client.connect("localhost", "127.0.0.1:4433".parse().unwrap())?;
+//! let (reader, writer): (StreamReader, StreamWriter) = connection
+//!     .open_bi_stream()
+//!     .await?
+//!     .expect("stream id exhausted");
+//! # reader.stop(0);
+//! # drop(writer);
+//! # Ok(()) }
+//! ```
+
+// 客户端与服务端，以及它们的构建器
+pub use quic::{
+    client::QuicClientBuilder,
+    server::{QuicServerBuilder, QuicServerSniBuilder},
+    QuicClient, QuicConnection, QuicServer,
+};
+
+// 启动前对证书、私钥与参数的校验
+pub use quic::validate::{ConfigDiagnostic, DiagnosticKind, Severity, Strictness, Validation};
+
+// 可靠的流，以及不可靠的数据报
+pub use qrecovery::{
+    recv::{
+        ReadTimedOut, Reader as StreamReader, StreamInspector, StreamReset,
+        DEFAULT_MAX_STREAM_WINDOW,
+    },
+    send::{
+        FlushMode, Priority as StreamPriority, SharedWriter as SharedStreamWriter, StreamStopped,
+        Writer as StreamWriter,
+    },
+    streams::{
+        classify::{ClassPolicy, StreamClass, StreamClassifier, StreamPreview},
+        policy::IncomingStreamPolicy,
+        scheduler::{Candidate as StreamCandidate, RoundRobin, StreamScheduler, DEFAULT_QUANTUM},
+    },
+};
+
+// 流的诊断快照，供运维工具列出连接上的流
+pub use qrecovery::{
+    recv::{RecvIntrospection, RecvState},
+    send::{SendIntrospection, SendState},
+    streams::data::StreamIntrospection,
+};
+
+// 流控疑似相互死锁的检测
+pub use qconnection::connection::stall::StallDetection;
+pub use qrecovery::streams::data::FlowControlStallSuspected;
+
+pub use qunreliable::{
+    DatagramHandle, DatagramOutcome, DatagramQueueCapacity, DatagramReader, DatagramSubscription,
+    DatagramWriter, QueueOverflow,
+};
+
+// 流ID，以及打开流的限速
+pub use qbase::streamid::{Dir as StreamDir, StreamId, StreamOpenRate};
+
+// 应用层协议常用的QUIC变长整数
+pub use qbase::varint::{read_varint, VarInt, WriteVarInt};
+
+// 传输参数
+pub use qbase::config::{
+    AddressDiscovery, ClientParameters, ClientParametersBuilder, CommonParameters, ParameterChange,
+    ParameterId, Parameters, ParametersChanged, RememberedField, RemoteParameters,
+    ServerParameters, ServerParametersBuilder,
+};
+
+// 确认策略
+pub use qcongestion::congestion::{
+    AckEagerness, AckFrequency, CongestionAlgorithm, LossDetectionConfig,
+};
+pub use qcongestion::pacing::{IdleRestart, PacingConfig};
+
+// 收包时处理帧的预算
+pub use qconnection::connection::scope::FrameBudget;
+
+// 握手时CRYPTO帧数据的上限
+pub use qrecovery::streams::crypto::{CryptoLimits, CryptoRecvStats};
+
+// 已发送数据包记录的上限，以及其占用的内存
+pub use qrecovery::reliable::sentpkt::{SentRecordsStats, DEFAULT_MAX_SENT_RECORDS};
+
+// 服务端连接数的限制，以及超出时的驱逐策略
+pub use qconnection::connection::activity::Activity;
+pub use quic::server::{
+    CandidateId, ConnectionLimits, EvictLongestIdle, EvictionCandidate, EvictionPolicy, RefuseNew,
+};
+
+// 非应用发起的流量的开销预算
+pub use qconnection::connection::overhead::{OverheadCounters, OverheadKind, OverheadStats};
+
+// 应用过载时的降级
+pub use qconnection::connection::pressure::{PressureLevel, PressureStats};
+
+// 连接的用途，如仅收发数据报
+pub use qconnection::connection::profile::ConnectionProfile;
+
+// 错误
+pub use qbase::error::{ConnectionError, Error, ErrorKind};
+pub use qconnection::error::{
+    ConnectError, MigrationError, StatelessReset, StreamLimitTimeout, StreamsDisabled,
+};
+
+// 统计
+pub use qbase::token::TokenStats;
+pub use qcongestion::congestion::PathMetrics;
+pub use qconnection::{
+    connection::ConnectionStats,
+    path::Pathway,
+    tls::{HandshakeStats, FIRST_FLIGHT_BUDGET},
+};
+
+// 按网段汇总的连接统计，不保存完整的地址
+pub use qconnection::connection::telemetry::{
+    ConnectionDigest, NetworkPrefix, NetworkReport, NetworkStats, NetworkTelemetry, PrefixLengths,
+    RttHistogram, MAX_IPV4_PREFIX_LEN, MAX_IPV6_PREFIX_LEN,
+};
+
+// 连接的健康状况，供负载均衡器的健康检查使用
+pub use qconnection::connection::health::{ConnectionHealth, ConnectionState};
+
+// 被静默丢弃的包，供排查连接故障
+pub use qconnection::drops::{DropEvent, DropReason, DropRecorder, DropStats, DROPS};
+
+// 派生无状态重置令牌的密钥，重启的服务端沿用之，即可重置旧的连接
+pub use qbase::token::StatelessResetKey;
+
+// 地址验证令牌，服务端的签发与验证，以及客户端的保存
+pub use qbase::{
+    cid::ConnectionId,
+    token::{
+        AeadTokenProvider, TokenAction, TokenError, TokenFallback, TokenKind, TokenOutcome,
+        TokenPolicy, TokenProvider, TokenSink, TokenStore,
+    },
+};
+
+// 版本协商，以及防止版本降级的version_information传输参数
+pub use qbase::version::{is_reserved, VersionInformation, Versions, QUIC_V1, SUPPORTED_VERSIONS};
+
+// 同步的客户端，供不想运行异步运行时的脚本与命令行工具使用
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+/// The internal crates and types are not reachable through the facade, so that
+/// depending on them by accident fails to compile.
+///
+/// ```compile_fail
+/// use gm_quic::qbase;
+/// ```
+///
+/// ```compile_fail
+/// use gm_quic::server::RawQuicServer;
+/// ```
+///
+/// ```compile_fail
+/// use gm_quic::ArcConnection;
+/// ```
+///
+/// ```compile_fail
+/// use gm_quic::DataStreams;
+/// ```
+///
+/// ```compile_fail
+/// use gm_quic::get_usc_or_create;
+/// ```
+#[cfg(doctest)]
+pub struct InternalsAreUnreachable;
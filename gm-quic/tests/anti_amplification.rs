@@ -0,0 +1,161 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use gm_quic::{DropReason, QuicServer, DROPS};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+};
+
+mod common;
+
+#[derive(Default)]
+struct Relay {
+    client: Option<SocketAddr>,
+    // 转发给服务端的字节数，以及服务端发回的字节数
+    forwarded: usize,
+    returned: usize,
+    // 服务端发回第一个数据报的时间
+    first_returned: Option<SystemTime>,
+    // 放行之前，扣下客户端第一个数据报之后的所有数据报
+    held: Vec<Vec<u8>>,
+    released: bool,
+}
+
+/// 客户端与服务端之间的中继，只放行客户端的第一个数据报，直到[`release`]
+async fn relay(server_addr: SocketAddr) -> (SocketAddr, Arc<UdpSocket>, Arc<Mutex<Relay>>) {
+    let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    back.connect(server_addr).await.unwrap();
+    let state = Arc::new(Mutex::new(Relay::default()));
+
+    tokio::spawn({
+        let (front, back, state) = (front.clone(), back.clone(), state.clone());
+        async move {
+            let mut buf = [0u8; 1500];
+            while let Ok((n, client)) = front.recv_from(&mut buf).await {
+                let forward = {
+                    let mut state = state.lock().unwrap();
+                    state.client = Some(client);
+                    if state.released || state.forwarded == 0 {
+                        state.forwarded += n;
+                        true
+                    } else {
+                        state.held.push(buf[..n].to_vec());
+                        false
+                    }
+                };
+                if forward && back.send(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    tokio::spawn({
+        let (front, back, state) = (front.clone(), back.clone(), state.clone());
+        async move {
+            let mut buf = [0u8; 1500];
+            while let Ok(n) = back.recv(&mut buf).await {
+                let client = {
+                    let mut state = state.lock().unwrap();
+                    state.returned += n;
+                    state.first_returned.get_or_insert_with(SystemTime::now);
+                    state.client
+                };
+                if let Some(client) = client {
+                    _ = front.send_to(&buf[..n], client).await;
+                }
+            }
+        }
+    });
+    (front.local_addr().unwrap(), back, state)
+}
+
+async fn release(back: &UdpSocket, state: &Mutex<Relay>) {
+    let held = {
+        let mut state = state.lock().unwrap();
+        state.released = true;
+        state.forwarded += state.held.iter().map(Vec::len).sum::<usize>();
+        std::mem::take(&mut state.held)
+    };
+    for datagram in held {
+        back.send(&datagram).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn server_blocked_until_second_flight() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    let (accepted_tx, mut accepted) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                _ = accepted_tx.send(conn.clone());
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let (relay_addr, back, state) = relay(server_addr).await;
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, relay_addr).unwrap();
+
+    // 服务端发完3倍于客户端第一个数据报的字节后，因抗放大限制而停止发送
+    let source = back.local_addr().unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let since = state.lock().unwrap().first_returned;
+            let blocked = since.is_some_and(|since| {
+                DROPS.recent().iter().any(|event| {
+                    event.reason == DropReason::AntiAmplification
+                        && event.source == Some(source)
+                        && event.time >= since
+                })
+            });
+            if blocked {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let first_flight = state.lock().unwrap().forwarded;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(state.lock().unwrap().returned <= 3 * first_flight);
+
+    // 客户端的第二轮数据报到达后，服务端恢复发送，完成握手
+    release(&back, &state).await;
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+    assert!(state.lock().unwrap().returned > 3 * first_flight);
+
+    // 被抗放大限制挡住的发送，也计入了服务端连接自己的统计
+    let accepted = accepted.recv().await.unwrap();
+    let dropped = accepted.stats().dropped;
+    assert!(dropped.get(DropReason::AntiAmplification) >= 1);
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "blocking")]
+
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use gm_quic::{blocking::Client, QuicServer, ReadTimedOut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+/// Run the async server on a runtime of its own thread, it echoes each bidirectional
+/// stream and each datagram, and leaves the stream opened for "hang" unanswered.
+fn spawn_echo_server(addr: SocketAddr) {
+    let (ready_tx, ready_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let (cert, key) = common::server_cert();
+            let server = QuicServer::bind([addr], true)
+                .with_supported_versions([0x00000001u32])
+                .without_cert_verifier()
+                .with_single_cert(cert, key)
+                .listen()
+                .unwrap();
+            ready_tx.send(()).unwrap();
+
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn({
+                    let conn = conn.clone();
+                    async move {
+                        let mut reader = conn.datagrams()?.reader()?;
+                        let writer = conn.datagram_writer().await?;
+                        let mut buf = [0u8; 1200];
+                        while let Ok(n) = reader.recv_into(&mut buf).await {
+                            writer.send(&buf[..n])?;
+                        }
+                        std::io::Result::Ok(())
+                    }
+                });
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::spawn(async move {
+                            let mut request = Vec::new();
+                            reader.read_to_end(&mut request).await?;
+                            if request != b"hang" {
+                                writer.write_all(&request).await?;
+                                writer.shutdown().await?;
+                            } else {
+                                // 不回应，让客户端的读超时
+                                tokio::time::sleep(Duration::from_secs(10)).await;
+                                writer.reset(0);
+                            }
+                            std::io::Result::Ok(())
+                        });
+                    }
+                });
+            }
+        });
+    });
+    ready_rx.recv().unwrap();
+}
+
+// 普通的测试函数，没有tokio运行时，客户端自己拥有运行时
+#[test]
+fn blocking_client_against_async_server() {
+    let addr = common::unused_addr();
+    spawn_echo_server(addr);
+
+    let mut client = Client::connect(addr, common::SERVER_NAME, common::client()).unwrap();
+    client.set_timeout(Some(Duration::from_secs(5)));
+
+    let mut stream = client.open_bi_stream().unwrap();
+    stream.set_timeout(Some(Duration::from_secs(5)));
+    stream.write_all(b"hello, blocking world").unwrap();
+    stream.finish().unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply, b"hello, blocking world");
+
+    client.send_datagram(b"ping").unwrap();
+    let mut buf = [0u8; 64];
+    let n = client.recv_datagram(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"ping");
+
+    // 读超时由流自己的计时器给出，错误里带着ReadTimedOut
+    let mut stream = client.open_bi_stream().unwrap();
+    stream.set_timeout(Some(Duration::from_millis(200)));
+    stream.write_all(b"hang").unwrap();
+    stream.finish().unwrap();
+    let error = stream.read(&mut buf).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert!(error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<ReadTimedOut>()));
+    drop(stream);
+
+    client.close(0, "done").unwrap();
+}
+
+#[test]
+fn blocking_client_refuses_async_context() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let handle = runtime.handle().clone();
+    let result = runtime.block_on(async move {
+        Client::connect_with_handle(
+            handle,
+            common::unused_addr(),
+            common::SERVER_NAME,
+            common::client(),
+        )
+    });
+    let error = result.err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+}
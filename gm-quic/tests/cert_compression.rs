@@ -0,0 +1,72 @@
+#![cfg(feature = "cert-compression")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gm_quic::QuicServer;
+use rustls::{
+    compress::{CertDecompressor, DecompressionFailed, BROTLI_DECOMPRESSOR},
+    CertificateCompressionAlgorithm,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 数着解压了几次证书链的brotli
+#[derive(Debug)]
+struct Counting(AtomicUsize);
+
+impl CertDecompressor for Counting {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        BROTLI_DECOMPRESSOR.decompress(input, output)
+    }
+
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        BROTLI_DECOMPRESSOR.algorithm()
+    }
+}
+
+static DECOMPRESSED: Counting = Counting(AtomicUsize::new(0));
+
+#[tokio::test]
+async fn certificate_chain_compressed() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .enable_cert_compression(true)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    tokio::io::copy(&mut reader, &mut writer).await?;
+                    writer.shutdown().await?;
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client_builder()
+        .with_cert_decompressors([&DECOMPRESSED as &dyn CertDecompressor])
+        .build()
+        .unwrap();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+
+    // 服务端发来的是压缩过的证书链，由客户端解压之后才完成了握手
+    assert_eq!(DECOMPRESSED.0.load(Ordering::Relaxed), 1);
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,53 @@
+
+
+use gm_quic::{ConnectionError, QuicServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn close_with_app_error_code() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    let accepted = tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (conn, _addr) = server.accept().await.unwrap();
+            let (mut reader, _writer) = conn.accept_bi_stream().await.unwrap();
+            let mut request = [0u8; 5];
+            reader.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"hello");
+            // 对方关闭了连接，尚未读完的流随之出错，带着对方的应用层错误码
+            let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+            let error = error
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<ConnectionError>())
+                .cloned()
+                .unwrap();
+            (error, conn.closed().await)
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (_reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.flush().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    conn.close(42, "bye");
+
+    let expected = ConnectionError::Application {
+        code: 42,
+        reason: "bye".into(),
+    };
+    let (stream_error, server_closed) = accepted.await.unwrap();
+    assert_eq!(stream_error, expected);
+    assert_eq!(server_closed, expected);
+    assert_eq!(conn.closed().await, expected);
+}
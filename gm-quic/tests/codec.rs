@@ -0,0 +1,58 @@
+
+
+use futures::{SinkExt, StreamExt};
+use gm_quic::QuicServer;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+
+mod common;
+
+// 流的读写端直接套上tokio_util的编解码器，逐行对话
+#[tokio::test]
+async fn lines_codec_over_bi_stream() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端把每一行转成大写回复，读到流的末尾后也结束自己的发送
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let (reader, writer) = conn.accept_bi_stream().await?;
+                    let mut lines = FramedRead::new(reader, LinesCodec::new());
+                    let mut replies = FramedWrite::new(writer, LinesCodec::new());
+                    while let Some(line) = lines.next().await {
+                        let line = line.map_err(std::io::Error::other)?;
+                        replies
+                            .send(line.to_uppercase())
+                            .await
+                            .map_err(std::io::Error::other)?;
+                    }
+                    replies.close().await.map_err(std::io::Error::other)
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (reader, writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    let mut lines = FramedRead::new(reader, LinesCodec::new());
+    let mut requests = FramedWrite::new(writer, LinesCodec::new());
+
+    for request in ["hello", "quic", "lines"] {
+        requests.send(request).await.unwrap();
+        let reply = lines.next().await.unwrap().unwrap();
+        assert_eq!(reply, request.to_uppercase());
+    }
+    // 关闭写端即发送FIN，对方读到末尾后关闭它的写端，这边随之读到EOF
+    requests.close().await.unwrap();
+    assert!(lines.next().await.is_none());
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,133 @@
+//! 集成测试共用的证书与地址。
+//!
+//! 证书在测试时生成，不依赖examples里会过期的证书；端口由系统挑选，
+//! 测试之间不会因为写死的端口而冲突。
+#![allow(dead_code)]
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use gm_quic::{QuicClient, QuicClientBuilder};
+use rcgen::{CertificateParams, DnType, IsCa, KeyPair};
+use rustls::pki_types::CertificateDer;
+
+/// 测试证书签发给的服务器名
+pub const SERVER_NAME: &str = "quic.test.net";
+
+/// 一个由系统挑选的、当前空闲的本地地址。
+///
+/// 服务端不能直接绑定`127.0.0.1:0`：套接字按请求的地址复用，
+/// 同一进程里绑定`127.0.0.1:0`的客户端会与服务端共用一个套接字。
+/// 所以先让系统挑一个端口，释放之后再交给服务端绑定。
+pub fn unused_addr() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0")
+        .and_then(|socket| socket.local_addr())
+        .unwrap()
+}
+
+/// 测试时生成的CA，及由它签发的证书所在的目录，目录随之删除
+pub struct Keychain {
+    pub dir: PathBuf,
+    pub ca: rcgen::Certificate,
+    ca_key: KeyPair,
+}
+
+impl Keychain {
+    pub fn new(name: &str) -> Self {
+        let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("gm-quic-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test ca");
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        Self { dir, ca, ca_key }
+    }
+
+    pub fn write(&self, file: &str, pem: &str) -> PathBuf {
+        let path = self.dir.join(file);
+        std::fs::write(&path, pem).unwrap();
+        path
+    }
+
+    /// 由CA签发给[`SERVER_NAME`]的叶子证书，返回证书链文件和私钥文件
+    pub fn leaf(
+        &self,
+        name: &str,
+        set: impl FnOnce(&mut CertificateParams),
+        chain: impl FnOnce(String, String) -> String,
+    ) -> (PathBuf, PathBuf) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![SERVER_NAME.to_owned()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, SERVER_NAME);
+        set(&mut params);
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+        (
+            self.write(&format!("{name}.crt"), &chain(cert.pem(), self.ca.pem())),
+            self.write(&format!("{name}.key"), &key.serialize_pem()),
+        )
+    }
+
+    pub fn valid(&self, name: &str) -> (PathBuf, PathBuf) {
+        self.leaf(name, |_| {}, |leaf, ca| leaf + &ca)
+    }
+}
+
+impl Drop for Keychain {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+struct Shared {
+    cert: PathBuf,
+    key: PathBuf,
+    ca: CertificateDer<'static>,
+}
+
+// 同一进程里的测试共用一份证书；证书文件要在整个进程里可用，所以不删除目录
+fn shared() -> &'static Shared {
+    static SHARED: OnceLock<Shared> = OnceLock::new();
+    SHARED.get_or_init(|| {
+        let keychain = std::mem::ManuallyDrop::new(Keychain::new("tests"));
+        let (cert, key) = keychain.valid("server");
+        Shared {
+            cert,
+            key,
+            ca: keychain.ca.der().clone(),
+        }
+    })
+}
+
+/// 服务端的证书链文件和私钥文件
+pub fn server_cert() -> (&'static Path, &'static Path) {
+    let shared = shared();
+    (&shared.cert, &shared.key)
+}
+
+/// 信任测试CA的根证书库
+pub fn roots() -> Arc<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(shared().ca.clone()).unwrap();
+    Arc::new(roots)
+}
+
+/// 信任测试CA、只使用QUIC v1的客户端构建器，留给测试再做调整
+pub fn client_builder() -> QuicClientBuilder<rustls::ClientConfig> {
+    QuicClient::bind(["127.0.0.1:0".parse().unwrap()])
+        .prefer_versions([0x00000001u32])
+        .with_root_certificates(roots())
+        .without_cert()
+}
+
+pub fn client() -> QuicClient {
+    client_builder().build().unwrap()
+}
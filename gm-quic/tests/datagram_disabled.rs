@@ -0,0 +1,48 @@
+use std::io;
+
+use gm_quic::{QuicServer, ServerParameters, VarInt};
+use tokio::sync::oneshot;
+
+mod common;
+
+#[tokio::test]
+async fn server_without_datagrams() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let mut params = ServerParameters::default();
+    params.set_max_datagram_frame_size(VarInt::from_u32(0));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_parameters(params)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    let (writer_tx, writer_rx) = oneshot::channel();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (conn, _addr) = server.accept().await?;
+            // 客户端支持数据报，服务端照常发送
+            let writer = conn.datagram_writer().await?;
+            writer.send(b"hello")?;
+            _ = writer_tx.send(());
+            conn.accept_bi_stream().await?;
+            io::Result::Ok(())
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let mut reader = conn.datagrams().unwrap().reader().unwrap();
+    // 等待对方的传输参数，其中的0表示不支持数据报
+    let error = conn.datagram_writer().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+
+    writer_rx.await.unwrap();
+    let mut buf = [0u8; 1200];
+    let n = reader.recv_into(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    conn.close(0, "done");
+}
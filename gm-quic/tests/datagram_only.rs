@@ -0,0 +1,72 @@
+use std::io;
+
+use gm_quic::{ConnectionProfile, QuicClient, QuicServer, StreamsDisabled};
+
+mod common;
+
+fn client_config(profile: ConnectionProfile) -> QuicClient {
+    common::client_builder()
+        .with_connection_profile(profile)
+        .build()
+        .unwrap()
+}
+
+fn is_disabled(error: io::Error) -> bool {
+    error.kind() == io::ErrorKind::Unsupported
+        && error
+            .get_ref()
+            .is_some_and(|error| error.downcast_ref::<StreamsDisabled>().is_some())
+}
+
+#[tokio::test]
+async fn datagrams_without_streams() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_connection_profile(ConnectionProfile::DatagramOnly)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    // 服务端同样没有流
+                    assert!(is_disabled(conn.accept_bi_stream().await.unwrap_err()));
+                    let mut reader = conn.datagrams()?.reader()?;
+                    let writer = conn.datagram_writer().await?;
+                    let mut buf = [0u8; 1200];
+                    while let Ok(n) = reader.recv_into(&mut buf).await {
+                        writer.send(&buf[..n])?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = client_config(ConnectionProfile::DatagramOnly);
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let mut reader = conn.datagrams().unwrap().reader().unwrap();
+    let writer = conn.datagram_writer().await.unwrap();
+    writer.send(b"ping").unwrap();
+    let mut buf = [0u8; 1200];
+    let n = reader.recv_into(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+
+    assert!(is_disabled(conn.open_bi_stream().await.unwrap_err()));
+    assert!(is_disabled(conn.try_open_uni_stream().unwrap_err()));
+    assert!(is_disabled(conn.accept_uni_stream().await.unwrap_err()));
+
+    // 缺省的连接要为流分配状态，占用更多
+    let full = client_config(ConnectionProfile::Full)
+        .connect(common::SERVER_NAME, server_addr)
+        .unwrap();
+    assert!(conn.stats().footprint < full.stats().footprint);
+
+    full.close(0, "done");
+    conn.close(0, "done");
+}
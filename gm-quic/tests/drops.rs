@@ -0,0 +1,220 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use gm_quic::{
+    ConnectionLimits, DropReason, QuicConnection, QuicServer, TokenFallback, TokenPolicy, DROPS,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+};
+
+mod common;
+
+#[derive(Default)]
+struct Relay {
+    client: Option<SocketAddr>,
+    // 扣下服务端发来的下一个较大的1-RTT数据报，不转发给客户端
+    hold: bool,
+    held: Option<Vec<u8>>,
+}
+
+/// 客户端与服务端之间的中继，返回面向客户端的套接字，以及面向服务端的地址
+async fn relay(server_addr: SocketAddr) -> (Arc<UdpSocket>, SocketAddr, Arc<Mutex<Relay>>) {
+    let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    back.connect(server_addr).await.unwrap();
+    let state = Arc::new(Mutex::new(Relay::default()));
+
+    tokio::spawn({
+        let (front, back, state) = (front.clone(), back.clone(), state.clone());
+        async move {
+            let mut buf = [0u8; 1500];
+            while let Ok((n, client)) = front.recv_from(&mut buf).await {
+                state.lock().unwrap().client = Some(client);
+                if back.send(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    tokio::spawn({
+        let (front, back, state) = (front.clone(), back.clone(), state.clone());
+        async move {
+            let mut buf = [0u8; 1500];
+            while let Ok(n) = back.recv(&mut buf).await {
+                let client = {
+                    let mut state = state.lock().unwrap();
+                    // 足够长的短包头，篡改其最后一个字节不会影响头部保护的采样
+                    if state.hold && buf[0] & 0x80 == 0 && n > 64 {
+                        state.hold = false;
+                        state.held = Some(buf[..n].to_vec());
+                        continue;
+                    }
+                    state.client
+                };
+                if let Some(client) = client {
+                    _ = front.send_to(&buf[..n], client).await;
+                }
+            }
+        }
+    });
+    let back_addr = back.local_addr().unwrap();
+    (front, back_addr, state)
+}
+
+async fn until(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the condition is met in time");
+}
+
+// 环形缓冲区中是否有since之后、来自source的该原因的丢包
+fn recorded(reason: DropReason, source: SocketAddr, since: SystemTime) -> bool {
+    DROPS
+        .recent()
+        .iter()
+        .any(|event| event.reason == reason && event.source == Some(source) && event.time >= since)
+}
+
+async fn echo(conn: &QuicConnection, request: &[u8]) {
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(request).await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, request);
+}
+
+fn echo_server(server: QuicServer) {
+    tokio::spawn(async move {
+        while let Ok((conn, _addr)) = server.accept().await {
+            tokio::spawn(async move {
+                while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                    tokio::io::copy(&mut reader, &mut writer).await?;
+                    writer.shutdown().await?;
+                }
+                io::Result::Ok(())
+            });
+        }
+    });
+}
+
+// 全局只有一个server，所有场景都在这一个测试里，每种丢包都由真正丢弃它的地方记录
+#[tokio::test]
+async fn every_drop_recorded_where_it_happens() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .with_connection_limits(ConnectionLimits {
+            max_connections_per_addr: Some(1),
+            ..ConnectionLimits::default()
+        })
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    echo_server(server);
+
+    // 收包循环：解析不了的包头、不支持的版本、找不到连接的短包头
+    let raw = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let source = raw.local_addr().unwrap();
+    let datagrams = [
+        (DropReason::MalformedHeader, vec![0xc0, 0, 0, 0, 1, 8]),
+        (
+            DropReason::UnsupportedVersion,
+            [&[0xc0, 0x0a, 0x1a, 0x2a, 0x3a][..], &[0; 40]].concat(),
+        ),
+        (
+            DropReason::UnknownDcid,
+            [&[0x40][..], &[0x11; 8], &[0; 40]].concat(),
+        ),
+    ];
+    for (reason, datagram) in datagrams {
+        let before = DROPS.stats().get(reason);
+        raw.send_to(&datagram, server_addr).await.unwrap();
+        until(|| {
+            DROPS.recent().iter().any(|event| {
+                event.reason == reason
+                    && event.source == Some(source)
+                    && !event.header.is_empty()
+                    && datagram.starts_with(&event.header)
+            })
+        })
+        .await;
+        assert!(DROPS.stats().get(reason) > before);
+    }
+
+    let (front, back, state) = relay(server_addr).await;
+    let front_addr = front.local_addr().unwrap();
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, front_addr).unwrap();
+    echo(&conn, b"hello").await;
+
+    // 连接数的限制：同一地址的第二个连接，其Initial包被服务端丢弃
+    let since = SystemTime::now();
+    let before = DROPS.stats().get(DropReason::ConnectionLimit);
+    let refused = client.connect(common::SERVER_NAME, front_addr).unwrap();
+    until(|| recorded(DropReason::ConnectionLimit, back, since)).await;
+    assert!(DROPS.stats().get(DropReason::ConnectionLimit) > before);
+    refused.close(0, "done");
+
+    // 扣下服务端回显的数据报，由中继向客户端注入篡改过的、原样的、重复的
+    state.lock().unwrap().hold = true;
+    echo(&conn, &[0x5a; 256]).await;
+    let held = state.lock().unwrap().held.take().unwrap();
+    let client_addr = state.lock().unwrap().client.unwrap();
+    let dropped = conn.stats().dropped;
+
+    // 解密：篡改了认证标签的包无法解密
+    let since = SystemTime::now();
+    let mut tampered = held.clone();
+    *tampered.last_mut().unwrap() ^= 0xff;
+    front.send_to(&tampered, client_addr).await.unwrap();
+    let undecryptable = dropped.get(DropReason::Undecryptable);
+    until(|| conn.stats().dropped.get(DropReason::Undecryptable) > undecryptable).await;
+    assert!(recorded(DropReason::Undecryptable, front_addr, since));
+
+    // 解密：原样的包被接收，再来一次则是重复的包号
+    let since = SystemTime::now();
+    front.send_to(&held, client_addr).await.unwrap();
+    front.send_to(&held, client_addr).await.unwrap();
+    let duplicate = dropped.get(DropReason::DuplicatePn);
+    until(|| conn.stats().dropped.get(DropReason::DuplicatePn) > duplicate).await;
+    assert!(recorded(DropReason::DuplicatePn, front_addr, since));
+
+    // 关闭状态：关闭之后收到的包只用来回应CONNECTION_CLOSE
+    let since = SystemTime::now();
+    conn.close(0, "done");
+    front.send_to(&held, client_addr).await.unwrap();
+    until(|| conn.stats().dropped.get(DropReason::Closing) > 0).await;
+    assert!(recorded(DropReason::Closing, front_addr, since));
+
+    // 令牌策略：不带令牌的Initial包被换上的服务端丢弃
+    let server_addr = common::unused_addr();
+    let _server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .with_token_policy(TokenPolicy::default().on_empty(TokenFallback::Discard))
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    let (front, back, _state) = relay(server_addr).await;
+    let since = SystemTime::now();
+    let before = DROPS.stats().get(DropReason::InvalidToken);
+    let discarded = client
+        .connect(common::SERVER_NAME, front.local_addr().unwrap())
+        .unwrap();
+    until(|| recorded(DropReason::InvalidToken, back, since)).await;
+    assert!(DROPS.stats().get(DropReason::InvalidToken) > before);
+    discarded.close(0, "done");
+}
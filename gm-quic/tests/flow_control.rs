@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use gm_quic::QuicServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 默认的initial_max_data为64KB，传输其数倍的数据，全靠MAX_DATA帧续上连接级的额度
+#[tokio::test]
+async fn transfer_beyond_initial_max_data() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端原样回显
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    conn.set_max_data_threshold(16 * 1024);
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    tokio::io::copy(&mut reader, &mut writer).await?;
+                    writer.shutdown().await?;
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+
+    let data = (0..300_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let echo = tokio::time::timeout(Duration::from_secs(10), async {
+        let write = async {
+            writer.write_all(&data).await.unwrap();
+            writer.shutdown().await.unwrap();
+        };
+        let mut echo = Vec::new();
+        let read = reader.read_to_end(&mut echo);
+        let (_, read) = tokio::join!(write, read);
+        read.unwrap();
+        echo
+    })
+    .await
+    .expect("stalled by the connection-level flow control");
+    assert_eq!(echo, data);
+
+    conn.close(0, "done");
+}
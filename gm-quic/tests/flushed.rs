@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use gm_quic::{FlushMode, QuicServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 多个任务同时等待连接发完数据，都要等到
+#[tokio::test]
+async fn concurrent_flushed() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, _writer) = conn.accept_bi_stream().await?;
+                    reader.read_to_end(&mut Vec::new()).await?;
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (_reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    let data = vec![0u8; 256 * 1024];
+    writer.write_all(&data).await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    let waiters = [FlushMode::SentOnce, FlushMode::FullyAcked].map(|mode| {
+        let conn = conn.clone();
+        tokio::spawn(async move { conn.flushed(mode).await })
+    });
+    for waiter in waiters {
+        tokio::time::timeout(Duration::from_secs(10), waiter)
+            .await
+            .expect("every waiter is woken up")
+            .unwrap()
+            .unwrap();
+    }
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,130 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use gm_quic::{ConnectionState, QuicServer};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+};
+
+mod common;
+
+// 在客户端与服务端之间转发数据报，可以随时黑洞掉两个方向的流量
+async fn relay(server_addr: SocketAddr) -> (SocketAddr, Arc<AtomicBool>) {
+    let downstream = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let upstream = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    upstream.connect(server_addr).await.unwrap();
+    let relay_addr = downstream.local_addr().unwrap();
+    let blackholed = Arc::new(AtomicBool::new(false));
+    let client_addr = Arc::new(Mutex::new(None));
+
+    tokio::spawn({
+        let (downstream, upstream) = (downstream.clone(), upstream.clone());
+        let (blackholed, client_addr) = (blackholed.clone(), client_addr.clone());
+        async move {
+            let mut buf = [0u8; 65536];
+            while let Ok((n, from)) = downstream.recv_from(&mut buf).await {
+                *client_addr.lock().unwrap() = Some(from);
+                if !blackholed.load(Ordering::Relaxed) {
+                    _ = upstream.send(&buf[..n]).await;
+                }
+            }
+        }
+    });
+    tokio::spawn({
+        let blackholed = blackholed.clone();
+        async move {
+            let mut buf = [0u8; 65536];
+            while let Ok(n) = upstream.recv(&mut buf).await {
+                let client_addr = *client_addr.lock().unwrap();
+                if let Some(client_addr) = client_addr {
+                    if !blackholed.load(Ordering::Relaxed) {
+                        _ = downstream.send_to(&buf[..n], client_addr).await;
+                    }
+                }
+            }
+        }
+    });
+    (relay_addr, blackholed)
+}
+
+#[tokio::test]
+async fn liveness_of_idle_and_blackholed_connection() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        let mut request = Vec::new();
+                        reader.read_to_end(&mut request).await?;
+                        writer.write_all(&request).await?;
+                        writer.shutdown().await?;
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let (relay_addr, blackholed) = relay(server_addr).await;
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, relay_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"ping").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"ping");
+
+    let health = conn.health();
+    assert_eq!(health.state, ConnectionState::Established);
+    assert_eq!(health.paths_validated, 1);
+    assert!(health.last_tx_elapsed.is_some());
+    // 刚刚收到过包，不必再发PING
+    let rtt = conn.verify_liveness(Duration::from_secs(10)).await.unwrap();
+    assert!(rtt < Duration::from_secs(1));
+
+    // 空闲的连接收包已不新鲜，验证时发PING，对端回应
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let health = conn.health();
+    assert!(health.last_rx_elapsed.unwrap() >= Duration::from_millis(200));
+    let rtt = conn
+        .verify_liveness(Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert!(rtt < Duration::from_secs(1));
+    assert!(conn.health().last_rx_elapsed.unwrap() < Duration::from_millis(100));
+    assert_eq!(conn.health().consecutive_pto_count, 0);
+
+    // 黑洞之后，验证在期限内失败，PTO的次数持续攀升
+    blackholed.store(true, Ordering::Relaxed);
+    let error = tokio::time::timeout(Duration::from_secs(5), conn.verify_liveness(Duration::ZERO))
+        .await
+        .expect("verify_liveness is bounded by the probe timeouts")
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    let pto_count = conn.health().consecutive_pto_count;
+    assert!(pto_count >= 1);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let health = conn.health();
+    assert!(health.consecutive_pto_count > pto_count);
+    assert!(health.last_rx_elapsed.unwrap() >= Duration::from_secs(1));
+
+    conn.close(0, "done");
+}
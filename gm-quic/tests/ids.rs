@@ -0,0 +1,63 @@
+
+
+use gm_quic::QuicServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 两端看到的流ID与连接的odcid一致，连接ID轮换之后odcid也不变
+#[tokio::test]
+async fn ids_of_connection_and_stream() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端把它看到的连接id和流id写回去
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    reader.read_to_end(&mut Vec::new()).await?;
+                    let ids = format!(
+                        "{} {} {} {}",
+                        conn.id(),
+                        reader.conn_id(),
+                        writer.conn_id(),
+                        reader.stream_id()
+                    );
+                    writer.write_all(ids.as_bytes()).await?;
+                    writer.shutdown().await?;
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let odcid = conn.odcid();
+    assert_eq!(conn.id(), odcid);
+
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    assert_eq!((reader.conn_id(), writer.conn_id()), (odcid, odcid));
+    writer.shutdown().await.unwrap();
+    let mut ids = String::new();
+    reader.read_to_string(&mut ids).await.unwrap();
+    let sid = writer.stream_id();
+    assert_eq!(ids, format!("{odcid} {odcid} {odcid} {sid}"));
+
+    // 收到服务端的包后，换成了服务端选取的连接ID，odcid依旧
+    assert!(!conn.remote_cids().is_empty());
+    assert!(!conn.remote_cids().contains(&odcid));
+    assert!(!conn.local_cids().is_empty());
+    assert_eq!(conn.odcid(), odcid);
+
+    conn.close(0, "done");
+    assert_eq!(conn.id(), odcid);
+}
@@ -0,0 +1,63 @@
+use std::io;
+
+use gm_quic::QuicServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+const TOTAL: usize = 2 * 1024 * 1024;
+// 每个密钥阶段只发这么多包，远小于AEAD的保密性上限，以便传输中途多次更新密钥
+const UPDATE_INTERVAL: u64 = 256;
+
+#[tokio::test]
+async fn echo_across_key_updates() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                conn.set_key_update_interval(Some(UPDATE_INTERVAL));
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    tokio::io::copy(&mut reader, &mut writer).await?;
+                    writer.shutdown().await?;
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    conn.set_key_update_interval(Some(UPDATE_INTERVAL));
+    // 握手尚未确认，不能更新密钥
+    assert!(!conn.update_keys());
+
+    let data = (0..TOTAL).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    let sending = tokio::spawn({
+        let data = data.clone();
+        async move {
+            writer.write_all(&data).await?;
+            writer.shutdown().await
+        }
+    });
+    let mut echo = Vec::with_capacity(TOTAL);
+    reader.read_to_end(&mut echo).await.unwrap();
+    sending.await.unwrap().unwrap();
+
+    // 密钥更新前后的数据完好无损地往返
+    assert_eq!(echo.len(), TOTAL);
+    assert!(echo == data, "the echo differs from the data sent");
+    let key_updates = conn.stats().key_updates;
+    assert!(key_updates >= 2, "only {key_updates} key updates");
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,82 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use gm_quic::{ConnectionLimits, QuicServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 全局只有一个server，所有场景都在这一个测试里
+#[tokio::test]
+async fn one_listener_on_two_ports() {
+    let addrs: [SocketAddr; 2] = [common::unused_addr(), common::unused_addr()];
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind(addrs, true)
+        .with_supported_versions([0x00000001u32])
+        .with_connection_limits(ConnectionLimits {
+            max_connections_per_addr: Some(2),
+            ..ConnectionLimits::default()
+        })
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    assert_eq!(server.listen_addresses(), &addrs);
+
+    // 每个双向流都回复连接到来的本地地址
+    let accepted = Arc::new(AtomicUsize::new(0));
+    tokio::spawn({
+        let server = server.clone();
+        let accepted = accepted.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                accepted.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        let mut request = Vec::new();
+                        reader.read_to_end(&mut request).await?;
+                        let local_addr = conn.local_addr().map(|addr| addr.to_string());
+                        writer
+                            .write_all(local_addr.unwrap_or_default().as_bytes())
+                            .await?;
+                        writer.shutdown().await?;
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 连向两个端口的连接，出现在同一个accept队列中，各自记得从哪个端口到来
+    let client = common::client();
+    let mut conns = Vec::new();
+    for addr in addrs {
+        let conn = client.connect(common::SERVER_NAME, addr).unwrap();
+        let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+        writer.shutdown().await.unwrap();
+        let mut reply = String::new();
+        reader.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, addr.to_string());
+        conns.push(conn);
+    }
+    assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    assert_eq!(server.active_connections(), 2);
+
+    // 连接数的限制由两个端口共用，同一客户端地址的第三个连接被拒绝
+    let refused = client.connect(common::SERVER_NAME, addrs[0]).unwrap();
+    let opened = tokio::time::timeout(Duration::from_secs(1), refused.open_bi_stream()).await;
+    assert!(opened.is_err());
+    assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    assert_eq!(server.active_connections(), 2);
+
+    refused.close(0, "done");
+    for conn in conns {
+        conn.close(0, "done");
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use gm_quic::{QuicServer, ServerParameters, VarInt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+const INITIAL_MAX_STREAMS: u32 = 4;
+
+// 服务端没有设置接受策略，客户端一个接一个地建流，全靠MAX_STREAMS帧续上流的额度
+#[tokio::test]
+async fn open_streams_beyond_initial_max_streams() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let mut params = ServerParameters::default();
+    params.set_initial_max_streams_bidi(VarInt::from_u32(INITIAL_MAX_STREAMS));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_parameters(params)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端原样回显
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let streams = INITIAL_MAX_STREAMS * 3;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        for i in 0..streams {
+            let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+            let request = format!("request {i}");
+            writer.write_all(request.as_bytes()).await.unwrap();
+            writer.shutdown().await.unwrap();
+            let mut reply = Vec::new();
+            reader.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, request.as_bytes());
+        }
+    })
+    .await
+    .expect("stuck at the initial max streams");
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,74 @@
+use std::{io, time::Duration};
+
+use gm_quic::QuicServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::oneshot,
+};
+
+mod common;
+
+#[tokio::test]
+async fn client_rebinding() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    let (conn_tx, conn_rx) = oneshot::channel();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (conn, _addr) = server.accept().await?;
+            let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+            _ = conn_tx.send(conn);
+            tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.shutdown().await?;
+            io::Result::Ok(())
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let echo = tokio::spawn(async move {
+        let mut reply = Vec::new();
+        reader.read_to_end(&mut reply).await.map(|_| reply)
+    });
+
+    // 传输到一半，客户端换了一个本地地址
+    let (head, tail) = data.split_at(data.len() / 2);
+    writer.write_all(head).await.unwrap();
+    let new_addr = common::unused_addr();
+    conn.rebind(new_addr).unwrap();
+    assert_eq!(conn.active_pathway().unwrap().local_addr(), new_addr);
+    writer.write_all(tail).await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    let reply = tokio::time::timeout(Duration::from_secs(10), echo)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(reply == data);
+
+    // 服务端验证了新路径之后，迁移过去
+    let server_conn = conn_rx.await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while server_conn
+            .active_pathway()
+            .map(|pathway| pathway.remote_addr())
+            != Some(new_addr)
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,69 @@
+use std::io;
+
+use gm_quic::{MigrationError, QuicServer, ServerParameters};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn server_disables_migration() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let mut params = ServerParameters::default();
+    params.set_disable_active_migration(true);
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_parameters(params)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let echo = |data: &'static [u8]| {
+        let conn = conn.clone();
+        async move {
+            let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+            writer.write_all(data).await.unwrap();
+            writer.shutdown().await.unwrap();
+            let mut reply = Vec::new();
+            reader.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, data);
+        }
+    };
+    // 一来一回之后，客户端已经收到了服务端的传输参数
+    echo(b"hello").await;
+
+    let active = conn.active_pathway().unwrap();
+    let error = conn.rebind(common::unused_addr()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    let error = error.into_inner().unwrap().downcast::<MigrationError>();
+    assert_eq!(*error.unwrap(), MigrationError::Disabled);
+
+    // 没有用新的路径，数据仍走原路径
+    assert_eq!(conn.active_pathway(), Some(active));
+    echo(b"world").await;
+    let pathways = conn
+        .path_metrics()
+        .into_iter()
+        .map(|(pathway, _)| pathway)
+        .collect::<Vec<_>>();
+    assert_eq!(pathways, [active]);
+
+    conn.close(0, "done");
+}
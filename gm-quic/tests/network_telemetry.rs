@@ -0,0 +1,86 @@
+use std::{io, time::Duration};
+
+use gm_quic::{NetworkReport, PrefixLengths, QuicClient, QuicServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+fn client_config() -> QuicClient {
+    common::client_builder()
+        .with_network_telemetry(PrefixLengths::default())
+        .build()
+        .unwrap()
+}
+
+fn check(report: &NetworkReport, connections: u64) {
+    assert_eq!(report.len(), 1);
+    let (prefix, stats) = report.iter().next().unwrap();
+    // 只有网段，没有完整的地址
+    assert_eq!(prefix.to_string(), "127.0.0.0/24");
+    assert_eq!(stats.connections, connections);
+    assert_eq!(stats.handshakes, connections);
+    assert_eq!(stats.rtt.count(), connections);
+    assert!(stats.packets_sent > 0 && stats.bytes_sent > 0 && stats.bytes_rcvd > 0);
+}
+
+// 连接是异步地关闭的，累计多次取走的汇总，直到汇报了所有的连接
+async fn collect(take: impl Fn() -> NetworkReport, connections: u64) -> NetworkReport {
+    let mut report = NetworkReport::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            for (prefix, stats) in take() {
+                report.entry(prefix).or_default().merge(&stats);
+            }
+            if report.values().map(|stats| stats.connections).sum::<u64>() >= connections {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    report
+}
+
+#[tokio::test]
+async fn report_on_close() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_network_telemetry(PrefixLengths::default())
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = client_config();
+    for _ in 0..2 {
+        let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+        let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut reply = Vec::new();
+        reader.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"hello");
+        conn.close(0, "done");
+    }
+    // 客户端主动关闭，进入closing时汇报；服务端收到CONNECTION_CLOSE后汇报
+    check(&collect(|| client.network_report(), 2).await, 2);
+    assert!(client.network_report().is_empty());
+    check(&collect(|| server.network_report(), 2).await, 2);
+}
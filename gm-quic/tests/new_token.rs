@@ -0,0 +1,77 @@
+use std::{io, sync::Arc, time::Duration};
+
+use gm_quic::{QuicClient, QuicConnection, QuicServer, TokenKind, TokenOutcome, TokenStore};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+fn client_config(tokens: Arc<TokenStore>) -> QuicClient {
+    common::client_builder()
+        .with_token_sink(tokens)
+        .build()
+        .unwrap()
+}
+
+async fn echo(conn: &QuicConnection) {
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+}
+
+#[tokio::test]
+async fn second_connection_skips_retry() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .require_retry(true)
+        .issue_new_tokens(1)
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 首次连接经过Retry，握手确认之后服务端通过NEW_TOKEN帧颁发令牌
+    let tokens = Arc::new(TokenStore::default());
+    let client = client_config(tokens.clone());
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    echo(&conn).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while tokens.count(common::SERVER_NAME) == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    conn.close(0, "done");
+
+    let stats = server.token_stats();
+    assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::Retry)), 1);
+    assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::NewToken)), 0);
+
+    // 再次连接，Initial包携带着该令牌，服务端验证之后不再Retry
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    assert_eq!(tokens.count(common::SERVER_NAME), 0);
+    echo(&conn).await;
+    assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::NewToken)), 1);
+    assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::Retry)), 1);
+
+    conn.close(0, "done");
+}
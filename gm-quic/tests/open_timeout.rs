@@ -0,0 +1,108 @@
+use std::{io, time::Duration};
+
+use gm_quic::{QuicServer, ServerParameters, StreamLimitTimeout, VarInt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    time::Instant,
+};
+
+mod common;
+
+const MAX_STREAMS: u32 = 2;
+
+#[tokio::test]
+async fn open_stream_with_timeout() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let mut params = ServerParameters::default();
+    params.set_initial_max_streams_bidi(VarInt::from_u32(MAX_STREAMS));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_parameters(params)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端接受流后先占着，收到通知才回显完并释放一个，对方随之得到新的额度
+    let (accepted_tx, mut accepted_rx) = mpsc::unbounded_channel();
+    let (release_tx, mut release_rx) = mpsc::unbounded_channel::<()>();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (conn, _addr) = server.accept().await?;
+            let mut held = Vec::new();
+            loop {
+                tokio::select! {
+                    stream = conn.accept_bi_stream() => match stream {
+                        Ok(stream) => {
+                            held.push(stream);
+                            _ = accepted_tx.send(());
+                        }
+                        Err(_) => break,
+                    },
+                    Some(()) = release_rx.recv() => {
+                        let (mut reader, mut writer) = held.remove(0);
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                }
+            }
+            io::Result::Ok(())
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let mut streams = Vec::new();
+    for _ in 0..MAX_STREAMS {
+        let (reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        writer.shutdown().await.unwrap();
+        streams.push(reader);
+        accepted_rx.recv().await.unwrap();
+    }
+
+    // 额度用完，立即返回None；限时的打开超时后返回StreamLimitTimeout
+    assert!(conn.try_open_bi_stream().unwrap().is_none());
+    let error = conn
+        .open_bi_stream_timeout(Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    let timeout = error
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<StreamLimitTimeout>();
+    assert_eq!(timeout.unwrap().timeout, Duration::from_millis(200));
+    assert!(conn.try_open_bi_stream().unwrap().is_none());
+
+    // 等待中的打开，在对方提高MAX_STREAMS后，赶在期限之前被唤醒
+    let deadline = Duration::from_secs(5);
+    let waiting = tokio::spawn({
+        let conn = conn.clone();
+        async move {
+            let start = Instant::now();
+            let stream = conn.open_bi_stream_timeout(deadline).await;
+            (stream, start.elapsed())
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    release_tx.send(()).unwrap();
+    let mut reply = Vec::new();
+    streams[0].read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+
+    let (stream, elapsed) = waiting.await.unwrap();
+    assert!(elapsed < deadline, "woken after {elapsed:?}");
+    let (_reader, mut writer) = stream.unwrap().unwrap();
+    // 放弃的尝试都没有占用流ID
+    assert_eq!(
+        u64::from(VarInt::from(writer.stream_id())),
+        4 * MAX_STREAMS as u64
+    );
+    writer.write_all(b"world").await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,136 @@
+use std::{io, time::Duration};
+
+use gm_quic::{
+    IncomingStreamPolicy, PressureLevel, QuicConnection, QuicServer, StreamReader, StreamReset,
+    StreamWriter,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+const CHUNK: usize = 128 * 1024;
+const SHED_CODE: u64 = 0x77;
+
+fn acks_sent(conn: &QuicConnection) -> u64 {
+    conn.path_metrics()
+        .into_iter()
+        .map(|(_, metrics)| metrics.acks_sent)
+        .sum()
+}
+
+// 发送一块数据，等服务端收全后的回执
+async fn send_chunk(reader: &mut StreamReader, writer: &mut StreamWriter) {
+    writer.write_all(&[0x5a; CHUNK]).await.unwrap();
+    writer.flush().await.unwrap();
+    let mut receipt = [0u8; 1];
+    reader.read_exact(&mut receipt).await.unwrap();
+}
+
+async fn echo(conn: &QuicConnection, request: &[u8]) -> io::Result<Vec<u8>> {
+    let (mut reader, mut writer) = conn.open_bi_stream().await?.unwrap();
+    writer.write_all(request).await?;
+    writer.shutdown().await?;
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await?;
+    Ok(reply)
+}
+
+#[tokio::test]
+async fn shed_new_work_under_pressure() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 第一个流是持续进行的传输，每收全一块回一个字节；其余的流原样回显
+    let (conn_tx, mut conn_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                conn.set_incoming_stream_policy(IncomingStreamPolicy {
+                    max_in_flight: 4,
+                    queue_depth: 4,
+                    shed_code: SHED_CODE,
+                    queue_timeout: Duration::from_secs(5),
+                });
+                _ = conn_tx.send(conn.clone());
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    tokio::spawn(async move {
+                        let mut chunk = vec![0u8; CHUNK];
+                        while reader.read_exact(&mut chunk).await.is_ok() {
+                            writer.write_all(b"+").await?;
+                        }
+                        writer.shutdown().await?;
+                        std::io::Result::Ok(())
+                    });
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    // 让服务端平时每个包都立即确认，以便与压力下对比
+    conn.request_ack_frequency(0, Duration::from_millis(1), 0)
+        .await
+        .unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    let server_conn = conn_rx.recv().await.unwrap();
+    send_chunk(&mut reader, &mut writer).await;
+
+    let before = acks_sent(&server_conn);
+    send_chunk(&mut reader, &mut writer).await;
+    let normal_acks = acks_sent(&server_conn) - before;
+
+    server.set_pressure_level(PressureLevel::Elevated);
+    assert_eq!(server.pressure_level(), PressureLevel::Elevated);
+    let pressure = server_conn.stats().pressure;
+    assert_eq!(pressure.level, PressureLevel::Elevated);
+    assert!(pressure.shedding_streams);
+
+    // 新的流被丢弃，进行中的传输照常，但确认的频率降了下来
+    for _ in 0..3 {
+        let error = echo(&conn, b"hello").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        let reset = error.get_ref().unwrap().downcast_ref::<StreamReset>();
+        assert_eq!(reset.unwrap().error_code, SHED_CODE);
+    }
+    let before = acks_sent(&server_conn);
+    let transfer = tokio::time::timeout(Duration::from_secs(10), async {
+        send_chunk(&mut reader, &mut writer).await;
+    });
+    transfer
+        .await
+        .expect("the transfer in progress stalled under pressure");
+    let elevated_acks = acks_sent(&server_conn) - before;
+    assert!(
+        elevated_acks < normal_acks,
+        "{elevated_acks} acks sent under pressure, {normal_acks} normally"
+    );
+
+    // 压力解除后，新的流又被接受
+    server.set_pressure_level(PressureLevel::Normal);
+    assert!(!server_conn.stats().pressure.shedding_streams);
+    let reply = tokio::time::timeout(Duration::from_secs(5), echo(&conn, b"hello"))
+        .await
+        .expect("the MAX_STREAMS credit is not restored")
+        .unwrap();
+    assert_eq!(reply, b"hello");
+
+    writer.shutdown().await.unwrap();
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,73 @@
+use std::{io, time::Duration};
+
+use gm_quic::{QuicServer, StreamReset, StreamStopped};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 流被重置或者停止的错误，其中带有对方给出的错误码
+fn downcast<E: std::error::Error + 'static>(error: &io::Error) -> &E {
+    assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    error.get_ref().unwrap().downcast_ref::<E>().unwrap()
+}
+
+#[tokio::test]
+async fn error_codes_of_reset_and_stop() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    // 第一个流被对方重置，把读到的错误码写回去
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+                    let code = downcast::<StreamReset>(&error).error_code;
+                    writer.write_all(code.to_string().as_bytes()).await?;
+                    writer.shutdown().await?;
+
+                    // 第二个流，读到一些数据后就要求对方停止发送
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    reader.read_exact(&mut [0u8; 2]).await?;
+                    reader.stop(7);
+                    writer.shutdown().await?;
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.reset(42);
+    let mut reply = String::new();
+    reader.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "42");
+
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    // 对方的STOP_SENDING到达之后，写入失败，错误中带有对方的错误码
+    let error = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Err(error) = writer.write_all(b"hi").await {
+                break error;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(downcast::<StreamStopped>(&error).error_code, 7);
+    reader.read_to_end(&mut Vec::new()).await.unwrap();
+
+    conn.close(0, "done");
+}
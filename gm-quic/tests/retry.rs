@@ -0,0 +1,50 @@
+use std::io;
+
+use gm_quic::{QuicServer, TokenKind, TokenOutcome};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn handshake_through_retry() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .require_retry(true)
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 客户端的首个Initial包没有令牌，服务端回应Retry，客户端带着令牌重新发起，
+    // 双方核对过original_destination_connection_id和retry_source_connection_id后完成握手
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+
+    let stats = server.token_stats();
+    assert!(stats.count(&TokenOutcome::Empty) >= 1);
+    assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::Retry)), 1);
+
+    conn.close(0, "done");
+}
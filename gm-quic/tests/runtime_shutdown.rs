@@ -0,0 +1,66 @@
+use std::{sync::mpsc, time::Duration};
+
+use gm_quic::QuicServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+// 连接和它的几个流都还活着时，运行时先关闭了，之后关闭、释放它们都不能panic
+#[test]
+fn drop_runtime_with_live_connection() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let server_addr = common::unused_addr();
+    let (client, conn, streams) = runtime.block_on(async {
+        let (cert, key) = common::server_cert();
+        let server = QuicServer::bind([server_addr], true)
+            .with_supported_versions([0x00000001u32])
+            .without_cert_verifier()
+            .with_single_cert(cert, key)
+            .listen()
+            .unwrap();
+        // 服务端回显第一段数据之后，流就一直开着，直到运行时关闭
+        tokio::spawn(async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let mut streams = Vec::new();
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        let mut buf = [0u8; 5];
+                        reader.read_exact(&mut buf).await?;
+                        writer.write_all(&buf).await?;
+                        writer.flush().await?;
+                        streams.push((reader, writer));
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        });
+
+        let client = common::client();
+        let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+        let mut streams = Vec::new();
+        for _ in 0..4 {
+            let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+            writer.write_all(b"hello").await.unwrap();
+            writer.flush().await.unwrap();
+            let mut reply = [0u8; 5];
+            reader.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"hello");
+            streams.push((reader, writer));
+        }
+        (client, conn, streams)
+    });
+    drop(runtime);
+
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        conn.close(0, "bye");
+        drop(streams);
+        drop(conn);
+        drop(client);
+        done_tx.send(()).unwrap();
+    });
+    // 线程panic的话，发送端随之释放，这里也会失败
+    done_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("destructors completed without panicking");
+}
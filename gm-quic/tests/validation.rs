@@ -0,0 +1,201 @@
+use std::{net::UdpSocket, sync::Arc, time::Duration};
+
+use gm_quic::{
+    ConfigDiagnostic, DiagnosticKind, QuicClient, QuicServer, QuicServerBuilder, ServerParameters,
+    Severity, Strictness, Validation, VarInt,
+};
+use rustls::{server::WantsServerCert, ConfigBuilder, ServerConfig};
+
+mod common;
+
+fn kinds(result: Result<(), Vec<ConfigDiagnostic>>) -> Vec<DiagnosticKind> {
+    result
+        .err()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|diagnostic| diagnostic.kind)
+        .collect()
+}
+
+type ServerBuilder = QuicServerBuilder<ConfigBuilder<ServerConfig, WantsServerCert>>;
+
+fn server_builder() -> ServerBuilder {
+    QuicServer::bind([common::unused_addr()], true).without_cert_verifier()
+}
+
+#[test]
+fn certificates() {
+    let keychain = common::Keychain::new("validation-certs");
+
+    let (cert, key) = keychain.valid("valid");
+    let builder = server_builder().with_single_cert(&cert, &key);
+    assert_eq!(builder.validate(), Ok(()));
+
+    // 私钥与叶子证书不匹配
+    let (_, other_key) = keychain.valid("other");
+    let builder = server_builder().with_single_cert(&cert, &other_key);
+    assert_eq!(kinds(builder.validate()), [DiagnosticKind::KeyMismatch]);
+
+    // 证书链的顺序颠倒，叶子证书不在最前
+    let (reversed, key) = keychain.leaf("reversed", |_| {}, |leaf, ca| ca + &leaf);
+    let builder = server_builder().with_single_cert(&reversed, &key);
+    assert_eq!(kinds(builder.validate()), [DiagnosticKind::ChainOutOfOrder]);
+
+    let (expired, key) = keychain.leaf(
+        "expired",
+        |params| {
+            params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+            params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        },
+        |leaf, ca| leaf + &ca,
+    );
+    let builder = server_builder().with_single_cert(&expired, &key);
+    assert_eq!(
+        kinds(builder.validate()),
+        [DiagnosticKind::CertificateExpired]
+    );
+
+    // 有效期内，但在告警的阈值之内
+    let (expiring, key) = keychain.leaf(
+        "expiring",
+        |params| params.not_after = rcgen::date_time_ymd(2090, 1, 1),
+        |leaf, ca| leaf + &ca,
+    );
+    let builder = server_builder()
+        .with_validation(Validation {
+            expiry_warning: Duration::from_secs(100 * 365 * 24 * 3600),
+            ..Validation::default()
+        })
+        .with_single_cert(&expiring, &key);
+    let diagnostics = builder.validate().unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::CertificateExpiringSoon);
+    assert_eq!(diagnostics[0].severity(), Severity::Warning);
+
+    let missing = keychain.dir.join("missing.crt");
+    let builder = server_builder().with_single_cert(&missing, &key);
+    assert_eq!(kinds(builder.validate()), [DiagnosticKind::UnreadableFile]);
+
+    let garbage = keychain.write("garbage.key", "not a key");
+    let builder = server_builder().with_single_cert(&cert, &garbage);
+    assert_eq!(
+        kinds(builder.validate()),
+        [DiagnosticKind::InvalidPrivateKey]
+    );
+
+    let mut builder = server_builder().enable_sni();
+    builder.add_host(
+        common::SERVER_NAME,
+        &cert,
+        &other_key,
+        ServerParameters::default(),
+    );
+    assert_eq!(kinds(builder.validate()), [DiagnosticKind::KeyMismatch]);
+}
+
+#[test]
+fn parameters() {
+    let keychain = common::Keychain::new("validation-params");
+    let (cert, key) = keychain.valid("valid");
+
+    let mut parameters = ServerParameters::default();
+    parameters.set_max_idle_timeout(Duration::ZERO);
+    parameters.set_initial_max_streams_bidi(VarInt::from_u32(0));
+    parameters.set_initial_max_streams_uni(VarInt::from_u32(0));
+    parameters.set_active_connection_id_limit(VarInt::from_u32(1));
+    let builder = server_builder()
+        .with_parameters(parameters)
+        .with_single_cert(&cert, &key);
+    let mut kinds = kinds(builder.validate());
+    kinds.sort_by_key(|kind| format!("{kind:?}"));
+    assert_eq!(
+        kinds,
+        [
+            DiagnosticKind::InvalidParameters,
+            DiagnosticKind::ZeroIdleTimeout,
+            DiagnosticKind::ZeroStreamLimits,
+        ]
+    );
+}
+
+#[test]
+fn trust_anchors() {
+    let builder = || {
+        QuicClient::bind(["127.0.0.1:0".parse().unwrap()])
+            .with_root_certificates(Arc::new(rustls::RootCertStore::empty()))
+            .without_cert()
+    };
+    assert_eq!(
+        kinds(builder().validate()),
+        [DiagnosticKind::NoTrustAnchors]
+    );
+    assert!(builder().try_build().is_err());
+    // 同监听一样，构建被拒绝时返回错误，而不是panic
+    let error = builder().build().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    let keychain = common::Keychain::new("validation-mtls");
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(keychain.ca.der().clone()).unwrap();
+    let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+        .clear_root_hint_subjects()
+        .build()
+        .unwrap();
+    let (cert, key) = keychain.valid("valid");
+    let builder = QuicServer::bind([common::unused_addr()], true)
+        .with_cert_verifier(verifier)
+        .with_single_cert(&cert, &key);
+    assert_eq!(
+        kinds(builder.validate()),
+        [DiagnosticKind::ClientAuthWithoutRoots]
+    );
+}
+
+#[test]
+fn refused_before_binding() {
+    let addr = common::unused_addr();
+    let keychain = common::Keychain::new("validation-refused");
+    let (expired, key) = keychain.leaf(
+        "expired",
+        |params| {
+            params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+            params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        },
+        |leaf, ca| leaf + &ca,
+    );
+    let diagnostics = QuicServer::bind([addr], true)
+        .without_cert_verifier()
+        .with_single_cert(&expired, &key)
+        .try_listen()
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::CertificateExpired);
+    // listen不会panic，而是返回错误
+    let error = QuicServer::bind([addr], true)
+        .without_cert_verifier()
+        .with_single_cert(&expired, &key)
+        .listen()
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    // 警告只在严格模式下拒绝
+    let mut parameters = ServerParameters::default();
+    parameters.set_max_idle_timeout(Duration::ZERO);
+    let (cert, key) = keychain.valid("valid");
+    let diagnostics = QuicServer::bind([addr], true)
+        .with_validation(Validation {
+            strictness: Strictness::Strict,
+            ..Validation::default()
+        })
+        .without_cert_verifier()
+        .with_parameters(parameters)
+        .with_single_cert(&cert, &key)
+        .try_listen()
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::ZeroIdleTimeout);
+
+    // 没有绑定过这个地址
+    UdpSocket::bind(addr).unwrap();
+}
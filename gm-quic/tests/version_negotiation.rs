@@ -0,0 +1,78 @@
+use std::{io, time::Duration};
+
+use gm_quic::{is_reserved, QuicClient, QuicServer, QUIC_V1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+};
+
+mod common;
+
+fn client_config(versions: impl IntoIterator<Item = u32>) -> QuicClient {
+    common::client_builder()
+        .prefer_versions(versions)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn steered_to_v1() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([QUIC_V1])
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 不支持的版本，服务端回应版本协商包，交换两个连接ID，列出版本1和一个保留版本
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut initial = vec![0xc0, 0x1a, 0x2a, 0x3a, 0x4a];
+    initial.extend([8, 1, 1, 1, 1, 1, 1, 1, 1]);
+    initial.extend([4, 2, 2, 2, 2]);
+    initial.resize(1200, 0);
+    socket.send_to(&initial, server_addr).await.unwrap();
+    let mut buf = [0u8; 1500];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let vn = &buf[..len];
+    assert_eq!(vn[0] & 0x80, 0x80);
+    assert_eq!(&vn[1..5], &[0; 4]);
+    assert_eq!(&vn[5..10], &[4, 2, 2, 2, 2]);
+    assert_eq!(&vn[10..19], &[8, 1, 1, 1, 1, 1, 1, 1, 1]);
+    let versions = vn[19..]
+        .chunks(4)
+        .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(versions[0], QUIC_V1);
+    assert!(versions[1..].iter().all(|version| is_reserved(*version)));
+
+    // 客户端先以保留版本试探，经版本协商换用版本1，并核对服务端的version_information之后完成握手
+    let client = client_config([0x1a2a3a4a, QUIC_V1]);
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"hello");
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,128 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use gm_quic::{QuicServer, ServerParameters, VarInt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    sync::mpsc,
+    time::Instant,
+};
+
+mod common;
+
+const RTT: Duration = Duration::from_millis(100);
+const INITIAL_WINDOW: u32 = 64 * 1024;
+const TOTAL: usize = 4 * 1024 * 1024;
+
+// 按到达的顺序，到了各自的时间再从socket发出
+fn delayed(socket: Arc<UdpSocket>) -> mpsc::UnboundedSender<(Instant, Vec<u8>, SocketAddr)> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Vec<u8>, SocketAddr)>();
+    tokio::spawn(async move {
+        while let Some((at, packet, to)) = rx.recv().await {
+            tokio::time::sleep_until(at).await;
+            _ = socket.send_to(&packet, to).await;
+        }
+    });
+    tx
+}
+
+// 在客户端与服务端之间转发数据包，每个方向各延迟半个RTT，模拟长距离的路径
+async fn relay(front: UdpSocket, server_addr: SocketAddr) {
+    let front = Arc::new(front);
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let client_addr = Arc::new(OnceLock::new());
+
+    let to_client = delayed(front.clone());
+    tokio::spawn({
+        let back = back.clone();
+        let client_addr = client_addr.clone();
+        async move {
+            let mut buf = vec![0u8; 65536];
+            while let Ok((len, _)) = back.recv_from(&mut buf).await {
+                if let Some(client_addr) = client_addr.get() {
+                    let at = Instant::now() + RTT / 2;
+                    _ = to_client.send((at, buf[..len].to_vec(), *client_addr));
+                }
+            }
+        }
+    });
+
+    let to_server = delayed(back);
+    let mut buf = vec![0u8; 65536];
+    while let Ok((len, from)) = front.recv_from(&mut buf).await {
+        _ = client_addr.set(from);
+        _ = to_server.send((Instant::now() + RTT / 2, buf[..len].to_vec(), server_addr));
+    }
+}
+
+// 固定的接收窗口下，每个RTT至多传输一个窗口的数据；窗口随吞吐增长后，远超这个上限
+#[tokio::test]
+async fn stream_window_grows_with_bdp() {
+    let server_addr = common::unused_addr();
+    let relay_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+
+    let mut params = ServerParameters::default();
+    params.set_initial_max_data(VarInt::from_u32(16 * 1024 * 1024));
+    params.set_initial_max_stream_data_bidi_remote(VarInt::from_u32(INITIAL_WINDOW));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .without_cert_verifier()
+        .with_parameters(params)
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端读完之后，回复收到的字节数
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.accept_bi_stream().await?;
+                    let mut buf = vec![0u8; 65536];
+                    let mut rcvd = 0u64;
+                    loop {
+                        match reader.read(&mut buf).await? {
+                            0 => break,
+                            n => rcvd += n as u64,
+                        }
+                    }
+                    writer.write_all(&rcvd.to_be_bytes()).await?;
+                    writer.shutdown().await?;
+                    std::io::Result::Ok(())
+                });
+            }
+        }
+    });
+    let front = UdpSocket::bind(relay_addr).await.unwrap();
+    tokio::spawn(relay(front, server_addr));
+
+    let client = common::client();
+    let conn = client.connect(common::SERVER_NAME, relay_addr).unwrap();
+    let (mut reader, mut writer) = conn.open_bi_stream().await.unwrap().unwrap();
+
+    let bound = RTT * (TOTAL / INITIAL_WINDOW as usize) as u32;
+    let start = Instant::now();
+    let reply = tokio::time::timeout(bound, async {
+        let data = vec![0x5a; TOTAL];
+        writer.write_all(&data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut reply = Vec::new();
+        reader.read_to_end(&mut reply).await.unwrap();
+        reply
+    })
+    .await
+    .expect("capped by the initial receive window");
+    let elapsed = start.elapsed();
+    assert_eq!(reply, (TOTAL as u64).to_be_bytes());
+    assert!(
+        elapsed < bound / 2,
+        "{TOTAL} bytes took {elapsed:?}, the initial window allows {bound:?}"
+    );
+
+    conn.close(0, "done");
+}
@@ -0,0 +1,182 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use gm_quic::{QuicClient, QuicConnection, QuicServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+fn client_config() -> QuicClient {
+    common::client_builder().enable_0rtt(true).build().unwrap()
+}
+
+// 连接一建立就打开流发出请求，0-RTT的话无须等待握手完成
+async fn echo(conn: &QuicConnection, request: &[u8], early: bool) {
+    let opened = conn.try_open_bi_stream().unwrap();
+    assert_eq!(opened.is_some(), early);
+    let (mut reader, mut writer) = match opened {
+        Some(stream) => stream,
+        None => conn.open_bi_stream().await.unwrap().unwrap(),
+    };
+    writer.write_all(request).await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    reader.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, request);
+}
+
+async fn resumed_request_in_first_flight() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    // 第1、2个连接接受0-RTT，第3个连接拒绝
+    let decisions = Arc::new(AtomicUsize::new(0));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .with_0rtt_policy({
+            let decisions = decisions.clone();
+            move |_| decisions.fetch_add(1, Ordering::Relaxed) < 2
+        })
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                tokio::spawn(async move {
+                    while let Ok((mut reader, mut writer)) = conn.accept_bi_stream().await {
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                        writer.shutdown().await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 首次连接没有会话票据，要等握手完成才知道服务端的额度
+    let client = client_config();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    echo(&conn, b"first", false).await;
+    assert_eq!(conn.is_0rtt_accepted(), Some(false));
+    // 握手之后服务端在1-RTT包中发来NewSessionTicket
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    conn.close(0, "done");
+
+    // 恢复会话，沿用记住的额度打开流，请求随第一个飞行以0-RTT包发出
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    echo(&conn, b"early", true).await;
+    assert_eq!(conn.is_0rtt_accepted(), Some(true));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    conn.close(0, "done");
+
+    // 服务端拒绝0-RTT，丢弃的请求透明地在1-RTT包中重传
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    echo(&conn, b"rejected", true).await;
+    assert_eq!(conn.is_0rtt_accepted(), Some(false));
+    conn.close(0, "done");
+
+    assert_eq!(decisions.load(Ordering::Relaxed), 3);
+}
+
+// 等到条件成立，比如收到服务端的传输参数之后，才知道0-RTT是否被接受
+async fn until(mut condition: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the condition is met in time");
+}
+
+async fn datagram_in_first_flight() {
+    let server_addr = common::unused_addr();
+    let (cert, key) = common::server_cert();
+    // 第1、2个连接接受0-RTT，第3个连接拒绝
+    let decisions = Arc::new(AtomicUsize::new(0));
+    let server = QuicServer::bind([server_addr], true)
+        .with_supported_versions([0x00000001u32])
+        .with_0rtt_policy({
+            let decisions = decisions.clone();
+            move |_| decisions.fetch_add(1, Ordering::Relaxed) < 2
+        })
+        .accept_0rtt_datagrams(true)
+        .without_cert_verifier()
+        .with_single_cert(cert, key)
+        .listen()
+        .unwrap();
+    // 服务端收到的数据报，以及收到时握手是否已经完成
+    let (received_tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Ok((conn, _addr)) = server.accept().await {
+                let received_tx = received_tx.clone();
+                tokio::spawn(async move {
+                    let mut reader = conn.datagrams()?.reader()?;
+                    while let Ok(datagram) = reader.recv().await {
+                        // 服务端发出第一个飞行之后，收到客户端的Finished，握手才完成
+                        let handshaked = conn
+                            .handshake_stats()
+                            .is_some_and(|stats| stats.round_trips > 0);
+                        _ = received_tx.send((datagram, handshaked));
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        }
+    });
+
+    // 首次连接没有会话票据，要等握手完成才知道服务端的额度
+    let client = client_config();
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let writer = conn.datagram_writer().await.unwrap();
+    writer.send(b"first").unwrap();
+    let (datagram, _) = received.recv().await.unwrap();
+    assert_eq!(&datagram[..], b"first");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    conn.close(0, "done");
+
+    // 恢复会话，沿用记住的额度，数据报随第一个飞行以0-RTT包发出，服务端在握手完成之前就收到了
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let writer = conn.datagram_writer().await.unwrap();
+    writer.send(b"early").unwrap();
+    let (datagram, handshaked) = received.recv().await.unwrap();
+    assert_eq!(&datagram[..], b"early");
+    assert!(!handshaked);
+    until(|| conn.is_0rtt_accepted().is_some()).await;
+    assert_eq!(conn.is_0rtt_accepted(), Some(true));
+    until(|| writer.unconfirmed_0rtt().unwrap() == 0).await;
+    assert_eq!(writer.rejected_0rtt().unwrap(), 0);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    conn.close(0, "done");
+
+    // 服务端拒绝0-RTT，其中的数据报丢失，只计入被拒绝的数量，不在1-RTT包中重发
+    let conn = client.connect(common::SERVER_NAME, server_addr).unwrap();
+    let writer = conn.datagram_writer().await.unwrap();
+    writer.send(b"rejected").unwrap();
+    until(|| writer.rejected_0rtt().unwrap() == 1).await;
+    assert_eq!(conn.is_0rtt_accepted(), Some(false));
+    assert_eq!(writer.unconfirmed_0rtt().unwrap(), 0);
+    writer.send(b"late").unwrap();
+    let (datagram, _) = received.recv().await.unwrap();
+    assert_eq!(&datagram[..], b"late");
+    conn.close(0, "done");
+
+    assert_eq!(decisions.load(Ordering::Relaxed), 3);
+}
+
+// 服务端是全局唯一的，新监听的会接管别的服务端地址上的新连接，两个场景只能依次进行
+#[tokio::test]
+async fn zero_rtt() {
+    resumed_request_in_first_flight().await;
+    datagram_in_first_flight().await;
+}
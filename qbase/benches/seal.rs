@@ -0,0 +1,70 @@
+//! Compare sealing the packets of a GSO batch one by one with [`SealKeys::seal_batch`].
+//!
+//! Run with `cargo bench -p qbase --bench seal`.
+
+use std::{hint::black_box, sync::Arc, time::Instant};
+
+use qbase::packet::encrypt::{PacketBuf, SealKeys};
+use rustls::{CipherSuite, Side};
+
+const SEGMENT: usize = 1200;
+const BATCH: usize = 16;
+const ROUNDS: usize = 2000;
+
+fn seal_keys() -> SealKeys {
+    let suite = rustls::crypto::ring::default_provider()
+        .cipher_suites
+        .iter()
+        .find_map(|cs| match (cs.suite(), cs.tls13()) {
+            (CipherSuite::TLS13_AES_128_GCM_SHA256, Some(suite)) => suite.quic_suite(),
+            _ => None,
+        })
+        .unwrap();
+    let keys = suite.keys(
+        &[0x83, 0x94, 0xc8, 0xf0],
+        Side::Client,
+        rustls::quic::Version::V1,
+    );
+    SealKeys::new(Arc::from(keys.local.packet), Arc::from(keys.local.header))
+}
+
+fn packet_bufs(batch: &mut [u8], first_pn: u64) -> Vec<PacketBuf<'_>> {
+    batch
+        .chunks_mut(SEGMENT)
+        .zip(first_pn..)
+        .map(|(buf, pn)| PacketBuf {
+            buf,
+            pn,
+            payload_offset: 9,
+            pn_len: 4,
+        })
+        .collect()
+}
+
+fn bench(name: &str, mut f: impl FnMut(&mut [u8], u64)) {
+    let mut batch = vec![0x5au8; SEGMENT * BATCH];
+    let start = Instant::now();
+    for round in 0..ROUNDS {
+        f(&mut batch, (round * BATCH) as u64);
+        black_box(&batch);
+    }
+    let elapsed = start.elapsed();
+    let packets = ROUNDS * BATCH;
+    println!(
+        "{name:>6}: {:>8.1} ns/packet, {:>7.1} MB/s",
+        elapsed.as_nanos() as f64 / packets as f64,
+        (packets * SEGMENT) as f64 / elapsed.as_secs_f64() / 1e6,
+    );
+}
+
+fn main() {
+    let keys = seal_keys();
+    bench("single", |batch, first_pn| {
+        for mut packet in packet_bufs(batch, first_pn) {
+            keys.seal(&mut packet);
+        }
+    });
+    bench("batch", |batch, first_pn| {
+        keys.seal_batch(&mut packet_bufs(batch, first_pn));
+    });
+}
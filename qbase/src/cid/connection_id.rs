@@ -74,6 +74,13 @@ impl ConnectionId {
     }
 }
 
+/// Print the connection ID in lowercase hex, for the logs.
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
 impl std::ops::Deref for ConnectionId {
     type Target = [u8];
 
@@ -86,6 +93,14 @@ pub trait UniqueCid {
     fn is_unique_cid(&self, cid: &ConnectionId) -> bool;
 }
 
+/// Derive the stateless reset token of a connection ID issued to the peer, usually from a
+/// [`StatelessResetKey`].
+///
+/// [`StatelessResetKey`]: crate::token::StatelessResetKey
+pub trait GenResetToken {
+    fn gen_reset_token(&self, cid: &ConnectionId) -> crate::token::ResetToken;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +122,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_display_connection_id() {
+        let cid = ConnectionId::from_slice(&[0x01, 0xab, 0x00, 0xff]);
+        assert_eq!(cid.to_string(), "01ab00ff");
+        assert_eq!(ConnectionId::default().to_string(), "");
+    }
+
     #[test]
     #[should_panic]
     fn test_cid_from_large_slice() {
@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 
-use super::{ConnectionId, UniqueCid};
+use super::{ConnectionId, GenResetToken, UniqueCid};
 use crate::{
     error::{Error, ErrorKind},
     frame::{
@@ -17,7 +17,7 @@ use crate::{
 pub struct RawLocalCids<GENERATOR, ISSUED>
 where
     GENERATOR: Fn() -> ConnectionId,
-    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid,
+    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid + GenResetToken,
 {
     generator: GENERATOR,
     // If the item in cid_deque is None, it means the connection ID has been retired.
@@ -36,12 +36,12 @@ where
 impl<GENERATOR, ISSUED> RawLocalCids<GENERATOR, ISSUED>
 where
     GENERATOR: Fn() -> ConnectionId,
-    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid,
+    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid + GenResetToken,
 {
     fn new(generator: GENERATOR, scid: ConnectionId, issued_cids: ISSUED) -> Self {
         let mut cid_deque = IndexDeque::default();
         cid_deque
-            .push_back(Some((scid, ResetToken::default())))
+            .push_back(Some((scid, issued_cids.gen_reset_token(&scid))))
             .unwrap();
 
         let new_cid_frame = NewConnectionIdFrame::gen(
@@ -127,12 +127,12 @@ where
 pub struct ArcLocalCids<GENERATOR, ISSUED>(Arc<Mutex<RawLocalCids<GENERATOR, ISSUED>>>)
 where
     GENERATOR: Fn() -> ConnectionId,
-    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid;
+    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid + GenResetToken;
 
 impl<GENERATOR, ISSUED> ArcLocalCids<GENERATOR, ISSUED>
 where
     GENERATOR: Fn() -> ConnectionId,
-    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid,
+    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid + GenResetToken,
 {
     pub fn new(generator: GENERATOR, scid: ConnectionId, issued_cids: ISSUED) -> Self {
         let raw_local_cids = RawLocalCids::new(generator, scid, issued_cids);
@@ -157,7 +157,7 @@ where
 impl<GENERATOR, ISSUED> ReceiveFrame<RetireConnectionIdFrame> for ArcLocalCids<GENERATOR, ISSUED>
 where
     GENERATOR: Fn() -> ConnectionId,
-    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid,
+    ISSUED: SendFrame<NewConnectionIdFrame> + UniqueCid + GenResetToken,
 {
     type Output = Option<ConnectionId>;
 
@@ -190,6 +190,12 @@ mod tests {
         }
     }
 
+    impl GenResetToken for IssuedCids {
+        fn gen_reset_token(&self, _cid: &ConnectionId) -> ResetToken {
+            ResetToken::random_gen()
+        }
+    }
+
     impl SendFrame<NewConnectionIdFrame> for IssuedCids {
         fn send_frame<I: IntoIterator<Item = NewConnectionIdFrame>>(&self, iter: I) {
             self.0.lock().unwrap().extend(iter);
@@ -32,6 +32,9 @@ where
     cursor: u64,
     // The retired cids, each needs send a RetireConnectionIdFrame to peer
     retired_cids: RETIRED,
+    // The number of times a cell in use was forced to switch to a new cid,
+    // because the peer retired the cid it was using
+    forced_switches: u64,
 }
 
 impl<RETIRED> RawRemoteCids<RETIRED>
@@ -52,12 +55,15 @@ where
             cid_cells: Default::default(),
             cursor: 0,
             retired_cids,
+            forced_switches: 0,
         }
     }
 
     fn revise_initial_dcid(&mut self, initial_dcid: ConnectionId) {
         let first_dcid = self.cid_deque.get_mut(0).unwrap();
-        *first_dcid = Some((0, initial_dcid, ResetToken::default()));
+        // 服务端的传输参数中的无状态重置令牌可能先到，不能被之后的Initial包抹掉
+        let reset_token = first_dcid.map(|(_, _, token)| token).unwrap_or_default();
+        *first_dcid = Some((0, initial_dcid, reset_token));
 
         if let Some(apply) = self.cid_cells.get_mut(0) {
             apply.0.lock().unwrap().state.revise(initial_dcid);
@@ -104,7 +110,9 @@ where
             let next_unused_cid = self.cid_deque.get(self.cursor);
 
             if let (Some(cell), Some(Some((_, cid, _)))) = (next_unalloced_cell, next_unused_cid) {
-                cell.0.lock().unwrap().state.assign(*cid);
+                if cell.0.lock().unwrap().assign(*cid) {
+                    self.forced_switches += 1;
+                }
                 self.cursor += 1;
             } else {
                 break;
@@ -152,17 +160,27 @@ where
                 if guard.is_retired() {
                     continue;
                 }
-                // reset the cell, and wait for the new cid to be assigned inside
                 assert_eq!(guard.seq, seq);
                 guard.seq = next_apply;
-                guard.state.clear();
-                drop(guard);
+                if guard.is_ready() && guard.retiring.is_none() {
+                    // The cid is being used to send packets. Keep using it until a new cid is
+                    // assigned inside, and then retire it, otherwise the path will stall.
+                    guard.retiring = Some(seq);
+                    drop(guard);
+                } else {
+                    // reset the cell, and wait for the new cid to be assigned inside.
+                    // If the cell is still using a retiring cid, keep using it as well.
+                    if guard.retiring.is_none() {
+                        guard.state.clear();
+                    }
+                    drop(guard);
 
-                // retire the old cid and prepare to inform the peer with a RetireConnectionIdFrame
-                self.retired_cids.send_frame([RetireConnectionIdFrame {
-                    sequence: VarInt::from_u64(seq)
-                        .expect("Sequence of connection id is very hard to exceed VARINT_MAX"),
-                }]);
+                    // retire the old cid and prepare to inform the peer with a RetireConnectionIdFrame
+                    self.retired_cids.send_frame([RetireConnectionIdFrame {
+                        sequence: VarInt::from_u64(seq)
+                            .expect("Sequence of connection id is very hard to exceed VARINT_MAX"),
+                    }]);
+                }
                 // The reason for using insert instead of push_back is to keep the cid and cell consistent,
                 // "jumping retired cid" will lead to "jumping allocation", although it is unlikely to happen.
                 self.cid_cells
@@ -184,6 +202,43 @@ where
         }
     }
 
+    fn in_use_cids(&self) -> Vec<ConnectionId> {
+        self.cid_cells
+            .iter()
+            .filter_map(|cell| cell.0.lock().unwrap().state.0.as_ref().copied())
+            .collect()
+    }
+
+    fn set_initial_reset_token(&mut self, token: ResetToken) {
+        if let Some(Some((0, _, reset_token))) = self.cid_deque.get_mut(0) {
+            *reset_token = token;
+        }
+    }
+
+    fn initial_dcid(&self) -> Option<ConnectionId> {
+        match self.cid_deque.get(0) {
+            Some(Some((0, cid, _))) => Some(*cid),
+            _ => None,
+        }
+    }
+
+    fn is_active_reset_token(&self, token: &ResetToken) -> bool {
+        // 初始的连接ID没有重置令牌，除非服务端在传输参数中给出，默认的全零令牌不算
+        if *token == ResetToken::default() {
+            return false;
+        }
+        let in_use = self.in_use_cids();
+        self.cid_deque
+            .iter()
+            .flatten()
+            // 常数时间比较，不泄露伪造的令牌与有效令牌相同的前缀长度
+            .any(|(_, cid, reset_token)| reset_token == token && in_use.contains(cid))
+    }
+
+    fn has_spare_cid(&self) -> bool {
+        matches!(self.cid_deque.get(self.cursor), Some(Some(_)))
+    }
+
     fn apply_dcid(&mut self) -> ArcCidCell<RETIRED> {
         let state = if let Some(Some((_, cid, _))) = self.cid_deque.get(self.cursor) {
             self.cursor += 1;
@@ -233,6 +288,49 @@ where
     pub fn apply_dcid(&self) -> ArcCidCell<RETIRED> {
         self.0.lock().unwrap().apply_dcid()
     }
+
+    /// The connection ID with sequence number 0, None once it is retired. For the client,
+    /// it is the source connection ID of the server's Initial packets once one arrives,
+    /// see [`revise_initial_dcid`].
+    ///
+    /// [`revise_initial_dcid`]: ArcRemoteCids::revise_initial_dcid
+    pub fn initial_dcid(&self) -> Option<ConnectionId> {
+        self.0.lock().unwrap().initial_dcid()
+    }
+
+    /// Set the stateless reset token of the initial connection ID, which the server gives in
+    /// the stateless_reset_token transport parameter.
+    pub fn set_initial_reset_token(&self, token: ResetToken) {
+        self.0.lock().unwrap().set_initial_reset_token(token);
+    }
+
+    /// Whether `token` is the stateless reset token of a connection ID a path is using. The
+    /// tokens of the connection IDs not used yet or retired already must not be checked, see
+    /// [section 10.3.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3.1) of RFC 9000.
+    pub fn is_active_reset_token(&self, token: &ResetToken) -> bool {
+        self.0.lock().unwrap().is_active_reset_token(token)
+    }
+
+    /// Whether a connection ID issued by the peer has never been used, so that
+    /// [`apply_dcid`] is ready at once. A new path should use such a fresh one,
+    /// to avoid being linked to the other paths by the peer's connection ID.
+    ///
+    /// [`apply_dcid`]: ArcRemoteCids::apply_dcid
+    pub fn has_spare_cid(&self) -> bool {
+        self.0.lock().unwrap().has_spare_cid()
+    }
+
+    /// The connection IDs issued by the peer which the paths are using right now. They
+    /// change as the peer issues new ones and retires the old ones.
+    pub fn in_use_cids(&self) -> Vec<ConnectionId> {
+        self.0.lock().unwrap().in_use_cids()
+    }
+
+    /// Return the number of times a path was forced to switch to a new connection ID,
+    /// because the peer retired the connection ID it was using.
+    pub fn forced_switches(&self) -> u64 {
+        self.0.lock().unwrap().forced_switches
+    }
 }
 
 impl<RETIRED> ReceiveFrame<NewConnectionIdFrame> for ArcRemoteCids<RETIRED>
@@ -264,6 +362,16 @@ impl CidState {
         _ = self.0.write(cid);
     }
 
+    fn replace(&mut self, cid: ConnectionId) {
+        // Only allow transition from Ready state to Ready state
+        debug_assert!(self.0.is_ready());
+        _ = self.0.write(cid);
+    }
+
+    fn is_ready(&self) -> bool {
+        self.0.is_ready()
+    }
+
     fn clear(&mut self) {
         // Allow transition from Ready state to None state, but not from Closed state
         // While meeting Demand state, it will not change && not wake the waker
@@ -287,10 +395,40 @@ where
     retired_cids: RETIRED,
     // The sequence number of the connection ID had beed assigned or to be allocated
     seq: u64,
+    // The sequence number of the connection ID retired by the peer but still in use,
+    // because no new connection ID has been assigned to replace it yet
+    retiring: Option<u64>,
     #[deref]
     state: CidState,
 }
 
+impl<RETIRED> CidCell<RETIRED>
+where
+    RETIRED: SendFrame<RetireConnectionIdFrame>,
+{
+    /// Assign a new connection ID to the cell. If the cell is still using a connection ID
+    /// retired by the peer, switch to the new one, and then inform the peer to retire the
+    /// old one, so that the RetireConnectionIdFrame never goes ahead of the switch.
+    ///
+    /// Return true if it is a forced switch.
+    fn assign(&mut self, cid: ConnectionId) -> bool {
+        match self.retiring.take() {
+            Some(retiring) => {
+                self.state.replace(cid);
+                self.retired_cids.send_frame([RetireConnectionIdFrame {
+                    sequence: VarInt::from_u64(retiring)
+                        .expect("Sequence of connection id is very hard to exceed VARINT_MAX"),
+                }]);
+                true
+            }
+            None => {
+                self.state.assign(cid);
+                false
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ArcCidCell<RETIRED>(Arc<Mutex<CidCell<RETIRED>>>)
 where
@@ -304,6 +442,7 @@ where
         Self(Arc::new(Mutex::new(CidCell {
             retired_cids,
             seq,
+            retiring: None,
             state,
         })))
     }
@@ -326,11 +465,14 @@ where
 
         if !guard.state.is_retired() {
             guard.state.retire();
-            let sequence = VarInt::try_from(guard.seq)
-                .expect("Sequence of connection id is very hard to exceed VARINT_MAX");
+            let retiring = guard.retiring.take();
+            let sequences = retiring.into_iter().chain([guard.seq]).map(|seq| {
+                VarInt::try_from(seq)
+                    .expect("Sequence of connection id is very hard to exceed VARINT_MAX")
+            });
             guard
                 .retired_cids
-                .send_frame([RetireConnectionIdFrame { sequence }]);
+                .send_frame(sequences.map(|sequence| RetireConnectionIdFrame { sequence }));
         }
     }
 }
@@ -395,6 +537,64 @@ mod tests {
         // insufficient, it will still return Pending.
         let cid_apply2 = remote_cids.apply_dcid();
         assert_eq!(cid_apply2.get_cid().poll_unpin(&mut cx), Poll::Pending);
+        // 尚未分配到连接ID的路径不算在内
+        assert_eq!(remote_cids.in_use_cids(), [initial_dcid, cid]);
+        cid_apply0.retire();
+        assert_eq!(remote_cids.in_use_cids(), [cid]);
+    }
+
+    #[test]
+    fn test_active_reset_token() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = ArcAsyncDeque::<RetireConnectionIdFrame>::new();
+        let mut remote_cids = RawRemoteCids::new(initial_dcid, 8, retired_cids);
+        let initial_token = ResetToken::random_gen();
+        let cid_apply0 = remote_cids.apply_dcid();
+        assert!(!remote_cids.is_active_reset_token(&ResetToken::default()));
+        assert!(!remote_cids.is_active_reset_token(&initial_token));
+        remote_cids.set_initial_reset_token(initial_token);
+        assert!(remote_cids.is_active_reset_token(&initial_token));
+
+        // 尚未用上的连接ID的令牌不算
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: ConnectionId::random_gen(8),
+            reset_token: ResetToken::random_gen(),
+        };
+        assert!(remote_cids.recv_new_cid_frame(&frame).is_ok());
+        assert!(!remote_cids.is_active_reset_token(&frame.reset_token));
+        let _cid_apply1 = remote_cids.apply_dcid();
+        assert!(remote_cids.is_active_reset_token(&frame.reset_token));
+
+        // 退役了的连接ID的令牌也不算
+        cid_apply0.retire();
+        assert!(!remote_cids.is_active_reset_token(&initial_token));
+    }
+
+    #[test]
+    fn test_spare_cid() {
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = ArcAsyncDeque::<RetireConnectionIdFrame>::new();
+        let mut remote_cids = RawRemoteCids::new(initial_dcid, 8, retired_cids);
+        assert!(remote_cids.has_spare_cid());
+        let _cid_apply0 = remote_cids.apply_dcid();
+        assert!(!remote_cids.has_spare_cid());
+
+        for seq in 1..3 {
+            let frame = NewConnectionIdFrame {
+                sequence: VarInt::from_u32(seq),
+                retire_prior_to: VarInt::from_u32(0),
+                id: ConnectionId::random_gen(8),
+                reset_token: ResetToken::random_gen(),
+            };
+            assert!(remote_cids.recv_new_cid_frame(&frame).is_ok());
+        }
+        assert!(remote_cids.has_spare_cid());
+        let _cid_apply1 = remote_cids.apply_dcid();
+        assert!(remote_cids.has_spare_cid());
+        let _cid_apply2 = remote_cids.apply_dcid();
+        assert!(!remote_cids.has_spare_cid());
     }
 
     #[test]
@@ -436,12 +636,13 @@ mod tests {
         guard.retire_prior_to(4);
         assert_eq!(guard.cid_deque.offset(), 4);
         assert_eq!(guard.cid_cells.offset(), 4);
-        assert_eq!(guard.retired_cids.len(), 4);
+        // cid 0 and 1 are in use, they won't be retired until new cids are assigned
+        assert_eq!(guard.retired_cids.len(), 2);
 
         assert_eq!(cid_apply1.0.lock().unwrap().seq, 4);
         assert_eq!(cid_apply2.0.lock().unwrap().seq, 5);
 
-        for i in 0..4 {
+        for i in 2..4 {
             assert_eq!(
                 guard.retired_cids.poll_pop(&mut cx),
                 Poll::Ready(Some(RetireConnectionIdFrame {
@@ -450,8 +651,15 @@ mod tests {
             );
         }
 
-        assert_eq!(cid_apply1.get_cid().poll_unpin(&mut cx), Poll::Pending);
-        assert_eq!(cid_apply2.get_cid().poll_unpin(&mut cx), Poll::Pending);
+        // keep using the old cids, instead of stalling
+        assert_eq!(
+            cid_apply1.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cids[0]))
+        );
+        assert_eq!(
+            cid_apply2.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cids[1]))
+        );
 
         guard.arrange_idle_cid();
         assert_eq!(
@@ -462,6 +670,15 @@ mod tests {
             cid_apply2.get_cid().poll_unpin(&mut cx),
             Poll::Ready(Some(cids[5]))
         );
+        assert_eq!(guard.forced_switches, 2);
+        for i in 0..2 {
+            assert_eq!(
+                guard.retired_cids.poll_pop(&mut cx),
+                Poll::Ready(Some(RetireConnectionIdFrame {
+                    sequence: VarInt::from_u32(i),
+                }))
+            );
+        }
 
         cid_apply2.retire();
         assert_eq!(guard.retired_cids.len(), 1);
@@ -473,6 +690,125 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_retire_active_cid_with_replacement() {
+        let waker = futures::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = ArcAsyncDeque::<RetireConnectionIdFrame>::new();
+        let remote_cids = ArcRemoteCids::new(initial_dcid, 8, retired_cids.clone());
+
+        let cid_apply = remote_cids.apply_dcid();
+        assert_eq!(
+            cid_apply.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(initial_dcid))
+        );
+
+        // The peer retires the cid in use, and provides a replacement in the same frame
+        let cid = ConnectionId::random_gen(8);
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(1),
+            id: cid,
+            reset_token: ResetToken::random_gen(),
+        };
+        assert!(remote_cids.recv_frame(&frame).is_ok());
+
+        // switch to the new cid, and then retire the old one
+        assert_eq!(
+            cid_apply.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cid))
+        );
+        assert_eq!(remote_cids.forced_switches(), 1);
+        assert_eq!(retired_cids.len(), 1);
+        assert_eq!(
+            retired_cids.poll_pop(&mut cx),
+            Poll::Ready(Some(RetireConnectionIdFrame {
+                sequence: VarInt::from_u32(0),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_retire_active_cid_without_replacement() {
+        let waker = futures::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let initial_dcid = ConnectionId::random_gen(8);
+        let retired_cids = ArcAsyncDeque::<RetireConnectionIdFrame>::new();
+        let remote_cids = ArcRemoteCids::new(initial_dcid, 8, retired_cids.clone());
+
+        let cid1 = ConnectionId::random_gen(8);
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(1),
+            retire_prior_to: VarInt::from_u32(0),
+            id: cid1,
+            reset_token: ResetToken::random_gen(),
+        };
+        assert!(remote_cids.recv_frame(&frame).is_ok());
+
+        let cid_apply0 = remote_cids.apply_dcid();
+        let cid_apply1 = remote_cids.apply_dcid();
+        assert_eq!(
+            cid_apply0.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(initial_dcid))
+        );
+        assert_eq!(
+            cid_apply1.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cid1))
+        );
+
+        // The peer retires both cids in use, but only provides one replacement
+        let cid2 = ConnectionId::random_gen(8);
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(2),
+            retire_prior_to: VarInt::from_u32(2),
+            id: cid2,
+            reset_token: ResetToken::random_gen(),
+        };
+        assert!(remote_cids.recv_frame(&frame).is_ok());
+
+        assert_eq!(
+            cid_apply0.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cid2))
+        );
+        // No replacement for the second path, keep using the old one without stalling,
+        // and the old one must not be retired yet.
+        assert_eq!(
+            cid_apply1.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cid1))
+        );
+        assert_eq!(remote_cids.forced_switches(), 1);
+        assert_eq!(retired_cids.len(), 1);
+        assert_eq!(
+            retired_cids.poll_pop(&mut cx),
+            Poll::Ready(Some(RetireConnectionIdFrame {
+                sequence: VarInt::from_u32(0),
+            }))
+        );
+
+        // The replacement arrives later, switch to it and retire the old one
+        let cid3 = ConnectionId::random_gen(8);
+        let frame = NewConnectionIdFrame {
+            sequence: VarInt::from_u32(3),
+            retire_prior_to: VarInt::from_u32(2),
+            id: cid3,
+            reset_token: ResetToken::random_gen(),
+        };
+        assert!(remote_cids.recv_frame(&frame).is_ok());
+        assert_eq!(
+            cid_apply1.get_cid().poll_unpin(&mut cx),
+            Poll::Ready(Some(cid3))
+        );
+        assert_eq!(remote_cids.forced_switches(), 2);
+        assert_eq!(
+            retired_cids.poll_pop(&mut cx),
+            Poll::Ready(Some(RetireConnectionIdFrame {
+                sequence: VarInt::from_u32(1),
+            }))
+        );
+        assert!(retired_cids.is_empty());
+    }
+
     #[test]
     fn test_retire_without_apply() {
         let waker = futures::task::noop_waker();
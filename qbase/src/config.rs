@@ -1,7 +1,42 @@
+// 客户端与服务端的构建器，转发共有传输参数的设置
+macro_rules! forward_common_setters {
+    () => {
+        forward_common_setters! {
+            max_idle_timeout: Duration,
+            max_udp_payload_size: VarInt,
+            initial_max_data: VarInt,
+            initial_max_stream_data_bidi_local: VarInt,
+            initial_max_stream_data_bidi_remote: VarInt,
+            initial_max_stream_data_uni: VarInt,
+            initial_max_streams_bidi: VarInt,
+            initial_max_streams_uni: VarInt,
+            ack_delay_exponent: VarInt,
+            max_ack_delay: VarInt,
+            min_ack_delay: VarInt,
+            address_discovery: AddressDiscovery,
+            disable_active_migration: bool,
+            active_connection_id_limit: VarInt,
+            initial_source_connection_id: ConnectionId,
+            max_datagram_frame_size: VarInt,
+            grease_quic_bit: bool,
+        }
+    };
+    ($($field:ident: $ty:ty),+ $(,)?) => {
+        $(
+            pub fn $field<V: Into<$ty>>(&mut self, value: V) -> &mut Self {
+                self.common.$field(value);
+                self
+            }
+        )+
+    };
+}
+
 mod client;
+mod remembered;
+mod remote;
 mod server;
 use std::{
-    net::{SocketAddrV4, SocketAddrV6},
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
     time::Duration,
 };
 
@@ -10,26 +45,23 @@ pub use client::*;
 // QUIC的config配置
 use derive_builder::*;
 use getset::{Getters, MutGetters, Setters, *};
+pub use remembered::*;
+pub use remote::*;
 pub use server::*;
 
 use super::varint::VarInt;
-use crate::{cid::ConnectionId, generate_validate, token::ResetToken};
+use crate::{cid::ConnectionId, token::ResetToken, util::canonical_addr};
 
+/// The transport parameters both the client and the server may send, the core view that the
+/// streams, the flow control and the datagrams consume.
+///
+/// The ones only the server may send are added by [`ServerParameters`], the ones received
+/// from the peer are wrapped in [`RemoteParameters`].
 #[derive(Builder, Getters, CopyGetters, Setters, MutGetters, Debug, Clone, Copy, PartialEq)]
-#[builder(
-    default,
-    setter(strip_option, into,),
-    build_fn(skip),
-    name = "CommonBuilder"
-)]
-pub struct Parameters {
-    #[getset(get = "pub", set = "pub")]
-    original_destination_connection_id: Option<ConnectionId>,
+#[builder(default, setter(strip_option, into,), build_fn(skip))]
+pub struct CommonParameters {
     #[getset(get_copy = "pub", set = "pub")]
     max_idle_timeout: Duration,
-
-    #[getset(get = "pub", set = "pub")]
-    statelss_reset_token: Option<ResetToken>,
     #[getset(get_copy = "pub", set = "pub")]
     max_udp_payload_size: VarInt,
     #[getset(get_copy = "pub", set = "pub")]
@@ -48,30 +80,35 @@ pub struct Parameters {
     ack_delay_exponent: VarInt,
     #[getset(get_copy = "pub", set = "pub")]
     max_ack_delay: VarInt,
+    /// The minimum ack delay in microseconds, the peer can ask for an ack delay no less
+    /// than it by the ACK_FREQUENCY frame, None disables the ack frequency extension.
     #[getset(get_copy = "pub", set = "pub")]
-    disable_active_migration: bool,
+    min_ack_delay: Option<VarInt>,
+    /// Whether to report the observed address to the peer and/or receive the reports,
+    /// None disables the address discovery extension.
+    #[getset(get_copy = "pub", set = "pub")]
+    address_discovery: Option<AddressDiscovery>,
     #[getset(get_copy = "pub", set = "pub")]
-    preferred_address: Option<PreferredAddress>,
+    disable_active_migration: bool,
     #[getset(get_copy = "pub", set = "pub")]
     active_connection_id_limit: VarInt,
 
     #[getset(get_copy = "pub", set = "pub")]
     initial_source_connection_id: Option<ConnectionId>,
     #[getset(get_copy = "pub", set = "pub")]
-    retry_source_connection_id: Option<ConnectionId>,
-    #[getset(get_copy = "pub", set = "pub")]
     max_datagram_frame_size: VarInt,
     // TOOD: 对此传输参数的支持
     #[getset(get_copy = "pub", set = "pub")]
     grease_quic_bit: bool,
 }
 
-impl Default for Parameters {
+/// The former name of [`CommonParameters`], kept for compatibility.
+pub type Parameters = CommonParameters;
+
+impl Default for CommonParameters {
     fn default() -> Self {
         Self {
-            original_destination_connection_id: None,
             max_idle_timeout: Duration::from_secs(10_000),
-            statelss_reset_token: None,
             max_udp_payload_size: VarInt::from_u32(1472), // 65535 - 8
             initial_max_data: VarInt::from_u32(65536),
             initial_max_stream_data_bidi_local: VarInt::from_u32(1_250_000),
@@ -81,41 +118,154 @@ impl Default for Parameters {
             initial_max_streams_uni: VarInt::from_u32(10),
             ack_delay_exponent: VarInt::from_u32(3),
             max_ack_delay: VarInt::from_u32(1000),
+            min_ack_delay: Some(VarInt::from_u32(1000)),
+            address_discovery: None,
             disable_active_migration: false,
-            preferred_address: None,
             active_connection_id_limit: VarInt::from_u32(2),
             initial_source_connection_id: None,
-            retry_source_connection_id: None,
             max_datagram_frame_size: VarInt::from_u32(65535),
             grease_quic_bit: false,
         }
     }
 }
 
-#[macro_export(local_inner_macros)]
-macro_rules! generate_validate {
-    ($t:ty) => {
-        impl $t {
-            pub fn validate(&self) -> Result<(), &'static str> {
-                if !(1200..65527).contains(&self.max_udp_payload_size.into_inner()) {
-                    return Err("max_udp_payload_size must be at least 1200 bytes");
-                }
-                if self.ack_delay_exponent > 20 {
-                    return Err("ack_delay_exponent must be at most 20");
-                }
-                if self.max_ack_delay > 1 << 14 {
-                    return Err("max_ack_delay must be at most 2^14");
-                }
-                if self.active_connection_id_limit < 2 {
-                    return Err("active_connection_id_limit must be at least 2");
-                }
-                Ok(())
+impl CommonParameters {
+    /// The values of the transport parameters absent from the peer, see
+    /// [section 18.2](https://www.rfc-editor.org/rfc/rfc9000.html#name-transport-parameter-definit)
+    /// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) and the extensions.
+    ///
+    /// They are not the [`Default`], which is what this endpoint prefers to send.
+    pub fn spec_default() -> Self {
+        Self {
+            max_idle_timeout: Duration::ZERO,
+            max_udp_payload_size: VarInt::from_u32(65527),
+            initial_max_data: VarInt::from_u32(0),
+            initial_max_stream_data_bidi_local: VarInt::from_u32(0),
+            initial_max_stream_data_bidi_remote: VarInt::from_u32(0),
+            initial_max_stream_data_uni: VarInt::from_u32(0),
+            initial_max_streams_bidi: VarInt::from_u32(0),
+            initial_max_streams_uni: VarInt::from_u32(0),
+            ack_delay_exponent: VarInt::from_u32(3),
+            max_ack_delay: VarInt::from_u32(25),
+            // 未携带min_ack_delay的对端不支持ACK_FREQUENCY扩展
+            min_ack_delay: None,
+            address_discovery: None,
+            disable_active_migration: false,
+            active_connection_id_limit: VarInt::from_u32(2),
+            initial_source_connection_id: None,
+            // 未携带max_datagram_frame_size的对端不支持DATAGRAM帧
+            max_datagram_frame_size: VarInt::from_u32(0),
+            grease_quic_bit: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !(1200..=65527).contains(&self.max_udp_payload_size.into_inner()) {
+            return Err("max_udp_payload_size must be at least 1200 bytes");
+        }
+        if self.ack_delay_exponent > 20 {
+            return Err("ack_delay_exponent must be at most 20");
+        }
+        if self.max_ack_delay >= 1 << 14 {
+            return Err("max_ack_delay must be less than 2^14");
+        }
+        if let Some(min_ack_delay) = self.min_ack_delay {
+            if min_ack_delay >= 1 << 24 {
+                return Err("min_ack_delay must be less than 2^24");
+            }
+            // min_ack_delay的单位是微秒，max_ack_delay是毫秒
+            if min_ack_delay > self.max_ack_delay.into_inner() * 1000 {
+                return Err("min_ack_delay must not exceed max_ack_delay");
             }
         }
-    };
+        if self.active_connection_id_limit < 2 {
+            return Err("active_connection_id_limit must be at least 2");
+        }
+        Ok(())
+    }
+}
+
+impl CommonParametersBuilder {
+    // 未设置的取本端的默认值，由各自角色的构建器校验
+    fn build_or_default(&self) -> CommonParameters {
+        let default = CommonParameters::default();
+        CommonParameters {
+            max_idle_timeout: self.max_idle_timeout.unwrap_or(default.max_idle_timeout),
+            max_udp_payload_size: self
+                .max_udp_payload_size
+                .unwrap_or(default.max_udp_payload_size),
+            initial_max_data: self.initial_max_data.unwrap_or(default.initial_max_data),
+            initial_max_stream_data_bidi_local: self
+                .initial_max_stream_data_bidi_local
+                .unwrap_or(default.initial_max_stream_data_bidi_local),
+            initial_max_stream_data_bidi_remote: self
+                .initial_max_stream_data_bidi_remote
+                .unwrap_or(default.initial_max_stream_data_bidi_remote),
+            initial_max_stream_data_uni: self
+                .initial_max_stream_data_uni
+                .unwrap_or(default.initial_max_stream_data_uni),
+            initial_max_streams_bidi: self
+                .initial_max_streams_bidi
+                .unwrap_or(default.initial_max_streams_bidi),
+            initial_max_streams_uni: self
+                .initial_max_streams_uni
+                .unwrap_or(default.initial_max_streams_uni),
+            ack_delay_exponent: self
+                .ack_delay_exponent
+                .unwrap_or(default.ack_delay_exponent),
+            max_ack_delay: self.max_ack_delay.unwrap_or(default.max_ack_delay),
+            min_ack_delay: self.min_ack_delay.unwrap_or(default.min_ack_delay),
+            address_discovery: self.address_discovery.unwrap_or(default.address_discovery),
+            disable_active_migration: self
+                .disable_active_migration
+                .unwrap_or(default.disable_active_migration),
+            active_connection_id_limit: self
+                .active_connection_id_limit
+                .unwrap_or(default.active_connection_id_limit),
+            initial_source_connection_id: self
+                .initial_source_connection_id
+                .unwrap_or(default.initial_source_connection_id),
+            max_datagram_frame_size: self
+                .max_datagram_frame_size
+                .unwrap_or(default.max_datagram_frame_size),
+            grease_quic_bit: self.grease_quic_bit.unwrap_or(default.grease_quic_bit),
+        }
+    }
+}
+
+/// The value of the address discovery transport parameter, see
+/// [draft-ietf-quic-address-discovery](https://datatracker.ietf.org/doc/html/draft-ietf-quic-address-discovery).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressDiscovery {
+    /// Willing to report the observed addresses to the peer, but not interested in its own.
+    Provide = 0,
+    /// Interested in the address observed by the peer, but not willing to report.
+    Receive = 1,
+    Both = 2,
+}
+
+impl AddressDiscovery {
+    pub fn provides(self) -> bool {
+        matches!(self, AddressDiscovery::Provide | AddressDiscovery::Both)
+    }
+
+    pub fn receives(self) -> bool {
+        matches!(self, AddressDiscovery::Receive | AddressDiscovery::Both)
+    }
 }
 
-generate_validate!(Parameters);
+impl TryFrom<VarInt> for AddressDiscovery {
+    type Error = VarInt;
+
+    fn try_from(value: VarInt) -> Result<Self, Self::Error> {
+        match value.into_inner() {
+            0 => Ok(AddressDiscovery::Provide),
+            1 => Ok(AddressDiscovery::Receive),
+            2 => Ok(AddressDiscovery::Both),
+            _ => Err(value),
+        }
+    }
+}
 
 #[derive(Getters, Setters, MutGetters, Debug, PartialEq, Clone, Copy)]
 pub struct PreferredAddress {
@@ -133,169 +283,194 @@ impl PreferredAddress {
     pub fn encoding_size(&self) -> usize {
         6 + 18 + self.connection_id.encoding_size() + self.stateless_reset_token.encoding_size()
     }
+
+    /// The addresses to migrate to in the canonical form, see [`canonical_addr`].
+    ///
+    /// The unspecified address means the server doesn't prefer the address family, it is
+    /// skipped. The address_v6 mapped from the address_v4 is the same address, it is
+    /// reported once.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        let mut addresses = Vec::with_capacity(2);
+        for addr in [
+            SocketAddr::V4(self.address_v4),
+            SocketAddr::V6(self.address_v6),
+        ] {
+            let addr = canonical_addr(addr);
+            if !addr.ip().is_unspecified() && !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses
+    }
 }
 
 pub mod ext {
-    use std::time::Duration;
-
     use bytes::BufMut;
-    use nom::{bytes::complete::take, combinator::map};
+    use nom::combinator::map;
 
-    use super::{Parameters, PreferredAddress};
+    use super::{
+        ClientParameters, CommonParameters, ParameterId, PreferredAddress, ServerParameters,
+    };
     use crate::{
-        cid::{be_connection_id, ConnectionId, WriteConnectionId, MAX_CID_SIZE},
-        token::{be_reset_token, ResetToken, WriteResetToken},
-        varint::{be_varint, VarInt, WriteVarInt},
+        cid::{be_connection_id, ConnectionId, WriteConnectionId},
+        token::{be_reset_token, WriteResetToken},
+        varint::{VarInt, WriteVarInt},
+        version::{VersionInformation, WriteVersionInformation},
     };
 
-    pub fn be_parameters(input: &[u8]) -> nom::IResult<&[u8], Parameters> {
-        let be_connection_id = |input, len: VarInt| {
-            let len = len.into_inner() as usize;
-            if len > MAX_CID_SIZE {
-                return Err(nom::Err::Error(nom::error::make_error(
-                    input,
-                    nom::error::ErrorKind::TooLarge,
-                )));
-            }
-            let (remain, bytes) = take(len)(input)?;
-            Ok((remain, Some(ConnectionId::from_slice(bytes))))
-        };
+    /// Encode the transport parameters of each role, the client's can't carry the ones only
+    /// the server may send.
+    pub trait WriteParameters {
+        fn put_client_parameters(&mut self, params: &ClientParameters);
+        fn put_server_parameters(&mut self, params: &ServerParameters);
+        fn put_preferred_address(&mut self, addr: &PreferredAddress);
+        fn put_version_information_parameter(&mut self, info: &VersionInformation);
+    }
 
-        let be_max_idle_timeout = |input| {
-            let (remain, timeout) = be_varint(input)?;
-            Ok((remain, Duration::from_secs(timeout.into_inner())))
-        };
+    fn put_varint_parameter<B: BufMut>(buf: &mut B, id: ParameterId, varint: VarInt) {
+        buf.put_varint(&id.into());
+        buf.put_varint(&VarInt::from_u32(varint.encoding_size() as u32));
+        buf.put_varint(&varint);
+    }
 
-        let be_preferred_address = |input| {
-            let (remain, addr) = be_preferred_address(input)?;
-            Ok((remain, Some(addr)))
-        };
+    fn put_connection_id_parameter<B: BufMut>(buf: &mut B, id: ParameterId, cid: &ConnectionId) {
+        buf.put_varint(&id.into());
+        buf.put_connection_id(cid);
+    }
 
-        let be_reset_token = |input| {
-            let (remain, token) = be_reset_token(input)?;
-            Ok((remain, Some(token)))
+    // 与缺省值相同的传输参数不必发送，对方会按缺省值理解；与之不同的则必须发送，哪怕是0
+    fn put_common_parameters<B: BufMut>(buf: &mut B, params: &CommonParameters) {
+        let absent = CommonParameters::spec_default();
+        let put_varint = |buf: &mut B, id: ParameterId, varint: VarInt, absent: VarInt| {
+            if varint != absent {
+                put_varint_parameter(buf, id, varint);
+            }
         };
 
-        let mut remain = input;
-        let mut tp = Parameters::default();
-        while !remain.is_empty() {
-            let tag: VarInt;
-            let len: VarInt;
-            (remain, tag) = be_varint(remain)?;
-            (remain, len) = be_varint(remain)?;
-            match tag.into_inner() {
-                0x00 => {
-                    (remain, tp.original_destination_connection_id) = be_connection_id(remain, len)?
-                }
-                0x01 => (remain, tp.max_idle_timeout) = be_max_idle_timeout(remain)?,
-                0x02 => (remain, tp.statelss_reset_token) = be_reset_token(remain)?,
-                0x03 => (remain, tp.max_udp_payload_size) = be_varint(remain)?,
-                0x04 => (remain, tp.initial_max_data) = be_varint(remain)?,
-                0x05 => (remain, tp.initial_max_stream_data_bidi_local) = be_varint(remain)?,
-                0x06 => (remain, tp.initial_max_stream_data_bidi_remote) = be_varint(remain)?,
-                0x07 => (remain, tp.initial_max_stream_data_uni) = be_varint(remain)?,
-                0x08 => (remain, tp.initial_max_streams_bidi) = be_varint(remain)?,
-                0x09 => (remain, tp.initial_max_streams_uni) = be_varint(remain)?,
-                0x0a => (remain, tp.ack_delay_exponent) = be_varint(remain)?,
-                0x0b => (remain, tp.max_ack_delay) = be_varint(remain)?,
-                0x0c => tp.disable_active_migration = true,
-                0x0d => (remain, tp.preferred_address) = be_preferred_address(remain)?,
-                0x0e => (remain, tp.active_connection_id_limit) = be_varint(remain)?,
-                0x0f => (remain, tp.initial_source_connection_id) = be_connection_id(remain, len)?,
-                0x10 => (remain, tp.retry_source_connection_id) = be_connection_id(remain, len)?,
-                0x20 => (remain, tp.max_datagram_frame_size) = be_varint(remain)?,
-                // 0x2ab2 => tp.grease_quic_bit = true,
-                _ => {
-                    // Ref. `<https://www.rfc-editor.org/rfc/rfc9000.html#name-new-transport-parameters>
-                    // An endpoint MUST ignore transport parameters that it does not support.
-
-                    // take it, and ignore it
-                    (remain, ..) = take(len)(remain)?;
-                }
-            }
+        if params.max_idle_timeout != absent.max_idle_timeout {
+            let max_idle_timeout = VarInt::from_u64(params.max_idle_timeout.as_secs())
+                .expect("max_idle timeout can not exceed 2^62 seconds");
+            put_varint_parameter(buf, ParameterId::MaxIdleTimeout, max_idle_timeout);
         }
-
-        Ok((remain, tp))
-    }
-
-    pub trait WriteParameters {
-        fn put_parameters(&mut self, params: &Parameters);
-        fn put_preferred_address(&mut self, addr: &super::PreferredAddress);
+        put_varint(
+            buf,
+            ParameterId::MaxUdpPayloadSize,
+            params.max_udp_payload_size,
+            absent.max_udp_payload_size,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxData,
+            params.initial_max_data,
+            absent.initial_max_data,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxStreamDataBidiLocal,
+            params.initial_max_stream_data_bidi_local,
+            absent.initial_max_stream_data_bidi_local,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxStreamDataBidiRemote,
+            params.initial_max_stream_data_bidi_remote,
+            absent.initial_max_stream_data_bidi_remote,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxStreamDataUni,
+            params.initial_max_stream_data_uni,
+            absent.initial_max_stream_data_uni,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxStreamsBidi,
+            params.initial_max_streams_bidi,
+            absent.initial_max_streams_bidi,
+        );
+        put_varint(
+            buf,
+            ParameterId::InitialMaxStreamsUni,
+            params.initial_max_streams_uni,
+            absent.initial_max_streams_uni,
+        );
+        put_varint(
+            buf,
+            ParameterId::AckDelayExponent,
+            params.ack_delay_exponent,
+            absent.ack_delay_exponent,
+        );
+        put_varint(
+            buf,
+            ParameterId::MaxAckDelay,
+            params.max_ack_delay,
+            absent.max_ack_delay,
+        );
+        if params.disable_active_migration {
+            buf.put_varint(&ParameterId::DisableActiveMigration.into());
+            buf.put_u8(0);
+        }
+        put_varint(
+            buf,
+            ParameterId::ActiveConnectionIdLimit,
+            params.active_connection_id_limit,
+            absent.active_connection_id_limit,
+        );
+        if let Some(cid) = &params.initial_source_connection_id {
+            put_connection_id_parameter(buf, ParameterId::InitialSourceConnectionId, cid);
+        }
+        put_varint(
+            buf,
+            ParameterId::MaxDatagramFrameSize,
+            params.max_datagram_frame_size,
+            absent.max_datagram_frame_size,
+        );
+        if let Some(min_ack_delay) = params.min_ack_delay {
+            put_varint_parameter(buf, ParameterId::MinAckDelay, min_ack_delay);
+        }
+        if let Some(discovery) = params.address_discovery {
+            put_varint_parameter(
+                buf,
+                ParameterId::AddressDiscovery,
+                VarInt::from_u32(discovery as u32),
+            );
+        }
+        // if params.grease_quic_bit {
+        //     self.put_varint(&VarInt::from_u32(0x2ab2));
+        //     self.put_u8(0);
+        // }
     }
 
     impl<T: BufMut> WriteParameters for T {
-        fn put_parameters(&mut self, params: &Parameters) {
-            let put_varint = |buf: &mut Self, tag: u8, varint: VarInt| {
-                if varint.into_inner() > 0 {
-                    buf.put_u8(tag);
-                    buf.put_varint(&unsafe {
-                        VarInt::from_u64_unchecked(varint.encoding_size() as u64)
-                    });
-                    buf.put_varint(&varint);
-                }
-            };
-
-            let put_connection_id = |buf: &mut Self, tag: u8, cid: &Option<ConnectionId>| {
-                if let Some(cid) = cid {
-                    buf.put_u8(tag);
-                    buf.put_connection_id(cid);
-                }
-            };
-
-            let put_reset_token = |buf: &mut Self, tag: u8, token: &Option<ResetToken>| {
-                if let Some(token) = token {
-                    buf.put_u8(tag);
-                    buf.put_varint(&unsafe {
-                        VarInt::from_u64_unchecked(token.encoding_size() as u64)
-                    });
-                    buf.put_reset_token(token);
-                }
-            };
-
-            let put_preferred_address =
-                |buf: &mut Self, tag: u8, addr: &Option<PreferredAddress>| {
-                    if let Some(addr) = addr {
-                        buf.put_u8(tag);
-                        buf.put_varint(&unsafe {
-                            VarInt::from_u64_unchecked(addr.encoding_size() as u64)
-                        });
-                        buf.put_preferred_address(addr);
-                    }
-                };
-
-            put_connection_id(self, 0x00, &params.original_destination_connection_id);
-            put_varint(
-                self,
-                0x01,
-                VarInt::from_u64(params.max_idle_timeout.as_secs())
-                    .expect("max_idle timeout can not exceed 2^62 seconds"),
-            );
-            put_reset_token(self, 0x02, &params.statelss_reset_token);
-            put_varint(self, 0x03, params.max_udp_payload_size);
-            put_varint(self, 0x04, params.initial_max_data);
-            put_varint(self, 0x05, params.initial_max_stream_data_bidi_local);
-            put_varint(self, 0x06, params.initial_max_stream_data_bidi_remote);
-            put_varint(self, 0x07, params.initial_max_stream_data_uni);
-            put_varint(self, 0x08, params.initial_max_streams_bidi);
-            put_varint(self, 0x09, params.initial_max_streams_uni);
-            put_varint(self, 0x0a, params.ack_delay_exponent);
-            put_varint(self, 0x0b, params.max_ack_delay);
-            if params.disable_active_migration {
-                self.put_u8(0x0c);
-                self.put_u8(0);
+        fn put_client_parameters(&mut self, params: &ClientParameters) {
+            put_common_parameters(self, params);
+        }
+
+        fn put_server_parameters(&mut self, params: &ServerParameters) {
+            if let Some(cid) = params.original_destination_connection_id() {
+                put_connection_id_parameter(
+                    self,
+                    ParameterId::OriginalDestinationConnectionId,
+                    cid,
+                );
+            }
+            if let Some(token) = params.statelss_reset_token() {
+                self.put_varint(&ParameterId::StatelessResetToken.into());
+                self.put_varint(&VarInt::from_u32(token.encoding_size() as u32));
+                self.put_reset_token(token);
             }
-            put_preferred_address(self, 0x0d, &params.preferred_address);
-            put_varint(self, 0x0e, params.active_connection_id_limit);
-            put_connection_id(self, 0x0f, &params.initial_source_connection_id);
-            put_connection_id(self, 0x10, &params.retry_source_connection_id);
-            put_varint(self, 0x20, params.max_datagram_frame_size);
-            // if params.grease_quic_bit {
-            //     self.put_varint(&VarInt::from_u32(0x2ab2));
-            //     self.put_u8(0);
-            // }
+            if let Some(addr) = &params.preferred_address() {
+                self.put_varint(&ParameterId::PreferredAddress.into());
+                self.put_varint(&VarInt::from_u32(addr.encoding_size() as u32));
+                self.put_preferred_address(addr);
+            }
+            if let Some(cid) = &params.retry_source_connection_id() {
+                put_connection_id_parameter(self, ParameterId::RetrySourceConnectionId, cid);
+            }
+            put_common_parameters(self, params);
         }
 
-        fn put_preferred_address(&mut self, addr: &super::PreferredAddress) {
+        fn put_preferred_address(&mut self, addr: &PreferredAddress) {
             self.put_slice(&addr.address_v4.ip().octets());
             self.put_u16(addr.address_v4.port());
 
@@ -305,9 +480,15 @@ pub mod ext {
             self.put_connection_id(&addr.connection_id);
             self.put_reset_token(&addr.stateless_reset_token);
         }
+
+        fn put_version_information_parameter(&mut self, info: &VersionInformation) {
+            self.put_varint(&ParameterId::VersionInformation.into());
+            self.put_varint(&VarInt::from_u32(info.encoding_size() as u32));
+            self.put_version_information(info);
+        }
     }
 
-    pub fn be_preferred_address(input: &[u8]) -> nom::IResult<&[u8], super::PreferredAddress> {
+    pub fn be_preferred_address(input: &[u8]) -> nom::IResult<&[u8], PreferredAddress> {
         use nom::bytes::streaming::take;
 
         let (input, address_v4) = map(take(6usize), |buf: &[u8]| {
@@ -329,7 +510,7 @@ pub mod ext {
 
         Ok((
             input,
-            super::PreferredAddress {
+            PreferredAddress {
                 address_v4,
                 address_v6,
                 connection_id,
@@ -343,8 +524,30 @@ pub mod ext {
 mod test {
     use std::net::Ipv4Addr;
 
+    use bytes::BufMut;
+
     use super::{ext::WriteParameters, *};
-    use crate::cid::{be_connection_id, RESET_TOKEN_SIZE};
+    use crate::{
+        cid::{be_connection_id, RESET_TOKEN_SIZE},
+        error::ErrorKind,
+        streamid::Role,
+        varint::WriteVarInt,
+        version::VersionInformation,
+    };
+
+    fn preferred_address(connection_id: ConnectionId) -> PreferredAddress {
+        PreferredAddress {
+            address_v4: SocketAddrV4::new(Ipv4Addr::new(0x01, 0x02, 0x03, 0x04), 0x1234),
+            address_v6: SocketAddrV6::new(
+                std::net::Ipv6Addr::new(0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08),
+                0x1234,
+                0,
+                0,
+            ),
+            connection_id,
+            stateless_reset_token: ResetToken::new(&[0x02; RESET_TOKEN_SIZE]),
+        }
+    }
 
     #[test]
     fn coding() {
@@ -363,31 +566,217 @@ mod test {
             .initial_max_streams_uni(VarInt::from_u32(10))
             .ack_delay_exponent(VarInt::from_u32(0x12))
             .max_ack_delay(VarInt::from_u32(0x98))
+            .min_ack_delay(VarInt::from_u32(0x1234))
+            .address_discovery(AddressDiscovery::Both)
             .disable_active_migration(true)
-            .preferred_address(PreferredAddress {
-                address_v4: SocketAddrV4::new(Ipv4Addr::new(0x01, 0x02, 0x03, 0x04), 0x1234),
-                address_v6: SocketAddrV6::new(
-                    std::net::Ipv6Addr::new(0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08),
-                    0x1234,
-                    0,
-                    0,
-                ),
-                connection_id: init_cid,
-                stateless_reset_token: ResetToken::new(&[0x02; RESET_TOKEN_SIZE]),
-            })
+            .preferred_address(preferred_address(init_cid))
             .active_connection_id_limit(VarInt::from_u32(0x1234))
             .initial_source_connection_id(init_cid)
             .retry_source_connection_id(init_cid)
             .max_datagram_frame_size(VarInt::from_u32(65535))
             .grease_quic_bit(false)
             .build()
-            .unwrap()
-            .into();
+            .unwrap();
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_server_parameters(&params);
+        let remote = RemoteParameters::decode(&buf, Role::Server).unwrap();
+        assert_eq!(*remote, *params);
+        assert_eq!(
+            remote.original_destination_connection_id().as_ref(),
+            params.original_destination_connection_id().as_ref()
+        );
+        assert_eq!(
+            remote.statelss_reset_token().as_ref(),
+            params.statelss_reset_token().as_ref()
+        );
+        assert_eq!(remote.preferred_address(), params.preferred_address());
+        assert_eq!(
+            remote.retry_source_connection_id(),
+            params.retry_source_connection_id()
+        );
+    }
+
+    #[test]
+    fn client_coding() {
+        let init_cid = be_connection_id(&[0x04, 0x01, 0x02, 0x03, 0x04]).unwrap().1;
+        let params = ClientParameters::builder()
+            .initial_max_data(VarInt::from_u32(0))
+            .initial_max_streams_bidi(VarInt::from_u32(0))
+            .initial_source_connection_id(init_cid)
+            .build()
+            .unwrap();
 
         let mut buf = bytes::BytesMut::new();
-        buf.put_parameters(&params);
-        let params2 = ext::be_parameters(&buf).unwrap().1;
-        assert_eq!(params, params2);
+        buf.put_client_parameters(&params);
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(*remote, *params);
+        for id in ParameterId::ALL
+            .into_iter()
+            .filter(|id| id.is_server_only())
+        {
+            assert!(!remote.is_present(id));
+        }
+        assert_eq!(remote.original_destination_connection_id(), None);
+        assert_eq!(remote.preferred_address(), None);
+    }
+
+    // 编码一个值为raw的传输参数
+    fn put_raw_parameter(buf: &mut bytes::BytesMut, id: impl Into<VarInt>, raw: &[u8]) {
+        buf.put_varint(&id.into());
+        buf.put_varint(&VarInt::from_u32(raw.len() as u32));
+        buf.put_slice(raw);
+    }
+
+    #[test]
+    fn server_only_parameters() {
+        let cid = [0x01, 0x02, 0x03, 0x04];
+        let token = [0x01; RESET_TOKEN_SIZE];
+        let mut preferred = bytes::BytesMut::new();
+        let init_cid = be_connection_id(&[0x04, 0x01, 0x02, 0x03, 0x04]).unwrap().1;
+        preferred.put_preferred_address(&preferred_address(init_cid));
+        let cases: [(ParameterId, &[u8]); 4] = [
+            (ParameterId::OriginalDestinationConnectionId, &cid),
+            (ParameterId::StatelessResetToken, &token),
+            (ParameterId::PreferredAddress, &preferred[..]),
+            (ParameterId::RetrySourceConnectionId, &cid),
+        ];
+
+        for (id, raw) in cases {
+            let mut buf = bytes::BytesMut::new();
+            put_raw_parameter(&mut buf, id, raw);
+
+            let error = RemoteParameters::decode(&buf, Role::Client).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::TransportParameter);
+
+            let remote = RemoteParameters::decode(&buf, Role::Server).unwrap();
+            assert!(remote.is_present(id));
+        }
+    }
+
+    #[test]
+    fn authenticate_server_cids() {
+        let odcid = ConnectionId::random_gen(8);
+        let initial_scid = ConnectionId::random_gen(8);
+        let retry_scid = ConnectionId::random_gen(8);
+        let decode = |retry_scid: Option<ConnectionId>| {
+            let mut params = ServerParameters::default();
+            params.set_original_destination_connection_id(Some(odcid));
+            params.set_initial_source_connection_id(Some(initial_scid));
+            params.set_retry_source_connection_id(retry_scid);
+            let mut buf = bytes::BytesMut::new();
+            buf.put_server_parameters(&params);
+            RemoteParameters::decode(&buf, Role::Server).unwrap()
+        };
+
+        let remote = decode(None);
+        assert!(remote
+            .authenticate_server_cids(&odcid, &initial_scid, None)
+            .is_ok());
+        // 客户端接受了Retry，服务端却没有给出retry_source_connection_id
+        assert!(remote
+            .authenticate_server_cids(&odcid, &initial_scid, Some(&retry_scid))
+            .is_err());
+        assert!(remote
+            .authenticate_server_cids(&retry_scid, &initial_scid, None)
+            .is_err());
+        assert!(remote
+            .authenticate_server_cids(&odcid, &retry_scid, None)
+            .is_err());
+
+        let remote = decode(Some(retry_scid));
+        assert!(remote
+            .authenticate_server_cids(&odcid, &initial_scid, Some(&retry_scid))
+            .is_ok());
+        assert!(remote
+            .authenticate_server_cids(&odcid, &initial_scid, None)
+            .is_err());
+        let error = remote
+            .authenticate_server_cids(&odcid, &initial_scid, Some(&odcid))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+    }
+
+    #[test]
+    fn version_information() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_client_parameters(&ClientParameters::default());
+        buf.put_version_information_parameter(&VersionInformation::local());
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(
+            remote.version_information(),
+            Some(&VersionInformation::local())
+        );
+
+        let remote = RemoteParameters::decode(&[], Role::Server).unwrap();
+        assert_eq!(remote.version_information(), None);
+
+        // 选定的版本为0，或者长度不是4的倍数
+        let cases: [&[u8]; 3] = [&[0, 0, 0, 0], &[0, 0, 0, 1, 0, 0], &[]];
+        for raw in cases {
+            let mut buf = bytes::BytesMut::new();
+            put_raw_parameter(&mut buf, ParameterId::VersionInformation, raw);
+            let error = RemoteParameters::decode(&buf, Role::Server).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::TransportParameter);
+        }
+    }
+
+    #[test]
+    fn repeated_parameter() {
+        let mut buf = bytes::BytesMut::new();
+        put_raw_parameter(&mut buf, ParameterId::InitialMaxData, &[0x01]);
+        put_raw_parameter(&mut buf, ParameterId::InitialMaxData, &[0x02]);
+        let error = RemoteParameters::decode(&buf, Role::Server).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TransportParameter);
+    }
+
+    #[test]
+    fn unknown_parameter() {
+        let mut buf = bytes::BytesMut::new();
+        // 保留给GREASE的传输参数，31 * N + 27，必须忽略
+        put_raw_parameter(&mut buf, ParameterId::InitialMaxData, &[0x01]);
+        put_raw_parameter(&mut buf, VarInt::from_u32(31 * 2 + 27), &[0xff, 0xff]);
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(remote.initial_max_data(), VarInt::from_u32(1));
+    }
+
+    #[test]
+    fn absent_or_default() {
+        // 没有携带的传输参数取RFC规定的缺省值，而非本端的缺省值
+        let remote = RemoteParameters::decode(&[], Role::Client).unwrap();
+        assert_eq!(remote.ack_delay_exponent(), VarInt::from_u32(3));
+        assert_eq!(remote.max_ack_delay(), VarInt::from_u32(25));
+        assert!(!remote.is_present(ParameterId::AckDelayExponent));
+        assert!(!remote.is_present(ParameterId::MaxAckDelay));
+
+        // 值为0也要发送，不能与缺省值混淆
+        let params = ClientParameters::builder()
+            .ack_delay_exponent(VarInt::from_u32(0))
+            .max_ack_delay(VarInt::from_u32(0))
+            .min_ack_delay(VarInt::from_u32(0))
+            .build()
+            .unwrap();
+        let mut buf = bytes::BytesMut::new();
+        buf.put_client_parameters(&params);
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(remote.ack_delay_exponent(), VarInt::from_u32(0));
+        assert_eq!(remote.max_ack_delay(), VarInt::from_u32(0));
+        assert!(remote.is_present(ParameterId::AckDelayExponent));
+        assert!(remote.is_present(ParameterId::MaxAckDelay));
+
+        // 与缺省值相同的不必发送
+        let params = ClientParameters::builder()
+            .ack_delay_exponent(VarInt::from_u32(3))
+            .max_ack_delay(VarInt::from_u32(25))
+            .build()
+            .unwrap();
+        let mut buf = bytes::BytesMut::new();
+        buf.put_client_parameters(&params);
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(remote.ack_delay_exponent(), VarInt::from_u32(3));
+        assert_eq!(remote.max_ack_delay(), VarInt::from_u32(25));
+        assert!(!remote.is_present(ParameterId::AckDelayExponent));
+        assert!(!remote.is_present(ParameterId::MaxAckDelay));
     }
 
     #[test]
@@ -406,11 +795,73 @@ mod test {
             .active_connection_id_limit(VarInt::from_u32(1))
             .build();
         assert!(build_result.is_err());
+
+        let build_result = ClientParameters::builder()
+            .max_ack_delay(VarInt::from_u32(25))
+            .min_ack_delay(VarInt::from_u32(25_001))
+            .build();
+        assert!(build_result.is_err());
+
+        let mut buf = bytes::BytesMut::new();
+        put_raw_parameter(&mut buf, ParameterId::DisableActiveMigration, &[0x00]);
+        assert!(RemoteParameters::decode(&buf, Role::Client).is_err());
+    }
+
+    #[test]
+    fn preferred_addresses() {
+        let preferred = |address_v4: &str, address_v6: &str| PreferredAddress {
+            address_v4: address_v4.parse().unwrap(),
+            address_v6: address_v6.parse().unwrap(),
+            connection_id: ConnectionId::default(),
+            stateless_reset_token: ResetToken::new(&[0x02; RESET_TOKEN_SIZE]),
+        };
+        let v4: SocketAddr = "1.2.3.4:4433".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+
+        let addresses = preferred("1.2.3.4:4433", "[2001:db8::1]:4433").addresses();
+        assert_eq!(addresses, vec![v4, v6]);
+        let addresses = preferred("1.2.3.4:4433", "[::ffff:1.2.3.4]:4433").addresses();
+        assert_eq!(addresses, vec![v4]);
+        let addresses = preferred("0.0.0.0:0", "[::ffff:1.2.3.4]:4433").addresses();
+        assert_eq!(addresses, vec![v4]);
+        let addresses = preferred("0.0.0.0:0", "[2001:db8::1]:4433").addresses();
+        assert_eq!(addresses, vec![v6]);
+    }
+
+    #[test]
+    fn absent_min_ack_delay() {
+        let mut params = ClientParameters::default();
+        params.set_min_ack_delay(None);
+        let mut buf = bytes::BytesMut::new();
+        buf.put_client_parameters(&params);
+        let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+        assert_eq!(remote.min_ack_delay(), None);
+        assert_eq!(*remote, *params);
+    }
+
+    #[test]
+    fn address_discovery() {
+        let mut params = ClientParameters::default();
+        for discovery in [
+            AddressDiscovery::Provide,
+            AddressDiscovery::Receive,
+            AddressDiscovery::Both,
+        ] {
+            params.set_address_discovery(Some(discovery));
+            let mut buf = bytes::BytesMut::new();
+            buf.put_client_parameters(&params);
+            let remote = RemoteParameters::decode(&buf, Role::Client).unwrap();
+            assert_eq!(remote.address_discovery(), Some(discovery));
+        }
+
+        // 取值3是非法的
+        let buf = [0xc0, 0x00, 0x00, 0x00, 0x9f, 0x81, 0xa1, 0x76, 0x01, 0x03];
+        assert!(RemoteParameters::decode(&buf, Role::Client).is_err());
     }
 
     #[test]
     fn default_params_test() {
-        let params = Parameters::default();
-        params.validate().unwrap();
+        ClientParameters::default().validate().unwrap();
+        ServerParameters::default().validate().unwrap();
     }
 }
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use super::Parameters;
+use crate::{
+    error::{Error, ErrorKind},
+    varint::VarInt,
+};
+
+/// The transport parameters of the server that the client remembers along with the session
+/// ticket, and relies on when sending 0-RTT data.
+///
+/// See [section 7.4.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-values-of-transport-parameters-for-0-rtt)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html) and
+/// [section 3](https://www.rfc-editor.org/rfc/rfc9221.html#name-transport-parameter) of
+/// [RFC 9221](https://www.rfc-editor.org/rfc/rfc9221.html).
+///
+/// All of them are limits, a larger value is always more permissive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RememberedField {
+    ActiveConnectionIdLimit,
+    InitialMaxData,
+    InitialMaxStreamDataBidiLocal,
+    InitialMaxStreamDataBidiRemote,
+    InitialMaxStreamDataUni,
+    InitialMaxStreamsBidi,
+    InitialMaxStreamsUni,
+    MaxDatagramFrameSize,
+}
+
+impl RememberedField {
+    pub const ALL: [Self; 8] = [
+        Self::ActiveConnectionIdLimit,
+        Self::InitialMaxData,
+        Self::InitialMaxStreamDataBidiLocal,
+        Self::InitialMaxStreamDataBidiRemote,
+        Self::InitialMaxStreamDataUni,
+        Self::InitialMaxStreamsBidi,
+        Self::InitialMaxStreamsUni,
+        Self::MaxDatagramFrameSize,
+    ];
+
+    pub fn value_of(self, params: &Parameters) -> VarInt {
+        match self {
+            Self::ActiveConnectionIdLimit => params.active_connection_id_limit,
+            Self::InitialMaxData => params.initial_max_data,
+            Self::InitialMaxStreamDataBidiLocal => params.initial_max_stream_data_bidi_local,
+            Self::InitialMaxStreamDataBidiRemote => params.initial_max_stream_data_bidi_remote,
+            Self::InitialMaxStreamDataUni => params.initial_max_stream_data_uni,
+            Self::InitialMaxStreamsBidi => params.initial_max_streams_bidi,
+            Self::InitialMaxStreamsUni => params.initial_max_streams_uni,
+            Self::MaxDatagramFrameSize => params.max_datagram_frame_size,
+        }
+    }
+}
+
+impl fmt::Display for RememberedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::ActiveConnectionIdLimit => "active_connection_id_limit",
+            Self::InitialMaxData => "initial_max_data",
+            Self::InitialMaxStreamDataBidiLocal => "initial_max_stream_data_bidi_local",
+            Self::InitialMaxStreamDataBidiRemote => "initial_max_stream_data_bidi_remote",
+            Self::InitialMaxStreamDataUni => "initial_max_stream_data_uni",
+            Self::InitialMaxStreamsBidi => "initial_max_streams_bidi",
+            Self::InitialMaxStreamsUni => "initial_max_streams_uni",
+            Self::MaxDatagramFrameSize => "max_datagram_frame_size",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A remembered transport parameter whose authenticated value differs on the resumed connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterChange {
+    pub field: RememberedField,
+    pub remembered: VarInt,
+    pub current: VarInt,
+}
+
+impl ParameterChange {
+    /// Whether the server lowered the limit since the session ticket was issued.
+    pub fn is_restricted(&self) -> bool {
+        self.current < self.remembered
+    }
+}
+
+/// The differences between the remembered transport parameters of the server and the ones
+/// received on the resumed connection, reported to the application so that it can update its
+/// own caches.
+///
+/// The relaxed ones apply silently. The restricted ones are legal only if the server rejected
+/// the 0-RTT data, see [`ParametersChanged::reconcile`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParametersChanged {
+    pub changes: Vec<ParameterChange>,
+}
+
+impl ParametersChanged {
+    pub fn between(remembered: &Parameters, current: &Parameters) -> Self {
+        let changes = RememberedField::ALL
+            .into_iter()
+            .filter_map(|field| {
+                let remembered = field.value_of(remembered);
+                let current = field.value_of(current);
+                (remembered != current).then_some(ParameterChange {
+                    field,
+                    remembered,
+                    current,
+                })
+            })
+            .collect();
+        Self { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn get(&self, field: RememberedField) -> Option<&ParameterChange> {
+        self.changes.iter().find(|change| change.field == field)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = RememberedField> + '_ {
+        self.changes.iter().map(|change| change.field)
+    }
+
+    pub fn restricted(&self) -> impl Iterator<Item = &ParameterChange> {
+        self.changes.iter().filter(|change| change.is_restricted())
+    }
+
+    /// Check the changes against whether the server accepted the 0-RTT data.
+    ///
+    /// A server that accepts 0-RTT data MUST NOT lower any of the remembered limits, the
+    /// 0-RTT data already sent may violate them, it's a PROTOCOL_VIOLATION. If the 0-RTT
+    /// data is rejected, the lower limits are legal and apply to the data replayed in 1-RTT
+    /// packets, it is the client that must adapt.
+    pub fn reconcile(&self, early_data_accepted: bool) -> Result<(), Error> {
+        match self.restricted().next() {
+            Some(change) if early_data_accepted => Err(Error::with_default_fty(
+                ErrorKind::ProtocolViolation,
+                format!(
+                    "server reduced {} from {} to {} after accepting 0-RTT",
+                    change.field, change.remembered, change.current
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remembered() -> Parameters {
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(100));
+        params.set_max_datagram_frame_size(VarInt::from_u32(1200));
+        params
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let changed = ParametersChanged::between(&remembered(), &remembered());
+        assert!(changed.is_empty());
+        assert!(changed.reconcile(true).is_ok());
+    }
+
+    #[test]
+    fn test_relaxed() {
+        let mut current = remembered();
+        current.set_initial_max_data(VarInt::from_u32(1 << 20));
+        let changed = ParametersChanged::between(&remembered(), &current);
+        assert_eq!(
+            changed.fields().collect::<Vec<_>>(),
+            [RememberedField::InitialMaxData]
+        );
+        assert_eq!(changed.restricted().count(), 0);
+        assert!(changed.reconcile(true).is_ok());
+        assert!(changed.reconcile(false).is_ok());
+    }
+
+    #[test]
+    fn test_datagram_disabled_on_return() {
+        let mut current = remembered();
+        current.set_max_datagram_frame_size(VarInt::from_u32(0));
+        let changed = ParametersChanged::between(&remembered(), &current);
+        let change = changed.get(RememberedField::MaxDatagramFrameSize).unwrap();
+        assert!(change.is_restricted());
+        assert_eq!(change.remembered, VarInt::from_u32(1200));
+        assert_eq!(change.current, VarInt::from_u32(0));
+
+        let error = changed.reconcile(true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert!(changed.reconcile(false).is_ok());
+    }
+
+    #[test]
+    fn test_streams_limit_reduced_on_return() {
+        let mut current = remembered();
+        current.set_initial_max_streams_bidi(VarInt::from_u32(10));
+        current.set_initial_max_data(VarInt::from_u32(1 << 20));
+        let changed = ParametersChanged::between(&remembered(), &current);
+        assert_eq!(changed.changes.len(), 2);
+        assert_eq!(
+            changed.restricted().map(|c| c.field).collect::<Vec<_>>(),
+            [RememberedField::InitialMaxStreamsBidi]
+        );
+
+        let error = changed.reconcile(true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert!(changed.reconcile(false).is_ok());
+    }
+}
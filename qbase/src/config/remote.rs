@@ -0,0 +1,329 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use deref_derive::Deref;
+use getset::CopyGetters;
+
+use super::{ext::be_preferred_address, AddressDiscovery, CommonParameters, PreferredAddress};
+use crate::{
+    cid::{ConnectionId, MAX_CID_SIZE},
+    error::{Error, ErrorKind},
+    streamid::Role,
+    token::{be_reset_token, ResetToken},
+    varint::{be_varint, VarInt},
+    version::{be_version_information, VersionInformation},
+};
+
+/// The identifiers of the transport parameters this endpoint understands, see
+/// [IANA](https://www.iana.org/assignments/quic/quic.xhtml#quic-transport).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ParameterId {
+    OriginalDestinationConnectionId = 0x00,
+    MaxIdleTimeout = 0x01,
+    StatelessResetToken = 0x02,
+    MaxUdpPayloadSize = 0x03,
+    InitialMaxData = 0x04,
+    InitialMaxStreamDataBidiLocal = 0x05,
+    InitialMaxStreamDataBidiRemote = 0x06,
+    InitialMaxStreamDataUni = 0x07,
+    InitialMaxStreamsBidi = 0x08,
+    InitialMaxStreamsUni = 0x09,
+    AckDelayExponent = 0x0a,
+    MaxAckDelay = 0x0b,
+    DisableActiveMigration = 0x0c,
+    PreferredAddress = 0x0d,
+    ActiveConnectionIdLimit = 0x0e,
+    InitialSourceConnectionId = 0x0f,
+    RetrySourceConnectionId = 0x10,
+    /// The transport parameter of RFC 9368.
+    VersionInformation = 0x11,
+    MaxDatagramFrameSize = 0x20,
+    /// The transport parameter of draft-ietf-quic-address-discovery.
+    AddressDiscovery = 0x9f81a176,
+    /// The transport parameter of draft-ietf-quic-ack-frequency.
+    MinAckDelay = 0xff04de1b,
+}
+
+impl ParameterId {
+    pub const ALL: [Self; 21] = [
+        Self::OriginalDestinationConnectionId,
+        Self::MaxIdleTimeout,
+        Self::StatelessResetToken,
+        Self::MaxUdpPayloadSize,
+        Self::InitialMaxData,
+        Self::InitialMaxStreamDataBidiLocal,
+        Self::InitialMaxStreamDataBidiRemote,
+        Self::InitialMaxStreamDataUni,
+        Self::InitialMaxStreamsBidi,
+        Self::InitialMaxStreamsUni,
+        Self::AckDelayExponent,
+        Self::MaxAckDelay,
+        Self::DisableActiveMigration,
+        Self::PreferredAddress,
+        Self::ActiveConnectionIdLimit,
+        Self::InitialSourceConnectionId,
+        Self::RetrySourceConnectionId,
+        Self::VersionInformation,
+        Self::MaxDatagramFrameSize,
+        Self::AddressDiscovery,
+        Self::MinAckDelay,
+    ];
+
+    /// Whether only the server may send it, a client sending it is a TRANSPORT_PARAMETER_ERROR.
+    pub fn is_server_only(self) -> bool {
+        matches!(
+            self,
+            Self::OriginalDestinationConnectionId
+                | Self::StatelessResetToken
+                | Self::PreferredAddress
+                | Self::RetrySourceConnectionId
+        )
+    }
+}
+
+impl From<ParameterId> for VarInt {
+    fn from(id: ParameterId) -> Self {
+        VarInt::from_u32(id as u32)
+    }
+}
+
+impl TryFrom<VarInt> for ParameterId {
+    type Error = VarInt;
+
+    fn try_from(value: VarInt) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|&id| VarInt::from(id) == value)
+            .ok_or(value)
+    }
+}
+
+impl fmt::Display for ParameterId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::OriginalDestinationConnectionId => "original_destination_connection_id",
+            Self::MaxIdleTimeout => "max_idle_timeout",
+            Self::StatelessResetToken => "stateless_reset_token",
+            Self::MaxUdpPayloadSize => "max_udp_payload_size",
+            Self::InitialMaxData => "initial_max_data",
+            Self::InitialMaxStreamDataBidiLocal => "initial_max_stream_data_bidi_local",
+            Self::InitialMaxStreamDataBidiRemote => "initial_max_stream_data_bidi_remote",
+            Self::InitialMaxStreamDataUni => "initial_max_stream_data_uni",
+            Self::InitialMaxStreamsBidi => "initial_max_streams_bidi",
+            Self::InitialMaxStreamsUni => "initial_max_streams_uni",
+            Self::AckDelayExponent => "ack_delay_exponent",
+            Self::MaxAckDelay => "max_ack_delay",
+            Self::DisableActiveMigration => "disable_active_migration",
+            Self::PreferredAddress => "preferred_address",
+            Self::ActiveConnectionIdLimit => "active_connection_id_limit",
+            Self::InitialSourceConnectionId => "initial_source_connection_id",
+            Self::RetrySourceConnectionId => "retry_source_connection_id",
+            Self::VersionInformation => "version_information",
+            Self::MaxDatagramFrameSize => "max_datagram_frame_size",
+            Self::AddressDiscovery => "address_discovery",
+            Self::MinAckDelay => "min_ack_delay",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The transport parameters received from the peer, validated against the role of the peer.
+///
+/// The absent ones take the values specified by the RFC rather than the local defaults, see
+/// [`CommonParameters::spec_default`], and which ones were explicitly present is kept, see
+/// [`RemoteParameters::is_present`]. The ones only the server may send are always None if
+/// the peer is a client.
+#[derive(Deref, CopyGetters, Debug, Clone, PartialEq)]
+pub struct RemoteParameters {
+    #[deref]
+    common: CommonParameters,
+    #[getset(get_copy = "pub")]
+    original_destination_connection_id: Option<ConnectionId>,
+    #[getset(get_copy = "pub")]
+    statelss_reset_token: Option<ResetToken>,
+    #[getset(get_copy = "pub")]
+    preferred_address: Option<PreferredAddress>,
+    #[getset(get_copy = "pub")]
+    retry_source_connection_id: Option<ConnectionId>,
+    version_information: Option<VersionInformation>,
+    present: HashSet<ParameterId>,
+}
+
+impl RemoteParameters {
+    /// Decode the transport parameters sent by the peer playing `peer`, the unknown ones are
+    /// ignored.
+    ///
+    /// It's a TRANSPORT_PARAMETER_ERROR if they are malformed or invalid, if any is repeated,
+    /// or if a client sends the ones only the server may send.
+    pub fn decode(input: &[u8], peer: Role) -> Result<Self, Error> {
+        let error = |reason: String| Error::with_default_fty(ErrorKind::TransportParameter, reason);
+        let mut params = Self {
+            common: CommonParameters::spec_default(),
+            original_destination_connection_id: None,
+            statelss_reset_token: None,
+            preferred_address: None,
+            retry_source_connection_id: None,
+            version_information: None,
+            present: HashSet::new(),
+        };
+
+        let mut remain = input;
+        while !remain.is_empty() {
+            let (rest, (tag, value)) = be_parameter(remain)
+                .map_err(|_| error("malformed transport parameters".to_owned()))?;
+            remain = rest;
+            // Ref. `<https://www.rfc-editor.org/rfc/rfc9000.html#name-new-transport-parameters>
+            // An endpoint MUST ignore transport parameters that it does not support.
+            let Ok(id) = ParameterId::try_from(tag) else {
+                continue;
+            };
+            if id.is_server_only() && peer == Role::Client {
+                return Err(error(format!("{id} is only allowed for server")));
+            }
+            if !params.present.insert(id) {
+                return Err(error(format!("{id} is repeated")));
+            }
+            params
+                .set_value(id, value)
+                .map_err(|_| error(format!("{id} is malformed")))?;
+        }
+
+        params
+            .common
+            .validate()
+            .map_err(|reason| error(reason.to_owned()))?;
+        Ok(params)
+    }
+
+    /// Authenticate the connection ids the server chose during the handshake, see
+    /// [RFC 9000 section 7.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-7.3).
+    ///
+    /// The odcid is the destination connection id of the client's first Initial packet, the
+    /// initial_scid is the source connection id of the server's Initial packets, and the
+    /// retry_scid is the source connection id of the Retry packet if the client accepted one.
+    /// Any absent or mismatched one is a TRANSPORT_PARAMETER_ERROR.
+    pub fn authenticate_server_cids(
+        &self,
+        odcid: &ConnectionId,
+        initial_scid: &ConnectionId,
+        retry_scid: Option<&ConnectionId>,
+    ) -> Result<(), Error> {
+        let check = |id: ParameterId,
+                     expected: Option<&ConnectionId>,
+                     actual: Option<ConnectionId>| {
+            match (expected, actual) {
+                (Some(expected), Some(actual)) if *expected == actual => Ok(()),
+                (None, None) => Ok(()),
+                (Some(_), Some(_)) => Err(format!("{id} mismatches")),
+                (Some(_), None) => Err(format!("{id} is absent")),
+                (None, Some(_)) => Err(format!("{id} is unexpected")),
+            }
+        };
+        check(
+            ParameterId::OriginalDestinationConnectionId,
+            Some(odcid),
+            self.original_destination_connection_id,
+        )
+        .and_then(|_| {
+            check(
+                ParameterId::InitialSourceConnectionId,
+                Some(initial_scid),
+                self.common.initial_source_connection_id,
+            )
+        })
+        .and_then(|_| {
+            check(
+                ParameterId::RetrySourceConnectionId,
+                retry_scid,
+                self.retry_source_connection_id,
+            )
+        })
+        .map_err(|reason| Error::with_default_fty(ErrorKind::TransportParameter, reason))
+    }
+
+    /// The versions the peer speaks and the one it chose, None if the peer doesn't support
+    /// the compatible version negotiation, see [`Versions::authenticate`](crate::version::Versions::authenticate).
+    pub fn version_information(&self) -> Option<&VersionInformation> {
+        self.version_information.as_ref()
+    }
+
+    /// Whether the transport parameter was explicitly sent by the peer, rather than absent
+    /// and taking the default value.
+    pub fn is_present(&self, id: ParameterId) -> bool {
+        self.present.contains(&id)
+    }
+
+    fn set_value(&mut self, id: ParameterId, value: &[u8]) -> Result<(), ()> {
+        let varint = || exactly(be_varint, value);
+        let cid = || match value.len() {
+            len if len <= MAX_CID_SIZE => Ok(Some(ConnectionId::from_slice(value))),
+            _ => Err(()),
+        };
+
+        let common = &mut self.common;
+        match id {
+            ParameterId::OriginalDestinationConnectionId => {
+                self.original_destination_connection_id = cid()?
+            }
+            ParameterId::MaxIdleTimeout => {
+                common.max_idle_timeout = Duration::from_secs(varint()?.into_inner())
+            }
+            ParameterId::StatelessResetToken => {
+                self.statelss_reset_token = Some(exactly(be_reset_token, value)?)
+            }
+            ParameterId::MaxUdpPayloadSize => common.max_udp_payload_size = varint()?,
+            ParameterId::InitialMaxData => common.initial_max_data = varint()?,
+            ParameterId::InitialMaxStreamDataBidiLocal => {
+                common.initial_max_stream_data_bidi_local = varint()?
+            }
+            ParameterId::InitialMaxStreamDataBidiRemote => {
+                common.initial_max_stream_data_bidi_remote = varint()?
+            }
+            ParameterId::InitialMaxStreamDataUni => common.initial_max_stream_data_uni = varint()?,
+            ParameterId::InitialMaxStreamsBidi => common.initial_max_streams_bidi = varint()?,
+            ParameterId::InitialMaxStreamsUni => common.initial_max_streams_uni = varint()?,
+            ParameterId::AckDelayExponent => common.ack_delay_exponent = varint()?,
+            ParameterId::MaxAckDelay => common.max_ack_delay = varint()?,
+            ParameterId::DisableActiveMigration if value.is_empty() => {
+                common.disable_active_migration = true
+            }
+            ParameterId::DisableActiveMigration => return Err(()),
+            ParameterId::PreferredAddress => {
+                self.preferred_address = Some(exactly(be_preferred_address, value)?)
+            }
+            ParameterId::ActiveConnectionIdLimit => common.active_connection_id_limit = varint()?,
+            ParameterId::InitialSourceConnectionId => common.initial_source_connection_id = cid()?,
+            ParameterId::RetrySourceConnectionId => self.retry_source_connection_id = cid()?,
+            ParameterId::VersionInformation => {
+                self.version_information = Some(exactly(be_version_information, value)?)
+            }
+            ParameterId::MaxDatagramFrameSize => common.max_datagram_frame_size = varint()?,
+            ParameterId::MinAckDelay => common.min_ack_delay = Some(varint()?),
+            ParameterId::AddressDiscovery => {
+                // 取值只能是0、1、2，其余的值视为传输参数错误
+                let discovery = AddressDiscovery::try_from(varint()?).map_err(|_| ())?;
+                common.address_discovery = Some(discovery);
+            }
+        }
+        Ok(())
+    }
+}
+
+// 一个传输参数：标识、长度、值
+fn be_parameter(input: &[u8]) -> nom::IResult<&[u8], (VarInt, &[u8])> {
+    let (remain, tag) = be_varint(input)?;
+    let (remain, len) = be_varint(remain)?;
+    let (remain, value) = nom::bytes::streaming::take(len.into_inner())(remain)?;
+    Ok((remain, (tag, value)))
+}
+
+// 传输参数的值必须恰好被解析完，不足或多余都视为格式错误
+fn exactly<'a, T>(
+    parser: impl Fn(&'a [u8]) -> nom::IResult<&'a [u8], T>,
+    value: &'a [u8],
+) -> Result<T, ()> {
+    match parser(value) {
+        Ok((remain, output)) if remain.is_empty() => Ok(output),
+        _ => Err(()),
+    }
+}
@@ -1,10 +1,16 @@
 use std::{borrow::Cow, fmt::Display};
 
+use bytes::Bytes;
 use thiserror::Error;
 
-use crate::{frame::FrameType, varint::VarInt};
+use crate::{
+    frame::FrameType,
+    util::{sanitize_cow, MAX_REASON_LEN},
+    varint::VarInt,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
 pub enum ErrorKind {
     None,
     Internal,
@@ -23,7 +29,11 @@ pub enum ErrorKind {
     KeyUpdate,
     AeadLimitReached,
     NoViablePath,
+    VersionNegotiation,
     Crypto(u8),
+    /// The error code of the application protocol, carried by the CONNECTION_CLOSE frame of
+    /// type 0x1d, unlike the others which are the codes of the transport.
+    App(VarInt),
 }
 
 impl Display for ErrorKind {
@@ -46,7 +56,9 @@ impl Display for ErrorKind {
             ErrorKind::KeyUpdate => "key update error",
             ErrorKind::AeadLimitReached => "the endpoint has reached the confidentiality or integrity limit for the AEAD algorithm",
             ErrorKind::NoViablePath => "no viable network path exists",
+            ErrorKind::VersionNegotiation => "the version negotiation failed, or a downgrade was detected",
             ErrorKind::Crypto(x) => return write!(f, "crypto error: {}", x),
+            ErrorKind::App(code) => return write!(f, "application error: {}", code),
         })
     }
 }
@@ -77,6 +89,7 @@ impl TryFrom<VarInt> for ErrorKind {
             0x0e => ErrorKind::KeyUpdate,
             0x0f => ErrorKind::AeadLimitReached,
             0x10 => ErrorKind::NoViablePath,
+            0x11 => ErrorKind::VersionNegotiation,
             0x0100..=0x01ff => ErrorKind::Crypto((value.into_inner() & 0xff) as u8),
             other => return Err(InvalidErrorKind(other)),
         })
@@ -103,17 +116,25 @@ impl From<ErrorKind> for VarInt {
             ErrorKind::KeyUpdate => VarInt::from(0x0eu8),
             ErrorKind::AeadLimitReached => VarInt::from(0x0fu8),
             ErrorKind::NoViablePath => VarInt::from(0x10u8),
+            ErrorKind::VersionNegotiation => VarInt::from(0x11u8),
             ErrorKind::Crypto(x) => VarInt::from(0x0100u16 | x as u16),
+            ErrorKind::App(code) => code,
         }
     }
 }
 
+/// A QUIC error that closes the connection.
+///
+/// The reason may come from the peer, it's sanitized on construction, see
+/// [`sanitize`](crate::util::sanitize), so that it's safe to display and log. The raw
+/// reason phrase of the peer, if it was altered, is kept by [`Error::raw_reason`].
 #[derive(Debug, Clone, PartialEq, Eq, Error)]
 #[error("{kind} in {frame_type:?}, reason: {reason}")]
 pub struct Error {
     kind: ErrorKind,
     frame_type: FrameType,
     reason: Cow<'static, str>,
+    raw_reason: Option<Bytes>,
 }
 
 impl Error {
@@ -125,16 +146,13 @@ impl Error {
         Self {
             kind,
             frame_type,
-            reason: reason.into(),
+            reason: sanitize_cow(reason.into(), MAX_REASON_LEN),
+            raw_reason: None,
         }
     }
 
     pub fn with_default_fty<T: Into<Cow<'static, str>>>(kind: ErrorKind, reason: T) -> Self {
-        Self {
-            kind,
-            frame_type: FrameType::Padding,
-            reason: reason.into(),
-        }
+        Self::new(kind, FrameType::Padding, reason)
     }
 
     pub fn kind(&self) -> ErrorKind {
@@ -144,11 +162,68 @@ impl Error {
     pub fn frame_type(&self) -> FrameType {
         self.frame_type
     }
+
+    /// The sanitized reason, safe to display and log.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// The raw reason phrase of the CONNECTION_CLOSE frame received from the peer, at most
+    /// [`MAX_RAW_LEN`](crate::util::MAX_RAW_LEN) bytes of it, only if it had to be sanitized.
+    ///
+    /// It may be anything, never log or display it as it is.
+    pub fn raw_reason(&self) -> Option<&[u8]> {
+        self.raw_reason.as_deref()
+    }
+}
+
+/// Why the connection was closed, it's inside the [`io::Error`]s of the stream and datagram
+/// operations pending or issued since then.
+///
+/// [`io::Error`]: std::io::Error
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConnectionError {
+    /// Closed by the application of either endpoint, with an error code of the application
+    /// protocol, see [`ErrorKind::App`].
+    #[error("closed by the application with code {code}, reason: {reason}")]
+    Application {
+        code: u64,
+        reason: Cow<'static, str>,
+    },
+    /// Closed because of an error of the transport, detected by either endpoint.
+    #[error(transparent)]
+    Transport(Error),
+}
+
+impl From<Error> for ConnectionError {
+    fn from(e: Error) -> Self {
+        match e.kind {
+            ErrorKind::App(code) => Self::Application {
+                code: code.into_inner(),
+                reason: e.reason,
+            },
+            _ => Self::Transport(e),
+        }
+    }
 }
 
 impl From<Error> for std::io::Error {
     fn from(e: Error) -> Self {
-        Self::new(std::io::ErrorKind::BrokenPipe, e)
+        Self::new(std::io::ErrorKind::BrokenPipe, ConnectionError::from(e))
+    }
+}
+
+/// Duplicate an [`io::Error`] kept in the state of a stream or a datagram queue, which is
+/// returned again and again, keeping the [`ConnectionError`] inside it, if any.
+///
+/// [`io::Error`]: std::io::Error
+pub fn clone_io_error(error: &std::io::Error) -> std::io::Error {
+    match error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ConnectionError>())
+    {
+        Some(conn_error) => std::io::Error::new(error.kind(), conn_error.clone()),
+        None => std::io::Error::new(error.kind(), error.to_string()),
     }
 }
 
@@ -156,8 +231,10 @@ impl From<Error> for crate::frame::ConnectionCloseFrame {
     fn from(e: Error) -> Self {
         Self {
             error_kind: e.kind,
-            frame_type: Some(e.frame_type),
+            // 应用层的错误码由0x1d类型的帧携带，没有帧类型字段
+            frame_type: (!matches!(e.kind, ErrorKind::App(_))).then_some(e.frame_type),
             reason: e.reason,
+            raw_reason: None,
         }
     }
 }
@@ -167,7 +244,8 @@ impl From<crate::frame::ConnectionCloseFrame> for Error {
         Self {
             kind: value.error_kind,
             frame_type: value.frame_type.unwrap_or(FrameType::Padding),
-            reason: value.reason,
+            reason: sanitize_cow(value.reason, MAX_REASON_LEN),
+            raw_reason: value.raw_reason,
         }
     }
 }
@@ -180,4 +258,62 @@ impl From<rustls::Error> for Error {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::frame::ConnectionCloseFrame;
+
+    #[test]
+    fn test_sanitized_reason() {
+        let error = Error::with_default_fty(
+            ErrorKind::Internal,
+            format!("\x1b[31m{}", "x".repeat(10_000)),
+        );
+        assert!(error.reason().starts_with("\u{fffd}[31m"));
+        let display = error.to_string();
+        let debug = format!("{error:?}");
+        for text in [&display, &debug] {
+            assert!(!text.contains('\x1b'));
+            assert!(text.len() < 1024);
+        }
+
+        // 对方的原因短语，解码时已清理，原始字节另行保留
+        let ccf = ConnectionCloseFrame {
+            error_kind: ErrorKind::Application,
+            frame_type: None,
+            reason: "\u{fffd}]0;pwned\u{fffd}".into(),
+            raw_reason: Some(Bytes::from_static(b"\x1b]0;pwned\x07")),
+        };
+        let error = Error::from(ccf);
+        assert_eq!(error.reason(), "\u{fffd}]0;pwned\u{fffd}");
+        assert_eq!(error.raw_reason(), Some(&b"\x1b]0;pwned\x07"[..]));
+        assert!(!format!("{error:?}").contains('\x1b'));
+        // 回应给对方的CONNECTION_CLOSE不带原始字节
+        assert_eq!(ConnectionCloseFrame::from(error).raw_reason, None);
+    }
+
+    #[test]
+    fn test_app_error_through_io() {
+        let error = Error::with_default_fty(ErrorKind::App(VarInt::from_u32(42)), "bye");
+        let ccf = ConnectionCloseFrame::from(error.clone());
+        assert_eq!(ccf.frame_type, None);
+        assert_eq!(Error::from(ccf), error);
+
+        let io_error = std::io::Error::from(error);
+        assert_eq!(io_error.kind(), std::io::ErrorKind::BrokenPipe);
+        // 流与数据报反复返回同一个错误，复制之后仍然可以取出
+        let expected = ConnectionError::Application {
+            code: 42,
+            reason: "bye".into(),
+        };
+        for io_error in [clone_io_error(&io_error), io_error] {
+            let inner = io_error.get_ref().unwrap();
+            assert_eq!(inner.downcast_ref::<ConnectionError>(), Some(&expected));
+        }
+
+        let error = Error::with_default_fty(ErrorKind::FlowControl, "too much");
+        assert_eq!(
+            ConnectionError::from(error.clone()),
+            ConnectionError::Transport(error)
+        );
+    }
+}
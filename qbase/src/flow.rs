@@ -23,6 +23,8 @@ use crate::{
 struct RawSendControler {
     total_sent: u64,
     max_data: u64,
+    // 新数据用尽了对方给的额度，尚未告知对方
+    blocked: bool,
     blocked_waker: Option<Waker>,
     wakers: Vec<Waker>,
 }
@@ -32,6 +34,7 @@ impl RawSendControler {
         Self {
             total_sent: 0,
             max_data: initial_max_data,
+            blocked: false,
             blocked_waker: None,
             wakers: Vec::with_capacity(4),
         }
@@ -42,7 +45,9 @@ impl RawSendControler {
         cx: &mut Context<'_>,
     ) -> Poll<Result<DataBlockedFrame, QuicError>> {
         debug_assert!(self.total_sent <= self.max_data);
-        if self.total_sent == self.max_data {
+        // 每个额度只告知一次，直到对方的MAX_DATA提高了额度，再次用尽
+        if self.blocked && self.total_sent == self.max_data {
+            self.blocked = false;
             Poll::Ready(Ok(DataBlockedFrame {
                 limit: VarInt::from_u64(self.total_sent)
                     .expect("max_data of flow controller is very very hard to exceed 2^62 - 1"),
@@ -69,6 +74,7 @@ impl RawSendControler {
     fn increase_limit(&mut self, max_data: u64) {
         if max_data > self.max_data {
             self.max_data = max_data;
+            self.blocked = false;
             for waker in self.wakers.drain(..) {
                 waker.wake();
             }
@@ -103,6 +109,9 @@ impl ArcSendControler {
 
     /// For external listening, whether it is blocked.
     /// If so, a DataBlockedFrame needs to be sent to the other party.
+    ///
+    /// It resolves once each time the fresh data sent used up the limit, the limit then
+    /// has to be increased by a MAX_DATA frame before it resolves again.
     pub fn would_block(&self) -> WouldBlock {
         WouldBlock(self.clone())
     }
@@ -182,7 +191,8 @@ impl Credit<'_> {
             Ok(inner) => {
                 debug_assert!(inner.total_sent + amount as u64 <= inner.max_data);
                 inner.total_sent += amount as u64;
-                if inner.total_sent == inner.max_data {
+                if amount > 0 && inner.total_sent == inner.max_data {
+                    inner.blocked = true;
                     if let Some(waker) = inner.blocked_waker.take() {
                         waker.wake();
                     }
@@ -199,11 +209,18 @@ impl Credit<'_> {
 pub struct Overflow(usize);
 
 /// Receiver flow controller for managing the flow of incoming data packets.
+///
+/// The window is the `initial_max_data`. Once the fresh data received consumed the
+/// threshold of the window since the last MAX_DATA frame, half of the window by default,
+/// a MAX_DATA frame opens the window in full again from the data received.
 #[derive(Debug, Default)]
 struct RecvController {
     total_rcvd: AtomicU64,
     max_data: AtomicU64,
-    step: u64,
+    initial_max_data: u64,
+    // 可能被调小以减缓对方的发送，见ArcRecvController::set_window
+    window: AtomicU64,
+    threshold: AtomicU64,
     is_closed: AtomicBool,
     waker: AtomicWaker,
 }
@@ -214,12 +231,46 @@ impl RecvController {
         Self {
             total_rcvd: AtomicU64::new(0),
             max_data: AtomicU64::new(initial_max_data),
-            step: initial_max_data / 2,
+            initial_max_data,
+            window: AtomicU64::new(initial_max_data),
+            threshold: AtomicU64::new((initial_max_data / 2).max(1)),
             is_closed: AtomicBool::new(false),
             waker: AtomicWaker::new(),
         }
     }
 
+    fn set_threshold(&self, threshold: u64) {
+        self.threshold.store(
+            threshold.clamp(1, self.initial_max_data.max(1)),
+            Ordering::Release,
+        );
+        // 调低的阈值可能已经达到了
+        self.waker.wake();
+    }
+
+    fn set_window(&self, window: u64) {
+        self.window.store(
+            window.clamp(1, self.initial_max_data.max(1)),
+            Ordering::Release,
+        );
+        // 调大的窗口可能已经可以通告了
+        self.waker.wake();
+    }
+
+    fn window(&self) -> u64 {
+        self.window.load(Ordering::Acquire)
+    }
+
+    // 自上次通告以来，窗口被消耗的部分达到了阈值
+    fn should_incr_limit(&self, total_rcvd: u64, max_data: u64) -> bool {
+        // 窗口调小后，阈值按比例随之调小
+        let window = self.window();
+        let threshold = self.threshold.load(Ordering::Acquire);
+        let threshold = (threshold as u128 * window as u128 / self.initial_max_data.max(1) as u128)
+            .max(1) as u64;
+        total_rcvd + window >= max_data + threshold
+    }
+
     /// Handles the event when new data is received.
     ///
     /// The data must be new, old retransmitted data does not count. Whether the data is
@@ -232,7 +283,7 @@ impl RecvController {
         let total_rcvd = self.total_rcvd.load(Ordering::Acquire);
         let max_data = self.max_data.load(Ordering::Acquire);
         if total_rcvd <= max_data {
-            if total_rcvd + self.step >= max_data {
+            if self.should_incr_limit(total_rcvd, max_data) {
                 self.waker.wake();
             }
             Ok(amount)
@@ -249,10 +300,12 @@ impl RecvController {
             let max_data = self.max_data.load(Ordering::Acquire);
             let total_rcvd = self.total_rcvd.load(Ordering::Acquire);
 
-            if total_rcvd + self.step >= max_data {
-                self.max_data.fetch_add(self.step, Ordering::Release);
+            if self.should_incr_limit(total_rcvd, max_data) {
+                // 窗口调小后，也不会收回已经通告的额度
+                let max_data = total_rcvd + self.window();
+                self.max_data.fetch_max(max_data, Ordering::Release);
                 Poll::Ready(Some(MaxDataFrame {
-                    max_data: VarInt::from_u64(self.max_data.load(Ordering::Acquire))
+                    max_data: VarInt::from_u64(max_data)
                         .expect("max_data of flow controller is very very hard to exceed 2^62 - 1"),
                 }))
             } else {
@@ -285,6 +338,29 @@ impl ArcRecvController {
         self.0.on_new_rcvd(amount)
     }
 
+    /// Set how many bytes of the window the fresh data consumes before a MAX_DATA frame
+    /// is sent, between 1 byte and the whole window, half of the window by default.
+    ///
+    /// A low threshold keeps the peer far from being blocked at the cost of more MAX_DATA
+    /// frames, while a high one may block the peer for a round trip on a fast path.
+    pub fn set_threshold(&self, threshold: u64) {
+        self.0.set_threshold(threshold);
+    }
+
+    /// Set how many bytes beyond the data received each MAX_DATA frame allows the peer to
+    /// send, at most the `initial_max_data`, which is the default.
+    ///
+    /// A smaller window slows the peer down, but the credit already granted is never
+    /// retracted, it only grows slower.
+    pub fn set_window(&self, window: u64) {
+        self.0.set_window(window);
+    }
+
+    /// The window in use, see [`ArcRecvController::set_window`].
+    pub fn window(&self) -> u64 {
+        self.0.window()
+    }
+
     /// Polls for an increase in the receive window limit.
     pub fn incr_limit(&self) -> IncrLimit {
         IncrLimit(self.0.clone())
@@ -349,3 +425,90 @@ impl FlowController {
         self.recver.on_error();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use super::*;
+
+    fn max_data(frame: Poll<Option<MaxDataFrame>>) -> Poll<Option<u64>> {
+        frame.map(|frame| frame.map(|frame| frame.max_data.into_inner()))
+    }
+
+    #[test]
+    fn test_recver_incr_limit() {
+        let recver = ArcRecvController::with_initial(100);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut poll = || max_data(pin!(recver.incr_limit()).poll(&mut cx));
+
+        recver.on_new_rcvd(49).unwrap();
+        assert_eq!(poll(), Poll::Pending);
+        // 消耗了一半的窗口，从已收到的数据起重新打开整个窗口
+        recver.on_new_rcvd(1).unwrap();
+        assert_eq!(poll(), Poll::Ready(Some(150)));
+        assert_eq!(poll(), Poll::Pending);
+
+        recver.on_new_rcvd(20).unwrap();
+        assert_eq!(poll(), Poll::Pending);
+        recver.set_threshold(20);
+        assert_eq!(poll(), Poll::Ready(Some(170)));
+
+        assert!(recver.on_new_rcvd(101).is_err());
+        recver.on_error();
+        assert_eq!(poll(), Poll::Ready(None));
+    }
+
+    #[test]
+    fn test_recver_shrink_window() {
+        let recver = ArcRecvController::with_initial(100);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut poll = || max_data(pin!(recver.incr_limit()).poll(&mut cx));
+
+        // 窗口调小，已通告的额度不收回，阈值按比例变为10
+        recver.set_window(20);
+        assert_eq!(recver.window(), 20);
+        recver.on_new_rcvd(89).unwrap();
+        assert_eq!(poll(), Poll::Pending);
+        recver.on_new_rcvd(1).unwrap();
+        assert_eq!(poll(), Poll::Ready(Some(110)));
+
+        // 恢复窗口，立即以整个窗口通告
+        recver.set_window(1000);
+        assert_eq!(recver.window(), 100);
+        assert_eq!(poll(), Poll::Ready(Some(190)));
+    }
+
+    #[test]
+    fn test_sender_would_block() {
+        let sender = ArcSendControler::with_initial(10);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut poll = || {
+            pin!(sender.would_block())
+                .poll(&mut cx)
+                .map(|frame| frame.unwrap().limit.into_inner())
+        };
+
+        assert_eq!(poll(), Poll::Pending);
+        sender.credit().unwrap().post_sent(4);
+        assert_eq!(poll(), Poll::Pending);
+        // 用尽额度时告知一次，此后不再重复
+        let credit = sender.credit().unwrap();
+        assert_eq!(credit.available(), 6);
+        credit.post_sent(6);
+        assert_eq!(poll(), Poll::Ready(10));
+        sender.credit().unwrap().post_sent(0);
+        assert_eq!(poll(), Poll::Pending);
+
+        let max_data = MaxDataFrame {
+            max_data: VarInt::from_u32(20),
+        };
+        sender.recv_frame(&max_data).unwrap();
+        assert_eq!(sender.credit().unwrap().available(), 10);
+        sender.credit().unwrap().post_sent(10);
+        assert_eq!(poll(), Poll::Ready(20));
+    }
+}
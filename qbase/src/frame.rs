@@ -6,16 +6,19 @@ use super::varint::VarInt;
 use crate::packet::r#type::Type;
 
 mod ack;
+mod ack_frequency;
 mod connection_close;
 mod crypto;
 mod data_blocked;
 mod datagram;
 mod handshake_done;
+mod immediate_ack;
 mod max_data;
 mod max_stream_data;
 mod max_streams;
 mod new_connection_id;
 mod new_token;
+mod observed_address;
 mod padding;
 mod path_challenge;
 mod path_response;
@@ -31,17 +34,20 @@ pub mod error;
 pub mod io;
 
 pub use ack::{AckFrame, EcnCounts};
+pub use ack_frequency::AckFrequencyFrame;
 pub use connection_close::ConnectionCloseFrame;
 pub use crypto::CryptoFrame;
 pub use data_blocked::DataBlockedFrame;
 pub use datagram::DatagramFrame;
 pub use error::Error;
 pub use handshake_done::HandshakeDoneFrame;
+pub use immediate_ack::ImmediateAckFrame;
 pub use max_data::MaxDataFrame;
 pub use max_stream_data::MaxStreamDataFrame;
 pub use max_streams::MaxStreamsFrame;
 pub use new_connection_id::NewConnectionIdFrame;
 pub use new_token::NewTokenFrame;
+pub use observed_address::ObservedAddressFrame;
 pub use padding::PaddingFrame;
 pub use path_challenge::PathChallengeFrame;
 pub use path_response::PathResponseFrame;
@@ -100,6 +106,11 @@ pub enum FrameType {
     ConnectionClose(u8),
     HandshakeDone,
     Datagram(u8),
+    // draft-ietf-quic-ack-frequency的扩展帧
+    AckFrequency,
+    ImmediateAck,
+    // draft-ietf-quic-address-discovery的扩展帧，0表示IPv4，1表示IPv6
+    ObservedAddress(u8),
 }
 
 impl FrameType {
@@ -150,6 +161,9 @@ impl FrameType {
             }
             FrameType::HandshakeDone => l,
             FrameType::Datagram(_) => o | l,
+            FrameType::AckFrequency => o | l,
+            FrameType::ImmediateAck => o | l,
+            FrameType::ObservedAddress(_) => l,
         }
     }
 
@@ -163,47 +177,50 @@ impl FrameType {
     }
 }
 
-impl TryFrom<u8> for FrameType {
+impl TryFrom<VarInt> for FrameType {
     type Error = Error;
 
-    fn try_from(frame_type: u8) -> Result<Self, Self::Error> {
-        Ok(match frame_type {
+    fn try_from(frame_type: VarInt) -> Result<Self, Self::Error> {
+        Ok(match frame_type.into_inner() {
             0x00 => FrameType::Padding,
             0x01 => FrameType::Ping,
             // The last bit is the ECN flag.
-            ty @ (0x02 | 0x03) => FrameType::Ack(ty & 0b1),
+            ty @ (0x02 | 0x03) => FrameType::Ack(ty as u8 & 0b1),
             0x04 => FrameType::ResetStream,
             0x05 => FrameType::StopSending,
             0x06 => FrameType::Crypto,
             0x07 => FrameType::NewToken,
             // The last three bits are the offset, length, and fin flag bits respectively.
-            ty @ 0x08..=0x0f => FrameType::Stream(ty & 0b111),
+            ty @ 0x08..=0x0f => FrameType::Stream(ty as u8 & 0b111),
             0x10 => FrameType::MaxData,
             0x11 => FrameType::MaxStreamData,
             // The last bit is the direction flag bit, 0 indicates bidirectional, 1 indicates unidirectional.
-            ty @ (0x12 | 0x13) => FrameType::MaxStreams(ty & 0b1),
+            ty @ (0x12 | 0x13) => FrameType::MaxStreams(ty as u8 & 0b1),
             0x14 => FrameType::DataBlocked,
             0x15 => FrameType::StreamDataBlocked,
             // The last bit is the direction flag bit, 0 indicates bidirectional, 1 indicates unidirectional.
-            ty @ (0x16 | 0x17) => FrameType::StreamsBlocked(ty & 0b1),
+            ty @ (0x16 | 0x17) => FrameType::StreamsBlocked(ty as u8 & 0b1),
             0x18 => FrameType::NewConnectionId,
             0x19 => FrameType::RetireConnectionId,
             0x1a => FrameType::PathChallenge,
             0x1b => FrameType::PathResponse,
             // The last bit is the layer flag bit, 0 indicates application layer, 1 indicates transport layer.
-            ty @ (0x1c | 0x1d) => FrameType::ConnectionClose(ty & 0x1),
+            ty @ (0x1c | 0x1d) => FrameType::ConnectionClose(ty as u8 & 0x1),
             0x1e => FrameType::HandshakeDone,
             // The last bit is the length flag bit, 0 the length field is absent and the Datagram Data
             // field extends to the end of the packet, 1 the length field is present.
-            ty @ (0x30 | 0x31) => FrameType::Datagram(ty & 1),
-            _ => return Err(Self::Error::InvalidType(VarInt::from(frame_type))),
+            ty @ (0x30 | 0x31) => FrameType::Datagram(ty as u8 & 1),
+            0xaf => FrameType::AckFrequency,
+            0x1f => FrameType::ImmediateAck,
+            ty @ (0x9f81a6 | 0x9f81a7) => FrameType::ObservedAddress(ty as u8 & 1),
+            _ => return Err(Self::Error::InvalidType(frame_type)),
         })
     }
 }
 
-impl From<FrameType> for u8 {
+impl From<FrameType> for VarInt {
     fn from(frame_type: FrameType) -> Self {
-        match frame_type {
+        let frame_type: u8 = match frame_type {
             FrameType::Padding => 0x00,
             FrameType::Ping => 0x01,
             FrameType::Ack(ecn) => 0x02 | ecn,
@@ -225,12 +242,22 @@ impl From<FrameType> for u8 {
             FrameType::ConnectionClose(layer) => 0x1c | layer,
             FrameType::HandshakeDone => 0x1e,
             FrameType::Datagram(with_len) => 0x30 | with_len,
-        }
+            FrameType::AckFrequency => 0xaf,
+            FrameType::ImmediateAck => 0x1f,
+            FrameType::ObservedAddress(ipv6) => return VarInt::from_u32(0x9f81a6 | ipv6 as u32),
+        };
+        VarInt::from(frame_type)
     }
 }
 
+/// The frame type is a variable-length integer, the extension frames like ACK_FREQUENCY
+/// take more than one byte.
 pub fn be_frame_type(input: &[u8]) -> nom::IResult<&[u8], FrameType, Error> {
-    let (remain, frame_type) = nom::number::complete::be_u8(input)?;
+    let (remain, frame_type) = crate::varint::be_varint(input).map_err(|_| {
+        nom::Err::Error(Error::IncompleteType(format!(
+            "incomplete frame type from buffer: {input:?}"
+        )))
+    })?;
     let frame_type = FrameType::try_from(frame_type).map_err(nom::Err::Error)?;
     Ok((remain, frame_type))
 }
@@ -256,6 +283,9 @@ pub enum ReliableFrame {
     RetireConnectionId(RetireConnectionIdFrame),
     HandshakeDone(HandshakeDoneFrame),
     Stream(StreamCtlFrame),
+    AckFrequency(AckFrequencyFrame),
+    // 对方确认后即可，不必重传，但随可靠帧一起发送最简单
+    ImmediateAck(ImmediateAckFrame),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -276,6 +306,25 @@ pub enum Frame {
     Stream(StreamFrame, Bytes),
     Crypto(CryptoFrame, Bytes),
     Datagram(DatagramFrame, Bytes),
+    AckFrequency(AckFrequencyFrame),
+    ImmediateAck(ImmediateAckFrame),
+    ObservedAddress(ObservedAddressFrame),
+}
+
+impl Frame {
+    /// Determine if the frame is a probing frame, a packet containing only the probing
+    /// frames doesn't make the peer migrate to the path it's received on.
+    ///
+    /// See [section 9.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-9.1) of RFC 9000.
+    pub fn is_probing(&self) -> bool {
+        matches!(
+            self,
+            Frame::Padding(_)
+                | Frame::NewConnectionId(_)
+                | Frame::Challenge(_)
+                | Frame::Response(_)
+        )
+    }
 }
 
 pub trait SendFrame<T> {
@@ -346,9 +395,51 @@ impl<T: BufMut> WriteFrame<ReliableFrame> for T {
             ReliableFrame::RetireConnectionId(frame) => self.put_frame(frame),
             ReliableFrame::HandshakeDone(frame) => self.put_frame(frame),
             ReliableFrame::Stream(frame) => self.put_frame(frame),
+            ReliableFrame::AckFrequency(frame) => self.put_frame(frame),
+            ReliableFrame::ImmediateAck(frame) => self.put_frame(frame),
         }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::packet::r#type::{
+        long::{Type::V1, Ver1},
+        short::OneRtt,
+    };
+
+    #[test]
+    fn test_datagram_belongs_to() {
+        let zero_rtt = Type::Long(V1(Ver1::ZERO_RTT));
+        let one_rtt = Type::Short(OneRtt(Default::default()));
+        for fty in [FrameType::Datagram(0), FrameType::Datagram(1)] {
+            assert!(fty.belongs_to(zero_rtt));
+            assert!(fty.belongs_to(one_rtt));
+            assert!(!fty.belongs_to(Type::Long(V1(Ver1::INITIAL))));
+            assert!(!fty.belongs_to(Type::Long(V1(Ver1::HANDSHAKE))));
+        }
+    }
+
+    #[test]
+    fn test_extension_frame_type() {
+        // 0xaf需要两个字节的变长整数编码
+        let (remain, fty) = be_frame_type(&[0x40, 0xaf, 0x01]).unwrap();
+        assert_eq!(remain, &[0x01]);
+        assert_eq!(fty, FrameType::AckFrequency);
+        assert_eq!(VarInt::from(fty), VarInt::from_u32(0xaf));
+        assert!(fty.is_ack_eliciting());
+
+        let (_, fty) = be_frame_type(&[0x1f]).unwrap();
+        assert_eq!(fty, FrameType::ImmediateAck);
+        assert!(be_frame_type(&[0x40, 0xb0]).is_err());
+    }
+
+    #[test]
+    fn test_probing_frame() {
+        assert!(Frame::Padding(PaddingFrame).is_probing());
+        assert!(Frame::Challenge(PathChallengeFrame::random()).is_probing());
+        assert!(!Frame::Ping(PingFrame).is_probing());
+        assert!(!Frame::HandshakeDone(HandshakeDoneFrame).is_probing());
+    }
+}
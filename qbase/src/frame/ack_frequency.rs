@@ -0,0 +1,114 @@
+// ACK_FREQUENCY Frame {
+//   Type (i) = 0xaf,
+//   Sequence Number (i),
+//   Ack-Eliciting Threshold (i),
+//   Request Max Ack Delay (i),
+//   Reordering Threshold (i),
+// }
+
+use crate::varint::{be_varint, VarInt, WriteVarInt};
+
+/// ACK_FREQUENCY frame, see
+/// [draft-ietf-quic-ack-frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency#name-ack_frequency-frame).
+///
+/// The `request_max_ack_delay` is in microseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AckFrequencyFrame {
+    pub sequence_number: VarInt,
+    pub ack_eliciting_threshold: VarInt,
+    pub request_max_ack_delay: VarInt,
+    pub reordering_threshold: VarInt,
+}
+
+const ACK_FREQUENCY_FRAME_TYPE: VarInt = VarInt::from_u32(0xaf);
+
+impl super::BeFrame for AckFrequencyFrame {
+    fn frame_type(&self) -> super::FrameType {
+        super::FrameType::AckFrequency
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        2 + 8 * 4
+    }
+
+    fn encoding_size(&self) -> usize {
+        ACK_FREQUENCY_FRAME_TYPE.encoding_size()
+            + self.sequence_number.encoding_size()
+            + self.ack_eliciting_threshold.encoding_size()
+            + self.request_max_ack_delay.encoding_size()
+            + self.reordering_threshold.encoding_size()
+    }
+}
+
+pub fn be_ack_frequency_frame(input: &[u8]) -> nom::IResult<&[u8], AckFrequencyFrame> {
+    use nom::{combinator::map, sequence::tuple};
+    map(
+        tuple((be_varint, be_varint, be_varint, be_varint)),
+        |(
+            sequence_number,
+            ack_eliciting_threshold,
+            request_max_ack_delay,
+            reordering_threshold,
+        )| AckFrequencyFrame {
+            sequence_number,
+            ack_eliciting_threshold,
+            request_max_ack_delay,
+            reordering_threshold,
+        },
+    )(input)
+}
+
+impl<T: bytes::BufMut> super::io::WriteFrame<AckFrequencyFrame> for T {
+    fn put_frame(&mut self, frame: &AckFrequencyFrame) {
+        self.put_varint(&ACK_FREQUENCY_FRAME_TYPE);
+        self.put_varint(&frame.sequence_number);
+        self.put_varint(&frame.ack_eliciting_threshold);
+        self.put_varint(&frame.request_max_ack_delay);
+        self.put_varint(&frame.reordering_threshold);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AckFrequencyFrame, ACK_FREQUENCY_FRAME_TYPE};
+    use crate::{
+        frame::{io::WriteFrame, BeFrame},
+        varint::VarInt,
+    };
+
+    fn frame() -> AckFrequencyFrame {
+        AckFrequencyFrame {
+            sequence_number: VarInt::from_u32(1),
+            ack_eliciting_threshold: VarInt::from_u32(9),
+            request_max_ack_delay: VarInt::from_u32(0x1234),
+            reordering_threshold: VarInt::from_u32(3),
+        }
+    }
+
+    #[test]
+    fn test_read_ack_frequency_frame() {
+        use nom::combinator::flat_map;
+
+        use super::be_ack_frequency_frame;
+        use crate::varint::be_varint;
+        let buf = vec![0x40, 0xaf, 0x01, 0x09, 0x52, 0x34, 0x03];
+        let (input, frame) = flat_map(be_varint, |frame_type| {
+            if frame_type == ACK_FREQUENCY_FRAME_TYPE {
+                be_ack_frequency_frame
+            } else {
+                panic!("wrong frame type: {}", frame_type)
+            }
+        })(buf.as_ref())
+        .unwrap();
+        assert!(input.is_empty());
+        assert_eq!(frame, self::frame());
+    }
+
+    #[test]
+    fn test_write_ack_frequency_frame() {
+        let mut buf = Vec::new();
+        buf.put_frame(&frame());
+        assert_eq!(buf, vec![0x40, 0xaf, 0x01, 0x09, 0x52, 0x34, 0x03]);
+        assert_eq!(buf.len(), frame().encoding_size());
+    }
+}
@@ -8,14 +8,26 @@
 
 use std::borrow::Cow;
 
+use bytes::Bytes;
+
 use super::FrameType;
-use crate::{error::ErrorKind, frame::be_frame_type, varint::VarInt};
+use crate::{
+    error::ErrorKind,
+    frame::be_frame_type,
+    util::{sanitize, MAX_RAW_LEN, MAX_REASON_LEN},
+    varint::VarInt,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionCloseFrame {
     pub error_kind: ErrorKind,
     pub frame_type: Option<FrameType>,
+    /// The reason phrase, the one received from the peer is sanitized, see
+    /// [`sanitize`](crate::util::sanitize).
     pub reason: Cow<'static, str>,
+    /// The raw reason phrase received from the peer, at most [`MAX_RAW_LEN`] bytes of it,
+    /// only if it had to be sanitized. It's never sent.
+    pub raw_reason: Option<Bytes>,
 }
 
 const CONNECTION_CLOSE_FRAME_TYPE: u8 = 0x1c;
@@ -39,7 +51,7 @@ impl super::BeFrame for ConnectionCloseFrame {
 
     fn encoding_size(&self) -> usize {
         1 + VarInt::from(self.error_kind).encoding_size()
-            + self.frame_type.is_some()  as usize
+            + self.frame_type.map_or(0, |ty| VarInt::from(ty).encoding_size())
             // reason's length could not exceed 16KB.
             + VarInt::try_from(self.reason.len()).unwrap().encoding_size()
             + self.reason.len()
@@ -56,6 +68,22 @@ impl ConnectionCloseFrame {
             error_kind,
             frame_type,
             reason,
+            raw_reason: None,
+        }
+    }
+
+    /// The frame to send in the Initial or Handshake packets instead, where the peer may
+    /// not have authenticated us yet.
+    ///
+    /// The application-specific variant of CONNECTION_CLOSE (type 0x1d) is replaced with
+    /// the one of type 0x1c carrying APPLICATION_ERROR, and the reason phrase is dropped, see
+    /// [RFC 9000 section 10.2.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3).
+    pub fn conceal_app_layer(&self) -> Self {
+        match self.error_kind {
+            ErrorKind::App(_) => {
+                Self::new(ErrorKind::Application, Some(FrameType::Padding), "".into())
+            }
+            _ => self.clone(),
         }
     }
 }
@@ -68,9 +96,14 @@ pub fn connection_close_frame_at_layer(
     use crate::varint::be_varint;
     move |input: &[u8]| {
         let (remain, error_code) = be_varint(input)?;
-        let kind = ErrorKind::try_from(error_code).map_err(|_e| {
-            nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
-        })?;
+        // 应用层的错误码由应用协议定义，不必是传输层的错误码
+        let kind = if layer == APP_LAYER {
+            ErrorKind::App(error_code)
+        } else {
+            ErrorKind::try_from(error_code).map_err(|_e| {
+                nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Alt))
+            })?
+        };
         // The application-specific variant of CONNECTION_CLOSE (type 0x1d) does not include frame_type field.
         let (remain, frame_type) = if layer == QUIC_LAYER {
             let (remain, frame_type) = be_frame_type(remain).map_err(|_e| {
@@ -82,13 +115,17 @@ pub fn connection_close_frame_at_layer(
         };
         let (remain, rease_length) = be_varint(remain)?;
         let (remain, reason) = take(rease_length.into_inner() as usize)(remain)?;
-        let cow = String::from_utf8_lossy(reason).into_owned();
+        // 原因短语由对方控制，截断并替换控制字符后才能用于错误与日志，改动过的保留有限的原始字节
+        let sanitized = sanitize(reason, MAX_REASON_LEN);
+        let raw_reason = (sanitized.as_bytes() != reason)
+            .then(|| Bytes::copy_from_slice(&reason[..reason.len().min(MAX_RAW_LEN)]));
         Ok((
             remain,
             ConnectionCloseFrame {
                 error_kind: kind,
                 frame_type,
-                reason: Cow::Owned(cow),
+                reason: Cow::Owned(sanitized.into_owned()),
+                raw_reason,
             },
         ))
     }
@@ -105,7 +142,7 @@ impl<T: bytes::BufMut> super::io::WriteFrame<ConnectionCloseFrame> for T {
         self.put_u8(CONNECTION_CLOSE_FRAME_TYPE | layer);
         self.put_varint(&frame.error_kind.into());
         if let Some(frame_type) = frame.frame_type {
-            self.put_u8(frame_type.into());
+            self.put_varint(&frame_type.into());
         }
         self.put_varint(&VarInt::from_u32(frame.reason.len() as u32));
         self.put_slice(frame.reason.as_bytes());
@@ -144,13 +181,80 @@ mod tests {
         assert_eq!(
             frame,
             super::ConnectionCloseFrame {
-                error_kind: ErrorKind::Application,
+                error_kind: ErrorKind::App(crate::varint::VarInt::from_u32(0x0c)),
                 frame_type: None,
                 reason: "wrong".into(),
+                raw_reason: None,
             }
         );
     }
 
+    #[test]
+    fn test_read_hostile_reason() {
+        use super::connection_close_frame_at_layer;
+        use crate::{
+            util::{MAX_RAW_LEN, MAX_REASON_LEN, TRUNCATED_MARK},
+            varint::{VarInt, WriteVarInt},
+        };
+
+        let read = |reason: &[u8]| {
+            let mut buf = vec![0x0c];
+            buf.put_varint(&VarInt::try_from(reason.len()).unwrap());
+            buf.extend_from_slice(reason);
+            let (remain, frame) = connection_close_frame_at_layer(0)(&buf).unwrap();
+            assert!(remain.is_empty());
+            frame
+        };
+
+        let frame = read(b"\x1b[2J\x1b[1;1Hrm -rf /\r\n\xff");
+        assert_eq!(
+            frame.reason,
+            "\u{fffd}[2J\u{fffd}[1;1Hrm -rf /\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(
+            frame.raw_reason.as_deref(),
+            Some(&b"\x1b[2J\x1b[1;1Hrm -rf /\r\n\xff"[..])
+        );
+
+        let huge = vec![b'z'; 10 * 1024 * 1024];
+        let frame = read(&huge);
+        assert_eq!(frame.reason.len(), MAX_REASON_LEN + TRUNCATED_MARK.len());
+        assert!(frame.reason.ends_with(TRUNCATED_MARK));
+        assert_eq!(frame.raw_reason.unwrap().len(), MAX_RAW_LEN);
+
+        // 干净的原因短语不另存原始字节
+        assert_eq!(read(b"bye").raw_reason, None);
+    }
+
+    #[test]
+    fn test_app_layer_in_handshake() {
+        use super::{connection_close_frame_at_layer, ConnectionCloseFrame, FrameType};
+        use crate::varint::VarInt;
+
+        let frame = ConnectionCloseFrame::new(
+            ErrorKind::App(VarInt::from_u32(0x1234)),
+            None,
+            "shutting down".into(),
+        );
+        let mut buf = Vec::<u8>::new();
+        buf.put_frame(&frame);
+        assert_eq!(
+            buf[0],
+            super::CONNECTION_CLOSE_FRAME_TYPE | super::APP_LAYER
+        );
+        let (remain, read) = connection_close_frame_at_layer(super::APP_LAYER)(&buf[1..]).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(read, frame);
+
+        // Initial、Handshake包里只能以APPLICATION_ERROR代之，不透露原因
+        let concealed = frame.conceal_app_layer();
+        assert_eq!(concealed.error_kind, ErrorKind::Application);
+        assert_eq!(concealed.frame_type, Some(FrameType::Padding));
+        assert!(concealed.reason.is_empty());
+        let transport = ConnectionCloseFrame::new(ErrorKind::Internal, None, "bug".into());
+        assert_eq!(transport.conceal_app_layer(), transport);
+    }
+
     #[test]
     fn test_write_connection_close_frame() {
         use super::FrameType;
@@ -159,6 +263,7 @@ mod tests {
             error_kind: ErrorKind::FlowControl,
             frame_type: Some(FrameType::Stream(0b110)),
             reason: "wrong".into(),
+            raw_reason: None,
         };
         buf.put_frame(&frame);
         assert_eq!(
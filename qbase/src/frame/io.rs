@@ -1,11 +1,12 @@
 use bytes::Bytes;
 
 use super::{
-    ack::ack_frame_with_flag, connection_close::connection_close_frame_at_layer,
-    crypto::be_crypto_frame, data_blocked::be_data_blocked_frame,
-    datagram::datagram_frame_with_flag, max_data::be_max_data_frame,
-    max_stream_data::be_max_stream_data_frame, max_streams::max_streams_frame_with_dir,
-    new_connection_id::be_new_connection_id_frame, new_token::be_new_token_frame,
+    ack::ack_frame_with_flag, ack_frequency::be_ack_frequency_frame,
+    connection_close::connection_close_frame_at_layer, crypto::be_crypto_frame,
+    data_blocked::be_data_blocked_frame, datagram::datagram_frame_with_flag,
+    max_data::be_max_data_frame, max_stream_data::be_max_stream_data_frame,
+    max_streams::max_streams_frame_with_dir, new_connection_id::be_new_connection_id_frame,
+    new_token::be_new_token_frame, observed_address::observed_address_frame_with_ipv6,
     path_challenge::be_path_challenge_frame, path_response::be_path_response_frame,
     reset_stream::be_reset_stream_frame, retire_connection_id::be_retire_connection_id_frame,
     stop_sending::be_stop_sending_frame, stream::stream_frame_with_flag,
@@ -37,6 +38,12 @@ fn complete_frame(
         FrameType::PathChallenge => map(be_path_challenge_frame, Frame::Challenge)(input),
         FrameType::PathResponse => map(be_path_response_frame, Frame::Response)(input),
         FrameType::HandshakeDone => Ok((input, Frame::HandshakeDone(HandshakeDoneFrame))),
+        FrameType::AckFrequency => map(be_ack_frequency_frame, Frame::AckFrequency)(input),
+        FrameType::ImmediateAck => Ok((input, Frame::ImmediateAck(ImmediateAckFrame))),
+        FrameType::ObservedAddress(ipv6) => map(
+            observed_address_frame_with_ipv6(ipv6),
+            Frame::ObservedAddress,
+        )(input),
         FrameType::NewToken => map(be_new_token_frame, Frame::NewToken)(input),
         FrameType::Ack(ecn) => map(ack_frame_with_flag(ecn), Frame::Ack)(input),
         FrameType::ResetStream => map(be_reset_stream_frame, |f| Frame::StreamCtl(f.into()))(input),
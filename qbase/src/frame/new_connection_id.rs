@@ -8,7 +8,7 @@
 // }
 
 use crate::{
-    cid::{be_connection_id, ConnectionId, UniqueCid, WriteConnectionId},
+    cid::{be_connection_id, ConnectionId, GenResetToken, UniqueCid, WriteConnectionId},
     token::{be_reset_token, ResetToken, RESET_TOKEN_SIZE},
     varint::{be_varint, VarInt, WriteVarInt},
 };
@@ -32,12 +32,12 @@ impl NewConnectionIdFrame {
     ) -> Self
     where
         G: Fn() -> ConnectionId,
-        U: UniqueCid,
+        U: UniqueCid + GenResetToken,
     {
         let id = std::iter::from_fn(|| Some(generator()))
             .find(|cid| uniqueness.is_unique_cid(cid))
             .unwrap();
-        let reset_token = ResetToken::random_gen();
+        let reset_token = uniqueness.gen_reset_token(&id);
         Self {
             sequence,
             retire_prior_to,
@@ -0,0 +1,123 @@
+// OBSERVED_ADDRESS Frame {
+//   Type (i) = 0x9f81a6..0x9f81a7,
+//   Sequence Number (i),
+//   IPv4 (32) / IPv6 (128),
+//   Port (16),
+// }
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use nom::{bytes::complete::take, number::complete::be_u16, sequence::tuple, IResult};
+
+use super::{BeFrame, FrameType};
+use crate::varint::{be_varint, VarInt, WriteVarInt};
+
+/// OBSERVED_ADDRESS frame, reports the address of the peer observed on the path it's sent
+/// on, see [draft-ietf-quic-address-discovery](https://datatracker.ietf.org/doc/draft-ietf-quic-address-discovery/).
+///
+/// The frame type tells the address family, 0x9f81a6 for IPv4 and 0x9f81a7 for IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObservedAddressFrame {
+    pub sequence_number: VarInt,
+    pub address: SocketAddr,
+}
+
+const OBSERVED_ADDRESS_FRAME_TYPE: u32 = 0x9f81a6;
+
+impl ObservedAddressFrame {
+    fn ip_len(&self) -> usize {
+        if self.address.is_ipv4() {
+            4
+        } else {
+            16
+        }
+    }
+}
+
+impl BeFrame for ObservedAddressFrame {
+    fn frame_type(&self) -> FrameType {
+        FrameType::ObservedAddress(self.address.is_ipv6() as _)
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        4 + 8 + 16 + 2
+    }
+
+    fn encoding_size(&self) -> usize {
+        VarInt::from_u32(OBSERVED_ADDRESS_FRAME_TYPE).encoding_size()
+            + self.sequence_number.encoding_size()
+            + self.ip_len()
+            + 2
+    }
+}
+
+pub fn observed_address_frame_with_ipv6(
+    ipv6: u8,
+) -> impl Fn(&[u8]) -> IResult<&[u8], ObservedAddressFrame> {
+    move |input| {
+        let ip_len = if ipv6 == 1 { 16usize } else { 4 };
+        let (remain, (sequence_number, ip, port)) =
+            tuple((be_varint, take(ip_len), be_u16))(input)?;
+        let address = if ipv6 == 1 {
+            let ip: [u8; 16] = ip.try_into().unwrap();
+            SocketAddr::new(Ipv6Addr::from(ip).into(), port)
+        } else {
+            let ip: [u8; 4] = ip.try_into().unwrap();
+            SocketAddr::new(Ipv4Addr::from(ip).into(), port)
+        };
+        Ok((
+            remain,
+            ObservedAddressFrame {
+                sequence_number,
+                address,
+            },
+        ))
+    }
+}
+
+impl<T: bytes::BufMut> super::io::WriteFrame<ObservedAddressFrame> for T {
+    fn put_frame(&mut self, frame: &ObservedAddressFrame) {
+        let frame_type = OBSERVED_ADDRESS_FRAME_TYPE | frame.address.is_ipv6() as u32;
+        self.put_varint(&VarInt::from_u32(frame_type));
+        self.put_varint(&frame.sequence_number);
+        match frame.address {
+            SocketAddr::V4(addr) => self.put_slice(&addr.ip().octets()),
+            SocketAddr::V6(addr) => self.put_slice(&addr.ip().octets()),
+        }
+        self.put_u16(frame.address.port());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{be_frame_type, io::WriteFrame};
+
+    #[test]
+    fn test_observed_address_frame() {
+        for address in ["203.0.113.7:40000", "[2001:db8::7]:40000"] {
+            let frame = ObservedAddressFrame {
+                sequence_number: VarInt::from_u32(3),
+                address: address.parse().unwrap(),
+            };
+            let mut buf = Vec::new();
+            buf.put_frame(&frame);
+            assert_eq!(buf.len(), frame.encoding_size());
+
+            let (remain, fty) = be_frame_type(&buf).unwrap();
+            assert_eq!(fty, frame.frame_type());
+            let FrameType::ObservedAddress(ipv6) = fty else {
+                panic!("wrong frame type: {fty:?}");
+            };
+            let (remain, parsed) = observed_address_frame_with_ipv6(ipv6)(remain).unwrap();
+            assert!(remain.is_empty());
+            assert_eq!(parsed, frame);
+        }
+    }
+
+    #[test]
+    fn test_truncated_observed_address_frame() {
+        let buf = [0x03, 203, 0, 113];
+        assert!(observed_address_frame_with_ipv6(0)(&buf).is_err());
+    }
+}
@@ -9,6 +9,7 @@ pub mod streamid;
 pub mod token;
 pub mod util;
 pub mod varint;
+pub mod version;
 
 #[cfg(test)]
 mod tests {}
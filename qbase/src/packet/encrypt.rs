@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{ops::Deref, sync::Arc};
 
 use rustls::quic::{HeaderProtectionKey, PacketKey};
 
@@ -69,5 +69,177 @@ pub fn encode_short_first_byte(first_byte: &mut u8, pn_len: usize, key_phase: Ke
     *first_byte |= clear_bits.deref();
 }
 
+/// A packet fully populated in a send buffer, waiting to be sealed.
+///
+/// In a GSO batch, each packet borrows its own segment of the batch buffer,
+/// so that sealing never touches the neighbouring packets.
+#[derive(Debug)]
+pub struct PacketBuf<'b> {
+    /// The whole packet, including the space for the tag at the end.
+    pub buf: &'b mut [u8],
+    pub pn: u64,
+    /// The offset of the packet number, i.e., the length of the header.
+    pub payload_offset: usize,
+    pub pn_len: usize,
+}
+
+impl PacketBuf<'_> {
+    fn body_offset(&self) -> usize {
+        self.payload_offset + self.pn_len
+    }
+}
+
+/// The local keys of one key generation, ready to seal packets.
+///
+/// The cipher instances are constructed only once when the keys are installed,
+/// sealing a packet does no setup but encrypting. A key update installs a new
+/// generation, the batches sealing with the old [`SealKeys`] are not affected,
+/// as the key phase bit obtained together with the keys.
+#[derive(Clone)]
+pub struct SealKeys {
+    packet: Arc<dyn PacketKey>,
+    header: Arc<dyn HeaderProtectionKey>,
+}
+
+impl SealKeys {
+    pub fn new(packet: Arc<dyn PacketKey>, header: Arc<dyn HeaderProtectionKey>) -> Self {
+        Self { packet, header }
+    }
+
+    pub fn tag_len(&self) -> usize {
+        self.packet.tag_len()
+    }
+
+    /// Encrypt the packet body and then protect the header, see [`encrypt_packet`]
+    /// and [`protect_header`].
+    pub fn seal(&self, packet: &mut PacketBuf) {
+        self.encrypt(packet);
+        self.protect(packet);
+    }
+
+    /// Seal a batch of packets, usually the segments of a GSO buffer, in one call.
+    ///
+    /// All the bodies are encrypted first, and then the headers are protected by
+    /// sampling the encrypted payloads in place, no copy is made.
+    pub fn seal_batch(&self, packets: &mut [PacketBuf]) {
+        for packet in packets.iter_mut() {
+            self.encrypt(packet);
+        }
+        for packet in packets.iter_mut() {
+            self.protect(packet);
+        }
+    }
+
+    fn encrypt(&self, packet: &mut PacketBuf) {
+        let body_offset = packet.body_offset();
+        encrypt_packet(self.packet.as_ref(), packet.pn, packet.buf, body_offset);
+    }
+
+    fn protect(&self, packet: &mut PacketBuf) {
+        // 采样不得越过本包，否则会采到下一个包的数据
+        debug_assert!(packet.payload_offset + 4 + self.header.sample_len() <= packet.buf.len());
+        protect_header(
+            self.header.as_ref(),
+            packet.buf,
+            packet.payload_offset,
+            packet.pn_len,
+        );
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use rustls::{quic::Keys, CipherSuite, Side};
+
+    use super::*;
+    use crate::packet::{
+        decrypt::{decrypt_packet, remove_protection_of_short_packet},
+        PacketNumber, WritePacketNumber,
+    };
+
+    const SEGMENT: usize = 64;
+    const HDR_LEN: usize = 9;
+
+    fn initial_keys(side: Side) -> Keys {
+        let suite = rustls::crypto::ring::default_provider()
+            .cipher_suites
+            .iter()
+            .find_map(|cs| match (cs.suite(), cs.tls13()) {
+                (CipherSuite::TLS13_AES_128_GCM_SHA256, Some(suite)) => suite.quic_suite(),
+                _ => None,
+            })
+            .unwrap();
+        suite.keys(&[0x83, 0x94, 0xc8, 0xf0], side, rustls::quic::Version::V1)
+    }
+
+    fn seal_keys(keys: Keys) -> SealKeys {
+        SealKeys::new(Arc::from(keys.local.packet), Arc::from(keys.local.header))
+    }
+
+    // 短包头：首字节 + 8字节dcid，4字节包号，其后是包体和tag
+    fn fill(segment: &mut [u8], pn: u64) {
+        let encoded_pn = PacketNumber::encode(pn, 0);
+        segment[0] = 0x40;
+        encode_short_first_byte(&mut segment[0], encoded_pn.size(), KeyPhaseBit::default());
+        segment[1..HDR_LEN].fill(0xcd);
+        (&mut segment[HDR_LEN..]).put_packet_number(encoded_pn);
+        let body_offset = HDR_LEN + encoded_pn.size();
+        for (i, b) in segment[body_offset..].iter_mut().enumerate() {
+            *b = pn as u8 ^ i as u8;
+        }
+    }
+
+    fn packet_bufs(batch: &mut [u8], first_pn: u64) -> Vec<PacketBuf<'_>> {
+        batch
+            .chunks_mut(SEGMENT)
+            .zip(first_pn..)
+            .map(|(buf, pn)| PacketBuf {
+                buf,
+                pn,
+                payload_offset: HDR_LEN,
+                pn_len: PacketNumber::encode(pn, 0).size(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_seal_batch() {
+        let local = seal_keys(initial_keys(Side::Client));
+        let tag_len = local.tag_len();
+        // 最后一个包比其他的短，GSO允许之
+        let mut batch = vec![0u8; SEGMENT * 4 - 20];
+        for (segment, pn) in batch.chunks_mut(SEGMENT).zip(0..) {
+            fill(segment, pn);
+        }
+        let mut single = batch.clone();
+        let plain = batch.clone();
+
+        local.seal_batch(&mut packet_bufs(&mut batch, 0));
+        for mut packet in packet_bufs(&mut single, 0) {
+            local.seal(&mut packet);
+        }
+        assert_eq!(batch, single);
+
+        let remote = initial_keys(Side::Server).remote;
+        for ((segment, plain), pn) in batch
+            .chunks_mut(SEGMENT)
+            .zip(plain.chunks(SEGMENT))
+            .zip(0u64..)
+        {
+            let (undecoded_pn, key_phase) =
+                remove_protection_of_short_packet(remote.header.as_ref(), segment, HDR_LEN)
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(undecoded_pn.decode(0), pn);
+            assert_eq!(key_phase, KeyPhaseBit::default());
+            let body_offset = HDR_LEN + undecoded_pn.size();
+            let len = decrypt_packet(remote.packet.as_ref(), pn, segment, body_offset).unwrap();
+            assert_eq!(len, segment.len() - body_offset - tag_len);
+            assert_eq!(&segment[..body_offset], &plain[..body_offset]);
+            assert_eq!(
+                &segment[body_offset..body_offset + len],
+                &plain[body_offset..body_offset + len]
+            );
+        }
+    }
+}
@@ -6,7 +6,7 @@ pub mod long;
 pub mod short;
 
 pub use long::{
-    ext::{LongHeaderBuilder, Write, WriteLongHeader},
+    ext::{verify_retry_integrity, LongHeaderBuilder, Write, WriteLongHeader},
     DataHeader, HandshakeHeader, InitialHeader, LongHeader, RetryHeader, VersionNegotiationHeader,
     ZeroRttHeader,
 };
@@ -48,6 +48,12 @@ impl Encode for Initial {
     }
 }
 
+impl Encode for Retry {
+    fn size(&self) -> usize {
+        self.token.len() + self.integrity.len()
+    }
+}
+
 impl Encode for ZeroRtt {}
 impl Encode for Handshake {}
 
@@ -180,6 +186,47 @@ pub(super) mod ext {
         Ok((&[][..], Retry::from_slice(token, integrity)))
     }
 
+    /// See [RFC 9001 section 5.8](https://www.rfc-editor.org/rfc/rfc9001.html#section-5.8),
+    /// the packet is the Retry packet without the integrity tag.
+    pub fn retry_integrity_tag(odcid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
+        use ring::aead;
+
+        const KEY: [u8; 16] = [
+            0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54, 0xe3, 0x68,
+            0xc8, 0x4e,
+        ];
+        const NONCE: [u8; 12] = [
+            0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
+        ];
+
+        let mut pseudo_packet = Vec::with_capacity(1 + odcid.len() + packet.len());
+        pseudo_packet.put_connection_id(odcid);
+        pseudo_packet.put_slice(packet);
+
+        let key = aead::LessSafeKey::new(
+            aead::UnboundKey::new(&aead::AES_128_GCM, &KEY).expect("valid retry integrity key"),
+        );
+        let tag = key
+            .seal_in_place_separate_tag(
+                aead::Nonce::assume_unique_for_key(NONCE),
+                aead::Aad::from(pseudo_packet),
+                &mut [],
+            )
+            .expect("sealing an empty plaintext never fails");
+        tag.as_ref().try_into().unwrap()
+    }
+
+    /// Verify the integrity tag at the end of the Retry `packet` with the original destination
+    /// connection id, a Retry packet failing it MUST be discarded by the client.
+    pub fn verify_retry_integrity(odcid: &ConnectionId, packet: &[u8]) -> bool {
+        let Some(tag_offset) = packet.len().checked_sub(16) else {
+            return false;
+        };
+        let (packet, tag) = packet.split_at(tag_offset);
+        ring::constant_time::verify_slices_are_equal(&retry_integrity_tag(odcid, packet), tag)
+            .is_ok()
+    }
+
     pub struct LongHeaderBuilder {
         pub(crate) dcid: ConnectionId,
         pub(crate) scid: ConnectionId,
@@ -202,6 +249,20 @@ pub(super) mod ext {
             self.wrap(Handshake)
         }
 
+        /// Build a Retry header, the integrity tag is computed over the Retry
+        /// pseudo-packet with the original destination connection id.
+        pub fn retry(self, odcid: &ConnectionId, token: Vec<u8>) -> LongHeader<Retry> {
+            let mut retry = self.wrap(Retry {
+                token,
+                integrity: [0; 16],
+            });
+            let mut packet = Vec::new();
+            packet.put_long_header(&retry);
+            packet.truncate(packet.len() - 16);
+            retry.integrity = retry_integrity_tag(odcid, &packet);
+            retry
+        }
+
         pub fn wrap<T>(self, specific: T) -> LongHeader<T> {
             LongHeader {
                 dcid: self.dcid,
@@ -306,6 +367,55 @@ mod tests {
         assert_eq!(remain.len(), 0);
     }
 
+    #[test]
+    fn test_retry_integrity_tag() {
+        use super::ext::{retry_integrity_tag, LongHeaderBuilder, WriteLongHeader};
+        use crate::cid::ConnectionId;
+
+        // RFC 9001 Appendix A.4
+        let odcid = ConnectionId::from_slice(&[0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08]);
+        let packet = [
+            0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50, 0x2a, 0x42, 0x62,
+            0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e,
+        ];
+        assert_eq!(
+            retry_integrity_tag(&odcid, &packet),
+            [
+                0x04, 0xa2, 0x65, 0xba, 0x2e, 0xff, 0x4d, 0x82, 0x90, 0x58, 0xfb, 0x3f, 0x0f, 0x24,
+                0x96, 0xba
+            ]
+        );
+
+        let scid = ConnectionId::from_slice(&packet[7..15]);
+        let retry = LongHeaderBuilder::with_cid(ConnectionId::default(), scid)
+            .retry(&odcid, b"token".to_vec());
+        let mut buf = Vec::new();
+        buf.put_long_header(&retry);
+        let (packet, tag) = buf.split_at(buf.len() - 16);
+        assert_eq!(retry_integrity_tag(&odcid, packet), tag);
+        assert_ne!(retry_integrity_tag(&scid, packet), tag);
+    }
+
+    #[test]
+    fn test_verify_retry_integrity() {
+        use super::ext::{verify_retry_integrity, LongHeaderBuilder, WriteLongHeader};
+        use crate::{cid::ConnectionId, packet::header::Encode};
+
+        let odcid = ConnectionId::random_gen(8);
+        let scid = ConnectionId::random_gen(8);
+        let retry = LongHeaderBuilder::with_cid(ConnectionId::random_gen(8), scid)
+            .retry(&odcid, vec![7; 32]);
+        let mut packet = Vec::new();
+        packet.put_long_header(&retry);
+        assert_eq!(packet.len(), retry.size());
+        assert!(verify_retry_integrity(&odcid, &packet));
+        // 换了原始的目的连接ID，或者令牌被篡改，都通不过校验
+        assert!(!verify_retry_integrity(&scid, &packet));
+        packet[20] ^= 0x01;
+        assert!(!verify_retry_integrity(&odcid, &packet));
+        assert!(!verify_retry_integrity(&odcid, &packet[..10]));
+    }
+
     #[test]
     fn test_be_retry() {
         use super::ext::be_retry;
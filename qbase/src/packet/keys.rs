@@ -9,6 +9,7 @@ use std::{
 use rustls::quic::{HeaderProtectionKey, Keys, PacketKey, Secrets};
 
 use super::KeyPhaseBit;
+use crate::error::{Error as QuicError, ErrorKind};
 
 #[derive(Clone)]
 enum KeysState {
@@ -59,6 +60,23 @@ impl ArcKeys {
         }
     }
 
+    /// Replace the keys in use. Only the Initial keys are ever replaced, when the client
+    /// derives them again from the source connection id of a Retry packet. It has no effect
+    /// after the keys are invalidated.
+    pub fn replace_keys(&self, keys: Keys) {
+        let mut state = self.lock_guard();
+        match &mut *state {
+            KeysState::Pending(rx_waker) => {
+                if let Some(waker) = rx_waker.take() {
+                    waker.wake();
+                }
+                *state = KeysState::Ready(Arc::new(keys));
+            }
+            KeysState::Ready(_) => *state = KeysState::Ready(Arc::new(keys)),
+            KeysState::Invalid => {}
+        }
+    }
+
     /// Invalidate the keys, which means that the keys are no longer available.
     /// This is used when the connection enters the closing state or draining state.
     /// Especially in the closing state, the return keys are used to generate the final packet
@@ -101,45 +119,186 @@ impl Future for GetRemoteKeys {
 pub struct OneRttPacketKeys {
     cur_key_phase: KeyPhaseBit,
     secrets: Secrets,
+    // The remote keys of the current key phase and the previous one, indexed by the key phase
     remote: [Option<Arc<dyn PacketKey>>; 2],
     local: Arc<dyn PacketKey>,
+    // The next generation of keys, derived in advance to try on the packets of the next key phase
+    next: (Arc<dyn PacketKey>, Arc<dyn PacketKey>),
+    // The smallest packet number received in the current key phase, the packets of the other
+    // key phase below it are protected with the previous keys, and above it with the next keys
+    first_rcvd_pn: Option<u64>,
+    // Whether an ACK frame acknowledging the packets received in the current key phase has been
+    // sent with the current keys
+    is_rcvd_acked: bool,
+    // The first packet number sent in the current key phase, and whether any packet since then
+    // has been acknowledged
+    first_sent_pn: Option<u64>,
+    is_sent_acked: bool,
+    // The number of packets sealed with the current local key, and how many may be sealed before
+    // initiating a key update
+    sealed: u64,
+    update_interval: u64,
+    // How many times the keys have been updated
+    generation: u64,
 }
 
 impl OneRttPacketKeys {
-    fn new(remote: Box<dyn PacketKey>, local: Box<dyn PacketKey>, secrets: Secrets) -> Self {
+    fn new(remote: Box<dyn PacketKey>, local: Box<dyn PacketKey>, mut secrets: Secrets) -> Self {
+        let next = secrets.next_packet_keys();
+        let update_interval = Self::default_update_interval(local.as_ref());
         Self {
             cur_key_phase: KeyPhaseBit::default(),
             secrets,
             remote: [Some(Arc::from(remote)), None],
             local: Arc::from(local),
+            next: (Arc::from(next.local), Arc::from(next.remote)),
+            first_rcvd_pn: None,
+            is_rcvd_acked: false,
+            first_sent_pn: None,
+            is_sent_acked: false,
+            sealed: 0,
+            update_interval,
+            generation: 0,
         }
     }
 
-    /// Key actively upgrades, which occurs when we want to actively change the key.
-    pub fn update(&mut self) {
+    /// A key update is initiated at 3/4 of the confidentiality limit of the AEAD algorithm,
+    /// leaving enough packets for the peer to acknowledge the previous update.
+    fn default_update_interval(key: &dyn PacketKey) -> u64 {
+        key.confidentiality_limit() / 4 * 3
+    }
+
+    /// Set how many packets are sealed with a generation of keys before a key update is
+    /// initiated automatically, `None` means the default, 3/4 of the confidentiality limit
+    /// of the AEAD algorithm. It is still capped by the confidentiality limit.
+    pub fn set_update_interval(&mut self, packets: Option<u64>) {
+        let default = Self::default_update_interval(self.local.as_ref());
+        self.update_interval = packets.map_or(default, |packets| {
+            packets.clamp(1, self.local.confidentiality_limit())
+        });
+    }
+
+    // Move to the next key phase, with the next generation of keys in both directions.
+    fn next_phase(&mut self) {
         self.cur_key_phase.toggle();
         let key_set = self.secrets.next_packet_keys();
-        self.remote[self.cur_key_phase.as_index()] = Some(Arc::from(key_set.remote));
-        self.local = Arc::from(key_set.local);
+        let (local, remote) = std::mem::replace(
+            &mut self.next,
+            (Arc::from(key_set.local), Arc::from(key_set.remote)),
+        );
+        self.remote[self.cur_key_phase.as_index()] = Some(remote);
+        self.local = local;
+        self.first_rcvd_pn = None;
+        self.is_rcvd_acked = false;
+        self.first_sent_pn = None;
+        self.is_sent_acked = false;
+        self.sealed = 0;
+        self.generation += 1;
+    }
+
+    /// How many times the keys have been updated, initiated by either side.
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
-    /// Old key must be phased out within a certain period of time. If the old one don't go,
-    /// the new ones won't come. If it is not phased out, it will be considered as new keys
-    /// after actively changing the keys, leading to the failure of decrypting the data packets
-    /// received from the other party.
-    pub fn phase_out(&mut self) {
-        self.remote[(!self.cur_key_phase).as_index()].take();
+    /// Key actively upgrades, which occurs when we want to actively change the key.
+    ///
+    /// A subsequent key update must not be initiated until a packet sent with the current
+    /// keys has been acknowledged, see [RFC 9001 section 6.1](https://www.rfc-editor.org/rfc/rfc9001.html#section-6.1),
+    /// in which case it returns false. Since an acknowledgment of a 1-RTT packet also confirms
+    /// the handshake, the first key update never happens before the handshake is confirmed.
+    pub fn update(&mut self) -> bool {
+        if !self.is_sent_acked {
+            return false;
+        }
+        self.next_phase();
+        true
+    }
+
+    // Whether the packet of the key phase is protected with the next generation of keys, or
+    // else with the current or the previous one.
+    fn is_next_phase(&self, key_phase: KeyPhaseBit, pn: u64) -> bool {
+        key_phase != self.cur_key_phase
+            && match self.first_rcvd_pn {
+                Some(first_rcvd_pn) => pn > first_rcvd_pn,
+                None => self.remote[key_phase.as_index()].is_none(),
+            }
     }
 
-    /// Get the remote key to decrypt the incoming packet.
-    /// If the key phase is not the current key phase, update the key.
+    /// Get the remote key to decrypt the incoming packet, which is either the current key,
+    /// the previous one for the delayed packets, or the next one if the peer initiated a key
+    /// update, distinguished by the packet number, see [RFC 9001 section 6.5](https://www.rfc-editor.org/rfc/rfc9001.html#section-6.5).
+    ///
+    /// The keys don't change until the packet is decrypted and [`Self::on_rcvd`] is called,
+    /// a forged packet can't make a key update happen. Returns `None` if the previous key
+    /// is needed but not available, the packet should be dropped.
+    ///
     /// Returning `Arc<PacketKey>` is to encrypt and decrypt packets at the same time.
     /// Compared to &'a PacketKey, `Arc<PacketKey>` does not occupy mutable borrowing &mut self.
-    pub fn get_remote(&mut self, key_phase: KeyPhaseBit, _pn: u64) -> Arc<dyn PacketKey> {
-        if key_phase != self.cur_key_phase && self.remote[key_phase.as_index()].is_none() {
-            self.update();
+    pub fn get_remote(&self, key_phase: KeyPhaseBit, pn: u64) -> Option<Arc<dyn PacketKey>> {
+        if self.is_next_phase(key_phase, pn) {
+            Some(self.next.1.clone())
+        } else {
+            self.remote[key_phase.as_index()].clone()
         }
-        self.remote[key_phase.as_index()].clone().unwrap()
+    }
+
+    /// Called after the packet is decrypted by the key returned by [`Self::get_remote`].
+    ///
+    /// If the packet is of the next key phase, the peer initiated a key update, and the local
+    /// keys are updated in response. It's an error of KEY_UPDATE_ERROR if the peer updates the
+    /// keys again before the packets received in the current key phase are acknowledged,
+    /// see [RFC 9001 section 6.2](https://www.rfc-editor.org/rfc/rfc9001.html#section-6.2).
+    pub fn on_rcvd(&mut self, key_phase: KeyPhaseBit, pn: u64) -> Result<(), QuicError> {
+        if self.is_next_phase(key_phase, pn) {
+            if self.first_rcvd_pn.is_some() && !self.is_rcvd_acked {
+                return Err(QuicError::with_default_fty(
+                    ErrorKind::KeyUpdate,
+                    "consecutive key updates without awaiting the acknowledgment",
+                ));
+            }
+            self.next_phase();
+        }
+        if key_phase == self.cur_key_phase {
+            self.first_rcvd_pn = Some(self.first_rcvd_pn.map_or(pn, |first| first.min(pn)));
+        }
+        Ok(())
+    }
+
+    /// Called when a packet number sent in the data space is acknowledged, once any packet
+    /// sent in the current key phase is acknowledged, the next key update is allowed.
+    pub fn on_acked(&mut self, pn: u64) {
+        if self.first_sent_pn.is_some_and(|first| pn >= first) {
+            self.is_sent_acked = true;
+        }
+    }
+
+    /// Get the local key with the current key phase to seal a batch of outgoing packets, each
+    /// given by its packet number and the largest packet number acknowledged by its ACK frame,
+    /// if any.
+    ///
+    /// A key update is initiated automatically, once the packets sealed with the current key
+    /// reach the update interval, see [`Self::set_update_interval`]. It's initiated before the
+    /// batch, never in the middle of it, all the packets of a batch are sealed with the same
+    /// generation of keys.
+    pub fn seal_batch(
+        &mut self,
+        packets: impl IntoIterator<Item = (u64, Option<u64>)>,
+    ) -> (KeyPhaseBit, Arc<dyn PacketKey>) {
+        if self.sealed >= self.update_interval && self.update() {
+            log::debug!(
+                "{} packets sealed, initiate a key update",
+                self.update_interval
+            );
+        }
+        for (pn, acked) in packets {
+            self.sealed += 1;
+            self.first_sent_pn = Some(self.first_sent_pn.map_or(pn, |first| first.min(pn)));
+            if let (Some(first), Some(acked)) = (self.first_rcvd_pn, acked) {
+                self.is_rcvd_acked |= acked >= first;
+            }
+        }
+        (self.cur_key_phase, self.local.clone())
     }
 
     /// Get the local key with the current key phase to encrypt the outgoing packet.
@@ -171,7 +330,8 @@ pub struct ArcHeaderProtectionKeys {
 }
 
 enum OneRttKeysState {
-    Pending(Option<Waker>),
+    // The waker of the receiving task, and the update interval set before the keys are ready
+    Pending(Option<Waker>, Option<u64>),
     Ready {
         hpk: ArcHeaderProtectionKeys,
         pk: ArcOneRttPacketKeys,
@@ -188,26 +348,22 @@ impl ArcOneRttKeys {
     }
 
     pub fn new_pending() -> Self {
-        Self(Arc::new(OneRttKeysState::Pending(None).into()))
+        Self(Arc::new(OneRttKeysState::Pending(None, None).into()))
     }
 
     pub fn set_keys(&self, keys: Keys, secrets: Secrets) {
         let mut state = self.lock_guard();
         match &mut *state {
-            OneRttKeysState::Pending(waker) => {
+            OneRttKeysState::Pending(waker, update_interval) => {
                 let hpk = ArcHeaderProtectionKeys {
                     local: Arc::from(keys.local.header),
                     remote: Arc::from(keys.remote.header),
                 };
                 let tag_len = keys.local.packet.tag_len();
-                let pk = ArcOneRttPacketKeys(Arc::new((
-                    Mutex::new(OneRttPacketKeys::new(
-                        keys.remote.packet,
-                        keys.local.packet,
-                        secrets,
-                    )),
-                    tag_len,
-                )));
+                let mut packet_keys =
+                    OneRttPacketKeys::new(keys.remote.packet, keys.local.packet, secrets);
+                packet_keys.set_update_interval(*update_interval);
+                let pk = ArcOneRttPacketKeys(Arc::new((Mutex::new(packet_keys), tag_len)));
                 if let Some(w) = waker.take() {
                     w.wake();
                 }
@@ -221,7 +377,7 @@ impl ArcOneRttKeys {
     pub fn invalid(&self) -> Option<(ArcHeaderProtectionKeys, ArcOneRttPacketKeys)> {
         let mut state = self.lock_guard();
         match std::mem::replace(state.deref_mut(), OneRttKeysState::Invalid) {
-            OneRttKeysState::Pending(rx_waker) => {
+            OneRttKeysState::Pending(rx_waker, _) => {
                 if let Some(waker) = rx_waker {
                     waker.wake();
                 }
@@ -243,6 +399,37 @@ impl ArcOneRttKeys {
     pub fn get_remote_keys(&self) -> GetRemoteOneRttKeys {
         GetRemoteOneRttKeys(self.clone())
     }
+
+    /// Initiate a key update, see [`OneRttPacketKeys::update`].
+    ///
+    /// Returns false if the keys are not ready yet, or no packet sent with the current keys
+    /// has been acknowledged yet.
+    pub fn update(&self) -> bool {
+        match &*self.lock_guard() {
+            OneRttKeysState::Ready { pk, .. } => pk.lock_guard().update(),
+            _ => false,
+        }
+    }
+
+    /// How many times the keys have been updated, 0 if the keys are not ready.
+    pub fn generation(&self) -> u64 {
+        match &*self.lock_guard() {
+            OneRttKeysState::Ready { pk, .. } => pk.lock_guard().generation(),
+            _ => 0,
+        }
+    }
+
+    /// Set how many packets are sealed with a generation of keys before a key update is
+    /// initiated automatically, see [`OneRttPacketKeys::set_update_interval`].
+    ///
+    /// It can be set before the keys are ready, and takes effect once they are.
+    pub fn set_update_interval(&self, packets: Option<u64>) {
+        match &mut *self.lock_guard() {
+            OneRttKeysState::Pending(_, update_interval) => *update_interval = packets,
+            OneRttKeysState::Ready { pk, .. } => pk.lock_guard().set_update_interval(packets),
+            OneRttKeysState::Invalid => {}
+        }
+    }
 }
 
 pub struct GetRemoteOneRttKeys(ArcOneRttKeys);
@@ -253,7 +440,7 @@ impl Future for GetRemoteOneRttKeys {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut keys = self.0.lock_guard();
         match &mut *keys {
-            OneRttKeysState::Pending(waker) => {
+            OneRttKeysState::Pending(waker, _) => {
                 assert!(waker.is_none());
                 *waker = Some(cx.waker().clone());
                 Poll::Pending
@@ -1,10 +1,16 @@
 use std::{
-    fmt, ops,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    ops,
+    pin::Pin,
     sync::{Arc, Mutex},
-    task::{Context, Poll, Waker},
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
 };
 
 use thiserror::Error;
+use tokio::time::{Instant, Sleep};
 
 use super::varint::{be_varint, VarInt, WriteVarInt};
 
@@ -84,6 +90,9 @@ pub struct StreamId(u64);
 
 pub const MAX_STREAM_ID: u64 = (1 << 60) - 1;
 
+/// The RTT the stream open pacer assumes before the first sample, as RFC 9002 does.
+pub const INITIAL_RTT: Duration = Duration::from_millis(333);
+
 impl StreamId {
     /// It is prohibited to directly create a StreamId from external sources. StreamId can
     /// only be allocated incrementally by the StreamId manager or received from the peer.
@@ -200,12 +209,98 @@ impl Iterator for NeedCreate {
     }
 }
 
+/// The rate of opening the local streams, independent of the MAX_STREAMS limit of peer,
+/// see [`ArcLocalStreamIds::set_open_rate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOpenRate {
+    /// The number of streams that can be opened at once.
+    pub burst: u32,
+    /// The number of streams that can be opened per RTT once the burst is used up.
+    pub per_rtt: u32,
+}
+
+/// 打开流的令牌桶，令牌按RTT补充，与对方给的流额度无关，
+/// 只为把大量同时发起的打开平摊开，免得一下子发出一堆只有首帧的小包
+#[derive(Debug)]
+struct OpenPacer {
+    rate: StreamOpenRate,
+    tokens: f64,
+    refilled_at: Instant,
+    // 双向流与单向流各自排队，各有各的计时器
+    sleeps: [Option<Pin<Box<Sleep>>>; 2],
+}
+
+impl OpenPacer {
+    fn new(rate: StreamOpenRate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst.max(1) as f64,
+            refilled_at: now,
+            sleeps: [None, None],
+        }
+    }
+
+    /// Take a token at `now`, or return when the next token is available.
+    fn try_acquire(&mut self, now: Instant, rtt: Duration) -> Result<(), Instant> {
+        let burst = self.rate.burst.max(1) as f64;
+        let per_rtt = self.rate.per_rtt.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() / rtt.as_secs_f64() * per_rtt).min(burst);
+        self.refilled_at = now;
+        // 容忍浮点误差，否则到点醒来时可能还差一丝而空转
+        if self.tokens >= 1.0 - 1e-9 {
+            self.tokens = (self.tokens - 1.0).max(0.0);
+            Ok(())
+        } else {
+            Err(now + rtt.mul_f64((1.0 - self.tokens) / per_rtt))
+        }
+    }
+
+    fn poll_acquire(&mut self, cx: &mut Context<'_>, dir: Dir, rtt: Duration) -> Poll<()> {
+        let idx = dir as usize;
+        loop {
+            match self.try_acquire(Instant::now(), rtt) {
+                Ok(()) => {
+                    self.sleeps[idx] = None;
+                    return Poll::Ready(());
+                }
+                Err(next) => {
+                    let sleep = self.sleeps[idx]
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(next)));
+                    if sleep.deadline() != next {
+                        sleep.as_mut().reset(next);
+                    }
+                    ready!(sleep.as_mut().poll(cx));
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct LocalStreamIds {
     role: Role,                 // Our role
     max: [StreamId; 2],         // The maximum stream ID we can create
     unallocated: [StreamId; 2], // The stream ID that we have not used
-    wakers: [Option<Waker>; 2], // Used for waiting for the MaxStream frame notification from peer when we have exhausted the creation of stream IDs
+    // The opens waiting in order, for the MaxStream frame notification from peer when we have
+    // exhausted the creation of stream IDs, or for the pacer. Only the first one can allocate.
+    wakers: [VecDeque<Waker>; 2],
+    pacer: Option<OpenPacer>,
+    rtt: Duration,
+    // 应用注册的各个标签，各自预留了首个流ID，按注册的顺序排列
+    lanes: [Vec<Lane>; 2],
+    // 等待注册在前的标签先打开首个流，或等待额度的标签
+    lane_wakers: [Vec<Waker>; 2],
+    // 已告知过对方STREAMS_BLOCKED的额度
+    blocked: [Option<StreamId>; 2],
+}
+
+#[derive(Debug)]
+struct Lane {
+    label: String,
+    first: StreamId,
+    opened: bool,
 }
 
 impl LocalStreamIds {
@@ -220,7 +315,12 @@ impl LocalStreamIds {
                 StreamId::new(role, Dir::Bi, 0),
                 StreamId::new(role, Dir::Uni, 0),
             ],
-            wakers: [None, None],
+            wakers: [VecDeque::new(), VecDeque::new()],
+            pacer: None,
+            rtt: INITIAL_RTT,
+            lanes: [Vec::new(), Vec::new()],
+            lane_wakers: [Vec::new(), Vec::new()],
+            blocked: [None, None],
         }
     }
 
@@ -234,27 +334,174 @@ impl LocalStreamIds {
         // RFC9000: MAX_STREAMS frames that do not increase the stream limit MUST be ignored.
         if sid.id() < val {
             *sid = StreamId::new(self.role, dir, val);
-            if let Some(waker) = self.wakers[dir as usize].take() {
-                waker.wake();
+            self.wake_first(dir);
+            self.wake_lanes(dir);
+        }
+    }
+
+    fn set_open_rate(&mut self, rate: Option<StreamOpenRate>) {
+        self.pacer = rate.map(|rate| OpenPacer::new(rate, Instant::now()));
+        // 不再限速，或者换了速率，排队的打开都重新来过
+        self.wake_first(Dir::Bi);
+        self.wake_first(Dir::Uni);
+    }
+
+    fn update_rtt(&mut self, rtt: Duration) {
+        if !rtt.is_zero() {
+            self.rtt = rtt;
+        }
+    }
+
+    fn wake_first(&self, dir: Dir) {
+        if let Some(waker) = self.wakers[dir as usize].front() {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// 排在最前面的才能分配，其他的排到队尾等着
+    fn is_first(&mut self, cx: &mut Context<'_>, dir: Dir) -> bool {
+        let queue = &mut self.wakers[dir as usize];
+        match queue.iter().position(|waker| waker.will_wake(cx.waker())) {
+            Some(0) => true,
+            Some(_) => false,
+            None if queue.is_empty() => true,
+            None => {
+                queue.push_back(cx.waker().clone());
+                false
             }
         }
     }
 
+    fn park_first(&mut self, cx: &mut Context<'_>, dir: Dir) {
+        let queue = &mut self.wakers[dir as usize];
+        match queue.front_mut() {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => queue.push_front(cx.waker().clone()),
+        }
+    }
+
     fn poll_alloc_sid(&mut self, cx: &mut Context<'_>, dir: Dir) -> Poll<Option<StreamId>> {
         let idx = dir as usize;
-        let cur = &mut self.unallocated[idx];
-        if cur.id() > MAX_STREAM_ID {
-            Poll::Ready(None)
-        } else if *cur <= self.max[idx] {
-            let id = *cur;
-            *cur = unsafe { cur.next_unchecked() };
-            Poll::Ready(Some(id))
-        } else {
-            assert!(self.wakers[idx].is_none());
+        if self.unallocated[idx].id() > MAX_STREAM_ID {
+            return Poll::Ready(None);
+        }
+        if !self.is_first(cx, dir) {
+            return Poll::Pending;
+        }
+        if self.unallocated[idx] > self.max[idx] {
             // waiting for MAX_STREAMS frame from peer
-            self.wakers[idx] = Some(cx.waker().clone());
+            self.park_first(cx, dir);
             // if Poll::Pending is returned, connection can send a STREAMS_BLOCKED frame to peer
-            Poll::Pending
+            return Poll::Pending;
+        }
+        let rtt = self.rtt;
+        if let Some(pacer) = self.pacer.as_mut() {
+            if pacer.poll_acquire(cx, dir, rtt).is_pending() {
+                self.park_first(cx, dir);
+                return Poll::Pending;
+            }
+        }
+
+        let cur = &mut self.unallocated[idx];
+        let id = *cur;
+        *cur = unsafe { cur.next_unchecked() };
+        let queue = &mut self.wakers[idx];
+        if queue
+            .front()
+            .is_some_and(|waker| waker.will_wake(cx.waker()))
+        {
+            queue.pop_front();
+        }
+        // 轮到下一个，额度与令牌够的话它也能马上分配
+        self.wake_first(dir);
+        Poll::Ready(Some(id))
+    }
+
+    fn register_lanes(&mut self, dir: Dir, labels: Vec<String>) -> Vec<StreamId> {
+        let idx = dir as usize;
+        labels
+            .into_iter()
+            .map(|label| {
+                if let Some(lane) = self.lanes[idx].iter().find(|lane| lane.label == label) {
+                    return lane.first;
+                }
+                let first = self.unallocated[idx];
+                self.unallocated[idx] = unsafe { first.next_unchecked() };
+                self.lanes[idx].push(Lane {
+                    label,
+                    first,
+                    opened: false,
+                });
+                first
+            })
+            .collect()
+    }
+
+    fn lane(&self, dir: Dir, label: &str) -> Option<StreamId> {
+        self.lanes[dir as usize]
+            .iter()
+            .find(|lane| lane.label == label)
+            .map(|lane| lane.first)
+    }
+
+    fn wake_lanes(&mut self, dir: Dir) {
+        for waker in self.lane_wakers[dir as usize].drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn poll_alloc_lane_sid(
+        &mut self,
+        cx: &mut Context<'_>,
+        dir: Dir,
+        label: &str,
+    ) -> Poll<Option<StreamId>> {
+        let idx = dir as usize;
+        let pos = self.lanes[idx]
+            .iter()
+            .position(|lane| lane.label == label && !lane.opened);
+        let Some(pos) = pos else {
+            // 没注册的标签，或者首个流已经打开了，和其他的流一起依次分配
+            return self.poll_alloc_sid(cx, dir);
+        };
+        let first = self.lanes[idx][pos].first;
+        if first.id() > MAX_STREAM_ID {
+            return Poll::Ready(None);
+        }
+        // 预留的流ID也依次使用：注册在前的标签先打开，且要等对方的额度
+        let preceded = self.lanes[idx][..pos].iter().any(|lane| !lane.opened);
+        if preceded || first > self.max[idx] {
+            let wakers = &mut self.lane_wakers[idx];
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+        self.lanes[idx][pos].opened = true;
+        self.wake_lanes(dir);
+        Poll::Ready(Some(first))
+    }
+
+    fn take_blocked(&mut self, dir: Dir) -> Option<StreamId> {
+        let idx = dir as usize;
+        let max = self.max[idx];
+        if self.unallocated[idx] > max && self.blocked[idx] != Some(max) {
+            self.blocked[idx] = Some(max);
+            Some(max)
+        } else {
+            None
+        }
+    }
+
+    /// An open waiting in the queue is abandoned, pass its turn to the next one.
+    fn cancel_alloc(&mut self, dir: Dir, waker: &Waker) {
+        self.lane_wakers[dir as usize].retain(|w| !w.will_wake(waker));
+        let queue = &mut self.wakers[dir as usize];
+        if let Some(pos) = queue.iter().position(|w| w.will_wake(waker)) {
+            queue.remove(pos);
+            if pos == 0 {
+                self.wake_first(dir);
+            }
         }
     }
 }
@@ -327,6 +574,21 @@ impl RemoteStreamIds {
             Poll::Pending
         }
     }
+
+    fn max_sid(&self, dir: Dir) -> u64 {
+        self.max[dir as usize].id()
+    }
+
+    fn extend_max_sid(&mut self, dir: Dir, val: u64) -> bool {
+        let val = val.min(MAX_STREAM_ID);
+        let sid = &mut self.max[dir as usize];
+        if sid.id() < val {
+            *sid = StreamId::new(self.role, dir, val);
+            true
+        } else {
+            false
+        }
+    }
 }
 
 /// Management of stream IDs created actively by us. The maximum stream ID
@@ -359,9 +621,77 @@ impl ArcLocalStreamIds {
     /// to inform peer to increase MAX_STREAMS. It is also possible that we have reached the
     /// maximum stream ID and cannot increase it further. In this case, we should close the connection
     /// because sending MAX_STREAMS will not be received and would violate the protocol.
+    ///
+    /// The opens waiting for the MAX_STREAMS frame or the pacer complete in the order they
+    /// were queued.
     pub fn poll_alloc_sid(&self, cx: &mut Context<'_>, dir: Dir) -> Poll<Option<StreamId>> {
         self.0.lock().unwrap().poll_alloc_sid(cx, dir)
     }
+
+    /// Reserve the first stream ID of each lane, i.e. the streams of a kind the application
+    /// opens, like the control stream of HTTP/3, in the order the labels are given. The
+    /// labels already registered keep their IDs. Return the first stream ID of each label.
+    ///
+    /// The first streams of the lanes are opened in the order they are registered, see
+    /// [`ArcLocalStreamIds::poll_alloc_lane_sid`], so both endpoints can rely on which
+    /// stream ID comes from which lane. The streams opened without a label skip the
+    /// reserved IDs.
+    pub fn register_lanes(
+        &self,
+        dir: Dir,
+        labels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Vec<StreamId> {
+        let labels = labels.into_iter().map(Into::into).collect();
+        self.0.lock().unwrap().register_lanes(dir, labels)
+    }
+
+    /// The first stream ID reserved for the lane, None if the label isn't registered.
+    pub fn lane(&self, dir: Dir, label: &str) -> Option<StreamId> {
+        self.0.lock().unwrap().lane(dir, label)
+    }
+
+    /// Allocate a stream ID for the lane. The first stream of the lane gets its reserved
+    /// ID, once the lanes registered before it have opened their first streams and the
+    /// peer allows it. The later streams, or the streams of a label not registered, are
+    /// allocated like [`ArcLocalStreamIds::poll_alloc_sid`].
+    pub fn poll_alloc_lane_sid(
+        &self,
+        cx: &mut Context<'_>,
+        dir: Dir,
+        label: &str,
+    ) -> Poll<Option<StreamId>> {
+        self.0.lock().unwrap().poll_alloc_lane_sid(cx, dir, label)
+    }
+
+    /// Return the limit once the stream IDs are used up and the opens are blocked on the
+    /// peer's MAX_STREAMS, a STREAMS_BLOCKED frame should be sent to the peer then. Each
+    /// limit is returned only once, until the peer raises it and it's used up again.
+    pub fn take_blocked(&self, dir: Dir) -> Option<StreamId> {
+        self.0.lock().unwrap().take_blocked(dir)
+    }
+
+    /// Withdraw an open that's waiting to allocate a stream ID, when its future is dropped,
+    /// so that it doesn't hold up the opens queued after it.
+    pub fn cancel_alloc(&self, dir: Dir, waker: &Waker) {
+        self.0.lock().unwrap().cancel_alloc(dir, waker);
+    }
+
+    /// Limit how fast the local streams are opened, None disables the pacer, which is the
+    /// default.
+    ///
+    /// Opening hundreds of streams at once produces a burst of tiny packets, and once the
+    /// MAX_STREAMS limit is hit, another burst as soon as the peer raises it. The pacer
+    /// spaces the opens out at the given rate per RTT, the opens beyond the MAX_STREAMS
+    /// limit still wait for the peer as usual.
+    pub fn set_open_rate(&self, rate: Option<StreamOpenRate>) {
+        self.0.lock().unwrap().set_open_rate(rate);
+    }
+
+    /// Update the RTT the pacer refills its tokens by, [`INITIAL_RTT`] before the first
+    /// sample.
+    pub fn update_rtt(&self, rtt: Duration) {
+        self.0.lock().unwrap().update_rtt(rtt);
+    }
 }
 
 /// Management of stream IDs used by the peer.
@@ -390,6 +720,19 @@ impl ArcRemoteStreamIds {
     pub fn poll_extend_sid(&self, cx: &mut Context<'_>, dir: Dir) -> Poll<Option<VarInt>> {
         self.0.lock().unwrap().poll_extend_sid(cx, dir)
     }
+
+    /// The maximum stream ID that peer can create currently, in the number of streams
+    /// as carried by the MAX_STREAMS frame.
+    pub fn max_sid(&self, dir: Dir) -> u64 {
+        self.0.lock().unwrap().max_sid(dir)
+    }
+
+    /// Raise the maximum stream ID that peer can create to `val`, capped at the maximum
+    /// allowed by the protocol. Return false if it doesn't increase the limit, no
+    /// MAX_STREAMS frame should be sent then.
+    pub fn extend_max_sid(&self, dir: Dir, val: u64) -> bool {
+        self.0.lock().unwrap().extend_max_sid(dir, val)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -445,15 +788,14 @@ mod tests {
             Poll::Ready(Some(StreamId(0)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[0].is_some());
+        assert!(!local.0.lock().unwrap().wakers[0].is_empty());
         local.permit_max_sid(Dir::Bi, 1);
-        let _ = local.0.lock().unwrap().wakers[0].take();
         assert_eq!(
             local.poll_alloc_sid(&mut cx, Dir::Bi),
             Poll::Ready(Some(StreamId(4)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[0].is_some());
+        assert!(!local.0.lock().unwrap().wakers[0].is_empty());
 
         local.permit_max_sid(Dir::Uni, 2);
         assert_eq!(
@@ -469,7 +811,208 @@ mod tests {
             Poll::Ready(Some(StreamId(10)))
         );
         assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Uni), Poll::Pending);
-        assert!(local.0.lock().unwrap().wakers[1].is_some());
+        assert!(!local.0.lock().unwrap().wakers[1].is_empty());
+    }
+
+    #[test]
+    fn test_take_blocked() {
+        let StreamIds { local, remote: _ } = StreamIds::new(Role::Client, 10, 2);
+        let waker = empty_waker();
+        let mut cx = Context::from_waker(&waker);
+        local.permit_max_sid(Dir::Bi, 0);
+        assert_eq!(local.take_blocked(Dir::Bi), None);
+        assert!(local.poll_alloc_sid(&mut cx, Dir::Bi).is_ready());
+        assert_eq!(local.take_blocked(Dir::Bi), None);
+
+        // 额度用尽后，每个额度只返回一次
+        assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
+        assert_eq!(local.take_blocked(Dir::Bi), Some(StreamId(0)));
+        assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
+        assert_eq!(local.take_blocked(Dir::Bi), None);
+
+        local.permit_max_sid(Dir::Bi, 1);
+        assert_eq!(local.take_blocked(Dir::Bi), None);
+        assert!(local.poll_alloc_sid(&mut cx, Dir::Bi).is_ready());
+        assert_eq!(local.take_blocked(Dir::Bi), Some(StreamId(4)));
+        assert_eq!(local.take_blocked(Dir::Bi), None);
+    }
+
+    struct CountingWaker(std::sync::atomic::AtomicUsize);
+
+    impl futures::task::ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self
+                .0
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+        let counter = Arc::new(CountingWaker(Default::default()));
+        (counter.clone(), futures::task::waker(counter))
+    }
+
+    #[test]
+    fn test_alloc_in_order() {
+        let StreamIds { local, remote: _ } = StreamIds::new(Role::Client, 10, 2);
+        let wakers = [(); 4].map(|_| counting_waker());
+        let mut cxs = wakers
+            .each_ref()
+            .map(|(_, waker)| Context::from_waker(waker));
+        let count = |i: usize| wakers[i].0 .0.load(std::sync::atomic::Ordering::Relaxed);
+
+        assert_eq!(
+            local.poll_alloc_sid(&mut cxs[0], Dir::Bi),
+            Poll::Ready(Some(StreamId(0)))
+        );
+        for cx in cxs.iter_mut() {
+            assert_eq!(local.poll_alloc_sid(cx, Dir::Bi), Poll::Pending);
+        }
+        local.permit_max_sid(Dir::Bi, 2);
+        assert_eq!(count(0), 1);
+
+        // 后来的不能插队
+        assert_eq!(local.poll_alloc_sid(&mut cxs[2], Dir::Bi), Poll::Pending);
+        assert_eq!(
+            local.poll_alloc_sid(&mut cxs[0], Dir::Bi),
+            Poll::Ready(Some(StreamId(4)))
+        );
+        assert_eq!(count(1), 1);
+        assert_eq!(
+            local.poll_alloc_sid(&mut cxs[1], Dir::Bi),
+            Poll::Ready(Some(StreamId(8)))
+        );
+
+        // 额度用完了，第3个排在最前面等着，它放弃之后轮到第4个
+        assert_eq!(local.poll_alloc_sid(&mut cxs[2], Dir::Bi), Poll::Pending);
+        local.cancel_alloc(Dir::Bi, &wakers[2].1);
+        assert_eq!(count(3), 1);
+        local.permit_max_sid(Dir::Bi, 3);
+        assert_eq!(
+            local.poll_alloc_sid(&mut cxs[3], Dir::Bi),
+            Poll::Ready(Some(StreamId(12)))
+        );
+    }
+
+    #[test]
+    fn test_alloc_lanes() {
+        let StreamIds { local, remote: _ } = StreamIds::new(Role::Client, 10, 2);
+        let wakers = [(); 3].map(|_| counting_waker());
+        let mut cxs = wakers
+            .each_ref()
+            .map(|(_, waker)| Context::from_waker(waker));
+        let count = |i: usize| wakers[i].0 .0.load(std::sync::atomic::Ordering::Relaxed);
+
+        let firsts = local.register_lanes(Dir::Uni, ["control", "data", "telemetry"]);
+        assert_eq!(firsts, [StreamId(2), StreamId(6), StreamId(10)]);
+        assert_eq!(local.register_lanes(Dir::Uni, ["data"]), [StreamId(6)]);
+        assert_eq!(local.lane(Dir::Uni, "telemetry"), Some(StreamId(10)));
+        assert_eq!(local.lane(Dir::Bi, "telemetry"), None);
+
+        // 不带标签的流跳过预留的流ID
+        local.permit_max_sid(Dir::Uni, 4);
+        assert_eq!(
+            local.poll_alloc_sid(&mut cxs[0], Dir::Uni),
+            Poll::Ready(Some(StreamId(14)))
+        );
+
+        // 后注册的标签先来打开，也要等注册在前的标签
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[2], Dir::Uni, "telemetry"),
+            Poll::Pending
+        );
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[1], Dir::Uni, "data"),
+            Poll::Pending
+        );
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[0], Dir::Uni, "control"),
+            Poll::Ready(Some(StreamId(2)))
+        );
+        assert_eq!((count(1), count(2)), (1, 1));
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[2], Dir::Uni, "telemetry"),
+            Poll::Pending
+        );
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[1], Dir::Uni, "data"),
+            Poll::Ready(Some(StreamId(6)))
+        );
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[2], Dir::Uni, "telemetry"),
+            Poll::Ready(Some(StreamId(10)))
+        );
+
+        // 首个流之后，和其他流一样依次分配
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[0], Dir::Uni, "control"),
+            Poll::Ready(Some(StreamId(18)))
+        );
+        assert_eq!(
+            local.poll_alloc_lane_sid(&mut cxs[0], Dir::Uni, "control"),
+            Poll::Pending
+        );
+    }
+
+    #[test]
+    fn test_open_pacer() {
+        let rate = StreamOpenRate {
+            burst: 16,
+            per_rtt: 32,
+        };
+        let rtt = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut pacer = OpenPacer::new(rate, start);
+
+        // 在虚拟的时间里依次打开500个流，记下每个完成的时刻
+        let mut now = start;
+        let opened = (0..500)
+            .map(|_| loop {
+                match pacer.try_acquire(now, rtt) {
+                    Ok(()) => break now - start,
+                    Err(next) => {
+                        assert!(next > now);
+                        now = next;
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let within = |time: Duration| opened.iter().filter(|t| **t <= time).count();
+        let margin = Duration::from_micros(10);
+        assert_eq!(within(Duration::ZERO), 16);
+        assert_eq!(within(rtt - margin), 16 + 31);
+        assert_eq!(within(rtt + margin), 16 + 32);
+        assert_eq!(within(rtt * 10 + margin), 16 + 320);
+        // 剩下的484个按每RTT 32个的速率打开
+        let last = opened[499].as_secs_f64();
+        assert!((last - rtt.as_secs_f64() * 484.0 / 32.0).abs() < 1e-3);
+        assert!(opened.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[tokio::test]
+    async fn test_open_pacer_disabled() {
+        let StreamIds { local, remote: _ } = StreamIds::new(Role::Client, 10, 2);
+        local.permit_max_sid(Dir::Bi, 10);
+        local.update_rtt(Duration::from_secs(10));
+        local.set_open_rate(Some(StreamOpenRate {
+            burst: 2,
+            per_rtt: 1,
+        }));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(local.poll_alloc_sid(&mut cx, Dir::Bi).is_ready());
+        assert!(local.poll_alloc_sid(&mut cx, Dir::Bi).is_ready());
+        assert_eq!(local.poll_alloc_sid(&mut cx, Dir::Bi), Poll::Pending);
+
+        local.set_open_rate(None);
+        for id in [8, 12, 16, 20] {
+            assert_eq!(
+                local.poll_alloc_sid(&mut cx, Dir::Bi),
+                Poll::Ready(Some(StreamId(id)))
+            );
+        }
     }
 
     #[test]
@@ -1,19 +1,46 @@
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
 
 use bytes::BufMut;
-use nom::{bytes::complete::take, IResult};
+use nom::{
+    bytes::complete::take,
+    combinator::map_res,
+    number::complete::{be_u16, be_u64, be_u8},
+    IResult,
+};
 use rand::Rng;
+use thiserror::Error;
 
 use crate::{
+    cid::{be_connection_id, ConnectionId, WriteConnectionId},
     error::{Error, ErrorKind},
     frame::{BeFrame, NewTokenFrame, ReceiveFrame},
+    util::canonical_addr,
 };
 
 pub const RESET_TOKEN_SIZE: usize = 16;
 
-#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, Default, Eq)]
 pub struct ResetToken([u8; RESET_TOKEN_SIZE]);
 
+/// Compared in constant time, not to leak how many leading bytes of a forged stateless reset
+/// match a valid token, see RFC 9000 10.3.1.
+impl PartialEq for ResetToken {
+    fn eq(&self, other: &Self) -> bool {
+        ring::constant_time::verify_slices_are_equal(&self.0, &other.0).is_ok()
+    }
+}
+
+impl std::hash::Hash for ResetToken {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
 impl ResetToken {
     pub fn new(bytes: &[u8]) -> Self {
         Self(bytes.try_into().unwrap())
@@ -53,20 +80,404 @@ impl std::ops::Deref for ResetToken {
     }
 }
 
+/// The minimum size of a stateless reset, 5 bytes looking like a short header followed by
+/// the reset token, a smaller datagram is neither sent nor recognized as a stateless reset,
+/// see [section 10.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3) of RFC 9000.
+pub const MIN_STATELESS_RESET_SIZE: usize = 5 + RESET_TOKEN_SIZE;
+
+/// The static key to derive the stateless reset tokens of the issued connection IDs, with
+/// HMAC-SHA256 over the connection ID. The endpoint can still compute the token of a connection
+/// ID after losing the state of the connection, e.g. after a restart, and then reset it.
+///
+/// The key must be kept secret, and stay the same across the restarts to be useful, see
+/// [section 10.3.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3.2) of RFC 9000.
+#[derive(Debug, Clone)]
+pub struct StatelessResetKey(ring::hmac::Key);
+
+impl StatelessResetKey {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret))
+    }
+
+    /// A key from a random secret, the tokens derived from it are no longer recognized by
+    /// this endpoint after a restart.
+    pub fn random() -> Self {
+        let mut secret = [0; 32];
+        rand::thread_rng().fill(&mut secret);
+        Self::new(&secret)
+    }
+
+    /// The stateless reset token of the connection ID `cid`.
+    pub fn reset_token(&self, cid: &ConnectionId) -> ResetToken {
+        let tag = ring::hmac::sign(&self.0, cid);
+        ResetToken::new(&tag.as_ref()[..RESET_TOKEN_SIZE])
+    }
+}
+
+impl Default for StatelessResetKey {
+    fn default() -> Self {
+        Self::random()
+    }
+}
+
+/// Build a stateless reset carrying `token`, in response to a datagram of `received` bytes.
+///
+/// It looks like a short header packet with unpredictable bits, and is always smaller than
+/// the datagram triggering it, so that two endpoints resetting each other can't loop for ever.
+/// None if the received datagram is too small to respond to.
+pub fn stateless_reset(token: &ResetToken, received: usize) -> Option<Vec<u8>> {
+    if received <= MIN_STATELESS_RESET_SIZE {
+        return None;
+    }
+    let mut rng = rand::thread_rng();
+    let size = rng.gen_range(MIN_STATELESS_RESET_SIZE..received);
+    let mut datagram = vec![0; size];
+    let (unpredictable, trailing) = datagram.split_at_mut(size - RESET_TOKEN_SIZE);
+    rng.fill(unpredictable);
+    // 首字节的最高位为0，固定位为1，看起来像是一个短包头
+    unpredictable[0] = 0x40 | (unpredictable[0] & 0x3f);
+    trailing.copy_from_slice(token);
+    Some(datagram)
+}
+
+/// The trailing 16 bytes of `datagram` as a reset token, None if the datagram is too small to
+/// be a stateless reset.
+pub fn trailing_reset_token(datagram: &[u8]) -> Option<ResetToken> {
+    (datagram.len() >= MIN_STATELESS_RESET_SIZE)
+        .then(|| ResetToken::new(&datagram[datagram.len() - RESET_TOKEN_SIZE..]))
+}
+
 pub trait TokenSink: Send + Sync {
     fn sink(&self, server_name: &str, token: Vec<u8>);
 
     fn get_token(&self, server_name: &str) -> Vec<u8>;
 }
 
+/// The first byte of a token is the type tag, tells how the token was provided
+/// to the client, see [RFC 9000 section 8.1.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.1).
+/// The rest of the token is the body, which is opaque and up to the [`TokenProvider`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Retry = 0x01,
+    NewToken = 0x02,
+}
+
+impl TryFrom<u8> for TokenKind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(TokenKind::Retry),
+            0x02 => Ok(TokenKind::NewToken),
+            other => Err(other),
+        }
+    }
+}
+
+/// Parse the type tag and the body of a token, the body must not be empty.
+pub fn be_token(input: &[u8]) -> IResult<&[u8], (TokenKind, &[u8])> {
+    let (body, kind) = map_res(be_u8, TokenKind::try_from)(input)?;
+    if body.is_empty() {
+        return Err(nom::Err::Error(nom::error::make_error(
+            input,
+            nom::error::ErrorKind::Eof,
+        )));
+    }
+    Ok((&[][..], (kind, body)))
+}
+
+/// Tag the body provided by the [`TokenProvider`], to be sent in a Retry packet
+/// or a NEW_TOKEN frame.
+pub fn encode_token(kind: TokenKind, body: &[u8]) -> Vec<u8> {
+    let mut token = Vec::with_capacity(1 + body.len());
+    token.put_u8(kind as u8);
+    token.put_slice(body);
+    token
+}
+
+/// Tag the body of a Retry token, along with the original destination connection id of the
+/// client's first Initial packet, which the server can't remember without keeping state.
+///
+/// The body should bind the odcid, see [`TokenProvider::provide_retry_token`], so that the
+/// odcid carried in clear can't be tampered with.
+pub fn encode_retry_token(odcid: &ConnectionId, body: &[u8]) -> Vec<u8> {
+    let mut token = Vec::with_capacity(2 + odcid.len() + body.len());
+    token.put_u8(TokenKind::Retry as u8);
+    token.put_connection_id(odcid);
+    token.put_slice(body);
+    token
+}
+
+/// Parse the original destination connection id and the body of a Retry token, see
+/// [`encode_retry_token`]. None if the token is not a Retry token or is malformed.
+pub fn be_retry_token(token: &[u8]) -> Option<(ConnectionId, &[u8])> {
+    let (TokenKind::Retry, body) = be_token(token).ok()?.1 else {
+        return None;
+    };
+    let (body, odcid) = be_connection_id(body).ok()?;
+    (!body.is_empty()).then_some((odcid, body))
+}
+
+/// The reasons why a token failed to validate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Error)]
+pub enum TokenError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("token expired")]
+    Expired,
+    #[error("token was issued to another address")]
+    WrongAddress,
+    #[error("token can not be decrypted")]
+    Undecryptable,
+    #[error("token was issued for another original destination connection id")]
+    WrongOdcid,
+}
+
 pub trait TokenProvider: Send + Sync {
-    fn provide_new_token(&self, server_name: &str) -> Vec<u8>;
+    /// The body of the token to be sent in a NEW_TOKEN frame to the client at `peer`, which
+    /// should bind the address of the client, so that the token presented from another
+    /// address doesn't validate that address. The client connects again from another port
+    /// usually, binding the IP address or the subnet is enough.
+    ///
+    /// The peer is in the canonical form, see [`canonical_addr`](crate::util::canonical_addr).
+    fn provide_new_token(&self, server_name: &str, peer: SocketAddr) -> Vec<u8>;
 
-    fn provide_retry_token(&self, server_name: &str) -> Vec<u8>;
+    /// The body of the token to be sent in a Retry packet, which should bind the
+    /// address of the client and the original destination connection id of its first
+    /// Initial packet, the server echoes the latter in the original_destination_connection_id
+    /// transport parameter after validating the token, see [`encode_retry_token`].
+    ///
+    /// The peer is in the canonical form, see [`canonical_addr`](crate::util::canonical_addr).
+    fn provide_retry_token(&self, peer: SocketAddr, odcid: &ConnectionId) -> Vec<u8>;
 
+    /// The dcid is the original destination connection id carried with a Retry token, or
+    /// the destination connection id of the Initial packet carrying a NEW_TOKEN token.
+    /// The peer is in the canonical form as well, so that the token issued to an IPv4
+    /// client is still valid when it arrives through a dual-stack socket.
     // A token sent in a NEW_TOKEN frame or a Retry packet MUST be constructed in
-    // a way that allows the server to identify how it was provided to a client
-    fn validate_token(&self, server_name: String, token: &[u8]) -> bool;
+    // a way that allows the server to identify how it was provided to a client,
+    // which is the type tag, so only the body is passed to the provider.
+    fn validate_token(
+        &self,
+        kind: TokenKind,
+        body: &[u8],
+        peer: SocketAddr,
+        dcid: &ConnectionId,
+    ) -> Result<(), TokenError>;
+}
+
+/// The classification of the token carried in an Initial packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenOutcome {
+    Empty,
+    Valid(TokenKind),
+    /// The kind is None if the token is malformed, so that the type tag is unknown.
+    Invalid(Option<TokenKind>, TokenError),
+}
+
+/// What the server does with the Initial packet, according to the [`TokenOutcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAction {
+    /// The address of the client is validated, only for the valid tokens.
+    Accept,
+    /// Proceed the handshake as if the client did not have a validated address.
+    Proceed,
+    /// Send a Retry packet to validate the address of the client.
+    Retry,
+    /// Discard the Initial packet silently.
+    Discard,
+    /// Close the connection with an INVALID_TOKEN error.
+    Close,
+}
+
+/// What the server does with the Initial packet whose token can not validate the address
+/// of the client, that is, the [`TokenAction`]s except [`TokenAction::Accept`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFallback {
+    Proceed,
+    Retry,
+    Discard,
+    Close,
+}
+
+impl From<TokenFallback> for TokenAction {
+    fn from(fallback: TokenFallback) -> Self {
+        match fallback {
+            TokenFallback::Proceed => TokenAction::Proceed,
+            TokenFallback::Retry => TokenAction::Retry,
+            TokenFallback::Discard => TokenAction::Discard,
+            TokenFallback::Close => TokenAction::Close,
+        }
+    }
+}
+
+/// The policy of the server for the empty tokens and every failure class.
+///
+/// By default, an invalid Retry token closes the connection with INVALID_TOKEN, see
+/// [RFC 9000 section 8.1.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.2),
+/// while the other invalid tokens and the empty token proceed the handshake without
+/// a validated address, see [section 8.1.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.3).
+///
+/// It also tells how many NEW_TOKEN frames the server sends after the handshake is confirmed,
+/// 1 by default, see [`TokenPolicy::new_tokens`].
+#[derive(Debug, Clone)]
+pub struct TokenPolicy {
+    on_empty: TokenFallback,
+    on_invalid: HashMap<(Option<TokenKind>, TokenError), TokenFallback>,
+    new_tokens: usize,
+}
+
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        Self {
+            on_empty: TokenFallback::Proceed,
+            on_invalid: HashMap::new(),
+            new_tokens: 1,
+        }
+    }
+}
+
+impl TokenPolicy {
+    /// How many NEW_TOKEN frames to send after the handshake is confirmed, 0 to send none.
+    ///
+    /// Each token is meant to be used once by the client, so issuing more lets it make as many
+    /// connections without the address validation before the next one is confirmed, see
+    /// [RFC 9000 section 8.1.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.3).
+    pub fn new_tokens(mut self, count: usize) -> Self {
+        self.new_tokens = count;
+        self
+    }
+
+    pub fn on_empty(mut self, action: TokenFallback) -> Self {
+        self.on_empty = action;
+        self
+    }
+
+    /// Set the action for the token of the kind failed with the error, the kind of
+    /// a [`TokenError::Malformed`] token is None.
+    pub fn on_invalid(
+        mut self,
+        kind: Option<TokenKind>,
+        error: TokenError,
+        action: TokenFallback,
+    ) -> Self {
+        self.on_invalid.insert((kind, error), action);
+        self
+    }
+
+    pub fn action(&self, outcome: &TokenOutcome) -> TokenAction {
+        match outcome {
+            TokenOutcome::Empty => self.on_empty.into(),
+            TokenOutcome::Valid(_) => TokenAction::Accept,
+            TokenOutcome::Invalid(kind, error) => match self.on_invalid.get(&(*kind, *error)) {
+                Some(action) => (*action).into(),
+                None if *kind == Some(TokenKind::Retry) => TokenAction::Close,
+                None => self.on_empty.into(),
+            },
+        }
+    }
+}
+
+/// Count of each [`TokenOutcome`], shared by all the connections of a server.
+#[derive(Debug, Default)]
+pub struct TokenStats(Mutex<HashMap<TokenOutcome, u64>>);
+
+impl TokenStats {
+    pub fn record(&self, outcome: TokenOutcome) {
+        *self.0.lock().unwrap().entry(outcome).or_default() += 1;
+    }
+
+    pub fn count(&self, outcome: &TokenOutcome) -> u64 {
+        self.0.lock().unwrap().get(outcome).copied().unwrap_or(0)
+    }
+}
+
+/// Classify the tokens in the Initial packets with the [`TokenProvider`], and decide
+/// what to do with the [`TokenPolicy`].
+#[derive(Clone)]
+pub struct TokenValidator {
+    provider: Arc<dyn TokenProvider>,
+    policy: TokenPolicy,
+    stats: Arc<TokenStats>,
+}
+
+impl TokenValidator {
+    pub fn new(provider: Arc<dyn TokenProvider>, policy: TokenPolicy) -> Self {
+        Self {
+            provider,
+            policy,
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn provider(&self) -> &Arc<dyn TokenProvider> {
+        &self.provider
+    }
+
+    pub fn stats(&self) -> &Arc<TokenStats> {
+        &self.stats
+    }
+
+    /// Classify the token without counting it, the token may be any bytes from the network.
+    pub fn classify(&self, token: &[u8], peer: SocketAddr, dcid: &ConnectionId) -> TokenOutcome {
+        if token.is_empty() {
+            return TokenOutcome::Empty;
+        }
+        let peer = canonical_addr(peer);
+        let (kind, body, dcid) = match be_token(token) {
+            // Retry令牌携带着原始的目的连接ID，由provider核对其是否是颁发时绑定的那个
+            Ok((_, (TokenKind::Retry, _))) => match be_retry_token(token) {
+                Some((odcid, body)) => (TokenKind::Retry, body, odcid),
+                None => {
+                    return TokenOutcome::Invalid(Some(TokenKind::Retry), TokenError::Undecryptable)
+                }
+            },
+            Ok((_, (kind, body))) => (kind, body, *dcid),
+            Err(_) => return TokenOutcome::Invalid(None, TokenError::Malformed),
+        };
+        match self.provider.validate_token(kind, body, peer, &dcid) {
+            Ok(()) => TokenOutcome::Valid(kind),
+            Err(error) => TokenOutcome::Invalid(Some(kind), error),
+        }
+    }
+
+    /// The token to be sent in a Retry packet in response to the first Initial packet, whose
+    /// destination connection id is `odcid`, of the client at `peer`.
+    pub fn retry_token(&self, peer: SocketAddr, odcid: &ConnectionId) -> Vec<u8> {
+        let body = self
+            .provider
+            .provide_retry_token(canonical_addr(peer), odcid);
+        encode_retry_token(odcid, &body)
+    }
+
+    /// The tokens to be sent in NEW_TOKEN frames after the handshake with the client at `peer`
+    /// asking for `server_name` is confirmed, as many as the [`TokenPolicy::new_tokens`]. None
+    /// if the provider issues no NEW_TOKEN token, i.e. an empty body.
+    pub fn new_tokens(&self, server_name: &str, peer: SocketAddr) -> Vec<Vec<u8>> {
+        let peer = canonical_addr(peer);
+        (0..self.policy.new_tokens)
+            .map(|_| self.provider.provide_new_token(server_name, peer))
+            .take_while(|body| !body.is_empty())
+            .map(|body| encode_token(TokenKind::NewToken, &body))
+            .collect()
+    }
+
+    /// Decide what to do with the outcome, without counting it.
+    pub fn action(&self, outcome: &TokenOutcome) -> TokenAction {
+        self.policy.action(outcome)
+    }
+
+    /// Classify the token and count the outcome, then decide what to do with it.
+    pub fn validate(
+        &self,
+        token: &[u8],
+        peer: SocketAddr,
+        dcid: &ConnectionId,
+    ) -> (TokenOutcome, TokenAction) {
+        let outcome = self.classify(token, peer, dcid);
+        self.stats.record(outcome);
+        (outcome, self.policy.action(&outcome))
+    }
 }
 
 #[derive(Clone)]
@@ -81,9 +492,7 @@ impl ArcTokenRegistry {
     }
 
     pub fn default_provider() -> Self {
-        Self(Arc::new(Mutex::new(TokenRegistry::Server(Arc::new(
-            DefaultTokenRegistry,
-        )))))
+        Self::with_provider(Arc::new(DefaultTokenRegistry))
     }
 
     pub fn with_sink(server_name: String, client: Arc<dyn TokenSink>) -> Self {
@@ -94,16 +503,33 @@ impl ArcTokenRegistry {
     }
 
     pub fn with_provider(provider: Arc<dyn TokenProvider>) -> Self {
-        Self(Arc::new(Mutex::new(TokenRegistry::Server(provider))))
+        Self::with_validator(TokenValidator::new(provider, TokenPolicy::default()))
+    }
+
+    pub fn with_validator(validator: TokenValidator) -> Self {
+        Self(Arc::new(Mutex::new(TokenRegistry::Server(validator))))
     }
 
     pub fn lock_guard(&self) -> MutexGuard<TokenRegistry> {
         self.0.lock().unwrap()
     }
+
+    /// The NEW_TOKEN frames the server sends after the handshake is confirmed, see
+    /// [`TokenValidator::new_tokens`]. The client issues none.
+    pub fn new_token_frames(&self, server_name: &str, peer: SocketAddr) -> Vec<NewTokenFrame> {
+        match &*self.0.lock().unwrap() {
+            TokenRegistry::Client(_) => Vec::new(),
+            TokenRegistry::Server(validator) => validator
+                .new_tokens(server_name, peer)
+                .into_iter()
+                .map(|token| NewTokenFrame { token })
+                .collect(),
+        }
+    }
 }
 pub enum TokenRegistry {
     Client((String, Arc<dyn TokenSink>)),
-    Server(Arc<dyn TokenProvider>),
+    Server(TokenValidator),
 }
 
 impl ReceiveFrame<NewTokenFrame> for ArcTokenRegistry {
@@ -125,7 +551,8 @@ impl ReceiveFrame<NewTokenFrame> for ArcTokenRegistry {
     }
 }
 
-struct DefaultTokenRegistry;
+/// Issue no token, and accept no token.
+pub struct DefaultTokenRegistry;
 
 impl TokenSink for DefaultTokenRegistry {
     fn sink(&self, _: &str, _: Vec<u8>) {}
@@ -136,26 +563,315 @@ impl TokenSink for DefaultTokenRegistry {
 }
 
 impl TokenProvider for DefaultTokenRegistry {
-    fn provide_new_token(&self, _: &str) -> Vec<u8> {
+    fn provide_new_token(&self, _: &str, _: SocketAddr) -> Vec<u8> {
         Vec::new()
     }
 
-    fn provide_retry_token(&self, _: &str) -> Vec<u8> {
+    fn provide_retry_token(&self, _: SocketAddr, _: &ConnectionId) -> Vec<u8> {
         Vec::new()
     }
 
-    fn validate_token(&self, _: String, _: &[u8]) -> bool {
-        false
+    // 从未颁发过令牌，任何令牌都不是自己的
+    fn validate_token(
+        &self,
+        _: TokenKind,
+        _: &[u8],
+        _: SocketAddr,
+        _: &ConnectionId,
+    ) -> Result<(), TokenError> {
+        Err(TokenError::Undecryptable)
+    }
+}
+
+/// Keep the tokens received in NEW_TOKEN frames in memory, by the server name, for the later
+/// connections to the same server to skip the address validation.
+///
+/// Each token is handed out once, the latest first, so that the connections can't be linked
+/// by the same token, see [RFC 9000 section 8.1.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.3).
+/// At most [`TokenStore::capacity`] tokens are kept for a server, the older ones are dropped.
+#[derive(Debug)]
+pub struct TokenStore {
+    capacity: usize,
+    tokens: Mutex<HashMap<String, VecDeque<Vec<u8>>>>,
+}
+
+impl TokenStore {
+    pub const DEFAULT_CAPACITY: usize = 4;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tokens: Mutex::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of the tokens kept for the server.
+    pub fn count(&self, server_name: &str) -> usize {
+        let tokens = self.tokens.lock().unwrap();
+        tokens.get(server_name).map_or(0, VecDeque::len)
+    }
+}
+
+impl Default for TokenStore {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl TokenSink for TokenStore {
+    fn sink(&self, server_name: &str, token: Vec<u8>) {
+        if self.capacity == 0 || token.is_empty() {
+            return;
+        }
+        let mut tokens = self.tokens.lock().unwrap();
+        let queue = tokens.entry(server_name.to_owned()).or_default();
+        if queue.len() == self.capacity {
+            queue.pop_front();
+        }
+        queue.push_back(token);
+    }
+
+    fn get_token(&self, server_name: &str) -> Vec<u8> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let Some(queue) = tokens.get_mut(server_name) else {
+            return Vec::new();
+        };
+        let token = queue.pop_back().unwrap_or_default();
+        if queue.is_empty() {
+            tokens.remove(server_name);
+        }
+        token
+    }
+}
+
+/// A [`TokenProvider`] sealing the tokens with AES-256-GCM under a static key, so that the
+/// server validates them without keeping any state.
+///
+/// A Retry token binds the address of the client and the original destination connection id,
+/// and expires quickly; a NEW_TOKEN token binds the IP address of the client but not the port,
+/// since the client connects again from another port. Like the [`StatelessResetKey`], the key
+/// must be kept secret, and shared by the servers behind a load balancer.
+#[derive(Debug)]
+pub struct AeadTokenProvider {
+    key: ring::aead::LessSafeKey,
+    retry_lifetime: Duration,
+    new_token_lifetime: Duration,
+}
+
+impl AeadTokenProvider {
+    /// The default lifetime of a Retry token, long enough for a client to respond to the Retry.
+    pub const RETRY_LIFETIME: Duration = Duration::from_secs(15);
+    /// The default lifetime of a NEW_TOKEN token.
+    pub const NEW_TOKEN_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+    const NONCE_LEN: usize = 12;
+
+    /// The key is derived from the secret with SHA-256, the same secret gives the same key.
+    pub fn new(secret: &[u8]) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, secret);
+        let key = ring::aead::UnboundKey::new(&ring::aead::AES_256_GCM, digest.as_ref())
+            .expect("SHA-256 digest is a valid AES-256 key");
+        Self {
+            key: ring::aead::LessSafeKey::new(key),
+            retry_lifetime: Self::RETRY_LIFETIME,
+            new_token_lifetime: Self::NEW_TOKEN_LIFETIME,
+        }
+    }
+
+    /// A provider with a random secret, the tokens it issued are no longer valid after a restart.
+    pub fn random() -> Self {
+        let mut secret = [0; 32];
+        rand::thread_rng().fill(&mut secret);
+        Self::new(&secret)
+    }
+
+    /// How long the tokens of the kind are valid after being issued.
+    pub fn with_lifetime(mut self, kind: TokenKind, lifetime: Duration) -> Self {
+        match kind {
+            TokenKind::Retry => self.retry_lifetime = lifetime,
+            TokenKind::NewToken => self.new_token_lifetime = lifetime,
+        }
+        self
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    // 随机的nonce + 密文，令牌的类型作为附加数据，一种令牌不能冒充另一种
+    fn seal(&self, kind: TokenKind, mut plaintext: Vec<u8>) -> Vec<u8> {
+        let mut nonce = [0; Self::NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        self.key
+            .seal_in_place_append_tag(
+                ring::aead::Nonce::assume_unique_for_key(nonce),
+                ring::aead::Aad::from([kind as u8]),
+                &mut plaintext,
+            )
+            .expect("sealing a token never fails");
+        let mut body = nonce.to_vec();
+        body.extend_from_slice(&plaintext);
+        body
+    }
+
+    fn open(&self, kind: TokenKind, body: &[u8]) -> Option<Vec<u8>> {
+        if body.len() < Self::NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = body.split_at(Self::NONCE_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(
+                ring::aead::Nonce::try_assume_unique_for_key(nonce).ok()?,
+                ring::aead::Aad::from([kind as u8]),
+                &mut plaintext,
+            )
+            .ok()?
+            .len();
+        plaintext.truncate(len);
+        Some(plaintext)
+    }
+}
+
+impl Default for AeadTokenProvider {
+    fn default() -> Self {
+        Self::random()
+    }
+}
+
+fn put_ip(buf: &mut Vec<u8>, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.put_u8(4);
+            buf.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.put_u8(6);
+            buf.put_slice(&ip.octets());
+        }
+    }
+}
+
+fn put_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    put_ip(buf, addr.ip());
+    buf.put_u16(addr.port());
+}
+
+fn be_ip(input: &[u8]) -> IResult<&[u8], IpAddr> {
+    let (remain, family) = be_u8(input)?;
+    let (remain, ip) = match family {
+        4 => {
+            let (remain, octets) = take(4usize)(remain)?;
+            let octets: [u8; 4] = octets.try_into().unwrap();
+            (remain, IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        6 => {
+            let (remain, octets) = take(16usize)(remain)?;
+            let octets: [u8; 16] = octets.try_into().unwrap();
+            (remain, IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => {
+            return Err(nom::Err::Error(nom::error::make_error(
+                input,
+                nom::error::ErrorKind::Alt,
+            )))
+        }
+    };
+    Ok((remain, ip))
+}
+
+fn be_addr(input: &[u8]) -> IResult<&[u8], SocketAddr> {
+    let (remain, ip) = be_ip(input)?;
+    let (remain, port) = be_u16(remain)?;
+    Ok((remain, SocketAddr::new(ip, port)))
+}
+
+impl TokenProvider for AeadTokenProvider {
+    fn provide_new_token(&self, _: &str, peer: SocketAddr) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(8 + 17);
+        plaintext.put_u64(Self::now());
+        put_ip(&mut plaintext, peer.ip());
+        self.seal(TokenKind::NewToken, plaintext)
+    }
+
+    fn provide_retry_token(&self, peer: SocketAddr, odcid: &ConnectionId) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(8 + 19 + 1 + odcid.len());
+        plaintext.put_u64(Self::now());
+        put_addr(&mut plaintext, peer);
+        plaintext.put_connection_id(odcid);
+        self.seal(TokenKind::Retry, plaintext)
+    }
+
+    fn validate_token(
+        &self,
+        kind: TokenKind,
+        body: &[u8],
+        peer: SocketAddr,
+        dcid: &ConnectionId,
+    ) -> Result<(), TokenError> {
+        let plaintext = self.open(kind, body).ok_or(TokenError::Undecryptable)?;
+        let (remain, issued) =
+            be_u64::<_, ()>(plaintext.as_slice()).map_err(|_| TokenError::Undecryptable)?;
+        let lifetime = match kind {
+            TokenKind::Retry => {
+                let (remain, addr) = be_addr(remain).map_err(|_| TokenError::Undecryptable)?;
+                let (_, odcid) = be_connection_id(remain).map_err(|_| TokenError::Undecryptable)?;
+                if addr != peer {
+                    return Err(TokenError::WrongAddress);
+                }
+                if odcid != *dcid {
+                    return Err(TokenError::WrongOdcid);
+                }
+                self.retry_lifetime
+            }
+            TokenKind::NewToken => {
+                // 客户端再次连接时通常换了端口，只核对IP地址
+                let (_, ip) = be_ip(remain).map_err(|_| TokenError::Undecryptable)?;
+                if ip != peer.ip() {
+                    return Err(TokenError::WrongAddress);
+                }
+                self.new_token_lifetime
+            }
+        };
+        if Self::now().saturating_sub(issued) > lifetime.as_millis() as u64 {
+            return Err(TokenError::Expired);
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use rand::Rng;
+
+    use super::*;
+
     #[test]
     fn test_create_token() {
         super::ResetToken::new(&[0; 16]);
     }
 
+    #[test]
+    fn test_reset_token_eq() {
+        let token = ResetToken::random_gen();
+        assert_eq!(token, ResetToken::new(&token.0));
+        for i in [0, RESET_TOKEN_SIZE - 1] {
+            let mut bytes = token.0;
+            bytes[i] ^= 1;
+            assert_ne!(token, ResetToken::new(&bytes));
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_creat_token_with_less_size() {
@@ -192,4 +908,505 @@ mod tests {
         buf.put_reset_token(&token);
         assert_eq!(buf, &[0; 16]);
     }
+
+    #[test]
+    fn test_reset_token_derivation() {
+        let key = StatelessResetKey::new(b"secret");
+        let cid = ConnectionId::from_slice(&[1, 2, 3, 4]);
+        assert_eq!(key.reset_token(&cid), key.reset_token(&cid));
+        let other_cid = ConnectionId::from_slice(&[1, 2, 3, 5]);
+        assert_ne!(key.reset_token(&cid), key.reset_token(&other_cid));
+        // 重启之后，同样的密钥派生出同样的令牌
+        assert_eq!(
+            key.reset_token(&cid),
+            StatelessResetKey::new(b"secret").reset_token(&cid)
+        );
+        assert_ne!(
+            key.reset_token(&cid),
+            StatelessResetKey::new(b"another").reset_token(&cid)
+        );
+    }
+
+    #[test]
+    fn test_stateless_reset_size() {
+        let token = ResetToken::random_gen();
+        assert!(stateless_reset(&token, MIN_STATELESS_RESET_SIZE).is_none());
+        let reset = stateless_reset(&token, MIN_STATELESS_RESET_SIZE + 1).unwrap();
+        assert_eq!(reset.len(), MIN_STATELESS_RESET_SIZE);
+        for _ in 0..100 {
+            let reset = stateless_reset(&token, 1200).unwrap();
+            assert!((MIN_STATELESS_RESET_SIZE..1200).contains(&reset.len()));
+            assert_eq!(reset[0] & 0xc0, 0x40);
+            assert_eq!(trailing_reset_token(&reset), Some(token));
+        }
+        assert_eq!(
+            trailing_reset_token(&[0; MIN_STATELESS_RESET_SIZE - 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stateless_reset_loop() {
+        // 两端都丢了状态，互相用无状态重置回应对方的无状态重置，也会很快停下来
+        let token = ResetToken::random_gen();
+        let mut received = 1500;
+        let mut rounds = 0;
+        while let Some(reset) = stateless_reset(&token, received) {
+            assert!(reset.len() < received);
+            received = reset.len();
+            rounds += 1;
+        }
+        assert!(rounds <= 1500 - MIN_STATELESS_RESET_SIZE);
+        assert_eq!(received, MIN_STATELESS_RESET_SIZE);
+    }
+
+    const KEY: u8 = 0x5a;
+
+    // 测试用的令牌：密钥(1) + 端口(2) + ODCID长度(1) + ODCID + 是否过期(1)
+    struct TestProvider;
+
+    impl TestProvider {
+        fn body(peer: SocketAddr, dcid: &ConnectionId, expired: bool) -> Vec<u8> {
+            let mut body = vec![KEY];
+            body.put_u16(peer.port());
+            body.put_u8(dcid.len() as u8);
+            body.put_slice(dcid);
+            body.put_u8(expired as u8);
+            body
+        }
+    }
+
+    impl TokenProvider for TestProvider {
+        // NEW_TOKEN令牌不绑定连接ID
+        fn provide_new_token(&self, _: &str, peer: SocketAddr) -> Vec<u8> {
+            Self::body(peer, &ConnectionId::default(), false)
+        }
+
+        fn provide_retry_token(&self, peer: SocketAddr, dcid: &ConnectionId) -> Vec<u8> {
+            Self::body(peer, dcid, false)
+        }
+
+        fn validate_token(
+            &self,
+            kind: TokenKind,
+            body: &[u8],
+            peer: SocketAddr,
+            dcid: &ConnectionId,
+        ) -> Result<(), TokenError> {
+            let [KEY, port @ .., expired] = body else {
+                return Err(TokenError::Undecryptable);
+            };
+            let Some((port, cid)) = port.split_first_chunk::<2>() else {
+                return Err(TokenError::Undecryptable);
+            };
+            let Some((len, cid)) = cid.split_first() else {
+                return Err(TokenError::Undecryptable);
+            };
+            if cid.len() != *len as usize {
+                return Err(TokenError::Undecryptable);
+            }
+            if u16::from_be_bytes(*port) != peer.port() {
+                return Err(TokenError::WrongAddress);
+            }
+            // NEW_TOKEN令牌与ODCID无关
+            if kind == TokenKind::Retry && cid != &dcid[..] {
+                return Err(TokenError::WrongOdcid);
+            }
+            if *expired != 0 {
+                return Err(TokenError::Expired);
+            }
+            Ok(())
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        "127.0.0.1:4433".parse().unwrap()
+    }
+
+    #[test]
+    fn test_token_tag() {
+        let token = encode_token(TokenKind::NewToken, b"body");
+        assert_eq!(token, b"\x02body");
+        assert_eq!(
+            be_token(&token).unwrap().1,
+            (TokenKind::NewToken, &b"body"[..])
+        );
+        assert!(be_token(&[]).is_err());
+        assert!(be_token(&[0x01]).is_err());
+        assert!(be_token(&[0x03, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_classify_token() {
+        let validator = TokenValidator::new(Arc::new(TestProvider), TokenPolicy::default());
+        let dcid = ConnectionId::random_gen(8);
+        let classify = |token: &[u8]| validator.classify(token, peer(), &dcid);
+
+        assert_eq!(classify(&[]), TokenOutcome::Empty);
+        let retry = encode_retry_token(&dcid, &TestProvider::body(peer(), &dcid, false));
+        assert_eq!(classify(&retry), TokenOutcome::Valid(TokenKind::Retry));
+        let other_dcid = ConnectionId::random_gen(8);
+        let new_token = encode_token(
+            TokenKind::NewToken,
+            &TestProvider::body(peer(), &other_dcid, false),
+        );
+        assert_eq!(
+            classify(&new_token),
+            TokenOutcome::Valid(TokenKind::NewToken)
+        );
+
+        let invalid = |kind, error| TokenOutcome::Invalid(kind, error);
+        assert_eq!(
+            classify(&[0xff, 1, 2]),
+            invalid(None, TokenError::Malformed)
+        );
+        assert_eq!(classify(&[0x02]), invalid(None, TokenError::Malformed));
+        let expired = encode_token(
+            TokenKind::NewToken,
+            &TestProvider::body(peer(), &dcid, true),
+        );
+        assert_eq!(
+            classify(&expired),
+            invalid(Some(TokenKind::NewToken), TokenError::Expired)
+        );
+        let other_peer = "127.0.0.1:4434".parse().unwrap();
+        let wrong_address =
+            encode_retry_token(&dcid, &TestProvider::body(other_peer, &dcid, false));
+        assert_eq!(
+            classify(&wrong_address),
+            invalid(Some(TokenKind::Retry), TokenError::WrongAddress)
+        );
+        // 携带的原始目的连接ID与颁发时绑定的不同
+        let wrong_odcid =
+            encode_retry_token(&dcid, &TestProvider::body(peer(), &other_dcid, false));
+        assert_eq!(
+            classify(&wrong_odcid),
+            invalid(Some(TokenKind::Retry), TokenError::WrongOdcid)
+        );
+        assert_eq!(
+            classify(&[0x01, 0x00, 0x01]),
+            invalid(Some(TokenKind::Retry), TokenError::Undecryptable)
+        );
+    }
+
+    #[test]
+    fn test_token_policy() {
+        use TokenAction::*;
+        use TokenError::*;
+
+        let default = TokenPolicy::default();
+        assert_eq!(default.action(&TokenOutcome::Empty), Proceed);
+        assert_eq!(
+            default.action(&TokenOutcome::Valid(TokenKind::Retry)),
+            Accept
+        );
+        assert_eq!(
+            default.action(&TokenOutcome::Valid(TokenKind::NewToken)),
+            Accept
+        );
+        for error in [Expired, WrongAddress, Undecryptable, WrongOdcid] {
+            let retry = TokenOutcome::Invalid(Some(TokenKind::Retry), error);
+            assert_eq!(default.action(&retry), Close);
+            let new_token = TokenOutcome::Invalid(Some(TokenKind::NewToken), error);
+            assert_eq!(default.action(&new_token), Proceed);
+        }
+        assert_eq!(
+            default.action(&TokenOutcome::Invalid(None, Malformed)),
+            Proceed
+        );
+
+        let policy = TokenPolicy::default()
+            .on_empty(TokenFallback::Retry)
+            .on_invalid(Some(TokenKind::NewToken), Expired, TokenFallback::Retry)
+            .on_invalid(
+                Some(TokenKind::NewToken),
+                Undecryptable,
+                TokenFallback::Close,
+            )
+            .on_invalid(None, Malformed, TokenFallback::Discard);
+        assert_eq!(policy.action(&TokenOutcome::Empty), Retry);
+        assert_eq!(
+            policy.action(&TokenOutcome::Invalid(Some(TokenKind::NewToken), Expired)),
+            Retry
+        );
+        assert_eq!(
+            policy.action(&TokenOutcome::Invalid(
+                Some(TokenKind::NewToken),
+                Undecryptable
+            )),
+            Close
+        );
+        assert_eq!(
+            policy.action(&TokenOutcome::Invalid(None, Malformed)),
+            Discard
+        );
+        // 未配置的失败类别，NEW_TOKEN按照空令牌处理
+        assert_eq!(
+            policy.action(&TokenOutcome::Invalid(
+                Some(TokenKind::NewToken),
+                WrongAddress
+            )),
+            Retry
+        );
+        assert_eq!(
+            policy.action(&TokenOutcome::Invalid(Some(TokenKind::Retry), Expired)),
+            Close
+        );
+    }
+
+    #[test]
+    fn test_stale_token_falls_back_to_retry() {
+        let policy = TokenPolicy::default().on_invalid(
+            Some(TokenKind::NewToken),
+            TokenError::Expired,
+            TokenFallback::Retry,
+        );
+        let validator = TokenValidator::new(Arc::new(TestProvider), policy);
+        let dcid = ConnectionId::random_gen(8);
+
+        // 刚颁发的NEW_TOKEN令牌是有效的
+        let fresh = validator.new_tokens("quic.test.net", peer());
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(
+            validator.classify(&fresh[0], peer(), &dcid),
+            TokenOutcome::Valid(TokenKind::NewToken)
+        );
+
+        // 客户端使用之前连接中获得的过期令牌
+        let stale = encode_token(
+            TokenKind::NewToken,
+            &TestProvider::body(peer(), &dcid, true),
+        );
+        let (outcome, action) = validator.validate(&stale, peer(), &dcid);
+        assert_eq!(
+            outcome,
+            TokenOutcome::Invalid(Some(TokenKind::NewToken), TokenError::Expired)
+        );
+        assert_eq!(action, TokenAction::Retry);
+
+        // 服务端回复Retry包，客户端用其中的令牌重新发送Initial包
+        let retry_token = validator.retry_token(peer(), &dcid);
+        assert_eq!(be_retry_token(&retry_token).unwrap().0, dcid);
+        // 重新发送的Initial包的目的连接ID是Retry包的源连接ID，与原始的目的连接ID无关
+        let retry_scid = ConnectionId::random_gen(8);
+        let (outcome, action) = validator.validate(&retry_token, peer(), &retry_scid);
+        assert_eq!(outcome, TokenOutcome::Valid(TokenKind::Retry));
+        assert_eq!(action, TokenAction::Accept);
+
+        let stats = validator.stats();
+        assert_eq!(stats.count(&TokenOutcome::Valid(TokenKind::Retry)), 1);
+        assert_eq!(
+            stats.count(&TokenOutcome::Invalid(
+                Some(TokenKind::NewToken),
+                TokenError::Expired
+            )),
+            1
+        );
+        assert_eq!(stats.count(&TokenOutcome::Empty), 0);
+    }
+
+    #[test]
+    fn test_fuzz_token() {
+        let validator = TokenValidator::new(Arc::new(TestProvider), TokenPolicy::default());
+        let dcid = ConnectionId::random_gen(8);
+        let mut rng = rand::thread_rng();
+        for _ in 0..10000 {
+            let len = rng.gen_range(0..64);
+            let mut token = vec![0u8; len];
+            rng.fill(&mut token[..]);
+            if len > 0 && rng.gen_bool(0.5) {
+                // 让一半的令牌有合法的类型标签，以深入到provider的解析
+                token[0] = rng.gen_range(1..=2);
+            }
+            if let Ok((remain, (kind, body))) = be_token(&token) {
+                assert!(remain.is_empty());
+                assert_eq!(encode_token(kind, body), token);
+            }
+            let outcome = validator.classify(&token, peer(), &dcid);
+            assert!(!matches!(outcome, TokenOutcome::Valid(_)));
+        }
+    }
+
+    #[test]
+    fn test_aead_token_provider() {
+        use TokenError::*;
+
+        let provider = AeadTokenProvider::new(b"secret");
+        let odcid = ConnectionId::random_gen(8);
+        let retry = provider.provide_retry_token(peer(), &odcid);
+        let validate =
+            |kind, body: &[u8], peer, dcid| provider.validate_token(kind, body, peer, dcid);
+
+        assert_eq!(validate(TokenKind::Retry, &retry, peer(), &odcid), Ok(()));
+        // 同样的密钥，重启之后仍然有效
+        assert_eq!(
+            AeadTokenProvider::new(b"secret").validate_token(
+                TokenKind::Retry,
+                &retry,
+                peer(),
+                &odcid
+            ),
+            Ok(())
+        );
+        let other_peer = "127.0.0.1:4434".parse().unwrap();
+        assert_eq!(
+            validate(TokenKind::Retry, &retry, other_peer, &odcid),
+            Err(WrongAddress)
+        );
+        let other_odcid = ConnectionId::random_gen(8);
+        assert_eq!(
+            validate(TokenKind::Retry, &retry, peer(), &other_odcid),
+            Err(WrongOdcid)
+        );
+        // 一种令牌不能冒充另一种，另一个密钥颁发的、被篡改的令牌都无法解密
+        assert_eq!(
+            validate(TokenKind::NewToken, &retry, peer(), &odcid),
+            Err(Undecryptable)
+        );
+        assert_eq!(
+            AeadTokenProvider::new(b"another").validate_token(
+                TokenKind::Retry,
+                &retry,
+                peer(),
+                &odcid
+            ),
+            Err(Undecryptable)
+        );
+        let mut tampered = retry.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert_eq!(
+            validate(TokenKind::Retry, &tampered, peer(), &odcid),
+            Err(Undecryptable)
+        );
+        assert_eq!(
+            validate(TokenKind::Retry, &retry[..8], peer(), &odcid),
+            Err(Undecryptable)
+        );
+
+        // NEW_TOKEN令牌只绑定了IP地址，换了端口依然有效，换了地址则不然
+        let new_token = provider.provide_new_token("localhost", peer());
+        assert_eq!(
+            validate(TokenKind::NewToken, &new_token, other_peer, &odcid),
+            Ok(())
+        );
+        assert_eq!(
+            validate(
+                TokenKind::NewToken,
+                &new_token,
+                "127.0.0.2:4433".parse().unwrap(),
+                &odcid
+            ),
+            Err(WrongAddress)
+        );
+
+        let provider =
+            AeadTokenProvider::new(b"secret").with_lifetime(TokenKind::Retry, Duration::ZERO);
+        let retry = provider.provide_retry_token(peer(), &odcid);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            provider.validate_token(TokenKind::Retry, &retry, peer(), &odcid),
+            Err(Expired)
+        );
+
+        // 经由TokenValidator，Retry令牌携带着原始的目的连接ID
+        let validator = TokenValidator::new(
+            Arc::new(AeadTokenProvider::random()),
+            TokenPolicy::default().on_empty(TokenFallback::Retry),
+        );
+        let token = validator.retry_token(peer(), &odcid);
+        let retry_scid = ConnectionId::random_gen(8);
+        assert_eq!(
+            validator.validate(&token, peer(), &retry_scid),
+            (TokenOutcome::Valid(TokenKind::Retry), TokenAction::Accept)
+        );
+        assert_eq!(be_retry_token(&token).map(|(odcid, _)| odcid), Some(odcid));
+        assert_eq!(
+            validator.validate(&[], peer(), &odcid),
+            (TokenOutcome::Empty, TokenAction::Retry)
+        );
+    }
+
+    #[test]
+    fn test_new_tokens() {
+        let validator = TokenValidator::new(
+            Arc::new(AeadTokenProvider::random()),
+            TokenPolicy::default().new_tokens(2),
+        );
+        let tokens = validator.new_tokens("quic.test.net", peer());
+        assert_eq!(tokens.len(), 2);
+        assert_ne!(tokens[0], tokens[1]);
+        // 客户端换了端口、连接ID，NEW_TOKEN令牌依然有效
+        let dcid = ConnectionId::random_gen(8);
+        let other_port = "127.0.0.1:4434".parse().unwrap();
+        // 经由双栈socket收到的IPv4映射地址，规范化之后也是同一个地址
+        let mapped = "[::ffff:127.0.0.1]:4435".parse().unwrap();
+        for token in &tokens {
+            assert_eq!(token[0], TokenKind::NewToken as u8);
+            for peer in [other_port, mapped] {
+                assert_eq!(
+                    validator.classify(token, peer, &dcid),
+                    TokenOutcome::Valid(TokenKind::NewToken)
+                );
+            }
+        }
+        // 令牌被别的地址拿去用，不能为之验证地址，按默认策略当作没有令牌
+        let other_peer = "[2001:db8::1]:4434".parse().unwrap();
+        let (outcome, action) = validator.validate(&tokens[0], other_peer, &dcid);
+        assert_eq!(
+            outcome,
+            TokenOutcome::Invalid(Some(TokenKind::NewToken), TokenError::WrongAddress)
+        );
+        assert_eq!(action, TokenAction::Proceed);
+
+        // 改作Retry令牌重放，无法通过验证
+        let (_, body) = be_token(&tokens[0]).unwrap().1;
+        let replayed = encode_retry_token(&dcid, body);
+        assert_eq!(
+            validator.classify(&replayed, peer(), &dcid),
+            TokenOutcome::Invalid(Some(TokenKind::Retry), TokenError::Undecryptable)
+        );
+
+        let disabled = TokenValidator::new(
+            Arc::new(AeadTokenProvider::random()),
+            TokenPolicy::default().new_tokens(0),
+        );
+        assert!(disabled.new_tokens("quic.test.net", peer()).is_empty());
+        // 不颁发令牌的provider，也就不发送NEW_TOKEN帧
+        let registry = ArcTokenRegistry::default_provider();
+        assert!(registry
+            .new_token_frames("quic.test.net", peer())
+            .is_empty());
+        let registry = ArcTokenRegistry::with_sink(
+            "quic.test.net".to_owned(),
+            Arc::new(TokenStore::default()),
+        );
+        assert!(registry
+            .new_token_frames("quic.test.net", peer())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_token_store() {
+        let store = TokenStore::new(2);
+        assert!(store.get_token("a.test").is_empty());
+
+        store.sink("a.test", b"a1".to_vec());
+        store.sink("a.test", b"a2".to_vec());
+        store.sink("a.test", b"a3".to_vec());
+        store.sink("b.test", b"b1".to_vec());
+        store.sink("b.test", Vec::new());
+        assert_eq!(store.count("a.test"), 2);
+        assert_eq!(store.count("b.test"), 1);
+
+        // 最新的先用，每个令牌只用一次，最旧的已被挤出
+        assert_eq!(store.get_token("a.test"), b"a3");
+        assert_eq!(store.get_token("a.test"), b"a2");
+        assert!(store.get_token("a.test").is_empty());
+        assert_eq!(store.get_token("b.test"), b"b1");
+        assert_eq!(store.count("b.test"), 0);
+
+        let disabled = TokenStore::new(0);
+        disabled.sink("a.test", b"a1".to_vec());
+        assert!(disabled.get_token("a.test").is_empty());
+    }
 }
@@ -1,11 +1,28 @@
+mod addr;
+pub use addr::canonical_addr;
+
 mod async_deque;
 pub use async_deque::{ArcAsyncDeque, ArcAsyncDequeWriter};
 
 mod async_cell;
 pub use async_cell::{AsyncCell, Get, RawAsyncCell};
 
+#[cfg(any(test, feature = "test-util"))]
+mod captured;
+#[cfg(any(test, feature = "test-util"))]
+pub use captured::Captured;
+
 mod data;
-pub use data::{DescribeData, WriteData};
+pub use data::{Chunks, DescribeData, WriteData};
 
 mod index_deque;
 pub use index_deque::{Error as IndexError, IndexDeque};
+
+mod sanitize;
+pub use sanitize::{sanitize, sanitize_cow, MAX_RAW_LEN, MAX_REASON_LEN, TRUNCATED_MARK};
+
+mod trace;
+pub use trace::{spawn_traced, try_spawn_traced, ArcTraceContext, TraceGuard, Traced};
+
+mod wakers;
+pub use wakers::Wakers;
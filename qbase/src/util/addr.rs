@@ -0,0 +1,38 @@
+use std::net::SocketAddr;
+
+/// Convert the IPv4-mapped IPv6 address into the IPv4 address, the other addresses are
+/// returned as they are.
+///
+/// A dual-stack socket receives the IPv4 clients as `::ffff:a.b.c.d`, the same client would
+/// appear in two forms across the IPv4 and the dual-stack sockets, or after a migration.
+/// The address with a flow info or a scope id is not an IPv4 client, it is kept.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) if v6.flowinfo() == 0 && v6.scope_id() == 0 => {
+            match v6.ip().to_ipv4_mapped() {
+                Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+                None => addr,
+            }
+        }
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_addr() {
+        let v4: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:1234".parse().unwrap();
+        let v6: SocketAddr = "[::1]:1234".parse().unwrap();
+        // ::127.0.0.1 是废弃的IPv4兼容地址，并非映射地址
+        let compatible: SocketAddr = "[::127.0.0.1]:1234".parse().unwrap();
+
+        assert_eq!(canonical_addr(mapped), v4);
+        assert_eq!(canonical_addr(v4), v4);
+        assert_eq!(canonical_addr(v6), v6);
+        assert_eq!(canonical_addr(compatible), compatible);
+    }
+}
@@ -1,3 +1,5 @@
+use std::{collections::VecDeque, fmt};
+
 use bytes::{BufMut, Bytes};
 
 pub trait DescribeData {
@@ -54,6 +56,96 @@ impl DescribeData for Bytes {
     }
 }
 
+/// A range of the bytes held in a sequence of [`Bytes`] chunks, such as a send buffer keeping
+/// the [`Bytes`] written by the application as they are. It's written out chunk by chunk,
+/// without copying the chunks together first.
+#[derive(Clone, Copy)]
+pub struct Chunks<'a> {
+    chunks: &'a VecDeque<Bytes>,
+    // 区间从第first块的第skip个字节开始
+    first: usize,
+    skip: usize,
+    len: usize,
+}
+
+static NO_CHUNKS: VecDeque<Bytes> = VecDeque::new();
+
+impl Default for Chunks<'_> {
+    fn default() -> Self {
+        Self {
+            chunks: &NO_CHUNKS,
+            first: 0,
+            skip: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<'a> Chunks<'a> {
+    /// The `len` bytes from the `start`th byte of the chunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of the chunks.
+    pub fn new(chunks: &'a VecDeque<Bytes>, start: usize, len: usize) -> Self {
+        let (mut first, mut skip) = (0, start);
+        while let Some(chunk) = chunks.get(first) {
+            if skip < chunk.len() {
+                break;
+            }
+            skip -= chunk.len();
+            first += 1;
+        }
+        let available = chunks.range(first..).map(Bytes::len).sum::<usize>();
+        assert!(
+            available
+                .checked_sub(skip)
+                .is_some_and(|available| len <= available),
+            "range out of the chunks"
+        );
+        Self {
+            chunks,
+            first,
+            skip,
+            len,
+        }
+    }
+
+    /// The slices of the range in order, none of them is empty.
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> {
+        let (mut skip, mut left) = (self.skip, self.len);
+        self.chunks.range(self.first..).map_while(move |chunk| {
+            if left == 0 {
+                return None;
+            }
+            let slice = &chunk[skip..];
+            let slice = &slice[..slice.len().min(left)];
+            skip = 0;
+            left -= slice.len();
+            Some(slice)
+        })
+    }
+}
+
+// 只输出长度，不输出数据，以免应用数据泄露到日志中
+impl fmt::Debug for Chunks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunks").field("len", &self.len).finish()
+    }
+}
+
+impl DescribeData for Chunks<'_> {
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
 pub trait WriteData<D: DescribeData> {
     fn put_data(&mut self, data: &D);
 }
@@ -86,3 +178,46 @@ impl<T: BufMut> WriteData<Bytes> for T {
         self.put_slice(data);
     }
 }
+
+impl<T: BufMut> WriteData<Chunks<'_>> for T {
+    #[inline]
+    fn put_data(&mut self, data: &Chunks<'_>) {
+        for slice in data.iter() {
+            self.put_slice(slice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let chunks = VecDeque::from([
+            Bytes::from_static(b"hello"),
+            Bytes::from_static(b" "),
+            Bytes::from_static(b"world"),
+        ]);
+        let collect = |data: Chunks| {
+            let mut buf = Vec::new();
+            buf.put_data(&data);
+            assert_eq!(buf.len(), data.len());
+            buf
+        };
+        assert_eq!(collect(Chunks::new(&chunks, 0, 11)), b"hello world");
+        assert_eq!(collect(Chunks::new(&chunks, 3, 5)), b"lo wo");
+        assert_eq!(collect(Chunks::new(&chunks, 5, 1)), b" ");
+        assert_eq!(collect(Chunks::new(&chunks, 6, 5)), b"world");
+        assert_eq!(Chunks::new(&chunks, 11, 0).iter().count(), 0);
+        assert_eq!(Chunks::new(&chunks, 4, 3).iter().count(), 3);
+        assert!(Chunks::default().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_chunks_out_of_range() {
+        let chunks = VecDeque::from([Bytes::from_static(b"hello")]);
+        Chunks::new(&chunks, 3, 3);
+    }
+}
@@ -0,0 +1,96 @@
+use std::borrow::Cow;
+
+/// The most bytes of a peer-controlled string kept in the errors and the logs, such as the
+/// reason phrase of a CONNECTION_CLOSE frame.
+pub const MAX_REASON_LEN: usize = 256;
+
+/// The most raw bytes of a peer-controlled string kept for the applications that need them,
+/// see [`Error::raw_reason`](crate::error::Error::raw_reason).
+pub const MAX_RAW_LEN: usize = 1024;
+
+/// Appended to a sanitized string that was truncated.
+pub const TRUNCATED_MARK: &str = "...";
+
+/// Make the bytes controlled by the peer safe to log and display.
+///
+/// The invalid UTF-8 and the control characters, including the ESC of the terminal escape
+/// sequences, are replaced with U+FFFD, and the text is cut to at most `max_len` bytes
+/// followed by the [`TRUNCATED_MARK`]. Only the first `max_len` bytes are ever looked at.
+pub fn sanitize(bytes: &[u8], max_len: usize) -> Cow<'_, str> {
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(max_len)]);
+    if bytes.len() <= max_len && matches!(text, Cow::Borrowed(_)) && is_clean(&text) {
+        return text;
+    }
+
+    // 替换之后可能变长，仍要按字节数截断，且不能截断在字符中间
+    let mut truncated = bytes.len() > max_len;
+    let mut clean = String::with_capacity(max_len + TRUNCATED_MARK.len());
+    for c in text.chars() {
+        let c = if c.is_control() {
+            char::REPLACEMENT_CHARACTER
+        } else {
+            c
+        };
+        if clean.len() + c.len_utf8() > max_len {
+            truncated = true;
+            break;
+        }
+        clean.push(c);
+    }
+    if truncated {
+        clean.push_str(TRUNCATED_MARK);
+    }
+    Cow::Owned(clean)
+}
+
+/// Same as [`sanitize`], but keeps the clean string as it is, without copying.
+pub fn sanitize_cow(text: Cow<'static, str>, max_len: usize) -> Cow<'static, str> {
+    if text.len() <= max_len && is_clean(&text) {
+        text
+    } else {
+        Cow::Owned(sanitize(text.as_bytes(), max_len).into_owned())
+    }
+}
+
+fn is_clean(text: &str) -> bool {
+    !text.chars().any(char::is_control)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert!(matches!(sanitize(b"bye", 16), Cow::Borrowed("bye")));
+
+        // 终端转义序列与换行都被替换，不会改写日志
+        let sanitized = sanitize(b"\x1b[2J\x1b[31mowned\r\nfake log line", 64);
+        assert_eq!(
+            sanitized,
+            "\u{fffd}[2J\u{fffd}[31mowned\u{fffd}\u{fffd}fake log line"
+        );
+
+        let sanitized = sanitize(b"bad \xff\xfe utf-8", 64);
+        assert_eq!(sanitized, "bad \u{fffd}\u{fffd} utf-8");
+
+        let huge = vec![b'a'; 10 * 1024 * 1024];
+        let sanitized = sanitize(&huge, MAX_REASON_LEN);
+        assert_eq!(sanitized.len(), MAX_REASON_LEN + TRUNCATED_MARK.len());
+        assert!(sanitized.ends_with(TRUNCATED_MARK));
+
+        // 替换字符占3字节，截断在字符边界上
+        let sanitized = sanitize(b"ab\x01\x02", 4);
+        assert_eq!(sanitized, format!("ab{TRUNCATED_MARK}"));
+        let sanitized = sanitize("中文".as_bytes(), 4);
+        assert_eq!(sanitized, format!("中{TRUNCATED_MARK}"));
+    }
+
+    #[test]
+    fn test_sanitize_cow() {
+        let clean = sanitize_cow(Cow::Borrowed("clean"), 16);
+        assert!(matches!(clean, Cow::Borrowed("clean")));
+        let dirty = sanitize_cow(Cow::Owned("\x1b]0;title\x07".to_owned()), 16);
+        assert_eq!(dirty, "\u{fffd}]0;title\u{fffd}");
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tracing::{span::EnteredSpan, Span};
+
+thread_local! {
+    static CURRENT: RefCell<Option<ArcTraceContext>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug)]
+struct RawTraceContext {
+    span: Mutex<Span>,
+    parent: Option<ArcTraceContext>,
+}
+
+impl Default for RawTraceContext {
+    fn default() -> Self {
+        Self {
+            span: Mutex::new(Span::none()),
+            parent: None,
+        }
+    }
+}
+
+/// 追踪上下文，连接级别或者流级别，承载应用设置的[`tracing::Span`]。
+///
+/// 连接内部派生的异步任务，在创建时捕获当前的追踪上下文，之后每次被poll时都会进入
+/// 上下文中的span。span是动态读取的，因此即便任务在应用设置span之前就已经被派生，
+/// 之后产生的事件也能带上应用的span。
+///
+/// 子上下文（如流级别的）会先进入父上下文的span，再进入自己的span。若要在订阅者中
+/// 呈现嵌套关系，子上下文的span应在父上下文中创建，比如在[`ArcTraceContext::enter`]之后。
+#[derive(Debug, Default, Clone)]
+pub struct ArcTraceContext(Arc<RawTraceContext>);
+
+impl ArcTraceContext {
+    /// Create a child context whose span nests inside this context's span.
+    pub fn child(&self) -> Self {
+        Self(Arc::new(RawTraceContext {
+            span: Mutex::new(Span::none()),
+            parent: Some(self.clone()),
+        }))
+    }
+
+    /// Replace the span of this context, it takes effect on the next poll of
+    /// every task spawned within this context.
+    pub fn set_span(&self, span: Span) {
+        *self.0.span.lock().unwrap() = span;
+    }
+
+    pub fn span(&self) -> Span {
+        self.0.span.lock().unwrap().clone()
+    }
+
+    /// The trace context of the task which is being polled on this thread, or
+    /// the one entered by [`ArcTraceContext::enter`].
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Make this context the current one, and enter the spans from the root to
+    /// this context, until the returned guard is dropped.
+    pub fn enter(&self) -> TraceGuard {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let mut spans = Vec::new();
+        let mut ctx = Some(self);
+        while let Some(c) = ctx {
+            spans.push(c.span());
+            ctx = c.0.parent.as_ref();
+        }
+        let entered = spans
+            .into_iter()
+            .rev()
+            .filter(|span| !span.is_none())
+            .map(Span::entered)
+            .collect();
+        TraceGuard { entered, previous }
+    }
+
+    pub fn instrument<F: Future>(&self, future: F) -> Traced<F> {
+        Traced {
+            future,
+            ctx: self.clone(),
+        }
+    }
+}
+
+/// Returned by [`ArcTraceContext::enter`], restores the previous context when dropped.
+pub struct TraceGuard {
+    entered: Vec<EnteredSpan>,
+    previous: Option<ArcTraceContext>,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        // 由内而外地退出span
+        while let Some(entered) = self.entered.pop() {
+            drop(entered);
+        }
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// A future that enters its trace context on every poll, see [`ArcTraceContext::instrument`].
+    pub struct Traced<F> {
+        #[pin]
+        future: F,
+        ctx: ArcTraceContext,
+    }
+}
+
+impl<F: Future> Future for Traced<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = this.ctx.enter();
+        this.future.poll(cx)
+    }
+}
+
+/// Spawn a task which inherits the current trace context, if there is one.
+///
+/// Tasks spawned inside a connection should use this instead of [`tokio::spawn`],
+/// so that all the work of the connection is traced within the spans set by
+/// the application.
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime, like [`tokio::spawn`].
+pub fn spawn_traced<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match ArcTraceContext::current() {
+        Some(ctx) => tokio::spawn(ctx.instrument(future)),
+        None => tokio::spawn(future),
+    }
+}
+
+/// Like [`spawn_traced`], but returns [`None`] instead of panicking if there is no
+/// runtime available.
+///
+/// For the tasks spawned while tearing a connection down, or while handling the frames
+/// and packets received, like the tasks of the streams and the paths they create. Both
+/// may run after the runtime has shut down, when the connection is dying anyway and
+/// skipping its remaining tasks is harmless. Elsewhere, a missing runtime is a bug.
+pub fn try_spawn_traced<F>(future: F) -> Option<tokio::task::JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::debug!("no runtime available, the task is not spawned");
+        return None;
+    };
+    Some(match ArcTraceContext::current() {
+        Some(ctx) => runtime.spawn(ctx.instrument(future)),
+        None => runtime.spawn(future),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Captured;
+
+    #[tokio::test]
+    async fn test_span_set_after_spawn() {
+        let (captured, _guard) = Captured::capture(tracing::Level::TRACE);
+        let ctx = ArcTraceContext::default();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = {
+            let _enter = ctx.enter();
+            spawn_traced(async move {
+                _ = rx.await;
+                tracing::info!("driver woken");
+            })
+        };
+
+        ctx.set_span(tracing::info_span!("conn", id = 42));
+        tx.send(()).unwrap();
+        task.await.unwrap();
+
+        let lines = captured.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("conn{id=42}"));
+        assert!(lines[0].contains("driver woken"));
+    }
+
+    #[tokio::test]
+    async fn test_nested_spawn_and_child() {
+        let (captured, _guard) = Captured::capture(tracing::Level::TRACE);
+        let ctx = ArcTraceContext::default();
+        ctx.set_span(tracing::info_span!("conn", id = 1));
+        let stream = ctx.child();
+        // 在连接上下文中创建流的span，使其以连接的span为父
+        stream.set_span({
+            let _enter = ctx.enter();
+            tracing::info_span!("stream", sid = 4)
+        });
+
+        ctx.instrument(async move {
+            spawn_traced(async {
+                tracing::info!("nested");
+            })
+            .await
+            .unwrap();
+            stream
+                .instrument(async {
+                    tracing::info!("stream work");
+                })
+                .await;
+        })
+        .await;
+        assert!(ArcTraceContext::current().is_none());
+
+        let lines = captured.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("conn{id=1}") && lines[0].contains("nested"));
+        assert!(lines[1].contains("conn{id=1}:stream{sid=4}"));
+    }
+
+    #[test]
+    fn test_try_spawn_without_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime
+            .block_on(async { try_spawn_traced(async {}) })
+            .is_some());
+        drop(runtime);
+        // 运行时已关闭，不再派生任务，也不会panic
+        assert!(try_spawn_traced(async {}).is_none());
+    }
+}
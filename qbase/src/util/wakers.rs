@@ -0,0 +1,68 @@
+use std::task::Waker;
+
+/// The wakers of the tasks waiting for the same condition.
+///
+/// A condition shared by several tasks, like the send window of a shared writer or the
+/// flush of a connection, must wake them all once it changes. Keeping only the waker
+/// registered last leaves the others waiting forever.
+#[derive(Debug, Default)]
+pub struct Wakers(Vec<Waker>);
+
+impl Wakers {
+    /// Register the waker, unless it would wake the same task as a registered one.
+    pub fn register(&mut self, waker: &Waker) {
+        if !self.0.iter().any(|w| w.will_wake(waker)) {
+            self.0.push(waker.clone());
+        }
+    }
+
+    pub fn wake_all(&mut self) {
+        for waker in self.0.drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::Wake,
+    };
+
+    use super::*;
+
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_wake_all() {
+        let first = Arc::new(Counter(AtomicUsize::new(0)));
+        let second = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut wakers = Wakers::default();
+        wakers.register(&Waker::from(first.clone()));
+        wakers.register(&Waker::from(second.clone()));
+        // 同一个任务再次登记，不会重复
+        wakers.register(&Waker::from(first.clone()));
+        wakers.wake_all();
+        assert_eq!(first.0.load(Ordering::Relaxed), 1);
+        assert_eq!(second.0.load(Ordering::Relaxed), 1);
+        assert!(wakers.is_empty());
+    }
+}
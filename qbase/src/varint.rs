@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, convert::TryFrom, fmt};
+use std::{cmp::Ordering, convert::TryFrom, fmt, io, num::TryFromIntError};
 
 /// An integer less than 2^62
 ///
@@ -24,16 +24,30 @@ impl VarInt {
     pub const MAX_SIZE: usize = 8;
 
     /// Construct a `VarInt` infallibly
-    pub fn from_u32(x: u32) -> Self {
+    pub const fn from_u32(x: u32) -> Self {
         Self(x as u64)
     }
 
+    /// Construct a `VarInt` from a literal, panics if `x` >= 2^62, which fails the
+    /// compilation in the const context.
+    ///
+    /// ```
+    /// use qbase::varint::VarInt;
+    ///
+    /// const H3_FRAME_SETTINGS: VarInt = VarInt::from_const(0x04);
+    /// assert_eq!(H3_FRAME_SETTINGS.into_inner(), 4);
+    /// ```
+    pub const fn from_const(x: u64) -> Self {
+        assert!(x <= VARINT_MAX, "value too large for varint encoding");
+        Self(x)
+    }
+
     /// Succeeds if `x` < 2^62
     pub fn from_u64(x: u64) -> Result<Self, err::Overflow> {
         if x < (1 << 62) {
             Ok(Self(x))
         } else {
-            Err(err::Overflow(x))
+            Err(err::Overflow(x as i128))
         }
     }
 
@@ -42,16 +56,17 @@ impl VarInt {
     /// # Safety
     ///
     /// `x` must be less than 2^62.
-    pub unsafe fn from_u64_unchecked(x: u64) -> Self {
+    pub const unsafe fn from_u64_unchecked(x: u64) -> Self {
         Self(x)
     }
 
     /// Extract the integer value
-    pub fn into_inner(self) -> u64 {
+    pub const fn into_inner(self) -> u64 {
         self.0
     }
 
-    /// Compute the number of bytes needed to encode this value
+    /// Compute the number of bytes needed to encode this value, which is the number
+    /// of bytes written by [`WriteVarInt::put_varint`].
     pub fn encoding_size(self) -> usize {
         let x = self.0;
         if x < (1 << 6) {
@@ -66,6 +81,44 @@ impl VarInt {
             unreachable!("malformed VarInt");
         }
     }
+
+    /// Decode a variable-length integer from the front of the buffer, which may hold a
+    /// partial encoding, for example the data read from a stream so far.
+    ///
+    /// If the buffer is too short, nothing is consumed, and the error tells how many more
+    /// bytes are needed. Any bit pattern is a valid encoding, the non-minimal encodings
+    /// are accepted too, as required by QUIC.
+    ///
+    /// ```
+    /// use qbase::varint::{err, VarInt};
+    ///
+    /// let mut buf = &[0x80, 0x01][..];
+    /// assert_eq!(VarInt::decode(&mut buf), Err(err::Incomplete(2)));
+    /// assert_eq!(buf.len(), 2);
+    ///
+    /// let mut buf = &[0x80, 0x01, 0x00, 0x00, 0xff][..];
+    /// assert_eq!(VarInt::decode(&mut buf), Ok(VarInt::from_u32(0x010000)));
+    /// assert_eq!(buf, &[0xff]);
+    /// ```
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, err::Incomplete> {
+        let Some(&first) = buf.chunk().first() else {
+            return Err(err::Incomplete(1));
+        };
+        let len = 1usize << (first >> 6);
+        if buf.remaining() < len {
+            return Err(err::Incomplete(len - buf.remaining()));
+        }
+        let mut value = (buf.get_u8() & 0x3f) as u64;
+        for _ in 1..len {
+            value = value << 8 | buf.get_u8() as u64;
+        }
+        Ok(Self(value))
+    }
+
+    /// Encode in the smallest number of bytes, the same as [`WriteVarInt::put_varint`].
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        buf.put_varint(self);
+    }
 }
 
 impl From<VarInt> for u64 {
@@ -110,6 +163,70 @@ impl TryFrom<usize> for VarInt {
     }
 }
 
+impl TryFrom<u128> for VarInt {
+    type Error = err::Overflow;
+
+    /// Succeeds if `x` < 2^62
+    fn try_from(x: u128) -> Result<Self, Self::Error> {
+        match u64::try_from(x) {
+            Ok(x) => Self::from_u64(x),
+            Err(_) => Err(err::Overflow(x as i128)),
+        }
+    }
+}
+
+// 负数同样无法编码为VarInt
+macro_rules! impl_try_from_signed {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<$ty> for VarInt {
+            type Error = err::Overflow;
+
+            /// Succeeds if 0 <= `x` < 2^62
+            fn try_from(x: $ty) -> Result<Self, Self::Error> {
+                match u64::try_from(x) {
+                    Ok(x) => Self::from_u64(x),
+                    Err(_) => Err(err::Overflow(x as i128)),
+                }
+            }
+        }
+    )*};
+}
+
+impl_try_from_signed!(i8, i16, i32, i64, isize, i128);
+
+impl From<VarInt> for u128 {
+    fn from(x: VarInt) -> Self {
+        x.0.into()
+    }
+}
+
+impl From<VarInt> for i64 {
+    fn from(x: VarInt) -> Self {
+        // 小于2^62，不会溢出
+        x.0 as i64
+    }
+}
+
+impl From<VarInt> for i128 {
+    fn from(x: VarInt) -> Self {
+        x.0.into()
+    }
+}
+
+macro_rules! impl_try_into_narrow {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<VarInt> for $ty {
+            type Error = TryFromIntError;
+
+            fn try_from(x: VarInt) -> Result<Self, Self::Error> {
+                <$ty>::try_from(x.0)
+            }
+        }
+    )*};
+}
+
+impl_try_into_narrow!(u8, u16, u32, usize, i8, i16, i32, isize);
+
 impl nom::ToUsize for VarInt {
     fn to_usize(&self) -> usize {
         self.0 as usize
@@ -140,12 +257,18 @@ pub mod err {
     use thiserror::Error;
 
     #[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
-    #[error("value({0}) too large for varint encoding")]
-    pub struct Overflow(pub(super) u64);
+    #[error("value({0}) out of the range of varint encoding")]
+    pub struct Overflow(pub(super) i128);
+
+    /// The buffer is too short to decode a varint, `.0` more bytes are needed.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+    #[error("incomplete varint, {0} more bytes needed")]
+    pub struct Incomplete(pub usize);
 }
 
-use bytes::BufMut;
+use bytes::{Buf, BufMut};
 use nom::{bits::streaming::take, combinator::flat_map, error::Error, IResult};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 /// Parse a variable-length integer, can be used like `be_u8/be_u16/be_u32` etc.
 /// ## Example
@@ -169,6 +292,18 @@ pub fn be_varint(input: &[u8]) -> IResult<&[u8], VarInt> {
     .map(|((buf, _), value)| (buf, VarInt(value)))
 }
 
+/// Read a variable-length integer directly off a stream, such as a stream reader.
+///
+/// Only the bytes of the varint are consumed. If the stream ends in the middle of a
+/// varint, an error of [`io::ErrorKind::UnexpectedEof`] is returned.
+pub async fn read_varint<R: AsyncRead + Unpin + ?Sized>(reader: &mut R) -> io::Result<VarInt> {
+    let mut buf = [0u8; VarInt::MAX_SIZE];
+    buf[0] = reader.read_u8().await?;
+    let len = 1usize << (buf[0] >> 6);
+    reader.read_exact(&mut buf[1..len]).await?;
+    VarInt::decode(&mut &buf[..len]).map_err(|e| io::Error::new(io::ErrorKind::UnexpectedEof, e))
+}
+
 /// Write a variable-length integer.
 ///
 /// `put_varint` will write the smallest number of bytes needed to represent the value.
@@ -240,7 +375,9 @@ impl<T: BufMut> WriteVarInt for T {
 
 #[cfg(test)]
 mod tests {
-    use super::{EncodeBytes, VarInt, WriteVarInt};
+    use proptest::prelude::*;
+
+    use super::{err, read_varint, EncodeBytes, VarInt, WriteVarInt, VARINT_MAX};
 
     #[test]
     fn test_be_varint() {
@@ -311,4 +448,151 @@ mod tests {
         assert_eq!(buf.len(), 6);
         assert_eq!(encode_buf[0..2], [0x40, 0x01]);
     }
+
+    // 每种编码长度的边界值
+    const BOUNDARIES: [u64; 8] = [
+        0,
+        (1 << 6) - 1,
+        1 << 6,
+        (1 << 14) - 1,
+        1 << 14,
+        (1 << 30) - 1,
+        1 << 30,
+        VARINT_MAX,
+    ];
+
+    fn assert_roundtrip(x: u64) {
+        let val = VarInt::from_u64(x).unwrap();
+        let mut buf = vec![];
+        val.encode(&mut buf);
+        assert_eq!(buf.len(), val.encoding_size());
+        assert_eq!(super::be_varint(&buf), Ok((&[][..], val)));
+
+        // 逐个字节地喂给增量解码器
+        for available in 0..buf.len() {
+            let mut partial = &buf[..available];
+            // 没有首字节，就不知道编码长度，至少还需要1字节
+            let needed = if available == 0 {
+                1
+            } else {
+                buf.len() - available
+            };
+            assert_eq!(VarInt::decode(&mut partial), Err(err::Incomplete(needed)));
+            assert_eq!(partial.len(), available);
+        }
+        let mut full = &buf[..];
+        assert_eq!(VarInt::decode(&mut full), Ok(val));
+        assert!(full.is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for x in BOUNDARIES {
+            assert_roundtrip(x);
+        }
+    }
+
+    #[test]
+    fn test_decode_non_minimal() {
+        for nbytes in [
+            EncodeBytes::One,
+            EncodeBytes::Two,
+            EncodeBytes::Four,
+            EncodeBytes::Eight,
+        ] {
+            let mut buf = vec![];
+            buf.encode_varint(&VarInt::from_u32(37), nbytes);
+            assert_eq!(VarInt::decode(&mut &buf[..]), Ok(VarInt::from_u32(37)));
+        }
+        // 非最短的编码也能读，但写出的总是最短的
+        let mut buf = vec![];
+        VarInt::decode(&mut &[0x40, 0x25][..])
+            .unwrap()
+            .encode(&mut buf);
+        assert_eq!(buf, [0x25]);
+    }
+
+    // 先取编码的位数再取值，让各种编码长度都有足够的样本
+    fn varint_value() -> impl Strategy<Value = u64> {
+        (0..=62u32).prop_flat_map(|bits| 0..=VARINT_MAX >> (62 - bits))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn roundtrip_arbitrary_values(x in varint_value()) {
+            assert_roundtrip(x);
+        }
+
+        // 任何字节序列都能被解码，或者给出还需要多少字节
+        #[test]
+        fn decode_arbitrary_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..=9)) {
+            let mut buf = &bytes[..];
+            match VarInt::decode(&mut buf) {
+                Ok(val) => {
+                    prop_assert!(val <= VARINT_MAX);
+                    prop_assert_eq!(bytes.len() - buf.len(), 1 << (bytes[0] >> 6));
+                }
+                Err(err::Incomplete(needed)) => {
+                    prop_assert_eq!(buf.len(), bytes.len());
+                    prop_assert!(needed > 0 && needed <= VarInt::MAX_SIZE);
+                    prop_assert!(super::be_varint(&bytes).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(VarInt::from_u64(VARINT_MAX), Ok(VarInt::MAX));
+        assert!(VarInt::from_u64(VARINT_MAX + 1).is_err());
+        assert!(VarInt::try_from(1u128 << 62).is_err());
+        assert!(VarInt::try_from(u128::MAX).is_err());
+        assert!(VarInt::try_from(-1i32).is_err());
+        assert!(VarInt::try_from(i64::MIN).is_err());
+        assert_eq!(
+            VarInt::try_from(i64::try_from(VARINT_MAX).unwrap()),
+            Ok(VarInt::MAX)
+        );
+        assert_eq!(VarInt::try_from(200i16), Ok(VarInt::from_u32(200)));
+
+        assert_eq!(u8::try_from(VarInt::from_u32(255)), Ok(255));
+        assert!(u8::try_from(VarInt::from_u32(256)).is_err());
+        assert!(u32::try_from(VarInt::MAX).is_err());
+        assert_eq!(i64::from(VarInt::MAX), VARINT_MAX as i64);
+        assert_eq!(u128::from(VarInt::MAX), VARINT_MAX as u128);
+
+        const LITERAL: VarInt = VarInt::from_const(VARINT_MAX);
+        assert_eq!(LITERAL, VarInt::MAX);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_const_overflow() {
+        let x = VARINT_MAX + 1;
+        VarInt::from_const(x);
+    }
+
+    #[tokio::test]
+    async fn test_read_varint() {
+        let mut stream = &[
+            0x25, 0x7b, 0xbd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ][..];
+        assert_eq!(
+            read_varint(&mut stream).await.unwrap(),
+            VarInt::from_u32(0x25)
+        );
+        assert_eq!(
+            read_varint(&mut stream).await.unwrap(),
+            VarInt::from_u32(15293)
+        );
+        assert_eq!(read_varint(&mut stream).await.unwrap(), VarInt::MAX);
+
+        let err = read_varint(&mut stream).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        let mut truncated = &[0x80, 0x01][..];
+        let err = read_varint(&mut truncated).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
 }
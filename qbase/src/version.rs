@@ -0,0 +1,379 @@
+use bytes::BufMut;
+use nom::{
+    combinator::{eof, verify},
+    multi::many_till,
+    number::complete::be_u32,
+};
+use rand::Rng;
+
+use crate::{
+    error::{Error, ErrorKind},
+    streamid::Role,
+};
+
+/// QUIC version 1, see [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html).
+pub const QUIC_V1: u32 = 0x00000001;
+
+/// The versions this implementation speaks, in the order of preference.
+pub const SUPPORTED_VERSIONS: [u32; 1] = [QUIC_V1];
+
+/// The smallest datagram carrying a client's Initial packet, the server responds to a smaller
+/// one with an unsupported version with nothing, see
+/// [RFC 9000 section 14.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-14.1).
+pub const MIN_INITIAL_SIZE: usize = 1200;
+
+pub fn is_supported(version: u32) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
+
+/// The versions of the form 0x?a?a?a?a are reserved for greasing the version negotiation, no
+/// endpoint ever speaks them, see [RFC 9000 section 15](https://www.rfc-editor.org/rfc/rfc9000.html#section-15).
+pub fn is_reserved(version: u32) -> bool {
+    version & 0x0f0f0f0f == 0x0a0a0a0a
+}
+
+/// A random reserved version, see [`is_reserved`].
+pub fn grease_version() -> u32 {
+    (rand::thread_rng().gen::<u32>() & 0xf0f0f0f0) | 0x0a0a0a0a
+}
+
+/// Build a Version Negotiation packet in response to the `datagram` whose long header carries a
+/// version this endpoint doesn't speak, listing the `versions` and a reserved one, see
+/// [RFC 9000 section 6.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-6.1).
+///
+/// Only the version-independent fields of the long header are parsed, see
+/// [RFC 8999](https://www.rfc-editor.org/rfc/rfc8999.html), so the connection ids of a future
+/// version longer than 20 bytes are echoed as well. None if the datagram is smaller than
+/// [`MIN_INITIAL_SIZE`], is not a long header, or is a Version Negotiation packet itself.
+pub fn version_negotiation(datagram: &[u8], versions: &[u32]) -> Option<Vec<u8>> {
+    if datagram.len() < MIN_INITIAL_SIZE || datagram[0] & 0x80 == 0 {
+        return None;
+    }
+    let version = u32::from_be_bytes(datagram.get(1..5)?.try_into().unwrap());
+    if version == 0 {
+        return None;
+    }
+    let dcid_len = *datagram.get(5)? as usize;
+    let dcid = datagram.get(6..6 + dcid_len)?;
+    let scid_len = *datagram.get(6 + dcid_len)? as usize;
+    let scid = datagram.get(7 + dcid_len..7 + dcid_len + scid_len)?;
+
+    let mut packet = Vec::with_capacity(7 + dcid_len + scid_len + 4 * (versions.len() + 1));
+    // 首字节除了最高位，其余都是任意的，固定位置1以便与其他协议复用端口
+    packet.put_u8(0xc0 | (rand::thread_rng().gen::<u8>() & 0x3f));
+    packet.put_u32(0);
+    // 交换客户端的连接ID
+    packet.put_u8(scid_len as u8);
+    packet.put_slice(scid);
+    packet.put_u8(dcid_len as u8);
+    packet.put_slice(dcid);
+    for version in versions {
+        packet.put_u32(*version);
+    }
+    packet.put_u32(grease_version());
+    Some(packet)
+}
+
+/// The value of the version_information transport parameter, for the compatible version
+/// negotiation and the downgrade prevention, see [RFC 9368](https://www.rfc-editor.org/rfc/rfc9368.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInformation {
+    /// The version the endpoint speaks on the connection, i.e. of the packets carrying the
+    /// transport parameters.
+    pub chosen_version: u32,
+    /// The versions the endpoint is willing to speak, in the order of preference.
+    pub available_versions: Vec<u32>,
+}
+
+impl VersionInformation {
+    /// What this implementation sends, version 1 chosen among the [`SUPPORTED_VERSIONS`].
+    pub fn local() -> Self {
+        Self {
+            chosen_version: QUIC_V1,
+            available_versions: SUPPORTED_VERSIONS.to_vec(),
+        }
+    }
+
+    pub fn encoding_size(&self) -> usize {
+        4 * (1 + self.available_versions.len())
+    }
+}
+
+/// Parse the version_information transport parameter, the chosen version and the available
+/// ones must not be 0.
+pub fn be_version_information(input: &[u8]) -> nom::IResult<&[u8], VersionInformation> {
+    let non_zero = || verify(be_u32, |version: &u32| *version != 0);
+    let (remain, chosen_version) = non_zero()(input)?;
+    let (remain, (available_versions, _)) = many_till(non_zero(), eof)(remain)?;
+    Ok((
+        remain,
+        VersionInformation {
+            chosen_version,
+            available_versions,
+        },
+    ))
+}
+
+pub trait WriteVersionInformation {
+    fn put_version_information(&mut self, info: &VersionInformation);
+}
+
+impl<T: BufMut> WriteVersionInformation for T {
+    fn put_version_information(&mut self, info: &VersionInformation) {
+        self.put_u32(info.chosen_version);
+        for version in &info.available_versions {
+            self.put_u32(*version);
+        }
+    }
+}
+
+fn version_negotiation_error(reason: &'static str) -> Error {
+    Error::with_default_fty(ErrorKind::VersionNegotiation, reason)
+}
+
+/// The version a connection speaks, and for a client, how it got to it.
+///
+/// A client may start with a reserved version to elicit a Version Negotiation packet from the
+/// server, learning its versions before speaking one of them, see [`Versions::recv_version_negotiation`].
+/// Once the handshake carries the server's version_information, the client makes sure the
+/// version negotiation was not tampered with, see [`Versions::authenticate`].
+#[derive(Debug, Clone)]
+pub struct Versions {
+    preferred: Vec<u32>,
+    current: u32,
+    // 收到并采纳了版本协商包，即经过了不兼容的版本协商
+    negotiated: bool,
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Self::new([QUIC_V1])
+    }
+}
+
+impl Versions {
+    /// The versions in the order of preference, the first one supported or reserved is the
+    /// version of the first Initial packets; the unsupported ones are skipped, and version 1
+    /// is taken if none is left.
+    pub fn new(preferred: impl IntoIterator<Item = u32>) -> Self {
+        let preferred = preferred
+            .into_iter()
+            .filter(|version| is_supported(*version) || is_reserved(*version))
+            .collect::<Vec<_>>();
+        let current = preferred.first().copied().unwrap_or(QUIC_V1);
+        Self {
+            preferred,
+            current,
+            negotiated: false,
+        }
+    }
+
+    /// The version of the long header packets.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Whether the version was switched by a Version Negotiation packet.
+    pub fn is_negotiated(&self) -> bool {
+        self.negotiated
+    }
+
+    // 客户端偏好的、对方提供的、并且自己真正会说的第一个版本
+    fn choose(&self, offered: &[u32]) -> Option<u32> {
+        self.preferred
+            .iter()
+            .copied()
+            .find(|version| is_supported(*version) && offered.contains(version))
+    }
+
+    /// The client received a Version Negotiation packet offering the versions, see
+    /// [RFC 9000 section 6.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-6.2).
+    ///
+    /// Ok(Some(version)) if the client switches to the version, and starts over with it.
+    /// Ok(None) if the packet must be discarded, for it lists the current version, or a
+    /// version was already negotiated. A VERSION_NEGOTIATION_ERROR if no version the client
+    /// prefers is offered, the client abandons the connection attempt.
+    pub fn recv_version_negotiation(&mut self, offered: &[u32]) -> Result<Option<u32>, Error> {
+        if self.negotiated || offered.contains(&self.current) {
+            return Ok(None);
+        }
+        let version = self
+            .choose(offered)
+            .ok_or_else(|| version_negotiation_error("no mutually supported version"))?;
+        self.current = version;
+        self.negotiated = true;
+        Ok(Some(version))
+    }
+
+    /// Validate the version_information of the `peer`, see
+    /// [RFC 9368 section 4](https://www.rfc-editor.org/rfc/rfc9368.html#section-4).
+    ///
+    /// The chosen version must be the one the connection speaks. After a version negotiation,
+    /// the client also makes sure it would have chosen the same version from the versions the
+    /// server says it speaks, so that a forged Version Negotiation packet can't downgrade the
+    /// connection. Any failure is a VERSION_NEGOTIATION_ERROR.
+    pub fn authenticate(&self, peer: Role, info: Option<&VersionInformation>) -> Result<(), Error> {
+        if let Some(info) = info {
+            if info.chosen_version != self.current {
+                return Err(version_negotiation_error("chosen version mismatches"));
+            }
+        }
+        if peer == Role::Server && self.negotiated {
+            let Some(info) = info else {
+                return Err(version_negotiation_error(
+                    "version_information is absent after version negotiation",
+                ));
+            };
+            if self.choose(&info.available_versions) != Some(self.current) {
+                return Err(version_negotiation_error("version downgrade detected"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn initial(version: u32, dcid: &[u8], scid: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0xc0];
+        datagram.put_u32(version);
+        datagram.put_u8(dcid.len() as u8);
+        datagram.put_slice(dcid);
+        datagram.put_u8(scid.len() as u8);
+        datagram.put_slice(scid);
+        datagram.resize(MIN_INITIAL_SIZE, 0);
+        datagram
+    }
+
+    #[test]
+    fn test_reserved_version() {
+        assert!(is_reserved(0x0a0a0a0a));
+        assert!(is_reserved(0x1a2a3a4a));
+        assert!(!is_reserved(QUIC_V1));
+        for _ in 0..100 {
+            assert!(is_reserved(grease_version()));
+        }
+    }
+
+    #[test]
+    fn test_version_negotiation_packet() {
+        let datagram = initial(0x1a2a3a4a, &[1; 8], &[2; 30]);
+        let packet = version_negotiation(&datagram, &[QUIC_V1]).unwrap();
+        assert_eq!(packet[0] & 0xc0, 0xc0);
+        assert_eq!(&packet[1..5], &[0; 4]);
+        // 连接ID互换，即便超过了20字节
+        assert_eq!(packet[5], 30);
+        assert_eq!(&packet[6..36], &[2; 30]);
+        assert_eq!(packet[36], 8);
+        assert_eq!(&packet[37..45], &[1; 8]);
+        let versions = packet[45..]
+            .chunks(4)
+            .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0], QUIC_V1);
+        assert!(is_reserved(versions[1]));
+
+        // 太小的数据报、短包头、版本协商包本身，都不回应
+        assert!(version_negotiation(&datagram[..MIN_INITIAL_SIZE - 1], &[QUIC_V1]).is_none());
+        let mut short = datagram.clone();
+        short[0] = 0x40;
+        assert!(version_negotiation(&short, &[QUIC_V1]).is_none());
+        assert!(version_negotiation(&initial(0, &[1; 8], &[2; 8]), &[QUIC_V1]).is_none());
+    }
+
+    #[test]
+    fn test_version_information_coding() {
+        let info = VersionInformation {
+            chosen_version: QUIC_V1,
+            available_versions: vec![QUIC_V1, 0x6b3343cf],
+        };
+        let mut buf = Vec::new();
+        buf.put_version_information(&info);
+        assert_eq!(buf.len(), info.encoding_size());
+        assert_eq!(be_version_information(&buf).unwrap(), (&[][..], info));
+
+        // 选定的版本或者可用的版本为0，长度不是4的倍数，都是格式错误
+        assert!(be_version_information(&[0, 0, 0, 0]).is_err());
+        assert!(be_version_information(&[0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+        assert!(be_version_information(&[0, 0, 0, 1, 0, 0]).is_err());
+        assert!(be_version_information(&[]).is_err());
+    }
+
+    #[test]
+    fn test_client_version_negotiation() {
+        let mut versions = Versions::new([0x1a2a3a4a, 0x00000002, QUIC_V1]);
+        assert_eq!(versions.current(), 0x1a2a3a4a);
+
+        // 列出了当前版本的版本协商包，被丢弃
+        assert_eq!(
+            versions.recv_version_negotiation(&[0x1a2a3a4a, QUIC_V1]),
+            Ok(None)
+        );
+        assert_eq!(
+            versions.recv_version_negotiation(&[0x0a0a0a0a, QUIC_V1]),
+            Ok(Some(QUIC_V1))
+        );
+        assert_eq!(versions.current(), QUIC_V1);
+        assert!(versions.is_negotiated());
+        // 至多协商一次
+        assert_eq!(versions.recv_version_negotiation(&[0x0a0a0a0a]), Ok(None));
+
+        let mut versions = Versions::new([0x1a2a3a4a, QUIC_V1]);
+        let error = versions
+            .recv_version_negotiation(&[0x00000002])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::VersionNegotiation);
+
+        // 全都不支持的偏好，退回版本1
+        assert_eq!(Versions::new([0x00000002]).current(), QUIC_V1);
+    }
+
+    #[test]
+    fn test_authenticate_version_information() {
+        let info = |chosen_version, available_versions: &[u32]| VersionInformation {
+            chosen_version,
+            available_versions: available_versions.to_vec(),
+        };
+        let is_vn_error = |result: Result<(), Error>| {
+            result.is_err_and(|error| error.kind() == ErrorKind::VersionNegotiation)
+        };
+
+        // 服务端核对客户端选定的版本
+        let server = Versions::default();
+        assert!(server
+            .authenticate(Role::Client, Some(&VersionInformation::local()))
+            .is_ok());
+        assert!(server.authenticate(Role::Client, None).is_ok());
+        assert!(is_vn_error(server.authenticate(
+            Role::Client,
+            Some(&info(0x1a2a3a4a, &[QUIC_V1]))
+        )));
+
+        // 没有经过版本协商的客户端，只核对服务端选定的版本
+        let client = Versions::default();
+        assert!(client.authenticate(Role::Server, None).is_ok());
+        assert!(client
+            .authenticate(Role::Server, Some(&info(QUIC_V1, &[QUIC_V1])))
+            .is_ok());
+        assert!(is_vn_error(client.authenticate(
+            Role::Server,
+            Some(&info(0x00000002, &[QUIC_V1]))
+        )));
+
+        // 经过版本协商的客户端，还要防止版本降级
+        let mut client = Versions::new([0x1a2a3a4a, QUIC_V1]);
+        client.recv_version_negotiation(&[QUIC_V1]).unwrap();
+        assert!(client
+            .authenticate(Role::Server, Some(&info(QUIC_V1, &[QUIC_V1, 0x0a0a0a0a])))
+            .is_ok());
+        assert!(is_vn_error(client.authenticate(Role::Server, None)));
+        // 篡改过的version_information，服务端可用的版本中没有客户端协商到的版本
+        assert!(is_vn_error(client.authenticate(
+            Role::Server,
+            Some(&info(QUIC_V1, &[0x00000002]))
+        )));
+    }
+}
@@ -4,7 +4,7 @@ use std::{
 };
 
 use crate::{
-    congestion::{AckedPkt, Algorithm, SentPkt, MSS},
+    congestion::{AckedPkt, Algorithm, Handover, SentPkt, MSS},
     delivery_rate::Rate,
     min_max::MinMax,
 };
@@ -38,6 +38,9 @@ const MIN_PIPE_CWND_PKTS: usize = 4;
 
 const MINIMUM_WINDOW_PACKETS: usize = 2;
 
+// The factor the cwnd and pacing rate are multiplied by on the ECN-CE marks of a round.
+const ECN_CE_REDUCTION_FACTOR: f64 = 0.7;
+
 // BBR State
 //
 // https://datatracker.ietf.org/doc/html/draft-cardwell-iccrg-bbr-congestion-control-00#section-3.4
@@ -134,10 +137,14 @@ pub(crate) struct Bbr {
     last_ack_packet_sent_time: Instant,
     // The amount of data that was in flight before processing this ACK.
     prior_bytes_in_flight: u64,
+    // The round in which the congestion window was last reduced for ECN-CE marks.
+    ecn_ce_round: Option<u64>,
     // The sum of the size in bytes of all sent packets that contain at least
     // one ack-eliciting or PADDING frame and have not been acknowledged or
     // declared lost. The size does not include IP or UDP overhead.
     pub bytes_in_flight: u64,
+    // The part of bytes_in_flight sent as probes, see PacketClass::is_probe.
+    probe_bytes_in_flight: u64,
 }
 
 impl Bbr {
@@ -179,7 +186,9 @@ impl Bbr {
             prior_bytes_in_flight: 0,
             packet_delivered: 0,
             bytes_in_flight: 0,
+            probe_bytes_in_flight: 0,
             bytes_lost_in_total: 0,
+            ecn_ce_round: None,
         };
         bbr.on_connection_init();
         bbr
@@ -190,16 +199,32 @@ impl Algorithm for Bbr {
     fn on_sent(&mut self, sent: &mut SentPkt, _: usize, _: Instant) {
         self.delivery_rate.on_packet_sent(
             sent,
-            self.bytes_in_flight as usize,
+            self.bytes_in_flight
+                .saturating_sub(self.probe_bytes_in_flight) as usize,
             self.bytes_lost_in_total,
         );
 
         self.bytes_in_flight += sent.size as u64;
+        if sent.class.is_probe() {
+            self.probe_bytes_in_flight += sent.size as u64;
+        }
         self.on_transmit();
     }
 
     //  todo: VecDeque 是否有必要
     fn on_ack(&mut self, packets: VecDeque<AckedPkt>, now: Instant) {
+        // 探测包只是不再在途，既不计入带宽采样，也不扩大窗口
+        let (probes, packets): (VecDeque<_>, VecDeque<_>) =
+            packets.into_iter().partition(|ack| ack.class.is_probe());
+        for probe in probes {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(probe.size as u64);
+            self.probe_bytes_in_flight =
+                self.probe_bytes_in_flight.saturating_sub(probe.size as u64);
+        }
+        if packets.is_empty() {
+            return;
+        }
+
         self.newly_acked_bytes = 0;
         self.newly_lost_bytes = 0;
         self.packet_delivered = 0;
@@ -210,6 +235,7 @@ impl Algorithm for Bbr {
         for mut ack in packets {
             self.delivery_rate.update_rate_sample(&ack, now);
             self.bytes_in_flight = self.bytes_in_flight.saturating_sub(ack.size as u64);
+            self.newly_acked_bytes += ack.size as u64;
             self.packet_delivered = self
                 .packet_delivered
                 .max(self.delivery_rate.delivered() as u64);
@@ -232,11 +258,40 @@ impl Algorithm for Bbr {
         self.update_control_parameters();
     }
 
-    fn on_congestion_event(&mut self, _: &SentPkt, _: Instant) {
+    fn on_congestion_event(&mut self, lost: &SentPkt, _: Instant) {
+        // 丢失的包不再在途
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost.size as u64);
+        self.bytes_lost_in_total += lost.size as u64;
         // todo: enter_recovery
         // update newly lost bytes, set BBR.packet_conservation = true
     }
 
+    fn on_probe_lost(&mut self, lost: &SentPkt) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost.size as u64);
+        self.probe_bytes_in_flight = self.probe_bytes_in_flight.saturating_sub(lost.size as u64);
+    }
+
+    fn on_discarded(&mut self, bytes: u64) {
+        self.bytes_in_flight = self.bytes_in_flight.saturating_sub(bytes);
+        self.probe_bytes_in_flight = self.probe_bytes_in_flight.min(self.bytes_in_flight);
+    }
+
+    // 带宽与RTprop的估计保留，之后的确认会按估计重新扩大窗口
+    fn on_persistent_congestion(&mut self, _: Instant) {
+        self.cwnd = self.min_pipe_cwnd();
+    }
+
+    // 每个往返只对CE标记反应一次，按比例收缩窗口与发送速率，但不低于最小窗口
+    fn on_ecn_ce(&mut self, _: Instant, _: Instant) {
+        if self.ecn_ce_round == Some(self.round_count) {
+            return;
+        }
+        self.ecn_ce_round = Some(self.round_count);
+        self.cwnd = ((self.cwnd as f64 * ECN_CE_REDUCTION_FACTOR) as u64).max(self.min_pipe_cwnd());
+        self.pacing_rate = (self.pacing_rate as f64 * ECN_CE_REDUCTION_FACTOR) as u64;
+        self.set_send_quantum();
+    }
+
     fn cwnd(&self) -> u64 {
         self.cwnd
     }
@@ -244,6 +299,40 @@ impl Algorithm for Bbr {
     fn pacing_rate(&self) -> Option<u64> {
         Some(self.pacing_rate)
     }
+
+    fn in_slow_start(&self) -> bool {
+        self.state == BbrStateMachine::Startup
+    }
+
+    // BBR不以慢启动阈值区分阶段
+    fn ssthresh(&self) -> Option<u64> {
+        None
+    }
+
+    // 从Startup开始重新探测带宽，但以旧算法的pacing rate作为初始估计
+    fn inherit(&mut self, handover: &Handover, now: Instant) {
+        self.cwnd = handover.cwnd.min(INITIAL_CWND).max(self.min_pipe_cwnd());
+        self.bytes_in_flight = handover.bytes_in_flight;
+        self.pacing_rate = handover.pacing_rate;
+        if let Some(min_rtt) = handover.min_rtt {
+            self.rtprop = min_rtt;
+            self.rtprop_stamp = now;
+        }
+        self.set_send_quantum();
+    }
+
+    // 窗口未用满却无数据可发，直到此后发出的包被确认，带宽采样都受限于应用，
+    // 这些采样不会拉低带宽估计，也不会被当作带宽停止增长而退出Startup
+    fn on_app_limited(&mut self, _: Instant) {
+        if self.bytes_in_flight < self.cwnd {
+            self.delivery_rate.update_app_limited(true);
+        }
+    }
+
+    // 带宽与RTprop的估计保留，窗口回到初始窗口，之后的确认会按估计重新扩大
+    fn on_idle_restart(&mut self, _: Instant) {
+        self.cwnd = self.cwnd.min(INITIAL_CWND).max(self.min_pipe_cwnd());
+    }
 }
 
 impl Bbr {
@@ -282,8 +371,10 @@ mod tests {
     };
 
     use crate::{
-        bbr::{BbrStateMachine, HIGH_GAIN, INITIAL_CWND, MSS},
-        congestion::{AckedPkt, Algorithm, SentPkt},
+        bbr::{
+            BbrStateMachine, HIGH_GAIN, INITIAL_CWND, MSS, PROBE_RTT_DURATION, RTPROP_FILTER_LEN,
+        },
+        congestion::{AckedPkt, Algorithm, Handover, PacketClass, SentPkt},
         rtt::INITIAL_RTT,
     };
 
@@ -315,6 +406,10 @@ mod tests {
             bbr.on_sent(&mut sent, MSS, now);
         }
         assert_eq!(bbr.bytes_in_flight, 10 * MSS as u64);
+
+        // 丢弃了密钥的空间的包，不再在途
+        bbr.on_discarded(4 * MSS as u64);
+        assert_eq!(bbr.bytes_in_flight, 6 * MSS as u64);
     }
 
     #[test]
@@ -359,6 +454,329 @@ mod tests {
         assert_eq!(bbr.pacing_rate, (bbr.btlbw as f64 * bbr.pacing_gain) as u64);
     }
 
+    #[test]
+    fn test_bbr_inherit() {
+        let mut bbr = super::Bbr::new();
+        let now = Instant::now();
+        let handover = Handover {
+            cwnd: 200 * MSS as u64,
+            bytes_in_flight: 10 * MSS as u64,
+            pacing_rate: 1_000_000,
+            min_rtt: Some(Duration::from_millis(20)),
+        };
+        bbr.inherit(&handover, now);
+        assert_eq!(bbr.cwnd, INITIAL_CWND);
+        assert_eq!(bbr.bytes_in_flight, 10 * MSS as u64);
+        assert_eq!(bbr.pacing_rate, 1_000_000);
+        assert_eq!(bbr.rtprop, Duration::from_millis(20));
+
+        // 旧算法发出的包，被确认或判定丢失后从在途中扣除
+        let acks = (0..6)
+            .map(|pn| {
+                let mut ack: AckedPkt = SentPkt {
+                    pn,
+                    size: MSS,
+                    time_sent: now,
+                    ..Default::default()
+                }
+                .into();
+                ack.rtt = Duration::from_millis(20);
+                ack
+            })
+            .collect();
+        bbr.on_ack(acks, now + Duration::from_millis(20));
+        assert_eq!(bbr.bytes_in_flight, 4 * MSS as u64);
+        for pn in 6..10 {
+            let lost = SentPkt {
+                pn,
+                size: MSS,
+                ..Default::default()
+            };
+            bbr.on_congestion_event(&lost, now + Duration::from_millis(20));
+        }
+        assert_eq!(bbr.bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn test_bbr_ecn_ce() {
+        let mut bbr = super::Bbr::new();
+        let now = Instant::now();
+        let pacing_rate = bbr.pacing_rate;
+        bbr.on_ecn_ce(now, now);
+        assert_eq!(bbr.cwnd, (INITIAL_CWND as f64 * 0.7) as u64);
+        assert_eq!(bbr.pacing_rate, (pacing_rate as f64 * 0.7) as u64);
+
+        // 同一往返内的更多标记不再收缩
+        bbr.on_ecn_ce(now, now);
+        assert_eq!(bbr.cwnd, (INITIAL_CWND as f64 * 0.7) as u64);
+
+        bbr.round_count += 1;
+        bbr.cwnd = 5 * MSS as u64;
+        bbr.on_ecn_ce(now, now);
+        assert_eq!(bbr.cwnd, bbr.min_pipe_cwnd());
+    }
+
+    // 确定性的模拟：按pacing rate发送，一条瓶颈链路逐个转发包，确认逐个到达，
+    // 时间只随这些事件推进
+    struct Harness {
+        bbr: super::Bbr,
+        now: Instant,
+        // 队列为空时的往返时延
+        rtt: Duration,
+        // 瓶颈链路转发一个包所需的时间
+        serialization: Duration,
+        // 瓶颈链路转发完已排队的包的时间
+        link_free: Instant,
+        // 受pacing限制，下一个包最早的发送时间
+        next_send: Instant,
+        next_pn: u64,
+        // 在途的包及其确认到达的时间，按发送顺序排列
+        in_flight: VecDeque<(Instant, SentPkt)>,
+    }
+
+    impl Harness {
+        // 传播时延100ms，每1ms转发一个包，BDP为100个包
+        fn new() -> Self {
+            let bbr = super::Bbr::new();
+            let now = bbr.ack_time;
+            Self {
+                bbr,
+                now,
+                rtt: Duration::from_millis(100),
+                serialization: Duration::from_millis(1),
+                link_free: now,
+                next_send: now,
+                next_pn: 0,
+                in_flight: VecDeque::new(),
+            }
+        }
+
+        fn bottleneck_bw(&self) -> u64 {
+            (MSS as f64 / self.serialization.as_secs_f64()) as u64
+        }
+
+        fn send(&mut self) {
+            let mut sent = SentPkt {
+                pn: self.next_pn,
+                size: MSS,
+                time_sent: self.now,
+                ..Default::default()
+            };
+            self.bbr.on_sent(&mut sent, MSS, self.now);
+            self.next_pn += 1;
+            self.link_free = self.link_free.max(self.now) + self.serialization;
+            self.in_flight.push_back((self.link_free + self.rtt, sent));
+        }
+
+        fn ack_next(&mut self) {
+            let (arrival, sent) = self.in_flight.pop_front().unwrap();
+            self.now = arrival;
+            let mut ack: AckedPkt = sent.into();
+            ack.rtt = arrival.saturating_duration_since(ack.time_sent);
+            self.bbr.on_ack(VecDeque::from([ack]), arrival);
+        }
+
+        // 处理下一个事件：窗口允许时按pacing rate发送，否则等待确认
+        fn step(&mut self) {
+            let cwnd_available = self.bbr.bytes_in_flight + MSS as u64 <= self.bbr.cwnd;
+            match self.in_flight.front() {
+                Some((arrival, _)) if !cwnd_available || *arrival < self.next_send => {
+                    self.ack_next()
+                }
+                _ => {
+                    self.now = self.now.max(self.next_send);
+                    self.send();
+                    let interval = MSS as f64 / self.bbr.pacing_rate as f64;
+                    self.next_send = self.now + Duration::from_secs_f64(interval);
+                }
+            }
+        }
+
+        // 一直发送，直到满足条件，超时则失败
+        fn run_until(&mut self, timeout: Duration, until: impl Fn(&super::Bbr) -> bool) {
+            let deadline = self.now + timeout;
+            while !until(&self.bbr) {
+                assert!(self.now < deadline, "timeout in {:?}", self.bbr.state);
+                self.step();
+            }
+        }
+
+        // 应用只有少量数据，窗口远未用满，每个往返只发送两个包
+        fn idle_rounds(&mut self, rounds: usize, app_limited: bool) {
+            while !self.in_flight.is_empty() {
+                self.ack_next();
+            }
+            for _ in 0..rounds {
+                if app_limited {
+                    self.bbr.on_app_limited(self.now);
+                }
+                self.send();
+                self.send();
+                while !self.in_flight.is_empty() {
+                    self.ack_next();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bbr_state_machine() {
+        let mut harness = Harness::new();
+        let bottleneck_bw = harness.bottleneck_bw();
+        assert_eq!(harness.bbr.state, BbrStateMachine::Startup);
+
+        // Startup：以高增益指数增长，直到连续三个往返带宽不再明显增长
+        harness.run_until(Duration::from_secs(3), |bbr| {
+            bbr.state != BbrStateMachine::Startup
+        });
+        let bbr = &harness.bbr;
+        assert!(bbr.is_filled_pipe);
+        assert_eq!(bbr.state, BbrStateMachine::Drain);
+        assert_eq!(bbr.pacing_gain, 1.0 / HIGH_GAIN);
+        assert_eq!(bbr.cwnd_gain, HIGH_GAIN);
+        assert!(bbr.btlbw > bottleneck_bw * 9 / 10 && bbr.btlbw <= bottleneck_bw * 11 / 10);
+        assert_eq!(bbr.pacing_rate, (bbr.pacing_gain * bbr.btlbw as f64) as u64);
+        // Startup中排起了队列
+        assert!(bbr.bytes_in_flight > bbr.inflight(1.0));
+
+        // Drain：排空Startup中的队列，直到在途的数据不超过BDP
+        harness.run_until(Duration::from_secs(3), |bbr| {
+            bbr.state != BbrStateMachine::Drain
+        });
+        let bbr = &harness.bbr;
+        assert_eq!(bbr.state, BbrStateMachine::ProbeBW);
+        assert!(bbr.bytes_in_flight <= bbr.inflight(1.0));
+        let probe_bw_start = harness.now;
+
+        // ProbeBW：按增益循环探测带宽，窗口为两倍的BDP
+        while harness.now < probe_bw_start + Duration::from_secs(3) {
+            harness.step();
+            let bbr = &harness.bbr;
+            assert_eq!(bbr.state, BbrStateMachine::ProbeBW);
+            assert_eq!(bbr.cwnd_gain, 2.0);
+            assert!(super::state::PACING_GAIN_CYCLE.contains(&bbr.pacing_gain));
+            assert_eq!(bbr.pacing_rate, (bbr.pacing_gain * bbr.btlbw as f64) as u64);
+            assert!(bbr.cwnd <= bbr.inflight(2.0));
+        }
+        let bbr = &harness.bbr;
+        assert_eq!(bbr.cwnd, bbr.inflight(2.0));
+
+        // 路径的时延变大，RTprop迟迟得不到刷新，10s后进入ProbeRTT
+        harness.rtt = Duration::from_millis(150);
+        harness.run_until(RTPROP_FILTER_LEN, |bbr| {
+            bbr.state == BbrStateMachine::ProbeRTT
+        });
+        let probe_rtt_start = harness.now;
+        let bbr = &harness.bbr;
+        assert!(probe_rtt_start - bbr.rtprop_stamp <= RTPROP_FILTER_LEN);
+        assert_eq!(bbr.pacing_gain, 1.0);
+        assert_eq!(bbr.cwnd_gain, 1.0);
+        assert_eq!(bbr.cwnd, bbr.min_pipe_cwnd());
+
+        // ProbeRTT：在途的数据收缩到4个包，至少维持200ms和一个往返
+        while harness.bbr.state == BbrStateMachine::ProbeRTT {
+            assert_eq!(harness.bbr.cwnd, harness.bbr.min_pipe_cwnd());
+            harness.step();
+        }
+        let bbr = &harness.bbr;
+        assert!(harness.now - probe_rtt_start >= PROBE_RTT_DURATION + harness.rtt);
+        // 队列排空后刷新了RTprop，带宽已经探测过，回到ProbeBW
+        assert_eq!(bbr.state, BbrStateMachine::ProbeBW);
+        assert!(bbr.rtprop <= harness.rtt + 4 * harness.serialization);
+        assert!(bbr.cwnd > bbr.min_pipe_cwnd());
+    }
+
+    #[test]
+    fn test_bbr_app_limited() {
+        let mut harness = Harness::new();
+        harness.run_until(Duration::from_secs(6), |bbr| {
+            bbr.state == BbrStateMachine::ProbeBW
+        });
+        let btlbw = harness.bbr.btlbw;
+        harness.idle_rounds(20, true);
+        // 受限于应用的采样，不会拉低带宽的估计
+        assert!(harness.bbr.delivery_rate.sample_is_app_limited());
+        assert_eq!(harness.bbr.btlbw, btlbw);
+
+        // 没有标记的话，过了带宽滤波器的窗口，带宽的估计就降到了应用的发送速率
+        let mut harness = Harness::new();
+        harness.run_until(Duration::from_secs(6), |bbr| {
+            bbr.state == BbrStateMachine::ProbeBW
+        });
+        harness.idle_rounds(20, false);
+        assert!(harness.bbr.btlbw < btlbw / 10);
+    }
+
+    // 稳定的传输中夹杂着探测包，它们很快被确认或者丢失，带宽估计与没有探测包时一致
+    #[test]
+    fn test_bbr_ignores_probes() {
+        fn transfer(with_probes: bool) -> super::Bbr {
+            let mut bbr = super::Bbr::new();
+            let mut now = bbr.ack_time;
+            let rtt = Duration::from_millis(100);
+            let mut pn = 0;
+            for round in 0..20u64 {
+                let mut acks = VecDeque::new();
+                for _ in 0..10 + round {
+                    let mut sent = SentPkt {
+                        pn,
+                        size: MSS,
+                        time_sent: now,
+                        ..Default::default()
+                    };
+                    bbr.on_sent(&mut sent, MSS, now);
+                    pn += 1;
+                    let mut ack: AckedPkt = sent.into();
+                    ack.rtt = rtt;
+                    acks.push_back(ack);
+                }
+                if with_probes {
+                    let class = match round % 2 {
+                        0 => PacketClass::MtuProbe,
+                        _ => PacketClass::PathProbe,
+                    };
+                    let mut probe = SentPkt {
+                        pn,
+                        size: 4 * MSS,
+                        time_sent: now,
+                        class,
+                        ..Default::default()
+                    };
+                    bbr.on_sent(&mut probe, 4 * MSS, now);
+                    pn += 1;
+                    if round % 3 == 0 {
+                        bbr.on_probe_lost(&probe);
+                    } else {
+                        // 孤零零的大包很快被确认，若计入采样，看起来带宽极大
+                        let mut ack: AckedPkt = probe.into();
+                        ack.rtt = rtt / 10;
+                        bbr.on_ack(VecDeque::from([ack]), now + rtt / 10);
+                    }
+                }
+                now += rtt;
+                bbr.on_ack(acks, now);
+            }
+            bbr
+        }
+
+        let plain = transfer(false);
+        let probed = transfer(true);
+        assert_eq!(probed.btlbw, plain.btlbw);
+        assert_eq!(
+            probed.delivery_rate.sample_delivery_rate(),
+            plain.delivery_rate.sample_delivery_rate()
+        );
+        assert_eq!(
+            probed.delivery_rate.delivered(),
+            plain.delivery_rate.delivered()
+        );
+        assert_eq!(probed.round_count, plain.round_count);
+        assert_eq!(probed.cwnd, plain.cwnd);
+        assert_eq!(probed.pacing_rate, plain.pacing_rate);
+        assert_eq!(probed.bytes_in_flight, 0);
+        assert_eq!(probed.probe_bytes_in_flight, 0);
+    }
+
     pub(super) fn simulate_round_trip(
         bbr: &mut super::Bbr,
         start_time: Instant,
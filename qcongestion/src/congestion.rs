@@ -6,32 +6,194 @@ use std::{
     time::{Duration, Instant},
 };
 
-use qbase::frame::{AckFrame, EcnCounts};
+use qbase::{
+    frame::{AckFrame, AckFrequencyFrame, EcnCounts},
+    streamid::Role,
+};
 use qrecovery::space::Epoch;
 
 use crate::{
     bbr::{self, INITIAL_CWND},
+    ecn::{EcnCodepoint, EcnState, EcnValidator},
     new_reno::NewReno,
-    pacing::{self, Pacer},
+    pacing::{self, IdleRestart, Pacer, PacingConfig, N},
     rtt::{ArcRtt, INITIAL_RTT},
 };
 
 const K_GRANULARITY: Duration = Duration::from_millis(1);
-const K_PACKET_THRESHOLD: usize = 3;
+// 记录的最近判定为丢失的包的数量上限，用以发现虚假丢包
+const MAX_DECLARED_LOST: usize = 256;
 const MAX_SENT_DELAY: Duration = Duration::from_millis(30);
+const K_PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
 
 //  default datagram size in bytes.
 pub const MSS: usize = 1200;
 
+/// The congestion control algorithm of a path, it can be switched on the fly,
+/// see [`ArcCC::switch_algorithm`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum CongestionAlgorithm {
+    #[default]
     Bbr,
     NewReno,
 }
 
+impl CongestionAlgorithm {
+    fn create(self) -> Box<dyn Algorithm + Send> {
+        match self {
+            CongestionAlgorithm::Bbr => Box::new(bbr::Bbr::new()),
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new()),
+        }
+    }
+}
+
+/// What the new algorithm inherits from the old one when switching the algorithm.
+///
+/// The sent packets, the RTT estimator and the loss detection stay in the
+/// [`CongestionController`], only the state kept by the algorithm itself is handed over.
+#[derive(Debug, Clone, Copy)]
+pub struct Handover {
+    /// The congestion window of the old algorithm.
+    pub cwnd: u64,
+    /// The bytes of the in-flight packets that are neither acknowledged nor lost yet,
+    /// which may be sent under the old algorithm.
+    pub bytes_in_flight: u64,
+    /// The pacing rate of the old algorithm, or `N * cwnd / smoothed_rtt` if it doesn't pace.
+    pub pacing_rate: u64,
+    /// The minimum RTT observed on the path, None if there is no RTT sample yet.
+    pub min_rtt: Option<Duration>,
+}
+
+/// A snapshot of the congestion control state of a path, see
+/// [`CongestionControl::metrics`](super::CongestionControl::metrics).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PathMetrics {
+    pub algorithm: CongestionAlgorithm,
+    /// The congestion window in bytes.
+    pub cwnd: u64,
+    /// The bytes of the in-flight packets that are neither acknowledged nor lost yet.
+    pub bytes_in_flight: u64,
+    /// The slow start threshold, None if it's not set yet, or the algorithm doesn't use it.
+    pub ssthresh: Option<u64>,
+    /// The rate the packets are paced at, in bytes per second.
+    pub pacing_rate: u64,
+    pub smoothed_rtt: Duration,
+    pub rttvar: Duration,
+    /// None if there is no RTT sample yet.
+    pub min_rtt: Option<Duration>,
+    /// None if there is no RTT sample yet.
+    pub latest_rtt: Option<Duration>,
+    /// The number of probe timeouts without receiving an acknowledgment, which backs off
+    /// the probe timeout.
+    pub pto_count: u32,
+    /// The packets sent on the path in total, including the ones not in flight.
+    pub packets_sent: u64,
+    /// The bytes of the packets sent on the path in total.
+    pub bytes_sent: u64,
+    /// The packets declared lost on the path in total.
+    pub packets_lost: u64,
+    /// The packets declared lost but acknowledged later on the path in total, a sign of
+    /// reordering rather than loss.
+    pub spurious_losses: u64,
+    /// The packet threshold of the loss detection in use, see [`LossDetectionConfig`].
+    pub packet_threshold: u64,
+    /// The packets carrying an ACK frame sent on the path in total, it drops as the
+    /// acknowledgments are delayed, see [`AckEagerness`].
+    pub acks_sent: u64,
+    /// The times the peer reported an ack_delay beyond its max_ack_delay, see
+    /// [`ArcRtt::ack_delay_violations`].
+    pub ack_delay_violations: u64,
+    /// Whether the peer keeps violating its max_ack_delay, see
+    /// [`ArcRtt::peer_violates_max_ack_delay`].
+    pub peer_violates_max_ack_delay: bool,
+}
+
+/// How eagerly the received ack-eliciting packets are acknowledged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AckEagerness {
+    /// Acknowledge every ack-eliciting packet without delay, suitable for latency-critical
+    /// request/response exchanges, where the peer's congestion window waits on the ACK.
+    Immediate,
+    /// Acknowledge immediately when packets are reordered or missing, otherwise delay
+    /// up to max_ack_delay.
+    #[default]
+    Default,
+    /// Always delay up to max_ack_delay in the application data space, even if packets
+    /// are reordered or missing, to send fewer ACKs in bulk transfers.
+    Relaxed,
+    /// Like [`AckEagerness::Relaxed`], and also ignore the peer's ACK_FREQUENCY frame,
+    /// always delaying up to the local max_ack_delay, to cut the cost of acknowledging
+    /// when the endpoint is overloaded. The peer may time out more spuriously.
+    Lazy,
+}
+
+/// How the peer asks the received ack-eliciting packets in the application data space to be
+/// acknowledged, carried by the latest ACK_FREQUENCY frame, see
+/// [draft-ietf-quic-ack-frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency).
+///
+/// It takes the place of [`AckEagerness::Default`] and [`AckEagerness::Relaxed`] once
+/// received, while [`AckEagerness::Immediate`] still acknowledges every packet, and
+/// [`AckEagerness::Lazy`] ignores it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckFrequency {
+    /// The number of ack-eliciting packets that can be received without acknowledging
+    /// immediately.
+    pub ack_eliciting_threshold: u64,
+    /// How long an acknowledgment can be delayed.
+    pub max_ack_delay: Duration,
+    /// How far a missing packet lags behind the largest received one before it's reported
+    /// immediately, 0 never reports the reordering immediately.
+    pub reordering_threshold: u64,
+}
+
+impl From<&AckFrequencyFrame> for AckFrequency {
+    fn from(frame: &AckFrequencyFrame) -> Self {
+        Self {
+            ack_eliciting_threshold: frame.ack_eliciting_threshold.into_inner(),
+            max_ack_delay: Duration::from_micros(frame.request_max_ack_delay.into_inner()),
+            reordering_threshold: frame.reordering_threshold.into_inner(),
+        }
+    }
+}
+
+/// How the in-flight packets are declared lost, see
+/// [section 6.1](https://www.rfc-editor.org/rfc/rfc9002.html#name-acknowledgment-based-detecti)
+/// of RFC 9002.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossDetectionConfig {
+    /// A packet is declared lost once a packet sent this many packets later on the path is
+    /// acknowledged, kPacketThreshold of RFC 9002, 3 by default and at least 1.
+    pub packet_threshold: u64,
+    /// A packet is declared lost once it's sent this many times of max(smoothed_rtt,
+    /// latest_rtt) earlier than now, if a later packet is acknowledged, kTimeThreshold of
+    /// RFC 9002, 9/8 by default and at least 1.
+    pub time_threshold: f32,
+    /// When a packet declared lost is acknowledged later, the packet threshold is raised to
+    /// tolerate the reordering observed, up to this value. None keeps the packet threshold
+    /// fixed, 16 by default.
+    pub max_packet_threshold: Option<u64>,
+    /// The probe timeout doubles on every expiry without an acknowledgment, but never
+    /// exceeds this value, unless the probe timeout without backoff already does. None
+    /// backs off without a cap, 60 seconds by default.
+    pub max_pto: Option<Duration>,
+}
+
+impl Default for LossDetectionConfig {
+    fn default() -> Self {
+        Self {
+            packet_threshold: 3,
+            time_threshold: 9.0 / 8.0,
+            max_packet_threshold: Some(16),
+            max_pto: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
 // imple RFC 9002 Appendix A. Loss Recovery
 pub struct CongestionController {
     // congestion controlle algorithm: bbr or cubic
     algorithm: Box<dyn Algorithm + Send>,
+    kind: CongestionAlgorithm,
     rtt: ArcRtt,
     loss_timer: LossDetectionTimer,
     // The number of times a PTO has been sent without receiving an acknowledgment.
@@ -50,14 +212,61 @@ pub struct CongestionController {
     // pacer is used to control the burst rate
     pacer: pacing::Pacer,
     last_sent_time: Instant,
+    // 本次空闲已经重启过拥塞窗口，发包之后清除
+    idle_restarted: bool,
 
     ack_records: [AckRecord; Epoch::count()],
+    ack_eagerness: AckEagerness,
     send_waker: Option<Waker>,
     loss: Box<dyn Fn(Epoch, u64) + Send + Sync>,
     retire: Box<dyn Fn(Epoch, u64) + Send + Sync>,
 
     has_handshake_keys: bool,
     is_handshake_done: bool,
+    // 客户端需要知道服务端是否已经验证了其地址，否则没有在途的包时也要发探测包以免死锁
+    role: Role,
+    // 收到过Handshake包的确认，意味着服务端已经验证了客户端的地址
+    handshake_acked: bool,
+    // 服务端受抗放大限制而无法发送，此时取消丢包检测定时器，直到收到新的数据报
+    amplification_limited: bool,
+    // PTO触发后，各空间尚待发送的探测包数，即便拥塞窗口已满也要发出
+    probes: [u8; Epoch::count()],
+
+    // The number of PTOs in a row since the last acknowledgment, unlike pto_count, it
+    // is reset by any newly acknowledged packet.
+    pto_in_a_row: u32,
+    // Give up the path once pto_in_a_row reaches it, None never gives up.
+    max_pto_count: Option<u32>,
+    exhausted_waker: Option<Waker>,
+
+    // Validate the ECN counts reported by the peer, and decide whether to mark the packets.
+    ecn: EcnValidator,
+
+    // The time the first RTT sample was obtained, only the packets sent after it count
+    // towards persistent congestion.
+    first_rtt_sample: Option<Instant>,
+    // The sent time of the acknowledged packets, in ascending order, which may fall between
+    // two lost packets. The ones sent before all the unacknowledged packets are dropped.
+    acked_sent_times: VecDeque<Instant>,
+    // The packets and their bytes sent in total, reported by the metrics.
+    packets_sent: u64,
+    bytes_sent: u64,
+    // The packets declared lost in total, reported by the metrics.
+    packets_lost: u64,
+    // The packets carrying an ACK frame sent in total, reported by the metrics.
+    acks_sent: u64,
+
+    loss_detection: LossDetectionConfig,
+    // The packet threshold in use, raised from the configured one by spurious losses.
+    packet_threshold: u64,
+    // The packets recently declared lost, with how many packets later the acknowledged one
+    // was sent, in ascending order of the packet number. Acknowledged later, it's a spurious
+    // loss.
+    declared_lost: [VecDeque<(u64, u64)>; Epoch::count()],
+    // The packets declared lost but acknowledged later in total, reported by the metrics.
+    spurious_losses: u64,
+    // 密钥已丢弃的空间，其包不再记录
+    discarded: [bool; Epoch::count()],
 }
 
 impl CongestionController {
@@ -68,14 +277,10 @@ impl CongestionController {
         loss: Box<dyn Fn(Epoch, u64) + Send + Sync>,
         retire: Box<dyn Fn(Epoch, u64) + Send + Sync>,
     ) -> Self {
-        let algorithm: Box<dyn Algorithm> = match algorithm {
-            CongestionAlgorithm::Bbr => Box::new(bbr::Bbr::new()),
-            CongestionAlgorithm::NewReno => Box::new(NewReno::new()),
-        };
-
         let now = Instant::now();
         CongestionController {
-            algorithm,
+            algorithm: algorithm.create(),
+            kind: algorithm,
             rtt: ArcRtt::new(),
             loss_timer: LossDetectionTimer::default(),
             max_ack_delay,
@@ -89,17 +294,39 @@ impl CongestionController {
                 AckRecord::new(Epoch::Handshake),
                 AckRecord::new(Epoch::Data),
             ],
+            ack_eagerness: AckEagerness::default(),
             pacer: Pacer::new(INITIAL_RTT, INITIAL_CWND, MSS, now, None),
             last_sent_time: now,
+            idle_restarted: false,
             send_waker: None,
             loss,
             retire,
             has_handshake_keys: false,
             is_handshake_done: false,
+            role: Role::Client,
+            handshake_acked: false,
+            amplification_limited: false,
+            probes: [0; Epoch::count()],
+            pto_in_a_row: 0,
+            max_pto_count: None,
+            exhausted_waker: None,
+            ecn: EcnValidator::default(),
+            first_rtt_sample: None,
+            acked_sent_times: VecDeque::new(),
+            packets_sent: 0,
+            bytes_sent: 0,
+            packets_lost: 0,
+            acks_sent: 0,
+            loss_detection: LossDetectionConfig::default(),
+            packet_threshold: LossDetectionConfig::default().packet_threshold,
+            declared_lost: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            spurious_losses: 0,
+            discarded: [false; Epoch::count()],
         }
     }
 
     // A.5. On Sending a Packet
+    #[allow(clippy::too_many_arguments)]
     pub fn on_packet_sent(
         &mut self,
         pn: u64,
@@ -107,9 +334,20 @@ impl CongestionController {
         ack_eliciting: bool,
         in_flight: bool,
         sent_bytes: usize,
+        ecn: EcnCodepoint,
+        class: PacketClass,
         now: Instant,
     ) {
+        if self.discarded[space] {
+            return;
+        }
         let mut sent = SentPkt::new(pn, ack_eliciting, in_flight, sent_bytes, now);
+        sent.ecn = ecn;
+        sent.class = class;
+        if ack_eliciting {
+            self.probes[space] = self.probes[space].saturating_sub(1);
+        }
+        self.ecn.on_sent(space, ecn);
         if in_flight {
             if ack_eliciting {
                 self.time_of_last_ack_eliciting_packet[space] = Some(now);
@@ -123,11 +361,18 @@ impl CongestionController {
             assert!(pn > last_pn.pn);
         }
         self.sent_packets[space].push_back(sent);
+        self.packets_sent += 1;
+        self.bytes_sent += sent_bytes as u64;
         self.pacer.on_sent(sent_bytes as u64);
+        self.last_sent_time = now;
+        self.idle_restarted = false;
     }
 
     // A.6. On Receiving a Datagram
     pub fn on_datagram_rcvd(&mut self, now: Instant) {
+        if !std::mem::take(&mut self.amplification_limited) {
+            return;
+        }
         // If this datagram unblocks the server, arm the PTO timer to avoid deadlock.
         self.set_loss_timer();
         if self.loss_timer.is_timeout(now) {
@@ -138,35 +383,60 @@ impl CongestionController {
 
     // A.7. On Receiving an Acknowledgment
     pub fn on_ack_rcvd(&mut self, space: Epoch, ack_frame: &AckFrame, now: Instant) {
+        if self.discarded[space] {
+            return;
+        }
         let largest_acked: u64 = ack_frame.largest.into();
 
         self.largest_acked_packet[space] =
             Some(largest_acked.max(self.largest_acked_packet[space].unwrap_or(0)));
 
-        let (newly_acked_packets, latest_rtt) = self.get_newly_acked_packets(space, ack_frame);
+        let (newly_acked_packets, latest_rtt) = self.get_newly_acked_packets(space, ack_frame, now);
         if newly_acked_packets.is_empty() {
             return;
         }
 
-        let ack_delay = Duration::from_millis(ack_frame.delay.into());
+        self.pto_in_a_row = 0;
+        if space == Epoch::Handshake {
+            self.handshake_acked = true;
+        }
+
+        // Initial包的确认不会被对方延迟，忽略其ack_delay
+        let ack_delay = match space {
+            Epoch::Initial => Duration::ZERO,
+            _ => self.rtt.decode_ack_delay(ack_frame.delay.into_inner()),
+        };
         if let Some(latest_rtt) = latest_rtt {
-            self.rtt.update(latest_rtt, ack_delay);
+            self.rtt.update(latest_rtt, ack_delay, now);
+            self.first_rtt_sample.get_or_insert(now);
+        }
+        for acked in &newly_acked_packets {
+            let index = self
+                .acked_sent_times
+                .partition_point(|&time_sent| time_sent <= acked.time_sent);
+            self.acked_sent_times.insert(index, acked.time_sent);
         }
 
         // Process ECN information if present.
-        if let Some(ecn) = ack_frame.ecn {
-            self.process_ecn(space, ecn)
-        }
+        self.process_ecn(space, ack_frame.ecn, &newly_acked_packets, now);
 
         let lost_packets = self.remove_loss_packets(space, now);
         if !lost_packets.is_empty() {
-            self.on_packets_lost(lost_packets, space);
+            self.on_packets_lost(lost_packets, space, now);
+        }
+        // 只有计入在途的包，才由拥塞算法核减在途字节数
+        let newly_acked_packets = newly_acked_packets
+            .into_iter()
+            .filter(|acked| acked.in_flight)
+            .collect::<VecDeque<_>>();
+        if !newly_acked_packets.is_empty() {
+            self.algorithm.on_ack(newly_acked_packets, now);
         }
-        self.algorithm.on_ack(newly_acked_packets, now);
 
-        if self.server_completed_address_validation() {
+        if self.peer_completed_address_validation() {
             self.pto_count = 0;
         }
+        self.prune_acked_sent_times();
         self.set_loss_timer();
     }
 
@@ -174,45 +444,280 @@ impl CongestionController {
         &mut self,
         space: Epoch,
         ack_frame: &AckFrame,
+        now: Instant,
     ) -> (VecDeque<AckedPkt>, Option<Duration>) {
         let mut newly_acked_packets: VecDeque<AckedPkt> = VecDeque::new();
-        let mut newly_acked_pns = Vec::new();
         let largest_acked: u64 = ack_frame.largest.into();
         let mut latest_rtt = None;
         for range in ack_frame.iter() {
             for pn in range {
-                let acked: Option<AckedPkt> = self.sent_packets[space]
-                    .binary_search_by_key(&pn, |p| p.pn)
-                    .ok()
-                    // 检测ack的包，标记为 is_acked,不能直接remove
-                    .map(|idx| {
-                        self.ack_records[space].ack(pn, &self.retire);
-                        self.sent_packets[space][idx].is_acked = true;
-                        self.sent_packets[space][idx].clone().into()
-                    });
-                if let Some(ack) = acked {
-                    // largest is newly ackd, update latest_rtt
-                    if pn == largest_acked {
-                        latest_rtt = Some(ack.rtt);
-                    }
-                    newly_acked_packets.push_back(ack);
-                    newly_acked_pns.push(pn);
+                let Ok(idx) = self.sent_packets[space].binary_search_by_key(&pn, |p| p.pn) else {
+                    self.on_maybe_spurious_loss(space, pn);
+                    continue;
+                };
+                // 重复确认的包已经计算过，不能再次交给拥塞算法
+                if self.sent_packets[space][idx].is_acked {
+                    continue;
                 }
+                // 检测ack的包，标记为 is_acked,不能直接remove
+                self.ack_records[space].ack(pn, &self.retire);
+                self.sent_packets[space][idx].is_acked = true;
+                let mut acked: AckedPkt = self.sent_packets[space][idx].clone().into();
+                acked.rtt = now.saturating_duration_since(acked.time_sent);
+                // largest is newly ackd, update latest_rtt
+                if pn == largest_acked {
+                    latest_rtt = Some(acked.rtt);
+                }
+                newly_acked_packets.push_back(acked);
             }
         }
         self.slide_sent_packets(space);
         (newly_acked_packets, latest_rtt)
     }
 
-    // A.8. Setting the Loss Detection Timer
-    fn on_packets_lost(&mut self, packets: Vec<SentPkt>, epoch: Epoch) {
-        let now = Instant::now();
+    // 被判定为丢失的包又被确认了，说明只是乱序，调高包序阈值以容忍这种程度的乱序；
+    // 其中的帧已经报告过丢失，不再重复报告，只是其携带的ACK帧可以认为已被收到
+    fn on_maybe_spurious_loss(&mut self, space: Epoch, pn: u64) {
+        let declared_lost = &mut self.declared_lost[space];
+        let Ok(idx) = declared_lost.binary_search_by_key(&pn, |&(lost, _)| lost) else {
+            return;
+        };
+        let (_, reordering) = declared_lost.remove(idx).unwrap();
+        self.spurious_losses += 1;
+        self.ack_records[space].ack(pn, &self.retire);
+        if let Some(max_packet_threshold) = self.loss_detection.max_packet_threshold {
+            let raised = (reordering + 1).min(max_packet_threshold);
+            if raised > self.packet_threshold {
+                log::debug!(
+                    "spurious loss of packet {pn}, raise the packet threshold from {} to {raised}",
+                    self.packet_threshold
+                );
+                self.packet_threshold = raised;
+            }
+        }
+    }
+
+    /// Set how the packets are declared lost, it discards the packet threshold raised by
+    /// the spurious losses before.
+    pub fn set_loss_detection(&mut self, config: LossDetectionConfig) {
+        let config = LossDetectionConfig {
+            packet_threshold: config.packet_threshold.max(1),
+            time_threshold: config.time_threshold.max(1.0),
+            ..config
+        };
+        self.loss_detection = config;
+        self.packet_threshold = config.packet_threshold;
+    }
+
+    // B.8. On Packets Lost
+    fn on_packets_lost(&mut self, packets: Vec<SentPkt>, epoch: Epoch, now: Instant) {
+        let persistent_congestion = self.in_persistent_congestion(&packets);
+        self.packets_lost += packets.len() as u64;
+        self.declare_lost(packets, epoch, now);
+        if persistent_congestion {
+            log::debug!(
+                "persistent congestion, collapse the congestion window to the minimum window"
+            );
+            self.algorithm.on_persistent_congestion(now);
+        }
+    }
+
+    fn declare_lost(&mut self, packets: Vec<SentPkt>, epoch: Epoch, now: Instant) {
         for lost in packets {
-            self.algorithm.on_congestion_event(&lost, now);
+            // 探测包的丢失不代表拥塞，比如超过了路径MTU，只是不再在途
+            if lost.in_flight && lost.class.is_probe() {
+                self.algorithm.on_probe_lost(&lost);
+            } else if lost.in_flight {
+                self.algorithm.on_congestion_event(&lost, now);
+            }
+            self.ecn.on_lost(lost.ecn);
             (self.loss)(epoch, lost.pn);
         }
     }
 
+    // 7.6.1. Duration
+    fn persistent_congestion_duration(&self) -> Duration {
+        (self.rtt.smoothed_rtt() + (self.rtt.rttvar() * 4).max(K_GRANULARITY) + self.max_ack_delay)
+            * K_PERSISTENT_CONGESTION_THRESHOLD
+    }
+
+    // 7.6.2. Establishing Persistent Congestion
+    // 两个丢失的ack-eliciting包，发送时间相隔超过持续拥塞时长，且其间发送的包无一被确认；
+    // 只考虑首个RTT样本之后发送的包，探测包的丢失不算
+    fn in_persistent_congestion(&self, lost: &[SentPkt]) -> bool {
+        let Some(first_rtt_sample) = self.first_rtt_sample else {
+            return false;
+        };
+        let mut lost_sent_times = lost
+            .iter()
+            .filter(|lost| {
+                lost.ack_eliciting && !lost.class.is_probe() && lost.time_sent > first_rtt_sample
+            })
+            .map(|lost| lost.time_sent)
+            .collect::<Vec<_>>();
+        lost_sent_times.sort_unstable();
+
+        let duration = self.persistent_congestion_duration();
+        let mut period_start = match lost_sent_times.first() {
+            Some(&time_sent) => time_sent,
+            None => return false,
+        };
+        for pair in lost_sent_times.windows(2) {
+            let (prev, next) = (pair[0], pair[1]);
+            // 其间有包被确认，丢包期从下一个丢失的包重新开始
+            let acked_between = self.acked_sent_times.partition_point(|&t| t <= prev)
+                < self.acked_sent_times.partition_point(|&t| t < next);
+            if acked_between {
+                period_start = next;
+            } else if next - period_start > duration {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn prune_acked_sent_times(&mut self) {
+        let earliest_unacked = self
+            .sent_packets
+            .iter()
+            .flatten()
+            .filter(|sent| !sent.is_acked)
+            .map(|sent| sent.time_sent)
+            .min();
+        match earliest_unacked {
+            Some(earliest_unacked) => {
+                let stale = self
+                    .acked_sent_times
+                    .partition_point(|&time_sent| time_sent < earliest_unacked);
+                self.acked_sent_times.drain(..stale);
+            }
+            None => self.acked_sent_times.clear(),
+        }
+    }
+
+    /// Switch to another congestion control algorithm, the packets sent under the
+    /// old algorithm are acknowledged or declared lost to the new one.
+    ///
+    /// The new congestion window starts from the smaller of the old window and the
+    /// initial window of the new algorithm, so the switch never causes a burst.
+    pub fn switch_algorithm(&mut self, algorithm: CongestionAlgorithm, now: Instant) {
+        if algorithm == self.kind {
+            return;
+        }
+        let cwnd = self.algorithm.cwnd();
+        let pacing_rate = self
+            .algorithm
+            .pacing_rate()
+            .unwrap_or_else(|| (N * cwnd as f64 / self.rtt.smoothed_rtt().as_secs_f64()) as u64);
+        let handover = Handover {
+            cwnd,
+            bytes_in_flight: self.bytes_in_flight(),
+            pacing_rate,
+            min_rtt: self.rtt.min_rtt(),
+        };
+
+        let mut new_algorithm = algorithm.create();
+        new_algorithm.inherit(&handover, now);
+        log::debug!(
+            "switch congestion algorithm from {:?} to {:?}, {:?}",
+            self.kind,
+            algorithm,
+            handover
+        );
+        self.algorithm = new_algorithm;
+        self.kind = algorithm;
+    }
+
+    pub fn metrics(&self) -> PathMetrics {
+        let smoothed_rtt = self.rtt.smoothed_rtt();
+        let cwnd = self.algorithm.cwnd();
+        PathMetrics {
+            algorithm: self.kind,
+            cwnd,
+            bytes_in_flight: self.bytes_in_flight(),
+            ssthresh: self.algorithm.ssthresh(),
+            pacing_rate: self.pacer.rate(
+                smoothed_rtt,
+                cwnd,
+                self.algorithm.pacing_rate(),
+                self.algorithm.in_slow_start(),
+            ),
+            smoothed_rtt,
+            rttvar: self.rtt.rttvar(),
+            min_rtt: self.rtt.min_rtt(),
+            latest_rtt: self.rtt.latest_rtt(),
+            pto_count: self.pto_count,
+            packets_sent: self.packets_sent,
+            bytes_sent: self.bytes_sent,
+            packets_lost: self.packets_lost,
+            spurious_losses: self.spurious_losses,
+            packet_threshold: self.packet_threshold,
+            acks_sent: self.acks_sent,
+            ack_delay_violations: self.rtt.ack_delay_violations(),
+            peer_violates_max_ack_delay: self.rtt.peer_violates_max_ack_delay(),
+        }
+    }
+
+    /// The bytes of the in-flight packets that are neither acknowledged nor lost yet.
+    pub fn bytes_in_flight(&self) -> u64 {
+        self.sent_packets
+            .iter()
+            .flatten()
+            .filter(|sent| sent.in_flight && !sent.is_acked)
+            .map(|sent| sent.size as u64)
+            .sum()
+    }
+
+    /// Declare all the unacknowledged packets in the space lost at once, so that their
+    /// frames are sent again, and restart the probe timeouts, such as after the path
+    /// starts to send smaller datagrams.
+    pub fn requeue_unacked(&mut self, space: Epoch, now: Instant) {
+        let (lost, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.sent_packets[space])
+            .into_iter()
+            .partition(|sent| !sent.is_acked);
+        self.sent_packets[space] = kept.into();
+        // 主动重发，并非拥塞，不判定持续拥塞
+        self.declare_lost(lost, space, now);
+
+        self.time_of_last_ack_eliciting_packet[space] = None;
+        self.pto_count = 0;
+        self.pto_in_a_row = 0;
+        self.set_loss_timer();
+    }
+
+    // A.11. Upon Dropping Initial or Handshake Keys
+    /// Stop tracking the packets of the space whose keys are discarded: they leave the
+    /// bytes in flight, and are never declared lost nor probed. The packets sent in the
+    /// space later on are not tracked either.
+    pub fn discard_space(&mut self, space: Epoch) {
+        debug_assert_ne!(space, Epoch::Data);
+        if self.discarded[space] {
+            return;
+        }
+        self.discarded[space] = true;
+        // 丢弃Initial密钥之时，必然已拿到了握手密钥
+        if space == Epoch::Initial {
+            self.has_handshake_keys = true;
+        }
+        let discarded = std::mem::take(&mut self.sent_packets[space]);
+        let bytes = discarded
+            .iter()
+            .filter(|sent| sent.in_flight && !sent.is_acked)
+            .map(|sent| sent.size as u64)
+            .sum();
+        if bytes > 0 {
+            self.algorithm.on_discarded(bytes);
+        }
+
+        self.time_of_last_ack_eliciting_packet[space] = None;
+        self.loss_time[space] = None;
+        self.probes[space] = 0;
+        self.declared_lost[space].clear();
+        self.pto_count = 0;
+        self.set_loss_timer();
+    }
+
+    // A.8. Setting the Loss Detection Timer
     fn set_loss_timer(&mut self) {
         let (earliest_loss_time, _) = self.get_loss_time_and_space();
         if let Some(earliest_loss_time) = earliest_loss_time {
@@ -220,13 +725,21 @@ impl CongestionController {
             return;
         }
 
-        if self.no_ack_eliciting_in_flight() && self.server_completed_address_validation() {
+        // 受抗放大限制的服务端，即便PTO到期也发不出探测包，徒增pto_count
+        if self.amplification_limited {
+            self.loss_timer.cancel();
+            return;
+        }
+
+        if self.no_ack_eliciting_in_flight() && self.peer_completed_address_validation() {
             self.loss_timer.cancel();
             return;
         }
 
-        if let Some(t) = self.get_pto_timeout() {
-            self.loss_timer.update(t);
+        // 没有可以设定的PTO，比如握手确认之前只有数据空间的包在途，也要取消，否则超时会一再触发
+        match self.get_pto_time_and_space(Instant::now()) {
+            (Some(pto_timeout), _) => self.loss_timer.update(pto_timeout),
+            (None, _) => self.loss_timer.cancel(),
         }
     }
 
@@ -237,32 +750,119 @@ impl CongestionController {
         if earliest_loss_time.is_some() {
             let loss_packet = self.remove_loss_packets(space, now);
             assert!(!loss_packet.is_empty());
-            self.on_packets_lost(loss_packet, space);
+            self.on_packets_lost(loss_packet, space, now);
             self.set_loss_timer();
             return;
         }
 
         // probe timeout
-        let _ = if self.no_ack_eliciting_in_flight() {
-            assert!(!self.server_completed_address_validation());
+        if self.no_ack_eliciting_in_flight() {
+            assert!(!self.peer_completed_address_validation());
             // Client sends an anti-deadlock packet: Initial is padded
             // to earn more anti-amplification credit,
             // a Handshake packet proves address ownership.
-            if self.has_handshake_keys {
-                Some(Epoch::Handshake)
-            } else {
-                Some(Epoch::Initial)
+            // 尚未得知是否拿到了握手密钥时，两个空间都请求探测，没有密钥的空间发不出来
+            if !self.has_handshake_keys {
+                self.probes[Epoch::Initial] = 1;
             }
-        } else if self.get_pto_timeout().is_some() {
-            Some(Epoch::Data)
+            self.probes[Epoch::Handshake] = 1;
         } else {
-            None
-        };
-        self.pto_count += 1;
+            let (_, space) = self.get_pto_time_and_space(now);
+            self.probes[space] = 2;
+            self.requeue_oldest(space);
+        }
+        if let Some(waker) = self.send_waker.take() {
+            waker.wake();
+        }
+        self.pto_count = self.pto_count.saturating_add(1);
+        self.pto_in_a_row += 1;
+        if self.is_pto_exhausted() {
+            if let Some(waker) = self.exhausted_waker.take() {
+                waker.wake();
+            }
+        }
 
         self.set_loss_timer();
     }
 
+    // 可以发送的数据量，None表示暂时不能发送
+    fn send_quota(&mut self, now: Instant) -> Option<usize> {
+        if self.loss_timer.is_timeout(now) {
+            self.on_loss_timeout(now);
+        }
+        self.restart_after_idle(now);
+
+        let srtt = self.rtt.smoothed_rtt();
+        let cwnd = self.algorithm.cwnd();
+        let mtu = MSS;
+        let rate = self.algorithm.pacing_rate();
+        let in_slow_start = self.algorithm.in_slow_start();
+        let tokens = self
+            .pacer
+            .schedule(srtt, cwnd, mtu, now, rate, in_slow_start);
+        // pacer攒够一块额度才放行，一次发出一批；PTO的探测包不受拥塞窗口和pacing的限制
+        let probes = self.probes.iter().copied().max().unwrap_or(0) as usize;
+        if self.pacer.has_permit() || probes > 0 {
+            return Some(tokens.max(probes * mtu));
+        }
+
+        let ack_delay = self.ack_delay();
+        let mut need_ack = false;
+        for &epoch in Epoch::iter() {
+            if self.ack_records[epoch].need_ack(ack_delay).is_some() {
+                need_ack = true;
+                break;
+            }
+        }
+        // 1. 有 ack 要发送, 且距离上次发送时间大于 max ack dely
+        // 2. 距离上次发送时间大于 max sent delay
+        let elapsed = now.saturating_duration_since(self.last_sent_time);
+        if (need_ack && elapsed >= ack_delay) || elapsed >= MAX_SENT_DELAY {
+            return Some(tokens);
+        }
+        None
+    }
+
+    // 见CongestionControl::max_wait
+    fn max_wait(&self, now: Instant) -> Duration {
+        let pacer_wait = self.pacer.time_until_credit(
+            self.rtt.smoothed_rtt(),
+            self.algorithm.cwnd(),
+            MSS,
+            now,
+            self.algorithm.pacing_rate(),
+            self.algorithm.in_slow_start(),
+        );
+        // 已有额度，或者有探测包要发的，发送任务不会因pacer而等待
+        let pacer_wait = if pacer_wait.is_zero() || self.probes.iter().any(|&p| p > 0) {
+            Duration::MAX
+        } else {
+            pacer_wait
+        };
+        let loss_wait = self
+            .loss_timer
+            .timeout
+            .map_or(Duration::MAX, |t| t.saturating_duration_since(now));
+        pacer_wait.min(loss_wait).max(K_GRANULARITY)
+    }
+
+    // 超过一个RTO没有发包，空闲之前的拥塞窗口可能已不符合路径的现状，恢复发送时按配置重启
+    fn restart_after_idle(&mut self, now: Instant) {
+        if self.pacer.config().idle_restart != IdleRestart::Restart {
+            return;
+        }
+        let rto = self.rtt.smoothed_rtt() + std::cmp::max(K_GRANULARITY, self.rtt.rttvar() * 4);
+        if !self.idle_restarted && now.saturating_duration_since(self.last_sent_time) > rto {
+            self.algorithm.on_idle_restart(now);
+            self.idle_restarted = true;
+        }
+    }
+
+    fn is_pto_exhausted(&self) -> bool {
+        self.max_pto_count
+            .is_some_and(|max_pto_count| self.pto_in_a_row >= max_pto_count)
+    }
+
     fn get_loss_time_and_space(&self) -> (Option<Instant>, Epoch) {
         let mut time = self.loss_time[Epoch::Initial];
         let mut space = Epoch::Initial;
@@ -277,6 +877,16 @@ impl CongestionController {
         (time, space)
     }
 
+    // 立即确认时，不再延迟发送ack；对方通过ACK_FREQUENCY帧要求了最大延迟的，以其为准
+    fn ack_delay(&self) -> Duration {
+        match (self.ack_eagerness, self.ack_records[Epoch::Data].frequency) {
+            (AckEagerness::Immediate, _) => Duration::ZERO,
+            (AckEagerness::Lazy, _) => self.max_ack_delay,
+            (_, Some(frequency)) => frequency.max_ack_delay,
+            _ => self.max_ack_delay,
+        }
+    }
+
     fn get_pto_time(&self, epoch: Epoch) -> Duration {
         let smoothed_rtt = self.rtt.smoothed_rtt();
         let rttvar = self.rtt.rttvar();
@@ -285,34 +895,69 @@ impl CongestionController {
         if epoch == Epoch::Data && self.is_handshake_done {
             duration += self.max_ack_delay
         }
-        duration * 2_u32.pow(self.pto_count)
+        self.backoff(duration)
+    }
+
+    // 指数退避，每次超时翻倍，但不超过设定的上限；连续超时很多次也不会溢出
+    fn backoff(&self, duration: Duration) -> Duration {
+        let backoff = duration
+            .checked_mul(2_u32.saturating_pow(self.pto_count))
+            .unwrap_or(Duration::MAX);
+        match self.loss_detection.max_pto {
+            Some(max_pto) => backoff.min(max_pto.max(duration)),
+            None => backoff,
+        }
     }
 
-    fn get_pto_timeout(&self) -> Option<Instant> {
-        let mut duration = self.get_pto_time(Epoch::Initial);
+    // A.8. GetPtoTimeAndSpace
+    fn get_pto_time_and_space(&self, now: Instant) -> (Option<Instant>, Epoch) {
         if self.no_ack_eliciting_in_flight() {
-            return Some(Instant::now() + duration);
+            let space = if self.has_handshake_keys {
+                Epoch::Handshake
+            } else {
+                Epoch::Initial
+            };
+            return (now.checked_add(self.get_pto_time(space)), space);
         }
 
-        let mut pto_time = None;
+        let mut pto_timeout = None;
+        let mut pto_space = Epoch::Initial;
         for &space in Epoch::iter() {
-            if self.time_of_last_ack_eliciting_packet[space].is_none() {
+            if !self.ack_eliciting_in_flight(space) {
                 continue;
             }
-            if space == Epoch::Data {
-                // An endpoint MUST NOT set its PTO timer for the Application Data
-                // packet number space until the handshake is confirmed
-                if !self.is_handshake_done {
-                    return pto_time;
-                }
-                duration += self.max_ack_delay * 2_u32.pow(self.pto_count);
+            // An endpoint MUST NOT set its PTO timer for the Application Data
+            // packet number space until the handshake is confirmed
+            if space == Epoch::Data && !self.is_handshake_done {
+                break;
             }
-            let new_time = self.time_of_last_ack_eliciting_packet[space].unwrap() + duration;
-            if pto_time.is_none() || new_time < pto_time.unwrap() {
-                pto_time = Some(new_time);
+            let Some(time_sent) = self.time_of_last_ack_eliciting_packet[space] else {
+                continue;
+            };
+            // 退避到溢出的时刻，视同永不超时
+            let Some(t) = time_sent.checked_add(self.get_pto_time(space)) else {
+                continue;
+            };
+            if pto_timeout.is_none() || Some(t) < pto_timeout {
+                pto_timeout = Some(t);
+                pto_space = space;
             }
         }
-        pto_time
+        (pto_timeout, pto_space)
+    }
+
+    // 探测包优先重传最早的未确认的数据，其中的帧当作丢失重新排队，每个包只重传一次；
+    // 该包仍然在途，并非拥塞，之后被确认也无妨
+    fn requeue_oldest(&mut self, space: Epoch) {
+        let Some(oldest) = self.sent_packets[space]
+            .iter_mut()
+            .find(|sent| sent.ack_eliciting && !sent.is_acked && !sent.requeued)
+        else {
+            return;
+        };
+        oldest.requeued = true;
+        let pn = oldest.pn;
+        (self.loss)(space, pn);
     }
 
     fn remove_loss_packets(&mut self, space: Epoch, now: Instant) -> Vec<SentPkt> {
@@ -320,11 +965,10 @@ impl CongestionController {
         let largest_acked = self.largest_acked_packet[space].unwrap();
         self.loss_time[space] = None;
 
-        let loss_delay = self.rtt.loss_delay();
+        let loss_delay = self.rtt.loss_delay(self.loss_detection.time_threshold);
         let lost_send_time = now.checked_sub(loss_delay).unwrap();
 
         let mut loss_packets = Vec::new();
-        let mut loss_pn = Vec::new();
 
         let mut largest_ack_index = 0;
         while largest_ack_index != self.sent_packets[space].len()
@@ -341,12 +985,12 @@ impl CongestionController {
                 continue;
             }
             // 距离 largest ack index 相差超过 threshold 即为丢包
+            let reordering = (largest_ack_index - i) as u64;
             if self.sent_packets[space][i].time_sent <= lost_send_time
-                || largest_ack_index - i >= K_PACKET_THRESHOLD
+                || reordering >= self.packet_threshold
             {
                 if let Some(loss) = self.sent_packets[space].remove(i) {
-                    let pn = loss.pn;
-                    loss_pn.push(pn);
+                    self.record_declared_lost(space, loss.pn, reordering);
                     loss_packets.push(loss);
                     largest_ack_index -= 1;
                 }
@@ -364,6 +1008,15 @@ impl CongestionController {
         loss_packets
     }
 
+    fn record_declared_lost(&mut self, space: Epoch, pn: u64, reordering: u64) {
+        let declared_lost = &mut self.declared_lost[space];
+        let idx = declared_lost.partition_point(|&(lost, _)| lost < pn);
+        declared_lost.insert(idx, (pn, reordering));
+        if declared_lost.len() > MAX_DECLARED_LOST {
+            declared_lost.pop_front();
+        }
+    }
+
     fn slide_sent_packets(&mut self, space: Epoch) {
         while let Some(sent) = self.sent_packets[space].front() {
             if !sent.is_acked {
@@ -373,21 +1026,48 @@ impl CongestionController {
         }
     }
 
+    fn ack_eliciting_in_flight(&self, space: Epoch) -> bool {
+        self.sent_packets[space]
+            .iter()
+            .any(|sent| sent.ack_eliciting && sent.in_flight && !sent.is_acked)
+    }
+
     fn no_ack_eliciting_in_flight(&self) -> bool {
-        for space in Epoch::iter() {
-            if self.time_of_last_ack_eliciting_packet[*space].is_some() {
-                return false;
-            }
-        }
-        true
+        Epoch::iter().all(|&space| !self.ack_eliciting_in_flight(space))
     }
 
-    fn server_completed_address_validation(&mut self) -> bool {
-        self.has_handshake_keys || self.is_handshake_done
+    // A.8. PeerCompletedAddressValidation
+    // 客户端隐式地验证了服务端的地址；服务端收到受保护的包即完成了对客户端地址的验证
+    fn peer_completed_address_validation(&self) -> bool {
+        self.role == Role::Server || self.handshake_acked || self.is_handshake_done
     }
 
-    fn process_ecn(&mut self, _: Epoch, _: EcnCounts) {
-        todo!()
+    // 对方报告的ECN计数通过验证后，新增的CE标记如同丢包，触发一次拥塞事件；
+    // 验证失败只在该路径上停用ECN，不是连接错误
+    fn process_ecn(
+        &mut self,
+        space: Epoch,
+        ecn: Option<EcnCounts>,
+        newly_acked_packets: &VecDeque<AckedPkt>,
+        now: Instant,
+    ) {
+        let newly_acked_ect0 = newly_acked_packets
+            .iter()
+            .filter(|acked| acked.ecn == EcnCodepoint::Ect0)
+            .count() as u64;
+        let Some(newly_ce) = self.ecn.on_ack(space, ecn, newly_acked_ect0) else {
+            return;
+        };
+        if newly_ce > 0 {
+            // 以最新确认的包的发送时间判断是否已在恢复期内
+            if let Some(sent_time) = newly_acked_packets
+                .iter()
+                .map(|acked| acked.time_sent)
+                .max()
+            {
+                self.algorithm.on_ecn_ce(sent_time, now);
+            }
+        }
     }
 }
 
@@ -409,107 +1089,294 @@ impl ArcCC {
             retire,
         ))))
     }
-}
 
-impl super::CongestionControl for ArcCC {
-    fn do_tick(&self) {
+    /// Switch the congestion control algorithm, see [`CongestionController::switch_algorithm`].
+    ///
+    /// The controller is swapped under the lock, so the sending and the acknowledgment
+    /// never observe a half-switched state.
+    pub fn switch_algorithm(&self, algorithm: CongestionAlgorithm) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
-        if guard.loss_timer.is_timeout(now) {
-            guard.on_loss_timeout(now);
-        }
+        guard.switch_algorithm(algorithm, Instant::now());
         if let Some(waker) = guard.send_waker.take() {
             waker.wake();
         }
     }
 
-    fn poll_send(&self, cx: &mut Context<'_>) -> Poll<usize> {
-        let mut guard = self.0.lock().unwrap();
-        guard.send_waker = Some(cx.waker().clone());
-        let now = Instant::now();
-        if guard.loss_timer.is_timeout(now) {
-            guard.on_loss_timeout(now);
-        }
-
-        let srtt = guard.rtt.smoothed_rtt();
-        let cwnd = guard.algorithm.cwnd();
-        let mtu = MSS;
-        let rate = guard.algorithm.pacing_rate();
-        let tokens = guard.pacer.schedule(srtt, cwnd, mtu, now, rate);
-        if tokens >= mtu {
-            return Poll::Ready(tokens);
-        }
+    /// The congestion control algorithm in use.
+    pub fn algorithm(&self) -> CongestionAlgorithm {
+        self.0.lock().unwrap().kind
+    }
 
-        let mut need_ack = false;
-        for &epoch in Epoch::iter() {
-            if guard.ack_records[epoch]
-                .need_ack(guard.max_ack_delay)
-                .is_some()
-            {
-                need_ack = true;
-                break;
+    /// Give up the path after `max_pto_count` probe timeouts in a row without any
+    /// acknowledgment, see [`ArcCC::poll_pto_exhausted`]. None never gives up, which is
+    /// the default.
+    pub fn set_max_pto_count(&self, max_pto_count: Option<u32>) {
+        let mut guard = self.0.lock().unwrap();
+        guard.max_pto_count = max_pto_count;
+        if guard.is_pto_exhausted() {
+            if let Some(waker) = guard.exhausted_waker.take() {
+                waker.wake();
             }
         }
-        // 1. 有 ack 要发送, 且距离上次发送时间大于 max ack dely
-        // 2. 距离上次发送时间大于 max sent delay
-        let elapsed = now.saturating_duration_since(guard.last_sent_time);
-        if (need_ack && elapsed >= guard.max_ack_delay) || elapsed >= MAX_SENT_DELAY {
-            return Poll::Ready(tokens);
+    }
+
+    /// Poll whether the probe timeouts in a row have reached the limit set by
+    /// [`ArcCC::set_max_pto_count`], that is, the peer is likely unreachable on this path.
+    pub fn poll_pto_exhausted(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut guard = self.0.lock().unwrap();
+        if guard.is_pto_exhausted() {
+            return Poll::Ready(());
         }
+        guard.exhausted_waker = Some(cx.waker().clone());
         Poll::Pending
     }
 
-    fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)> {
-        let guard = self.0.lock().unwrap();
-        guard.ack_records[space].need_ack(guard.max_ack_delay)
+    /// Send the unacknowledged packets in the space again, see
+    /// [`CongestionController::requeue_unacked`].
+    pub fn requeue_unacked(&self, space: Epoch) {
+        let mut guard = self.0.lock().unwrap();
+        guard.requeue_unacked(space, Instant::now());
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
     }
 
-    fn on_pkt_sent(
-        &self,
-        epoch: Epoch,
-        pn: u64,
-        is_ack_eliciting: bool,
-        sent_bytes: usize,
-        in_flight: bool,
-        ack: Option<u64>,
-    ) {
+    /// Send an ack-eliciting packet in the application data space right away regardless of
+    /// the congestion window, a PING frame if there's nothing else to send, to elicit an
+    /// acknowledgment from the peer, such as for a liveness check.
+    ///
+    /// It's sent as a probe, the pending probes of the probe timeouts cover it.
+    pub fn request_probe(&self) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
-        guard.on_packet_sent(pn, epoch, is_ack_eliciting, in_flight, sent_bytes, now);
-
-        guard.last_sent_time = now;
-        if let Some(largest_acked) = ack {
-            guard.ack_records[epoch].sent_ack(pn, largest_acked);
+        guard.probes[Epoch::Data] = guard.probes[Epoch::Data].max(1);
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
         }
     }
 
-    fn on_ack(&self, space: Epoch, ack_frame: &AckFrame) {
+    /// Set how eagerly to acknowledge the received packets, it takes effect immediately.
+    pub fn set_ack_eagerness(&self, ack_eagerness: AckEagerness) {
         let mut guard = self.0.lock().unwrap();
-        let now = Instant::now();
-        guard.on_ack_rcvd(space, ack_frame, now);
+        guard.ack_eagerness = ack_eagerness;
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
     }
 
-    fn on_recv_pkt(&self, epoch: Epoch, pn: u64, is_ack_eliciting: bool) {
-        if !is_ack_eliciting {
-            return;
-        }
+    /// Acknowledge the packets in the application data space as the peer requested in
+    /// an ACK_FREQUENCY frame, it takes effect immediately.
+    ///
+    /// The frames received out of order should be filtered by the sequence number before.
+    pub fn set_ack_frequency(&self, frequency: AckFrequency) {
         let mut guard = self.0.lock().unwrap();
-        guard.ack_records[epoch].recv_pkt(pn);
+        guard.ack_records[Epoch::Data].frequency = Some(frequency);
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
     }
 
-    fn pto_time(&self, epoch: Epoch) -> Duration {
-        self.0.lock().unwrap().get_pto_time(epoch)
+    /// Decode the ack delay of the peer's ACK frames by its ack_delay_exponent, and cap it
+    /// by its max_ack_delay once the handshake is confirmed, see [`ArcRtt::set_peer_ack_delay`].
+    pub fn set_peer_ack_delay(&self, ack_delay_exponent: u8, max_ack_delay: Duration) {
+        let guard = self.0.lock().unwrap();
+        guard
+            .rtt
+            .set_peer_ack_delay(ack_delay_exponent, max_ack_delay);
     }
 
-    fn on_get_handshake_keys(&self) {
-        let mut gurad = self.0.lock().unwrap();
-        gurad.has_handshake_keys = true;
+    /// The peer asks for an acknowledgment immediately by an IMMEDIATE_ACK frame.
+    pub fn on_immediate_ack(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.ack_records[Epoch::Data].need_ack = true;
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
     }
 
-    fn on_handshake_done(&self) {
+    /// Set how the packets are declared lost on the path, see
+    /// [`CongestionController::set_loss_detection`].
+    pub fn set_loss_detection(&self, config: LossDetectionConfig) {
+        self.0.lock().unwrap().set_loss_detection(config);
+    }
+
+    /// Set the role of this endpoint, a client keeps probing before the server validates
+    /// its address even if nothing is in flight, lest the handshake deadlocks when the
+    /// server is blocked by the anti-amplification limit. It's a client by default.
+    pub fn set_role(&self, role: Role) {
+        let mut guard = self.0.lock().unwrap();
+        guard.role = role;
+        guard.set_loss_timer();
+    }
+
+    /// Called when the sending on the path is blocked by the anti-amplification limit, the
+    /// loss detection timer is cancelled until [`ArcCC::on_datagram_rcvd`].
+    pub fn on_amplification_limited(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.amplification_limited = true;
+        guard.set_loss_timer();
+    }
+
+    /// Called when a datagram is received on the path, if the path was blocked by the
+    /// anti-amplification limit, the loss detection timer is armed again, and the PTO is
+    /// executed at once if it would have expired while blocked.
+    pub fn on_datagram_rcvd(&self) {
+        self.0.lock().unwrap().on_datagram_rcvd(Instant::now());
+    }
+
+    /// Set how the packets are paced on the path, it takes effect immediately.
+    pub fn set_pacing(&self, config: PacingConfig) {
+        let mut guard = self.0.lock().unwrap();
+        let srtt = guard.rtt.smoothed_rtt();
+        let cwnd = guard.algorithm.cwnd();
+        guard.pacer.set_config(config, srtt, cwnd, MSS);
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl super::CongestionControl for ArcCC {
+    fn do_tick(&self) {
+        let mut guard = self.0.lock().unwrap();
+        // send_quota中会处理到期的丢包检测定时器
+        if guard.send_quota(Instant::now()).is_some() {
+            if let Some(waker) = guard.send_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn max_wait(&self) -> Duration {
+        self.0.lock().unwrap().max_wait(Instant::now())
+    }
+
+    fn poll_send(&self, cx: &mut Context<'_>) -> Poll<usize> {
+        let mut guard = self.0.lock().unwrap();
+        guard.send_waker = Some(cx.waker().clone());
+        match guard.send_quota(Instant::now()) {
+            Some(quota) => Poll::Ready(quota),
+            None => Poll::Pending,
+        }
+    }
+
+    fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)> {
+        let guard = self.0.lock().unwrap();
+        guard.ack_records[space].need_ack(guard.ack_delay())
+    }
+
+    fn need_probe(&self, space: Epoch) -> bool {
+        self.0.lock().unwrap().probes[space] > 0
+    }
+
+    fn discard_probes(&self) {
+        self.0.lock().unwrap().probes = [0; Epoch::count()];
+    }
+
+    fn on_pkt_sent(
+        &self,
+        epoch: Epoch,
+        pn: u64,
+        is_ack_eliciting: bool,
+        sent_bytes: usize,
+        in_flight: bool,
+        ack: Option<u64>,
+        ecn: EcnCodepoint,
+        class: PacketClass,
+    ) {
         let mut guard = self.0.lock().unwrap();
+        let now = Instant::now();
+        guard.on_packet_sent(
+            pn,
+            epoch,
+            is_ack_eliciting,
+            in_flight,
+            sent_bytes,
+            ecn,
+            class,
+            now,
+        );
+        if let Some(largest_acked) = ack {
+            guard.ack_records[epoch].sent_ack(pn, largest_acked);
+            guard.acks_sent += 1;
+        }
+        // 仍有一块额度，发送任务不必等待
+        if guard.pacer.has_permit() {
+            if let Some(waker) = guard.send_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn on_ack(&self, space: Epoch, ack_frame: &AckFrame) {
+        let mut guard = self.0.lock().unwrap();
+        let now = Instant::now();
+        guard.on_ack_rcvd(space, ack_frame, now);
+        // 确认可能扩大了拥塞窗口，或者判定了丢包需要重传
+        if let Some(waker) = guard.send_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn on_recv_pkt(&self, epoch: Epoch, pn: u64, is_ack_eliciting: bool) {
+        if !is_ack_eliciting {
+            return;
+        }
+        let mut guard = self.0.lock().unwrap();
+        let ack_eagerness = guard.ack_eagerness;
+        guard.ack_records[epoch].recv_pkt(pn, ack_eagerness);
+        // 立即确认，不必等到下次驱动
+        if ack_eagerness == AckEagerness::Immediate
+            || (epoch == Epoch::Data && guard.ack_records[epoch].need_ack)
+        {
+            if let Some(waker) = guard.send_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn pto_time(&self, epoch: Epoch) -> Duration {
+        self.0.lock().unwrap().get_pto_time(epoch)
+    }
+
+    fn smoothed_rtt(&self) -> Duration {
+        self.0.lock().unwrap().rtt.smoothed_rtt()
+    }
+
+    fn ecn_codepoint(&self) -> EcnCodepoint {
+        self.0.lock().unwrap().ecn.codepoint()
+    }
+
+    fn ecn_state(&self) -> EcnState {
+        self.0.lock().unwrap().ecn.state()
+    }
+
+    fn metrics(&self) -> PathMetrics {
+        self.0.lock().unwrap().metrics()
+    }
+
+    fn on_get_handshake_keys(&self) {
+        let mut gurad = self.0.lock().unwrap();
+        gurad.has_handshake_keys = true;
+    }
+
+    fn on_handshake_done(&self) {
+        let mut guard = self.0.lock().unwrap();
+        if guard.is_handshake_done {
+            return;
+        }
         guard.is_handshake_done = true;
         guard.rtt.on_handshake_done();
+        // 握手确认后，数据空间的包开始计入PTO
+        guard.set_loss_timer();
+    }
+
+    fn on_app_limited(&self) {
+        let mut guard = self.0.lock().unwrap();
+        guard.algorithm.on_app_limited(Instant::now());
+    }
+
+    fn on_pkt_space_discarded(&self, epoch: Epoch) {
+        self.0.lock().unwrap().discard_space(epoch);
     }
 }
 
@@ -519,6 +1386,10 @@ struct AckRecord {
     last_ack_sent: Option<(u64, u64)>,
     largest_recv_time: Option<(u64, Instant)>,
     rcvd_queue: VecDeque<u64>,
+    // 仅数据空间有，对方通过ACK_FREQUENCY帧要求的确认频率
+    frequency: Option<AckFrequency>,
+    // 上次发送ack之后收到的ack-eliciting包数
+    unacked_eliciting: u64,
 }
 
 impl AckRecord {
@@ -529,15 +1400,88 @@ impl AckRecord {
             last_ack_sent: None,
             largest_recv_time: None,
             rcvd_queue: VecDeque::new(),
+            frequency: None,
+            unacked_eliciting: 0,
         }
     }
 
-    fn recv_pkt(&mut self, pn: u64) {
-        if self.epoch == Epoch::Initial || self.epoch == Epoch::Handshake {
+    fn recv_pkt(&mut self, pn: u64, ack_eagerness: AckEagerness) {
+        match self.frequency {
+            Some(frequency) if self.epoch == Epoch::Data && ack_eagerness != AckEagerness::Lazy => {
+                self.recv_pkt_with_frequency(pn, ack_eagerness, frequency)
+            }
+            _ => self.recv_pkt_by_eagerness(pn, ack_eagerness),
+        }
+    }
+
+    fn recv_pkt_with_frequency(
+        &mut self,
+        pn: u64,
+        ack_eagerness: AckEagerness,
+        frequency: AckFrequency,
+    ) {
+        self.unacked_eliciting += 1;
+        if ack_eagerness == AckEagerness::Immediate
+            || self.unacked_eliciting > frequency.ack_eliciting_threshold
+        {
+            self.need_ack = true;
+        }
+        self.insert(pn);
+
+        if frequency.reordering_threshold > 0 {
+            if let (Some(missing), Some((largest, _))) =
+                (self.first_missing(), self.largest_recv_time)
+            {
+                if largest - missing >= frequency.reordering_threshold {
+                    self.need_ack = true;
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, pn: u64) {
+        match self.largest_recv_time {
+            Some((largest, _)) if pn < largest => {
+                let index = self.rcvd_queue.partition_point(|&x| x < pn);
+                if self.rcvd_queue.get(index) != Some(&pn) {
+                    self.rcvd_queue.insert(index, pn);
+                }
+            }
+            Some((largest, _)) if pn == largest => {}
+            _ => {
+                self.largest_recv_time = Some((pn, Instant::now()));
+                self.rcvd_queue.push_back(pn);
+            }
+        }
+    }
+
+    // 上次ack报告过的最大包号之后，第一个尚未收到的包号
+    fn first_missing(&self) -> Option<u64> {
+        let (largest, _) = self.largest_recv_time?;
+        let start = self
+            .last_ack_sent
+            .map_or(0, |(_, largest_acked)| largest_acked + 1);
+        let mut expect = start;
+        for &pn in self.rcvd_queue.iter().filter(|&&pn| pn >= start) {
+            if pn != expect {
+                break;
+            }
+            expect += 1;
+        }
+        (expect < largest).then_some(expect)
+    }
+
+    fn recv_pkt_by_eagerness(&mut self, pn: u64, ack_eagerness: AckEagerness) {
+        if self.epoch == Epoch::Initial
+            || self.epoch == Epoch::Handshake
+            || ack_eagerness == AckEagerness::Immediate
+        {
             self.need_ack = true;
         }
         if let Some((largest, _)) = self.largest_recv_time {
-            if pn < largest || pn - largest > 1 {
+            let is_relaxed = matches!(ack_eagerness, AckEagerness::Relaxed | AckEagerness::Lazy)
+                && self.epoch == Epoch::Data;
+            if !is_relaxed && (pn < largest || pn - largest > 1) {
                 self.need_ack = true;
             }
             if pn >= largest {
@@ -574,6 +1518,7 @@ impl AckRecord {
     fn sent_ack(&mut self, pn: u64, largest_acked: u64) {
         self.last_ack_sent = Some((pn, largest_acked));
         self.need_ack = false;
+        self.unacked_eliciting = 0;
     }
 
     fn ack(&mut self, ack: u64, retrie: &(dyn Fn(Epoch, u64) + Send + Sync)) {
@@ -591,6 +1536,33 @@ impl AckRecord {
     }
 }
 
+/// What a sent packet is for, which decides whether it speaks for the capacity of the path.
+///
+/// Each path has its own congestion controller, so the RTT sampled from a packet always
+/// belongs to the path it was sent on, whatever its class.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PacketClass {
+    /// Carrying the frames of the connection.
+    #[default]
+    Normal,
+    /// Probing a larger path MTU, its size is unrepresentative of the traffic.
+    MtuProbe,
+    /// Padded to validate a new path, before any traffic is on it.
+    PathProbe,
+    /// Padding warming up the congestion window of the path it's sent on.
+    Prewarm,
+}
+
+impl PacketClass {
+    /// Whether the packet is a probe, which is excluded from the bandwidth estimation and
+    /// the growth of the congestion window, and its loss is no sign of congestion.
+    ///
+    /// Its acknowledgment still samples the RTT.
+    pub fn is_probe(self) -> bool {
+        matches!(self, Self::MtuProbe | Self::PathProbe)
+    }
+}
+
 #[derive(Clone)]
 pub struct AckedPkt {
     pub pn: u64,
@@ -603,6 +1575,9 @@ pub struct AckedPkt {
     pub is_app_limited: bool,
     pub tx_in_flight: usize,
     pub lost: u64,
+    pub in_flight: bool,
+    pub ecn: EcnCodepoint,
+    pub class: PacketClass,
 }
 
 impl From<SentPkt> for AckedPkt {
@@ -619,6 +1594,9 @@ impl From<SentPkt> for AckedPkt {
             is_app_limited: sent.is_app_limited,
             tx_in_flight: sent.tx_in_flight,
             lost: sent.lost,
+            in_flight: sent.in_flight,
+            ecn: sent.ecn,
+            class: sent.class,
         }
     }
 }
@@ -636,7 +1614,13 @@ pub struct SentPkt {
     pub is_app_limited: bool,
     pub tx_in_flight: usize,
     pub lost: u64,
+    // The ECN codepoint the packet was sent with.
+    pub ecn: EcnCodepoint,
+    // What the packet is for, the probes don't speak for the capacity of the path.
+    pub class: PacketClass,
     pub is_acked: bool,
+    // Whether the frames are sent again by a probe packet.
+    pub requeued: bool,
 }
 
 impl Default for SentPkt {
@@ -653,7 +1637,10 @@ impl Default for SentPkt {
             is_app_limited: false,
             tx_in_flight: 0,
             lost: 0,
+            ecn: EcnCodepoint::NotEct,
+            class: PacketClass::Normal,
             is_acked: false,
+            requeued: false,
         }
     }
 }
@@ -672,7 +1659,10 @@ impl SentPkt {
             is_app_limited: false,
             tx_in_flight: 0,
             lost: 0,
+            ecn: EcnCodepoint::NotEct,
+            class: PacketClass::Normal,
             is_acked: false,
+            requeued: false,
         }
     }
 }
@@ -702,9 +1692,47 @@ pub trait Algorithm: Send {
 
     fn on_congestion_event(&mut self, lost: &SentPkt, now: Instant);
 
+    /// A probe is lost, see [`PacketClass::is_probe`], it's no longer in flight but no
+    /// congestion event.
+    fn on_probe_lost(&mut self, lost: &SentPkt) {
+        self.on_discarded(lost.size as u64);
+    }
+
+    /// The peer reported new ECN-CE marks, the largest newly acknowledged packet was
+    /// sent at `sent_time`.
+    fn on_ecn_ce(&mut self, sent_time: Instant, now: Instant);
+
+    /// Persistent congestion is established, after the losses are reported through
+    /// [`Algorithm::on_congestion_event`], see
+    /// [section 7.6](https://www.rfc-editor.org/rfc/rfc9002.html#name-persistent-congestion)
+    /// of RFC 9002.
+    fn on_persistent_congestion(&mut self, now: Instant);
+
     fn cwnd(&self) -> u64;
 
     fn pacing_rate(&self) -> Option<u64>;
+
+    /// Whether the algorithm is probing for the bandwidth by growing the window quickly,
+    /// the pacer picks the gain of [`PacingConfig`] by it.
+    fn in_slow_start(&self) -> bool;
+
+    /// The slow start threshold, None if it's not set yet, or the algorithm doesn't use it.
+    fn ssthresh(&self) -> Option<u64>;
+
+    /// Take over the path from another algorithm, see [`Handover`].
+    fn inherit(&mut self, handover: &Handover, now: Instant);
+
+    /// The sender has nothing to send while the congestion window allows, the delivery
+    /// rate sampled until the packets sent from now on are acknowledged is app-limited.
+    fn on_app_limited(&mut self, _now: Instant) {}
+
+    /// The in-flight packets of a space whose keys are discarded leave the bytes in
+    /// flight, neither acknowledged nor lost.
+    fn on_discarded(&mut self, _bytes: u64) {}
+
+    /// The path has sent nothing for a retransmission timeout, restart from the initial
+    /// window if the congestion window is larger, see [`IdleRestart::Restart`].
+    fn on_idle_restart(&mut self, now: Instant);
 }
 
 #[derive(Default)]
@@ -731,13 +1759,23 @@ mod tests {
     use qbase::varint::VarInt;
 
     use super::*;
+    use crate::CongestionControl;
 
     #[test]
     fn test_on_packet_sent_multiple_packets() {
         let mut congestion = create_congestion_controller_for_test();
         let now = Instant::now();
         for i in 1..=5 {
-            congestion.on_packet_sent(i, Epoch::Initial, true, true, 1000, now);
+            congestion.on_packet_sent(
+                i,
+                Epoch::Initial,
+                true,
+                true,
+                1000,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
         }
         assert_eq!(congestion.sent_packets[Epoch::Initial].len(), 5);
         for (i, sent) in congestion.sent_packets[Epoch::Initial].iter().enumerate() {
@@ -753,9 +1791,36 @@ mod tests {
     fn test_on_packet_sent_different_epochs() {
         let mut congestion = create_congestion_controller_for_test();
         let now = Instant::now();
-        congestion.on_packet_sent(1, Epoch::Initial, true, true, 1000, now);
-        congestion.on_packet_sent(2, Epoch::Handshake, true, true, 1000, now);
-        congestion.on_packet_sent(3, Epoch::Data, true, true, 1000, now);
+        congestion.on_packet_sent(
+            1,
+            Epoch::Initial,
+            true,
+            true,
+            1000,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.on_packet_sent(
+            2,
+            Epoch::Handshake,
+            true,
+            true,
+            1000,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.on_packet_sent(
+            3,
+            Epoch::Data,
+            true,
+            true,
+            1000,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
         assert_eq!(congestion.sent_packets[Epoch::Initial].len(), 1);
         assert_eq!(congestion.sent_packets[Epoch::Handshake].len(), 1);
         assert_eq!(congestion.sent_packets[Epoch::Data].len(), 1);
@@ -775,7 +1840,16 @@ mod tests {
         let now = Instant::now();
         let space = Epoch::Initial;
         for i in 1..=5 {
-            congestion.on_packet_sent(i, space, true, true, 1000, now);
+            congestion.on_packet_sent(
+                i,
+                space,
+                true,
+                true,
+                1000,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
         }
         // ack 5，检测出 1,2 因为乱序丢包
         congestion.largest_acked_packet[space] = Some(5);
@@ -796,6 +1870,145 @@ mod tests {
         }
     }
 
+    fn ack_frame(largest: u32, first_range: u32) -> AckFrame {
+        AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(first_range),
+            ranges: vec![],
+            ecn: None,
+        }
+    }
+
+    #[test]
+    fn test_spurious_loss_raises_threshold() {
+        let lost = Arc::new(Mutex::new(vec![]));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |_, pn| lost.lock().unwrap().push(pn)
+            }),
+            Box::new(|_, _| {}),
+        );
+        let now = Instant::now();
+        let space = Epoch::Data;
+        // 0~5发出后，5先到达，0~4乱序晚到
+        for pn in 0..=5 {
+            congestion.on_packet_sent(
+                pn,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
+        }
+        congestion.on_ack_rcvd(space, &ack_frame(5, 0), now);
+        assert_eq!(*lost.lock().unwrap(), vec![0, 1, 2]);
+        congestion.on_ack_rcvd(space, &ack_frame(5, 5), now);
+        // 丢失的包又被确认，不会重复报告丢失，只调高包序阈值
+        assert_eq!(*lost.lock().unwrap(), vec![0, 1, 2]);
+        let metrics = congestion.metrics();
+        assert_eq!(metrics.packets_sent, 6);
+        assert_eq!(metrics.bytes_sent, 6 * MSS as u64);
+        assert_eq!(metrics.packets_lost, 3);
+        assert_eq!(metrics.spurious_losses, 3);
+        assert_eq!(metrics.packet_threshold, 6);
+
+        // 同样程度的乱序，不再判定丢包
+        for pn in 6..=11 {
+            congestion.on_packet_sent(
+                pn,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
+        }
+        congestion.on_ack_rcvd(space, &ack_frame(11, 0), now);
+        congestion.on_ack_rcvd(space, &ack_frame(11, 5), now);
+        assert_eq!(lost.lock().unwrap().len(), 3);
+        assert_eq!(congestion.metrics().spurious_losses, 3);
+    }
+
+    #[test]
+    fn test_rtt_sample_across_key_update() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        let at = |millis: u64| now + Duration::from_millis(millis);
+        let space = Epoch::Data;
+        // 0~2用旧密钥，3~5用新密钥，每10ms发一个，包号连续，发送时刻按包号记录
+        for pn in 0..6 {
+            congestion.on_packet_sent(
+                pn,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                at(pn * 10),
+            );
+        }
+        // 确认跨越了密钥更新，RTT取最大的被确认包的发送时刻
+        congestion.on_ack_rcvd(space, &ack_frame(4, 4), at(100));
+        assert_eq!(
+            congestion.metrics().latest_rtt,
+            Some(Duration::from_millis(60))
+        );
+        congestion.on_ack_rcvd(space, &ack_frame(5, 5), at(120));
+        assert_eq!(
+            congestion.metrics().latest_rtt,
+            Some(Duration::from_millis(70))
+        );
+        // 重复的确认不产生RTT样本
+        congestion.on_ack_rcvd(space, &ack_frame(5, 5), at(200));
+        assert_eq!(
+            congestion.metrics().latest_rtt,
+            Some(Duration::from_millis(70))
+        );
+    }
+
+    #[test]
+    fn test_configured_packet_threshold() {
+        let now = Instant::now();
+        let space = Epoch::Data;
+        for (packet_threshold, packets_lost) in [(3, 3), (6, 0)] {
+            let mut congestion = create_congestion_controller_for_test();
+            congestion.set_loss_detection(LossDetectionConfig {
+                packet_threshold,
+                max_packet_threshold: None,
+                ..Default::default()
+            });
+            for pn in 0..=5 {
+                congestion.on_packet_sent(
+                    pn,
+                    space,
+                    true,
+                    true,
+                    MSS,
+                    EcnCodepoint::NotEct,
+                    PacketClass::Normal,
+                    now,
+                );
+            }
+            congestion.on_ack_rcvd(space, &ack_frame(5, 0), now);
+            congestion.on_ack_rcvd(space, &ack_frame(5, 5), now);
+            let metrics = congestion.metrics();
+            assert_eq!(metrics.packets_lost, packets_lost);
+            assert_eq!(metrics.spurious_losses, packets_lost);
+            // 不自适应时，阈值保持不变
+            assert_eq!(metrics.packet_threshold, packet_threshold);
+        }
+    }
+
     #[test]
     fn test_on_ack_received() {
         let now = Instant::now();
@@ -809,6 +2022,8 @@ mod tests {
                 true, // ack_eliciting
                 true, // in_flight
                 1000, // sent_bytes
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
                 now,
             );
         }
@@ -838,6 +2053,8 @@ mod tests {
                 true, // ack_eliciting
                 true, // in_flight
                 1000, // sent_bytes
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
                 now,
             );
         }
@@ -872,31 +2089,31 @@ mod tests {
     fn test_ack_record() {
         let max_ack_delay = Duration::from_millis(100);
         let mut ack_reocrd = AckRecord::new(Epoch::Initial);
-        ack_reocrd.recv_pkt(1);
+        ack_reocrd.recv_pkt(1, AckEagerness::Default);
         assert!(ack_reocrd.need_ack(max_ack_delay).is_some());
 
-        ack_reocrd.recv_pkt(1);
+        ack_reocrd.recv_pkt(1, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue.len(), 1);
 
         ack_reocrd.sent_ack(1, 1);
         assert_eq!(ack_reocrd.last_ack_sent, Some((1, 1)));
         assert!(ack_reocrd.need_ack(max_ack_delay).is_none());
 
-        ack_reocrd.recv_pkt(3);
+        ack_reocrd.recv_pkt(3, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue, vec![1, 3]);
 
-        ack_reocrd.recv_pkt(0);
+        ack_reocrd.recv_pkt(0, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3]);
         assert_eq!(ack_reocrd.need_ack(max_ack_delay).unwrap().0, 3);
 
-        ack_reocrd.recv_pkt(5);
-        ack_reocrd.recv_pkt(7);
+        ack_reocrd.recv_pkt(5, AckEagerness::Default);
+        ack_reocrd.recv_pkt(7, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7]);
         assert_eq!(ack_reocrd.need_ack(max_ack_delay).unwrap().0, 7);
 
         // pn 2 ack 0,1,3,5,7
         ack_reocrd.sent_ack(2, 7);
-        ack_reocrd.recv_pkt(9);
+        ack_reocrd.recv_pkt(9, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9]);
 
         // pn 3 ack 0,1,3,5,7,9
@@ -906,7 +2123,7 @@ mod tests {
         ack_reocrd.ack(2, &|_, _| {});
         assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9]);
 
-        ack_reocrd.recv_pkt(11);
+        ack_reocrd.recv_pkt(11, AckEagerness::Default);
         assert_eq!(ack_reocrd.rcvd_queue, vec![0, 1, 3, 5, 7, 9, 11]);
         // recv pn 3 ack, ret
 
@@ -914,6 +2131,1216 @@ mod tests {
         assert_eq!(ack_reocrd.rcvd_queue, vec![11]);
     }
 
+    #[test]
+    fn test_ack_eagerness() {
+        let max_ack_delay = Duration::from_millis(100);
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.ack_records[Epoch::Data].recv_pkt(0, AckEagerness::Default);
+        assert!(congestion.ack_records[Epoch::Data]
+            .need_ack(congestion.ack_delay())
+            .is_none());
+        // 乱序，立即确认
+        congestion.ack_records[Epoch::Data].recv_pkt(2, AckEagerness::Default);
+        assert!(congestion.ack_records[Epoch::Data]
+            .need_ack(congestion.ack_delay())
+            .is_some());
+
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.ack_eagerness = AckEagerness::Immediate;
+        assert_eq!(congestion.ack_delay(), Duration::ZERO);
+        congestion.ack_records[Epoch::Data].recv_pkt(0, AckEagerness::Immediate);
+        assert_eq!(
+            congestion.ack_records[Epoch::Data]
+                .need_ack(max_ack_delay)
+                .map(|(pn, _)| pn),
+            Some(0)
+        );
+
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.ack_eagerness = AckEagerness::Relaxed;
+        congestion.ack_records[Epoch::Data].recv_pkt(0, AckEagerness::Relaxed);
+        congestion.ack_records[Epoch::Data].recv_pkt(2, AckEagerness::Relaxed);
+        assert!(congestion.ack_records[Epoch::Data]
+            .need_ack(congestion.ack_delay())
+            .is_none());
+        // 握手期间的包仍需立即确认
+        congestion.ack_records[Epoch::Initial].recv_pkt(0, AckEagerness::Relaxed);
+        assert!(congestion.ack_records[Epoch::Initial]
+            .need_ack(congestion.ack_delay())
+            .is_some());
+    }
+
+    #[test]
+    fn test_ack_frequency() {
+        let max_delay = Duration::from_secs(1);
+        let mut record = AckRecord::new(Epoch::Data);
+        record.frequency = Some(AckFrequency {
+            ack_eliciting_threshold: 9,
+            max_ack_delay: Duration::from_millis(50),
+            reordering_threshold: 0,
+        });
+        let mut acked = vec![];
+        for pn in 0..40 {
+            record.recv_pkt(pn, AckEagerness::Default);
+            if let Some((largest, _)) = record.need_ack(max_delay) {
+                acked.push(largest);
+                record.sent_ack(pn, largest);
+            }
+        }
+        // 每收到10个ack-eliciting包确认一次
+        assert_eq!(acked, vec![9, 19, 29, 39]);
+
+        // 对方要求的最大延迟取代本地的
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.ack_records[Epoch::Data].frequency = record.frequency;
+        assert_eq!(congestion.ack_delay(), Duration::from_millis(50));
+        congestion.ack_eagerness = AckEagerness::Immediate;
+        assert_eq!(congestion.ack_delay(), Duration::ZERO);
+        // 过载时无视对方的要求，延迟到本地的max_ack_delay
+        congestion.ack_eagerness = AckEagerness::Lazy;
+        assert_eq!(congestion.ack_delay(), congestion.max_ack_delay);
+
+        let mut record = AckRecord::new(Epoch::Data);
+        record.frequency = congestion.ack_records[Epoch::Data].frequency;
+        for pn in (0..40).filter(|pn| pn % 3 != 0) {
+            record.recv_pkt(pn, AckEagerness::Lazy);
+            assert!(record.need_ack(max_delay).is_none());
+        }
+    }
+
+    #[test]
+    fn test_ack_frequency_reordering() {
+        let max_delay = Duration::from_secs(1);
+        let mut record = AckRecord::new(Epoch::Data);
+        record.frequency = Some(AckFrequency {
+            ack_eliciting_threshold: 9,
+            max_ack_delay: Duration::from_millis(50),
+            reordering_threshold: 3,
+        });
+        for pn in [0, 1, 2, 4, 5] {
+            record.recv_pkt(pn, AckEagerness::Default);
+            assert!(record.need_ack(max_delay).is_none());
+        }
+        // 缺失的3落后最大包号达到阈值，立即确认
+        record.recv_pkt(6, AckEagerness::Default);
+        assert_eq!(record.need_ack(max_delay).map(|(pn, _)| pn), Some(6));
+        record.sent_ack(0, 6);
+        assert_eq!(record.rcvd_queue, vec![0, 1, 2, 4, 5, 6]);
+
+        // 已经报告过的缺失不再触发
+        for pn in [7, 8, 9, 10] {
+            record.recv_pkt(pn, AckEagerness::Default);
+            assert!(record.need_ack(max_delay).is_none());
+        }
+        // 迟到的3不影响
+        record.recv_pkt(3, AckEagerness::Default);
+        assert!(record.need_ack(max_delay).is_none());
+    }
+
+    // 模拟一条丢包的路径：每轮发满拥塞窗口，一个RTT后逐个确认，每10个包丢1个
+    fn transfer(
+        congestion: &mut CongestionController,
+        rounds: usize,
+        pn: &mut u64,
+        now: &mut Instant,
+        lost: &Arc<Mutex<u64>>,
+        switch_to: Option<CongestionAlgorithm>,
+    ) -> u64 {
+        const RTT: Duration = Duration::from_millis(50);
+        let mut delivered = 0;
+        for _ in 0..rounds {
+            let in_flight = congestion.bytes_in_flight();
+            let budget = congestion.algorithm.cwnd().saturating_sub(in_flight) / MSS as u64;
+            let first = *pn;
+            for _ in 0..budget.clamp(1, 200) {
+                congestion.on_packet_sent(
+                    *pn,
+                    Epoch::Data,
+                    true,
+                    true,
+                    MSS,
+                    EcnCodepoint::NotEct,
+                    PacketClass::Normal,
+                    *now,
+                );
+                *pn += 1;
+            }
+            let sent = *pn;
+            *now += RTT;
+
+            for acked in (first..sent).filter(|pn| pn % 10 != 7) {
+                // 在途的包一半已确认，一半未确认时切换算法
+                if acked == (first + sent) / 2 {
+                    if let Some(algorithm) = switch_to {
+                        let (cwnd, in_flight) =
+                            (congestion.algorithm.cwnd(), congestion.bytes_in_flight());
+                        congestion.switch_algorithm(algorithm, *now);
+                        assert_eq!(congestion.kind, algorithm);
+                        assert!(congestion.algorithm.cwnd() <= cwnd);
+                        assert_eq!(congestion.bytes_in_flight(), in_flight);
+                    }
+                }
+                let ack_frame = AckFrame {
+                    largest: VarInt::from_u32(acked as u32),
+                    delay: VarInt::from_u32(0),
+                    first_range: VarInt::from_u32(0),
+                    ranges: vec![],
+                    ecn: None,
+                };
+                congestion.on_ack_rcvd(Epoch::Data, &ack_frame, *now);
+                delivered += MSS as u64;
+
+                // 在途字节数 = 发出的 - 确认的 - 判定丢失的
+                let tracked = congestion.sent_packets[Epoch::Data]
+                    .iter()
+                    .filter(|sent| !sent.is_acked)
+                    .count() as u64;
+                assert_eq!(congestion.bytes_in_flight(), tracked * MSS as u64);
+                let acked_total = (0..=acked).filter(|pn| pn % 10 != 7).count() as u64;
+                assert_eq!(
+                    congestion.bytes_in_flight(),
+                    (*pn - acked_total - *lost.lock().unwrap()) * MSS as u64
+                );
+            }
+        }
+        delivered
+    }
+
+    #[test]
+    fn test_switch_algorithm_mid_transfer() {
+        let lost = Arc::new(Mutex::new(0));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |_, _| *lost.lock().unwrap() += 1
+            }),
+            Box::new(|_, _| {}),
+        );
+        let mut pn = 0;
+        let mut now = Instant::now();
+
+        // NewReno 在随机丢包下窗口不断减半
+        let reno = transfer(&mut congestion, 10, &mut pn, &mut now, &lost, None);
+        assert!(*lost.lock().unwrap() > 0);
+        transfer(
+            &mut congestion,
+            1,
+            &mut pn,
+            &mut now,
+            &lost,
+            Some(CongestionAlgorithm::Bbr),
+        );
+        // BBR 不把丢包当作拥塞信号，吞吐量很快恢复
+        let bbr = transfer(&mut congestion, 10, &mut pn, &mut now, &lost, None);
+        assert!(bbr > 4 * reno);
+
+        // 切回 NewReno，在途的包仍由新算法接手
+        transfer(
+            &mut congestion,
+            1,
+            &mut pn,
+            &mut now,
+            &lost,
+            Some(CongestionAlgorithm::NewReno),
+        );
+        transfer(&mut congestion, 5, &mut pn, &mut now, &lost, None);
+    }
+
+    #[test]
+    fn test_metrics_on_lossy_path() {
+        let lost = Arc::new(Mutex::new(0));
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |_, _| *lost.lock().unwrap() += 1
+            }),
+            Box::new(|_, _| {}),
+        );
+        let metrics = cc.metrics();
+        assert_eq!(metrics.algorithm, CongestionAlgorithm::NewReno);
+        assert_eq!(metrics.bytes_in_flight, 0);
+        assert_eq!(metrics.ssthresh, None);
+        assert_eq!(metrics.latest_rtt, None);
+        assert_eq!(metrics.min_rtt, None);
+        assert_eq!(metrics.packets_lost, 0);
+
+        let mut pn = 0;
+        let mut now = Instant::now();
+        let mut reduced = false;
+        let mut last = metrics;
+        for _ in 0..10 {
+            transfer(&mut cc.0.lock().unwrap(), 1, &mut pn, &mut now, &lost, None);
+            let metrics = cc.metrics();
+            // 丢包计数与实际判定丢失的包一致
+            assert_eq!(metrics.packets_lost, *lost.lock().unwrap());
+            assert_eq!(metrics.latest_rtt, Some(Duration::from_millis(50)));
+            assert_eq!(metrics.min_rtt, Some(Duration::from_millis(50)));
+            if metrics.packets_lost > last.packets_lost && metrics.cwnd < last.cwnd {
+                reduced = true;
+                assert!(metrics.ssthresh.is_some());
+            }
+            last = metrics;
+        }
+        assert!(last.packets_lost > 0);
+        // 丢包后窗口减半，可以从快照中观察到
+        assert!(reduced);
+    }
+
+    #[test]
+    fn test_algorithm_per_controller() {
+        // 同一进程中的两个控制器各用各的算法，互不影响
+        let [bbr, reno] =
+            [CongestionAlgorithm::Bbr, CongestionAlgorithm::NewReno].map(|algorithm| {
+                let lost = Arc::new(Mutex::new(0));
+                let cc = ArcCC::new(
+                    algorithm,
+                    Duration::from_millis(100),
+                    Box::new({
+                        let lost = lost.clone();
+                        move |_, _| *lost.lock().unwrap() += 1
+                    }),
+                    Box::new(|_, _| {}),
+                );
+                assert_eq!(cc.algorithm(), algorithm);
+
+                let mut now = Instant::now();
+                let mut pn = 0;
+                let mut guard = cc.0.lock().unwrap();
+                let mut cwnds = vec![guard.algorithm.cwnd()];
+                for _ in 0..10 {
+                    transfer(&mut guard, 1, &mut pn, &mut now, &lost, None);
+                    cwnds.push(guard.algorithm.cwnd());
+                }
+                cwnds
+            });
+
+        assert_ne!(bbr, reno);
+        // NewReno 在随机丢包下窗口不断减半，BBR 不把丢包当作拥塞信号
+        assert!(bbr.last() > reno.last());
+    }
+
+    #[test]
+    fn test_send_waker() {
+        use std::{
+            sync::atomic::{self, AtomicUsize},
+            task::Wake,
+        };
+
+        struct CountWaker(AtomicUsize);
+
+        impl Wake for CountWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+        }
+
+        let count = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        let woken = || count.0.load(atomic::Ordering::Relaxed);
+
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new(|_, _| {}),
+            Box::new(|_, _| {}),
+        );
+        // pacer放出额度极慢，360B/s，测试期间攒不出一个包的额度
+        cc.set_pacing(PacingConfig {
+            gain_slow_start: 0.01,
+            gain_congestion_avoidance: 0.01,
+            ..PacingConfig::default()
+        });
+        let mut pn = 0;
+        while let Poll::Ready(quota) = cc.poll_send(&mut cx) {
+            cc.on_pkt_sent(
+                Epoch::Data,
+                pn,
+                true,
+                quota,
+                true,
+                None,
+                EcnCodepoint::NotEct,
+            );
+            pn += 1;
+        }
+        assert!(pn >= 10);
+
+        // 额度耗尽，驱动只需在pacer放出额度时醒来，此前do_tick不唤醒发送任务
+        let wait = cc.max_wait();
+        assert!(wait > Duration::from_secs(1) && wait < Duration::MAX);
+        let before = woken();
+        cc.do_tick();
+        assert_eq!(woken(), before);
+
+        // 收到确认，立即唤醒
+        cc.on_ack(Epoch::Data, &ack_frame(0, 0));
+        assert_eq!(woken(), before + 1);
+    }
+
+    // 按探测超时的时刻驱动，返回探测的次数
+    fn probe_until_exhausted(congestion: &mut CongestionController, limit: u32) -> u32 {
+        let mut probes = 0;
+        while !congestion.is_pto_exhausted() && probes < limit {
+            let timeout = congestion.loss_timer.timeout.unwrap();
+            congestion.on_loss_timeout(timeout + K_GRANULARITY);
+            probes += 1;
+        }
+        probes
+    }
+
+    #[test]
+    fn test_pto_exhausted_without_response() {
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.max_pto_count = Some(3);
+        let now = Instant::now();
+        congestion.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+
+        assert_eq!(probe_until_exhausted(&mut congestion, 10), 3);
+        assert_eq!(congestion.pto_in_a_row, 3);
+    }
+
+    #[test]
+    fn test_pto_in_a_row_reset_by_ack() {
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.max_pto_count = Some(3);
+        let now = Instant::now();
+        congestion.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.on_packet_sent(
+            1,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.on_loss_timeout(congestion.loss_timer.timeout.unwrap() + K_GRANULARITY);
+        congestion.on_loss_timeout(congestion.loss_timer.timeout.unwrap() + K_GRANULARITY);
+        assert!(!congestion.is_pto_exhausted());
+
+        // 对端回应过一次，重新计数
+        let ack_frame = AckFrame {
+            largest: VarInt::from_u32(0),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        };
+        congestion.on_ack_rcvd(Epoch::Initial, &ack_frame, now);
+        assert_eq!(congestion.pto_in_a_row, 0);
+        assert_eq!(probe_until_exhausted(&mut congestion, 10), 3);
+
+        // 不设上限，则永不放弃
+        congestion.max_pto_count = None;
+        assert!(!congestion.is_pto_exhausted());
+    }
+
+    #[test]
+    fn test_requeue_unacked() {
+        let lost = Arc::new(Mutex::new(vec![]));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |epoch: Epoch, pn: u64| lost.lock().unwrap().push((epoch, pn))
+            }),
+            Box::new(|_: Epoch, _: u64| {}),
+        );
+        congestion.max_pto_count = Some(2);
+        let now = Instant::now();
+        congestion.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.on_packet_sent(
+            1,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        assert_eq!(probe_until_exhausted(&mut congestion, 10), 2);
+        // 每次探测重传一个最早的未确认的包
+        assert_eq!(
+            std::mem::take(&mut *lost.lock().unwrap()),
+            vec![(Epoch::Initial, 0), (Epoch::Initial, 1)]
+        );
+
+        // 改用更小的数据报重发，未确认的包都视为丢失，重新计数探测超时
+        congestion.requeue_unacked(Epoch::Initial, now);
+        assert_eq!(
+            *lost.lock().unwrap(),
+            vec![(Epoch::Initial, 0), (Epoch::Initial, 1)]
+        );
+        assert_eq!(congestion.bytes_in_flight(), 0);
+        assert!(!congestion.is_pto_exhausted());
+
+        congestion.on_packet_sent(
+            2,
+            Epoch::Initial,
+            true,
+            true,
+            1100,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        assert_eq!(probe_until_exhausted(&mut congestion, 10), 2);
+    }
+
+    #[test]
+    fn test_discard_space() {
+        let lost = Arc::new(Mutex::new(vec![]));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |epoch: Epoch, pn: u64| lost.lock().unwrap().push((epoch, pn))
+            }),
+            Box::new(|_: Epoch, _: u64| {}),
+        );
+        let now = Instant::now();
+        for pn in 0..2 {
+            congestion.on_packet_sent(
+                pn,
+                Epoch::Initial,
+                true,
+                true,
+                1200,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
+        }
+        congestion.on_packet_sent(
+            0,
+            Epoch::Handshake,
+            true,
+            true,
+            1000,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        assert_eq!(congestion.bytes_in_flight(), 3400);
+
+        // 丢弃Initial密钥，其在途的包不再计入，PTO改由握手空间设定
+        congestion.discard_space(Epoch::Initial);
+        assert_eq!(congestion.bytes_in_flight(), 1000);
+        assert_eq!(
+            congestion.get_pto_time_and_space(now),
+            (
+                Some(now + congestion.get_pto_time(Epoch::Handshake)),
+                Epoch::Handshake
+            )
+        );
+        // 迟到的确认与之后发的包都不再记录
+        congestion.on_ack_rcvd(Epoch::Initial, &ack_frame(1, 0), now);
+        congestion.on_packet_sent(
+            2,
+            Epoch::Initial,
+            false,
+            false,
+            50,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        assert!(congestion.sent_packets[Epoch::Initial].is_empty());
+        assert_eq!(congestion.largest_acked_packet[Epoch::Initial], None);
+
+        // PTO只探测握手空间，从不判定Initial的包丢失
+        let later = now + Duration::from_secs(1);
+        assert!(congestion.loss_timer.is_timeout(later));
+        congestion.on_loss_timeout(later);
+        assert_eq!(congestion.probes[Epoch::Initial], 0);
+        assert_eq!(congestion.probes[Epoch::Handshake], 2);
+        assert_eq!(*lost.lock().unwrap(), vec![(Epoch::Handshake, 0)]);
+
+        // 握手确认后丢弃握手密钥，不再有在途的包，也不再有探测
+        congestion.on_packet_sent(
+            0,
+            Epoch::Data,
+            true,
+            true,
+            500,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        congestion.is_handshake_done = true;
+        congestion.discard_space(Epoch::Handshake);
+        assert_eq!(congestion.bytes_in_flight(), 500);
+        assert_eq!(congestion.probes[Epoch::Handshake], 0);
+        assert_eq!(congestion.pto_count, 0);
+        assert_eq!(congestion.get_pto_time_and_space(now).1, Epoch::Data);
+        let later = later + Duration::from_secs(10);
+        congestion.on_loss_timeout(later);
+        assert_eq!(
+            *lost.lock().unwrap(),
+            vec![(Epoch::Handshake, 0), (Epoch::Data, 0)]
+        );
+    }
+
+    #[test]
+    fn test_misreported_ack_delay() {
+        let lost = Arc::new(Mutex::new(vec![]));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |_, pn| lost.lock().unwrap().push(pn)
+            }),
+            Box::new(|_, _| {}),
+        );
+        congestion
+            .rtt
+            .set_peer_ack_delay(3, Duration::from_millis(10));
+        congestion.is_handshake_done = true;
+        congestion.rtt.on_handshake_done();
+
+        // 真实的RTT是40ms，对方却每次都报告1s的ack_delay，远超其通告的10ms
+        let rtt = Duration::from_millis(40);
+        let start = Instant::now();
+        let space = Epoch::Data;
+        for pn in 0..50 {
+            let sent = start + rtt * pn;
+            congestion.on_packet_sent(
+                pn as u64,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                sent,
+            );
+            let mut ack = ack_frame(pn, 0);
+            ack.delay = VarInt::from_u32(125_000);
+            congestion.on_ack_rcvd(space, &ack, sent + rtt);
+
+            // 丢包检测的时限始终围绕着真实的RTT
+            let loss_delay = congestion
+                .rtt
+                .loss_delay(congestion.loss_detection.time_threshold);
+            assert!(loss_delay >= rtt && loss_delay < rtt * 2, "{loss_delay:?}");
+            let pto = congestion.get_pto_time(space);
+            assert!(
+                pto > rtt && pto < rtt * 4 + congestion.max_ack_delay,
+                "{pto:?}"
+            );
+        }
+        assert!(lost.lock().unwrap().is_empty());
+        let metrics = congestion.metrics();
+        assert_eq!(metrics.min_rtt, Some(rtt));
+        assert_eq!(metrics.ack_delay_violations, 50);
+        assert!(metrics.peer_violates_max_ack_delay);
+        assert_eq!(metrics.pto_count, 0);
+        assert_eq!(metrics.spurious_losses, 0);
+    }
+
+    #[test]
+    fn test_ack_delay_of_initial_ignored() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        let at = |millis: u64| now + Duration::from_millis(millis);
+        for (pn, space) in [
+            (0, Epoch::Initial),
+            (1, Epoch::Initial),
+            (0, Epoch::Handshake),
+        ] {
+            congestion.on_packet_sent(
+                pn,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
+        }
+        congestion.on_ack_rcvd(Epoch::Initial, &ack_frame(0, 0), at(50));
+        // Initial包的确认不会被延迟，其ack_delay不被扣除
+        let mut ack = ack_frame(1, 0);
+        ack.delay = VarInt::from_u32(3750);
+        congestion.on_ack_rcvd(Epoch::Initial, &ack, at(80));
+        let expected =
+            Duration::from_millis(50).mul_f32(0.875) + Duration::from_millis(80).mul_f32(0.125);
+        assert_eq!(congestion.metrics().smoothed_rtt, expected);
+
+        // 握手空间的ack_delay照常扣除
+        let mut ack = ack_frame(0, 0);
+        ack.delay = VarInt::from_u32(3750);
+        congestion.on_ack_rcvd(Epoch::Handshake, &ack, at(80));
+        let expected = expected.mul_f32(0.875) + Duration::from_millis(50).mul_f32(0.125);
+        assert_eq!(congestion.metrics().smoothed_rtt, expected);
+    }
+
+    #[test]
+    fn test_pto_backoff_capped() {
+        let mut congestion = create_congestion_controller_for_test();
+        congestion.set_loss_detection(LossDetectionConfig {
+            max_pto: Some(Duration::from_secs(5)),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        congestion.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        for _ in 0..100 {
+            let timeout = congestion.loss_timer.timeout.unwrap();
+            congestion.on_loss_timeout(timeout + K_GRANULARITY);
+        }
+        assert_eq!(congestion.pto_count, 100);
+        assert_eq!(
+            congestion.get_pto_time(Epoch::Initial),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            congestion.loss_timer.timeout,
+            Some(now + Duration::from_secs(5))
+        );
+
+        // 不设上限，退避到溢出也不会崩溃，视同永不超时
+        congestion.set_loss_detection(LossDetectionConfig {
+            max_pto: None,
+            ..Default::default()
+        });
+        assert_eq!(congestion.get_pto_time(Epoch::Initial), Duration::MAX);
+        congestion.set_loss_timer();
+        assert_eq!(congestion.loss_timer.timeout, None);
+    }
+
+    #[test]
+    fn test_probe_ignores_exhausted_cwnd() {
+        let lost = Arc::new(Mutex::new(vec![]));
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::Bbr,
+            Duration::from_millis(100),
+            Box::new({
+                let lost = lost.clone();
+                move |epoch: Epoch, pn: u64| lost.lock().unwrap().push((epoch, pn))
+            }),
+            Box::new(|_: Epoch, _: u64| {}),
+        );
+        congestion.role = Role::Server;
+        congestion.is_handshake_done = true;
+        let now = congestion.last_sent_time;
+        for pn in 0..3 {
+            congestion.on_packet_sent(
+                pn,
+                Epoch::Data,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                now,
+            );
+        }
+        // 令牌耗尽，不能再发
+        let drain = |congestion: &mut CongestionController, now: Instant| {
+            congestion.send_quota(now);
+            congestion.pacer.on_sent(u64::MAX);
+            congestion.last_sent_time = now;
+        };
+        drain(&mut congestion, now);
+        assert_eq!(congestion.send_quota(now), None);
+
+        // PTO之后，可以发两个探测包，最早的未确认的包被重传
+        let pto = congestion.loss_timer.timeout.unwrap() + K_GRANULARITY;
+        congestion.on_loss_timeout(pto);
+        drain(&mut congestion, pto);
+        assert_eq!(congestion.send_quota(pto), Some(2 * MSS));
+        assert_eq!(*lost.lock().unwrap(), vec![(Epoch::Data, 0)]);
+        for pn in 3..5 {
+            assert_eq!(congestion.probes[Epoch::Data], 5 - pn as u8);
+            congestion.on_packet_sent(
+                pn,
+                Epoch::Data,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                pto,
+            );
+        }
+        assert_eq!(congestion.send_quota(pto), None);
+
+        // 再次超时，重传下一个未确认的包
+        let pto = congestion.loss_timer.timeout.unwrap() + K_GRANULARITY;
+        congestion.on_loss_timeout(pto);
+        assert_eq!(
+            *lost.lock().unwrap(),
+            vec![(Epoch::Data, 0), (Epoch::Data, 1)]
+        );
+        assert_eq!(congestion.pto_count, 2);
+    }
+
+    #[test]
+    fn test_idle_restart() {
+        const RTT: Duration = Duration::from_millis(1);
+        // 同一时刻连续发包直到令牌耗尽，再全部确认，返回这一轮发出的包数
+        fn round(congestion: &mut CongestionController, pn: &mut u64, now: &mut Instant) -> u64 {
+            let first = *pn;
+            while congestion
+                .send_quota(*now)
+                .is_some_and(|quota| quota >= MSS)
+            {
+                congestion.on_packet_sent(
+                    *pn,
+                    Epoch::Data,
+                    true,
+                    true,
+                    MSS,
+                    EcnCodepoint::NotEct,
+                    PacketClass::Normal,
+                    *now,
+                );
+                *pn += 1;
+            }
+            *now += RTT;
+            for acked in first..*pn {
+                let ack_frame = AckFrame {
+                    largest: VarInt::from_u32(acked as u32),
+                    delay: VarInt::from_u32(0),
+                    first_range: VarInt::from_u32(0),
+                    ranges: vec![],
+                    ecn: None,
+                };
+                congestion.on_ack_rcvd(Epoch::Data, &ack_frame, *now);
+            }
+            *pn - first
+        }
+
+        let burst_after_idle = |idle_restart: IdleRestart| {
+            let mut congestion = CongestionController::new(
+                CongestionAlgorithm::NewReno,
+                Duration::from_millis(100),
+                Box::new(|_, _| {}),
+                Box::new(|_, _| {}),
+            );
+            congestion.role = Role::Server;
+            congestion.is_handshake_done = true;
+            // 逐包放行，突发的包数不受块大小的取整影响
+            let config = PacingConfig {
+                idle_restart,
+                chunk_packets: 1,
+                ..PacingConfig::default()
+            };
+            congestion
+                .pacer
+                .set_config(config, INITIAL_RTT, congestion.algorithm.cwnd(), MSS);
+            let (mut pn, mut now) = (0, congestion.last_sent_time);
+
+            // 慢启动数轮之后，一次突发达到上限
+            for _ in 0..6 {
+                round(&mut congestion, &mut pn, &mut now);
+            }
+            assert!(congestion.algorithm.cwnd() >= 128 * MSS as u64);
+            assert_eq!(round(&mut congestion, &mut pn, &mut now), 128);
+
+            // 空闲1秒，远超一个RTO
+            now += Duration::from_secs(1);
+            let burst = round(&mut congestion, &mut pn, &mut now);
+            (burst, congestion.metrics().cwnd)
+        };
+
+        // 回到初始窗口，突发也随之回到初始窗口；之后仍按慢启动增长
+        let (burst, cwnd) = burst_after_idle(IdleRestart::Restart);
+        assert_eq!(burst, 10);
+        assert_eq!(cwnd, 20 * MSS as u64);
+
+        // 保留窗口，只靠pacer限制突发
+        let (burst, cwnd) = burst_after_idle(IdleRestart::Pace);
+        assert_eq!(burst, 128);
+        assert!(cwnd > 128 * MSS as u64);
+    }
+
+    #[test]
+    fn test_anti_deadlock_probe() {
+        let mut client = create_congestion_controller_for_test();
+        let now = Instant::now();
+        client.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        client.on_ack_rcvd(Epoch::Initial, &ack_frame(0, 0), now);
+        assert!(client.no_ack_eliciting_in_flight());
+
+        // 服务端受抗放大限制而无法发送，客户端即便没有在途的包也要探测
+        let timeout = client.loss_timer.timeout.unwrap();
+        client.on_loss_timeout(timeout + K_GRANULARITY);
+        assert_eq!(client.probes, [1, 1, 0]);
+        client.on_packet_sent(
+            0,
+            Epoch::Handshake,
+            true,
+            true,
+            100,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        assert_eq!(client.probes, [1, 0, 0]);
+
+        // Handshake包被确认，服务端已经验证了客户端的地址，不再需要反死锁
+        client.on_ack_rcvd(Epoch::Handshake, &ack_frame(0, 0), now);
+        assert_eq!(client.pto_count, 0);
+        assert_eq!(client.loss_timer.timeout, None);
+
+        // 服务端无需反死锁
+        let mut server = create_congestion_controller_for_test();
+        server.role = Role::Server;
+        server.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        server.on_ack_rcvd(Epoch::Initial, &ack_frame(0, 0), now);
+        assert_eq!(server.loss_timer.timeout, None);
+    }
+
+    #[test]
+    fn test_amplification_limited_server() {
+        let now = Instant::now();
+        let mut server = create_congestion_controller_for_test();
+        server.role = Role::Server;
+        server.on_packet_sent(
+            0,
+            Epoch::Initial,
+            true,
+            true,
+            1200,
+            EcnCodepoint::NotEct,
+            PacketClass::Normal,
+            now,
+        );
+        let timeout = server.loss_timer.timeout.unwrap();
+
+        // 受抗放大限制时发不出探测包，取消定时器
+        server.amplification_limited = true;
+        server.set_loss_timer();
+        assert_eq!(server.loss_timer.timeout, None);
+
+        // 没受限制时收到数据报，无事发生
+        let mut unlimited = create_congestion_controller_for_test();
+        unlimited.on_datagram_rcvd(timeout + K_GRANULARITY);
+        assert_eq!(unlimited.pto_count, 0);
+
+        // 收到新的数据报解除了限制，PTO在受限期间本该到期，立即执行
+        server.on_datagram_rcvd(timeout + K_GRANULARITY);
+        assert!(!server.amplification_limited);
+        assert_eq!(server.pto_count, 1);
+        assert_eq!(server.probes[Epoch::Initial], 2);
+    }
+
+    fn ack_with_ecn(largest: u32, first_range: u32, ecn: (u32, u32, u32)) -> AckFrame {
+        AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(first_range),
+            ranges: vec![],
+            ecn: Some(EcnCounts {
+                ect0: VarInt::from_u32(ecn.0),
+                ect1: VarInt::from_u32(ecn.1),
+                ce: VarInt::from_u32(ecn.2),
+            }),
+        }
+    }
+
+    fn send_marked(congestion: &mut CongestionController, pns: std::ops::Range<u64>, now: Instant) {
+        for pn in pns {
+            let ecn = congestion.ecn.codepoint();
+            congestion.on_packet_sent(
+                pn,
+                Epoch::Data,
+                true,
+                true,
+                MSS,
+                ecn,
+                PacketClass::Normal,
+                now,
+            );
+        }
+    }
+
+    #[test]
+    fn test_ecn_ce_reduces_cwnd() {
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new(|_, _| {}),
+            Box::new(|_, _| {}),
+        );
+        let now = Instant::now();
+        send_marked(&mut congestion, 0..4, now);
+        let now = now + Duration::from_millis(50);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(1, 1, (2, 0, 0)), now);
+        assert_eq!(congestion.ecn.state(), EcnState::Capable);
+        let cwnd = congestion.algorithm.cwnd();
+
+        // 没有丢包，但CE标记同样减窗
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(3, 1, (3, 0, 1)), now);
+        assert_eq!(congestion.ecn.state(), EcnState::Capable);
+        assert_eq!(congestion.algorithm.cwnd(), cwnd / 2);
+    }
+
+    // PMTU探测包超过了路径MTU而丢失，不是拥塞，不减窗；其确认也不扩大窗口
+    #[test]
+    fn test_probe_not_congestion() {
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new(|_, _| {}),
+            Box::new(|_, _| {}),
+        );
+        let now = Instant::now();
+        let class = |pn| match pn {
+            0 | 9 => PacketClass::MtuProbe,
+            _ => PacketClass::Normal,
+        };
+        for pn in 0..10 {
+            congestion.on_packet_sent(
+                pn,
+                Epoch::Data,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                class(pn),
+                now,
+            );
+        }
+        let cwnd = congestion.algorithm.cwnd();
+
+        // 确认了1~4，0的探测包按包序阈值判定丢失
+        let now = now + Duration::from_millis(50);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(4, 3), now);
+        assert_eq!(congestion.packets_lost, 1);
+        assert_eq!(congestion.algorithm.cwnd(), cwnd + 4 * MSS as u64);
+        assert!(congestion.algorithm.ssthresh().is_none());
+
+        // 9的探测包被确认，采样了RTT，但不扩大窗口
+        let now = now + Duration::from_millis(30);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_frame(9, 4), now);
+        assert_eq!(congestion.algorithm.cwnd(), cwnd + 8 * MSS as u64);
+        assert_eq!(congestion.rtt.latest_rtt(), Some(Duration::from_millis(80)));
+        assert_eq!(congestion.bytes_in_flight(), 0);
+    }
+
+    // 验证新路径的PATH_CHALLENGE包丢了，不是拥塞，该路径的窗口不变
+    #[test]
+    fn test_lost_path_probe_keeps_cwnd() {
+        let cc = ArcCC::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new(|_, _| {}),
+            Box::new(|_, _| {}),
+        );
+        let cwnd = cc.metrics().cwnd;
+        for pn in 0..4 {
+            cc.on_pkt_sent(
+                Epoch::Data,
+                pn,
+                true,
+                MSS,
+                true,
+                None,
+                EcnCodepoint::NotEct,
+                PacketClass::PathProbe,
+            );
+        }
+
+        // 确认了3，0按包序阈值判定丢失
+        cc.on_ack(Epoch::Data, &ack_frame(3, 0));
+        let metrics = cc.metrics();
+        assert!(metrics.packets_lost > 0);
+        assert_eq!(metrics.cwnd, cwnd);
+        assert_eq!(metrics.ssthresh, None);
+    }
+
+    #[test]
+    fn test_forged_ecn_counts_disable_ecn() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        send_marked(&mut congestion, 0..4, now);
+        let now = now + Duration::from_millis(50);
+        // 报告的标记数多于发出的，ECN在该路径上停用，连接照常
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(1, 1, (6, 0, 0)), now);
+        assert_eq!(congestion.ecn.state(), EcnState::Failed);
+        assert_eq!(congestion.ecn.codepoint(), EcnCodepoint::NotEct);
+        assert_eq!(congestion.bytes_in_flight(), 2 * MSS as u64);
+
+        // 之后的包不再标记，确认也照常处理
+        send_marked(&mut congestion, 4..6, now);
+        assert!(congestion.sent_packets[Epoch::Data]
+            .iter()
+            .filter(|sent| sent.pn >= 4)
+            .all(|sent| sent.ecn == EcnCodepoint::NotEct));
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(5, 3, (0, 0, 0)), now);
+        assert_eq!(congestion.bytes_in_flight(), 0);
+    }
+
+    #[test]
+    fn test_decreasing_ecn_counts_disable_ecn() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        send_marked(&mut congestion, 0..4, now);
+        let now = now + Duration::from_millis(50);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(1, 1, (2, 0, 0)), now);
+        assert_eq!(congestion.ecn.state(), EcnState::Capable);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_with_ecn(3, 1, (1, 0, 0)), now);
+        assert_eq!(congestion.ecn.state(), EcnState::Failed);
+        assert_eq!(congestion.bytes_in_flight(), 0);
+    }
+
+    fn ack_of(largest: u32) -> AckFrame {
+        AckFrame {
+            largest: VarInt::from_u32(largest),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u32(0),
+            ranges: vec![],
+            ecn: None,
+        }
+    }
+
+    // 取得首个RTT样本后，一批包发出，路径中断，中断期间只有探测包发出；
+    // 中断结束后的一个ACK宣告之前的包全部丢失
+    fn blackout(handshake_acked_between: bool) -> CongestionController {
+        let mut congestion = CongestionController::new(
+            CongestionAlgorithm::NewReno,
+            Duration::from_millis(100),
+            Box::new(|_, _| {}),
+            Box::new(|_, _| {}),
+        );
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let send = |congestion: &mut CongestionController, pn, space, millis| {
+            congestion.on_packet_sent(
+                pn,
+                space,
+                true,
+                true,
+                MSS,
+                EcnCodepoint::NotEct,
+                PacketClass::Normal,
+                at(millis),
+            )
+        };
+
+        send(&mut congestion, 0, Epoch::Data, 0);
+        congestion.on_ack_rcvd(Epoch::Data, &ack_of(0), at(50));
+        for pn in 1..=5 {
+            send(&mut congestion, pn, Epoch::Data, 100);
+        }
+        send(&mut congestion, 6, Epoch::Data, 400);
+        send(&mut congestion, 0, Epoch::Handshake, 700);
+        if handshake_acked_between {
+            congestion.on_ack_rcvd(Epoch::Handshake, &ack_of(0), at(750));
+        }
+        send(&mut congestion, 7, Epoch::Data, 1000);
+        send(&mut congestion, 8, Epoch::Data, 1300);
+        send(&mut congestion, 9, Epoch::Data, 1350);
+
+        congestion.on_ack_rcvd(Epoch::Data, &ack_of(9), at(1400));
+        assert!(congestion.sent_packets[Epoch::Data].is_empty());
+        congestion
+    }
+
+    #[test]
+    fn test_persistent_congestion() {
+        let congestion = blackout(false);
+        assert!(congestion.persistent_congestion_duration() < Duration::from_millis(1200));
+        assert_eq!(congestion.algorithm.cwnd(), 2 * MSS as u64);
+    }
+
+    #[test]
+    fn test_acked_between_losses_prevents_persistent_congestion() {
+        // 两段丢包期都短于持续拥塞时长，只按普通的拥塞事件减半
+        let congestion = blackout(true);
+        assert!(congestion.persistent_congestion_duration() > Duration::from_millis(300));
+        assert!(congestion.algorithm.cwnd() > 2 * MSS as u64);
+        assert_eq!(congestion.algorithm.cwnd(), 6 * MSS as u64);
+    }
+
+    #[test]
+    fn test_no_persistent_congestion_without_rtt_sample() {
+        let mut congestion = create_congestion_controller_for_test();
+        let now = Instant::now();
+        let lost = (0..2)
+            .map(|pn| SentPkt {
+                pn,
+                ack_eliciting: true,
+                time_sent: now + Duration::from_secs(pn * 10),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        assert!(!congestion.in_persistent_congestion(&lost));
+
+        congestion.first_rtt_sample = Some(now);
+        // 首个RTT样本之前发出的包不计
+        assert!(!congestion.in_persistent_congestion(&lost));
+        congestion.first_rtt_sample = Some(now - Duration::from_millis(1));
+        assert!(congestion.in_persistent_congestion(&lost));
+    }
+
     fn create_congestion_controller_for_test() -> CongestionController {
         let loss = Box::new(|_: Epoch, _: u64| {});
         let retire = Box::new(|_: Epoch, _: u64| {});
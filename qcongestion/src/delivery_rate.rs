@@ -37,7 +37,11 @@ impl Default for Rate {
 
 impl Rate {
     // 3.2. Transmitting or retransmitting a data packet
+    // 探测包的大小与时机都不代表真实的流量，不参与采样，bytes_in_flight也不应计入探测包
     pub fn on_packet_sent(&mut self, pkt: &mut SentPkt, bytes_in_flight: usize, bytes_lost: u64) {
+        if pkt.class.is_probe() {
+            return;
+        }
         // No packets in flight.
         if bytes_in_flight == 0 {
             self.first_sent_time = pkt.time_sent;
@@ -56,6 +60,9 @@ impl Rate {
 
     // Update the delivery rate sample when a packet is acked.
     pub fn update_rate_sample(&mut self, pkt: &AckedPkt, now: Instant) {
+        if pkt.class.is_probe() {
+            return;
+        }
         self.delivered += pkt.size;
         self.delivered_time = now;
 
@@ -0,0 +1,236 @@
+use qbase::frame::EcnCounts;
+use qrecovery::space::Epoch;
+
+// 测试阶段带ECT(0)标记发送的包数，之后暂停标记，等待对方的确认给出结论
+const TESTING_PACKETS: u64 = 10;
+
+/// The ECN codepoint in the IP header of a datagram, see
+/// [RFC 3168](https://www.rfc-editor.org/rfc/rfc3168.html#section-5).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EcnCodepoint {
+    #[default]
+    NotEct = 0b00,
+    Ect1 = 0b01,
+    Ect0 = 0b10,
+    Ce = 0b11,
+}
+
+impl EcnCodepoint {
+    /// The bits to be set in the IP header, None for Not-ECT.
+    pub fn bits(self) -> Option<u8> {
+        (self != EcnCodepoint::NotEct).then_some(self as u8)
+    }
+}
+
+/// The ECN validation state of a path, see
+/// [Appendix A.4](https://www.rfc-editor.org/rfc/rfc9000.html#name-sample-ecn-validation-algor)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EcnState {
+    /// Marking the first packets with ECT(0), to test whether the path and the peer
+    /// support ECN.
+    #[default]
+    Testing,
+    /// The testing packets are sent, no more packets are marked until an ACK frame
+    /// validates them.
+    Unknown,
+    /// The path or the peer doesn't support ECN, or mangles the marks, ECN is disabled
+    /// on this path.
+    Failed,
+    /// The ECN counts are validated, the packets are marked, the CE marks are reacted to.
+    Capable,
+}
+
+/// Validate the ECN counts reported by the peer in the ACK frames on a path.
+///
+/// A failed validation disables ECN on the path, it's never a connection error, since the
+/// counts may be mangled by the network rather than by the peer.
+#[derive(Debug, Default)]
+pub struct EcnValidator {
+    state: EcnState,
+    testing_sent: u64,
+    testing_lost: u64,
+    // 每个空间中带ECT(0)标记发送的包数
+    ect0_sent: [u64; Epoch::count()],
+    // 每个空间中对方最近一次报告的ECN计数
+    reported: [(u64, u64, u64); Epoch::count()],
+}
+
+impl EcnValidator {
+    pub fn state(&self) -> EcnState {
+        self.state
+    }
+
+    /// The codepoint to mark the packets sent next.
+    pub fn codepoint(&self) -> EcnCodepoint {
+        match self.state {
+            EcnState::Testing | EcnState::Capable => EcnCodepoint::Ect0,
+            EcnState::Unknown | EcnState::Failed => EcnCodepoint::NotEct,
+        }
+    }
+
+    pub fn on_sent(&mut self, space: Epoch, ecn: EcnCodepoint) {
+        if ecn != EcnCodepoint::Ect0 {
+            return;
+        }
+        self.ect0_sent[space] += 1;
+        if self.state == EcnState::Testing {
+            self.testing_sent += 1;
+            if self.testing_sent >= TESTING_PACKETS {
+                self.state = EcnState::Unknown;
+            }
+        }
+    }
+
+    pub fn on_lost(&mut self, ecn: EcnCodepoint) {
+        if ecn != EcnCodepoint::Ect0 || !matches!(self.state, EcnState::Testing | EcnState::Unknown)
+        {
+            return;
+        }
+        self.testing_lost += 1;
+        // 测试的包全部丢失，可能是路径丢弃了带ECN标记的包
+        if self.state == EcnState::Unknown && self.testing_lost >= self.testing_sent {
+            self.fail("all the ECT(0) marked packets in testing are lost");
+        }
+    }
+
+    /// Validate the ECN counts in an ACK frame of `space`, which newly acknowledges
+    /// `newly_acked_ect0` packets sent with ECT(0), return the increase of the CE count
+    /// if the counts are valid.
+    pub fn on_ack(
+        &mut self,
+        space: Epoch,
+        ecn: Option<EcnCounts>,
+        newly_acked_ect0: u64,
+    ) -> Option<u64> {
+        if self.state == EcnState::Failed {
+            return None;
+        }
+        let Some(ecn) = ecn else {
+            if newly_acked_ect0 > 0 {
+                self.fail("ECN counts are missing for the ECT(0) marked packets");
+            }
+            return None;
+        };
+        let (ect0, ect1, ce) = (
+            ecn.ect0.into_inner(),
+            ecn.ect1.into_inner(),
+            ecn.ce.into_inner(),
+        );
+        let (prev_ect0, prev_ect1, prev_ce) = self.reported[space];
+        if ect0 < prev_ect0 || ect1 < prev_ect1 || ce < prev_ce {
+            self.fail("ECN counts decreased");
+            return None;
+        }
+        if ect1 > 0 {
+            self.fail("ECT(1) is reported, but never sent");
+            return None;
+        }
+        if ect0 + ce > self.ect0_sent[space] {
+            self.fail("ECN counts exceed the marked packets sent");
+            return None;
+        }
+        if (ect0 - prev_ect0) + (ce - prev_ce) < newly_acked_ect0 {
+            self.fail("ECT(0) marks are cleared on the path");
+            return None;
+        }
+        self.reported[space] = (ect0, ect1, ce);
+        if newly_acked_ect0 > 0 && matches!(self.state, EcnState::Testing | EcnState::Unknown) {
+            log::debug!("ECN is validated on the path");
+            self.state = EcnState::Capable;
+        }
+        Some(ce - prev_ce)
+    }
+
+    fn fail(&mut self, reason: &str) {
+        log::debug!("ECN validation failed: {reason}, disable ECN on the path");
+        self.state = EcnState::Failed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::varint::VarInt;
+
+    use super::*;
+
+    fn counts(ect0: u32, ect1: u32, ce: u32) -> Option<EcnCounts> {
+        Some(EcnCounts {
+            ect0: VarInt::from_u32(ect0),
+            ect1: VarInt::from_u32(ect1),
+            ce: VarInt::from_u32(ce),
+        })
+    }
+
+    fn send(validator: &mut EcnValidator, n: u64) {
+        for _ in 0..n {
+            let ecn = validator.codepoint();
+            validator.on_sent(Epoch::Data, ecn);
+        }
+    }
+
+    #[test]
+    fn test_testing_to_capable() {
+        let mut validator = EcnValidator::default();
+        send(&mut validator, TESTING_PACKETS);
+        assert_eq!(validator.state(), EcnState::Unknown);
+        assert_eq!(validator.codepoint(), EcnCodepoint::NotEct);
+        // 已经超出测试包数的不再标记
+        send(&mut validator, 5);
+
+        assert_eq!(validator.on_ack(Epoch::Data, counts(3, 0, 1), 4), Some(1));
+        assert_eq!(validator.state(), EcnState::Capable);
+        assert_eq!(validator.codepoint(), EcnCodepoint::Ect0);
+        assert_eq!(validator.on_ack(Epoch::Data, counts(6, 0, 1), 3), Some(0));
+    }
+
+    #[test]
+    fn test_forged_counts() {
+        let mut validator = EcnValidator::default();
+        send(&mut validator, 4);
+        assert_eq!(validator.on_ack(Epoch::Data, counts(8, 0, 0), 4), None);
+        assert_eq!(validator.state(), EcnState::Failed);
+        assert_eq!(validator.codepoint(), EcnCodepoint::NotEct);
+    }
+
+    #[test]
+    fn test_decreasing_counts() {
+        let mut validator = EcnValidator::default();
+        send(&mut validator, 4);
+        assert_eq!(validator.on_ack(Epoch::Data, counts(2, 0, 1), 3), Some(1));
+        assert_eq!(validator.on_ack(Epoch::Data, counts(3, 0, 0), 1), None);
+        assert_eq!(validator.state(), EcnState::Failed);
+        // 失败之后的计数一概忽略
+        assert_eq!(validator.on_ack(Epoch::Data, counts(4, 0, 0), 1), None);
+    }
+
+    #[test]
+    fn test_bleached_marks() {
+        let mut validator = EcnValidator::default();
+        send(&mut validator, 4);
+        assert_eq!(validator.on_ack(Epoch::Data, None, 2), None);
+        assert_eq!(validator.state(), EcnState::Failed);
+
+        let mut validator = EcnValidator::default();
+        send(&mut validator, 4);
+        assert_eq!(validator.on_ack(Epoch::Data, counts(1, 0, 0), 2), None);
+        assert_eq!(validator.state(), EcnState::Failed);
+
+        let mut validator = EcnValidator::default();
+        send(&mut validator, 4);
+        assert_eq!(validator.on_ack(Epoch::Data, counts(0, 2, 0), 2), None);
+        assert_eq!(validator.state(), EcnState::Failed);
+    }
+
+    #[test]
+    fn test_testing_packets_lost() {
+        let mut validator = EcnValidator::default();
+        send(&mut validator, TESTING_PACKETS);
+        for _ in 0..TESTING_PACKETS - 1 {
+            validator.on_lost(EcnCodepoint::Ect0);
+        }
+        assert_eq!(validator.state(), EcnState::Unknown);
+        validator.on_lost(EcnCodepoint::Ect0);
+        assert_eq!(validator.state(), EcnState::Failed);
+    }
+}
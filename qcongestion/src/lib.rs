@@ -3,11 +3,14 @@ use std::{
     time::{Duration, Instant},
 };
 
+use congestion::{PacketClass, PathMetrics};
+use ecn::{EcnCodepoint, EcnState};
 use qbase::frame::AckFrame;
 use qrecovery::space::Epoch;
 
 pub mod bbr;
 pub mod congestion;
+pub mod ecn;
 pub mod new_reno;
 pub mod rtt;
 pub use rtt::RawRtt;
@@ -16,17 +19,30 @@ pub mod min_max;
 pub mod pacing;
 
 pub trait CongestionControl {
-    /// 驱动 congestion control 算法
+    /// 驱动 congestion control 算法，处理到期的丢包检测定时器；可以发包了才唤醒发送任务
     fn do_tick(&self);
 
+    /// 距离下次需要调用do_tick的时长：pacer放出下一块额度，或者丢包检测定时器到期，取其早者。
+    /// 已有额度的不算，都没有则为Duration::MAX；不短于定时器的粒度，以免驱动空转
+    fn max_wait(&self) -> Duration;
+
     /// 轮询是否可以发包，若可以，返回可以发包的数据量；该数据量包含各个空间的包能发的数据量总和
+    /// pacer按块放行，一块额度是一批包，发送任务应一次组装好，借GSO一并发出
     /// 如果返回0，代表着结束，不再发包，并停止循环
+    /// 返回Pending时记下waker，收到确认、do_tick发现pacer放出了额度、发包后仍有额度时唤醒
     fn poll_send(&self, cx: &mut Context<'_>) -> Poll<usize>;
 
     /// 发某个空间的包时，询问是否需要发送AckFrame，若需要，返回该Path接收的最大包id及其接收时间
     /// 不需要的话，则返回None。每次需要发包，每个Epoch都需要询问
     fn need_ack(&self, space: Epoch) -> Option<(u64, Instant)>;
 
+    /// PTO触发后，发某个空间的包时，询问是否需要发探测包。若需要，而该空间又没有ack-eliciting的帧可发，
+    /// 就发一个PING帧。探测包不受拥塞窗口限制，发出ack-eliciting的包即消耗一个
+    fn need_probe(&self, space: Epoch) -> bool;
+
+    /// 一轮发包结束，仍未发出的探测包作废，比如该空间的密钥尚未到手，免得poll_send一直绕过拥塞窗口
+    fn discard_probes(&self);
+
     /* 下面发送PathChallenge和PathResponse帧，像是Path的，单独抽象在另外一个trait比较合适
     /// 发数据空间的包时，询问是否需要发送PathChallengeFrame，可在0RTT和1RTT数据包内发送
     fn need_path_challenge(&self) -> Option<PathChallengeFrame>;
@@ -38,6 +54,9 @@ pub trait CongestionControl {
     /// 每当发送一个数据包后，由Path的cc记录发包信息，供未来确认时计算RTT和发送速率，并减少发送信用
     /// 最后一个参数，是这次发包是否携带了ack frame，若没携带，是None；若携带了，则是ack frame的最大包号
     /// 若有Ack信息，也要记录下来。未来该包被确认，那么该AckFrame中largest之前的，接收到的包，通知ack观察者失活
+    /// ecn是该包所在数据报的ECN标记，用于验证对方报告的ECN计数
+    /// class是该包的用途，PMTU探测包、路径验证的探测包不计入带宽估计，也不扩大拥塞窗口
+    #[allow(clippy::too_many_arguments)]
     fn on_pkt_sent(
         &self,
         epoch: Epoch,
@@ -46,6 +65,8 @@ pub trait CongestionControl {
         sent_bytes: usize,
         in_flight: bool,
         ack: Option<u64>,
+        ecn: EcnCodepoint,
+        class: PacketClass,
     );
 
     /// 当收到AckFrame，其中有该Path的部分包被确认，调用该函数，驱动拥塞控制算法演进
@@ -64,9 +85,27 @@ pub trait CongestionControl {
     /// 获取当前 path 的 pto time
     fn pto_time(&self, epoch: Epoch) -> Duration;
 
+    /// 获取当前 path 的平滑rtt
+    fn smoothed_rtt(&self) -> Duration;
+
+    /// 接下来发送的数据报应带的ECN标记，同一批数据报用同一个标记
+    fn ecn_codepoint(&self) -> EcnCodepoint;
+
+    /// 当前 path 的ECN验证状态
+    fn ecn_state(&self) -> EcnState;
+
+    /// 当前 path 拥塞控制状态的快照，不持有任何锁，可以定期轮询用于监控
+    fn metrics(&self) -> PathMetrics;
+
     /// 更新握手密钥状态
     fn on_get_handshake_keys(&self);
 
     /// 握手完成
     fn on_handshake_done(&self);
+
+    /// 拥塞窗口允许，却没有数据可发，此后的带宽采样受限于应用，不代表路径的能力
+    fn on_app_limited(&self);
+
+    /// 某空间的密钥已丢弃，其在途的包不再计入在途数据量，也不再判定丢失或触发PTO，之后在该空间发的包也不再记录
+    fn on_pkt_space_discarded(&self, epoch: Epoch);
 }
@@ -1,6 +1,6 @@
 use std::{collections::VecDeque, time::Instant};
 
-use crate::congestion::{AckedPkt, Algorithm, MSS};
+use crate::congestion::{AckedPkt, Algorithm, Handover, MSS};
 
 // The upper bound for the initial window will be
 // min (10*MSS, max (2*MSS, 14600))
@@ -8,6 +8,7 @@ use crate::congestion::{AckedPkt, Algorithm, MSS};
 const INIT_CWND: u64 = 10 * MSS as u64;
 const INFINITRE_SSTHRESH: u64 = u64::MAX;
 const LOSS_REDUCTION_FACTOR: f64 = 0.5;
+const MINIMUM_WINDOW: u64 = 2 * MSS as u64;
 
 pub(super) struct NewReno {
     // Congestion window.
@@ -38,6 +39,18 @@ impl NewReno {
             .unwrap_or(false)
     }
 
+    fn enter_recovery(&mut self, sent_time: Instant, now: Instant) {
+        if self.in_congestion_recovery(&sent_time) {
+            return;
+        }
+        self.recovery_start_time = Some(now);
+        self.cwnd = (self.cwnd as f64 * LOSS_REDUCTION_FACTOR) as u64;
+        self.cwnd = self.cwnd.max(MINIMUM_WINDOW);
+
+        self.bytes_acked = (self.bytes_acked as f64 * LOSS_REDUCTION_FACTOR) as u64;
+        self.ssthresh = self.cwnd;
+    }
+
     fn on_per_ack(&mut self, ack: &AckedPkt) {
         if self.in_congestion_recovery(&ack.time_sent) {
             return;
@@ -68,21 +81,25 @@ impl Algorithm for NewReno {
     fn on_sent(&mut self, _: &mut crate::congestion::SentPkt, _: usize, _: std::time::Instant) {}
 
     fn on_ack(&mut self, packet: VecDeque<AckedPkt>, _: std::time::Instant) {
-        for acked in packet {
-            self.on_per_ack(&acked);
+        // 探测包的确认不扩大窗口
+        for acked in packet.iter().filter(|acked| !acked.class.is_probe()) {
+            self.on_per_ack(acked);
         }
     }
 
     fn on_congestion_event(&mut self, lost: &crate::congestion::SentPkt, now: std::time::Instant) {
-        if self.in_congestion_recovery(&lost.time_sent) {
-            return;
-        }
-        self.recovery_start_time = Some(now);
-        self.cwnd = (self.cwnd as f64 * LOSS_REDUCTION_FACTOR) as u64;
-        self.cwnd = self.cwnd.max(2 * MSS as u64);
+        self.enter_recovery(lost.time_sent, now);
+    }
 
-        self.bytes_acked = (self.bytes_acked as f64 * LOSS_REDUCTION_FACTOR) as u64;
-        self.ssthresh = self.cwnd;
+    // ECN-CE与丢包一样，每个恢复期只减一次窗口
+    fn on_ecn_ce(&mut self, sent_time: Instant, now: Instant) {
+        self.enter_recovery(sent_time, now);
+    }
+
+    // 窗口收缩到最小，仍处于丢包开启的恢复期内，宣告丢包的这个ACK不会立即扩大窗口
+    fn on_persistent_congestion(&mut self, _: Instant) {
+        self.cwnd = MINIMUM_WINDOW;
+        self.bytes_acked = 0;
     }
 
     fn cwnd(&self) -> u64 {
@@ -92,6 +109,37 @@ impl Algorithm for NewReno {
     fn pacing_rate(&self) -> Option<u64> {
         None
     }
+
+    fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    fn ssthresh(&self) -> Option<u64> {
+        (self.ssthresh != INFINITRE_SSTHRESH).then_some(self.ssthresh)
+    }
+
+    // 慢启动回到旧算法的窗口，之后进入拥塞避免
+    fn inherit(&mut self, handover: &Handover, _: std::time::Instant) {
+        self.cwnd = handover.cwnd.min(INIT_CWND).max(MINIMUM_WINDOW);
+        self.ssthresh = handover.cwnd.max(self.cwnd);
+        self.bytes_acked = 0;
+        self.recovery_start_time = None;
+    }
+
+    // RFC 5681 4.1：重启窗口为min(IW, cwnd)；慢启动阈值至少保留原窗口的3/4，
+    // 以便慢启动尽快回到空闲之前的水平，见RFC 2861
+    fn on_idle_restart(&mut self, _: Instant) {
+        if self.cwnd <= INIT_CWND {
+            return;
+        }
+        self.ssthresh = if self.ssthresh == INFINITRE_SSTHRESH {
+            self.cwnd * 3 / 4
+        } else {
+            self.ssthresh.max(self.cwnd * 3 / 4)
+        };
+        self.cwnd = INIT_CWND;
+        self.bytes_acked = 0;
+    }
 }
 
 #[cfg(test)]
@@ -171,6 +219,62 @@ mod tests {
         assert_eq!(reno.recovery_start_time, Some(time_lost));
     }
 
+    #[test]
+    fn test_reno_ecn_ce() {
+        let mut reno = NewReno::new();
+        let now = Instant::now();
+        reno.on_ack(generate_acks(0, 10), now);
+        assert_eq!(reno.cwnd, 20 * MSS as u64);
+
+        let time_marked = now + std::time::Duration::from_millis(100);
+        reno.on_ecn_ce(now, time_marked);
+        assert_eq!(reno.cwnd, 10 * MSS as u64);
+        assert_eq!(reno.ssthresh, 10 * MSS as u64);
+
+        // 同一恢复期内发出的包再被标记，不再减窗
+        reno.on_ecn_ce(time_marked, time_marked);
+        assert_eq!(reno.cwnd, 10 * MSS as u64);
+    }
+
+    #[test]
+    fn test_reno_inherit() {
+        let mut reno = NewReno::new();
+        let handover = Handover {
+            cwnd: 100 * MSS as u64,
+            bytes_in_flight: 50 * MSS as u64,
+            pacing_rate: 0,
+            min_rtt: None,
+        };
+        reno.inherit(&handover, Instant::now());
+        // 从初始窗口慢启动，回到旧算法的窗口后进入拥塞避免
+        assert_eq!(reno.cwnd, INIT_CWND);
+        assert_eq!(reno.ssthresh, 100 * MSS as u64);
+
+        let handover = Handover {
+            cwnd: 4 * MSS as u64,
+            ..handover
+        };
+        reno.inherit(&handover, Instant::now());
+        assert_eq!(reno.cwnd, 4 * MSS as u64);
+        assert_eq!(reno.ssthresh, 4 * MSS as u64);
+    }
+
+    #[test]
+    fn test_reno_idle_restart() {
+        let mut reno = NewReno::new();
+        reno.cwnd = 100 * MSS as u64;
+        reno.on_idle_restart(Instant::now());
+        assert_eq!(reno.cwnd, INIT_CWND);
+        assert_eq!(reno.ssthresh, 75 * MSS as u64);
+        assert!(reno.in_slow_start());
+
+        // 窗口本就不大于初始窗口，不变
+        reno.cwnd = MINIMUM_WINDOW;
+        reno.on_idle_restart(Instant::now());
+        assert_eq!(reno.cwnd, MINIMUM_WINDOW);
+        assert_eq!(reno.ssthresh, 75 * MSS as u64);
+    }
+
     fn generate_acks(start: usize, end: usize) -> VecDeque<AckedPkt> {
         let mut acks = VecDeque::with_capacity(end - start);
         for i in start..end {
@@ -4,16 +4,77 @@ use std::time::{Duration, Instant};
 const BURST_INTERVAL: Duration = Duration::from_millis(1);
 const MIN_BURST_SIZE: u64 = 10;
 const MAX_BURST_SIZE: u64 = 128;
+// 低于10Mbps的速率逐包放行，攒一批的等待得不偿失
+const PER_PACKET_RATE: u64 = 10_000_000 / 8;
+// 一块额度不超过一次GSO所能发出的64KB
+const MAX_CHUNK_SIZE: u64 = 64 * 1024;
+const DEFAULT_CHUNK_PACKETS: u64 = 10;
 // Using a value for N that is small, but at least 1 (for example, 1.25)
 // ensures that variations in RTT do not result in underutilization of the congestion window.
-const N: f64 = 1.25;
+pub(super) const N: f64 = 1.25;
+
+/// How the packets of a path are paced, see
+/// [section 7.7](https://www.rfc-editor.org/rfc/rfc9002.html#name-pacing) of RFC 9002.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacingConfig {
+    /// The most packets sent back to back at once, the tokens of the pacer never exceed
+    /// `burst_packets` packets. 0 is taken as 1.
+    pub burst_packets: u64,
+    /// The pacing rate is `gain * cwnd / smoothed_rtt` in slow start. The algorithms with
+    /// their own pacing rate, like BBR, ignore the gains.
+    pub gain_slow_start: f64,
+    /// The pacing rate is `gain * cwnd / smoothed_rtt` in congestion avoidance.
+    pub gain_congestion_avoidance: f64,
+    /// What happens to the congestion window once the path has sent nothing for a
+    /// retransmission timeout, [`IdleRestart::Restart`] by default.
+    pub idle_restart: IdleRestart,
+    /// The most packets the pacer releases at once, to be sent in one GSO batch. 0 is
+    /// taken as 1, which paces packet by packet.
+    ///
+    /// The pacer waits for a whole chunk of tokens before releasing any, so the average
+    /// rate is kept while the packets go out in batches. A chunk is one packet below
+    /// 10 Mbps, and grows with the pacing rate to what is sent in a millisecond, but no
+    /// more than an eighth of the congestion window, 64 KB, or `chunk_packets` packets.
+    pub chunk_packets: u64,
+}
+
+/// How a path resumes sending after being idle, the congestion window grown before may
+/// no longer reflect the path, see
+/// [section 7.8](https://www.rfc-editor.org/rfc/rfc9002.html#name-underutilizing-the-congesti)
+/// of RFC 9002.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IdleRestart {
+    /// Shrink the congestion window back to the initial window of the algorithm if it's
+    /// larger, as the restart window of
+    /// [section 4.1](https://www.rfc-editor.org/rfc/rfc5681.html#section-4.1) of RFC 5681.
+    #[default]
+    Restart,
+    /// Keep the congestion window, and rely on the pacer to spread the packets out, the
+    /// burst after idle is still bounded by [`PacingConfig::burst_packets`].
+    Pace,
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        Self {
+            burst_packets: MAX_BURST_SIZE,
+            gain_slow_start: N,
+            gain_congestion_avoidance: N,
+            idle_restart: IdleRestart::Restart,
+            chunk_packets: DEFAULT_CHUNK_PACKETS,
+        }
+    }
+}
 
 pub(super) struct Pacer {
     capacity: u64,
+    // 每次放行的额度，攒够了才放行
+    chunk: u64,
     cwnd: u64,
     tokens: u64,
     last_burst_time: Instant,
     rate: Option<u64>,
+    config: PacingConfig,
 }
 
 impl Pacer {
@@ -24,22 +85,48 @@ impl Pacer {
         now: Instant,
         rate: Option<u64>,
     ) -> Self {
-        let capacity = Pacer::calculate_capacity(smoothed_rtt, cwnd, mtu, rate);
+        let config = PacingConfig::default();
+        let capacity = Pacer::calculate_capacity(smoothed_rtt, cwnd, mtu, rate, &config);
 
-        Pacer {
+        let mut pacer = Pacer {
             capacity,
+            chunk: mtu as u64,
             cwnd,
             tokens: capacity,
             last_burst_time: now,
             rate,
-        }
+            config,
+        };
+        // 新建的路径处于慢启动
+        pacer.chunk = pacer.calculate_chunk(smoothed_rtt, cwnd, mtu, rate, true);
+        pacer
+    }
+
+    // 新的配置立即生效，突发上限调小时，多出的令牌作废
+    pub(super) fn set_config(
+        &mut self,
+        config: PacingConfig,
+        smoothed_rtt: Duration,
+        cwnd: u64,
+        mtu: usize,
+    ) {
+        self.config = config;
+        self.capacity = Pacer::calculate_capacity(smoothed_rtt, cwnd, mtu, self.rate, &config);
+        self.chunk = self.chunk.min(config.chunk_packets.max(1) * mtu as u64);
+        self.tokens = self.tokens.min(self.capacity);
+        self.cwnd = cwnd;
+    }
+
+    pub(super) fn config(&self) -> &PacingConfig {
+        &self.config
     }
 
     pub(super) fn on_sent(&mut self, packet_size: u64) {
         self.tokens = self.tokens.saturating_sub(packet_size);
     }
 
-    // Schedule and return the the packet size to send, max size is mtu
+    // Schedule and return the tokens to send, at most a chunk. Less than a chunk is not a
+    // permit yet, see Pacer::has_permit
     pub(super) fn schedule(
         &mut self,
         srtt: Duration,
@@ -47,25 +134,22 @@ impl Pacer {
         mtu: usize,
         now: Instant,
         rate: Option<u64>,
+        in_slow_start: bool,
     ) -> usize {
         // Update capacity if cwnd or rate has changed
         if self.cwnd != cwnd || rate != self.rate {
-            self.capacity = Pacer::calculate_capacity(srtt, cwnd, mtu, rate);
+            self.capacity = Pacer::calculate_capacity(srtt, cwnd, mtu, rate, &self.config);
             self.tokens = self.tokens.min(self.capacity);
         }
 
         self.cwnd = cwnd;
         self.rate = rate;
-        if self.tokens > mtu as u64 {
-            return mtu;
+        self.chunk = self.calculate_chunk(srtt, cwnd, mtu, rate, in_slow_start);
+        if self.tokens >= self.chunk {
+            return self.chunk as usize;
         }
 
-        let rate = match rate {
-            Some(r) => r,
-            // RFC 9002 7.7. Pacing
-            // rate = N * congestion_window / smoothed_rtt
-            None => (N * cwnd as f64 / srtt.as_secs_f64()) as u64,
-        };
+        let rate = self.rate(srtt, cwnd, rate, in_slow_start);
 
         // Update the last_burst_time and tokens
         let elapsed = now.duration_since(self.last_burst_time);
@@ -76,10 +160,94 @@ impl Pacer {
             .min(self.capacity);
         self.last_burst_time = now;
 
-        self.tokens.min(mtu as u64) as usize
+        self.tokens.min(self.chunk) as usize
+    }
+
+    pub(super) fn chunk(&self) -> u64 {
+        self.chunk
     }
 
-    fn calculate_capacity(smoothed_rtt: Duration, cwnd: u64, mtu: usize, rate: Option<u64>) -> u64 {
+    // 令牌攒够了一块，可以放行一批包
+    pub(super) fn has_permit(&self) -> bool {
+        self.tokens >= self.chunk
+    }
+
+    // 距离令牌攒够一块还需多久，令牌已经够了的为零；速率为零则永远攒不够
+    pub(super) fn time_until_credit(
+        &self,
+        srtt: Duration,
+        cwnd: u64,
+        mtu: usize,
+        now: Instant,
+        rate: Option<u64>,
+        in_slow_start: bool,
+    ) -> Duration {
+        let chunk = self.calculate_chunk(srtt, cwnd, mtu, rate, in_slow_start);
+        if self.tokens >= chunk {
+            return Duration::ZERO;
+        }
+        let rate = self.rate(srtt, cwnd, rate, in_slow_start);
+        if rate == 0 {
+            return Duration::MAX;
+        }
+        let lacking = (chunk - self.tokens) as f64 / rate as f64;
+        Duration::from_secs_f64(lacking)
+            .saturating_sub(now.saturating_duration_since(self.last_burst_time))
+    }
+
+    // The pacing rate in bytes per second, the algorithm's own rate if it paces
+    pub(super) fn rate(
+        &self,
+        srtt: Duration,
+        cwnd: u64,
+        rate: Option<u64>,
+        in_slow_start: bool,
+    ) -> u64 {
+        match rate {
+            Some(r) => r,
+            // RFC 9002 7.7. Pacing
+            // rate = N * congestion_window / smoothed_rtt
+            None => {
+                let gain = if in_slow_start {
+                    self.config.gain_slow_start
+                } else {
+                    self.config.gain_congestion_avoidance
+                };
+                (gain * cwnd as f64 / srtt.as_secs_f64()) as u64
+            }
+        }
+    }
+
+    // 速率越高，一块越大：低于10Mbps时逐包，此后为BURST_INTERVAL内的发送量，不超过cwnd的1/8、
+    // 64KB、配置的包数以及突发上限，按整包取整
+    fn calculate_chunk(
+        &self,
+        srtt: Duration,
+        cwnd: u64,
+        mtu: usize,
+        rate: Option<u64>,
+        in_slow_start: bool,
+    ) -> u64 {
+        let mtu = mtu as u64;
+        let rate = self.rate(srtt, cwnd, rate, in_slow_start);
+        if rate < PER_PACKET_RATE {
+            return mtu;
+        }
+        let chunk = ((rate as f64 * BURST_INTERVAL.as_secs_f64()) as u64)
+            .min(cwnd / 8)
+            .min(MAX_CHUNK_SIZE)
+            .min(self.config.chunk_packets.max(1) * mtu)
+            .min(self.capacity);
+        (chunk / mtu).max(1) * mtu
+    }
+
+    fn calculate_capacity(
+        smoothed_rtt: Duration,
+        cwnd: u64,
+        mtu: usize,
+        rate: Option<u64>,
+        config: &PacingConfig,
+    ) -> u64 {
         let rtt = smoothed_rtt.as_nanos().max(1);
 
         let capacity = match rate {
@@ -89,8 +257,10 @@ impl Pacer {
             None => ((cwnd as u128 * BURST_INTERVAL.as_nanos()) / rtt) as u64,
         };
 
-        // capacity between [15KB,192KB]
-        capacity.clamp(MIN_BURST_SIZE * mtu as u64, MAX_BURST_SIZE * mtu as u64)
+        // capacity between [15KB,192KB]，且不超过配置的突发上限
+        capacity
+            .clamp(MIN_BURST_SIZE * mtu as u64, MAX_BURST_SIZE * mtu as u64)
+            .min(config.burst_packets.max(1) * mtu as u64)
     }
 }
 
@@ -162,29 +332,31 @@ mod tests {
         // rate  = 1.25 * cwnd / srtt
         // after 2 ms
         update_time += BURST_INTERVAL * 2;
-        let packet_size = pacer.schedule(srtt, cwnd, mtu, update_time, None);
+        let permit = pacer.schedule(srtt, cwnd, mtu, update_time, None, false);
 
+        // 25MB/s，一块为10个包
         assert_eq!(pacer.tokens, 20_000);
-        assert_eq!(packet_size, 1500);
+        assert_eq!(pacer.chunk, 15_000);
+        assert_eq!(permit, 15_000);
         pacer.on_sent(1500 * 13);
 
         assert_eq!(pacer.tokens, 500);
 
         // add token
         update_time += BURST_INTERVAL;
-        let packet_size = pacer.schedule(srtt, cwnd, mtu, update_time, None);
+        let permit = pacer.schedule(srtt, cwnd, mtu, update_time, None, false);
 
         // burst interval add token 25000
         assert_eq!(pacer.capacity, 20_000);
         assert_eq!(pacer.tokens, 20_000);
-        assert_eq!(packet_size, 1500);
+        assert_eq!(permit, 15_000);
 
         // change cwnd, change capacity
         cwnd = 1_500_000; // 1.5 MB
-        let packet_size = pacer.schedule(srtt, cwnd, mtu, update_time, None);
+        let permit = pacer.schedule(srtt, cwnd, mtu, update_time, None, false);
         assert_eq!(pacer.capacity, 15_000);
         assert_eq!(pacer.tokens, 15_000);
-        assert_eq!(packet_size, 1500);
+        assert_eq!(permit, 15_000);
     }
 
     #[test]
@@ -199,21 +371,186 @@ mod tests {
         let mut pacer = Pacer::new(srtt, cwnd, mtu, update_time, rate);
         assert_eq!(pacer.capacity, 16_000);
 
-        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate);
-        assert_eq!(size, 1500);
+        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate, false);
+        assert_eq!(size, 15_000);
         pacer.on_sent(15_000);
-        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate);
+        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate, false);
         assert_eq!(size, 1_000);
 
         // udpate rate to update capacity
-        // 1 MB
+        // 1 MB，低于10Mbps，逐包放行
         rate = Some(1_000_000);
-        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate);
+        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate, false);
         assert_eq!(size, 1_000);
+        assert_eq!(pacer.chunk, 1500);
         assert_eq!(pacer.capacity, 15_000);
         update_time += BURST_INTERVAL;
-        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate);
+        let size = pacer.schedule(srtt, cwnd, mtu, update_time, rate, false);
         assert_eq!(pacer.tokens, 2000);
         assert_eq!(size, 1500);
     }
+
+    #[test]
+    fn test_burst_cap() {
+        let srtt = Duration::from_millis(100);
+        let cwnd = 2_000_000;
+        let mtu: usize = 1500;
+        let now = Instant::now();
+        let mut pacer = Pacer::new(srtt, cwnd, mtu, now, None);
+        pacer.set_config(
+            PacingConfig {
+                burst_packets: 4,
+                ..PacingConfig::default()
+            },
+            srtt,
+            cwnd,
+            mtu,
+        );
+        assert_eq!(pacer.capacity, 4 * 1500);
+
+        // 时间不前进，至多连续发出4个包
+        let mut burst = 0;
+        while pacer.schedule(srtt, cwnd, mtu, now, None, false) >= mtu {
+            pacer.on_sent(mtu as u64);
+            burst += 1;
+        }
+        assert_eq!(burst, 4);
+
+        // 稳定状态下，无论空闲多久，攒下的令牌都不超过突发上限
+        let mut now = now;
+        for _ in 0..10 {
+            now += Duration::from_secs(1);
+            let mut burst = 0;
+            while pacer.schedule(srtt, cwnd, mtu, now, None, false) >= mtu {
+                pacer.on_sent(mtu as u64);
+                burst += 1;
+            }
+            assert_eq!(burst, 4);
+        }
+    }
+
+    #[test]
+    fn test_time_until_credit() {
+        let srtt = Duration::from_millis(100);
+        let cwnd = 2_000_000;
+        let mtu: usize = 1500;
+        let now = Instant::now();
+        let mut pacer = Pacer::new(srtt, cwnd, mtu, now, None);
+        assert_eq!(
+            pacer.time_until_credit(srtt, cwnd, mtu, now, None, false),
+            Duration::ZERO
+        );
+
+        // rate = 1.25 * 2MB / 100ms = 25MB/s，攒够一块10个包要600us
+        pacer.on_sent(20_000);
+        let wait = pacer.time_until_credit(srtt, cwnd, mtu, now, None, false);
+        assert!(wait.as_nanos().abs_diff(600_000) <= 1);
+        let later = now + Duration::from_micros(20);
+        let wait = pacer.time_until_credit(srtt, cwnd, mtu, later, None, false);
+        assert!(wait.as_nanos().abs_diff(580_000) <= 1);
+        // 算法自己的速率优先，1.5MB/s时一块只有一个包
+        let wait = pacer.time_until_credit(srtt, cwnd, mtu, now, Some(1_500_000), false);
+        assert!(wait.as_nanos().abs_diff(1_000_000) <= 1);
+        assert_eq!(
+            pacer.time_until_credit(srtt, cwnd, mtu, now, Some(0), false),
+            Duration::MAX
+        );
+    }
+
+    #[test]
+    fn test_gain_spacing() {
+        let srtt = Duration::from_millis(100);
+        // rate = gain * 1_500_000 / 0.1s
+        let cwnd = 1_500_000;
+        let mtu: usize = 1500;
+        // 逐包放行，才能量出包之间的间隔
+        let config = PacingConfig {
+            burst_packets: 10,
+            gain_slow_start: 2.0,
+            gain_congestion_avoidance: 1.0,
+            chunk_packets: 1,
+            ..PacingConfig::default()
+        };
+
+        // 令牌用完之后，按速率补充，返回相邻两次可以发出一个MTU的时间间隔
+        let spacing = |in_slow_start: bool| {
+            let start = Instant::now();
+            let mut pacer = Pacer::new(srtt, cwnd, mtu, start, None);
+            pacer.set_config(config, srtt, cwnd, mtu);
+            pacer.on_sent(pacer.capacity);
+
+            let mut now = start;
+            let mut sent_times = Vec::new();
+            while sent_times.len() < 5 {
+                now += Duration::from_micros(10);
+                if pacer.schedule(srtt, cwnd, mtu, now, None, in_slow_start) >= mtu {
+                    pacer.on_sent(mtu as u64);
+                    sent_times.push(now);
+                }
+            }
+            let spacings = sent_times
+                .windows(2)
+                .map(|w| w[1] - w[0])
+                .collect::<Vec<_>>();
+            // 间隔均匀
+            assert!(spacings
+                .iter()
+                .all(|s| s.abs_diff(spacings[0]) <= Duration::from_micros(10)));
+            spacings[0]
+        };
+
+        // 拥塞避免：15MB/s，每100us一个MTU；慢启动的增益加倍，间隔减半
+        let avoidance = spacing(false);
+        assert!(avoidance.abs_diff(Duration::from_micros(100)) <= Duration::from_micros(10));
+        let slow_start = spacing(true);
+        assert!(slow_start.abs_diff(Duration::from_micros(50)) <= Duration::from_micros(10));
+    }
+
+    #[test]
+    fn test_chunked_pacing() {
+        let srtt = Duration::from_millis(100);
+        let cwnd = 2_000_000;
+        let mtu: usize = 1500;
+
+        // 以10us为步长模拟1秒，发送方总有数据可发，放行一块就一次发完；返回平均速率和最大的一批
+        let simulate = |rate: u64| {
+            let start = Instant::now();
+            let mut pacer = Pacer::new(srtt, cwnd, mtu, start, Some(rate));
+            pacer.on_sent(pacer.capacity);
+
+            let (mut now, mut sent, mut max_batch) = (start, 0, 0);
+            let end = start + Duration::from_secs(1);
+            while now < end {
+                now += Duration::from_micros(10);
+                let permit = pacer.schedule(srtt, cwnd, mtu, now, Some(rate), false) as u64;
+                if pacer.has_permit() {
+                    pacer.on_sent(permit);
+                    sent += permit;
+                    max_batch = max_batch.max(permit);
+                }
+            }
+            (sent, max_batch, pacer.chunk)
+        };
+
+        // 低于10Mbps，逐包放行
+        let rate = 500_000;
+        let (sent, max_batch, chunk) = simulate(rate);
+        assert_eq!(chunk, mtu as u64);
+        assert_eq!(max_batch, mtu as u64);
+        assert!(sent.abs_diff(rate) <= rate / 20);
+
+        // 100Mbps，一毫秒的量凑成一批
+        let rate = 12_500_000;
+        let (sent, max_batch, chunk) = simulate(rate);
+        assert_eq!(chunk, 12_000);
+        assert_eq!(max_batch, chunk);
+        assert!(sent.abs_diff(rate) <= rate / 20);
+
+        // 1Gbps，一批不超过配置的包数，且不超过64KB
+        let rate = 125_000_000;
+        let (sent, max_batch, chunk) = simulate(rate);
+        assert_eq!(chunk, DEFAULT_CHUNK_PACKETS * mtu as u64);
+        assert!(max_batch <= chunk && chunk <= MAX_CHUNK_SIZE);
+        assert!(sent.abs_diff(rate) <= rate / 20);
+    }
 }
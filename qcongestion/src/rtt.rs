@@ -5,57 +5,119 @@ use std::{
 
 pub const INITIAL_RTT: Duration = Duration::from_millis(333);
 const GRANULARITY: Duration = Duration::from_millis(1);
-const TIME_THRESHOLD: f32 = 1.125;
+// 传输参数的默认值，收到对端的传输参数之前使用
+const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+const DEFAULT_MAX_ACK_DELAY: Duration = Duration::from_millis(25);
+/// The window of the min_rtt, a larger RTT sample replaces the min_rtt older than it, so
+/// that the increase of the RTT after a path change is noticed.
+pub const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
+/// How much the ack_delay reported by the peer may exceed its max_ack_delay, for the
+/// granularity of its timer, before it counts as a violation.
+pub const ACK_DELAY_TOLERANCE: Duration = Duration::from_millis(5);
+/// The peer is flagged as violating its max_ack_delay once it's violated this many times
+/// after the handshake is confirmed.
+pub const ACK_DELAY_VIOLATIONS_TO_FLAG: u64 = 3;
 
 #[derive(Debug, Clone)]
 pub struct RawRtt {
+    // 对端的max_ack_delay和ack_delay_exponent传输参数
     max_ack_delay: Duration,
+    ack_delay_exponent: u8,
     first_rtt_sample: Option<Instant>,
     latest_rtt: Duration,
     smoothed_rtt: Duration,
     rttvar: Duration,
     min_rtt: Duration,
+    // min_rtt的采样时间，超出窗口后被新的采样替换
+    min_rtt_stamp: Option<Instant>,
     is_handshake_confirmed: bool,
+    // 握手确认之后，对端报告的ack_delay超出其max_ack_delay的次数
+    ack_delay_violations: u64,
 }
 
 impl Default for RawRtt {
     fn default() -> Self {
         Self {
-            max_ack_delay: Duration::from_millis(0),
+            max_ack_delay: DEFAULT_MAX_ACK_DELAY,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
             first_rtt_sample: None,
             latest_rtt: Duration::from_millis(0),
             smoothed_rtt: INITIAL_RTT,
             rttvar: INITIAL_RTT / 2,
             min_rtt: Duration::from_millis(0),
+            min_rtt_stamp: None,
             is_handshake_confirmed: false,
+            ack_delay_violations: 0,
         }
     }
 }
 
 impl RawRtt {
-    fn update(&mut self, latest_rtt: Duration, mut ack_delay: Duration) {
+    fn set_peer_ack_delay(&mut self, ack_delay_exponent: u8, max_ack_delay: Duration) {
+        self.ack_delay_exponent = ack_delay_exponent;
+        self.max_ack_delay = max_ack_delay;
+    }
+
+    // ACK帧中的ack_delay以2^ack_delay_exponent微秒为单位
+    fn decode_ack_delay(&self, encoded: u64) -> Duration {
+        let micros = encoded
+            .checked_shl(self.ack_delay_exponent as u32)
+            .filter(|micros| micros >> self.ack_delay_exponent == encoded)
+            .unwrap_or(u64::MAX);
+        Duration::from_micros(micros)
+    }
+
+    fn update_min_rtt(&mut self, latest_rtt: Duration, now: Instant) {
+        let expired = self
+            .min_rtt_stamp
+            .is_some_and(|stamp| now.saturating_duration_since(stamp) > MIN_RTT_WINDOW);
+        if self.min_rtt_stamp.is_none() || expired || latest_rtt <= self.min_rtt {
+            self.min_rtt = latest_rtt;
+            self.min_rtt_stamp = Some(now);
+        }
+    }
+
+    // 握手确认之前，对端可能确实延迟了更久，比如等待密钥，不算违反
+    fn check_ack_delay(&mut self, ack_delay: Duration) {
+        if !self.is_handshake_confirmed
+            || ack_delay <= self.max_ack_delay.saturating_add(ACK_DELAY_TOLERANCE)
+        {
+            return;
+        }
+        self.ack_delay_violations += 1;
+        if self.ack_delay_violations == ACK_DELAY_VIOLATIONS_TO_FLAG {
+            log::warn!(
+                "peer reported ack_delay {ack_delay:?} beyond its max_ack_delay {:?} {} times",
+                self.max_ack_delay,
+                self.ack_delay_violations
+            );
+        }
+    }
+
+    fn update(&mut self, latest_rtt: Duration, mut ack_delay: Duration, now: Instant) {
+        self.check_ack_delay(ack_delay);
         self.latest_rtt = latest_rtt;
+        // min_rtt ignores acknowledgment delay.
+        self.update_min_rtt(latest_rtt, now);
         if self.first_rtt_sample.is_none() {
-            self.min_rtt = latest_rtt;
             self.smoothed_rtt = latest_rtt;
             self.rttvar = latest_rtt / 2;
-            self.first_rtt_sample = Some(Instant::now());
+            self.first_rtt_sample = Some(now);
             return;
         }
 
-        // min_rtt ignores acknowledgment delay.
-        self.min_rtt = std::cmp::min(self.min_rtt, latest_rtt);
-
         // Limit ack_delay by max_ack_delay after handshake confirmation.
         if self.is_handshake_confirmed {
             ack_delay = std::cmp::min(ack_delay, self.max_ack_delay);
         }
 
-        // Adjust for acknowledgment delay if plausible.
-        let mut adjusted_rtt = latest_rtt;
-        if latest_rtt >= self.min_rtt + ack_delay {
-            adjusted_rtt = latest_rtt - ack_delay;
-        }
+        // Adjust for acknowledgment delay if plausible, never below min_rtt. So the adjusted
+        // sample always lies in [min_rtt, latest_rtt], however large the ack_delay is, even
+        // before the handshake is confirmed.
+        let adjusted_rtt = latest_rtt
+            .checked_sub(ack_delay)
+            .filter(|adjusted_rtt| *adjusted_rtt >= self.min_rtt)
+            .unwrap_or(latest_rtt);
 
         let abs_diff = if self.smoothed_rtt > adjusted_rtt {
             self.smoothed_rtt - adjusted_rtt
@@ -70,9 +132,9 @@ impl RawRtt {
         self.is_handshake_confirmed = true;
     }
 
-    fn loss_delay(&self) -> Duration {
+    fn loss_delay(&self, time_threshold: f32) -> Duration {
         std::cmp::max(
-            std::cmp::max(self.latest_rtt, self.smoothed_rtt).mul_f32(TIME_THRESHOLD),
+            std::cmp::max(self.latest_rtt, self.smoothed_rtt).mul_f32(time_threshold),
             GRANULARITY,
         )
     }
@@ -91,12 +153,36 @@ impl ArcRtt {
         Self(Arc::new(Mutex::new(RawRtt::default())))
     }
 
-    pub fn update(&self, latest_rtt: Duration, ack_delay: Duration) {
-        self.0.lock().unwrap().update(latest_rtt, ack_delay);
+    /// Update the estimation with an RTT sample, and the ack_delay decoded by
+    /// [`ArcRtt::decode_ack_delay`].
+    ///
+    /// The ack_delay is capped by the peer's max_ack_delay once the handshake is confirmed,
+    /// and is not subtracted if the sample would fall below the min_rtt, so that a single
+    /// misreported ack_delay can't drag the smoothed_rtt down. The ack_delay exceeding the
+    /// max_ack_delay after the handshake is confirmed is counted, see
+    /// [`ArcRtt::ack_delay_violations`].
+    pub fn update(&self, latest_rtt: Duration, ack_delay: Duration, now: Instant) {
+        self.0.lock().unwrap().update(latest_rtt, ack_delay, now);
+    }
+
+    /// Set the ack_delay_exponent and max_ack_delay transport parameters of the peer,
+    /// the defaults 3 and 25ms apply before they're received.
+    pub fn set_peer_ack_delay(&self, ack_delay_exponent: u8, max_ack_delay: Duration) {
+        self.0
+            .lock()
+            .unwrap()
+            .set_peer_ack_delay(ack_delay_exponent, max_ack_delay);
     }
 
-    pub fn loss_delay(&self) -> Duration {
-        self.0.lock().unwrap().loss_delay()
+    /// Decode the ACK Delay field of an ACK frame by the peer's ack_delay_exponent.
+    pub fn decode_ack_delay(&self, encoded: u64) -> Duration {
+        self.0.lock().unwrap().decode_ack_delay(encoded)
+    }
+
+    /// How long after a packet is sent it's declared lost, if a later packet is acknowledged,
+    /// that is `time_threshold` times max(smoothed_rtt, latest_rtt).
+    pub fn loss_delay(&self, time_threshold: f32) -> Duration {
+        self.0.lock().unwrap().loss_delay(time_threshold)
     }
 
     pub fn on_handshake_done(&self) {
@@ -114,7 +200,161 @@ impl ArcRtt {
     pub fn rttvar(&self) -> Duration {
         self.0.lock().unwrap().rttvar
     }
+
+    /// The most recent RTT sample, None if there is no RTT sample yet.
+    pub fn latest_rtt(&self) -> Option<Duration> {
+        let guard = self.0.lock().unwrap();
+        guard.first_rtt_sample.map(|_| guard.latest_rtt)
+    }
+
+    /// The minimum RTT observed within [`MIN_RTT_WINDOW`], None if there is no RTT sample yet.
+    pub fn min_rtt(&self) -> Option<Duration> {
+        let guard = self.0.lock().unwrap();
+        guard.first_rtt_sample.map(|_| guard.min_rtt)
+    }
+
+    /// The times the peer reported an ack_delay beyond its max_ack_delay by more than
+    /// [`ACK_DELAY_TOLERANCE`], after the handshake is confirmed.
+    pub fn ack_delay_violations(&self) -> u64 {
+        self.0.lock().unwrap().ack_delay_violations
+    }
+
+    /// Whether the peer keeps violating its max_ack_delay, i.e. it has violated it
+    /// [`ACK_DELAY_VIOLATIONS_TO_FLAG`] times. A warning is logged once it's flagged.
+    pub fn peer_violates_max_ack_delay(&self) -> bool {
+        self.ack_delay_violations() >= ACK_DELAY_VIOLATIONS_TO_FLAG
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn confirmed_rtt(ack_delay_exponent: u8) -> RawRtt {
+        let mut rtt = RawRtt::default();
+        rtt.set_peer_ack_delay(ack_delay_exponent, Duration::from_millis(25));
+        rtt.on_handshake_done();
+        rtt
+    }
+
+    #[test]
+    fn test_ack_delay_exponent() {
+        let rtt = confirmed_rtt(DEFAULT_ACK_DELAY_EXPONENT);
+        assert_eq!(rtt.decode_ack_delay(1000), Duration::from_micros(8000));
+        let rtt = confirmed_rtt(8);
+        assert_eq!(rtt.decode_ack_delay(1000), Duration::from_micros(256_000));
+        // 溢出的值饱和到最大
+        assert_eq!(
+            rtt.decode_ack_delay(u64::MAX >> 2),
+            Duration::from_micros(u64::MAX)
+        );
+
+        let now = Instant::now();
+        for ack_delay_exponent in [DEFAULT_ACK_DELAY_EXPONENT, 8] {
+            let mut rtt = confirmed_rtt(ack_delay_exponent);
+            rtt.update(Duration::from_millis(100), Duration::ZERO, now);
+            // 编码为39的ack_delay，指数为3时是312us，指数为8时是9984us
+            let ack_delay = rtt.decode_ack_delay(39);
+            rtt.update(Duration::from_millis(120), ack_delay, now);
+            let adjusted = Duration::from_millis(120) - ack_delay;
+            let expected = Duration::from_millis(100).mul_f32(0.875) + adjusted.mul_f32(0.125);
+            assert_eq!(rtt.smoothed_rtt, expected);
+        }
+    }
+
+    #[test]
+    fn test_ack_delay_capped() {
+        let now = Instant::now();
+        let mut rtt = confirmed_rtt(DEFAULT_ACK_DELAY_EXPONENT);
+        rtt.update(Duration::from_millis(10), Duration::ZERO, now);
+        // 超过max_ack_delay的部分不被扣除
+        rtt.update(Duration::from_millis(100), Duration::from_millis(60), now);
+        let expected =
+            Duration::from_millis(10).mul_f32(0.875) + Duration::from_millis(75).mul_f32(0.125);
+        assert_eq!(rtt.smoothed_rtt, expected);
+
+        // 扣除ack_delay后会低于min_rtt的，不扣除
+        let mut rtt = confirmed_rtt(DEFAULT_ACK_DELAY_EXPONENT);
+        rtt.update(Duration::from_millis(50), Duration::ZERO, now);
+        rtt.update(Duration::from_millis(60), Duration::from_millis(20), now);
+        let expected =
+            Duration::from_millis(50).mul_f32(0.875) + Duration::from_millis(60).mul_f32(0.125);
+        assert_eq!(rtt.smoothed_rtt, expected);
+        assert_eq!(rtt.min_rtt, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_ack_delay_violations() {
+        let now = Instant::now();
+        let mut rtt = RawRtt::default();
+        rtt.set_peer_ack_delay(DEFAULT_ACK_DELAY_EXPONENT, Duration::from_millis(25));
+        // 握手确认之前，再大的ack_delay都不算违反
+        for _ in 0..5 {
+            rtt.update(Duration::from_millis(100), Duration::from_millis(500), now);
+        }
+        assert_eq!(rtt.ack_delay_violations, 0);
+
+        rtt.on_handshake_done();
+        // 容差之内的不算
+        rtt.update(Duration::from_millis(100), Duration::from_millis(30), now);
+        assert_eq!(rtt.ack_delay_violations, 0);
+        for (ack_delay, violations) in [(31, 1), (20, 1), (200, 2), (100, 3), (40, 4)] {
+            rtt.update(
+                Duration::from_millis(100),
+                Duration::from_millis(ack_delay),
+                now,
+            );
+            assert_eq!(rtt.ack_delay_violations, violations);
+        }
+        assert!(rtt.ack_delay_violations >= ACK_DELAY_VIOLATIONS_TO_FLAG);
+    }
+
+    #[test]
+    fn test_large_ack_delay_bounded() {
+        let now = Instant::now();
+        let huge = Duration::from_micros(u64::MAX);
+        for confirmed in [false, true] {
+            let mut rtt = RawRtt::default();
+            rtt.update(Duration::from_millis(50), Duration::ZERO, now);
+            if confirmed {
+                rtt.on_handshake_done();
+            }
+            // 一个离谱的ack_delay，既不会溢出，也不会把smoothed_rtt拉到min_rtt之下
+            rtt.update(Duration::from_millis(80), huge, now);
+            let adjusted = if confirmed {
+                Duration::from_millis(55)
+            } else {
+                Duration::from_millis(80)
+            };
+            let expected = Duration::from_millis(50).mul_f32(0.875) + adjusted.mul_f32(0.125);
+            assert_eq!(rtt.smoothed_rtt, expected);
+
+            // 持续误报，调整后的样本始终在[min_rtt, latest_rtt]之内
+            for _ in 0..100 {
+                rtt.update(Duration::from_millis(80), Duration::from_millis(79), now);
+                assert!(rtt.smoothed_rtt >= rtt.min_rtt);
+                assert!(rtt.smoothed_rtt < Duration::from_millis(81));
+            }
+            assert_eq!(rtt.min_rtt, Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_min_rtt_window() {
+        let now = Instant::now();
+        let mut rtt = RawRtt::default();
+        rtt.update(Duration::from_millis(20), Duration::ZERO, now);
+        // 路径变化后，RTT确实增大了
+        let later = now + MIN_RTT_WINDOW;
+        rtt.update(Duration::from_millis(80), Duration::ZERO, later);
+        assert_eq!(rtt.min_rtt, Duration::from_millis(20));
+
+        let expired = later + Duration::from_millis(1);
+        rtt.update(Duration::from_millis(80), Duration::ZERO, expired);
+        assert_eq!(rtt.min_rtt, Duration::from_millis(80));
+        rtt.update(Duration::from_millis(90), Duration::ZERO, expired);
+        assert_eq!(rtt.min_rtt, Duration::from_millis(80));
+        rtt.update(Duration::from_millis(70), Duration::ZERO, expired);
+        assert_eq!(rtt.min_rtt, Duration::from_millis(70));
+    }
+}
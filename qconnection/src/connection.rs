@@ -1,43 +1,85 @@
 use std::{
     borrow::Cow,
     fmt::Debug,
-    io, mem,
+    future, io, mem,
+    net::SocketAddr,
     ops::DerefMut,
     sync::{Arc, Mutex},
+    task::Poll,
     time::{Duration, Instant},
 };
 
 use closing::ClosingConnection;
 use draining::DrainingConnection;
 use futures::{channel::mpsc, StreamExt};
+use health::{ConnectionHealth, ConnectionState};
+use overhead::OverheadStats;
+use pressure::{PressureLevel, PressureStats};
+use profile::ConnectionProfile;
 use qbase::{
     cid::{self, ConnectionId},
-    config::Parameters,
-    error::{Error, ErrorKind},
-    packet::{DataPacket, RetryHeader},
-    streamid::Role,
-    token::ArcTokenRegistry,
+    config::{ClientParameters, Parameters, ParametersChanged, ServerParameters},
+    error::{ConnectionError, Error, ErrorKind},
+    frame::{ImmediateAckFrame, ReliableFrame},
+    packet::{header::verify_retry_integrity, DataPacket, RetryHeader, VersionNegotiationHeader},
+    streamid::{Dir, Role, StreamId, StreamOpenRate},
+    token::{ArcTokenRegistry, StatelessResetKey},
+    util::{spawn_traced, try_spawn_traced, ArcTraceContext},
+    varint::VarInt,
+    version::Versions,
+};
+use qcongestion::{
+    congestion::{AckEagerness, CongestionAlgorithm, LossDetectionConfig, PathMetrics},
+    pacing::PacingConfig,
+    CongestionControl,
 };
-use qcongestion::CongestionControl;
 use qrecovery::{
-    recv::Reader, reliable::ArcReliableFrameDeque, send::Writer, space::Epoch, streams,
+    recv::Reader,
+    reliable::{sentpkt::SentRecordsStats, ArcReliableFrameDeque},
+    send::{FlushMode, Writer},
+    space::Epoch,
+    streams::{
+        self,
+        classify::{ClassPolicy, StreamClass, StreamClassifier},
+        crypto::{CryptoLimits, CryptoRecvStats},
+        data::{FlowControlStallSuspected, StreamIntrospection},
+        policy::IncomingStreamPolicy,
+        scheduler::{RoundRobin, StreamScheduler},
+    },
 };
 use qudp::ArcUsc;
-use qunreliable::DatagramFlow;
+use qunreliable::{DatagramFlow, DatagramQueueCapacity, DatagramWriter};
 use raw::RawConnection;
+use scope::FrameBudget;
+use stall::StallDetection;
+use telemetry::NetworkTelemetry;
+use tokio::sync::watch;
 
 use crate::{
     connection::ConnState::{Closed, Closing, Draining, Raw},
-    path::pathway::Pathway,
+    drops::{ArcDropCounters, DropStats},
+    error::{ConnError, ConnectError, MigrationError, StreamLimitTimeout, StreamsDisabled},
+    path::{pathway::Pathway, ArcPath},
     router::{RouterRegistry, ROUTER},
-    tls::ArcTlsSession,
+    tls::{ArcTlsSession, HandshakeStats},
 };
 
+pub mod ack_frequency;
+pub mod activity;
+pub mod address_discovery;
+pub mod attempts;
 pub mod closing;
 pub mod draining;
+pub mod health;
+pub mod overhead;
+pub mod pressure;
+pub mod profile;
 pub mod raw;
 pub mod scope;
+pub mod stall;
+pub mod telemetry;
 pub mod transmit;
+pub mod watchdog;
 
 pub type PacketEntry = mpsc::UnboundedSender<(DataPacket, Pathway, ArcUsc)>;
 pub type RcvdPackets = mpsc::UnboundedReceiver<(DataPacket, Pathway, ArcUsc)>;
@@ -56,8 +98,79 @@ enum ConnState {
     Closed,
 }
 
+/// The statistics of a connection, see [`ArcConnection::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionStats {
+    /// Whether our CONNECTION_CLOSE frame was likely received by the peer, inferred from
+    /// the packets received in the closing state, see [`closing::PostCloseRcvd`].
+    ///
+    /// None if the peer closed the connection first, or the connection died silently.
+    pub close_observed_by_peer: Option<bool>,
+    /// The number of incoming packets not responded with our CONNECTION_CLOSE frame in
+    /// the closing state, see [`closing::CloseResponder`].
+    pub close_responses_suppressed: u64,
+    /// The packets of this connection dropped silently, by reason, see [`DropReason`].
+    ///
+    /// [`DropReason`]: crate::drops::DropReason
+    pub dropped: DropStats,
+    /// Why the client failed to establish the connection, None if it was established, or
+    /// closed for other reasons, see [`ArcConnection::set_max_initial_pto_count`].
+    pub connect_error: Option<ConnectError>,
+    /// Whether the peer reset the connection with a stateless reset, maybe because it lost the
+    /// state of the connection, e.g. after a restart. The connection is torn down with the
+    /// [`StatelessReset`] error, without sending anything more.
+    ///
+    /// [`StatelessReset`]: crate::error::StatelessReset
+    pub stateless_reset: bool,
+    /// Whether the path MTU is suspected to be less than 1200 bytes, because the server
+    /// never responded to the Initial packets padded to 1200 bytes within the probe
+    /// timeouts set by [`ArcConnection::set_max_initial_pto_count`].
+    ///
+    /// It's only a heuristic, a dead address looks the same. If the handshake is retried
+    /// with smaller Initial packets, see [`ArcConnection::set_undersized_initial`], it's
+    /// cleared when the server doesn't respond to them either.
+    pub suspected_mtu_blackhole: bool,
+    /// The number of the streams created by the peer but shed, because they're beyond the
+    /// [`IncomingStreamPolicy`] or not accepted in time, see
+    /// [`ArcConnection::set_incoming_stream_policy`].
+    pub streams_shed: u64,
+    /// The data received in CRYPTO frames, indexed by [`Epoch`], see
+    /// [`ArcConnection::set_crypto_limits`].
+    pub crypto: [CryptoRecvStats; Epoch::count()],
+    /// The memory taken by the records of the packets sent, indexed by [`Epoch`], see
+    /// [`ArcConnection::set_max_sent_records`].
+    pub sent_records: [SentRecordsStats; Epoch::count()],
+    /// The overhead traffic sent and suppressed for the budget, by kind, see
+    /// [`ArcConnection::set_overhead_budget`].
+    pub overhead: OverheadStats,
+    /// What the pressure level is doing to the connection, see
+    /// [`ArcConnection::set_pressure_level`].
+    pub pressure: PressureStats,
+    /// The number of the [`FlowControlStallSuspected`] events, see
+    /// [`ArcConnection::set_stall_detection`].
+    pub flow_control_stalls: u64,
+    /// The number of 1-RTT key updates, initiated by either side, see
+    /// [`ArcConnection::update_keys`].
+    pub key_updates: u64,
+    /// The bytes allocated for the state of the connection when it was created, without
+    /// the paths, the streams opened and the data buffered. Much less with
+    /// [`ConnectionProfile::DatagramOnly`], which never allocates the state of the streams.
+    pub footprint: usize,
+}
+
 #[derive(Clone)]
-pub struct ArcConnection(Arc<Mutex<ConnState>>);
+pub struct ArcConnection(
+    Arc<Mutex<ConnState>>,
+    Arc<Mutex<ConnectionStats>>,
+    ArcDropCounters,
+    // 连接彻底终结的信号，即走完了closing或draining状态
+    Arc<watch::Sender<bool>>,
+    // 客户端首个Initial包的目的连接ID，连接的整个生命周期内不变
+    ConnectionId,
+    // 连接因何关闭，连接终结之后仍保留
+    ConnError,
+);
 
 impl Debug for ArcConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -66,131 +179,1026 @@ impl Debug for ArcConnection {
 }
 
 impl ArcConnection {
+    /// Whether the IPv4-mapped addresses in the pathways of the connection are canonicalized
+    /// is decided once here by `canonical_mapped_addresses`, see [`Pathway::canonical`].
+    #[allow(clippy::too_many_arguments)]
     pub fn new_client(
         scid: ConnectionId,
         server_name: String,
-        mut parameters: Parameters,
+        mut parameters: ClientParameters,
+        profile: ConnectionProfile,
         tls_config: Arc<rustls::ClientConfig>,
         token_registry: ArcTokenRegistry,
+        reset_key: StatelessResetKey,
+        canonical_mapped_addresses: bool,
     ) -> Self {
         let Ok(server_name) = server_name.try_into() else {
             panic!("server_name is not valid")
         };
 
         parameters.set_initial_source_connection_id(Some(scid));
+        profile.apply(&mut parameters);
 
         let dcid = ConnectionId::random_gen(8);
         let tls_session = ArcTlsSession::new_client(server_name, tls_config.clone(), &parameters);
+        // 恢复会话并发送0-RTT数据的，沿用会话票据一同记住的服务端传输参数
+        let remembered = tls_session.remembered_parameters();
         let raw_conn = RawConnection::new(
             Role::Client,
-            parameters,
+            profile,
+            parameters.into(),
             tls_session,
             scid,
             dcid,
             ArcTlsSession::initial_keys(tls_config.crypto_provider(), rustls::Side::Client, dcid),
             token_registry,
+            reset_key,
+            canonical_mapped_addresses,
         );
-        raw_conn.into()
+        let conn = ArcConnection::from(raw_conn);
+        if let Some(remembered) = remembered {
+            conn.set_remembered_parameters(remembered);
+        }
+        conn
     }
 
+    /// Start an initial attempt to the server via the pathway. A client can add several
+    /// initial paths to the resolved addresses of the server, and they run in parallel,
+    /// so that one dead address doesn't hold up the others, see [`attempts::ConnectAttempts`].
     pub fn add_initial_path(&self, pathway: Pathway, usc: ArcUsc) {
         let guard = self.0.lock().unwrap();
         if let Raw(ref conn) = *guard {
-            _ = conn.pathes.get_or_create(pathway, usc);
+            let _enter = conn.trace.enter();
+            let pathway = conn.pathway(pathway);
+            let path = conn.pathes.get_or_create(pathway, usc);
+            conn.attempts.begin(pathway);
+            spawn_traced(self.clone().watch_initial_attempt(pathway, path));
+        }
+    }
+
+    async fn watch_initial_attempt(self, pathway: Pathway, path: ArcPath) {
+        loop {
+            tokio::select! {
+                _ = future::poll_fn(|cx| path.cc.poll_pto_exhausted(cx)) => {}
+                _ = path.inactivated() => return,
+            }
+
+            let guard = self.0.lock().unwrap();
+            let Raw(ref conn) = *guard else {
+                return;
+            };
+            if conn.handshake.is_handshake_done() {
+                return;
+            }
+            let has_rcvd = path.has_rcvd();
+            if !has_rcvd {
+                let undersized_initial = *conn.undersized_initial.lock().unwrap();
+                let retried =
+                    undersized_initial.is_some_and(|size| path.max_datagram_size() <= size);
+                // 1200字节的Initial包始终没有回应，可能是路径MTU不足，也可能只是地址不可达
+                self.1.lock().unwrap().suspected_mtu_blackhole = !retried;
+                match undersized_initial {
+                    Some(size) if !retried => {
+                        tracing::warn!(
+                            ?pathway,
+                            size,
+                            "no response to Initial packets, retry with undersized ones"
+                        );
+                        path.set_max_datagram_size(size);
+                        path.cc.requeue_unacked(Epoch::Initial);
+                        continue;
+                    }
+                    None => tracing::warn!(
+                        ?pathway,
+                        "no response to Initial packets, the address is unreachable \
+                         or the path MTU is less than 1200 bytes"
+                    ),
+                    _ => {}
+                }
+                path.abandon();
+            }
+            if let Some(error) = conn.attempts.on_exhausted(pathway, has_rcvd) {
+                self.1.lock().unwrap().connect_error = Some(error);
+                conn.error.on_error(error.into());
+            }
+            return;
+        }
+    }
+
+    /// Migrate the connection to a new local address via `pathway`, such as after the network
+    /// of a mobile client changes. The data is sent via the new path at once, the old path
+    /// is kept as a standby one, and the new path is given up if it fails the path validation,
+    /// see [`Pathes::activate`]. Once the new path is validated, the old paths are abandoned
+    /// and their connection IDs retired, see [`ArcPathes::abandon_others`].
+    ///
+    /// The new path uses a connection ID issued by the peer which was never used before, the
+    /// connection stays on the current path if there is none, or if the peer disabled the
+    /// active migration with the disable_active_migration transport parameter, see
+    /// [`MigrationError`]. It doesn't prevent the passive migration caused by NAT rebinding.
+    ///
+    /// The server migrates to the new path once it's validated, see [`ArcPathes::migrate`].
+    ///
+    /// [`Pathes::activate`]: crate::path::Pathes::activate
+    /// [`ArcPathes::abandon_others`]: crate::path::ArcPathes::abandon_others
+    /// [`ArcPathes::migrate`]: crate::path::ArcPathes::migrate
+    pub fn migrate(&self, pathway: Pathway, usc: ArcUsc) -> Result<(), MigrationError> {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            let _enter = conn.trace.enter();
+            let pathway = conn.pathway(pathway);
+            if conn.pathes.active_pathway() == Some(pathway) {
+                return Ok(());
+            }
+            let disabled = conn
+                .remote_params
+                .state()
+                .as_ref()
+                .is_some_and(|params| params.disable_active_migration());
+            if disabled {
+                return Err(MigrationError::Disabled);
+            }
+            // 新路径要用一个从未用过的连接ID，免得对方把新旧路径关联起来
+            if conn.pathes.get(&pathway).is_none() && !conn.cid_registry.remote.has_spare_cid() {
+                return Err(MigrationError::NoSpareCid);
+            }
+            let path = conn.pathes.get_or_create(pathway, usc);
+            // 对方的地址已经在握手时验证过了，只需确认新路径可达
+            path.anti_amplifier.grant();
+            conn.pathes.activate(pathway);
+            let pathes = conn.pathes.clone();
+            spawn_traced(async move {
+                if path.challenge_answered().await {
+                    pathes.abandon_others(pathway).await;
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// The pathway of the path carrying the data, it changes after a migration.
+    ///
+    /// None if the connection is closing or closed.
+    pub fn active_pathway(&self) -> Option<Pathway> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.pathes.active_pathway(),
+            _ => None,
+        }
+    }
+
+    /// Fail the handshake after `count` probe timeouts in a row without any acknowledgment
+    /// from the server, instead of backing off for a long time. None disables it, which is
+    /// the default.
+    ///
+    /// If nothing has ever been received from the server, the connection fails with
+    /// [`ConnectError::Unreachable`] once all the initial attempts have given up, otherwise
+    /// with [`ConnectError::HandshakeTimeout`], see [`ConnectionStats::connect_error`].
+    ///
+    /// It only applies to the paths added before the handshake is done.
+    pub fn set_max_initial_pto_count(&self, count: Option<u32>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.max_initial_pto_count.lock().unwrap() = count;
+            if !conn.handshake.is_handshake_done() {
+                for path in conn.pathes.iter() {
+                    path.cc.set_max_pto_count(count);
+                }
+            }
+        }
+    }
+
+    /// The versions the client prefers, in the order of preference, version 1 only by default.
+    ///
+    /// The first Initial packets carry the first one supported or reserved, a reserved version
+    /// elicits a Version Negotiation packet from the server, which then steers the client to
+    /// the first one the server speaks as well, see [`Versions`]. It must be set before the
+    /// first Initial packet is sent, i.e. right after the connection is created.
+    pub fn set_preferred_versions(&self, versions: impl IntoIterator<Item = u32>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.versions.lock().unwrap() = Versions::new(versions);
+        }
+    }
+
+    /// Out of the spec, retry the handshake with Initial packets padded only to `size`
+    /// bytes, when the server never responded to the ones padded to 1200 bytes, see
+    /// [`ConnectionStats::suspected_mtu_blackhole`]. None disables it, which is the default.
+    ///
+    /// On the client, it only takes effect with [`ArcConnection::set_max_initial_pto_count`],
+    /// the retry begins once the probe timeouts are exhausted. On the server, the paths
+    /// receiving the undersized Initial packets send datagrams of `size` bytes too.
+    ///
+    /// The spec requires the datagrams carrying Initial packets to be at least 1200 bytes,
+    /// the paths that can't carry them can't support QUIC. This is only meant for the
+    /// private deployments, where both the client and the server enable it.
+    pub fn set_undersized_initial(&self, size: Option<usize>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.undersized_initial.lock().unwrap() = size;
+        }
+    }
+
+    /// Limit the data received in CRYPTO frames of each epoch, the connection is closed
+    /// with CRYPTO_BUFFER_EXCEEDED beyond them, see [`CryptoLimits`].
+    ///
+    /// It should be set before any packet is received, the defaults allow a certificate
+    /// chain up to 256KB.
+    pub fn set_crypto_limits(&self, limits: CryptoLimits) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.set_crypto_limits(limits);
+        }
+    }
+
+    /// Initiate a 1-RTT key update, see [RFC 9001 section 6](https://www.rfc-editor.org/rfc/rfc9001.html#name-key-update).
+    ///
+    /// Returns false if the handshake is not confirmed yet, or the previous key update has
+    /// not been acknowledged by the peer yet, in which case it can be tried again later.
+    /// Key updates are also initiated automatically, see [`Self::set_key_update_interval`].
+    pub fn update_keys(&self) -> bool {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.data.one_rtt_keys.update(),
+            _ => false,
+        }
+    }
+
+    /// Set how many packets are sent with a generation of 1-RTT keys before a key update is
+    /// initiated automatically. None means the default, 3/4 of the confidentiality limit of
+    /// the AEAD algorithm, that is about 6 million packets for AES-GCM.
+    pub fn set_key_update_interval(&self, packets: Option<u64>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.data.one_rtt_keys.set_update_interval(packets);
+        }
+    }
+
+    /// Set the span of the connection, all the asynchronous work spawned for this
+    /// connection, including the tasks spawned before this call, will be traced
+    /// within it from now on.
+    ///
+    /// The span of a stream, set by [`Writer::set_trace_span`] or [`Reader::set_trace_span`],
+    /// nests inside this span.
+    pub fn set_trace_context(&self, span: tracing::Span) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.trace.set_span(span);
+        }
+    }
+
+    /// The trace context of the connection, create the span of streams after
+    /// [`ArcTraceContext::enter`] to nest them inside the span of the connection.
+    pub fn trace_context(&self) -> Option<ArcTraceContext> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => Some(conn.trace.clone()),
+            _ => None,
         }
     }
 
+    /// Set how eagerly to acknowledge the packets received from the peer, on all the
+    /// current and future paths.
+    ///
+    /// [`AckEagerness::Immediate`] helps latency-critical request/response exchanges,
+    /// the peer won't wait for the delayed ACK of the last packet of its request.
+    pub fn set_ack_eagerness(&self, ack_eagerness: AckEagerness) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.ack_eagerness.lock().unwrap() = ack_eagerness;
+            // 负载压力下，仍以压力所要求的为准，压力解除后才生效
+            let ack_eagerness = conn.pressure.lock().unwrap().ack_eagerness(ack_eagerness);
+            for path in conn.pathes.iter() {
+                path.cc.set_ack_eagerness(ack_eagerness);
+            }
+        }
+    }
+
+    /// Ask the peer to acknowledge only every `ack_eliciting_threshold + 1` ack-eliciting
+    /// packets, and to delay the acknowledgment up to `max_ack_delay`, by an ACK_FREQUENCY
+    /// frame, see [draft-ietf-quic-ack-frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency).
+    ///
+    /// The missing packets lagging `reordering_threshold` behind the largest received one
+    /// are still reported immediately, 0 disables it. It waits for the transport parameters
+    /// of the peer, and fails if the peer doesn't support the extension, or `max_ack_delay`
+    /// is less than the peer's min_ack_delay.
+    pub async fn request_ack_frequency(
+        &self,
+        ack_eliciting_threshold: u32,
+        max_ack_delay: Duration,
+        reordering_threshold: u32,
+    ) -> io::Result<()> {
+        let connection_closed =
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
+        let remote_params = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(connection_closed);
+            };
+            raw_conn.remote_params.clone()
+        };
+        let remote_params = remote_params.get().await.as_ref().cloned();
+        let remote_params = remote_params.ok_or(connection_closed)?;
+
+        let Some(min_ack_delay) = remote_params.min_ack_delay() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "peer doesn't support the ack frequency extension",
+            ));
+        };
+        let request_max_ack_delay = VarInt::from_u64(max_ack_delay.as_micros() as u64)
+            .ok()
+            .filter(|delay| *delay >= min_ack_delay)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("max_ack_delay must be at least {min_ack_delay}us"),
+                )
+            })?;
+
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            let frame = conn.ack_frequency.next_frame(
+                VarInt::from_u32(ack_eliciting_threshold),
+                request_max_ack_delay,
+                VarInt::from_u32(reordering_threshold),
+            );
+            conn.reliable_frames
+                .lock_guard()
+                .push_back(ReliableFrame::AckFrequency(frame));
+        }
+        Ok(())
+    }
+
+    /// Ask the peer to acknowledge immediately by an IMMEDIATE_ACK frame, e.g. before
+    /// the idle period of an application, no matter what the ACK_FREQUENCY frame asked.
+    pub fn request_immediate_ack(&self) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.reliable_frames
+                .lock_guard()
+                .push_back(ReliableFrame::ImmediateAck(ImmediateAckFrame));
+        }
+    }
+
+    /// Set how the packets are paced on all the current and future paths.
+    ///
+    /// A small [`PacingConfig::burst_packets`] smooths the sending for the shallow buffers
+    /// on the path, at the cost of more wakeups of the sending task. The
+    /// [`PacingConfig::idle_restart`] decides whether a path idle for a retransmission
+    /// timeout resumes from the initial window or keeps its congestion window. A larger
+    /// [`PacingConfig::chunk_packets`] sends more packets in one GSO batch at high rates,
+    /// with a coarser pacing.
+    pub fn set_pacing(&self, config: PacingConfig) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.pacing.lock().unwrap() = config;
+            for path in conn.pathes.iter() {
+                path.cc.set_pacing(config);
+            }
+        }
+    }
+
+    /// Set how many bytes of the connection-level window, the local `initial_max_data`, the
+    /// fresh data received consumes before a MAX_DATA frame opens the window again, half of
+    /// the window by default.
+    pub fn set_max_data_threshold(&self, threshold: u64) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.flow_ctrl.recver.set_threshold(threshold);
+        }
+    }
+
+    /// Set the upper bound the receive window of a stream grows to, [`DEFAULT_MAX_STREAM_WINDOW`]
+    /// by default. The window starts from the `initial_max_stream_data` of the local transport
+    /// parameters, and grows toward twice the bandwidth-delay product measured as the
+    /// application reads, bounded by the connection-level window as well.
+    ///
+    /// [`DEFAULT_MAX_STREAM_WINDOW`]: qrecovery::recv::DEFAULT_MAX_STREAM_WINDOW
+    pub fn set_max_stream_window(&self, max_window: u64) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_max_stream_window(max_window);
+        }
+    }
+
+    /// Set how many packets sent are recorded one by one in each space at most,
+    /// [`DEFAULT_MAX_SENT_RECORDS`] by default, beyond which the oldest packets in flight
+    /// are summarized, so that the memory stays bounded however many packets are
+    /// unacknowledged. Their acknowledgments and losses get coarser, see
+    /// [`ArcSentPktRecords::set_max_records`].
+    ///
+    /// The records of the congestion controller are not bounded by it.
+    ///
+    /// [`DEFAULT_MAX_SENT_RECORDS`]: qrecovery::reliable::sentpkt::DEFAULT_MAX_SENT_RECORDS
+    /// [`ArcSentPktRecords::set_max_records`]: qrecovery::reliable::sentpkt::ArcSentPktRecords::set_max_records
+    pub fn set_max_sent_records(&self, max_records: usize) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.set_max_sent_records(max_records);
+        }
+    }
+
+    /// Tell the connection how saturated the application is, to spend less on the new work
+    /// and keep the transfers in progress going, see [`PressureLevel`] for what each level
+    /// does. Back to [`PressureLevel::Normal`], everything is restored, and the credit
+    /// withheld meanwhile is issued.
+    ///
+    /// The credit already granted to the peer is never retracted, the growth only slows down.
+    pub fn set_pressure_level(&self, level: PressureLevel) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            let previous = mem::replace(&mut *conn.pressure.lock().unwrap(), level);
+            if previous == level {
+                return;
+            }
+            tracing::debug!(?previous, ?level, "pressure level changed");
+            if let Some(streams) = &conn.streams {
+                streams.listener().set_shedding(level.sheds_streams());
+            }
+            let ack_eagerness = level.ack_eagerness(*conn.ack_eagerness.lock().unwrap());
+            for path in conn.pathes.iter() {
+                path.cc.set_ack_eagerness(ack_eagerness);
+            }
+            conn.datagrams.set_queue_limit(level.datagram_queue_limit());
+            let initial_max_data = conn.local_params.initial_max_data().into_inner();
+            conn.flow_ctrl
+                .recver
+                .set_window(level.recv_window(initial_max_data));
+            if let Some(streams) = &conn.streams {
+                streams.set_conn_recv_window(conn.flow_ctrl.recver.window());
+            }
+        }
+    }
+
+    /// The pressure level set by [`ArcConnection::set_pressure_level`].
+    pub fn pressure_level(&self) -> PressureLevel {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => *conn.pressure.lock().unwrap(),
+            _ => PressureLevel::Normal,
+        }
+    }
+
+    /// Set how the packets are declared lost on all the current and future paths, a larger
+    /// packet threshold avoids the spurious retransmissions on the paths reordering heavily.
+    pub fn set_loss_detection(&self, config: LossDetectionConfig) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.loss_detection.lock().unwrap() = config;
+            for path in conn.pathes.iter() {
+                path.cc.set_loss_detection(config);
+            }
+        }
+    }
+
+    /// Switch the congestion control algorithm of all the current and future paths,
+    /// e.g. start with [`CongestionAlgorithm::NewReno`] and switch to
+    /// [`CongestionAlgorithm::Bbr`] once the path proves to have a large bandwidth-delay product.
+    ///
+    /// The packets in flight stay tracked across the switch, and the new algorithm starts
+    /// from a window no larger than the old one, see [`ArcCC::switch_algorithm`].
+    ///
+    /// [`ArcCC::switch_algorithm`]: qcongestion::congestion::ArcCC::switch_algorithm
+    pub fn switch_congestion(&self, algorithm: CongestionAlgorithm) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            *conn.congestion.lock().unwrap() = algorithm;
+            for path in conn.pathes.iter() {
+                path.cc.switch_algorithm(algorithm);
+            }
+        }
+    }
+
+    /// Bound the ack-eliciting traffic this endpoint sends on its own initiative, such as
+    /// the path validation, to `bytes_per_minute`, None is unlimited, which is the default.
+    ///
+    /// The application data and the loss recovery are never throttled by it, see
+    /// [`overhead::ArcOverheadBudget`].
+    pub fn set_overhead_budget(&self, bytes_per_minute: Option<u64>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.overhead.set_limit(bytes_per_minute);
+        }
+    }
+
+    /// Report the [`ConnectionDigest`] of the connection to `telemetry` once it's closed,
+    /// None to report nothing, which is the default. Only the network of the peer is
+    /// reported, never its address.
+    ///
+    /// [`ConnectionDigest`]: telemetry::ConnectionDigest
+    pub fn set_network_telemetry(&self, telemetry: Option<NetworkTelemetry>) {
+        let mut guard = self.0.lock().unwrap();
+        if let Raw(ref mut conn) = *guard {
+            conn.network_telemetry = telemetry;
+        }
+    }
+
+    /// Set how many frames of a received packet are processed before yielding to the
+    /// other tasks, and the cap of the frames in a packet, see [`FrameBudget`].
+    pub fn set_frame_budget(&self, budget: FrameBudget) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.frame_budget.set(budget);
+        }
+    }
+
+    /// Close the connection if no data of any stream or datagram is received within the
+    /// timeout, None disables it, which is the default.
+    ///
+    /// Unlike the idle timeout, the ACK and other control frames don't count, it is a
+    /// liveness watchdog of the application data, see [`watchdog::ReceiveWatchdog`].
+    pub fn set_receive_timeout(&self, timeout: Option<Duration>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.receive_watchdog.set_timeout(timeout, &conn.error);
+        }
+    }
+
+    /// Set how to detect the flow control deadlocks, where our streams are blocked on the
+    /// peer's windows while the peer's data fills ours unread, see [`StallDetection`].
+    /// None disables it. It's on by default, with a threshold of a few seconds.
+    ///
+    /// The events are counted by [`ConnectionStats::flow_control_stalls`], and can be
+    /// watched by [`ArcConnection::watch_flow_control_stall`].
+    pub fn set_stall_detection(&self, detection: Option<StallDetection>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.stall_detector.set_config(detection);
+        }
+    }
+
+    /// Watch the [`FlowControlStallSuspected`] events, which are rate-limited, the watch ends
+    /// once the connection is closed.
+    pub fn watch_flow_control_stall(
+        &self,
+    ) -> io::Result<watch::Receiver<Option<FlowControlStallSuspected>>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => Ok(conn.stall_detector.subscribe()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            )),
+        }
+    }
+
+    /// Set the policy of accepting the streams created by the peer, on top of the MAX_STREAMS
+    /// limit, see [`IncomingStreamPolicy`]. The excess streams are shed instead of closing
+    /// the connection, counted by [`ConnectionStats::streams_shed`].
+    ///
+    /// It should be set before the peer creates any stream.
+    pub fn set_incoming_stream_policy(&self, policy: IncomingStreamPolicy) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_incoming_stream_policy(policy);
+        }
+    }
+
+    /// Keep the peer able to open `window` more streams in the direction, beyond the
+    /// streams it opened and we're done with, the `initial_max_streams` of the local
+    /// transport parameters by default. The MAX_STREAMS frames are sent as the streams
+    /// opened by the peer are released, i.e. accepted and both their reader and writer
+    /// dropped.
+    ///
+    /// It's overridden by the [`IncomingStreamPolicy`], see
+    /// [`ArcConnection::set_incoming_stream_policy`].
+    pub fn set_remote_stream_window(&self, dir: Dir, window: u64) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_remote_stream_window(dir, window);
+        }
+    }
+
+    /// Classify the streams created by the peer from now on, the streams of a class other
+    /// than [`StreamClass::DEFAULT`] are accepted by [`ArcConnection::accept_class`] instead
+    /// of [`ArcConnection::accept_bi_stream`] and [`ArcConnection::accept_uni_stream`],
+    /// see [`StreamClassifier`].
+    pub fn set_stream_classifier(&self, classifier: StreamClassifier) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_stream_classifier(classifier);
+        }
+    }
+
+    /// Bound the accept queue of the class, the excess streams of the class are shed,
+    /// counted by [`ConnectionStats::streams_shed`], see [`ClassPolicy`].
+    pub fn set_class_policy(&self, class: StreamClass, policy: ClassPolicy) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_class_policy(class, policy);
+        }
+    }
+
+    /// Set how many urgency levels a stream with lost data is lifted by at most when it
+    /// competes with the other streams, see [`Writer::set_urgency`]. The CRYPTO and the control
+    /// frames are always sent before the data of any stream.
+    pub fn set_retransmission_boost(&self, boost: u8) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_retransmission_boost(boost);
+        }
+    }
+
+    /// Replace the scheduler deciding which stream sends the next STREAM frame, see
+    /// [`StreamScheduler`]. The default is [`RoundRobin`], honoring the priorities set by
+    /// [`Writer::set_priority`].
+    pub fn set_stream_scheduler(&self, scheduler: Box<dyn StreamScheduler>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_stream_scheduler(scheduler);
+        }
+    }
+
+    /// Schedule the streams by [`RoundRobin`] with the quantum, i.e. the bytes a stream of
+    /// the default weight sends in its turn, [`DEFAULT_QUANTUM`] by default. It replaces the
+    /// scheduler set by [`ArcConnection::set_stream_scheduler`].
+    ///
+    /// [`DEFAULT_QUANTUM`]: qrecovery::streams::scheduler::DEFAULT_QUANTUM
+    pub fn set_stream_quantum(&self, quantum: usize) {
+        self.set_stream_scheduler(Box::new(RoundRobin::with_quantum(quantum)));
+    }
+
+    /// Limit how fast the streams are opened by [`ArcConnection::open_bi_stream`] and
+    /// [`ArcConnection::open_uni_stream`], None disables it, which is the default.
+    ///
+    /// The opens queue up and complete in order at the `burst` and `per_rtt` rate of
+    /// [`StreamOpenRate`], instead of all at once, which smooths the packets carrying the
+    /// first frames of the streams and the pressure on the peer accepting them. The opens
+    /// beyond the MAX_STREAMS limit of the peer still wait for it to raise the limit.
+    pub fn set_stream_open_rate(&self, rate: Option<StreamOpenRate>) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(RawConnection {
+            streams: Some(ref streams),
+            ..
+        }) = *guard
+        {
+            streams.set_stream_open_rate(rate);
+        }
+    }
+
+    /// Set whether to accept the datagrams received in 0-RTT packets, see [`DatagramFlow::accept_0rtt`].
+    pub fn set_accept_0rtt_datagrams(&self, accept: bool) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.datagrams.accept_0rtt(accept);
+        }
+    }
+
+    /// Bound the queue of the datagrams to send, see [`DatagramOutgoing::set_capacity`].
+    ///
+    /// [`DatagramOutgoing::set_capacity`]: qunreliable::DatagramOutgoing::set_capacity
+    pub fn set_datagram_send_capacity(&self, capacity: DatagramQueueCapacity) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.datagrams.set_send_capacity(capacity);
+        }
+    }
+
+    /// Set the transport parameters of the server remembered from the previous connection,
+    /// along with the session ticket used to resume this one.
+    ///
+    /// When the authenticated parameters of the server arrive, they are compared with the
+    /// remembered ones, see [`ParametersChanged`]. It should be set before the handshake
+    /// completes, it's meaningless for the server.
+    ///
+    /// The client resuming a session with 0-RTT sets them from the TLS session ticket when
+    /// created. The streams opened before the handshake completes, and the data sent in 0-RTT
+    /// packets, are limited by them.
+    pub fn set_remembered_parameters(&self, parameters: Parameters) {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            // 0-RTT的数据受限于记住的连接级额度，以及记住的流数量
+            conn.flow_ctrl.apply_transport_parameters(&parameters);
+            if let Some(streams) = &conn.streams {
+                streams.premit_max_sid(Dir::Bi, parameters.initial_max_streams_bidi().into());
+                streams.premit_max_sid(Dir::Uni, parameters.initial_max_streams_uni().into());
+            }
+            *conn.remembered_params.lock().unwrap() = Some(parameters);
+        }
+    }
+
+    /// Wait for the differences between the remembered transport parameters of the server,
+    /// see [`ArcConnection::set_remembered_parameters`], and the ones received on this
+    /// connection, so that the application can update its own caches.
+    ///
+    /// None if the connection is not resumed, nothing changed, or the connection is closed
+    /// before the parameters arrive.
+    pub async fn parameters_changed(&self) -> Option<ParametersChanged> {
+        let params_changed = {
+            let guard = self.0.lock().unwrap();
+            match *guard {
+                Raw(ref conn) => conn.params_changed.clone(),
+                _ => return None,
+            }
+        };
+        let changed = params_changed.get().await.as_ref().cloned();
+        changed
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new_server(
         initial_scid: ConnectionId,
         initial_dcid: ConnectionId,
-        mut parameters: Parameters,
+        mut parameters: ServerParameters,
+        profile: ConnectionProfile,
         initial_keys: rustls::quic::Keys,
         tls_config: Arc<rustls::ServerConfig>,
         token_registry: ArcTokenRegistry,
+        reset_key: StatelessResetKey,
+        canonical_mapped_addresses: bool,
     ) -> Self {
-        parameters.set_original_destination_connection_id(Some(initial_dcid));
+        parameters.set_initial_source_connection_id(Some(initial_scid));
+        parameters.set_statelss_reset_token(Some(reset_key.reset_token(&initial_scid)));
+        profile.apply(&mut parameters);
 
         let tls_session = ArcTlsSession::new_server(tls_config.clone(), &parameters);
         let raw_conn = RawConnection::new(
             Role::Server,
-            parameters,
+            profile,
+            parameters.into(),
             tls_session,
             initial_scid,
             initial_dcid,
             initial_keys,
             token_registry,
+            reset_key,
+            canonical_mapped_addresses,
         );
         raw_conn.into()
     }
 
-    // /// Get the streams of the connection, return error if the connection is in closing state or
-    // /// draining state. Even if the connection will enter closing state in future, the returned
-    // /// data streams are still available. It doesn't matter, because the returned DataStreams will
-    // /// be synced into Error state, and do anything about this DataStreams will return an Error.
-    // pub fn streams(&self) -> io::Result<DataStreams> {
-    //     // TODO: ArcConnection不再暴露赤裸的streams接口，而是根据双方Parameters使用
-    //     //      raw_conn.streams().open_bi(...)去异步地创建
-    //     let guard = self.0.lock().unwrap();
-    //     if let ConnState::Raw(ref raw_conn) = *guard {
-    //         Ok(raw_conn.streams.clone())
-    //     } else {
-    //         Err(io::Error::new(
-    //             io::ErrorKind::BrokenPipe,
-    //             "Connection is closing or closed",
-    //         ))
-    //     }
-    // }
+    // /// Get the streams of the connection, return error if the connection is in closing state or
+    // /// draining state. Even if the connection will enter closing state in future, the returned
+    // /// data streams are still available. It doesn't matter, because the returned DataStreams will
+    // /// be synced into Error state, and do anything about this DataStreams will return an Error.
+    // pub fn streams(&self) -> io::Result<DataStreams> {
+    //     // TODO: ArcConnection不再暴露赤裸的streams接口，而是根据双方Parameters使用
+    //     //      raw_conn.streams().open_bi(...)去异步地创建
+    //     let guard = self.0.lock().unwrap();
+    //     if let ConnState::Raw(ref raw_conn) = *guard {
+    //         Ok(raw_conn.streams.clone())
+    //     } else {
+    //         Err(io::Error::new(
+    //             io::ErrorKind::BrokenPipe,
+    //             "Connection is closing or closed",
+    //         ))
+    //     }
+    // }
+
+    pub async fn open_bi_stream(&self) -> io::Result<Option<(Reader, Writer)>> {
+        let connection_closed =
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
+        let (peer_params, data_streams, conn_error) = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(connection_closed);
+            };
+
+            (
+                raw_conn.wait_peer_parameters(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
+                raw_conn.error.clone(),
+            )
+        };
+
+        let peer_params = peer_params.await.ok_or(connection_closed)?;
+
+        let result = data_streams
+            .open_bi(peer_params.initial_max_stream_data_bidi_remote().into())
+            .await
+            .inspect_err(|e| conn_error.on_error(e.clone()));
+        Ok(result?)
+    }
+
+    pub async fn open_uni_stream(&self) -> io::Result<Option<Writer>> {
+        let connection_closed =
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
+        let (peer_params, data_streams, conn_error) = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(connection_closed);
+            };
+
+            (
+                raw_conn.wait_peer_parameters(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
+                raw_conn.error.clone(),
+            )
+        };
+
+        let peer_params = peer_params.await.ok_or(connection_closed)?;
+
+        let result = data_streams
+            .open_uni(peer_params.initial_max_stream_data_uni().into())
+            .await
+            .inspect_err(|e| conn_error.on_error(e.clone()));
+        Ok(result?)
+    }
+
+    /// Open a bidirectional stream without waiting, None if it can't be opened right now,
+    /// such as when the peer's MAX_STREAMS is reached, or its transport parameters are not
+    /// received yet, see [`DataStreams::try_open_bi`].
+    ///
+    /// [`DataStreams::try_open_bi`]: qrecovery::streams::DataStreams::try_open_bi
+    pub fn try_open_bi_stream(&self) -> io::Result<Option<(Reader, Writer)>> {
+        let guard = self.0.lock().unwrap();
+        let ConnState::Raw(raw_conn) = &*guard else {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            ));
+        };
+        let streams = raw_conn.streams.as_ref().ok_or(StreamsDisabled)?;
+        let Some(peer_params) = raw_conn.peer_parameters() else {
+            return Ok(None);
+        };
+        let result = streams
+            .try_open_bi(peer_params.initial_max_stream_data_bidi_remote().into())
+            .inspect_err(|e| raw_conn.error.on_error(e.clone()));
+        Ok(result?)
+    }
+
+    /// Open a unidirectional stream without waiting, see
+    /// [`ArcConnection::try_open_bi_stream`].
+    pub fn try_open_uni_stream(&self) -> io::Result<Option<Writer>> {
+        let guard = self.0.lock().unwrap();
+        let ConnState::Raw(raw_conn) = &*guard else {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            ));
+        };
+        let streams = raw_conn.streams.as_ref().ok_or(StreamsDisabled)?;
+        let Some(peer_params) = raw_conn.peer_parameters() else {
+            return Ok(None);
+        };
+        let result = streams
+            .try_open_uni(peer_params.initial_max_stream_data_uni().into())
+            .inspect_err(|e| raw_conn.error.on_error(e.clone()));
+        Ok(result?)
+    }
+
+    /// Open a bidirectional stream like [`ArcConnection::open_bi_stream`], but give up after
+    /// `timeout`, with an [`io::ErrorKind::TimedOut`] error carrying [`StreamLimitTimeout`].
+    /// Nothing is allocated if it gives up, the later opens get the stream IDs in order.
+    pub async fn open_bi_stream_timeout(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<Option<(Reader, Writer)>> {
+        tokio::time::timeout(timeout, self.open_bi_stream())
+            .await
+            .map_err(|_| {
+                let error = StreamLimitTimeout {
+                    dir: Dir::Bi,
+                    timeout,
+                };
+                io::Error::new(io::ErrorKind::TimedOut, error)
+            })?
+    }
+
+    /// Open a unidirectional stream like [`ArcConnection::open_uni_stream`], but give up
+    /// after `timeout`, see [`ArcConnection::open_bi_stream_timeout`].
+    pub async fn open_uni_stream_timeout(&self, timeout: Duration) -> io::Result<Option<Writer>> {
+        tokio::time::timeout(timeout, self.open_uni_stream())
+            .await
+            .map_err(|_| {
+                let error = StreamLimitTimeout {
+                    dir: Dir::Uni,
+                    timeout,
+                };
+                io::Error::new(io::ErrorKind::TimedOut, error)
+            })?
+    }
+
+    /// Reserve the first stream ID of each lane, i.e. the streams of a kind this endpoint
+    /// opens, like a control stream, in the order the labels are given. Return the first
+    /// stream ID of each label, the labels already registered keep their IDs.
+    ///
+    /// The first streams of the lanes, opened by [`ArcConnection::open_bi_stream_labeled`]
+    /// or [`ArcConnection::open_uni_stream_labeled`], get the reserved IDs in the order the
+    /// lanes are registered, whichever is opened first, so the peer can tell the lanes
+    /// apart by the stream IDs. The later streams of a lane get the next IDs like others.
+    pub fn register_stream_lanes(
+        &self,
+        dir: Dir,
+        labels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> io::Result<Vec<StreamId>> {
+        let guard = self.0.lock().unwrap();
+        let ConnState::Raw(raw_conn) = &*guard else {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            ));
+        };
+        let streams = raw_conn.streams.as_ref().ok_or(StreamsDisabled)?;
+        Ok(streams.register_lanes(dir, labels))
+    }
+
+    /// The first stream ID reserved for the lane, see [`ArcConnection::register_stream_lanes`].
+    pub fn stream_lane(&self, dir: Dir, label: &str) -> Option<StreamId> {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            conn.streams.as_ref()?.lane(dir, label)
+        } else {
+            None
+        }
+    }
 
-    pub async fn open_bi_stream(&self) -> io::Result<Option<(Reader, Writer)>> {
+    /// Open a bidirectional stream on the lane registered by
+    /// [`ArcConnection::register_stream_lanes`], returned with its stream ID.
+    pub async fn open_bi_stream_labeled(
+        &self,
+        label: &str,
+    ) -> io::Result<Option<(StreamId, Reader, Writer)>> {
         let connection_closed =
             io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
-        let (remote_params, data_streams, conn_error) = {
+        let (peer_params, data_streams, conn_error) = {
             let guard = self.0.lock().unwrap();
             let ConnState::Raw(raw_conn) = &*guard else {
                 return Err(connection_closed);
             };
 
             (
-                raw_conn.remote_params.clone(),
-                raw_conn.streams.clone(),
+                raw_conn.wait_peer_parameters(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
                 raw_conn.error.clone(),
             )
         };
+        if data_streams.lane(Dir::Bi, label).is_none() {
+            return Err(unknown_lane(label));
+        }
 
-        let remote_params = remote_params.get().await.as_ref().cloned();
-        let remote_params = remote_params.ok_or(connection_closed)?;
+        let peer_params = peer_params.await.ok_or(connection_closed)?;
 
         let result = data_streams
-            .open_bi(remote_params.initial_max_stream_data_bidi_remote().into())
+            .open_bi_labeled(
+                label,
+                peer_params.initial_max_stream_data_bidi_remote().into(),
+            )
             .await
             .inspect_err(|e| conn_error.on_error(e.clone()));
-        Ok(result?)
+        Ok(result?.map(|(reader, writer)| (writer.stream_id(), reader, writer)))
     }
 
-    pub async fn open_uni_stream(&self) -> io::Result<Option<Writer>> {
+    /// Open a unidirectional stream on the lane registered by
+    /// [`ArcConnection::register_stream_lanes`], returned with its stream ID.
+    pub async fn open_uni_stream_labeled(
+        &self,
+        label: &str,
+    ) -> io::Result<Option<(StreamId, Writer)>> {
         let connection_closed =
             io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
-        let (remote_params, data_streams, conn_error) = {
+        let (peer_params, data_streams, conn_error) = {
             let guard = self.0.lock().unwrap();
             let ConnState::Raw(raw_conn) = &*guard else {
                 return Err(connection_closed);
             };
 
             (
-                raw_conn.remote_params.clone(),
-                raw_conn.streams.clone(),
+                raw_conn.wait_peer_parameters(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
                 raw_conn.error.clone(),
             )
         };
+        if data_streams.lane(Dir::Uni, label).is_none() {
+            return Err(unknown_lane(label));
+        }
 
-        let remote_params = remote_params.get().await.as_ref().cloned();
-        let remote_params = remote_params.ok_or(connection_closed)?;
+        let peer_params = peer_params.await.ok_or(connection_closed)?;
 
         let result = data_streams
-            .open_uni(remote_params.initial_max_stream_data_uni().into())
+            .open_uni_labeled(label, peer_params.initial_max_stream_data_uni().into())
             .await
             .inspect_err(|e| conn_error.on_error(e.clone()));
-        Ok(result?)
+        Ok(result?.map(|writer| (writer.stream_id(), writer)))
     }
 
     pub async fn accept_bi_stream(&self) -> io::Result<(Reader, Writer)> {
@@ -204,7 +1212,7 @@ impl ArcConnection {
 
             (
                 raw_conn.remote_params.clone(),
-                raw_conn.streams.clone(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
                 raw_conn.error.clone(),
             )
         };
@@ -229,7 +1237,8 @@ impl ArcConnection {
                 ));
             };
 
-            (raw_conn.streams.clone(), raw_conn.error.clone())
+            let data_streams = raw_conn.streams.clone().ok_or(StreamsDisabled)?;
+            (data_streams, raw_conn.error.clone())
         };
 
         let result = data_streams
@@ -239,6 +1248,38 @@ impl ArcConnection {
         Ok(result)
     }
 
+    /// Accept a stream created by the peer of the class, in the order they're classified, see
+    /// [`ArcConnection::set_stream_classifier`]. The [`Writer`] is None for a unidirectional
+    /// stream.
+    pub async fn accept_class(&self, class: StreamClass) -> io::Result<(Reader, Option<Writer>)> {
+        let connection_closed =
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
+        let (remote_params, data_streams, conn_error) = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(connection_closed);
+            };
+
+            (
+                raw_conn.remote_params.clone(),
+                raw_conn.streams.clone().ok_or(StreamsDisabled)?,
+                raw_conn.error.clone(),
+            )
+        };
+
+        let remote_params = remote_params.get().await.as_ref().cloned();
+        let remote_params = remote_params.ok_or(connection_closed)?;
+
+        let result = data_streams
+            .accept_class(
+                class,
+                remote_params.initial_max_stream_data_bidi_local().into(),
+            )
+            .await
+            .inspect_err(|e| conn_error.on_error(e.clone()))?;
+        Ok(result)
+    }
+
     pub fn datagrams(&self) -> io::Result<DatagramFlow> {
         let guard = self.0.lock().unwrap();
         if let ConnState::Raw(ref raw_conn) = *guard {
@@ -251,20 +1292,107 @@ impl ArcConnection {
         }
     }
 
-    /// Gracefully closes the connection.
+    /// Create the writer of the datagrams once the transport parameters of the peer are
+    /// known, the datagrams larger than its max_datagram_frame_size are refused.
+    ///
+    /// The client resuming a session uses the remembered parameters of the server at once,
+    /// see [`ArcConnection::set_remembered_parameters`], so that the datagrams can be sent
+    /// in 0-RTT packets.
+    ///
+    /// Return an error of kind [`io::ErrorKind::Unsupported`] if the peer advertised a
+    /// max_datagram_frame_size of 0, or none at all, which means it doesn't support datagrams.
+    pub async fn datagram_writer(&self) -> io::Result<DatagramWriter> {
+        let connection_closed =
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closing or closed");
+        let (peer_params, datagrams) = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(connection_closed);
+            };
+
+            (raw_conn.wait_peer_parameters(), raw_conn.datagrams.clone())
+        };
+
+        let peer_params = peer_params.await.ok_or(connection_closed)?;
+        datagrams.writer(peer_params.max_datagram_frame_size().into_inner())
+    }
+
+    /// Wait until all the data submitted to the connection has left the local queues:
+    /// the data of all the streams, the queued datagrams and the pending control frames.
+    ///
+    /// With [`FlushMode::SentOnce`], the data waiting for retransmission does not block it.
+    /// With [`FlushMode::FullyAcked`], the data of the streams must be acknowledged by the
+    /// peer as well, while the datagrams and the control frames only need to be sent once.
+    ///
+    /// Return an error if the connection is closing or already closed.
+    pub async fn flushed(&self, mode: FlushMode) -> io::Result<()> {
+        let (data_streams, reliable_frames, datagrams) = {
+            let guard = self.0.lock().unwrap();
+            let ConnState::Raw(raw_conn) = &*guard else {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Connection is closing or closed",
+                ));
+            };
+
+            (
+                raw_conn.streams.clone(),
+                raw_conn.reliable_frames.clone(),
+                raw_conn.datagrams.clone(),
+            )
+        };
+
+        future::poll_fn(|cx| {
+            // 每个队列都要轮询到，以便各自登记waker
+            let streams = match &data_streams {
+                Some(data_streams) => data_streams.poll_flushed(cx, mode)?,
+                None => Poll::Ready(()),
+            };
+            let frames = reliable_frames.poll_drained(cx);
+            let datagrams = datagrams.poll_drained(cx)?;
+            if streams.is_ready() && frames.is_ready() && datagrams.is_ready() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Gracefully closes the connection, with an error code of the application protocol
+    /// and the reason.
+    ///
+    /// The CONNECTION_CLOSE frame of type 0x1d is sent at once, then the connection enters
+    /// the closing state, where it only responds to the incoming packets with the same frame,
+    /// at a decreasing rate. Then it drains for 3 PTOs, before its connection IDs are removed
+    /// from the router, see [RFC 9000 section 10.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2).
+    ///
+    /// The pending and later operations of the streams and the datagrams fail with
+    /// [`ConnectionError::Application`], on both sides. See [`Self::closed`] to await
+    /// the end of the closing.
     ///
-    /// Closes the connection with a specified error.
-    /// This function is intended for use by the application layer to signal an
-    /// error and initiate the connection closure.
-    pub fn close(&self, msg: impl Into<Cow<'static, str>>) {
+    /// # Panics
+    ///
+    /// Panics if the `error_code` exceeds [`VARINT_MAX`](qbase::varint::VARINT_MAX).
+    pub fn close(&self, error_code: u64, reason: impl Into<Cow<'static, str>>) {
+        let error_code =
+            VarInt::from_u64(error_code).expect("app error code must not exceed VARINT_MAX");
         let guard = self.0.lock().unwrap();
         if let ConnState::Raw(ref raw_conn) = *guard {
             raw_conn
                 .error
-                .set_app_error(Error::with_default_fty(ErrorKind::Application, msg));
+                .set_app_error(Error::with_default_fty(ErrorKind::App(error_code), reason));
         }
     }
 
+    /// Wait until the connection is terminated, like [`Self::terminated`], and return why it
+    /// was closed, by either side.
+    pub async fn closed(&self) -> ConnectionError {
+        self.terminated().await;
+        let (error, _is_active) = self.5.did_error_occur().await;
+        ConnectionError::from(error)
+    }
+
     /// This function transitioning connection to a `Closing` state and
     /// initiating a background task to manage the closing handshake. This task awaits
     /// confirmation from the peer (Connection Close Frame) within a timeout derived
@@ -278,21 +1406,38 @@ impl ArcConnection {
         };
 
         raw_conn.datagrams.on_conn_error(&error);
-        raw_conn.streams.on_conn_error(&error);
+        if let Some(streams) = &raw_conn.streams {
+            streams.on_conn_error(&error);
+        }
+        raw_conn.receive_watchdog.on_conn_error();
+        raw_conn.stall_detector.on_conn_error();
+        raw_conn.reset_tokens.revoke();
         raw_conn.tls_session.abort();
+        raw_conn.remote_params.invalid();
+        raw_conn.report_network_telemetry();
+        {
+            let mut stats = self.1.lock().unwrap();
+            stats.streams_shed = raw_conn.streams_shed();
+            stats.crypto = raw_conn.crypto_stats();
+            stats.sent_records = raw_conn.sent_records_stats();
+            stats.overhead = raw_conn.overhead.stats();
+            stats.flow_control_stalls = raw_conn.stall_detector.suspected();
+            stats.key_updates = raw_conn.data.one_rtt_keys.generation();
+        }
 
         let pto = raw_conn
             .pathes
             .iter()
             .map(|path| path.cc.pto_time(Epoch::Data))
             .max()
-            .unwrap();
+            .unwrap_or_default();
 
         let hs = raw_conn.hs.try_into();
         let one_rtt = raw_conn.data.try_into();
         if hs.is_err() && one_rtt.is_err() {
-            // 没法进入到Closing，则直接进入到Draining
-            self.enter_draining(pto * 3);
+            // 没法进入到Closing，则直接进入到Draining，此时仍持有锁，不能调用enter_draining
+            let draining_conn = DrainingConnection::new(raw_conn.cid_registry.local);
+            self.drain(guard.deref_mut(), draining_conn, pto * 3);
             return;
         }
 
@@ -302,32 +1447,51 @@ impl ArcConnection {
             raw_conn.cid_registry,
             hs.ok(),
             one_rtt.ok(),
+            raw_conn.drops,
         );
 
+        closing_conn.send_ccf();
         // Redirect the received packets of this connection to ClosingConnection
         raw_conn.notify.notify_waiters();
+        // 关闭连接可能发生在运行时关闭之后，此时不再转交收包、计时，连接随之释放
         for handle in raw_conn.join_handles {
             let mut closing_conn = closing_conn.clone();
-            tokio::spawn(async move {
-                let mut rcvd_packets = handle.await.unwrap();
+            try_spawn_traced(async move {
+                // 运行时正在关闭，任务被取消了，也就无从转交
+                let Ok(mut rcvd_packets) = handle.await else {
+                    return;
+                };
                 while let Some((packet, pathway, usc)) = rcvd_packets.next().await {
                     closing_conn.recv_packet_via_pathway(packet, pathway, usc);
                 }
             });
         }
 
-        tokio::spawn({
+        try_spawn_traced({
             let conn = self.clone();
             let duration = pto * 3;
             let rcvd_ccf = closing_conn.get_rcvd_ccf();
+            let post_close_rcvd = closing_conn.post_close_rcvd.clone();
+            let close_responder = closing_conn.close_responder.clone();
             async move {
                 let time = Instant::now();
                 if tokio::time::timeout(duration, rcvd_ccf.did_recv())
                     .await
                     .is_ok()
                 {
+                    // 对方回应了CONNECTION_CLOSE，必然已经进入了draining状态
+                    let mut stats = conn.1.lock().unwrap();
+                    stats.close_observed_by_peer = Some(true);
+                    stats.close_responses_suppressed = close_responder.suppressed();
+                    drop(stats);
                     conn.enter_draining(duration - time.elapsed());
                 } else {
+                    // 约一个RTT内对方不再发包，说明其很可能收到了CONNECTION_CLOSE
+                    let observed = post_close_rcvd.observed_by_peer(pto);
+                    let mut stats = conn.1.lock().unwrap();
+                    stats.close_observed_by_peer = Some(observed);
+                    stats.close_responses_suppressed = close_responder.suppressed();
+                    drop(stats);
                     conn.die();
                 }
             }
@@ -336,17 +1500,51 @@ impl ArcConnection {
         *guard = Closing(closing_conn);
     }
 
+    /// Enter draining state from raw state, because the peer closed or reset the connection,
+    /// the streams and the datagrams fail with `error`.
+    fn should_enter_draining_with_error(&self, error: Error, remaining: Duration) {
+        {
+            let guard = self.0.lock().unwrap();
+            if let Raw(ref conn) = *guard {
+                conn.datagrams.on_conn_error(&error);
+                if let Some(streams) = &conn.streams {
+                    streams.on_conn_error(&error);
+                }
+            }
+        }
+        self.enter_draining(remaining);
+    }
+
     /// Enter draining state from raw state or closing state.
     /// Can only be called internally, and the app should not care this method.
     pub(crate) fn enter_draining(&self, remaining: Duration) {
         let mut guard = self.0.lock().unwrap();
         let draining_conn = match mem::replace(guard.deref_mut(), ConnState::Closed) {
-            Raw(conn) => DrainingConnection::from(conn),
+            Raw(conn) => {
+                conn.receive_watchdog.on_conn_error();
+                conn.stall_detector.on_conn_error();
+                conn.reset_tokens.revoke();
+                conn.remote_params.invalid();
+                conn.report_network_telemetry();
+                {
+                    let mut stats = self.1.lock().unwrap();
+                    stats.streams_shed = conn.streams_shed();
+                    stats.crypto = conn.crypto_stats();
+                    stats.sent_records = conn.sent_records_stats();
+                    stats.overhead = conn.overhead.stats();
+                    stats.flow_control_stalls = conn.stall_detector.suspected();
+                    stats.key_updates = conn.data.one_rtt_keys.generation();
+                }
+                DrainingConnection::from(conn)
+            }
             Closing(closing_conn) => DrainingConnection::from(closing_conn),
             _ => unreachable!(),
         };
+        self.drain(guard.deref_mut(), draining_conn, remaining);
+    }
 
-        tokio::spawn({
+    fn drain(&self, state: &mut ConnState, draining_conn: DrainingConnection, remaining: Duration) {
+        try_spawn_traced({
             let conn = self.clone();
             async move {
                 tokio::time::sleep(remaining).await;
@@ -354,7 +1552,7 @@ impl ArcConnection {
             }
         });
 
-        *guard = Draining(draining_conn);
+        *state = Draining(draining_conn);
     }
 
     /// Dismiss the connection, remove it from the global router.
@@ -362,7 +1560,11 @@ impl ArcConnection {
     pub(crate) fn die(self) {
         let mut guard = self.0.lock().unwrap();
         let local_cids = match mem::replace(guard.deref_mut(), ConnState::Closed) {
-            Raw(conn) => conn.cid_registry.local,
+            Raw(conn) => {
+                conn.reset_tokens.revoke();
+                conn.report_network_telemetry();
+                conn.cid_registry.local
+            }
             Closing(conn) => conn.cid_registry.local,
             Draining(conn) => conn.local_cids().clone(),
             Closed => return,
@@ -371,6 +1573,301 @@ impl ArcConnection {
         local_cids.active_cids().iter().for_each(|cid| {
             ROUTER.remove(cid);
         });
+        self.3.send_replace(true);
+    }
+
+    /// Wait until the connection is terminated, i.e. its closing or draining state is over
+    /// and its packets are no longer routed to it, however it's closed.
+    pub async fn terminated(&self) {
+        let mut terminated = self.3.subscribe();
+        _ = terminated.wait_for(|terminated| *terminated).await;
+    }
+
+    /// The flights of the TLS handshake sent by this endpoint, see [`HandshakeStats`].
+    ///
+    /// None if the connection is closing or closed.
+    pub fn handshake_stats(&self) -> Option<HandshakeStats> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.tls_session.handshake_stats(),
+            _ => None,
+        }
+    }
+
+    /// Whether the server accepted the 0-RTT data of this resumed connection, known once the
+    /// transport parameters of the server are received, see
+    /// [`ArcTlsSession::is_early_data_accepted`].
+    ///
+    /// None on the server side, or if the connection is closing or closed.
+    pub fn is_0rtt_accepted(&self) -> Option<bool> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.tls_session.is_early_data_accepted(),
+            _ => None,
+        }
+    }
+
+    /// The snapshot of the statistics, it is final once the connection is closed.
+    pub fn stats(&self) -> ConnectionStats {
+        let guard = self.0.lock().unwrap();
+        let mut stats = ConnectionStats {
+            dropped: self.2.stats(),
+            ..*self.1.lock().unwrap()
+        };
+        // 连接关闭前，被丢弃的流、收到的CRYPTO数据还在增加
+        if let Raw(ref conn) = *guard {
+            stats.streams_shed = conn.streams_shed();
+            stats.crypto = conn.crypto_stats();
+            stats.sent_records = conn.sent_records_stats();
+            stats.overhead = conn.overhead.stats();
+            stats.flow_control_stalls = conn.stall_detector.suspected();
+            stats.key_updates = conn.data.one_rtt_keys.generation();
+            let level = *conn.pressure.lock().unwrap();
+            stats.pressure = PressureStats {
+                level,
+                shedding_streams: conn
+                    .streams
+                    .as_ref()
+                    .is_some_and(|streams| streams.listener().is_shedding()),
+                ack_eagerness: level.ack_eagerness(*conn.ack_eagerness.lock().unwrap()),
+                datagram_queue_limit: level.datagram_queue_limit(),
+                datagrams_dropped: conn.datagrams.dropped(),
+                recv_window: conn.flow_ctrl.recver.window(),
+            };
+        }
+        stats
+    }
+
+    /// How active the connection is recently, see [`activity::Activity`].
+    ///
+    /// None if the connection is closing or closed.
+    pub fn activity(&self) -> Option<activity::Activity> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => Some(conn.activity.snapshot()),
+            _ => None,
+        }
+    }
+
+    /// A cheap snapshot of the liveness of the connection for the health probes, see
+    /// [`ConnectionHealth`]. It never sends anything.
+    pub fn health(&self) -> ConnectionHealth {
+        let guard = self.0.lock().unwrap();
+        let conn = match *guard {
+            Raw(ref conn) => conn,
+            Closing(_) => return ConnectionHealth::closed(ConnectionState::Closing),
+            Draining(_) => return ConnectionHealth::closed(ConnectionState::Draining),
+            Closed => return ConnectionHealth::closed(ConnectionState::Closed),
+        };
+        let state = if conn.handshake.is_handshake_done() {
+            ConnectionState::Established
+        } else {
+            ConnectionState::Handshaking
+        };
+        let now = Instant::now();
+        let elapsed = |time: Option<Instant>| time.map(|time| now.saturating_duration_since(time));
+        let (consecutive_pto_count, paths_validated) =
+            conn.pathes
+                .iter()
+                .fold((0, 0), |(pto_count, validated), entry| {
+                    let path = entry.value();
+                    (
+                        pto_count.max(path.cc.metrics().pto_count),
+                        validated + path.anti_amplifier.is_granted() as usize,
+                    )
+                });
+        ConnectionHealth {
+            state,
+            last_rx_elapsed: elapsed(conn.activity.last_rcvd()),
+            last_tx_elapsed: elapsed(conn.activity.last_sent()),
+            consecutive_pto_count,
+            paths_validated,
+        }
+    }
+
+    /// Make sure the peer is still reachable, returns the round-trip time.
+    ///
+    /// If a packet is received within `max_age`, it returns the smoothed RTT of the path
+    /// immediately. Otherwise it sends an ack-eliciting packet on each path, a PING frame
+    /// if there's nothing else to send, and waits for any packet from the peer, for up to
+    /// three probe timeouts; the time it takes is returned.
+    ///
+    /// It fails with [`io::ErrorKind::TimedOut`] if the peer doesn't respond in time, or
+    /// [`io::ErrorKind::NotConnected`] if the handshake is not confirmed yet.
+    pub async fn verify_liveness(&self, max_age: Duration) -> io::Result<Duration> {
+        let (activity, pathes) = {
+            let guard = self.0.lock().unwrap();
+            let Raw(ref conn) = *guard else {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Connection is closing or closed",
+                ));
+            };
+            if !conn.handshake.is_handshake_done() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Connection is not established yet",
+                ));
+            }
+            let pathes = conn
+                .pathes
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect::<Vec<_>>();
+            (conn.activity.clone(), pathes)
+        };
+
+        let start = Instant::now();
+        let fresh = activity
+            .last_rcvd()
+            .is_some_and(|last_rcvd| start.saturating_duration_since(last_rcvd) <= max_age);
+        if fresh {
+            let latest_path = pathes
+                .iter()
+                .filter_map(|path| Some((path.last_recv_time()?, path)))
+                .max_by_key(|(recv_time, _)| *recv_time);
+            if let Some((_, path)) = latest_path {
+                return Ok(path.cc.smoothed_rtt());
+            }
+        }
+
+        // 每条路径都发一个ack-eliciting的包，收到对端的任何包都说明对端还活着
+        let mut deadline = Duration::ZERO;
+        for path in pathes.iter() {
+            path.cc.request_probe();
+            deadline = deadline.max(path.cc.pto_time(Epoch::Data) * 3);
+        }
+        match tokio::time::timeout(deadline, activity.rcvd_since(start)).await {
+            Ok(()) => Ok(start.elapsed()),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Peer didn't respond within 3 probe timeouts",
+            )),
+        }
+    }
+
+    /// The snapshots of the congestion control state of the living paths, see [`PathMetrics`].
+    /// It's cheap enough to be polled every second for dashboards.
+    ///
+    /// Empty if the connection is closing or closed.
+    pub fn path_metrics(&self) -> Vec<(Pathway, PathMetrics)> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn
+                .pathes
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().cc.metrics()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The stable id of the connection for correlating the logs, which is the
+    /// [`ArcConnection::odcid`]. The readers and writers of the streams carry it as well.
+    pub fn id(&self) -> ConnectionId {
+        self.odcid()
+    }
+
+    /// The original destination connection ID, i.e. the destination connection ID of the
+    /// first Initial packet sent by the client. Unlike the connection IDs in use, it never
+    /// changes, and it's still available after the connection is closed.
+    ///
+    /// Both endpoints see the same one, except that the server only knows the destination
+    /// connection ID of the Initial packet after the Retry, if it has sent a Retry.
+    pub fn odcid(&self) -> ConnectionId {
+        self.4
+    }
+
+    /// The connection IDs issued by this endpoint, which the peer can send packets to. They
+    /// rotate as the peer retires them and new ones are issued.
+    ///
+    /// Empty if the connection is closing or closed.
+    pub fn local_cids(&self) -> Vec<ConnectionId> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.cid_registry.local.active_cids(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The connection IDs issued by the peer, which the paths are sending packets to right
+    /// now. They rotate as the peer issues new ones and retires the old ones.
+    ///
+    /// Empty if the connection is closing or closed.
+    pub fn remote_cids(&self) -> Vec<ConnectionId> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.cid_registry.remote.in_use_cids(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The local address of the path a packet was received on most recently, i.e. the
+    /// socket the peer is reaching now. For a server bound to several addresses, it's the
+    /// one the connection arrived on, and it follows the client migrating between them.
+    ///
+    /// None if nothing is received yet, or the connection is closing or closed.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn
+                .pathes
+                .iter()
+                .filter_map(|entry| Some((entry.value().last_recv_time()?, *entry.key())))
+                .max_by_key(|(recv_time, _)| *recv_time)
+                .map(|(_, pathway)| pathway.local_addr()),
+            _ => None,
+        }
+    }
+
+    /// The address of this endpoint observed by the peer most recently, i.e. the reflexive
+    /// transport address behind a NAT, see [`address_discovery::ArcAddressDiscovery`].
+    ///
+    /// None if the address discovery extension is not negotiated to receive the reports,
+    /// the peer hasn't reported yet, or the connection is closing or closed.
+    pub fn observed_address(&self) -> Option<SocketAddr> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.address_discovery.observed_address(),
+            _ => None,
+        }
+    }
+
+    /// The address of this endpoint observed by the peer on the path.
+    pub fn path_observed_address(&self, pathway: &Pathway) -> Option<SocketAddr> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => conn.address_discovery.path_observed_address(pathway),
+            _ => None,
+        }
+    }
+
+    /// Watch the changes of [`ArcConnection::observed_address`], e.g. after a NAT rebinding
+    /// or a migration, the watch ends once the connection is closed.
+    pub fn watch_observed_address(&self) -> io::Result<watch::Receiver<Option<SocketAddr>>> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(ref conn) => Ok(conn.address_discovery.subscribe()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            )),
+        }
+    }
+
+    /// The snapshots of the living streams for diagnostics, ordered by the stream id, see
+    /// [`StreamIntrospection`]. They never carry any stream data.
+    ///
+    /// Empty if the connection is closing or closed.
+    pub fn introspect_streams(&self) -> Vec<StreamIntrospection> {
+        let guard = self.0.lock().unwrap();
+        match *guard {
+            Raw(RawConnection {
+                streams: Some(ref streams),
+                ..
+            }) => streams.introspect(),
+            _ => Vec::new(),
+        }
     }
 
     pub fn update_path_recv_time(&self, pathway: Pathway) {
@@ -380,19 +1877,72 @@ impl ArcConnection {
         }
     }
 
-    pub fn recv_retry_packet(&self, retry: &RetryHeader) {
+    /// 客户端收到Retry包，`packet`是该Retry包的全部字节，用以校验完整性标签。
+    ///
+    /// 一个连接至多接受一个Retry包，收到服务端的Initial包之后也不再接受；完整性标签不对、
+    /// 令牌为空、源连接ID与首个Initial包的目的连接ID相同的Retry包，都要丢弃，见RFC 9000 17.2.5.2。
+    /// 返回是否接受了该Retry包。
+    pub fn recv_retry_packet(&self, retry: &RetryHeader, packet: &[u8]) -> bool {
         let guard = self.0.lock().unwrap();
         if let Raw(ref conn) = *guard {
+            let mut retry_scid = conn.retry_scid.lock().unwrap();
+            if retry_scid.is_some()
+                || conn.cid_registry.remote.initial_dcid() != Some(conn.odcid)
+                || retry.token.is_empty()
+                || retry.scid == conn.odcid
+                || !verify_retry_integrity(&conn.odcid, packet)
+            {
+                return false;
+            }
+            *retry_scid = Some(retry.scid);
             *conn.token.lock().unwrap() = retry.token.to_vec();
             conn.cid_registry.remote.revise_initial_dcid(retry.scid);
-            let sent_record = conn.initial.space.sent_packets();
-            let mut guard = sent_record.receive();
-            for i in 0..guard.largest_pn() {
-                for frame in guard.may_loss_pkt(i) {
-                    conn.initial.crypto_stream.outgoing().may_loss_data(&frame);
+            // Initial包的密钥由客户端选取的目的连接ID派生，换用Retry包的源连接ID之后要重新派生
+            conn.initial.keys.replace_keys(ArcTlsSession::initial_keys(
+                &rustls::crypto::ring::default_provider(),
+                rustls::Side::Client,
+                retry.scid,
+            ));
+            conn.resend_initial_crypto();
+            return true;
+        }
+        false
+    }
+
+    /// 客户端收到版本协商包，见RFC 9000 6.2。
+    ///
+    /// 接受过Retry包、收到过服务端的Initial包之后，源连接ID与首个Initial包的目的连接ID不符的，
+    /// 列出了当前版本的，或者已经协商过一次的版本协商包，都要丢弃。否则换用双方都支持的、
+    /// 客户端最偏好的版本重新发送Initial包；没有这样的版本，则放弃连接，不再发送任何包。
+    /// 返回是否接受了该版本协商包。
+    pub fn recv_version_negotiation(&self, vn: &VersionNegotiationHeader) -> bool {
+        let guard = self.0.lock().unwrap();
+        if let Raw(ref conn) = *guard {
+            if conn.retry_scid.lock().unwrap().is_some()
+                || conn.cid_registry.remote.initial_dcid() != Some(conn.odcid)
+                || vn.scid != conn.odcid
+            {
+                return false;
+            }
+            let negotiated = conn
+                .versions
+                .lock()
+                .unwrap()
+                .recv_version_negotiation(&vn.versions);
+            match negotiated {
+                Ok(Some(version)) => {
+                    tracing::debug!(version, "switch version after version negotiation");
+                    conn.resend_initial_crypto();
+                    return true;
+                }
+                Ok(None) => return false,
+                Err(error) => {
+                    conn.error.on_version_negotiation_failed(error);
+                    return true;
                 }
             }
         }
+        false
     }
 }
 
@@ -400,21 +1950,38 @@ impl From<RawConnection> for ArcConnection {
     fn from(raw_conn: RawConnection) -> Self {
         let conn_error = raw_conn.error.clone();
         let pathes = raw_conn.pathes.clone();
-        let conn = ArcConnection(Arc::new(Mutex::new(ConnState::Raw(raw_conn))));
+        let _enter = raw_conn.trace.enter();
+        let drops = raw_conn.drops.clone();
+        let odcid = raw_conn.odcid;
+        let stats = ConnectionStats {
+            footprint: raw_conn.footprint(),
+            ..Default::default()
+        };
+        let conn = ArcConnection(
+            Arc::new(Mutex::new(ConnState::Raw(raw_conn))),
+            Arc::new(Mutex::new(stats)),
+            drops,
+            Arc::new(watch::channel(false).0),
+            odcid,
+            conn_error.clone(),
+        );
 
-        tokio::spawn({
+        spawn_traced({
             let conn = conn.clone();
             async move {
                 let (err, is_active) = conn_error.did_error_occur().await;
                 if is_active {
                     conn.should_enter_closing_with_error(err);
                 } else {
+                    if conn_error.is_stateless_reset() {
+                        conn.1.lock().unwrap().stateless_reset = true;
+                    }
                     let pto = pathes
                         .iter()
                         .map(|p| p.cc.pto_time(Epoch::Data))
                         .max()
-                        .unwrap();
-                    conn.enter_draining(pto * 3);
+                        .unwrap_or_default();
+                    conn.should_enter_draining_with_error(err, pto * 3);
                 }
             }
         });
@@ -422,5 +1989,178 @@ impl From<RawConnection> for ArcConnection {
         conn
     }
 }
+
+fn unknown_lane(label: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("stream lane {label:?} is not registered"),
+    )
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use qbase::{
+        cid::UniqueCid,
+        token::{stateless_reset, ResetToken},
+    };
+
+    use super::*;
+    use crate::router::RESET_TOKENS;
+
+    #[test]
+    fn test_drop_runtime_with_connection_alive() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let conn = runtime.block_on(async {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let tls_config = rustls::ClientConfig::builder_with_provider(provider)
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .unwrap()
+                .with_root_certificates(rustls::RootCertStore::empty())
+                .with_no_client_auth();
+            let conn = ArcConnection::new_client(
+                ConnectionId::random_gen(8),
+                "localhost".into(),
+                ClientParameters::default(),
+                ConnectionProfile::Full,
+                Arc::new(tls_config),
+                ArcTokenRegistry::default_sink("localhost".into()),
+                StatelessResetKey::random(),
+                true,
+            );
+            let usc = ArcUsc::new("127.0.0.1:0".parse().unwrap()).unwrap();
+            // 发往一个无人监听的地址，握手永远不会完成
+            let pathway = Pathway::direct(usc.local_addr(), "127.0.0.1:9".parse().unwrap());
+            conn.add_initial_path(pathway, usc);
+            // 等待打开流的任务，随运行时一同被丢弃
+            for _ in 0..4 {
+                let conn = conn.clone();
+                tokio::spawn(async move { conn.open_bi_stream().await });
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            conn
+        });
+        drop(runtime);
+
+        // 运行时已关闭，关闭连接、释放连接都不能panic
+        conn.close(0, "bye");
+        conn.should_enter_closing_with_error(Error::with_default_fty(
+            ErrorKind::Application,
+            "bye",
+        ));
+        drop(conn);
+    }
+
+    fn client(profile: ConnectionProfile) -> ArcConnection {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let tls_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        ArcConnection::new_client(
+            ConnectionId::random_gen(8),
+            "localhost".into(),
+            ClientParameters::default(),
+            profile,
+            Arc::new(tls_config),
+            ArcTokenRegistry::default_sink("localhost".into()),
+            StatelessResetKey::random(),
+            true,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_datagram_only_profile() {
+        let full = client(ConnectionProfile::Full);
+        let datagram_only = client(ConnectionProfile::DatagramOnly);
+        assert!(datagram_only.stats().footprint < full.stats().footprint);
+
+        let is_disabled = |error: io::Error| {
+            error.kind() == io::ErrorKind::Unsupported
+                && error
+                    .get_ref()
+                    .is_some_and(|error| error.downcast_ref::<StreamsDisabled>().is_some())
+        };
+        assert!(is_disabled(
+            datagram_only.open_bi_stream().await.unwrap_err()
+        ));
+        assert!(is_disabled(
+            datagram_only.try_open_uni_stream().unwrap_err()
+        ));
+        assert!(is_disabled(
+            datagram_only.accept_uni_stream().await.unwrap_err()
+        ));
+        // 数据报不受影响
+        assert!(datagram_only.datagrams().is_ok());
+        // 完整的连接仍可以尝试打开流，只是还没有对方的参数
+        assert!(full.try_open_uni_stream().is_ok());
+
+        full.close(0, "bye");
+        datagram_only.close(0, "bye");
+    }
+
+    #[tokio::test]
+    async fn test_stateless_reset() {
+        let conn = client(ConnectionProfile::Full);
+        let usc = ArcUsc::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let pathway = Pathway::direct(usc.local_addr(), "127.0.0.1:9".parse().unwrap());
+        conn.add_initial_path(pathway, usc);
+        let token = ResetToken::random_gen();
+        if let Raw(ref raw_conn) = *conn.0.lock().unwrap() {
+            raw_conn.cid_registry.remote.set_initial_reset_token(token);
+            raw_conn.reset_tokens.register(token);
+        }
+
+        // 末尾不是重置令牌的，或者太短的，都不是无状态重置
+        let other = stateless_reset(&ResetToken::random_gen(), 100).unwrap();
+        assert!(!RESET_TOKENS.recv_stateless_reset(&other));
+        assert!(!RESET_TOKENS.recv_stateless_reset(&token));
+
+        let reset = stateless_reset(&token, 100).unwrap();
+        assert!(RESET_TOKENS.recv_stateless_reset(&reset));
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !conn.stats().stateless_reset {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // 连接已经进入draining，令牌随之撤销，再收到也不予理会，更不会回应
+        assert!(!RESET_TOKENS.recv_stateless_reset(&reset));
+    }
+
+    #[tokio::test]
+    async fn test_close_with_app_error() {
+        let conn = client(ConnectionProfile::Full);
+        let usc = ArcUsc::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let pathway = Pathway::direct(usc.local_addr(), "127.0.0.1:9".parse().unwrap());
+        conn.add_initial_path(pathway, usc);
+        let cids = conn.local_cids();
+        assert!(!cids.is_empty());
+        let pto = match *conn.0.lock().unwrap() {
+            Raw(ref raw_conn) => raw_conn
+                .pathes
+                .iter()
+                .map(|path| path.cc.pto_time(Epoch::Data))
+                .max()
+                .unwrap_or_default(),
+            _ => unreachable!(),
+        };
+
+        let time = Instant::now();
+        conn.close(42, "bye");
+        // 关闭之后的一段时间内，仍要能收到对方的包并回应CONNECTION_CLOSE
+        assert!(cids.iter().all(|cid| !ROUTER.is_unique_cid(cid)));
+
+        let error = conn.closed().await;
+        assert!(time.elapsed() + Duration::from_millis(50) >= pto * 3);
+        assert!(cids.iter().all(|cid| ROUTER.is_unique_cid(cid)));
+        assert_eq!(
+            error,
+            ConnectionError::Application {
+                code: 42,
+                reason: "bye".into(),
+            }
+        );
+    }
+}
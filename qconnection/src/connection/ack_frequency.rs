@@ -0,0 +1,147 @@
+use std::sync::{Arc, Mutex};
+
+use qbase::{
+    error::{Error, ErrorKind},
+    frame::{AckFrequencyFrame, FrameType},
+    varint::VarInt,
+};
+use qcongestion::congestion::AckFrequency;
+
+#[derive(Debug, Default)]
+struct RawAckFrequency {
+    // 本地传输参数中的min_ack_delay，单位微秒，None表示不支持该扩展
+    min_ack_delay: Option<u64>,
+    // 收到的序号最大的ACK_FREQUENCY帧
+    latest: Option<AckFrequencyFrame>,
+    // 下一个发送的ACK_FREQUENCY帧的序号
+    next_sequence: u64,
+}
+
+/// The state of the ACK_FREQUENCY extension of a connection, see
+/// [draft-ietf-quic-ack-frequency](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency).
+///
+/// The peer's latest request applies to all the paths, including the ones created later.
+#[derive(Debug, Clone, Default)]
+pub struct ArcAckFrequency(Arc<Mutex<RawAckFrequency>>);
+
+impl ArcAckFrequency {
+    pub fn new(min_ack_delay: Option<VarInt>) -> Self {
+        Self(Arc::new(Mutex::new(RawAckFrequency {
+            min_ack_delay: min_ack_delay.map(VarInt::into_inner),
+            ..Default::default()
+        })))
+    }
+
+    /// Receive an ACK_FREQUENCY frame, return the frequency to apply if it's newer than
+    /// the ones received before, the ones out of order are ignored.
+    ///
+    /// It's a PROTOCOL_VIOLATION if the extension is not advertised by the local
+    /// min_ack_delay, or the requested max ack delay is less than it.
+    pub fn recv_frame(&self, frame: &AckFrequencyFrame) -> Result<Option<AckFrequency>, Error> {
+        let mut raw = self.0.lock().unwrap();
+        let Some(min_ack_delay) = raw.min_ack_delay else {
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                FrameType::AckFrequency,
+                "min_ack_delay transport parameter is not sent",
+            ));
+        };
+        if frame.request_max_ack_delay < min_ack_delay {
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                FrameType::AckFrequency,
+                "requested max ack delay is less than min_ack_delay",
+            ));
+        }
+        if raw
+            .latest
+            .is_some_and(|latest| latest.sequence_number >= frame.sequence_number)
+        {
+            return Ok(None);
+        }
+        raw.latest = Some(*frame);
+        Ok(Some(frame.into()))
+    }
+
+    /// The frequency of the latest ACK_FREQUENCY frame received, for the new paths.
+    pub fn latest(&self) -> Option<AckFrequency> {
+        self.0
+            .lock()
+            .unwrap()
+            .latest
+            .as_ref()
+            .map(AckFrequency::from)
+    }
+
+    /// Build the next ACK_FREQUENCY frame to send, with an increasing sequence number.
+    pub fn next_frame(
+        &self,
+        ack_eliciting_threshold: VarInt,
+        request_max_ack_delay: VarInt,
+        reordering_threshold: VarInt,
+    ) -> AckFrequencyFrame {
+        let mut raw = self.0.lock().unwrap();
+        let sequence_number = VarInt::from_u64(raw.next_sequence)
+            .expect("sequence number of ACK_FREQUENCY frame can not exceed 2^62");
+        raw.next_sequence += 1;
+        AckFrequencyFrame {
+            sequence_number,
+            ack_eliciting_threshold,
+            request_max_ack_delay,
+            reordering_threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn frame(sequence_number: u32, ack_eliciting_threshold: u32) -> AckFrequencyFrame {
+        AckFrequencyFrame {
+            sequence_number: VarInt::from_u32(sequence_number),
+            ack_eliciting_threshold: VarInt::from_u32(ack_eliciting_threshold),
+            request_max_ack_delay: VarInt::from_u32(25_000),
+            reordering_threshold: VarInt::from_u32(1),
+        }
+    }
+
+    #[test]
+    fn test_recv_in_order() {
+        let frequency = ArcAckFrequency::new(Some(VarInt::from_u32(1000)));
+        let applied = frequency.recv_frame(&frame(1, 9)).unwrap().unwrap();
+        assert_eq!(applied.ack_eliciting_threshold, 9);
+        assert_eq!(applied.max_ack_delay, Duration::from_millis(25));
+        // 乱序到达的旧帧被忽略
+        assert_eq!(frequency.recv_frame(&frame(0, 1)).unwrap(), None);
+        assert_eq!(frequency.recv_frame(&frame(1, 1)).unwrap(), None);
+        assert_eq!(frequency.latest(), Some(applied));
+        assert!(frequency.recv_frame(&frame(2, 4)).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_protocol_violation() {
+        let frequency = ArcAckFrequency::new(None);
+        let error = frequency.recv_frame(&frame(0, 9)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+
+        let frequency = ArcAckFrequency::new(Some(VarInt::from_u32(30_000)));
+        let error = frequency.recv_frame(&frame(0, 9)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert_eq!(frequency.latest(), None);
+    }
+
+    #[test]
+    fn test_next_frame() {
+        let frequency = ArcAckFrequency::default();
+        let threshold = VarInt::from_u32(9);
+        let delay = VarInt::from_u32(25_000);
+        let reordering = VarInt::from_u32(0);
+        let first = frequency.next_frame(threshold, delay, reordering);
+        let second = frequency.next_frame(threshold, delay, reordering);
+        assert_eq!(first.sequence_number, VarInt::from_u32(0));
+        assert_eq!(second.sequence_number, VarInt::from_u32(1));
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+// 统计接收字节数的时间窗口，按秒分桶
+const WINDOW_SECS: u64 = 60;
+
+#[derive(Debug)]
+struct RawActivity {
+    since: Instant,
+    last_rcvd: Instant,
+    // 第n秒收到的字节数，存放在buckets[n % WINDOW_SECS]
+    buckets: [u64; WINDOW_SECS as usize],
+    // buckets中最新的一秒，更早的桶在前进时被清零
+    latest_sec: u64,
+    rcvd_total: u64,
+}
+
+impl RawActivity {
+    fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            last_rcvd: now,
+            buckets: [0; WINDOW_SECS as usize],
+            latest_sec: 0,
+            rcvd_total: 0,
+        }
+    }
+
+    fn advance(&mut self, now: Instant) -> u64 {
+        let sec = now.saturating_duration_since(self.since).as_secs();
+        if sec > self.latest_sec {
+            let expired = (sec - self.latest_sec).min(WINDOW_SECS);
+            for n in self.latest_sec + 1..=self.latest_sec + expired {
+                self.buckets[(n % WINDOW_SECS) as usize] = 0;
+            }
+            self.latest_sec = sec;
+        }
+        sec
+    }
+
+    fn on_rcvd(&mut self, bytes: usize, now: Instant) {
+        let sec = self.advance(now);
+        // 并发收包时，稍早的时刻可能晚一些才记录
+        if self.latest_sec - sec < WINDOW_SECS {
+            self.buckets[(sec % WINDOW_SECS) as usize] += bytes as u64;
+        }
+        self.last_rcvd = self.last_rcvd.max(now);
+        self.rcvd_total += bytes as u64;
+    }
+
+    fn snapshot(&mut self, now: Instant) -> Activity {
+        self.advance(now);
+        Activity {
+            idle: now.saturating_duration_since(self.last_rcvd),
+            rcvd_last_minute: self.buckets.iter().sum(),
+            rcvd_total: self.rcvd_total,
+        }
+    }
+}
+
+// 最近一次收、发包的时刻，记为距since的纳秒数加1，0表示还没有过；无锁，供健康检查频繁读取
+#[derive(Debug)]
+struct LastSeen {
+    since: Instant,
+    rcvd: AtomicU64,
+    sent: AtomicU64,
+    // 每收到一个包都通知，等待对端回应的任务据此醒来
+    rcvd_notify: Notify,
+}
+
+impl LastSeen {
+    fn new(since: Instant) -> Self {
+        Self {
+            since,
+            rcvd: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            rcvd_notify: Notify::new(),
+        }
+    }
+
+    fn record(&self, slot: &AtomicU64, now: Instant) {
+        let nanos = now.saturating_duration_since(self.since).as_nanos() as u64;
+        slot.fetch_max(nanos + 1, Ordering::Relaxed);
+    }
+
+    fn load(&self, slot: &AtomicU64) -> Option<Instant> {
+        match slot.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(self.since + Duration::from_nanos(nanos - 1)),
+        }
+    }
+}
+
+/// How active the connection is recently, judged by the packets received from the peer.
+///
+/// Only the packets decrypted successfully count, the ones forged or duplicated don't keep
+/// a connection active.
+///
+/// The time of the last packet received and sent are kept apart without locking, they're
+/// cheap enough to be read by the health probes, see [`ArcActivity::last_rcvd`].
+#[derive(Debug, Clone)]
+pub struct ArcActivity {
+    raw: Arc<Mutex<RawActivity>>,
+    last_seen: Arc<LastSeen>,
+}
+
+impl Default for ArcActivity {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            raw: Arc::new(Mutex::new(RawActivity::new(now))),
+            last_seen: Arc::new(LastSeen::new(now)),
+        }
+    }
+}
+
+impl ArcActivity {
+    /// Called when a packet is received and decrypted, with the size of its payload.
+    pub fn on_rcvd(&self, bytes: usize) {
+        let now = Instant::now();
+        self.raw.lock().unwrap().on_rcvd(bytes, now);
+        self.last_seen.record(&self.last_seen.rcvd, now);
+        self.last_seen.rcvd_notify.notify_waiters();
+    }
+
+    /// Called when a datagram is sent on any path.
+    pub fn on_sent(&self) {
+        self.last_seen.record(&self.last_seen.sent, Instant::now());
+    }
+
+    /// When a packet was received most recently, None if nothing is received yet.
+    pub fn last_rcvd(&self) -> Option<Instant> {
+        self.last_seen.load(&self.last_seen.rcvd)
+    }
+
+    /// When a datagram was sent most recently, None if nothing is sent yet.
+    pub fn last_sent(&self) -> Option<Instant> {
+        self.last_seen.load(&self.last_seen.sent)
+    }
+
+    /// Resolves once a packet received after `since` is recorded.
+    pub async fn rcvd_since(&self, since: Instant) {
+        loop {
+            // 先登记再检查，以免漏掉检查之后、等待之前的通知
+            let notified = self.last_seen.rcvd_notify.notified();
+            if self.last_rcvd().is_some_and(|last_rcvd| last_rcvd > since) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// When the connection was created.
+    pub fn since(&self) -> Instant {
+        self.last_seen.since
+    }
+
+    pub fn snapshot(&self) -> Activity {
+        self.raw.lock().unwrap().snapshot(Instant::now())
+    }
+}
+
+/// The snapshot of [`ArcActivity`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    /// How long since the last packet was received, or since the connection was created if
+    /// nothing is received yet.
+    pub idle: Duration,
+    /// The payload bytes of the packets received within the last minute, at a granularity
+    /// of one second.
+    pub rcvd_last_minute: u64,
+    /// The payload bytes of the packets received since the connection was created.
+    pub rcvd_total: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sliding_window() {
+        let since = Instant::now();
+        let mut raw = RawActivity::new(since);
+        let at = |secs: u64| since + Duration::from_secs(secs);
+
+        raw.on_rcvd(100, at(0));
+        raw.on_rcvd(200, at(30));
+        let activity = raw.snapshot(at(45));
+        assert_eq!(activity.idle, Duration::from_secs(15));
+        assert_eq!(activity.rcvd_last_minute, 300);
+
+        // 第0秒收到的已滑出窗口
+        assert_eq!(raw.snapshot(at(60)).rcvd_last_minute, 200);
+        assert_eq!(raw.snapshot(at(90)).rcvd_last_minute, 0);
+
+        // 迟到的记录仍落在其所属的一秒
+        raw.on_rcvd(50, at(95));
+        raw.on_rcvd(10, at(94));
+        let activity = raw.snapshot(at(95));
+        assert_eq!(activity.idle, Duration::ZERO);
+        assert_eq!(activity.rcvd_last_minute, 60);
+
+        // 长时间空闲后，整个窗口都被清零
+        let activity = raw.snapshot(at(1000));
+        assert_eq!(activity.idle, Duration::from_secs(905));
+        assert_eq!(activity.rcvd_last_minute, 0);
+        assert_eq!(activity.rcvd_total, 360);
+    }
+
+    #[test]
+    fn test_last_seen() {
+        let since = Instant::now();
+        let last_seen = LastSeen::new(since);
+        assert_eq!(last_seen.load(&last_seen.rcvd), None);
+
+        // 刚创建时的收包也与从未收到区分开
+        last_seen.record(&last_seen.rcvd, since);
+        assert_eq!(last_seen.load(&last_seen.rcvd), Some(since));
+
+        // 并发记录时，稍早的时刻不会覆盖更晚的
+        let at = |millis: u64| since + Duration::from_millis(millis);
+        last_seen.record(&last_seen.sent, at(20));
+        last_seen.record(&last_seen.sent, at(10));
+        assert_eq!(last_seen.load(&last_seen.sent), Some(at(20)));
+        assert_eq!(last_seen.load(&last_seen.rcvd), Some(since));
+    }
+}
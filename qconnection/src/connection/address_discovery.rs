@@ -0,0 +1,251 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use qbase::{
+    config::AddressDiscovery,
+    error::{Error, ErrorKind},
+    frame::{BeFrame, ObservedAddressFrame},
+    varint::VarInt,
+};
+use tokio::sync::watch;
+
+use crate::path::Pathway;
+
+// 两次报告之间的最小间隔，频繁迁移的对端不至于引发大量的OBSERVED_ADDRESS帧
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct RawAddressDiscovery {
+    // 本地传输参数中的取值，None表示不支持该扩展
+    local: Option<AddressDiscovery>,
+    // 对端传输参数中的取值，收到对端的传输参数前为None
+    remote: Option<AddressDiscovery>,
+    // 已经报告过的路径，一条路径只报告一次，对端地址变化会产生新的路径
+    reported: HashSet<Pathway>,
+    last_reported: Option<Instant>,
+    next_sequence: u64,
+    // 对端在各条路径上观察到的本端地址，及其报告的序号
+    observed: HashMap<Pathway, (VarInt, SocketAddr)>,
+    // 序号最大的报告，即对端最新观察到的本端地址
+    latest: Option<(VarInt, SocketAddr)>,
+}
+
+impl RawAddressDiscovery {
+    fn should_report(&self) -> bool {
+        self.local.is_some_and(AddressDiscovery::provides)
+            && self.remote.is_some_and(AddressDiscovery::receives)
+    }
+}
+
+/// The state of the address discovery extension of a connection, see
+/// [draft-ietf-quic-address-discovery](https://datatracker.ietf.org/doc/html/draft-ietf-quic-address-discovery).
+///
+/// It reports the address of the peer observed on each validated path by an OBSERVED_ADDRESS
+/// frame, and keeps the addresses of this endpoint reported by the peer, so that an endpoint
+/// behind a NAT learns its reflexive transport address.
+#[derive(Debug, Clone)]
+pub struct ArcAddressDiscovery {
+    raw: Arc<Mutex<RawAddressDiscovery>>,
+    changed: Arc<watch::Sender<Option<SocketAddr>>>,
+}
+
+impl ArcAddressDiscovery {
+    pub fn new(local: Option<AddressDiscovery>) -> Self {
+        let (changed, _) = watch::channel(None);
+        Self {
+            raw: Arc::new(Mutex::new(RawAddressDiscovery {
+                local,
+                ..Default::default()
+            })),
+            changed: Arc::new(changed),
+        }
+    }
+
+    /// Called with the transport parameter of the peer, the reports are only sent if the
+    /// peer is willing to receive them.
+    pub fn on_remote_params(&self, remote: Option<AddressDiscovery>) {
+        self.raw.lock().unwrap().remote = remote;
+    }
+
+    /// Called when a packet is received on a validated path, return the OBSERVED_ADDRESS
+    /// frame to send on the path, if the path is not reported yet.
+    ///
+    /// The reports are rate limited, a path skipped is reported on a later packet.
+    pub fn on_path_rcvd(&self, pathway: Pathway, now: Instant) -> Option<ObservedAddressFrame> {
+        let mut raw = self.raw.lock().unwrap();
+        if !raw.should_report() || raw.reported.contains(&pathway) {
+            return None;
+        }
+        if raw
+            .last_reported
+            .is_some_and(|last| now.saturating_duration_since(last) < REPORT_INTERVAL)
+        {
+            return None;
+        }
+        let sequence_number = VarInt::from_u64(raw.next_sequence)
+            .expect("sequence number of OBSERVED_ADDRESS frame can not exceed 2^62");
+        raw.next_sequence += 1;
+        raw.last_reported = Some(now);
+        raw.reported.insert(pathway);
+        Some(ObservedAddressFrame {
+            sequence_number,
+            address: pathway.remote_addr(),
+        })
+    }
+
+    /// Receive an OBSERVED_ADDRESS frame on the path, the ones older than the latest
+    /// report of the path are ignored.
+    ///
+    /// It's a PROTOCOL_VIOLATION if the local transport parameter doesn't ask for them.
+    pub fn recv_frame(&self, frame: &ObservedAddressFrame, pathway: Pathway) -> Result<(), Error> {
+        let mut raw = self.raw.lock().unwrap();
+        if !raw.local.is_some_and(AddressDiscovery::receives) {
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "OBSERVED_ADDRESS frame is not negotiated",
+            ));
+        }
+        let report = (frame.sequence_number, frame.address);
+        match raw.observed.get(&pathway) {
+            Some((sequence_number, _)) if *sequence_number >= frame.sequence_number => {
+                return Ok(())
+            }
+            _ => _ = raw.observed.insert(pathway, report),
+        }
+        if raw
+            .latest
+            .is_some_and(|(sequence_number, _)| sequence_number >= frame.sequence_number)
+        {
+            return Ok(());
+        }
+        raw.latest = Some(report);
+        self.changed.send_if_modified(|address| {
+            let modified = *address != Some(frame.address);
+            *address = Some(frame.address);
+            modified
+        });
+        Ok(())
+    }
+
+    /// The address of this endpoint observed by the peer most recently.
+    pub fn observed_address(&self) -> Option<SocketAddr> {
+        self.raw.lock().unwrap().latest.map(|(_, address)| address)
+    }
+
+    /// The address of this endpoint observed by the peer on the path.
+    pub fn path_observed_address(&self, pathway: &Pathway) -> Option<SocketAddr> {
+        let raw = self.raw.lock().unwrap();
+        raw.observed.get(pathway).map(|(_, address)| *address)
+    }
+
+    /// Watch the changes of [`ArcAddressDiscovery::observed_address`].
+    pub fn subscribe(&self) -> watch::Receiver<Option<SocketAddr>> {
+        self.changed.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pathway(local: &str, remote: &str) -> Pathway {
+        Pathway::direct(local.parse().unwrap(), remote.parse().unwrap())
+    }
+
+    fn relay(
+        server: &ArcAddressDiscovery,
+        client: &ArcAddressDiscovery,
+        server_pathway: Pathway,
+        client_pathway: Pathway,
+        now: Instant,
+    ) -> Option<ObservedAddressFrame> {
+        let frame = server.on_path_rcvd(server_pathway, now)?;
+        client.recv_frame(&frame, client_pathway).unwrap();
+        Some(frame)
+    }
+
+    #[test]
+    fn test_learn_rewritten_address() {
+        let server = ArcAddressDiscovery::new(Some(AddressDiscovery::Provide));
+        let client = ArcAddressDiscovery::new(Some(AddressDiscovery::Receive));
+        server.on_remote_params(Some(AddressDiscovery::Receive));
+        client.on_remote_params(Some(AddressDiscovery::Provide));
+        let mut watcher = client.subscribe();
+
+        // 客户端的私有地址10.0.0.2:5000被NAT改写成了203.0.113.7:40000
+        let client_pathway = pathway("10.0.0.2:5000", "198.51.100.1:443");
+        let server_pathway = pathway("198.51.100.1:443", "203.0.113.7:40000");
+        let now = Instant::now();
+        relay(&server, &client, server_pathway, client_pathway, now).unwrap();
+        let public: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        assert_eq!(client.observed_address(), Some(public));
+        assert_eq!(client.path_observed_address(&client_pathway), Some(public));
+        assert!(watcher.has_changed().unwrap());
+        assert_eq!(*watcher.borrow_and_update(), Some(public));
+
+        // 同一路径不再重复报告
+        let later = now + REPORT_INTERVAL;
+        assert!(server.on_path_rcvd(server_pathway, later).is_none());
+
+        // NAT重新绑定，服务端看到了新的路径，报告新的地址
+        let rebound = pathway("198.51.100.1:443", "203.0.113.7:40001");
+        let frame = relay(&server, &client, rebound, client_pathway, later).unwrap();
+        assert_eq!(frame.sequence_number, VarInt::from_u32(1));
+        let public: SocketAddr = "203.0.113.7:40001".parse().unwrap();
+        assert_eq!(client.observed_address(), Some(public));
+        assert!(watcher.has_changed().unwrap());
+        assert_eq!(*watcher.borrow_and_update(), Some(public));
+
+        // 乱序到达的旧报告被忽略
+        let stale = ObservedAddressFrame {
+            sequence_number: VarInt::from_u32(0),
+            address: "203.0.113.7:40000".parse().unwrap(),
+        };
+        client.recv_frame(&stale, client_pathway).unwrap();
+        assert_eq!(client.observed_address(), Some(public));
+        assert!(!watcher.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let server = ArcAddressDiscovery::new(Some(AddressDiscovery::Both));
+        server.on_remote_params(Some(AddressDiscovery::Both));
+        let first = pathway("198.51.100.1:443", "203.0.113.7:40000");
+        let second = pathway("198.51.100.1:443", "203.0.113.7:40001");
+        let now = Instant::now();
+        assert!(server.on_path_rcvd(first, now).is_some());
+        assert!(server.on_path_rcvd(second, now).is_none());
+        let frame = server.on_path_rcvd(second, now + REPORT_INTERVAL).unwrap();
+        assert_eq!(frame.address, second.remote_addr());
+    }
+
+    #[test]
+    fn test_not_negotiated() {
+        let server_pathway = pathway("198.51.100.1:443", "203.0.113.7:40000");
+        // 对端不愿接收，不报告
+        let server = ArcAddressDiscovery::new(Some(AddressDiscovery::Both));
+        server.on_remote_params(Some(AddressDiscovery::Provide));
+        assert!(server
+            .on_path_rcvd(server_pathway, Instant::now())
+            .is_none());
+        server.on_remote_params(None);
+        assert!(server
+            .on_path_rcvd(server_pathway, Instant::now())
+            .is_none());
+
+        // 本地未要求报告，收到即是协议错误
+        let client = ArcAddressDiscovery::new(Some(AddressDiscovery::Provide));
+        let frame = ObservedAddressFrame {
+            sequence_number: VarInt::from_u32(0),
+            address: server_pathway.remote_addr(),
+        };
+        let error = client.recv_frame(&frame, server_pathway).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert_eq!(client.observed_address(), None);
+    }
+}
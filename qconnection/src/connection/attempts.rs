@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::ConnectError, path::Pathway};
+
+/// The initial attempts of a client connection, one per address of the server, which
+/// run in parallel, see [`ArcConnection::add_initial_path`].
+///
+/// An attempt is given up once its probe timeouts are exhausted. If the peer has ever
+/// responded on it, the handshake timed out; otherwise only this address is unreachable,
+/// the connection fails as [`ConnectError::Unreachable`] once all the attempts are.
+///
+/// [`ArcConnection::add_initial_path`]: crate::connection::ArcConnection::add_initial_path
+#[derive(Debug, Default, Clone)]
+pub struct ConnectAttempts(Arc<Mutex<HashMap<Pathway, bool>>>);
+
+impl ConnectAttempts {
+    /// Begin an attempt via the pathway.
+    pub fn begin(&self, pathway: Pathway) {
+        self.0.lock().unwrap().entry(pathway).or_insert(false);
+    }
+
+    /// The probe timeouts of the attempt via the pathway are exhausted, `has_rcvd` tells
+    /// whether anything was ever received from the peer on it.
+    ///
+    /// Return the error to fail the connection with, None if other attempts are still going.
+    pub fn on_exhausted(&self, pathway: Pathway, has_rcvd: bool) -> Option<ConnectError> {
+        if has_rcvd {
+            return Some(ConnectError::HandshakeTimeout);
+        }
+        let mut attempts = self.0.lock().unwrap();
+        attempts.insert(pathway, true);
+        attempts
+            .values()
+            .all(|&is_unreachable| is_unreachable)
+            .then_some(ConnectError::Unreachable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pathway(port: u16) -> Pathway {
+        Pathway::direct(
+            "127.0.0.1:0".parse().unwrap(),
+            ([127, 0, 0, 1], port).into(),
+        )
+    }
+
+    #[test]
+    fn test_unreachable_after_all_attempts() {
+        let attempts = ConnectAttempts::default();
+        attempts.begin(pathway(1));
+        attempts.begin(pathway(2));
+
+        // 一个地址不可达，不影响另一个地址的尝试
+        assert_eq!(attempts.on_exhausted(pathway(1), false), None);
+        assert_eq!(
+            attempts.on_exhausted(pathway(2), false),
+            Some(ConnectError::Unreachable)
+        );
+    }
+
+    #[test]
+    fn test_handshake_timeout_after_response() {
+        let attempts = ConnectAttempts::default();
+        attempts.begin(pathway(1));
+        attempts.begin(pathway(2));
+
+        // 对端回应过，说明地址可达，是握手超时
+        assert_eq!(
+            attempts.on_exhausted(pathway(1), true),
+            Some(ConnectError::HandshakeTimeout)
+        );
+    }
+}
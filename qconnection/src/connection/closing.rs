@@ -1,11 +1,9 @@
 use std::{
+    collections::HashMap,
     future::Future,
     ops::DerefMut,
     pin::Pin,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc, Mutex,
-    },
+    sync::{Arc, Mutex},
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
@@ -21,7 +19,10 @@ use super::{
     scope::{data::ClosingOneRttScope, handshake::ClosingHandshakeScope, RecvPacket},
     CidRegistry,
 };
-use crate::path::{pathway::Pathway, ArcPathes};
+use crate::{
+    drops::{ArcDropCounters, DropReason},
+    path::{pathway::Pathway, ArcPathes, ViaPathway},
+};
 
 #[derive(Clone)]
 pub struct ClosingConnection {
@@ -31,9 +32,10 @@ pub struct ClosingConnection {
     pub one_rtt: Option<ClosingOneRttScope>,
     pub final_ccf: ConnectionCloseFrame,
 
-    pub rcvd_packets: Arc<AtomicUsize>,
-    pub last_send_ccf: Arc<Mutex<Instant>>,
+    pub close_responder: CloseResponder,
     pub revd_ccf: RcvdCcf,
+    pub post_close_rcvd: PostCloseRcvd,
+    pub drops: ArcDropCounters,
 }
 
 impl ClosingConnection {
@@ -43,6 +45,7 @@ impl ClosingConnection {
         cid_registry: CidRegistry,
         hs: Option<ClosingHandshakeScope>,
         one_rtt: Option<ClosingOneRttScope>,
+        drops: ArcDropCounters,
     ) -> Self {
         Self {
             pathes,
@@ -50,27 +53,37 @@ impl ClosingConnection {
             hs,
             one_rtt,
             final_ccf: ConnectionCloseFrame::from(error),
-            rcvd_packets: Arc::new(AtomicUsize::new(0)),
-            last_send_ccf: Arc::new(Mutex::new(Instant::now())),
+            close_responder: CloseResponder::default(),
             revd_ccf: RcvdCcf::default(),
+            post_close_rcvd: PostCloseRcvd::new(Instant::now()),
+            drops,
         }
     }
 
-    // 记录收到的包数量，和收包时间，判断是否需要重发CCF；
-    pub fn recv_packet_via_pathway(&mut self, packet: DataPacket, _pathway: Pathway, _usc: ArcUsc) {
-        self.rcvd_packets.fetch_add(1, Ordering::Release);
-        // TODO: 数值从配置中读取, 还是直接固定值?
-        let mut last_send_ccf = self.last_send_ccf.lock().unwrap();
-        if self.rcvd_packets.load(Ordering::Relaxed) > 5
-            || last_send_ccf.elapsed() > Duration::from_millis(100)
-        {
-            self.rcvd_packets.store(0, Ordering::Release);
-            *last_send_ccf = Instant::now();
-            // TODO: 调用 usc 直接发送 报文
-            // usc.poll_send_via_pathway(iovecs, pathway, cx);
+    // 记录收到的包，按退避规律回应缓存好的CCF包
+    pub fn recv_packet_via_pathway(
+        &mut self,
+        packet: DataPacket,
+        pathway: Pathway,
+        mut usc: ArcUsc,
+    ) {
+        self.post_close_rcvd.on_rcvd(Instant::now());
+        // 关闭状态下的包只用于回应CCF、探测对方的CCF，不再处理其内容
+        self.drops
+            .on_dropped(DropReason::Closing, pathway.remote_addr(), &packet.bytes);
+        if self.close_responder.should_respond() {
+            let close_packet = self
+                .close_responder
+                .close_packet(pathway, || self.assemble_close_packet(pathway));
+            if let Some(close_packet) = close_packet {
+                _ = usc.sync_send_via_path_way(close_packet, pathway);
+            }
         }
-        drop(last_send_ccf);
 
+        // 已经收到了对方的CONNECTION_CLOSE，不必再解密解析后续的包
+        if self.revd_ccf.is_rcvd() {
+            return;
+        }
         match packet.header {
             DataHeader::Short(_) => self.parse_1rtt_packet(packet),
             DataHeader::Long(long::DataHeader::Handshake(_)) => self.parse_hs_packet(packet),
@@ -78,6 +91,39 @@ impl ClosingConnection {
         };
     }
 
+    /// Send the CONNECTION_CLOSE frame on every path once, right after entering the closing
+    /// state, later it's only sent in response to the incoming packets.
+    pub fn send_ccf(&self) {
+        let pathes = self
+            .pathes
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect::<Vec<_>>();
+        for (pathway, path) in pathes {
+            let close_packet = self
+                .close_responder
+                .close_packet(pathway, || self.assemble_close_packet(pathway));
+            if let Some(close_packet) = close_packet {
+                _ = path.send_datagram(close_packet, pathway);
+            }
+        }
+    }
+
+    // 优先使用1-RTT密钥，对方可能已经丢弃了Handshake密钥
+    fn assemble_close_packet(&self, pathway: Pathway) -> Option<Vec<u8>> {
+        let (scid, dcid) = self.pathes.get(&pathway)?.cids();
+        let dcid = dcid?;
+        match (&self.one_rtt, &self.hs) {
+            (Some(one_rtt), _) => Some(one_rtt.close_packet(&self.final_ccf, dcid)),
+            // 对方可能尚未验证本端的身份，Handshake包里不透露应用层的错误
+            (None, Some(hs)) => {
+                let ccf = self.final_ccf.conceal_app_layer();
+                Some(hs.close_packet(&ccf, scid, dcid))
+            }
+            (None, None) => None,
+        }
+    }
+
     fn parse_hs_packet(&self, packet: DataPacket) {
         if let Some(hs_scope) = &self.hs {
             if hs_scope.has_rcvd_ccf(packet) {
@@ -99,6 +145,115 @@ impl ClosingConnection {
     }
 }
 
+#[derive(Debug, Default)]
+struct RawCloseResponder {
+    rcvd: u64,
+    responded: u64,
+    suppressed: u64,
+    // 各路径的CCF包只组装加密一次，之后原样重发
+    packets: HashMap<Pathway, Vec<u8>>,
+}
+
+/// Limit the responses of the CONNECTION_CLOSE frame in the closing state.
+///
+/// Responding to every incoming packet lets the peer, or an attacker replaying the
+/// captured packets, make us send at their rate. See [RFC 9000 section 10.2.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.1),
+/// only the 1st, 2nd, 4th, 8th... incoming packets are responded, the others are
+/// suppressed and counted.
+#[derive(Debug, Default, Clone)]
+pub struct CloseResponder(Arc<Mutex<RawCloseResponder>>);
+
+impl CloseResponder {
+    /// Record an incoming packet, return true if it should be responded.
+    pub fn should_respond(&self) -> bool {
+        let mut guard = self.0.lock().unwrap();
+        guard.rcvd += 1;
+        if guard.rcvd.is_power_of_two() {
+            guard.responded += 1;
+            true
+        } else {
+            guard.suppressed += 1;
+            false
+        }
+    }
+
+    /// The cached close packet of the pathway, assembled by `assemble` at the first time.
+    pub fn close_packet(
+        &self,
+        pathway: Pathway,
+        assemble: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        let mut guard = self.0.lock().unwrap();
+        if let Some(packet) = guard.packets.get(&pathway) {
+            return Some(packet.clone());
+        }
+        let packet = assemble()?;
+        guard.packets.insert(pathway, packet.clone());
+        Some(packet)
+    }
+
+    /// The number of close packets sent in response to the incoming packets.
+    pub fn responded(&self) -> u64 {
+        self.0.lock().unwrap().responded
+    }
+
+    /// The number of incoming packets not responded, because of the backoff.
+    pub fn suppressed(&self) -> u64 {
+        self.0.lock().unwrap().suppressed
+    }
+}
+
+#[derive(Debug)]
+struct RawPostCloseRcvd {
+    closed_at: Instant,
+    count: usize,
+    last_rcvd: Option<Instant>,
+}
+
+/// The packets received from the peer after entering the closing state.
+///
+/// CONNECTION_CLOSE frame is never acknowledged, but whether the peer received it can
+/// be inferred: once received, the peer enters the draining state and stops sending,
+/// so no more packets would arrive after about one RTT. Instead, if the peer keeps
+/// sending to us until the end of the closing period, it likely never arrived.
+#[derive(Debug, Clone)]
+pub struct PostCloseRcvd(Arc<Mutex<RawPostCloseRcvd>>);
+
+impl PostCloseRcvd {
+    pub fn new(closed_at: Instant) -> Self {
+        Self(Arc::new(Mutex::new(RawPostCloseRcvd {
+            closed_at,
+            count: 0,
+            last_rcvd: None,
+        })))
+    }
+
+    pub fn on_rcvd(&self, now: Instant) {
+        let mut guard = self.0.lock().unwrap();
+        guard.count += 1;
+        guard.last_rcvd = Some(now);
+    }
+
+    /// The number of packets received after entering the closing state.
+    pub fn count(&self) -> usize {
+        self.0.lock().unwrap().count
+    }
+
+    pub fn last_rcvd(&self) -> Option<Instant> {
+        self.0.lock().unwrap().last_rcvd
+    }
+
+    /// Whether the peer likely received our CONNECTION_CLOSE frame, that is, the peer
+    /// stopped sending within the window, which should be about one RTT.
+    pub fn observed_by_peer(&self, window: Duration) -> bool {
+        let guard = self.0.lock().unwrap();
+        match guard.last_rcvd {
+            Some(last_rcvd) => last_rcvd.saturating_duration_since(guard.closed_at) <= window,
+            None => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 enum RcvdCcfState {
     #[default]
@@ -115,6 +270,10 @@ impl RcvdCcf {
         self.clone()
     }
 
+    pub fn is_rcvd(&self) -> bool {
+        matches!(*self.0.lock().unwrap(), RcvdCcfState::Rcvd)
+    }
+
     pub fn on_ccf_rcvd(&self) {
         let mut guard = self.0.lock().unwrap();
         if let RcvdCcfState::Pending(waker) = guard.deref_mut() {
@@ -138,3 +297,76 @@ impl Future for RcvdCcf {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_responder_backoff() {
+        let responder = CloseResponder::default();
+        let responded = (1..=1000)
+            .filter(|_| responder.should_respond())
+            .collect::<Vec<u64>>();
+        assert_eq!(responded, [1, 2, 4, 8, 16, 32, 64, 128, 256, 512]);
+        assert_eq!(responder.responded(), 10);
+        assert_eq!(responder.suppressed(), 990);
+    }
+
+    #[test]
+    fn test_close_packet_cached() {
+        let responder = CloseResponder::default();
+        let pathway = Pathway::Direct {
+            local: "127.0.0.1:1".parse().unwrap(),
+            remote: "127.0.0.1:2".parse().unwrap(),
+        };
+        let mut assembled = 0;
+        for _ in 0..3 {
+            let packet = responder.close_packet(pathway, || {
+                assembled += 1;
+                Some(vec![1, 2, 3])
+            });
+            assert_eq!(packet, Some(vec![1, 2, 3]));
+        }
+        assert_eq!(assembled, 1);
+
+        // 组装失败的不缓存，下次仍会尝试
+        let other = Pathway::Direct {
+            local: "127.0.0.1:1".parse().unwrap(),
+            remote: "127.0.0.1:3".parse().unwrap(),
+        };
+        assert_eq!(responder.close_packet(other, || None), None);
+        assert_eq!(
+            responder.close_packet(other, || Some(vec![4])),
+            Some(vec![4])
+        );
+    }
+
+    #[test]
+    fn test_peer_stopped_sending() {
+        let closed_at = Instant::now();
+        let rcvd = PostCloseRcvd::new(closed_at);
+        assert!(rcvd.observed_by_peer(Duration::from_millis(100)));
+
+        // 对方在收到CONNECTION_CLOSE之前发出的包，一个RTT内陆续到达
+        rcvd.on_rcvd(closed_at + Duration::from_millis(10));
+        rcvd.on_rcvd(closed_at + Duration::from_millis(60));
+        assert_eq!(rcvd.count(), 2);
+        assert!(rcvd.observed_by_peer(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_peer_kept_sending() {
+        let closed_at = Instant::now();
+        let rcvd = PostCloseRcvd::new(closed_at);
+        for i in 1..=30 {
+            rcvd.on_rcvd(closed_at + Duration::from_millis(10 * i));
+        }
+        assert_eq!(rcvd.count(), 30);
+        assert_eq!(
+            rcvd.last_rcvd(),
+            Some(closed_at + Duration::from_millis(300))
+        );
+        assert!(!rcvd.observed_by_peer(Duration::from_millis(100)));
+    }
+}
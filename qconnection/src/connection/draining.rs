@@ -10,7 +10,7 @@ pub struct DrainingConnection(ArcLocalCids);
 
 impl From<RawConnection> for DrainingConnection {
     fn from(value: RawConnection) -> Self {
-        Self(value.cid_registry.local)
+        Self::new(value.cid_registry.local)
     }
 }
 
@@ -21,6 +21,10 @@ impl From<ClosingConnection> for DrainingConnection {
 }
 
 impl DrainingConnection {
+    pub(super) fn new(local_cids: ArcLocalCids) -> Self {
+        Self(local_cids)
+    }
+
     /// Just ignore the packet, with a warning log
     pub fn recv_packet(&self, packet: DataPacket) {
         println!(
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+/// The coarse lifecycle state of a connection, see [`ConnectionHealth`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The handshake is not confirmed yet.
+    Handshaking,
+    /// The handshake is confirmed, the connection is usable.
+    Established,
+    /// The connection is closed by this endpoint, waiting for the peer to learn of it.
+    Closing,
+    /// The connection is closed by the peer, draining the packets in flight.
+    Draining,
+    /// The connection is terminated.
+    Closed,
+}
+
+/// A cheap snapshot of the liveness of a connection, see [`ArcConnection::health`].
+///
+/// It's meant to be polled by the health probes of load balancers: it only reads what is
+/// recorded by the connection anyway, and never sends anything. To make sure the peer is
+/// still reachable, see [`ArcConnection::verify_liveness`].
+///
+/// [`ArcConnection::health`]: crate::connection::ArcConnection::health
+/// [`ArcConnection::verify_liveness`]: crate::connection::ArcConnection::verify_liveness
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionHealth {
+    pub state: ConnectionState,
+    /// How long since a packet was received from the peer, None if nothing is received
+    /// yet, or the connection is closing or closed.
+    pub last_rx_elapsed: Option<Duration>,
+    /// How long since a datagram was sent to the peer, None if nothing is sent yet, or the
+    /// connection is closing or closed.
+    pub last_tx_elapsed: Option<Duration>,
+    /// The most probe timeouts without receiving an acknowledgment among the paths. It
+    /// keeps climbing while the peer is unreachable, and is reset by an acknowledgment.
+    pub consecutive_pto_count: u32,
+    /// The number of the paths whose peer address is validated.
+    pub paths_validated: usize,
+}
+
+impl ConnectionHealth {
+    pub(crate) fn closed(state: ConnectionState) -> Self {
+        Self {
+            state,
+            last_rx_elapsed: None,
+            last_tx_elapsed: None,
+            consecutive_pto_count: 0,
+            paths_validated: 0,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The ack-eliciting traffic sent by this endpoint on its own initiative rather than on
+/// behalf of the application, in descending order of priority, see [`ArcOverheadBudget`].
+///
+/// Loss recovery is not overhead, the probes sent on probe timeouts never draw from the
+/// budget, nor do the application data and the acknowledgments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverheadKind {
+    /// The PATH_CHALLENGE frames validating a path, including the retries.
+    PathValidation,
+    /// The PING frames keeping the connection alive.
+    KeepAlive,
+    /// The packets probing a larger path MTU.
+    Pmtud,
+    /// The padding warming up the congestion window.
+    Prewarm,
+}
+
+impl OverheadKind {
+    pub const ALL: [Self; 4] = [
+        Self::PathValidation,
+        Self::KeepAlive,
+        Self::Pmtud,
+        Self::Prewarm,
+    ];
+
+    // 0最优先
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+/// How much traffic of an [`OverheadKind`] is sent, and suppressed for the budget.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OverheadCounters {
+    pub sent: u64,
+    pub sent_bytes: u64,
+    pub suppressed: u64,
+    pub suppressed_bytes: u64,
+}
+
+/// The [`OverheadCounters`] of each [`OverheadKind`] of a connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OverheadStats([OverheadCounters; OverheadKind::ALL.len()]);
+
+impl OverheadStats {
+    pub fn of(&self, kind: OverheadKind) -> OverheadCounters {
+        self.0[kind.rank()]
+    }
+}
+
+#[derive(Debug)]
+struct RawOverheadBudget {
+    // 每分钟允许的字节数，也是令牌桶的容量；None表示不限
+    bytes_per_minute: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+    stats: OverheadStats,
+}
+
+impl RawOverheadBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            bytes_per_minute: None,
+            tokens: 0.0,
+            last_refill: now,
+            stats: OverheadStats::default(),
+        }
+    }
+
+    fn set_limit(&mut self, bytes_per_minute: Option<u64>, now: Instant) {
+        self.refill(now);
+        self.tokens = match (self.bytes_per_minute, bytes_per_minute) {
+            // 原本不限，从满桶开始
+            (None, Some(capacity)) => capacity as f64,
+            (Some(_), Some(capacity)) => self.tokens.min(capacity as f64),
+            (_, None) => 0.0,
+        };
+        self.bytes_per_minute = bytes_per_minute;
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = self.last_refill.max(now);
+        if let Some(capacity) = self.bytes_per_minute {
+            let refilled = elapsed.as_secs_f64() / Duration::from_secs(60).as_secs_f64();
+            self.tokens = (self.tokens + refilled * capacity as f64).min(capacity as f64);
+        }
+    }
+
+    fn try_draw(&mut self, kind: OverheadKind, bytes: usize, now: Instant) -> bool {
+        self.refill(now);
+        let allowed = match self.bytes_per_minute {
+            Some(capacity) => {
+                // 优先级低的要给优先级高的留出余量，预算紧张时，按优先级从低到高依次被压制
+                let reserve = capacity as f64 * kind.rank() as f64 / OverheadKind::ALL.len() as f64;
+                self.tokens - bytes as f64 >= reserve
+            }
+            None => true,
+        };
+        let counters = &mut self.stats.0[kind.rank()];
+        if allowed {
+            self.tokens = (self.tokens - bytes as f64).max(0.0);
+            counters.sent += 1;
+            counters.sent_bytes += bytes as u64;
+        } else {
+            counters.suppressed += 1;
+            counters.suppressed_bytes += bytes as u64;
+        }
+        allowed
+    }
+}
+
+/// The budget of the overhead traffic of a connection, a token bucket refilled at a
+/// configured number of bytes per minute, holding up to one minute's worth. Unlimited by
+/// default.
+///
+/// Each [`OverheadKind`] draws from it before sending. When the budget is tight, the kinds
+/// are suppressed in ascending order of priority: a kind only draws while the tokens left
+/// cover the reserve of the kinds above it, a quarter of the capacity per kind.
+///
+/// The suppressed traffic is skipped rather than delayed, the feature sending it decides
+/// how to degrade, e.g. an unsent PATH_CHALLENGE counts as an unanswered one.
+#[derive(Debug, Clone)]
+pub struct ArcOverheadBudget(Arc<Mutex<RawOverheadBudget>>);
+
+impl Default for ArcOverheadBudget {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(RawOverheadBudget::new(Instant::now()))))
+    }
+}
+
+impl ArcOverheadBudget {
+    /// Set the bytes per minute of the overhead traffic, None is unlimited.
+    pub fn set_limit(&self, bytes_per_minute: Option<u64>) {
+        self.0
+            .lock()
+            .unwrap()
+            .set_limit(bytes_per_minute, Instant::now());
+    }
+
+    /// Return whether the traffic of `kind` can be sent, and count it as sent or suppressed.
+    pub fn try_draw(&self, kind: OverheadKind, bytes: usize) -> bool {
+        self.0.lock().unwrap().try_draw(kind, bytes, Instant::now())
+    }
+
+    pub fn stats(&self) -> OverheadStats {
+        self.0.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_when_tight() {
+        let now = Instant::now();
+        let mut budget = RawOverheadBudget::new(now);
+        budget.set_limit(Some(4000), now);
+
+        // 满桶时各类都能发，余量渐少，优先级低的先被压制
+        for kind in OverheadKind::ALL {
+            assert!(budget.try_draw(kind, 250, now));
+        }
+        // 余3000，低于预热的余量
+        assert!(!budget.try_draw(OverheadKind::Prewarm, 100, now));
+        assert!(budget.try_draw(OverheadKind::Pmtud, 1000, now));
+        // 余2000，低于PMTUD的余量
+        assert!(!budget.try_draw(OverheadKind::Pmtud, 100, now));
+        assert!(budget.try_draw(OverheadKind::KeepAlive, 1000, now));
+        assert!(!budget.try_draw(OverheadKind::KeepAlive, 100, now));
+        // 路径验证可以用尽整个预算
+        assert!(budget.try_draw(OverheadKind::PathValidation, 1000, now));
+        assert!(!budget.try_draw(OverheadKind::PathValidation, 1, now));
+
+        let stats = budget.stats;
+        assert_eq!(
+            stats.of(OverheadKind::PathValidation),
+            OverheadCounters {
+                sent: 2,
+                sent_bytes: 1250,
+                suppressed: 1,
+                suppressed_bytes: 1,
+            }
+        );
+        assert_eq!(stats.of(OverheadKind::Prewarm).sent_bytes, 250);
+        assert_eq!(stats.of(OverheadKind::Prewarm).suppressed_bytes, 100);
+
+        // 半分钟后补充了一半
+        let later = now + Duration::from_secs(30);
+        assert!(budget.try_draw(OverheadKind::KeepAlive, 1000, later));
+        assert!(!budget.try_draw(OverheadKind::Pmtud, 1, later));
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let now = Instant::now();
+        let mut budget = RawOverheadBudget::new(now);
+        for kind in OverheadKind::ALL {
+            assert!(budget.try_draw(kind, 1 << 40, now));
+        }
+        assert_eq!(budget.stats.of(OverheadKind::Prewarm).suppressed, 0);
+
+        // 限额之后又解除，不再压制
+        budget.set_limit(Some(0), now);
+        assert!(!budget.try_draw(OverheadKind::PathValidation, 1, now));
+        budget.set_limit(None, now);
+        assert!(budget.try_draw(OverheadKind::Prewarm, 1, now));
+    }
+}
@@ -0,0 +1,104 @@
+use qcongestion::congestion::AckEagerness;
+
+/// How saturated the application is, the CPU for example, which only the application can
+/// tell, see [`ArcConnection::set_pressure_level`].
+///
+/// Under pressure, the connection spends less on the new work and keeps the transfers in
+/// progress going. It's reversible, nothing changes for good when the level drops again.
+///
+/// [`ArcConnection::set_pressure_level`]: crate::connection::ArcConnection::set_pressure_level
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PressureLevel {
+    #[default]
+    Normal,
+    /// The new MAX_STREAMS credit is withheld, and the streams queued to be accepted and
+    /// the new streams created by peer are shed, which requires an
+    /// [`IncomingStreamPolicy`]. The acknowledgments are delayed up to the local
+    /// max_ack_delay, see [`AckEagerness::Lazy`], and the queue of the received datagrams
+    /// is bounded.
+    ///
+    /// [`IncomingStreamPolicy`]: qrecovery::streams::policy::IncomingStreamPolicy
+    Elevated,
+    /// Beyond [`PressureLevel::Elevated`], the queue of the received datagrams is bounded
+    /// tighter, and the connection-level window of the MAX_DATA frames shrinks to a quarter,
+    /// so that the peer sends slower.
+    Critical,
+}
+
+impl PressureLevel {
+    /// Whether the new streams are shed, and the new MAX_STREAMS credit is withheld.
+    pub fn sheds_streams(self) -> bool {
+        self >= Self::Elevated
+    }
+
+    /// The eagerness to acknowledge in effect, given the configured one.
+    pub fn ack_eagerness(self, configured: AckEagerness) -> AckEagerness {
+        match self {
+            Self::Normal => configured,
+            _ => AckEagerness::Lazy,
+        }
+    }
+
+    /// The most datagrams queued to be read, unbounded if None.
+    pub fn datagram_queue_limit(self) -> Option<usize> {
+        match self {
+            Self::Normal => None,
+            Self::Elevated => Some(64),
+            Self::Critical => Some(16),
+        }
+    }
+
+    /// The connection-level window of the MAX_DATA frames, given the `initial_max_data`.
+    pub fn recv_window(self, initial_max_data: u64) -> u64 {
+        match self {
+            Self::Critical => initial_max_data / 4,
+            _ => initial_max_data,
+        }
+    }
+}
+
+/// What the current [`PressureLevel`] is doing to a connection, see
+/// [`ConnectionStats::pressure`].
+///
+/// [`ConnectionStats::pressure`]: crate::connection::ConnectionStats::pressure
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PressureStats {
+    pub level: PressureLevel,
+    /// Whether the new streams are shed and the new MAX_STREAMS credit is withheld.
+    pub shedding_streams: bool,
+    /// The eagerness to acknowledge in effect.
+    pub ack_eagerness: AckEagerness,
+    /// The most datagrams queued to be read, unbounded if None.
+    pub datagram_queue_limit: Option<usize>,
+    /// The received datagrams dropped from the full queue in total.
+    pub datagrams_dropped: u64,
+    /// The connection-level window of the MAX_DATA frames in use.
+    pub recv_window: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_levels() {
+        assert!(!PressureLevel::Normal.sheds_streams());
+        assert!(PressureLevel::Elevated.sheds_streams());
+        assert!(PressureLevel::Critical.sheds_streams());
+
+        let immediate = AckEagerness::Immediate;
+        assert_eq!(PressureLevel::Normal.ack_eagerness(immediate), immediate);
+        assert_eq!(
+            PressureLevel::Elevated.ack_eagerness(immediate),
+            AckEagerness::Lazy
+        );
+
+        assert_eq!(PressureLevel::Normal.datagram_queue_limit(), None);
+        assert!(
+            PressureLevel::Critical.datagram_queue_limit()
+                < PressureLevel::Elevated.datagram_queue_limit()
+        );
+        assert_eq!(PressureLevel::Elevated.recv_window(65536), 65536);
+        assert_eq!(PressureLevel::Critical.recv_window(65536), 16384);
+    }
+}
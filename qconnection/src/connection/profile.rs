@@ -0,0 +1,118 @@
+use qbase::{
+    config::CommonParameters,
+    error::{Error, ErrorKind},
+    frame::{BeFrame, StreamCtlFrame},
+    varint::VarInt,
+};
+
+/// What a connection is for, chosen when it's created, see [`ArcConnection::new_client`] and
+/// [`ArcConnection::new_server`].
+///
+/// [`ArcConnection::new_client`]: crate::connection::ArcConnection::new_client
+/// [`ArcConnection::new_server`]: crate::connection::ArcConnection::new_server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionProfile {
+    /// Both the streams and the datagrams.
+    #[default]
+    Full,
+    /// Only the datagrams, as a secured connection-oriented datagram transport.
+    ///
+    /// Zero streams of either direction are advertised to the peer, and the state of the
+    /// streams is never allocated. The frames of the streams from the peer are a
+    /// STREAM_LIMIT_ERROR, and the streams can't be opened or accepted locally, see
+    /// [`StreamsDisabled`].
+    ///
+    /// [`StreamsDisabled`]: crate::error::StreamsDisabled
+    DatagramOnly,
+}
+
+impl ConnectionProfile {
+    pub fn has_streams(self) -> bool {
+        self == Self::Full
+    }
+
+    /// Advertise no streams to the peer if the profile has none.
+    pub fn apply(self, parameters: &mut CommonParameters) {
+        if !self.has_streams() {
+            parameters.set_initial_max_streams_bidi(VarInt::from_u32(0));
+            parameters.set_initial_max_streams_uni(VarInt::from_u32(0));
+        }
+    }
+}
+
+/// The error of a STREAM frame received by a connection without streams, as if its
+/// stream were beyond the zero streams advertised.
+pub(crate) fn stream_frame_refused(frame: &impl BeFrame, sid: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::StreamLimit,
+        frame.frame_type(),
+        format!("{sid} exceeds the stream limit, streams are disabled"),
+    )
+}
+
+/// Same as [`stream_frame_refused`] for the stream control frames. MAX_STREAMS and
+/// STREAMS_BLOCKED are legal even without streams, so they are ignored.
+pub(crate) fn stream_ctl_frame_refused(frame: &StreamCtlFrame) -> Option<Error> {
+    let sid = match frame {
+        StreamCtlFrame::ResetStream(f) => f.stream_id,
+        StreamCtlFrame::StopSending(f) => f.stream_id,
+        StreamCtlFrame::MaxStreamData(f) => f.stream_id,
+        StreamCtlFrame::StreamDataBlocked(f) => f.stream_id,
+        StreamCtlFrame::MaxStreams(_) | StreamCtlFrame::StreamsBlocked(_) => return None,
+    };
+    Some(stream_frame_refused(frame, sid))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::channel::mpsc;
+    use qbase::{
+        flow::FlowController,
+        frame::{MaxStreamsFrame, ResetStreamFrame, StreamFrame},
+        streamid::StreamId,
+    };
+    use qrecovery::reliable::ArcReliableFrameDeque;
+
+    use super::*;
+    use crate::{connection::scope::data::DataScope, error::ConnError};
+
+    #[test]
+    fn test_stream_ctl_frame_refused() {
+        let reset = StreamCtlFrame::ResetStream(ResetStreamFrame {
+            stream_id: StreamId::from(VarInt::from_u32(0)),
+            app_error_code: VarInt::from_u32(0),
+            final_size: VarInt::from_u32(0),
+        });
+        let error = stream_ctl_frame_refused(&reset).unwrap();
+        assert_eq!(error.kind(), ErrorKind::StreamLimit);
+        // 对方仍可以合法地发送MAX_STREAMS与STREAMS_BLOCKED
+        let max_streams = StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(VarInt::from_u32(8)));
+        assert!(stream_ctl_frame_refused(&max_streams).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_frame_without_streams() {
+        let conn_error = ConnError::default();
+        let (stream_frames_entry, rcvd_stream_frames) = mpsc::unbounded();
+        let (_stream_ctrl_frames_entry, rcvd_stream_ctrl_frames) = mpsc::unbounded();
+        DataScope::default().handle_stream_frame_with_flow_ctrl(
+            &ArcReliableFrameDeque::with_capacity(0),
+            None,
+            &FlowController::with_initial(0, 1024),
+            conn_error.clone(),
+            rcvd_stream_frames,
+            rcvd_stream_ctrl_frames,
+        );
+
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let frame = StreamFrame::new(sid, 0, 5);
+        let fty = frame.frame_type();
+        stream_frames_entry
+            .unbounded_send((frame, Bytes::from_static(b"hello")))
+            .unwrap();
+        let (error, _) = conn_error.did_error_occur().await;
+        assert_eq!(error.kind(), ErrorKind::StreamLimit);
+        assert_eq!(error.frame_type(), fty);
+    }
+}
@@ -1,35 +1,63 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use futures::{channel::mpsc, FutureExt};
 use qbase::{
     cid::ConnectionId,
-    config::Parameters,
+    config::{Parameters, ParametersChanged, RememberedField, RemoteParameters},
+    error::{Error, ErrorKind},
     flow::FlowController,
     handshake::Handshake,
-    packet::keys::ArcKeys,
+    packet::{keys::ArcKeys, InitialHeader},
     streamid::Role,
-    token::{ArcTokenRegistry, TokenRegistry},
-    util::AsyncCell,
+    token::{ArcTokenRegistry, StatelessResetKey, TokenAction, TokenOutcome, TokenRegistry},
+    util::{spawn_traced, ArcTraceContext, AsyncCell},
+    version::Versions,
+};
+use qcongestion::{
+    congestion::{AckEagerness, CongestionAlgorithm, LossDetectionConfig, MSS},
+    pacing::PacingConfig,
+};
+use qrecovery::{
+    reliable::{sentpkt::SentRecordsStats, ArcReliableFrameDeque},
+    space::Epoch,
+    streams::crypto::{CryptoLimits, CryptoRecvStats},
 };
-use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
 use qunreliable::DatagramFlow;
 use rustls::quic::Keys;
 use tokio::{sync::Notify, task::JoinHandle};
 
 use super::{
-    scope::{data::DataScope, handshake::HandshakeScope, initial::InitialScope},
+    ack_frequency::ArcAckFrequency,
+    activity::ArcActivity,
+    address_discovery::ArcAddressDiscovery,
+    attempts::ConnectAttempts,
+    overhead::ArcOverheadBudget,
+    pressure::PressureLevel,
+    profile::ConnectionProfile,
+    scope::{data::DataScope, handshake::HandshakeScope, initial::InitialScope, ArcFrameBudget},
+    stall::ArcStallDetector,
+    telemetry::{ConnectionDigest, NetworkTelemetry},
+    watchdog::ReceiveWatchdog,
     ArcLocalCids, ArcRemoteCids, CidRegistry, DataStreams, RcvdPackets,
 };
 use crate::{
+    drops::ArcDropCounters,
     error::ConnError,
     path::{pathway::Pathway, ArcPath, ArcPathes, RawPath},
-    router::ROUTER,
+    router::{PacketEntries, ResetTokenRegistry, RESET_TOKENS, ROUTER},
     tls::ArcTlsSession,
 };
 
 pub struct RawConnection {
     pub token: Arc<Mutex<Vec<u8>>>,
     pub pathes: ArcPathes,
+    // 路径中的IPv4映射地址是否规范化，创建连接时即已决定，此后不变，见PacketEntries
+    pub canonical_mapped_addresses: bool,
     pub cid_registry: CidRegistry,
     // handshake done的信号
     pub handshake: Handshake<ArcReliableFrameDeque>,
@@ -37,7 +65,8 @@ pub struct RawConnection {
     pub error: ConnError,
 
     pub reliable_frames: ArcReliableFrameDeque,
-    pub streams: DataStreams,
+    // 仅数据报的连接没有流
+    pub streams: Option<DataStreams>,
     pub datagrams: DatagramFlow,
 
     pub initial: InitialScope,
@@ -47,8 +76,52 @@ pub struct RawConnection {
     pub join_handles: [JoinHandle<RcvdPackets>; 4],
 
     pub local_params: Arc<Parameters>,
-    pub remote_params: Arc<AsyncCell<Arc<Parameters>>>,
+    pub remote_params: Arc<AsyncCell<Arc<RemoteParameters>>>,
+    // 客户端恢复会话时，上次连接记住的服务端传输参数
+    pub remembered_params: Arc<Mutex<Option<Parameters>>>,
+    pub params_changed: Arc<AsyncCell<ParametersChanged>>,
     pub tls_session: ArcTlsSession,
+    pub trace: ArcTraceContext,
+    // 新建的路径也要沿用应用设置的确认策略
+    pub ack_eagerness: Arc<Mutex<AckEagerness>>,
+    // 新建的路径也要沿用应用设置的发包节奏
+    pub pacing: Arc<Mutex<PacingConfig>>,
+    // 新建的路径也要沿用应用设置的丢包判定阈值
+    pub loss_detection: Arc<Mutex<LossDetectionConfig>>,
+    // 新建的路径也要沿用对方通过ACK_FREQUENCY帧要求的确认频率
+    pub ack_frequency: ArcAckFrequency,
+    // 对端的ack_delay_exponent和max_ack_delay，新建的路径也要据此解码ACK帧中的ack_delay
+    pub peer_ack_delay: Arc<Mutex<Option<(u8, Duration)>>>,
+    // 向对端报告其地址，以及对端报告的本端地址
+    pub address_discovery: ArcAddressDiscovery,
+    // 新建的路径也要沿用应用切换的拥塞控制算法
+    pub congestion: Arc<Mutex<CongestionAlgorithm>>,
+    // 客户端握手期间，连续探测超时多少次后放弃一条路径
+    pub max_initial_pto_count: Arc<Mutex<Option<u32>>>,
+    pub attempts: ConnectAttempts,
+    // 私有部署中，路径容不下1200字节的数据报时，Initial包可以填充到的更小长度
+    pub undersized_initial: Arc<Mutex<Option<usize>>>,
+    pub receive_watchdog: ReceiveWatchdog,
+    pub frame_budget: ArcFrameBudget,
+    pub drops: ArcDropCounters,
+    // 最近收包的情况，供服务端挑选要驱逐的空闲连接
+    pub activity: ArcActivity,
+    // 非应用发起的ack-eliciting流量的预算，各条路径共用
+    pub overhead: ArcOverheadBudget,
+    // 客户端首个Initial包的目的连接ID，作为连接稳定的标识
+    pub odcid: ConnectionId,
+    // 客户端接受的Retry包的源连接ID，一个连接至多接受一个Retry包
+    pub retry_scid: Arc<Mutex<Option<ConnectionId>>>,
+    // 应用告知的负载压力，新建的路径也要按其调整确认策略
+    pub pressure: Arc<Mutex<PressureLevel>>,
+    // 定期检查流控是否疑似相互死锁
+    pub stall_detector: ArcStallDetector,
+    // 关闭时向所在端点汇报连接的摘要，缺省不汇报
+    pub network_telemetry: Option<NetworkTelemetry>,
+    // 对方发放的连接ID的无状态重置令牌，登记到全局以识别无状态重置
+    pub reset_tokens: ResetTokenRegistry,
+    // 连接所用的版本，客户端还记着是否经过了版本协商，以便防止版本降级
+    pub versions: Arc<Mutex<Versions>>,
 }
 
 impl RawConnection {
@@ -56,15 +129,23 @@ impl RawConnection {
         ConnectionId::random_gen_with_mark(8, 0x80, 0x7F)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         role: Role,
+        profile: ConnectionProfile,
         local_params: Parameters,
         tls_session: ArcTlsSession,
         initial_scid: ConnectionId,
         initial_dcid: ConnectionId,
         initial_keys: Keys,
         token_registry: ArcTokenRegistry,
+        reset_key: StatelessResetKey,
+        canonical_mapped_addresses: bool,
     ) -> Self {
+        // 连接内派生的所有异步任务，都继承该追踪上下文
+        let trace = ArcTraceContext::default();
+        let _enter = trace.enter();
+
         let (initial_packets_entry, rcvd_initial_packets) = mpsc::unbounded();
         let (zero_rtt_packets_entry, rcvd_0rtt_packets) = mpsc::unbounded();
         let (hs_packets_entry, rcvd_hs_packets) = mpsc::unbounded();
@@ -75,15 +156,20 @@ impl RawConnection {
         let hs = HandshakeScope::default();
         let data = DataScope::default();
 
+        let packet_entries = PacketEntries::new(
+            [
+                initial_packets_entry,
+                zero_rtt_packets_entry,
+                hs_packets_entry,
+                one_rtt_packets_entry,
+            ],
+            canonical_mapped_addresses,
+        );
         let router_registry = ROUTER.registry(
             initial_scid,
             reliable_frames.clone(),
-            [
-                initial_packets_entry.clone(),
-                zero_rtt_packets_entry.clone(),
-                hs_packets_entry.clone(),
-                one_rtt_packets_entry.clone(),
-            ],
+            packet_entries,
+            reset_key,
         );
         let local_cids = ArcLocalCids::new(Self::gen_cid, initial_scid, router_registry);
         let remote_cids = ArcRemoteCids::new(
@@ -93,16 +179,27 @@ impl RawConnection {
         );
         let cid_registry = CidRegistry::new(local_cids, remote_cids);
         let handshake = Handshake::new(role, reliable_frames.clone());
-        let flow_ctrl = FlowController::with_initial(65535, 65535);
+        // 对方的额度在收到其传输参数之前未知，0-RTT则沿用记住的，见ArcConnection::set_remembered_parameters
+        let flow_ctrl =
+            FlowController::with_initial(0, local_params.initial_max_data().into_inner());
         let conn_error = ConnError::default();
+        let reset_tokens = RESET_TOKENS.registry(cid_registry.remote.clone(), conn_error.clone());
 
-        let streams = DataStreams::new(
-            role,
-            // 流数量
-            &local_params,
-            Default::default(),
-        );
-        let datagrams = DatagramFlow::new(0);
+        // 客户端随机选取的，或者服务端从首个Initial包中得知的目的连接ID
+        let odcid = initial_dcid;
+        let retry_scid = Arc::new(Mutex::new(None));
+        let versions = Arc::new(Mutex::new(Versions::default()));
+        let streams = profile.has_streams().then(|| {
+            DataStreams::with_conn_id(
+                role,
+                // 流数量
+                &local_params,
+                Default::default(),
+                odcid,
+            )
+        });
+        // 本端通告0则不支持数据报，对方发来的DATAGRAM帧是违规
+        let datagrams = DatagramFlow::new(local_params.max_datagram_frame_size().into_inner());
 
         let token = match &*token_registry.lock_guard() {
             TokenRegistry::Client((server_name, client)) => {
@@ -111,7 +208,30 @@ impl RawConnection {
             TokenRegistry::Server(_) => Arc::new(Mutex::new(vec![])),
         };
 
+        let ack_eagerness = Arc::new(Mutex::new(AckEagerness::default()));
+        let pressure = Arc::new(Mutex::new(PressureLevel::default()));
+        let pacing = Arc::new(Mutex::new(PacingConfig::default()));
+        let loss_detection = Arc::new(Mutex::new(LossDetectionConfig::default()));
+        let ack_frequency = ArcAckFrequency::new(local_params.min_ack_delay());
+        let peer_ack_delay = Arc::new(Mutex::new(None));
+        let address_discovery = ArcAddressDiscovery::new(local_params.address_discovery());
+        let congestion = Arc::new(Mutex::new(CongestionAlgorithm::default()));
+        let max_initial_pto_count = Arc::new(Mutex::new(None));
+        let drops = ArcDropCounters::default();
+        let activity = ArcActivity::default();
+        let overhead = ArcOverheadBudget::default();
         let pathes = ArcPathes::new(Box::new({
+            let ack_eagerness = ack_eagerness.clone();
+            let pressure = pressure.clone();
+            let pacing = pacing.clone();
+            let loss_detection = loss_detection.clone();
+            let ack_frequency = ack_frequency.clone();
+            let peer_ack_delay = peer_ack_delay.clone();
+            let congestion = congestion.clone();
+            let max_initial_pto_count = max_initial_pto_count.clone();
+            let drops = drops.clone();
+            let overhead = overhead.clone();
+            let activity = activity.clone();
             let cid_registry = cid_registry.clone();
             let flow_ctrl = flow_ctrl.clone();
             let handshake = handshake.clone();
@@ -123,16 +243,19 @@ impl RawConnection {
                 let streams = streams.clone();
                 let datagrams = datagrams.clone();
                 let token = token.clone();
+                let versions = versions.clone();
                 move |path: &RawPath| {
                     (
-                        initial.reader(token.clone()),
+                        initial.reader(token.clone(), versions.clone()),
                         hs.reader(),
                         data.reader(
                             path.challenge_sndbuf(),
                             path.response_sndbuf(),
+                            path.observed_sndbuf(),
                             reliable_frames.clone(),
                             streams.clone(),
                             datagrams.clone(),
+                            role,
                         ),
                     )
                 }
@@ -144,10 +267,13 @@ impl RawConnection {
                 let data = data.clone();
                 let data_streams = streams.clone();
                 let reliable_frames = reliable_frames.clone();
+                let datagrams = datagrams.clone();
                 move |epoch: Epoch, pn: u64| match epoch {
                     Epoch::Initial => initial.may_loss(pn),
                     Epoch::Handshake => hs.may_loss(pn),
-                    Epoch::Data => data.may_loss(pn, &data_streams, &reliable_frames),
+                    Epoch::Data => {
+                        data.may_loss(pn, data_streams.as_ref(), &reliable_frames, &datagrams)
+                    }
                 }
             });
 
@@ -165,45 +291,111 @@ impl RawConnection {
             move |pathway, usc| {
                 let scid = cid_registry.local.active_cids()[0];
                 let dcid = cid_registry.remote.apply_dcid();
-                let path = ArcPath::new(usc.clone(), scid, dcid, loss.clone(), retire.clone());
+                let algorithm = *congestion.lock().unwrap();
+                let path = ArcPath::new(
+                    usc.clone(),
+                    scid,
+                    dcid,
+                    algorithm,
+                    loss.clone(),
+                    retire.clone(),
+                );
+                let pressure = *pressure.lock().unwrap();
+                path.cc
+                    .set_ack_eagerness(pressure.ack_eagerness(*ack_eagerness.lock().unwrap()));
+                path.cc.set_pacing(*pacing.lock().unwrap());
+                path.cc.set_loss_detection(*loss_detection.lock().unwrap());
+                path.cc.set_role(role);
+                if handshake.is_handshake_done() {
+                    path.cc.on_handshake_done();
+                    path.cc.on_pkt_space_discarded(Epoch::Initial);
+                    path.cc.on_pkt_space_discarded(Epoch::Handshake);
+                }
+                if let Some(frequency) = ack_frequency.latest() {
+                    path.cc.set_ack_frequency(frequency);
+                }
+                if let Some((exponent, max_ack_delay)) = *peer_ack_delay.lock().unwrap() {
+                    path.cc.set_peer_ack_delay(exponent, max_ack_delay);
+                }
+                if !handshake.is_handshake_done() {
+                    path.cc
+                        .set_max_pto_count(*max_initial_pto_count.lock().unwrap());
+                }
 
                 if !handshake.is_handshake_done() {
                     if role == Role::Client {
                         path.anti_amplifier.grant();
                     }
                 } else {
-                    path.begin_validation();
+                    // 握手之后的新路径验证通过之前不承载数据，见ArcPathes::migrate
+                    path.set_standby(true);
+                    path.begin_validation(&overhead);
                 }
-                path.begin_sending(pathway, &flow_ctrl, &gen_readers);
+                path.begin_sending(pathway, &flow_ctrl, &drops, &activity, &gen_readers);
                 path
             }
         }));
 
+        let undersized_initial = Arc::new(Mutex::new(None));
         let validate = {
-            let tls_session = tls_session.clone();
             let token_registry = token_registry.clone();
-            move |initial_token: &[u8], path: ArcPath| {
-                if let TokenRegistry::Server(provider) = &*token_registry.lock_guard() {
-                    if let Some(server_name) = tls_session.server_name() {
-                        if provider.validate_token(server_name, initial_token) {
-                            path.anti_amplifier.grant();
+            let conn_error = conn_error.clone();
+            let undersized_initial = undersized_initial.clone();
+            // 同一路径上的Initial包都携带同一个令牌，每条路径只需验证一次
+            let validated = Mutex::new(HashSet::new());
+            move |initial: &InitialHeader, pkt_size: usize, pathway: &Pathway, path: ArcPath| {
+                // 客户端已经退而发送更小的Initial包，服务端也随之发送更小的数据报
+                if let Some(size) = *undersized_initial.lock().unwrap() {
+                    if pkt_size < MSS && size < path.max_datagram_size() {
+                        path.set_max_datagram_size(size);
+                    }
+                }
+                if !validated.lock().unwrap().insert(*pathway) {
+                    return;
+                }
+                if let TokenRegistry::Server(validator) = &*token_registry.lock_guard() {
+                    // 服务端在创建连接之前已经统计过该令牌，丢弃、Retry也已经处理，这里只需验证地址或者关闭连接
+                    let outcome =
+                        validator.classify(&initial.token, pathway.remote_addr(), &initial.dcid);
+                    match validator.action(&outcome) {
+                        TokenAction::Accept => path.anti_amplifier.grant(),
+                        TokenAction::Close => {
+                            let reason = match outcome {
+                                TokenOutcome::Invalid(_, error) => error.to_string(),
+                                _ => "token is required".to_owned(),
+                            };
+                            conn_error
+                                .on_error(Error::with_default_fty(ErrorKind::InvalidToken, reason));
                         }
+                        _ => {}
                     }
                 }
             }
         };
 
         let notify = Arc::new(Notify::new());
+        let frame_budget = ArcFrameBudget::default();
         let join_initial = initial.build(
             rcvd_initial_packets,
             &pathes,
             &cid_registry.remote,
             &notify,
             &conn_error,
+            &frame_budget,
+            &drops,
+            &activity,
             validate,
         );
 
-        let join_hs = hs.build(rcvd_hs_packets, &pathes, &notify, &conn_error);
+        let join_hs = hs.build(
+            rcvd_hs_packets,
+            &pathes,
+            &notify,
+            &conn_error,
+            &frame_budget,
+            &drops,
+            &activity,
+        );
 
         let remote_params = tls_session.keys_upgrade(
             [
@@ -212,52 +404,170 @@ impl RawConnection {
                 &data.crypto_stream,
             ],
             hs.keys.clone(),
+            data.zero_rtt_keys.clone(),
             data.one_rtt_keys.clone(),
             conn_error.clone(),
         );
 
-        tokio::spawn({
+        let remembered_params = Arc::new(Mutex::new(None));
+        let params_changed = Arc::new(AsyncCell::new());
+        spawn_traced({
             let remote_params = remote_params.clone();
+            let remembered_params = remembered_params.clone();
+            let params_changed = params_changed.clone();
             let streams = streams.clone();
             let conn_error = conn_error.clone();
             let cid_registry = cid_registry.clone();
+            let tls_session = tls_session.clone();
+            let datagrams = datagrams.clone();
+            let flow_ctrl = flow_ctrl.clone();
+            let address_discovery = address_discovery.clone();
+            let peer_ack_delay = peer_ack_delay.clone();
+            let pathes = pathes.clone();
+            let reset_tokens = reset_tokens.clone();
+            let retry_scid = retry_scid.clone();
+            let versions = versions.clone();
+            let data = data.clone();
+            let reliable_frames = reliable_frames.clone();
             async move {
                 let remote_params = remote_params.get().map(|r| r.as_ref().cloned()).await;
                 let Some(remote_params) = remote_params else {
+                    params_changed.invalid();
                     return;
                 };
 
+                // 客户端核对服务端在握手期间选用的各个连接ID，以免被篡改，见RFC 9000 7.3
+                if role == Role::Client {
+                    let initial_scid = cid_registry.remote.initial_dcid().unwrap_or_default();
+                    let retry_scid = *retry_scid.lock().unwrap();
+                    if let Err(error) = remote_params.authenticate_server_cids(
+                        &odcid,
+                        &initial_scid,
+                        retry_scid.as_ref(),
+                    ) {
+                        conn_error.on_error(error);
+                        params_changed.invalid();
+                        return;
+                    }
+                }
+
+                // 核对对方的version_information，客户端经过了版本协商的，还要确认没有被降级，见RFC 9368
+                let authenticated = versions
+                    .lock()
+                    .unwrap()
+                    .authenticate(!role, remote_params.version_information());
+                if let Err(error) = authenticated {
+                    conn_error.on_error(error);
+                    params_changed.invalid();
+                    return;
+                }
+
+                // 恢复的连接，新的传输参数与记住的不同，宽松的直接生效，收紧的要与0-RTT的结果核对
+                let remembered = remembered_params.lock().unwrap().take();
+                match remembered.map(|r| ParametersChanged::between(&r, &remote_params)) {
+                    Some(changed) if !changed.is_empty() => {
+                        let accepted = tls_session.is_early_data_accepted().unwrap_or(false);
+                        if let Err(error) = changed.reconcile(accepted) {
+                            conn_error.on_error(error);
+                        } else if let Some(change) = changed
+                            .get(RememberedField::MaxDatagramFrameSize)
+                            .filter(|change| change.is_restricted())
+                        {
+                            let dropped =
+                                datagrams.on_max_frame_size_reduced(change.current.into_inner());
+                            tracing::debug!(
+                                max_datagram_frame_size = %change.current,
+                                dropped,
+                                "server reduced max_datagram_frame_size on return"
+                            );
+                        }
+                        _ = params_changed.write(changed);
+                    }
+                    _ => params_changed.invalid(),
+                }
+
+                // 收到服务端的传输参数时，便已知晓0-RTT数据是否被接受
+                match tls_session.is_early_data_accepted() {
+                    Some(true) => datagrams.on_0rtt_accepted(),
+                    Some(false) => {
+                        datagrams.on_0rtt_rejected();
+                        // 不再发送0-RTT包，已发送的其中的流数据等，透明地在1-RTT包中重传
+                        if data.zero_rtt_keys.invalid().is_some() {
+                            data.resend_0rtt_data(streams.as_ref(), &reliable_frames, &datagrams);
+                        }
+                    }
+                    None => {}
+                }
+
+                address_discovery.on_remote_params(remote_params.address_discovery());
+                flow_ctrl.apply_transport_parameters(&remote_params);
+
+                // 传输参数经过校验，ack_delay_exponent不超过20
+                let exponent = remote_params.ack_delay_exponent().into_inner() as u8;
+                let max_ack_delay =
+                    Duration::from_millis(remote_params.max_ack_delay().into_inner());
+                *peer_ack_delay.lock().unwrap() = Some((exponent, max_ack_delay));
+                for path in pathes.iter() {
+                    path.cc.set_peer_ack_delay(exponent, max_ack_delay);
+                }
+
                 let max_bidi_sid = remote_params.initial_max_streams_bidi().into();
                 let max_uni_sid = remote_params.initial_max_streams_uni().into();
                 let active_cid_limit = remote_params.active_connection_id_limit().into();
 
-                streams.premit_max_sid(qbase::streamid::Dir::Bi, max_uni_sid);
-                streams.premit_max_sid(qbase::streamid::Dir::Uni, max_bidi_sid);
+                if let Some(streams) = &streams {
+                    streams.premit_max_sid(qbase::streamid::Dir::Bi, max_uni_sid);
+                    streams.premit_max_sid(qbase::streamid::Dir::Uni, max_bidi_sid);
+                    // 对方给出了min_ack_delay，即支持ACK_FREQUENCY扩展，可以发IMMEDIATE_ACK帧
+                    if remote_params.min_ack_delay().is_some() {
+                        streams.enable_immediate_ack();
+                    }
+                }
                 if let Err(e) = cid_registry.local.set_limit(active_cid_limit) {
                     conn_error.on_error(e);
                 }
+                // 服务端在传输参数中给出初始连接ID的无状态重置令牌
+                if let Some(token) = remote_params.statelss_reset_token() {
+                    cid_registry.remote.set_initial_reset_token(token);
+                    reset_tokens.register(token);
+                }
             }
         });
 
+        let receive_watchdog = ReceiveWatchdog::default();
         let (join_0rtt, join_1rtt) = data.build(
             &pathes,
             &handshake,
             &reliable_frames,
-            &streams,
+            streams.as_ref(),
             &datagrams,
             &cid_registry,
+            &reset_tokens,
             &flow_ctrl,
             &notify,
             &conn_error,
+            &frame_budget,
+            &drops,
+            &activity,
             rcvd_0rtt_packets,
             rcvd_1rtt_packets,
             token_registry,
+            &tls_session,
+            &receive_watchdog,
+            &ack_frequency,
+            &address_discovery,
         );
         let join_handles = [join_initial, join_0rtt, join_hs, join_1rtt];
 
+        let stall_detector = ArcStallDetector::default();
+        if let Some(streams) = &streams {
+            stall_detector.launch(streams);
+        }
+
         Self {
             token,
             pathes,
+            canonical_mapped_addresses,
             cid_registry,
             handshake,
             flow_ctrl,
@@ -272,13 +582,171 @@ impl RawConnection {
             error: conn_error,
             local_params: local_params.into(),
             remote_params,
+            remembered_params,
+            params_changed,
             tls_session,
+            trace,
+            ack_eagerness,
+            pacing,
+            loss_detection,
+            ack_frequency,
+            peer_ack_delay,
+            address_discovery,
+            congestion,
+            max_initial_pto_count,
+            attempts: ConnectAttempts::default(),
+            undersized_initial,
+            receive_watchdog,
+            frame_budget,
+            drops,
+            activity,
+            overhead,
+            odcid,
+            retry_scid,
+            pressure,
+            stall_detector,
+            network_telemetry: None,
+            reset_tokens,
+            versions,
+        }
+    }
+
+    /// 已发送的Initial包里的CRYPTO数据都视作丢失，以便重新发送，比如换用了Retry包给的连接ID，
+    /// 或者版本协商之后换用了另一个版本
+    pub fn resend_initial_crypto(&self) {
+        let sent_record = self.initial.space.sent_packets();
+        let mut guard = sent_record.receive();
+        for i in 0..guard.largest_pn() {
+            for frame in guard.may_loss_pkt(i) {
+                self.initial.crypto_stream.outgoing().may_loss_data(&frame);
+            }
+        }
+    }
+
+    /// 打开流所依据的对方的传输参数。恢复会话的客户端在收到服务端的传输参数之前，沿用记住的，
+    /// 以便在0-RTT包中发送流数据
+    pub fn peer_parameters(&self) -> Option<Parameters> {
+        match self.remote_params.state().as_ref() {
+            Some(remote_params) => Some(***remote_params),
+            None => *self.remembered_params.lock().unwrap(),
+        }
+    }
+
+    /// 同[`RawConnection::peer_parameters`]，但都还没有的话，等待对方的传输参数，连接关闭则为None
+    pub fn wait_peer_parameters(&self) -> impl Future<Output = Option<Parameters>> + Send {
+        let known = self.peer_parameters();
+        let remote_params = self.remote_params.clone();
+        async move {
+            if known.is_some() {
+                return known;
+            }
+            let remote_params = remote_params.get().await.as_ref().map(|params| ***params);
+            remote_params
+        }
+    }
+
+    /// The bytes allocated for the state of the connection when it's created, see
+    /// [`ConnectionStats::footprint`].
+    ///
+    /// [`ConnectionStats::footprint`]: crate::connection::ConnectionStats::footprint
+    pub fn footprint(&self) -> usize {
+        let streams = self.streams.as_ref();
+        std::mem::size_of::<Self>() + streams.map_or(0, |streams| streams.footprint())
+    }
+
+    /// The digest of the connection for the [`NetworkTelemetry`], None if it has no path.
+    pub fn digest(&self, telemetry: &NetworkTelemetry) -> Option<ConnectionDigest> {
+        let mut latest = None;
+        let (mut bytes_sent, mut packets_sent, mut packets_lost) = (0, 0, 0);
+        for entry in self.pathes.iter() {
+            let metrics = entry.value().cc.metrics();
+            bytes_sent += metrics.bytes_sent;
+            packets_sent += metrics.packets_sent;
+            packets_lost += metrics.packets_lost;
+            // 取最近收过包的路径，都没收过则任取一条
+            let recv_time = entry.value().last_recv_time();
+            if latest
+                .as_ref()
+                .is_none_or(|(latest_recv_time, ..)| recv_time > *latest_recv_time)
+            {
+                latest = Some((recv_time, *entry.key(), metrics));
+            }
+        }
+        let (_, pathway, metrics) = latest?;
+        Some(ConnectionDigest {
+            prefix: telemetry.prefix_of(pathway.remote_addr()),
+            duration: self.activity.since().elapsed(),
+            bytes_sent,
+            bytes_rcvd: self.activity.snapshot().rcvd_total,
+            smoothed_rtt: metrics.latest_rtt.map(|_| metrics.smoothed_rtt),
+            packets_sent,
+            packets_lost,
+            handshake_done: self.handshake.is_handshake_done(),
+        })
+    }
+
+    /// Report the digest to the [`NetworkTelemetry`] if any, once the connection is closed.
+    pub fn report_network_telemetry(&self) {
+        let Some(telemetry) = &self.network_telemetry else {
+            return;
+        };
+        if let Some(digest) = self.digest(telemetry) {
+            telemetry.report(&digest);
+        }
+    }
+
+    pub fn streams_shed(&self) -> u64 {
+        let streams = self.streams.as_ref();
+        streams.map_or(0, |streams| streams.listener().shed_streams())
+    }
+
+    /// The pathway in the form of the connection, as the packets received via it are
+    /// delivered, see [`PacketEntries::pathway`].
+    pub fn pathway(&self, pathway: Pathway) -> Pathway {
+        if self.canonical_mapped_addresses {
+            pathway.canonical()
+        } else {
+            pathway
         }
     }
 
     pub fn update_path_recv_time(&self, pathway: Pathway) {
+        let pathway = self.pathway(pathway);
         if let Some(path) = self.pathes.try_get(&pathway).try_unwrap() {
             path.update_recv_time();
         }
     }
+
+    pub fn set_crypto_limits(&self, limits: CryptoLimits) {
+        self.initial.crypto_stream.set_recv_limits(limits);
+        self.hs.crypto_stream.set_recv_limits(limits);
+        self.data.crypto_stream.set_recv_limits(limits);
+    }
+
+    // 按Epoch索引
+    pub fn crypto_stats(&self) -> [CryptoRecvStats; Epoch::count()] {
+        [
+            self.initial.crypto_stream.recv_stats(),
+            self.hs.crypto_stream.recv_stats(),
+            self.data.crypto_stream.recv_stats(),
+        ]
+    }
+
+    pub fn set_max_sent_records(&self, max_records: usize) {
+        self.initial
+            .space
+            .sent_packets()
+            .set_max_records(max_records);
+        self.hs.space.sent_packets().set_max_records(max_records);
+        self.data.space.sent_packets().set_max_records(max_records);
+    }
+
+    // 按Epoch索引
+    pub fn sent_records_stats(&self) -> [SentRecordsStats; Epoch::count()] {
+        [
+            self.initial.space.sent_packets().stats(),
+            self.hs.space.sent_packets().stats(),
+            self.data.space.sent_packets().stats(),
+        ]
+    }
 }
@@ -2,15 +2,20 @@ pub mod data;
 pub mod handshake;
 pub mod initial;
 
-use std::future::Future;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
 
 pub use data::{ClosingOneRttScope, DataScope};
 pub use handshake::{ClosingHandshakeScope, HandshakeScope};
 pub use initial::InitialScope;
 use qbase::{
-    frame::{Frame, FrameReader},
+    error::{Error, ErrorKind},
+    frame::{AckFrame, BeFrame, Frame, FrameReader},
     packet::{decrypt::decrypt_packet, header::GetType, DataPacket},
 };
+use qrecovery::{reliable::sentpkt::ArcSentPktRecords, space::Epoch};
 use tokio::sync::Notify;
 
 pub trait RecvPacket {
@@ -30,6 +35,117 @@ pub trait RecvPacket {
     }
 }
 
+/// How many frames of a packet are processed before yielding to the other tasks, and
+/// the hard cap of the frames in a packet. The PADDING frames are not counted.
+///
+/// A packet stuffed with thousands of tiny frames is legal, processing it in one go
+/// would hold the runtime for a long slice. No legitimate sender produces a packet
+/// exceeding the cap, which closes the connection with a FRAME_ENCODING_ERROR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBudget {
+    pub frames_per_yield: usize,
+    pub max_frames_per_packet: usize,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self {
+            frames_per_yield: 64,
+            max_frames_per_packet: 10_000,
+        }
+    }
+}
+
+/// The [`FrameBudget`] shared by the receiving tasks of all the spaces of a connection.
+#[derive(Debug, Default, Clone)]
+pub struct ArcFrameBudget(Arc<Mutex<FrameBudget>>);
+
+impl ArcFrameBudget {
+    pub fn get(&self) -> FrameBudget {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, budget: FrameBudget) {
+        *self.0.lock().unwrap() = budget;
+    }
+}
+
+/// The entry of the ACK frames received in the packets of a packet number space.
+///
+/// The spaces are separate, an ACK frame only acknowledges the packets sent in the space of
+/// the packet carrying it, see [RFC 9000 section 12.3](https://www.rfc-editor.org/rfc/rfc9000.html#section-12.3).
+/// Within a space the packet numbers are continuous across the key generations, the ACK
+/// frames in 1-RTT packets acknowledge the 0-RTT packets and the ones protected by the
+/// previous keys alike.
+#[derive(Debug, Clone)]
+struct AckEntry<T> {
+    epoch: Epoch,
+    sent_packets: ArcSentPktRecords<T>,
+}
+
+impl<T> AckEntry<T> {
+    fn new(epoch: Epoch, sent_packets: ArcSentPktRecords<T>) -> Self {
+        Self {
+            epoch,
+            sent_packets,
+        }
+    }
+
+    /// Check that the ACK frame acknowledges only the packets sent in this space, return the
+    /// space to feed it to the congestion controller with.
+    ///
+    /// Acknowledging a packet never sent, such as the packet numbers of another space, is a
+    /// PROTOCOL_VIOLATION, see [RFC 9000 section 13.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-13.1).
+    fn check(&self, ack_frame: &AckFrame) -> Result<Epoch, Error> {
+        let largest = ack_frame.largest.into_inner();
+        if self.sent_packets.has_sent(largest) {
+            Ok(self.epoch)
+        } else {
+            Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                ack_frame.frame_type(),
+                format!("acknowledged {:?} packet {largest} never sent", self.epoch),
+            ))
+        }
+    }
+}
+
+/// Dispatch the frames of a packet in order, yielding every [`FrameBudget::frames_per_yield`]
+/// frames. Return whether the packet is ack-eliciting.
+///
+/// The frames before an error have been dispatched, and the packet should not be
+/// acknowledged, just like the packet is processed in one go.
+async fn dispatch_frames(
+    frames: FrameReader,
+    budget: FrameBudget,
+    mut dispatch_frame: impl FnMut(Frame),
+) -> Result<bool, Error> {
+    let mut is_ack_packet = false;
+    let mut counted = 0;
+    for frame in frames {
+        let (frame, is_ack_eliciting) = frame?;
+        is_ack_packet |= is_ack_eliciting;
+        if matches!(frame, Frame::Padding(_)) {
+            continue;
+        }
+        counted += 1;
+        if counted > budget.max_frames_per_packet {
+            return Err(Error::with_default_fty(
+                ErrorKind::FrameEncoding,
+                format!(
+                    "more than {} frames in a packet",
+                    budget.max_frames_per_packet
+                ),
+            ));
+        }
+        dispatch_frame(frame);
+        if counted % budget.frames_per_yield.max(1) == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+    Ok(is_ack_packet)
+}
+
 async fn any<F, T>(fut: F, notify: &Notify) -> Option<T>
 where
     F: Future<Output = Option<T>>,
@@ -39,3 +155,200 @@ where
         v = fut => v,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ops::RangeInclusive,
+        pin::pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    use bytes::{BufMut, Bytes};
+    use qbase::{
+        packet::r#type::{short::OneRtt, Type},
+        varint::VarInt,
+    };
+
+    use super::*;
+
+    // 记录被唤醒的次数，也就是让出执行权的次数
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // PADDING与长度为0的STREAM帧交替，每个STREAM帧只有3字节
+    fn stuffed_packet(stream_frames: usize) -> FrameReader {
+        let mut payload = Vec::new();
+        for _ in 0..stream_frames {
+            payload.put_u8(0x00);
+            payload.put_slice(&[0x0a, 0x00, 0x00]);
+        }
+        FrameReader::new(Bytes::from(payload), Type::Short(OneRtt::from(0)))
+    }
+
+    /// Poll the dispatching to the end, return the result, the number of dispatched
+    /// frames and the number of yields.
+    fn poll_to_end(
+        frames: FrameReader,
+        budget: FrameBudget,
+    ) -> (Result<bool, Error>, usize, usize) {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut dispatched = 0;
+        let result = {
+            let mut dispatch = pin!(dispatch_frames(frames, budget, |frame| {
+                assert!(matches!(frame, Frame::Stream(..)));
+                dispatched += 1;
+            }));
+            loop {
+                if let Poll::Ready(result) = dispatch.as_mut().poll(&mut cx) {
+                    break result;
+                }
+            }
+        };
+        (result, dispatched, counter.0.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn test_dispatch_with_yields() {
+        // 1200字节，600个帧
+        let (result, dispatched, yields) = poll_to_end(stuffed_packet(300), FrameBudget::default());
+        assert_eq!(result, Ok(true));
+        assert_eq!(dispatched, 300);
+        assert_eq!(yields, 300 / 64);
+    }
+
+    fn send(records: &ArcSentPktRecords<u64>, pns: impl IntoIterator<Item = u64>) {
+        // 每个包只有一个帧，帧就是包号，确认时可以看出有没有张冠李戴
+        for pn in pns {
+            records.send().record_frame(pn);
+        }
+    }
+
+    // 由大到小、互不相交的区间构造ACK帧
+    fn ack_of(ranges: &[RangeInclusive<u64>]) -> AckFrame {
+        let largest = *ranges[0].end();
+        let mut left = *ranges[0].start();
+        let mut gaps = Vec::new();
+        for range in &ranges[1..] {
+            let gap = left - range.end() - 2;
+            gaps.push((
+                VarInt::from_u64(gap).unwrap(),
+                VarInt::from_u64(range.end() - range.start()).unwrap(),
+            ));
+            left = *range.start();
+        }
+        AckFrame {
+            largest: VarInt::from_u64(largest).unwrap(),
+            delay: VarInt::from_u32(0),
+            first_range: VarInt::from_u64(largest - ranges[0].start()).unwrap(),
+            ranges: gaps,
+            ecn: None,
+        }
+    }
+
+    // 返回ACK帧新确认的帧
+    fn on_ack(entry: &AckEntry<u64>, ack_frame: &AckFrame) -> Result<Vec<u64>, Error> {
+        assert_eq!(entry.check(ack_frame)?, entry.epoch);
+        let mut guard = entry.sent_packets.receive();
+        guard.update_largest(ack_frame.largest.into_inner());
+        Ok(ack_frame
+            .iter()
+            .flat_map(|r| r.rev())
+            .flat_map(|pn| guard.on_pkt_acked(pn).collect::<Vec<_>>())
+            .collect())
+    }
+
+    #[test]
+    fn test_ack_across_key_phases() {
+        // 0~4用旧密钥发送，5~9用新密钥发送，包号是连续的
+        let entry = AckEntry::new(Epoch::Data, ArcSentPktRecords::with_capacity(16));
+        send(&entry.sent_packets, 0..5);
+        send(&entry.sent_packets, 5..10);
+
+        // 新密钥保护的包里的ACK帧，同时确认了新旧密钥发送的包
+        let acked = on_ack(&entry, &ack_of(&[6..=7, 2..=4])).unwrap();
+        assert_eq!(acked, vec![7, 6, 4, 3, 2]);
+        let acked = on_ack(&entry, &ack_of(&[0..=9])).unwrap();
+        assert_eq!(acked, vec![9, 8, 5, 1, 0]);
+    }
+
+    #[test]
+    fn test_ack_of_0rtt_in_1rtt() {
+        // 0-RTT的包与1-RTT的包同属数据空间，1-RTT包里的ACK帧确认了0-RTT的包
+        let entry = AckEntry::new(Epoch::Data, ArcSentPktRecords::with_capacity(16));
+        let zero_rtt = 0..3;
+        let one_rtt = 3..5;
+        send(&entry.sent_packets, zero_rtt);
+        send(&entry.sent_packets, one_rtt);
+        let acked = on_ack(&entry, &ack_of(&[0..=4])).unwrap();
+        assert_eq!(acked, vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_ack_of_another_space() {
+        let initial = AckEntry::new(Epoch::Initial, ArcSentPktRecords::with_capacity(16));
+        let handshake = AckEntry::new(Epoch::Handshake, ArcSentPktRecords::with_capacity(16));
+        send(&initial.sent_packets, 0..5);
+        send(&handshake.sent_packets, 0..2);
+
+        // Handshake包里的ACK帧，确认的却是Initial空间的包号，不能误用到Handshake空间
+        let error = on_ack(&handshake, &ack_of(&[0..=4])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert_eq!(handshake.sent_packets.receive().on_pkt_acked(0).count(), 1);
+        assert_eq!(on_ack(&handshake, &ack_of(&[0..=1])).unwrap(), vec![1]);
+        assert_eq!(on_ack(&initial, &ack_of(&[0..=4])).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_key_update_during_heavy_acking() {
+        const BATCHES: u64 = 125;
+        const BATCH: u64 = 8;
+
+        let entry = AckEntry::new(Epoch::Data, ArcSentPktRecords::with_capacity(16));
+        let mut acked = Vec::new();
+        let mut late = None;
+        for batch in 0..BATCHES {
+            // 发送记录与密钥无关，第50批起换用新密钥，确认照常进行
+            let start = batch * BATCH;
+            send(&entry.sent_packets, start..start + BATCH);
+            // 每批的第3个包晚到，随下一批一起确认，确认帧跨越了密钥更新的边界
+            let mut ranges = vec![start + 3..=start + BATCH - 1, start..=start + 1];
+            if let Some(pn) = late.replace(start + 2) {
+                ranges.push(pn..=pn);
+            }
+            acked.extend(on_ack(&entry, &ack_of(&ranges)).unwrap());
+            // 重复的确认不会重复交付
+            assert_eq!(on_ack(&entry, &ack_of(&ranges)).unwrap(), vec![]);
+        }
+        let pn = late.unwrap();
+        acked.extend(on_ack(&entry, &ack_of(&[pn..=pn])).unwrap());
+
+        // 每个包都被确认了恰好一次，帧与包号一一对应
+        acked.sort_unstable();
+        assert_eq!(acked, (0..BATCHES * BATCH).collect::<Vec<_>>());
+        // 尚未发送的包号仍然不能被确认
+        let error = on_ack(&entry, &ack_of(&[BATCHES * BATCH..=BATCHES * BATCH])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+    }
+
+    #[test]
+    fn test_too_many_frames() {
+        let budget = FrameBudget {
+            frames_per_yield: 64,
+            max_frames_per_packet: 100,
+        };
+        let (result, dispatched, _) = poll_to_end(stuffed_packet(300), budget);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::FrameEncoding);
+        // 超出上限之前的帧，已经按序处理了
+        assert_eq!(dispatched, 100);
+    }
+}
@@ -1,25 +1,30 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use bytes::Bytes;
 use futures::{channel::mpsc, StreamExt};
 use qbase::{
+    cid::ConnectionId,
     error::{Error as QuicError, ErrorKind},
     flow,
     frame::{
-        AckFrame, BeFrame, Frame, FrameReader, PathChallengeFrame, PathResponseFrame, ReceiveFrame,
-        ReliableFrame, StreamFrame,
+        io::WriteFrame, AckFrame, BeFrame, ConnectionCloseFrame, Frame, FrameReader,
+        ObservedAddressFrame, PathChallengeFrame, PathResponseFrame, ReceiveFrame, ReliableFrame,
+        SendFrame, StreamCtlFrame, StreamFrame,
     },
     handshake::Handshake,
     packet::{
         decrypt::{
             decrypt_packet, remove_protection_of_long_packet, remove_protection_of_short_packet,
         },
-        header::GetType,
+        encrypt::{encode_short_first_byte, PacketBuf, SealKeys},
+        header::{GetType, WriteOneRttHeader},
         keys::{ArcHeaderProtectionKeys, ArcKeys, ArcOneRttKeys, ArcOneRttPacketKeys},
         r#type::Type,
-        DataPacket, PacketNumber,
+        DataPacket, Encode, OneRttHeader, PacketNumber, SpinBit, WritePacketNumber,
     },
+    streamid::Role,
     token::ArcTokenRegistry,
+    util::spawn_traced,
 };
 use qcongestion::CongestionControl;
 use qrecovery::{
@@ -30,13 +35,23 @@ use qrecovery::{
 use qunreliable::DatagramFlow;
 use tokio::{sync::Notify, task::JoinHandle};
 
-use super::any;
+use super::{any, dispatch_frames, AckEntry, ArcFrameBudget};
 use crate::{
-    connection::{transmit::data::DataSpaceReader, CidRegistry, DataStreams, RcvdPackets},
+    connection::{
+        ack_frequency::ArcAckFrequency,
+        activity::ArcActivity,
+        address_discovery::ArcAddressDiscovery,
+        profile::{stream_ctl_frame_refused, stream_frame_refused},
+        transmit::data::DataSpaceReader,
+        watchdog::ReceiveWatchdog,
+        CidRegistry, DataStreams, RcvdPackets,
+    },
+    drops::{ArcDropCounters, DropReason},
     error::ConnError,
-    path::{ArcPathes, RawPath, SendBuffer},
+    path::{ArcPathes, Pathway, RawPath, SendBuffer},
     pipe,
-    router::ROUTER,
+    router::{ResetTokenRegistry, ROUTER},
+    tls::ArcTlsSession,
 };
 
 #[derive(Clone)]
@@ -53,7 +68,8 @@ impl Default for DataScope {
             zero_rtt_keys: ArcKeys::new_pending(),
             one_rtt_keys: ArcOneRttKeys::new_pending(),
             space: DataSpace::with_capacity(16),
-            crypto_stream: CryptoStream::new(0, 0),
+            // 握手之后服务端还要发送NewSessionTicket
+            crypto_stream: CryptoStream::new(4096, 4096),
         }
     }
 }
@@ -65,15 +81,23 @@ impl DataScope {
         pathes: &ArcPathes,
         handshake: &Handshake<ArcReliableFrameDeque>,
         reliable_frames: &ArcReliableFrameDeque,
-        streams: &DataStreams,
+        streams: Option<&DataStreams>,
         datagrams: &DatagramFlow,
         cid_registry: &CidRegistry,
+        reset_tokens: &ResetTokenRegistry,
         flow_ctrl: &flow::FlowController,
         notify: &Arc<Notify>,
         conn_error: &ConnError,
+        frame_budget: &ArcFrameBudget,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
         rcvd_0rtt_packets: RcvdPackets,
         rcvd_1rtt_packets: RcvdPackets,
-        recv_new_token: ArcTokenRegistry,
+        token_registry: ArcTokenRegistry,
+        tls_session: &ArcTlsSession,
+        receive_watchdog: &ReceiveWatchdog,
+        ack_frequency: &ArcAckFrequency,
+        address_discovery: &ArcAddressDiscovery,
     ) -> (JoinHandle<RcvdPackets>, JoinHandle<RcvdPackets>) {
         let (ack_frames_entry, rcvd_ack_frames) = mpsc::unbounded();
         // 连接级的
@@ -89,34 +113,87 @@ impl DataScope {
         let (stream_frames_entry, rcvd_stream_frames) = mpsc::unbounded();
         let (datagram_frames_entry, rcvd_datagram_frames) = mpsc::unbounded();
 
+        let ack_entry = AckEntry::new(Epoch::Data, self.space.sent_packets());
         let dispatch_data_frame = {
             let conn_error = conn_error.clone();
-            move |frame: Frame, pty: Type, path: &RawPath| match frame {
-                Frame::Ack(f) => {
-                    path.cc.on_ack(Epoch::Data, &f);
-                    _ = ack_frames_entry.unbounded_send(f)
-                }
+            let receive_watchdog = receive_watchdog.clone();
+            let datagrams = datagrams.clone();
+            let streams = streams.cloned();
+            let pathes = pathes.clone();
+            let ack_frequency = ack_frequency.clone();
+            let address_discovery = address_discovery.clone();
+            move |frame: Frame, pty: Type, path: &RawPath, pathway: Pathway| match frame {
+                // 0-RTT与1-RTT的包同属数据空间，密钥更新前后的包号也是连续的
+                Frame::Ack(f) => match ack_entry.check(&f) {
+                    Ok(epoch) => {
+                        path.cc.on_ack(epoch, &f);
+                        if let Some(streams) = &streams {
+                            streams.update_rtt(path.cc.smoothed_rtt());
+                        }
+                        _ = ack_frames_entry.unbounded_send(f)
+                    }
+                    Err(e) => conn_error.on_error(e),
+                },
                 Frame::NewToken(f) => _ = new_token_frames_entry.unbounded_send(f),
                 Frame::MaxData(f) => _ = max_data_frames_entry.unbounded_send(f),
                 Frame::NewConnectionId(f) => _ = new_cid_frames_entry.unbounded_send(f),
                 Frame::RetireConnectionId(f) => _ = retire_cid_frames_entry.unbounded_send(f),
-                Frame::HandshakeDone(f) => _ = handshake_done_frames_entry.unbounded_send(f),
+                Frame::HandshakeDone(f) => {
+                    // 客户端的握手至此确认，各路径开始为数据空间设定PTO，不再追踪握手空间的包
+                    for path in pathes.iter() {
+                        path.cc.on_handshake_done();
+                        path.cc.on_pkt_space_discarded(Epoch::Initial);
+                        path.cc.on_pkt_space_discarded(Epoch::Handshake);
+                    }
+                    _ = handshake_done_frames_entry.unbounded_send(f)
+                }
                 Frame::DataBlocked(f) => _ = data_blocked_frames_entry.unbounded_send(f),
                 Frame::Challenge(f) => path.recv_challenge(f),
                 Frame::Response(f) => path.recv_response(f),
                 Frame::StreamCtl(f) => _ = stream_ctrl_frames_entry.unbounded_send(f),
-                Frame::Stream(f, data) => _ = stream_frames_entry.unbounded_send((f, data)),
+                Frame::Stream(f, data) => {
+                    receive_watchdog.on_data_rcvd();
+                    _ = stream_frames_entry.unbounded_send((f, data))
+                }
                 Frame::Crypto(f, bytes) => _ = crypto_frames_entry.unbounded_send((f, bytes)),
-                Frame::Datagram(f, data) => _ = datagram_frames_entry.unbounded_send((f, data)),
+                // 0-RTT包中的Datagram可能被重放，除非策略允许，否则直接丢弃
+                Frame::Datagram(..)
+                    if matches!(pty, Type::Long(_)) && !datagrams.is_0rtt_accepted() => {}
+                Frame::Datagram(f, data) => {
+                    receive_watchdog.on_data_rcvd();
+                    _ = datagram_frames_entry.unbounded_send((f, data))
+                }
                 Frame::Close(f) if matches!(pty, Type::Short(_)) => conn_error.on_ccf_rcvd(&f),
+                // 对方要求的确认频率作用于所有路径
+                Frame::AckFrequency(f) => match ack_frequency.recv_frame(&f) {
+                    Ok(Some(frequency)) => {
+                        for path in pathes.iter() {
+                            path.cc.set_ack_frequency(frequency);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => conn_error.on_error(e),
+                },
+                Frame::ImmediateAck(_) => path.cc.on_immediate_ack(),
+                Frame::ObservedAddress(f) => {
+                    if let Err(e) = address_discovery.recv_frame(&f, pathway) {
+                        conn_error.on_error(e);
+                    }
+                }
                 _ => {}
             }
         };
         let on_data_acked = {
-            let data_streams = streams.clone();
+            let data_streams = streams.cloned();
+            let datagrams = datagrams.clone();
             let crypto_stream_outgoing = self.crypto_stream.outgoing();
             let sent_pkt_records = self.space.sent_packets();
+            let one_rtt_keys = self.one_rtt_keys.clone();
             move |ack_frame: &AckFrame| {
+                // 当前密钥阶段发出的包得到确认，才可以再次更新密钥
+                if let Some((_, pk)) = one_rtt_keys.get_local_keys() {
+                    pk.lock_guard().on_acked(ack_frame.largest.into_inner());
+                }
                 let mut recv_guard = sent_pkt_records.receive();
                 recv_guard.update_largest(ack_frame.largest.into_inner());
 
@@ -124,11 +201,16 @@ impl DataScope {
                     for frame in recv_guard.on_pkt_acked(pn) {
                         match frame {
                             GuaranteedFrame::Stream(stream_frame) => {
-                                data_streams.on_data_acked(stream_frame)
+                                if let Some(data_streams) = &data_streams {
+                                    data_streams.on_data_acked(stream_frame)
+                                }
                             }
                             GuaranteedFrame::Crypto(crypto_frame) => {
                                 crypto_stream_outgoing.on_data_acked(&crypto_frame)
                             }
+                            GuaranteedFrame::Datagram(datagram) => {
+                                datagrams.on_datagram_acked(datagram.seq)
+                            }
                             _ => { /* nothing to do */ }
                         }
                     }
@@ -137,19 +219,17 @@ impl DataScope {
         };
 
         // Assemble the pipelines of frame processing
-        // TODO: pipe rcvd_new_token_frames
         let local_cids_with_router = ROUTER.revoke(cid_registry.local.clone());
         pipe!(rcvd_retire_cid_frames |> local_cids_with_router, recv_frame);
-        pipe!(@error(conn_error) rcvd_new_cid_frames |> cid_registry.remote, recv_frame);
+        pipe!(@error(conn_error) rcvd_new_cid_frames |> *reset_tokens, recv_frame);
         pipe!(rcvd_max_data_frames |> flow_ctrl.sender, recv_frame);
         pipe!(rcvd_data_blocked_frames |> flow_ctrl.recver, recv_frame);
         pipe!(@error(conn_error) rcvd_handshake_done_frames |> *handshake, recv_frame);
         pipe!(@error(conn_error) rcvd_crypto_frames |> self.crypto_stream.incoming(), recv_frame);
-        pipe!(@error(conn_error) rcvd_stream_ctrl_frames |> *streams, recv_frame);
         // pipe!(@error(conn_error) rcvd_stream_frames |> receive_stream_frame);
         pipe!(@error(conn_error) rcvd_datagram_frames |> *datagrams, recv_frame);
         pipe!(rcvd_ack_frames |> on_data_acked);
-        pipe!(rcvd_new_token_frames |> recv_new_token,recv_frame);
+        pipe!(@error(conn_error) rcvd_new_token_frames |> token_registry, recv_frame);
 
         self.handle_stream_frame_with_flow_ctrl(
             reliable_frames,
@@ -157,6 +237,7 @@ impl DataScope {
             flow_ctrl,
             conn_error.clone(),
             rcvd_stream_frames,
+            rcvd_stream_ctrl_frames,
         );
 
         let join_handler0 = self.parse_rcvd_0rtt_packet_and_dispatch_frames(
@@ -165,33 +246,48 @@ impl DataScope {
             dispatch_data_frame.clone(),
             notify.clone(),
             conn_error.clone(),
+            frame_budget.clone(),
+            drops.clone(),
+            activity.clone(),
         );
         let join_handler1 = self.parse_rcvd_1rtt_packet_and_dispatch_frames(
             rcvd_1rtt_packets,
             pathes.clone(),
             handshake,
+            reliable_frames,
+            &token_registry,
+            tls_session,
+            address_discovery,
             dispatch_data_frame,
             notify.clone(),
             conn_error.clone(),
+            frame_budget.clone(),
+            drops.clone(),
+            activity.clone(),
         );
         (join_handler0, join_handler1)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn parse_rcvd_0rtt_packet_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
         pathes: ArcPathes,
-        dispatch_frame: impl Fn(Frame, Type, &RawPath) + Send + 'static,
+        dispatch_frame: impl Fn(Frame, Type, &RawPath, Pathway) + Send + Sync + 'static,
         notify: Arc<Notify>,
         conn_error: ConnError,
+        frame_budget: ArcFrameBudget,
+        drops: ArcDropCounters,
+        activity: ArcActivity,
     ) -> JoinHandle<RcvdPackets> {
-        tokio::spawn({
+        spawn_traced({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.zero_rtt_keys.clone();
             async move {
                 while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
+                    let pkt_size = packet.bytes.len();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
@@ -201,7 +297,14 @@ impl DataScope {
                         packet.offset,
                     ) {
                         Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
+                        Ok(None) => {
+                            drops.on_dropped(
+                                DropReason::Undecryptable,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                         Err(_e) => {
                             // conn_error.on_error(e);
                             break;
@@ -211,30 +314,42 @@ impl DataScope {
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
                         Ok(pn) => pn,
                         // TooOld/TooLarge/HasRcvd
-                        Err(_e) => continue,
+                        Err(_e) => {
+                            drops.on_dropped(
+                                DropReason::DuplicatePn,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                     };
                     let body_offset = packet.offset + undecoded_pn.size();
-                    let pkt_len = decrypt_packet(
+                    let Ok(pkt_len) = decrypt_packet(
                         keys.remote.packet.as_ref(),
                         pn,
                         packet.bytes.as_mut(),
                         body_offset,
-                    )
-                    .unwrap();
+                    ) else {
+                        drops.on_dropped(
+                            DropReason::Undecryptable,
+                            pathway.remote_addr(),
+                            &packet.bytes,
+                        );
+                        continue;
+                    };
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
 
                     let path = pathes.get_or_create(pathway, usc.clone());
-                    path.update_recv_time();
-
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
-                            let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, pty, &path);
-                            Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
+                    path.on_rcvd(pkt_size);
+                    activity.on_rcvd(pkt_len);
+
+                    let frames = FrameReader::new(packet.bytes.freeze(), pty);
+                    match dispatch_frames(frames, frame_budget.get(), |frame| {
+                        dispatch_frame(frame, pty, &path, pathway)
+                    })
+                    .await
+                    {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
                             path.cc.on_recv_pkt(Epoch::Data, pn, is_ack_packet);
@@ -247,23 +362,38 @@ impl DataScope {
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn parse_rcvd_1rtt_packet_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
         pathes: ArcPathes,
         handshake: &Handshake<ArcReliableFrameDeque>,
-        dispatch_frame: impl Fn(Frame, Type, &RawPath) + Send + 'static,
+        reliable_frames: &ArcReliableFrameDeque,
+        token_registry: &ArcTokenRegistry,
+        tls_session: &ArcTlsSession,
+        address_discovery: &ArcAddressDiscovery,
+        dispatch_frame: impl Fn(Frame, Type, &RawPath, Pathway) + Send + Sync + 'static,
         notify: Arc<Notify>,
         conn_error: ConnError,
+        frame_budget: ArcFrameBudget,
+        drops: ArcDropCounters,
+        activity: ArcActivity,
     ) -> JoinHandle<RcvdPackets> {
-        tokio::spawn({
+        spawn_traced({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.one_rtt_keys.clone();
             let handshake = handshake.clone();
+            let reliable_frames = reliable_frames.clone();
+            let token_registry = token_registry.clone();
+            let tls_session = tls_session.clone();
+            let address_discovery = address_discovery.clone();
             async move {
+                // 收到的最大包号的非探测包及其路径，服务端只随之迁移
+                let mut largest_non_probing: Option<(u64, Pathway)> = None;
                 while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
+                    let pkt_size = packet.bytes.len();
                     let Some((hpk, pk)) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
@@ -273,7 +403,14 @@ impl DataScope {
                         packet.offset,
                     ) {
                         Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
+                        Ok(None) => {
+                            drops.on_dropped(
+                                DropReason::Undecryptable,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                         Err(_e) => {
                             // conn_error.on_error(e);
                             break;
@@ -283,34 +420,87 @@ impl DataScope {
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
                         Ok(pn) => pn,
                         // TooOld/TooLarge/HasRcvd
-                        Err(_e) => continue,
+                        Err(_e) => {
+                            drops.on_dropped(
+                                DropReason::DuplicatePn,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                     };
                     let body_offset = packet.offset + undecoded_pn.size();
-                    let pk = pk.lock_guard().get_remote(key_phase, pn);
-                    let pkt_len =
-                        decrypt_packet(pk.as_ref(), pn, packet.bytes.as_mut(), body_offset)
-                            .unwrap();
+                    let remote_key = pk.lock_guard().get_remote(key_phase, pn);
+                    let Some(Ok(pkt_len)) = remote_key.map(|remote_key| {
+                        decrypt_packet(remote_key.as_ref(), pn, packet.bytes.as_mut(), body_offset)
+                    }) else {
+                        drops.on_dropped(
+                            DropReason::Undecryptable,
+                            pathway.remote_addr(),
+                            &packet.bytes,
+                        );
+                        continue;
+                    };
+                    // 解密成功才认可对端的密钥更新，随之更新本端的密钥
+                    if let Err(e) = pk.lock_guard().on_rcvd(key_phase, pn) {
+                        conn_error.on_error(e);
+                        break;
+                    }
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
                     if !handshake.is_handshake_done() {
                         handshake.done();
+                        // 服务端的握手至此确认，客户端则要等到HANDSHAKE_DONE帧；此后新建的路径在创建时即知晓
+                        if handshake.is_handshake_done() {
+                            for path in pathes.iter() {
+                                path.cc.on_handshake_done();
+                                path.cc.on_pkt_space_discarded(Epoch::Initial);
+                                path.cc.on_pkt_space_discarded(Epoch::Handshake);
+                            }
+                            // 颁发NEW_TOKEN令牌，客户端之后的连接携带之，即可免于地址验证
+                            let server_name = tls_session.server_name().unwrap_or_default();
+                            // 令牌绑定了客户端的地址，别的地址拿去用，不能免于地址验证
+                            let peer = pathway.remote_addr();
+                            reliable_frames
+                                .send_frame(token_registry.new_token_frames(&server_name, peer));
+                        }
                     }
                     let path = pathes.get_or_create(pathway, usc);
-                    path.update_recv_time();
-
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
-                            let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, pty, &path);
-                            Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
+                    path.on_rcvd(pkt_size);
+                    activity.on_rcvd(pkt_len);
+                    // 地址经过验证的路径，才向对端报告其地址
+                    if path.anti_amplifier.is_granted() {
+                        if let Some(frame) = address_discovery.on_path_rcvd(pathway, Instant::now())
+                        {
+                            path.observed_sndbuf().write(frame);
+                        }
+                    }
+
+                    let frames = FrameReader::new(packet.bytes.freeze(), pty);
+                    let mut is_probing = true;
+                    match dispatch_frames(frames, frame_budget.get(), |frame| {
+                        is_probing &= frame.is_probing();
+                        dispatch_frame(frame, pty, &path, pathway)
+                    })
+                    .await
+                    {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
                             path.cc.on_recv_pkt(Epoch::Data, pn, is_ack_packet);
                         }
-                        Err(e) => conn_error.on_error(e),
+                        Err(e) => {
+                            conn_error.on_error(e);
+                            continue;
+                        }
+                    }
+                    // 客户端从新的地址发来了非探测包，比如NAT重新绑定了端口，验证新路径之后迁移过去，
+                    // 在此之前仍由原来的路径承载数据，见RFC 9000 9.3节
+                    if !is_probing && largest_non_probing.is_none_or(|(largest, _)| pn > largest) {
+                        let last = largest_non_probing.replace((pn, pathway));
+                        let is_new = last.is_none_or(|(_, last)| last != pathway);
+                        if handshake.role() == Role::Server && is_new && path.is_standby() {
+                            pathes.migrate(pathway);
+                        }
                     }
                 }
                 rcvd_packets
@@ -321,13 +511,14 @@ impl DataScope {
     pub fn handle_stream_frame_with_flow_ctrl(
         &self,
         reliable_frames: &ArcReliableFrameDeque,
-        streams: &DataStreams,
+        streams: Option<&DataStreams>,
         flow_ctrl: &flow::FlowController,
         conn_error: ConnError,
         mut rcvd_stream_frames: mpsc::UnboundedReceiver<(StreamFrame, Bytes)>,
+        mut rcvd_stream_ctrl_frames: mpsc::UnboundedReceiver<StreamCtlFrame>,
     ) {
         // Sender Would Block
-        tokio::spawn({
+        spawn_traced({
             let flow_ctrl = flow_ctrl.clone();
             let reliable_frames = reliable_frames.clone();
             async move {
@@ -340,7 +531,7 @@ impl DataScope {
         });
 
         //  Recver Increasing Flow Control Limits
-        tokio::spawn({
+        spawn_traced({
             let flow_ctrl = flow_ctrl.clone();
             let reliable_frames = reliable_frames.clone();
             async move {
@@ -352,8 +543,28 @@ impl DataScope {
             }
         });
 
+        let Some(streams) = streams else {
+            // 没有流的连接，对方发来的流相关帧都是违规
+            spawn_traced({
+                let conn_error = conn_error.clone();
+                async move {
+                    while let Some((stream_frame, _)) = rcvd_stream_frames.next().await {
+                        conn_error.on_error(stream_frame_refused(&stream_frame, stream_frame.id));
+                    }
+                }
+            });
+            spawn_traced(async move {
+                while let Some(ctrl_frame) = rcvd_stream_ctrl_frames.next().await {
+                    if let Some(error) = stream_ctl_frame_refused(&ctrl_frame) {
+                        conn_error.on_error(error);
+                    }
+                }
+            });
+            return;
+        };
+
         // Handling Stream Frames
-        tokio::spawn({
+        spawn_traced({
             let streams = streams.clone();
             let flow_ctrl = flow_ctrl.clone();
             let conn_error = conn_error.clone();
@@ -374,22 +585,52 @@ impl DataScope {
                 }
             }
         });
+
+        // Handling Stream Control Frames
+        spawn_traced({
+            let streams = streams.clone();
+            let flow_ctrl = flow_ctrl.clone();
+            async move {
+                while let Some(ctrl_frame) = rcvd_stream_ctrl_frames.next().await {
+                    // RESET_STREAM的最终大小中从未收到的数据，对方已为其消耗了连接级的额度，同样计入
+                    match streams.recv_stream_control(&ctrl_frame) {
+                        Ok(new_data_size) => {
+                            if let Err(e) = flow_ctrl.recver().on_new_rcvd(new_data_size) {
+                                conn_error.on_error(QuicError::new(
+                                    ErrorKind::FlowControl,
+                                    ctrl_frame.frame_type(),
+                                    format!("final size flow control overflow: {e}"),
+                                ));
+                            }
+                        }
+                        Err(e) => conn_error.on_error(e),
+                    }
+                }
+            }
+        });
     }
 
     pub fn reader(
         &self,
         challenge_sndbuf: SendBuffer<PathChallengeFrame>,
         response_sndbuf: SendBuffer<PathResponseFrame>,
+        observed_sndbuf: SendBuffer<ObservedAddressFrame>,
         reliable_frames: ArcReliableFrameDeque,
-        streams: DataStreams,
+        streams: Option<DataStreams>,
         datagrams: DatagramFlow,
+        role: Role,
     ) -> DataSpaceReader {
         DataSpaceReader {
             space: self.space.clone(),
-            zero_rtt_keys: self.zero_rtt_keys.clone(),
+            // 服务端只解密0-RTT包，从不发送
+            zero_rtt_keys: match role {
+                Role::Client => self.zero_rtt_keys.clone(),
+                Role::Server => ArcKeys::new_pending(),
+            },
             one_rtt_keys: self.one_rtt_keys.clone(),
             challenge_sndbuf,
             response_sndbuf,
+            observed_sndbuf,
             crypto_stream_outgoing: self.crypto_stream.outgoing(),
             reliable_frames,
             streams,
@@ -400,18 +641,38 @@ impl DataScope {
     pub fn may_loss(
         &self,
         pn: u64,
-        data_streams: &DataStreams,
+        data_streams: Option<&DataStreams>,
         reliable_frames: &ArcReliableFrameDeque,
+        datagrams: &DatagramFlow,
     ) {
         for frame in self.space.sent_packets().receive().may_loss_pkt(pn) {
             match frame {
-                GuaranteedFrame::Stream(f) => data_streams.may_loss_data(&f),
+                GuaranteedFrame::Stream(f) => {
+                    if let Some(data_streams) = data_streams {
+                        data_streams.may_loss_data(&f)
+                    }
+                }
                 GuaranteedFrame::Reliable(f) => reliable_frames.lock_guard().push_back(f),
                 GuaranteedFrame::Crypto(f) => self.crypto_stream.outgoing().may_loss_data(&f),
+                // 数据报从不重传，只通知应用
+                GuaranteedFrame::Datagram(f) => datagrams.on_datagram_lost(f.seq),
             }
         }
     }
 
+    /// 服务端拒绝了0-RTT，已发送的0-RTT包都被丢弃了，都视作丢失，其中的数据改在1-RTT包中重传
+    pub fn resend_0rtt_data(
+        &self,
+        data_streams: Option<&DataStreams>,
+        reliable_frames: &ArcReliableFrameDeque,
+        datagrams: &DatagramFlow,
+    ) {
+        let largest_pn = self.space.sent_packets().receive().largest_pn();
+        for pn in 0..largest_pn {
+            self.may_loss(pn, data_streams, reliable_frames, datagrams);
+        }
+    }
+
     pub fn retire(&self, pn: u64) {
         self.space.rcvd_packets().write().retire(pn);
     }
@@ -422,7 +683,45 @@ pub struct ClosingOneRttScope {
     keys: (ArcHeaderProtectionKeys, ArcOneRttPacketKeys),
     rcvd_pkt_records: ArcRcvdPktRecords,
     // 发包时用得着
-    _next_sending_pn: (u64, PacketNumber),
+    next_sending_pn: (u64, PacketNumber),
+}
+
+impl ClosingOneRttScope {
+    /// Assemble a 1-RTT packet carrying only the CONNECTION_CLOSE frame.
+    ///
+    /// The packet is built once and resent as is, reusing the packet number is allowed
+    /// for the closing packet, see [RFC 9000 section 10.2.1](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.1).
+    pub fn close_packet(&self, ccf: &ConnectionCloseFrame, dcid: ConnectionId) -> Vec<u8> {
+        let hdr = OneRttHeader {
+            spin: SpinBit::default(),
+            dcid,
+        };
+        let (pn, encoded_pn) = self.next_sending_pn;
+        let (key_phase, pk) = self.keys.1.lock_guard().get_local();
+        let hdr_len = hdr.size();
+        let pn_len = encoded_pn.size();
+        let tag_len = pk.tag_len();
+        // payload(pn + body)至少20字节，为了保护包头的Sample至少16字节，不足的部分即是Padding帧
+        let body_len = ccf
+            .encoding_size()
+            .max(20usize.saturating_sub(pn_len + tag_len));
+
+        let mut buf = vec![0u8; hdr_len + pn_len + body_len + tag_len];
+        let (mut hdr_buf, payload_tag) = buf.split_at_mut(hdr_len);
+        let (mut pn_buf, mut body_buf) = payload_tag.split_at_mut(pn_len);
+        hdr_buf.put_one_rtt_header(&hdr);
+        pn_buf.put_packet_number(encoded_pn);
+        body_buf.put_frame(ccf);
+
+        encode_short_first_byte(&mut buf[0], pn_len, key_phase);
+        SealKeys::new(pk, self.keys.0.local.clone()).seal(&mut PacketBuf {
+            buf: &mut buf,
+            pn,
+            payload_offset: hdr_len,
+            pn_len,
+        });
+        buf
+    }
 }
 
 impl TryFrom<DataScope> for ClosingOneRttScope {
@@ -438,7 +737,7 @@ impl TryFrom<DataScope> for ClosingOneRttScope {
         Ok(Self {
             keys,
             rcvd_pkt_records,
-            _next_sending_pn: next_sending_pn,
+            next_sending_pn,
         })
     }
 }
@@ -460,7 +759,9 @@ impl super::RecvPacket for ClosingOneRttScope {
             Err(_e) => return false,
         };
         let body_offset = packet.offset + undecoded_pn.size();
-        let pk = self.keys.1.lock_guard().get_remote(key_phase, pn);
+        let Some(pk) = self.keys.1.lock_guard().get_remote(key_phase, pn) else {
+            return false;
+        };
         Self::decrypt_and_parse(pk.as_ref(), pn, packet, body_offset)
     }
 }
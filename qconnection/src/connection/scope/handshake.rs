@@ -2,13 +2,19 @@ use std::sync::Arc;
 
 use futures::{channel::mpsc, StreamExt};
 use qbase::{
-    frame::{AckFrame, Frame, FrameReader, ReceiveFrame},
+    cid::ConnectionId,
+    frame::{
+        io::WriteFrame, AckFrame, BeFrame, ConnectionCloseFrame, Frame, FrameReader, ReceiveFrame,
+    },
     packet::{
         decrypt::{decrypt_packet, remove_protection_of_long_packet},
-        header::GetType,
+        encrypt::{encode_long_first_byte, encrypt_packet, protect_header},
+        header::{GetType, WriteLongHeader},
         keys::ArcKeys,
-        DataPacket, PacketNumber,
+        DataPacket, Encode, LongHeaderBuilder, PacketNumber, WritePacketNumber,
     },
+    util::spawn_traced,
+    varint::{EncodeBytes, VarInt, WriteVarInt},
 };
 use qcongestion::CongestionControl;
 use qrecovery::{
@@ -18,9 +24,10 @@ use qrecovery::{
 };
 use tokio::{sync::Notify, task::JoinHandle};
 
-use super::any;
+use super::{any, dispatch_frames, AckEntry, ArcFrameBudget};
 use crate::{
-    connection::{transmit::handshake::HandshakeSpaceReader, RcvdPackets},
+    connection::{activity::ArcActivity, transmit::handshake::HandshakeSpaceReader, RcvdPackets},
+    drops::{ArcDropCounters, DropReason},
     error::ConnError,
     path::{ArcPathes, RawPath},
     pipe,
@@ -44,23 +51,31 @@ impl Default for HandshakeScope {
 }
 
 impl HandshakeScope {
+    #[allow(clippy::too_many_arguments)]
     pub fn build(
         &self,
         rcvd_packets: RcvdPackets,
         pathes: &ArcPathes,
         notify: &Arc<Notify>,
         conn_error: &ConnError,
+        frame_budget: &ArcFrameBudget,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
     ) -> JoinHandle<RcvdPackets> {
         let (crypto_frames_entry, rcvd_crypto_frames) = mpsc::unbounded();
         let (ack_frames_entry, rcvd_ack_frames) = mpsc::unbounded();
 
+        let ack_entry = AckEntry::new(Epoch::Handshake, self.space.sent_packets());
         let dispatch_frame = {
             let conn_error = conn_error.clone();
             move |frame: Frame, path: &RawPath| match frame {
-                Frame::Ack(f) => {
-                    path.cc.on_ack(Epoch::Initial, &f);
-                    _ = ack_frames_entry.unbounded_send(f);
-                }
+                Frame::Ack(f) => match ack_entry.check(&f) {
+                    Ok(epoch) => {
+                        path.cc.on_ack(epoch, &f);
+                        _ = ack_frames_entry.unbounded_send(f);
+                    }
+                    Err(e) => conn_error.on_error(e),
+                },
                 Frame::Close(f) => conn_error.on_ccf_rcvd(&f),
                 Frame::Crypto(f, bytes) => _ = crypto_frames_entry.unbounded_send((f, bytes)),
                 Frame::Padding(_) | Frame::Ping(_) => {}
@@ -90,27 +105,38 @@ impl HandshakeScope {
             dispatch_frame,
             notify,
             conn_error,
+            frame_budget,
+            drops,
+            activity,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn parse_rcvd_packets_and_dispatch_frames(
         &self,
         mut rcvd_packets: RcvdPackets,
         pathes: &ArcPathes,
-        dispatch_frame: impl Fn(Frame, &RawPath) + Send + 'static,
+        dispatch_frame: impl Fn(Frame, &RawPath) + Send + Sync + 'static,
         notify: &Arc<Notify>,
         conn_error: &ConnError,
+        frame_budget: &ArcFrameBudget,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
     ) -> JoinHandle<RcvdPackets> {
         let pathes = pathes.clone();
         let conn_error = conn_error.clone();
         let notify = notify.clone();
-        tokio::spawn({
+        let frame_budget = frame_budget.clone();
+        let drops = drops.clone();
+        let activity = activity.clone();
+        spawn_traced({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.keys.clone();
             async move {
                 while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
+                    let pkt_size = packet.bytes.len();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
@@ -120,7 +146,14 @@ impl HandshakeScope {
                         packet.offset,
                     ) {
                         Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
+                        Ok(None) => {
+                            drops.on_dropped(
+                                DropReason::Undecryptable,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                         Err(_e) => {
                             // conn_error.on_error(e);
                             break;
@@ -130,21 +163,35 @@ impl HandshakeScope {
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
                         Ok(pn) => pn,
                         // TooOld/TooLarge/HasRcvd
-                        Err(_e) => continue,
+                        Err(_e) => {
+                            drops.on_dropped(
+                                DropReason::DuplicatePn,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                     };
                     let body_offset = packet.offset + undecoded_pn.size();
-                    let pkt_len = decrypt_packet(
+                    let Ok(pkt_len) = decrypt_packet(
                         keys.remote.packet.as_ref(),
                         pn,
                         packet.bytes.as_mut(),
                         body_offset,
-                    )
-                    .unwrap();
+                    ) else {
+                        drops.on_dropped(
+                            DropReason::Undecryptable,
+                            pathway.remote_addr(),
+                            &packet.bytes,
+                        );
+                        continue;
+                    };
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
 
                     let path = pathes.get_or_create(pathway, usc);
-                    path.update_recv_time();
+                    path.on_rcvd(pkt_size);
+                    activity.on_rcvd(pkt_len);
 
                     // See [RFC 9000 section 8.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-address-validation-during-c)
                     // Once an endpoint has successfully processed a Handshake packet from the peer, it can consider the peer
@@ -152,17 +199,18 @@ impl HandshakeScope {
                     // It may have already been verified using tokens in the Initial space
                     path.anti_amplifier.grant();
 
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
-                            let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, &path);
-                            Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
+                    let frames = FrameReader::new(packet.bytes.freeze(), pty);
+                    match dispatch_frames(frames, frame_budget.get(), |frame| {
+                        dispatch_frame(frame, &path)
+                    })
+                    .await
+                    {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
                             path.cc.on_recv_pkt(Epoch::Handshake, pn, is_ack_packet);
+                            // See [RFC 9001 section 4.9.1](https://www.rfc-editor.org/rfc/rfc9001.html#name-discarding-initial-keys)
+                            // 成功处理了握手包，Initial空间的包不再追踪；客户端随即就会发出握手包
+                            path.cc.on_pkt_space_discarded(Epoch::Initial);
                         }
                         Err(e) => conn_error.on_error(e),
                     }
@@ -196,7 +244,52 @@ pub struct ClosingHandshakeScope {
     keys: Arc<rustls::quic::Keys>,
     rcvd_pkt_records: ArcRcvdPktRecords,
     // 发包时用得着
-    _next_sending_pn: (u64, PacketNumber),
+    next_sending_pn: (u64, PacketNumber),
+}
+
+impl ClosingHandshakeScope {
+    /// Assemble a Handshake packet carrying only the CONNECTION_CLOSE frame, which is
+    /// built once and resent as is, see [`ClosingOneRttScope::close_packet`].
+    ///
+    /// [`ClosingOneRttScope::close_packet`]: super::ClosingOneRttScope::close_packet
+    pub fn close_packet(
+        &self,
+        ccf: &ConnectionCloseFrame,
+        scid: ConnectionId,
+        dcid: ConnectionId,
+    ) -> Vec<u8> {
+        let hdr = LongHeaderBuilder::with_cid(dcid, scid).handshake();
+        let (pn, encoded_pn) = self.next_sending_pn;
+        // length字段固定2字节
+        let hdr_len = hdr.size() + 2;
+        let pn_len = encoded_pn.size();
+        let tag_len = self.keys.local.packet.tag_len();
+        // payload(pn + body)至少20字节，为了保护包头的Sample至少16字节，不足的部分即是Padding帧
+        let body_len = ccf
+            .encoding_size()
+            .max(20usize.saturating_sub(pn_len + tag_len));
+
+        let mut buf = vec![0u8; hdr_len + pn_len + body_len + tag_len];
+        let (mut hdr_buf, payload_tag) = buf.split_at_mut(hdr_len);
+        let (mut pn_buf, mut body_buf) = payload_tag.split_at_mut(pn_len);
+        hdr_buf.put_long_header(&hdr);
+        hdr_buf.encode_varint(
+            &VarInt::try_from(pn_len + body_len + tag_len).unwrap(),
+            EncodeBytes::Two,
+        );
+        pn_buf.put_packet_number(encoded_pn);
+        body_buf.put_frame(ccf);
+
+        encode_long_first_byte(&mut buf[0], pn_len);
+        encrypt_packet(
+            self.keys.local.packet.as_ref(),
+            pn,
+            &mut buf,
+            hdr_len + pn_len,
+        );
+        protect_header(self.keys.local.header.as_ref(), &mut buf, hdr_len, pn_len);
+        buf
+    }
 }
 
 impl TryFrom<HandshakeScope> for ClosingHandshakeScope {
@@ -212,7 +305,7 @@ impl TryFrom<HandshakeScope> for ClosingHandshakeScope {
         Ok(Self {
             keys,
             rcvd_pkt_records,
-            _next_sending_pn: next_sending_pn,
+            next_sending_pn,
         })
     }
 }
@@ -237,3 +330,104 @@ impl super::RecvPacket for ClosingHandshakeScope {
         Self::decrypt_and_parse(self.keys.remote.packet.as_ref(), pn, packet, body_offset)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use qbase::{
+        error::ErrorKind,
+        packet::{Packet, PacketReader},
+    };
+    use rustls::{quic::Keys, CipherSuite, Side};
+
+    use qudp::ArcUsc;
+
+    use super::*;
+    use crate::{connection::scope::RecvPacket, path::Pathway};
+
+    fn handshake_scope(side: Side) -> HandshakeScope {
+        let suite = rustls::crypto::ring::default_provider()
+            .cipher_suites
+            .iter()
+            .find_map(|cs| match (cs.suite(), cs.tls13()) {
+                (CipherSuite::TLS13_AES_128_GCM_SHA256, Some(suite)) => suite.quic_suite(),
+                _ => None,
+            })
+            .unwrap();
+        let keys: Keys = suite.keys(&[0x83, 0x94, 0xc8, 0xf0], side, rustls::quic::Version::V1);
+        let hs = HandshakeScope::default();
+        hs.keys.set_keys(keys);
+        hs
+    }
+
+    fn closing_scope(side: Side) -> ClosingHandshakeScope {
+        ClosingHandshakeScope::try_from(handshake_scope(side)).unwrap()
+    }
+
+    #[test]
+    fn test_close_packet() {
+        let ccf = ConnectionCloseFrame::new(ErrorKind::Internal, None, "bye".into());
+        let (scid, dcid) = (ConnectionId::random_gen(8), ConnectionId::random_gen(8));
+        let close_packet = closing_scope(Side::Server).close_packet(&ccf, scid, dcid);
+
+        let mut reader = PacketReader::new(BytesMut::from(&close_packet[..]), 8);
+        let Some(Ok(Packet::Data(packet))) = reader.next() else {
+            panic!("close packet should be parsed as a data packet");
+        };
+        assert!(reader.next().is_none());
+        assert!(closing_scope(Side::Client).has_rcvd_ccf(packet));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_packets() {
+        let hs = handshake_scope(Side::Client);
+        let (packets_entry, rcvd_packets) = mpsc::unbounded();
+        let pathes = ArcPathes::new(Box::new(|_, _| unreachable!("no packet is accepted")));
+        let drops = ArcDropCounters::default();
+        let _join = hs.build(
+            rcvd_packets,
+            &pathes,
+            &Arc::new(Notify::new()),
+            &ConnError::default(),
+            &ArcFrameBudget::default(),
+            &drops,
+            &ArcActivity::default(),
+        );
+
+        let ccf = ConnectionCloseFrame::new(ErrorKind::Internal, None, "bye".into());
+        let (scid, dcid) = (ConnectionId::random_gen(8), ConnectionId::random_gen(8));
+        let close_packet = closing_scope(Side::Server).close_packet(&ccf, scid, dcid);
+        let parse = |bytes: &[u8]| match PacketReader::new(BytesMut::from(bytes), 8).next() {
+            Some(Ok(Packet::Data(packet))) => packet,
+            _ => panic!("close packet should be parsed as a data packet"),
+        };
+        let usc = ArcUsc::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let pathway = Pathway::direct(
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+        );
+
+        // 篡改密文，无法解密
+        let mut tampered = close_packet.clone();
+        *tampered.last_mut().unwrap() ^= 0xff;
+        _ = packets_entry.unbounded_send((parse(&tampered), pathway, usc.clone()));
+        while drops.stats().total() < 1 {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+
+        // 包号0已经收到过
+        hs.space.rcvd_packets().register_pn(0);
+        _ = packets_entry.unbounded_send((parse(&close_packet), pathway, usc));
+        while drops.stats().total() < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        let stats = drops.stats();
+        assert_eq!(stats.get(DropReason::DuplicatePn), 1);
+        assert_eq!(stats.get(DropReason::Undecryptable), 1);
+        assert!(crate::drops::DROPS.recent().iter().any(|event| {
+            event.reason == DropReason::Undecryptable
+                && event.source == Some(pathway.remote_addr())
+                && event.header.len() == crate::drops::DROP_HEADER_LEN
+        }));
+    }
+}
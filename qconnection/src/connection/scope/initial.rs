@@ -7,8 +7,10 @@ use qbase::{
         decrypt::{decrypt_packet, remove_protection_of_long_packet},
         header::{GetScid, GetType},
         keys::ArcKeys,
-        long, DataHeader,
+        long, DataHeader, InitialHeader,
     },
+    util::spawn_traced,
+    version::Versions,
 };
 use qcongestion::CongestionControl;
 use qrecovery::{
@@ -17,11 +19,14 @@ use qrecovery::{
 };
 use tokio::{sync::Notify, task::JoinHandle};
 
-use super::any;
+use super::{any, dispatch_frames, AckEntry, ArcFrameBudget};
 use crate::{
-    connection::{transmit::initial::InitialSpaceReader, ArcRemoteCids, RcvdPackets},
+    connection::{
+        activity::ArcActivity, transmit::initial::InitialSpaceReader, ArcRemoteCids, RcvdPackets,
+    },
+    drops::{ArcDropCounters, DropReason},
     error::ConnError,
-    path::{ArcPath, ArcPathes, RawPath},
+    path::{pathway::Pathway, ArcPath, ArcPathes, RawPath},
     pipe,
 };
 
@@ -45,6 +50,7 @@ impl InitialScope {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn build(
         &self,
         rcvd_packets: RcvdPackets,
@@ -52,17 +58,25 @@ impl InitialScope {
         remote_cids: &ArcRemoteCids,
         notify: &Arc<Notify>,
         conn_error: &ConnError,
-        validate: impl Fn(&[u8], ArcPath) + Send + 'static,
+        frame_budget: &ArcFrameBudget,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
+        validate: impl Fn(&InitialHeader, usize, &Pathway, ArcPath) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
         let (crypto_frames_entry, rcvd_crypto_frames) = mpsc::unbounded();
         let (ack_frames_entry, rcvd_ack_frames) = mpsc::unbounded();
 
-        let dispatch_frame = move |frame: Frame, path: &RawPath| {
-            match frame {
-                Frame::Ack(f) => {
-                    path.cc.on_ack(Epoch::Initial, &f);
-                    _ = ack_frames_entry.unbounded_send(f)
-                }
+        let ack_entry = AckEntry::new(Epoch::Initial, self.space.sent_packets());
+        let dispatch_frame = {
+            let conn_error = conn_error.clone();
+            move |frame: Frame, path: &RawPath| match frame {
+                Frame::Ack(f) => match ack_entry.check(&f) {
+                    Ok(epoch) => {
+                        path.cc.on_ack(epoch, &f);
+                        _ = ack_frames_entry.unbounded_send(f)
+                    }
+                    Err(e) => conn_error.on_error(e),
+                },
                 Frame::Crypto(f, bytes) => _ = crypto_frames_entry.unbounded_send((f, bytes)),
                 Frame::Close(_) => { /* trustless */ }
                 Frame::Padding(_) | Frame::Ping(_) => {}
@@ -94,6 +108,9 @@ impl InitialScope {
             dispatch_frame,
             notify,
             conn_error,
+            frame_budget,
+            drops,
+            activity,
             validate,
         )
     }
@@ -104,14 +121,20 @@ impl InitialScope {
         mut rcvd_packets: RcvdPackets,
         pathes: &ArcPathes,
         remote_cids: &ArcRemoteCids,
-        dispatch_frame: impl Fn(Frame, &RawPath) + Send + 'static,
+        dispatch_frame: impl Fn(Frame, &RawPath) + Send + Sync + 'static,
         notify: &Arc<Notify>,
         conn_error: &ConnError,
-        validate: impl Fn(&[u8], ArcPath) + Send + 'static,
+        frame_budget: &ArcFrameBudget,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
+        validate: impl Fn(&InitialHeader, usize, &Pathway, ArcPath) + Send + 'static,
     ) -> JoinHandle<RcvdPackets> {
         let pathes = pathes.clone();
         let conn_error = conn_error.clone();
-        tokio::spawn({
+        let frame_budget = frame_budget.clone();
+        let drops = drops.clone();
+        let activity = activity.clone();
+        spawn_traced({
             let rcvd_pkt_records = self.space.rcvd_packets();
             let keys = self.keys.clone();
             let remote_cids = remote_cids.clone();
@@ -121,6 +144,7 @@ impl InitialScope {
                 while let Some((mut packet, pathway, usc)) = any(rcvd_packets.next(), &notify).await
                 {
                     let pty = packet.header.get_type();
+                    let pkt_size = packet.bytes.len();
                     let Some(keys) = any(keys.get_remote_keys(), &notify).await else {
                         break;
                     };
@@ -130,7 +154,14 @@ impl InitialScope {
                         packet.offset,
                     ) {
                         Ok(Some(pn)) => pn,
-                        Ok(None) => continue,
+                        Ok(None) => {
+                            drops.on_dropped(
+                                DropReason::Undecryptable,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                         Err(_e) => {
                             // conn_error.on_error(e);
                             break;
@@ -140,21 +171,35 @@ impl InitialScope {
                     let pn = match rcvd_pkt_records.decode_pn(undecoded_pn) {
                         Ok(pn) => pn,
                         // TooOld/TooLarge/HasRcvd
-                        Err(_e) => continue,
+                        Err(_e) => {
+                            drops.on_dropped(
+                                DropReason::DuplicatePn,
+                                pathway.remote_addr(),
+                                &packet.bytes,
+                            );
+                            continue;
+                        }
                     };
                     let body_offset = packet.offset + undecoded_pn.size();
-                    let pkt_len = decrypt_packet(
+                    let Ok(pkt_len) = decrypt_packet(
                         keys.remote.packet.as_ref(),
                         pn,
                         packet.bytes.as_mut(),
                         body_offset,
-                    )
-                    .unwrap();
+                    ) else {
+                        drops.on_dropped(
+                            DropReason::Undecryptable,
+                            pathway.remote_addr(),
+                            &packet.bytes,
+                        );
+                        continue;
+                    };
                     let _header = packet.bytes.split_to(body_offset);
                     packet.bytes.truncate(pkt_len);
 
                     let path = pathes.get_or_create(pathway, usc);
-                    path.update_recv_time();
+                    path.on_rcvd(pkt_size);
+                    activity.on_rcvd(pkt_len);
 
                     let remote_scid = match packet.header {
                         DataHeader::Long(ref long_header) => long_header.get_scid(),
@@ -164,14 +209,12 @@ impl InitialScope {
                     // path to the SCID carried in the received packet.
                     remote_cids.revise_initial_dcid(*remote_scid);
 
-                    match FrameReader::new(packet.bytes.freeze(), pty).try_fold(
-                        false,
-                        |is_ack_packet, frame| {
-                            let (frame, is_ack_eliciting) = frame?;
-                            dispatch_frame(frame, &path);
-                            Ok(is_ack_packet || is_ack_eliciting)
-                        },
-                    ) {
+                    let frames = FrameReader::new(packet.bytes.freeze(), pty);
+                    match dispatch_frames(frames, frame_budget.get(), |frame| {
+                        dispatch_frame(frame, &path)
+                    })
+                    .await
+                    {
                         Ok(is_ack_packet) => {
                             rcvd_pkt_records.register_pn(pn);
                             path.cc.on_recv_pkt(Epoch::Initial, pn, is_ack_packet);
@@ -187,9 +230,7 @@ impl InitialScope {
                     // This token is delivered to the client during connection establishment with a Retry packet (see Section 8.1.2)
                     // or in a previous connection using the NEW_TOKEN frame (see Section 8.1.3).
                     if let DataHeader::Long(long::DataHeader::Initial(initial)) = &packet.header {
-                        if !initial.token.is_empty() {
-                            validate(&initial.token, path);
-                        }
+                        validate(initial, pkt_size, &pathway, path);
                     }
                 }
                 rcvd_packets
@@ -197,9 +238,14 @@ impl InitialScope {
         })
     }
 
-    pub fn reader(&self, token: Arc<Mutex<Vec<u8>>>) -> InitialSpaceReader {
+    pub fn reader(
+        &self,
+        token: Arc<Mutex<Vec<u8>>>,
+        versions: Arc<Mutex<Versions>>,
+    ) -> InitialSpaceReader {
         InitialSpaceReader {
             token,
+            versions,
             keys: self.keys.clone(),
             space: self.space.clone(),
             crypto_stream_outgoing: self.crypto_stream.outgoing(),
@@ -0,0 +1,190 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use qbase::util::spawn_traced;
+use qrecovery::streams::data::FlowControlStallSuspected;
+use tokio::{
+    sync::{watch, Notify},
+    time::Instant,
+};
+
+use super::DataStreams;
+
+/// How to detect the flow control deadlocks of a connection, see
+/// [`ArcConnection::set_stall_detection`].
+///
+/// [`ArcConnection::set_stall_detection`]: crate::connection::ArcConnection::set_stall_detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallDetection {
+    /// How long a stream is blocked on the peer's window before it counts, see
+    /// [`FlowControlStallSuspected::blocked`].
+    pub threshold: Duration,
+    /// The least interval between two [`FlowControlStallSuspected`] events of a connection.
+    pub min_interval: Duration,
+}
+
+impl Default for StallDetection {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_secs(3),
+            min_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RawStallDetector {
+    config: Option<StallDetection>,
+    last_event: Option<Instant>,
+    suspected: u64,
+    is_stopped: bool,
+}
+
+/// Check the streams periodically for a flow control deadlock, see
+/// [`FlowControlStallSuspected`]. The events are rate-limited per connection, logged, and
+/// published to the watchers.
+///
+/// It's only a heuristic, a peer that stops reading looks the same from one side.
+#[derive(Debug, Clone)]
+pub struct ArcStallDetector {
+    raw: Arc<Mutex<RawStallDetector>>,
+    suspected: Arc<watch::Sender<Option<FlowControlStallSuspected>>>,
+    reconfigured: Arc<Notify>,
+}
+
+impl Default for ArcStallDetector {
+    fn default() -> Self {
+        let (suspected, _) = watch::channel(None);
+        Self {
+            raw: Arc::new(Mutex::new(RawStallDetector {
+                config: Some(StallDetection::default()),
+                last_event: None,
+                suspected: 0,
+                is_stopped: false,
+            })),
+            suspected: Arc::new(suspected),
+            reconfigured: Arc::default(),
+        }
+    }
+}
+
+impl ArcStallDetector {
+    /// Start checking the streams, until the connection is closing or draining.
+    pub fn launch(&self, streams: &DataStreams) {
+        spawn_traced(self.clone().watch(streams.clone()));
+    }
+
+    /// Set how to detect the stalls, None disables it.
+    pub fn set_config(&self, config: Option<StallDetection>) {
+        let mut raw = self.raw.lock().unwrap();
+        if raw.is_stopped {
+            return;
+        }
+        raw.config = config;
+        self.reconfigured.notify_one();
+    }
+
+    /// The number of the [`FlowControlStallSuspected`] events in total.
+    pub fn suspected(&self) -> u64 {
+        self.raw.lock().unwrap().suspected
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<FlowControlStallSuspected>> {
+        self.suspected.subscribe()
+    }
+
+    /// Stop checking once the connection is closing or draining.
+    pub fn on_conn_error(&self) {
+        self.raw.lock().unwrap().is_stopped = true;
+        self.reconfigured.notify_one();
+    }
+
+    /// Record a suspected stall, unless an event was emitted within the `min_interval`.
+    fn on_suspected(&self, stall: FlowControlStallSuspected) -> bool {
+        let mut raw = self.raw.lock().unwrap();
+        let Some(config) = raw.config else {
+            return false;
+        };
+        let now = Instant::now();
+        if raw
+            .last_event
+            .is_some_and(|last| now.duration_since(last) < config.min_interval)
+        {
+            return false;
+        }
+        raw.last_event = Some(now);
+        raw.suspected += 1;
+        drop(raw);
+
+        tracing::warn!(
+            blocked = ?stall.blocked,
+            full = ?stall.full,
+            "flow control stall suspected"
+        );
+        self.suspected.send_replace(Some(stall));
+        true
+    }
+
+    async fn watch(self, streams: DataStreams) {
+        loop {
+            let config = {
+                let raw = self.raw.lock().unwrap();
+                if raw.is_stopped {
+                    return;
+                }
+                raw.config
+            };
+            let Some(config) = config else {
+                self.reconfigured.notified().await;
+                continue;
+            };
+            // 流受限超过阈值后，至多再过半个阈值就能发现
+            tokio::select! {
+                _ = tokio::time::sleep(config.threshold / 2) => {
+                    if let Some(stall) = streams.flow_control_stall(config.threshold) {
+                        self.on_suspected(stall);
+                    }
+                }
+                _ = self.reconfigured.notified() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stall_rate_limited() {
+        let detector = ArcStallDetector::default();
+        detector.set_config(Some(StallDetection {
+            threshold: Duration::from_millis(10),
+            min_interval: Duration::from_millis(50),
+        }));
+        let mut suspected = detector.subscribe();
+        let stall = FlowControlStallSuspected {
+            blocked: vec![0],
+            full: vec![1],
+        };
+
+        assert!(detector.on_suspected(stall.clone()));
+        assert!(suspected.has_changed().unwrap());
+        assert_eq!(*suspected.borrow_and_update(), Some(stall.clone()));
+        // 间隔之内的再次怀疑被抑制
+        assert!(!detector.on_suspected(stall.clone()));
+        assert!(!suspected.has_changed().unwrap());
+        assert_eq!(detector.suspected(), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(detector.on_suspected(stall.clone()));
+        assert_eq!(detector.suspected(), 2);
+
+        detector.set_config(None);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!detector.on_suspected(stall));
+        assert_eq!(detector.suspected(), 2);
+    }
+}
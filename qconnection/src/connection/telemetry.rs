@@ -0,0 +1,429 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use dashmap::DashMap;
+
+/// The longest prefix of an IPv4 address kept, the longer [`PrefixLengths::ipv4`] is cut to
+/// it, so that a full address never persists.
+pub const MAX_IPV4_PREFIX_LEN: u8 = 24;
+
+/// The longest prefix of an IPv6 address kept, see [`MAX_IPV4_PREFIX_LEN`].
+pub const MAX_IPV6_PREFIX_LEN: u8 = 64;
+
+/// How many leading bits of the remote addresses identify a network, see [`NetworkPrefix`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixLengths {
+    /// At most [`MAX_IPV4_PREFIX_LEN`], 24 by default.
+    pub ipv4: u8,
+    /// At most [`MAX_IPV6_PREFIX_LEN`], 48 by default.
+    pub ipv6: u8,
+}
+
+impl Default for PrefixLengths {
+    fn default() -> Self {
+        Self { ipv4: 24, ipv6: 48 }
+    }
+}
+
+/// The network a remote address belongs to, such as `192.0.2.0/24`.
+///
+/// It can only be made by [`NetworkPrefix::of`], which drops the trailing bits of the address
+/// beyond [`MAX_IPV4_PREFIX_LEN`] or [`MAX_IPV6_PREFIX_LEN`] at least, so the full address of
+/// a peer can't be kept in the telemetry however it's configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetworkPrefix {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl NetworkPrefix {
+    /// The network of `ip`, the IPv4-mapped IPv6 addresses are taken as IPv4 ones.
+    pub fn of(ip: IpAddr, lengths: PrefixLengths) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let prefix_len = lengths.ipv4.min(MAX_IPV4_PREFIX_LEN);
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                Self {
+                    network: IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)),
+                    prefix_len,
+                }
+            }
+            IpAddr::V6(ip) => {
+                let prefix_len = lengths.ipv6.min(MAX_IPV6_PREFIX_LEN);
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                Self {
+                    network: IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)),
+                    prefix_len,
+                }
+            }
+        }
+    }
+
+    /// The address of the network, with the bits beyond the prefix cleared.
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+}
+
+impl fmt::Display for NetworkPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// 每个2倍区间分为8个桶，覆盖1微秒到约268秒
+const SUB_BUCKETS: u32 = 8;
+const OCTAVES: u32 = 28;
+const BUCKETS: usize = (SUB_BUCKETS * OCTAVES) as usize;
+
+/// A histogram of the RTTs in logarithmic buckets, small and mergeable.
+///
+/// Each doubling is split into 8 buckets, so a quantile is within 4.5% of the true one,
+/// except for the RTTs below 1µs or beyond 268s, which fall in the first or the last bucket.
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct RttHistogram {
+    // 首次记录时才分配
+    counts: Vec<u64>,
+    count: u64,
+}
+
+impl RttHistogram {
+    fn bucket_of(rtt: Duration) -> usize {
+        let micros = rtt.as_micros().max(1) as f64;
+        ((micros.log2() * SUB_BUCKETS as f64) as usize).min(BUCKETS - 1)
+    }
+
+    // 桶的几何中点，与桶内任何值的比值不超过2^(1/16)
+    fn midpoint_of(bucket: usize) -> Duration {
+        let micros = 2f64.powf((bucket as f64 + 0.5) / SUB_BUCKETS as f64);
+        Duration::from_secs_f64(micros / 1e6)
+    }
+
+    pub fn record(&mut self, rtt: Duration) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS];
+        }
+        self.counts[Self::bucket_of(rtt)] += 1;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &RttHistogram) {
+        if other.count == 0 {
+            return;
+        }
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS];
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+    }
+
+    /// The number of the RTTs recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The `q`-quantile of the RTTs, `q` in `[0, 1]`, None if nothing is recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(Self::midpoint_of(bucket))
+    }
+
+    pub fn median(&self) -> Option<Duration> {
+        self.quantile(0.5)
+    }
+}
+
+impl fmt::Debug for RttHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RttHistogram")
+            .field("count", &self.count)
+            .field("median", &self.median())
+            .finish()
+    }
+}
+
+/// What a connection reports to the [`NetworkTelemetry`] when it's closed, see
+/// [`ArcConnection::set_network_telemetry`].
+///
+/// [`ArcConnection::set_network_telemetry`]: crate::connection::ArcConnection::set_network_telemetry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionDigest {
+    /// The network of the peer, the only trace of its address.
+    pub prefix: NetworkPrefix,
+    /// How long the connection lasted until it was closed.
+    pub duration: Duration,
+    /// The bytes of the packets sent on all the paths.
+    pub bytes_sent: u64,
+    /// The payload bytes of the packets received.
+    pub bytes_rcvd: u64,
+    /// The smoothed RTT of the path used most recently, None if there is no RTT sample.
+    pub smoothed_rtt: Option<Duration>,
+    pub packets_sent: u64,
+    pub packets_lost: u64,
+    /// Whether the handshake was completed.
+    pub handshake_done: bool,
+}
+
+/// The statistics of the connections from a network, aggregated by [`NetworkTelemetry`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub connections: u64,
+    /// The connections that completed the handshake.
+    pub handshakes: u64,
+    /// The total time the connections lasted.
+    pub duration: Duration,
+    pub bytes_sent: u64,
+    pub bytes_rcvd: u64,
+    pub packets_sent: u64,
+    pub packets_lost: u64,
+    /// The smoothed RTTs of the connections, the ones without an RTT sample are left out.
+    pub rtt: RttHistogram,
+}
+
+impl NetworkStats {
+    fn add(&mut self, digest: &ConnectionDigest) {
+        self.connections += 1;
+        self.handshakes += digest.handshake_done as u64;
+        self.duration += digest.duration;
+        self.bytes_sent += digest.bytes_sent;
+        self.bytes_rcvd += digest.bytes_rcvd;
+        self.packets_sent += digest.packets_sent;
+        self.packets_lost += digest.packets_lost;
+        if let Some(rtt) = digest.smoothed_rtt {
+            self.rtt.record(rtt);
+        }
+    }
+
+    /// Merge the statistics of another network, such as the ones reported by another
+    /// endpoint.
+    pub fn merge(&mut self, other: &NetworkStats) {
+        self.connections += other.connections;
+        self.handshakes += other.handshakes;
+        self.duration += other.duration;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_rcvd += other.bytes_rcvd;
+        self.packets_sent += other.packets_sent;
+        self.packets_lost += other.packets_lost;
+        self.rtt.merge(&other.rtt);
+    }
+
+    /// The packets lost out of the ones sent, 0 if nothing is sent.
+    pub fn loss_ratio(&self) -> f64 {
+        if self.packets_sent == 0 {
+            return 0.0;
+        }
+        self.packets_lost as f64 / self.packets_sent as f64
+    }
+}
+
+/// The statistics by network, taken by [`NetworkTelemetry::take_report`].
+pub type NetworkReport = HashMap<NetworkPrefix, NetworkStats>;
+
+/// Aggregate the [`ConnectionDigest`]s of the connections of an endpoint by the network of
+/// their peers, for the fleet-wide telemetry without shipping the statistics of every
+/// connection.
+///
+/// The digests are folded into the statistics of their network on arrival, the networks
+/// are sharded so that the connections closing at the same time rarely contend.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkTelemetry {
+    lengths: PrefixLengths,
+    networks: Arc<DashMap<NetworkPrefix, NetworkStats>>,
+}
+
+impl NetworkTelemetry {
+    pub fn new(lengths: PrefixLengths) -> Self {
+        Self {
+            lengths,
+            networks: Arc::default(),
+        }
+    }
+
+    pub fn prefix_lengths(&self) -> PrefixLengths {
+        self.lengths
+    }
+
+    /// The network of `addr`, see [`NetworkPrefix::of`].
+    pub fn prefix_of(&self, addr: SocketAddr) -> NetworkPrefix {
+        NetworkPrefix::of(addr.ip(), self.lengths)
+    }
+
+    pub fn report(&self, digest: &ConnectionDigest) {
+        self.networks.entry(digest.prefix).or_default().add(digest);
+    }
+
+    /// Take the statistics aggregated since the last report, and start over.
+    ///
+    /// The digests reported meanwhile go to either this report or the next one, never lost.
+    pub fn take_report(&self) -> NetworkReport {
+        let prefixes = self
+            .networks
+            .iter()
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        prefixes
+            .into_iter()
+            .filter_map(|prefix| self.networks.remove(&prefix))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_prefix() {
+        let lengths = PrefixLengths::default();
+        let prefix = NetworkPrefix::of("192.0.2.77".parse().unwrap(), lengths);
+        assert_eq!(prefix.to_string(), "192.0.2.0/24");
+        let mapped = NetworkPrefix::of("::ffff:192.0.2.1".parse().unwrap(), lengths);
+        assert_eq!(mapped, prefix);
+
+        let prefix = NetworkPrefix::of("2001:db8:1:2:3::4".parse().unwrap(), lengths);
+        assert_eq!(prefix.to_string(), "2001:db8:1::/48");
+
+        // 配置得再长，也不会保留完整的地址
+        let full = PrefixLengths {
+            ipv4: 32,
+            ipv6: 128,
+        };
+        let prefix = NetworkPrefix::of("192.0.2.77".parse().unwrap(), full);
+        assert_eq!(prefix.to_string(), "192.0.2.0/24");
+        let prefix = NetworkPrefix::of("2001:db8:1:2:3::4".parse().unwrap(), full);
+        assert_eq!(prefix.to_string(), "2001:db8:1:2::/64");
+
+        let prefix = NetworkPrefix::of(
+            "192.0.2.77".parse().unwrap(),
+            PrefixLengths { ipv4: 0, ipv6: 0 },
+        );
+        assert_eq!(prefix.to_string(), "0.0.0.0/0");
+    }
+
+    #[test]
+    fn test_rtt_histogram() {
+        let mut histogram = RttHistogram::default();
+        assert_eq!(histogram.median(), None);
+
+        // 1ms到1000ms均匀分布
+        for millis in 1..=1000 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let within = |actual: Duration, expected: Duration| {
+            let ratio = actual.as_secs_f64() / expected.as_secs_f64();
+            (1.0 / 1.045..=1.045).contains(&ratio)
+        };
+        assert_eq!(histogram.count(), 1000);
+        assert!(within(
+            histogram.quantile(0.5).unwrap(),
+            Duration::from_millis(500)
+        ));
+        assert!(within(
+            histogram.quantile(0.99).unwrap(),
+            Duration::from_millis(990)
+        ));
+        assert!(within(
+            histogram.quantile(0.0).unwrap(),
+            Duration::from_millis(1)
+        ));
+        assert!(within(
+            histogram.quantile(1.0).unwrap(),
+            Duration::from_millis(1000)
+        ));
+
+        let mut other = RttHistogram::default();
+        for _ in 0..3000 {
+            other.record(Duration::from_secs(2));
+        }
+        histogram.merge(&other);
+        assert_eq!(histogram.count(), 4000);
+        assert!(within(histogram.median().unwrap(), Duration::from_secs(2)));
+
+        // 超出范围的落在首尾的桶
+        let mut extreme = RttHistogram::default();
+        extreme.record(Duration::ZERO);
+        extreme.record(Duration::from_secs(3600));
+        assert!(extreme.quantile(0.0).unwrap() < Duration::from_micros(2));
+        assert!(extreme.quantile(1.0).unwrap() > Duration::from_secs(200));
+    }
+
+    #[test]
+    fn test_network_telemetry() {
+        let telemetry = NetworkTelemetry::new(PrefixLengths::default());
+        // 模拟来自16个/24网段的连接，第n个网段的RTT为n*10ms上下，丢包率为n%
+        let mut expected = HashMap::new();
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for n in 1..=16u64 {
+            let mut rtts = Vec::new();
+            for i in 0..200u64 {
+                let addr = SocketAddr::from(([10, n as u8, 7, random() as u8], 443));
+                let rtt = Duration::from_micros(n * 10_000 + random() % 2_000);
+                let handshake_done = i % 10 != 0;
+                let digest = ConnectionDigest {
+                    prefix: telemetry.prefix_of(addr),
+                    duration: Duration::from_secs(1),
+                    bytes_sent: 1000,
+                    bytes_rcvd: 2000,
+                    smoothed_rtt: handshake_done.then_some(rtt),
+                    packets_sent: 100,
+                    packets_lost: n,
+                    handshake_done,
+                };
+                telemetry.report(&digest);
+                if handshake_done {
+                    rtts.push(rtt);
+                }
+            }
+            rtts.sort();
+            let prefix = NetworkPrefix::of(IpAddr::from([10, n as u8, 7, 0]), Default::default());
+            expected.insert(prefix, (n, rtts));
+        }
+
+        let report = telemetry.take_report();
+        assert_eq!(report.len(), 16);
+        for (prefix, (n, rtts)) in expected {
+            let stats = &report[&prefix];
+            assert_eq!(stats.connections, 200);
+            assert_eq!(stats.handshakes, 180);
+            assert_eq!(stats.duration, Duration::from_secs(200));
+            assert_eq!((stats.bytes_sent, stats.bytes_rcvd), (200_000, 400_000));
+            assert!((stats.loss_ratio() - n as f64 / 100.0).abs() < 1e-9);
+            assert_eq!(stats.rtt.count(), rtts.len() as u64);
+            for q in [0.1, 0.5, 0.9] {
+                let truth = rtts[(q * rtts.len() as f64).ceil() as usize - 1];
+                let estimate = stats.rtt.quantile(q).unwrap();
+                let ratio = estimate.as_secs_f64() / truth.as_secs_f64();
+                assert!((1.0 / 1.045..=1.045).contains(&ratio), "{prefix} q{q}");
+            }
+        }
+
+        // 取走之后重新开始累计
+        assert!(telemetry.take_report().is_empty());
+    }
+}
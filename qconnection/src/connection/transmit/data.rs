@@ -3,19 +3,21 @@ use std::{sync::Arc, time::Instant};
 use bytes::BufMut;
 use qbase::{
     cid::ConnectionId,
-    frame::{PathChallengeFrame, PathResponseFrame},
+    frame::{
+        io::WriteFrame, ImmediateAckFrame, ObservedAddressFrame, PathChallengeFrame,
+        PathResponseFrame, PingFrame,
+    },
     packet::{
-        encrypt::{
-            encode_long_first_byte, encode_short_first_byte, encrypt_packet, protect_header,
-        },
+        encrypt::{encode_long_first_byte, encrypt_packet, protect_header},
         header::{WriteLongHeader, WriteOneRttHeader},
         keys::{ArcKeys, ArcOneRttKeys, ArcOneRttPacketKeys},
         Encode, LongHeaderBuilder, OneRttHeader, SpinBit, WritePacketNumber,
     },
     varint::{EncodeBytes, VarInt, WriteVarInt},
 };
+use qcongestion::congestion::PacketClass;
 use qrecovery::{
-    reliable::{ArcReliableFrameDeque, GuaranteedFrame},
+    reliable::{ArcReliableFrameDeque, GuaranteedFrame, TrackedDatagram},
     space::DataSpace,
     streams::crypto::CryptoStreamOutgoing,
 };
@@ -24,6 +26,22 @@ use rustls::quic::HeaderProtectionKey;
 
 use crate::{connection::DataStreams, path::SendBuffer};
 
+/// A 1-RTT packet assembled in a datagram but not sealed yet.
+///
+/// The 1-RTT packets of a GSO batch are sealed together once the whole batch is assembled,
+/// with the same generation of keys, see [`SealKeys::seal_batch`](qbase::packet::encrypt::SealKeys::seal_batch).
+#[derive(Debug, Clone, Copy)]
+pub struct UnsealedPacket {
+    pub pn: u64,
+    /// The largest packet number acknowledged by the ACK frame in the packet, if any.
+    pub acked: Option<u64>,
+    /// The offset of the packet in the datagram.
+    pub offset: usize,
+    pub size: usize,
+    pub hdr_len: usize,
+    pub pn_len: usize,
+}
+
 #[derive(Clone)]
 pub struct DataSpaceReader {
     pub(crate) space: DataSpace,
@@ -32,9 +50,10 @@ pub struct DataSpaceReader {
     // 数据源
     pub(crate) challenge_sndbuf: SendBuffer<PathChallengeFrame>,
     pub(crate) response_sndbuf: SendBuffer<PathResponseFrame>,
+    pub(crate) observed_sndbuf: SendBuffer<ObservedAddressFrame>,
     pub(crate) crypto_stream_outgoing: CryptoStreamOutgoing,
     pub(crate) reliable_frames: ArcReliableFrameDeque,
-    pub(crate) streams: DataStreams,
+    pub(crate) streams: Option<DataStreams>,
     pub(crate) datagrams: DatagramFlow,
     // 为了各个流的公平性，包括不可靠数据帧，需要额外维护一些信息
 }
@@ -44,8 +63,9 @@ impl DataSpaceReader {
         self.one_rtt_keys.get_local_keys()
     }
 
-    /// Returns (pn, is_ack_eliciting, is_just_ack, sent_size, fresh_bytes, in_flight, sent_ack) or None
-    #[allow(clippy::type_complexity)]
+    /// Returns (packet, is_ack_eliciting, is_just_ack, fresh_bytes, in_flight, class) or None,
+    /// the packet is left to be sealed by the caller, see [`UnsealedPacket`].
+    #[allow(clippy::too_many_arguments)]
     pub fn try_read_1rtt(
         &self,
         buf: &mut [u8],
@@ -53,8 +73,10 @@ impl DataSpaceReader {
         dcid: ConnectionId,
         spin: SpinBit,
         ack_pkt: Option<(u64, Instant)>,
-        (hpk, pk): (Arc<dyn HeaderProtectionKey>, ArcOneRttPacketKeys),
-    ) -> Option<(u64, bool, bool, usize, usize, bool, Option<u64>)> {
+        probe: bool,
+        standby: bool,
+        pk: &ArcOneRttPacketKeys,
+    ) -> Option<(UnsealedPacket, bool, bool, usize, bool, PacketClass)> {
         // 0. 检查1rtt keys是否有效，没有则回退到0rtt包
         // 1. 生成包头，根据包头大小，配合constraints、剩余空间，检查是否能发送，不能的话，直接返回
         let hdr = OneRttHeader { spin, dcid };
@@ -83,6 +105,7 @@ impl DataSpaceReader {
 
         // 3. 检查PathFrameBuffer，尝试写，但发送记录并不记录，若写入，则constraints开始记录
         let n = self.challenge_sndbuf.try_read(body_buf);
+        let has_challenge = n > 0;
         if n > 0 {
             send_guard.record_trivial();
             is_ack_eliciting = true;
@@ -98,6 +121,17 @@ impl DataSpaceReader {
             in_flight = true;
             body_buf = &mut body_buf[n..];
         }
+        // 到此写入的都是探测帧，见RFC 9000 9.1
+        let mut probing_len = body_size - body_buf.remaining_mut();
+        // 观察到的地址丢了也无妨，对端的下一个包会触发新的报告
+        let n = self.observed_sndbuf.try_read(body_buf);
+        if n > 0 {
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+            body_buf = &mut body_buf[n..];
+        }
 
         // 4. 检查是否需要发送Ack，若是，且符合（constraints + buf）节制，生成ack并写入，但发送记录并不记录
         let mut sent_ack = None;
@@ -106,44 +140,79 @@ impl DataSpaceReader {
             let n = rcvd_pkt_records.read_ack_frame_util(body_buf, largest, recv_time)?;
             send_guard.record_trivial();
             sent_ack = Some(largest);
+            // ACK帧不改变包的类别
+            probing_len += n;
             body_buf = &mut body_buf[n..];
         }
 
-        // 5. 检查可靠帧，若有且符合（constraints + buf）节制，写入，burst、发包记录都记录
-        while let Some((frame, n)) = self.reliable_frames.try_read(body_buf) {
-            send_guard.record_frame(GuaranteedFrame::Reliable(frame));
-            body_buf = &mut body_buf[n..];
-            is_ack_eliciting = true;
-            is_just_ack = false;
-            in_flight = true;
-        }
+        let mut fresh_bytes = 0;
+        // 备用的路径只发探测帧与ACK，数据留给承载数据的路径，见RawPath::set_standby
+        if !standby {
+            // 5. 检查可靠帧，若有且符合（constraints + buf）节制，写入，burst、发包记录都记录
+            while let Some((frame, n)) = self.reliable_frames.try_read(body_buf) {
+                send_guard.record_frame(GuaranteedFrame::Reliable(frame));
+                body_buf = &mut body_buf[n..];
+                is_ack_eliciting = true;
+                is_just_ack = false;
+                in_flight = true;
+            }
 
-        // 6. 检查NewToken，是否需要发送
+            // 6. 检查NewToken，是否需要发送
 
-        // 7. 象征性地检查一下CryptoStream
-        while let Some((frame, n)) = self.crypto_stream_outgoing.try_read_data(body_buf) {
-            send_guard.record_frame(GuaranteedFrame::Crypto(frame));
-            body_buf = &mut body_buf[n..];
-            is_ack_eliciting = true;
-            is_just_ack = false;
-            in_flight = true;
-        }
+            // 7. 象征性地检查一下CryptoStream
+            while let Some((frame, n)) = self.crypto_stream_outgoing.try_read_data(body_buf) {
+                send_guard.record_frame(GuaranteedFrame::Crypto(frame));
+                body_buf = &mut body_buf[n..];
+                is_ack_eliciting = true;
+                is_just_ack = false;
+                in_flight = true;
+            }
 
-        // 8. 检查DataStreams是否需要发送，若有，且符合（constraints + buf）节制，写入，burst、发包记录都记录
-        let mut fresh_bytes = 0;
-        while let Some((frame, n, m)) = self.streams.try_read_data(body_buf, flow_limit) {
-            send_guard.record_frame(GuaranteedFrame::Stream(frame));
-            flow_limit -= m;
-            fresh_bytes += m;
-            body_buf = &mut body_buf[n..];
-            is_ack_eliciting = true;
-            is_just_ack = false;
-            in_flight = true;
-        }
+            // 8. 检查DataStreams是否需要发送，若有，且符合（constraints + buf）节制，写入，burst、发包记录都记录
+            while let Some((frame, n, m)) = self
+                .streams
+                .as_ref()
+                .and_then(|streams| streams.try_read_data(body_buf, flow_limit))
+            {
+                send_guard.record_frame(GuaranteedFrame::Stream(frame));
+                flow_limit -= m;
+                fresh_bytes += m;
+                body_buf = &mut body_buf[n..];
+                is_ack_eliciting = true;
+                is_just_ack = false;
+                in_flight = true;
+            }
+            // 某个流flush_eager的数据随这个包发出，请求对方立即确认；IMMEDIATE_ACK帧丢了也
+            // 不必重传，对方收到后续的包，按正常的节奏确认即可
+            if body_buf.remaining_mut() > 0
+                && self
+                    .streams
+                    .as_ref()
+                    .is_some_and(|streams| streams.take_immediate_ack())
+            {
+                body_buf.put_frame(&ImmediateAckFrame);
+                send_guard.record_trivial();
+            }
 
-        // 9. 检查Datagrams是否需要发送，若有，且符合(constraints + buf) 节制，写入，burst、发包记录都记录
-        while let Some((_frame, n)) = self.datagrams.try_read_datagram(body_buf) {
-            body_buf = &mut body_buf[n..];
+            // 9. 检查Datagrams是否需要发送，若有，且符合(constraints + buf) 节制，写入，burst、发包记录都记录
+            //    多个小数据报合进同一个包
+            for (frame, n, tracked) in self.datagrams.try_read_datagrams(body_buf) {
+                // 被跟踪的数据报随包记录，确认或丢失时通知应用
+                match tracked {
+                    Some(seq) => send_guard
+                        .record_frame(GuaranteedFrame::Datagram(TrackedDatagram { frame, seq })),
+                    None => send_guard.record_trivial(),
+                }
+                body_buf = &mut body_buf[n..];
+                is_ack_eliciting = true;
+                is_just_ack = false;
+                in_flight = true;
+            }
+        }
+        // PTO要求发探测包，却没有ack-eliciting的帧可发，就发一个PING帧
+        if probe && !is_ack_eliciting && body_buf.remaining_mut() > 0 {
+            body_buf.put_frame(&PingFrame);
+            send_guard.record_trivial();
             is_ack_eliciting = true;
             is_just_ack = false;
             in_flight = true;
@@ -157,6 +226,13 @@ impl DataSpaceReader {
             // 无有效数据，那就不打包1Rtt包发送了
             return None;
         }
+        // 只有PATH_CHALLENGE等探测帧的包，是在验证路径，比如迁移前探测新路径，其大小、
+        // 丢失与否都不反映路径的容量，拥塞控制不应据此调整窗口
+        let class = if has_challenge && body_len == probing_len {
+            PacketClass::PathProbe
+        } else {
+            PacketClass::Normal
+        };
         // payload(pn + body)长度不足20字节，填充之
         if pn_len + body_len + tag_len < 20 {
             let padding_len = 20 - pn_len - body_len - tag_len;
@@ -168,21 +244,22 @@ impl DataSpaceReader {
         hdr_buf.put_one_rtt_header(&hdr);
         pn_buf.put_packet_number(encoded_pn);
 
-        // 11 保护包头，加密数据
-        let pk_guard = pk.lock_guard();
-        let (key_phase, pk) = pk_guard.get_local();
-        encode_short_first_byte(&mut buf[0], pn_len, key_phase);
-        encrypt_packet(pk.as_ref(), pn, &mut buf[..sent_size], hdr_len + pn_len);
-        protect_header(hpk.as_ref(), &mut buf[..sent_size], hdr_len, pn_len);
-
-        Some((
+        // 11 保护包头、加密数据留待整批数据报装填完毕，一并进行
+        let packet = UnsealedPacket {
             pn,
+            acked: sent_ack,
+            offset: 0,
+            size: sent_size,
+            hdr_len,
+            pn_len,
+        };
+        Some((
+            packet,
             is_ack_eliciting,
             is_just_ack,
-            sent_size,
             fresh_bytes,
             in_flight,
-            sent_ack,
+            class,
         ))
     }
 
@@ -243,7 +320,11 @@ impl DataSpaceReader {
         // 6. 检查DataStreams是否需要发送，若有，且符合（constraints + buf）节制，写入，burst、发包记录都记录
         // TODO: 要注意和Datagrams的公平了
         let mut fresh_bytes = 0;
-        while let Some((frame, n, m)) = self.streams.try_read_data(body_buf, flow_limit) {
+        while let Some((frame, n, m)) = self
+            .streams
+            .as_ref()
+            .and_then(|streams| streams.try_read_data(body_buf, flow_limit))
+        {
             send_guard.record_frame(GuaranteedFrame::Stream(frame));
             body_buf = &mut body_buf[n..];
             flow_limit -= m;
@@ -253,7 +334,13 @@ impl DataSpaceReader {
         }
 
         // 7. 检查Datagrams是否需要发送，若有，且符合(constraints + buf) 节制，写入，burst、发包记录都记录
-        while let Some((_frame, n)) = self.datagrams.try_read_datagram(body_buf) {
+        for (frame, n, tracked) in self.datagrams.try_read_0rtt_datagrams(body_buf) {
+            // 被跟踪的数据报随包记录，确认或丢失时通知应用
+            match tracked {
+                Some(seq) => send_guard
+                    .record_frame(GuaranteedFrame::Datagram(TrackedDatagram { frame, seq })),
+                None => send_guard.record_trivial(),
+            }
             body_buf = &mut body_buf[n..];
             is_ack_eliciting = true;
             in_flight = true;
@@ -3,6 +3,7 @@ use std::time::Instant;
 use bytes::BufMut;
 use qbase::{
     cid::ConnectionId,
+    frame::{io::WriteFrame, PingFrame},
     packet::{
         encrypt::{encode_long_first_byte, encrypt_packet, protect_header},
         header::WriteLongHeader,
@@ -27,6 +28,7 @@ impl HandshakeSpaceReader {
         scid: ConnectionId,
         dcid: ConnectionId,
         ack_pkt: Option<(u64, Instant)>,
+        probe: bool,
     ) -> Option<(u64, bool, bool, usize, bool, Option<u64>)> {
         // 1. 判定keys是否有效，无效或者尚未拿到，直接返回
         let k = self.keys.get_local_keys()?;
@@ -74,6 +76,14 @@ impl HandshakeSpaceReader {
             is_just_ack = false;
             in_flight = true;
         }
+        // PTO要求发探测包，却没有ack-eliciting的帧可发，就发一个PING帧
+        if probe && !is_ack_eliciting && body_buf.remaining_mut() > 0 {
+            body_buf.put_frame(&PingFrame);
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+        }
         drop(send_guard); // 持有这把锁的时间越短越好，毕竟下面的加密可能会有点耗时
 
         // 7. 填充，保护头部，加密
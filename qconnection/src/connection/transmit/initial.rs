@@ -6,6 +6,7 @@ use std::{
 use bytes::BufMut;
 use qbase::{
     cid::ConnectionId,
+    frame::{io::WriteFrame, PingFrame},
     packet::{
         encrypt::{encode_long_first_byte, encrypt_packet, protect_header},
         header::WriteLongHeader,
@@ -13,12 +14,14 @@ use qbase::{
         Encode, LongHeaderBuilder, WritePacketNumber,
     },
     varint::{EncodeBytes, VarInt, WriteVarInt},
+    version::Versions,
 };
 use qrecovery::{space::InitialSpace, streams::crypto::CryptoStreamOutgoing};
 
 #[derive(Clone)]
 pub struct InitialSpaceReader {
     pub(crate) token: Arc<Mutex<Vec<u8>>>,
+    pub(crate) versions: Arc<Mutex<Versions>>,
     pub(crate) keys: ArcKeys,
     pub(crate) space: InitialSpace,
     pub(crate) crypto_stream_outgoing: CryptoStreamOutgoing,
@@ -31,6 +34,7 @@ impl InitialSpaceReader {
         scid: ConnectionId,
         dcid: ConnectionId,
         ack_pkt: Option<(u64, Instant)>,
+        probe: bool,
     ) -> Option<(
         impl FnOnce(&mut [u8], usize) -> (u64, bool, bool, usize, bool, Option<u64>),
         usize,
@@ -83,6 +87,14 @@ impl InitialSpaceReader {
             is_ack_eliciting = true;
             in_flight = true;
         }
+        // PTO要求发探测包，却没有ack-eliciting的帧可发，就发一个PING帧
+        if probe && !is_ack_eliciting && body_buf.remaining_mut() > 0 {
+            body_buf.put_frame(&PingFrame);
+            send_guard.record_trivial();
+            is_ack_eliciting = true;
+            is_just_ack = false;
+            in_flight = true;
+        }
         drop(send_guard); // 持有这把锁的时间越短越好，毕竟下面的加密可能会有点耗时
 
         let hdr_len = hdr_buf.len();
@@ -95,6 +107,8 @@ impl InitialSpaceReader {
 
         hdr_buf.put_long_header(&hdr);
         pn_buf.put_packet_number(encoded_pn);
+        // 包头总是按版本1写就，客户端以保留版本试探服务端时，换成当前的版本，密钥仍是版本1的
+        let version = self.versions.lock().unwrap().current();
 
         Some((
             move |buf: &mut [u8], len: usize| -> (u64, bool, bool, usize, bool, Option<u64>) {
@@ -125,6 +139,7 @@ impl InitialSpaceReader {
                 );
 
                 encode_long_first_byte(&mut buf[0], pn_len);
+                buf[1..5].copy_from_slice(&version.to_be_bytes());
                 encrypt_packet(
                     k.local.packet.as_ref(),
                     pn,
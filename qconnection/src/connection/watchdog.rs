@@ -0,0 +1,162 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use qbase::{
+    error::{Error, ErrorKind},
+    util::spawn_traced,
+};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::error::ConnError;
+
+#[derive(Debug)]
+struct RawReceiveWatchdog {
+    timeout: Option<Duration>,
+    last_rcvd: Instant,
+    // 只在第一次设置超时的时候，才派生看门狗任务
+    is_watching: bool,
+    is_stopped: bool,
+}
+
+/// Close the connection if no data of any stream or datagram is received within the
+/// receive timeout, see [`ArcConnection::set_receive_timeout`].
+///
+/// It is a liveness watchdog distinct from the idle timeout, the ACK and other control
+/// frames keep the connection from idle, but don't feed this watchdog.
+///
+/// [`ArcConnection::set_receive_timeout`]: crate::connection::ArcConnection::set_receive_timeout
+#[derive(Debug, Clone)]
+pub struct ReceiveWatchdog {
+    raw: Arc<Mutex<RawReceiveWatchdog>>,
+    reconfigured: Arc<Notify>,
+}
+
+impl Default for ReceiveWatchdog {
+    fn default() -> Self {
+        Self {
+            raw: Arc::new(Mutex::new(RawReceiveWatchdog {
+                timeout: None,
+                last_rcvd: Instant::now(),
+                is_watching: false,
+                is_stopped: false,
+            })),
+            reconfigured: Arc::default(),
+        }
+    }
+}
+
+impl ReceiveWatchdog {
+    /// Feed the watchdog when a STREAM or DATAGRAM frame is received.
+    pub fn on_data_rcvd(&self) {
+        self.raw.lock().unwrap().last_rcvd = Instant::now();
+    }
+
+    /// Set the receive timeout, the window starts from now. None disables the watchdog.
+    pub fn set_timeout(&self, timeout: Option<Duration>, conn_error: &ConnError) {
+        let mut raw = self.raw.lock().unwrap();
+        if raw.is_stopped {
+            return;
+        }
+        raw.timeout = timeout;
+        raw.last_rcvd = Instant::now();
+        if raw.is_watching {
+            self.reconfigured.notify_one();
+        } else if timeout.is_some() {
+            raw.is_watching = true;
+            spawn_traced(self.clone().watch(conn_error.clone()));
+        }
+    }
+
+    /// Stop the watchdog once the connection is closing or draining.
+    ///
+    /// The [`ConnError`] only wakes one waiter, which is the connection itself, so the
+    /// watchdog task can't wait for it, and must be stopped explicitly.
+    pub fn on_conn_error(&self) {
+        let mut raw = self.raw.lock().unwrap();
+        raw.is_stopped = true;
+        if raw.is_watching {
+            self.reconfigured.notify_one();
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let raw = self.raw.lock().unwrap();
+        raw.timeout.map(|timeout| raw.last_rcvd + timeout)
+    }
+
+    async fn watch(self, conn_error: ConnError) {
+        loop {
+            if self.raw.lock().unwrap().is_stopped {
+                return;
+            }
+            let expired = async {
+                match self.deadline() {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = expired => {
+                    // 期间收到过数据，则重新计时
+                    if self.deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+                        conn_error.set_app_error(Error::with_default_fty(
+                            ErrorKind::Application,
+                            "no data received within the receive timeout",
+                        ));
+                        return;
+                    }
+                }
+                _ = self.reconfigured.notified() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_receive_timeout() {
+        let conn_error = ConnError::default();
+        let watchdog = ReceiveWatchdog::default();
+        watchdog.set_timeout(Some(Duration::from_millis(100)), &conn_error);
+
+        // 持续收到数据，看门狗不会触发
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            watchdog.on_data_rcvd();
+        }
+        // 看门狗不会等待连接错误，这里的等待者能被唤醒
+        let fired = tokio::time::timeout(Duration::from_millis(5), conn_error.did_error_occur());
+        assert!(fired.await.is_err());
+
+        let (error, is_active) = conn_error.did_error_occur().await;
+        assert!(is_active);
+        assert_eq!(error.kind(), ErrorKind::Application);
+    }
+
+    #[tokio::test]
+    async fn test_receive_timeout_disabled() {
+        let conn_error = ConnError::default();
+        let watchdog = ReceiveWatchdog::default();
+        watchdog.set_timeout(Some(Duration::from_millis(20)), &conn_error);
+        watchdog.set_timeout(None, &conn_error);
+
+        let fired = tokio::time::timeout(Duration::from_millis(60), conn_error.did_error_occur());
+        assert!(fired.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_receive_timeout_stopped() {
+        let conn_error = ConnError::default();
+        let watchdog = ReceiveWatchdog::default();
+        watchdog.set_timeout(Some(Duration::from_millis(20)), &conn_error);
+        watchdog.on_conn_error();
+
+        let fired = tokio::time::timeout(Duration::from_millis(60), conn_error.did_error_occur());
+        assert!(fired.await.is_err());
+    }
+}
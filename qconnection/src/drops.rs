@@ -0,0 +1,321 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use qbase::packet::error::Error as PacketError;
+
+/// The endpoint-wide drop accounting, shared by all the connections as the [`ROUTER`].
+///
+/// Support can ask a customer to dump [`DropRecorder::recent`] when connections fail.
+///
+/// [`ROUTER`]: crate::router::ROUTER
+pub static DROPS: LazyLock<DropRecorder> = LazyLock::new(DropRecorder::default);
+
+/// The number of header bytes kept in a [`DropEvent`].
+pub const DROP_HEADER_LEN: usize = 32;
+
+/// Why an incoming packet was dropped silently, or an outgoing one was held back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// No connection is found for the destination connection ID, and it can't start a new one.
+    UnknownDcid,
+    /// The packet is of an unsupported QUIC version.
+    UnsupportedVersion,
+    /// The packet header can't be parsed.
+    MalformedHeader,
+    /// The packet can't be decrypted, or the keys of its epoch are not available or discarded.
+    /// A Retry packet failing its integrity check, or arriving too late, is counted here too.
+    Undecryptable,
+    /// The packet number has been received, or is too old to be decoded.
+    DuplicatePn,
+    /// The sending to an unvalidated address was blocked by the anti-amplification limit.
+    AntiAmplification,
+    /// The packet was received in the closing or draining state, it isn't processed.
+    Closing,
+    /// The Initial packet was discarded according to the token policy.
+    InvalidToken,
+    /// The Initial packet of a new connection was discarded, because the listener is at its
+    /// connection limits.
+    ConnectionLimit,
+}
+
+impl DropReason {
+    pub const COUNT: usize = 9;
+    pub const ALL: [DropReason; Self::COUNT] = [
+        DropReason::UnknownDcid,
+        DropReason::UnsupportedVersion,
+        DropReason::MalformedHeader,
+        DropReason::Undecryptable,
+        DropReason::DuplicatePn,
+        DropReason::AntiAmplification,
+        DropReason::Closing,
+        DropReason::InvalidToken,
+        DropReason::ConnectionLimit,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::UnknownDcid => "unknown_dcid",
+            DropReason::UnsupportedVersion => "unsupported_version",
+            DropReason::MalformedHeader => "malformed_header",
+            DropReason::Undecryptable => "undecryptable",
+            DropReason::DuplicatePn => "duplicate_pn",
+            DropReason::AntiAmplification => "anti_amplification",
+            DropReason::Closing => "closing",
+            DropReason::InvalidToken => "invalid_token",
+            DropReason::ConnectionLimit => "connection_limit",
+        }
+    }
+}
+
+/// The reason to drop a datagram whose packet can't be parsed.
+impl From<&PacketError> for DropReason {
+    fn from(error: &PacketError) -> Self {
+        match error {
+            PacketError::UnsupportedVersion(_) => DropReason::UnsupportedVersion,
+            _ => DropReason::MalformedHeader,
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The numbers of the dropped packets by reason.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DropStats([u64; DropReason::COUNT]);
+
+impl DropStats {
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.0[reason.index()]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DropReason, u64)> + '_ {
+        DropReason::ALL.into_iter().zip(self.0.iter().copied())
+    }
+}
+
+/// Lock-free counters of the dropped packets by reason.
+#[derive(Debug, Default)]
+pub struct DropCounters([AtomicU64; DropReason::COUNT]);
+
+impl DropCounters {
+    pub fn on_dropped(&self, reason: DropReason) {
+        self.0[reason.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> DropStats {
+        DropStats(std::array::from_fn(|i| self.0[i].load(Ordering::Relaxed)))
+    }
+}
+
+/// The per-connection drop counters, every drop is also recorded in the endpoint-wide [`DROPS`].
+#[derive(Debug, Default, Clone)]
+pub struct ArcDropCounters(Arc<DropCounters>);
+
+impl ArcDropCounters {
+    pub fn on_dropped(&self, reason: DropReason, source: SocketAddr, header: &[u8]) {
+        self.0.on_dropped(reason);
+        DROPS.record(reason, Some(source), header);
+    }
+
+    pub fn stats(&self) -> DropStats {
+        self.0.stats()
+    }
+}
+
+/// A dropped packet in the ring buffer of [`DropRecorder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropEvent {
+    pub reason: DropReason,
+    pub time: SystemTime,
+    /// The address the packet came from, None if unknown.
+    pub source: Option<SocketAddr>,
+    /// At most the first [`DROP_HEADER_LEN`] bytes of the packet, as received.
+    pub header: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct RawDropRecorder {
+    events: VecDeque<DropEvent>,
+    capacity: usize,
+    log_interval: Option<Duration>,
+    last_logged: [Option<Instant>; DropReason::COUNT],
+}
+
+/// Count the dropped packets by reason, keep the last N of them in a ring buffer, and
+/// optionally log a warning line per reason at most once every interval.
+#[derive(Debug)]
+pub struct DropRecorder {
+    counters: DropCounters,
+    raw: Mutex<RawDropRecorder>,
+}
+
+impl Default for DropRecorder {
+    fn default() -> Self {
+        Self::with_capacity(128)
+    }
+}
+
+impl DropRecorder {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            counters: DropCounters::default(),
+            raw: Mutex::new(RawDropRecorder {
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+                log_interval: None,
+                last_logged: [None; DropReason::COUNT],
+            }),
+        }
+    }
+
+    /// Record a dropped packet, the header is truncated to [`DROP_HEADER_LEN`] bytes.
+    pub fn record(&self, reason: DropReason, source: Option<SocketAddr>, header: &[u8]) {
+        self.counters.on_dropped(reason);
+        let total = self.counters.0[reason.index()].load(Ordering::Relaxed);
+
+        let mut raw = self.raw.lock().unwrap();
+        if let Some(interval) = raw.log_interval {
+            let now = Instant::now();
+            let last_logged = &mut raw.last_logged[reason.index()];
+            if last_logged.is_none_or(|last| now.duration_since(last) >= interval) {
+                *last_logged = Some(now);
+                tracing::warn!(%reason, ?source, total, "packet dropped");
+            }
+        }
+        if raw.capacity == 0 {
+            return;
+        }
+        if raw.events.len() == raw.capacity {
+            raw.events.pop_front();
+        }
+        raw.events.push_back(DropEvent {
+            reason,
+            time: SystemTime::now(),
+            source,
+            header: header[..header.len().min(DROP_HEADER_LEN)].to_vec(),
+        });
+    }
+
+    pub fn stats(&self) -> DropStats {
+        self.counters.stats()
+    }
+
+    /// The last dropped packets, the oldest first.
+    pub fn recent(&self) -> Vec<DropEvent> {
+        self.raw.lock().unwrap().events.iter().cloned().collect()
+    }
+
+    /// Set how many dropped packets are kept, the oldest ones are evicted.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut raw = self.raw.lock().unwrap();
+        let excess = raw.events.len().saturating_sub(capacity);
+        raw.events.drain(..excess);
+        raw.capacity = capacity;
+    }
+
+    /// Log a warning line per reason at most once every interval, None disables it,
+    /// which is the default.
+    pub fn set_log_interval(&self, interval: Option<Duration>) {
+        let mut raw = self.raw.lock().unwrap();
+        raw.log_interval = interval;
+        raw.last_logged = [None; DropReason::COUNT];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason_index() {
+        for (i, reason) in DropReason::ALL.into_iter().enumerate() {
+            assert_eq!(reason.index(), i);
+        }
+    }
+
+    #[test]
+    fn test_parse_error() {
+        use qbase::packet::PacketReader;
+
+        // 未知版本的长包头
+        let mut datagram = vec![0xc0, 0x0a, 0x1a, 0x2a, 0x3a];
+        datagram.extend_from_slice(&[0; 40]);
+        let error = PacketReader::new(datagram[..].into(), 8)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(DropReason::from(&error), DropReason::UnsupportedVersion);
+
+        // 截断的长包头
+        let error = PacketReader::new([0xc0, 0, 0, 0, 1, 8][..].into(), 8)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(DropReason::from(&error), DropReason::MalformedHeader);
+    }
+
+    #[test]
+    fn test_record() {
+        let recorder = DropRecorder::with_capacity(2);
+        let source = "127.0.0.1:4433".parse().unwrap();
+        recorder.record(DropReason::UnknownDcid, Some(source), &[0x40; 64]);
+        recorder.record(DropReason::Undecryptable, None, &[0xc0; 8]);
+        recorder.record(DropReason::Undecryptable, None, &[]);
+
+        let stats = recorder.stats();
+        assert_eq!(stats.get(DropReason::UnknownDcid), 1);
+        assert_eq!(stats.get(DropReason::Undecryptable), 2);
+        assert_eq!(stats.total(), 3);
+
+        let recent = recorder.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].reason, DropReason::Undecryptable);
+        assert_eq!(recent[0].header, [0xc0; 8]);
+        assert!(recent[1].header.is_empty());
+
+        recorder.set_capacity(1);
+        assert_eq!(recorder.recent(), recent[1..]);
+        recorder.set_capacity(0);
+        recorder.record(DropReason::Closing, Some(source), &[0x40; 64]);
+        assert!(recorder.recent().is_empty());
+        assert_eq!(recorder.stats().get(DropReason::Closing), 1);
+    }
+
+    #[test]
+    fn test_header_truncated() {
+        let recorder = DropRecorder::default();
+        recorder.record(DropReason::MalformedHeader, None, &[0xff; 1500]);
+        assert_eq!(recorder.recent()[0].header.len(), DROP_HEADER_LEN);
+    }
+
+    #[test]
+    fn test_connection_counters() {
+        let counters = ArcDropCounters::default();
+        let source = "[::1]:4433".parse().unwrap();
+        let before = DROPS.stats().get(DropReason::DuplicatePn);
+        counters.on_dropped(DropReason::DuplicatePn, source, &[0x40; 40]);
+        assert_eq!(counters.stats().get(DropReason::DuplicatePn), 1);
+        assert_eq!(counters.stats().total(), 1);
+        assert!(DROPS.stats().get(DropReason::DuplicatePn) > before);
+    }
+}
@@ -3,15 +3,120 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
-use qbase::{error::Error, frame::ConnectionCloseFrame, util::AsyncCell};
+use qbase::{
+    error::{Error, ErrorKind},
+    frame::ConnectionCloseFrame,
+    streamid::Dir,
+    util::AsyncCell,
+};
+use thiserror::Error;
 
 #[derive(Debug, Clone)]
 pub enum ConnErrorKind {
     Application(Error),
     Closing(Error),
     Draining(Error),
+    StatelessReset,
+}
+
+/// The error the connection is torn down with, when the peer reset it with a stateless reset,
+/// see [`ConnectionStats::stateless_reset`].
+///
+/// [`ConnectionStats::stateless_reset`]: crate::connection::ConnectionStats::stateless_reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("the peer reset the connection statelessly")]
+pub struct StatelessReset;
+
+impl From<StatelessReset> for Error {
+    fn from(error: StatelessReset) -> Self {
+        Error::with_default_fty(ErrorKind::None, error.to_string())
+    }
+}
+
+/// Why a client failed to establish the connection, see [`ConnectionStats::connect_error`].
+///
+/// [`ConnectionStats::connect_error`]: crate::connection::ConnectionStats::connect_error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ConnectError {
+    /// Nothing was ever received from the peer on any of the attempted addresses, until
+    /// the probe timeouts were exhausted, the address is likely dead or blackholed.
+    #[error("the peer is unreachable, nothing received before the probe timeouts were exhausted")]
+    Unreachable,
+    /// The peer responded at least once, but then stopped responding before the
+    /// handshake completed.
+    #[error("the handshake timed out after the peer stopped responding")]
+    HandshakeTimeout,
+}
+
+/// The error inside the [`io::Error`] of kind [`io::ErrorKind::TimedOut`], returned by
+/// [`ArcConnection::open_bi_stream_timeout`] and [`ArcConnection::open_uni_stream_timeout`]
+/// when the stream can't be opened in time, usually because the peer doesn't raise its
+/// MAX_STREAMS.
+///
+/// [`io::Error`]: std::io::Error
+/// [`io::ErrorKind::TimedOut`]: std::io::ErrorKind::TimedOut
+/// [`ArcConnection::open_bi_stream_timeout`]: crate::connection::ArcConnection::open_bi_stream_timeout
+/// [`ArcConnection::open_uni_stream_timeout`]: crate::connection::ArcConnection::open_uni_stream_timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("no {dir} stream could be opened within {timeout:?}")]
+pub struct StreamLimitTimeout {
+    pub dir: Dir,
+    pub timeout: Duration,
+}
+
+/// The error inside the [`io::Error`] of kind [`io::ErrorKind::Unsupported`], returned by
+/// the methods opening or accepting the streams of a connection created with
+/// [`ConnectionProfile::DatagramOnly`].
+///
+/// [`io::Error`]: std::io::Error
+/// [`io::ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+/// [`ConnectionProfile::DatagramOnly`]: crate::connection::profile::ConnectionProfile::DatagramOnly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("streams are disabled by the connection profile")]
+pub struct StreamsDisabled;
+
+impl From<StreamsDisabled> for std::io::Error {
+    fn from(error: StreamsDisabled) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Unsupported, error)
+    }
+}
+
+/// Why [`ArcConnection::migrate`] refused to migrate the connection, which stays on the
+/// current path. It's inside the [`io::Error`] returned by `migrate`, of kind
+/// [`io::ErrorKind::Unsupported`] or [`io::ErrorKind::WouldBlock`] respectively.
+///
+/// [`ArcConnection::migrate`]: crate::connection::ArcConnection::migrate
+/// [`io::Error`]: std::io::Error
+/// [`io::ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+/// [`io::ErrorKind::WouldBlock`]: std::io::ErrorKind::WouldBlock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MigrationError {
+    /// The peer sent the disable_active_migration transport parameter.
+    #[error("the peer disabled the active migration")]
+    Disabled,
+    /// Every connection ID issued by the peer is in use, the new path can't get a fresh
+    /// one. It may succeed once the peer issues more.
+    #[error("no spare connection id issued by the peer for the new path")]
+    NoSpareCid,
+}
+
+impl From<MigrationError> for std::io::Error {
+    fn from(error: MigrationError) -> Self {
+        let kind = match error {
+            MigrationError::Disabled => std::io::ErrorKind::Unsupported,
+            MigrationError::NoSpareCid => std::io::ErrorKind::WouldBlock,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(error: ConnectError) -> Self {
+        Error::with_default_fty(ErrorKind::NoViablePath, error.to_string())
+    }
 }
 
 /// Connection error, which is None first, and external can poll query whether an error has occurred.
@@ -54,6 +159,30 @@ impl ConnError {
             .write(ConnErrorKind::Draining(Error::from(ccf.clone())));
     }
 
+    /// When a stateless reset from the peer is recognized, the connection enters the draining
+    /// state at once, without sending anything more.
+    pub fn on_stateless_reset(&self) {
+        let mut state = self.0.state();
+        if state.is_pending() {
+            _ = state.write(ConnErrorKind::StatelessReset);
+        }
+    }
+
+    /// When the client can't agree on a version with the server after a Version Negotiation
+    /// packet, it abandons the connection attempt without sending anything more, see
+    /// [RFC 9000 section 6.2](https://www.rfc-editor.org/rfc/rfc9000.html#section-6.2).
+    pub fn on_version_negotiation_failed(&self, error: Error) {
+        let mut state = self.0.state();
+        if state.is_pending() {
+            _ = state.write(ConnErrorKind::Draining(error));
+        }
+    }
+
+    /// Whether the connection was reset by the peer with a stateless reset.
+    pub fn is_stateless_reset(&self) -> bool {
+        matches!(self.0.state().as_ref(), Some(ConnErrorKind::StatelessReset))
+    }
+
     pub fn on_error(&self, error: Error) {
         let mut state = self.0.state();
         if state.is_pending() {
@@ -96,6 +225,7 @@ impl Future for ConnError {
             ConnErrorKind::Application(e) => Poll::Ready((e, true)),
             ConnErrorKind::Closing(e) => Poll::Ready((e, true)),
             ConnErrorKind::Draining(e) => Poll::Ready((e, false)),
+            ConnErrorKind::StatelessReset => Poll::Ready((StatelessReset.into(), false)),
         }
     }
 }
@@ -159,4 +289,25 @@ mod tests {
 
         _ = task.await;
     }
+
+    #[tokio::test]
+    async fn test_stateless_reset() {
+        let conn_error = ConnError::default();
+
+        let task = tokio::spawn({
+            let conn_error = conn_error.clone();
+            async move {
+                let (_, is_active) = conn_error.await;
+                assert!(!is_active);
+            }
+        });
+
+        conn_error.on_stateless_reset();
+        assert!(conn_error.is_stateless_reset());
+        // 已经被重置的连接，不再理会其他的错误
+        conn_error.on_error(Error::with_default_fty(ErrorKind::Internal, "Test error"));
+        assert!(conn_error.is_stateless_reset());
+
+        _ = task.await;
+    }
 }
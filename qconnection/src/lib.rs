@@ -1,6 +1,7 @@
 use std::net::SocketAddr;
 
 pub mod connection;
+pub mod drops;
 pub mod error;
 pub mod path;
 pub mod pipe;
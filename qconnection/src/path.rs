@@ -4,12 +4,19 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{ready, Context, Poll},
+    time::Duration,
 };
 
 use dashmap::DashMap;
 use deref_derive::{Deref, DerefMut};
-use qbase::cid::{ArcCidCell, ConnectionId};
-use qcongestion::{congestion::MSS, CongestionControl};
+use qbase::{
+    cid::{ArcCidCell, ConnectionId},
+    util::try_spawn_traced,
+};
+use qcongestion::{
+    congestion::{CongestionAlgorithm, MSS},
+    CongestionControl,
+};
 use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
 use qudp::ArcUsc;
 
@@ -26,12 +33,16 @@ pub use pathway::Pathway;
 pub use raw::RawPath;
 pub use util::{RecvBuffer, SendBuffer};
 
+// 没有数据可发时，新写入的数据不会唤醒发送任务，至多等这么久再驱动一次，见read.rs中的TODO
+const MAX_TICK_INTERVAL: Duration = Duration::from_millis(10);
+
 pub trait ViaPathway {
     fn poll_send_via_pathway(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
         pathway: Pathway,
+        ecn: Option<u8>,
     ) -> Poll<io::Result<usize>>;
 
     fn sync_send_via_path_way(&mut self, iovec: Vec<u8>, pathway: Pathway) -> io::Result<()>;
@@ -43,13 +54,14 @@ impl ViaPathway for ArcUsc {
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
         pathway: Pathway,
+        ecn: Option<u8>,
     ) -> Poll<io::Result<usize>> {
         // todo: append relay hdr
         let hdr = qudp::PacketHeader {
             src: pathway.local_addr(),
             dst: pathway.dst_addr(),
             ttl: 64,
-            ecn: None,
+            ecn,
             seg_size: MSS as u16,
             gso: true,
         };
@@ -75,6 +87,7 @@ pub trait ViaPathWayExt: ViaPathway {
         &'s mut self,
         iovecs: &'s [IoSlice<'s>],
         pathway: Pathway,
+        ecn: Option<u8>,
     ) -> SendViaPathWay<'s, Self>
     where
         Self: Unpin,
@@ -83,6 +96,7 @@ pub trait ViaPathWayExt: ViaPathway {
             sender: self,
             iovecs,
             pathway,
+            ecn,
         }
     }
 
@@ -90,6 +104,7 @@ pub trait ViaPathWayExt: ViaPathway {
         &'s mut self,
         iovecs: &'s [IoSlice<'s>],
         pathway: Pathway,
+        ecn: Option<u8>,
     ) -> SendAllViaPathWay<'s, Self>
     where
         Self: Unpin,
@@ -98,6 +113,7 @@ pub trait ViaPathWayExt: ViaPathway {
             sender: self,
             iovecs,
             pathway,
+            ecn,
         }
     }
 }
@@ -108,6 +124,7 @@ pub struct SendViaPathWay<'s, S: ?Sized> {
     sender: &'s mut S,
     iovecs: &'s [IoSlice<'s>],
     pathway: Pathway,
+    ecn: Option<u8>,
 }
 
 impl<S: Unpin + ?Sized> Unpin for SendViaPathWay<'_, S> {}
@@ -117,7 +134,7 @@ impl<S: ViaPathway + Unpin + ?Sized> Future for SendViaPathWay<'_, S> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-        Pin::new(&mut *this.sender).poll_send_via_pathway(cx, this.iovecs, this.pathway)
+        Pin::new(&mut *this.sender).poll_send_via_pathway(cx, this.iovecs, this.pathway, this.ecn)
     }
 }
 
@@ -125,6 +142,7 @@ pub struct SendAllViaPathWay<'s, S: ?Sized> {
     sender: &'s mut S,
     iovecs: &'s [IoSlice<'s>],
     pathway: Pathway,
+    ecn: Option<u8>,
 }
 
 impl<S: Unpin + ?Sized> Unpin for SendAllViaPathWay<'_, S> {}
@@ -136,8 +154,12 @@ impl<S: ViaPathway + Unpin + ?Sized> Future for SendAllViaPathWay<'_, S> {
         let this = self.get_mut();
         let mut iovecs = this.iovecs;
         while !iovecs.is_empty() {
-            let send_once =
-                Pin::new(&mut *this.sender).poll_send_via_pathway(cx, iovecs, this.pathway);
+            let send_once = Pin::new(&mut *this.sender).poll_send_via_pathway(
+                cx,
+                iovecs,
+                this.pathway,
+                this.ecn,
+            );
             let n = ready!(send_once)?;
             iovecs = &iovecs[n..];
         }
@@ -153,24 +175,27 @@ impl ArcPath {
         usc: ArcUsc,
         scid: ConnectionId,
         dcid: ArcCidCell<ArcReliableFrameDeque>,
+        algorithm: CongestionAlgorithm,
         loss: Box<dyn Fn(Epoch, u64) + Send + Sync>,
         retire: Box<dyn Fn(Epoch, u64) + Send + Sync>,
     ) -> Self {
-        Self(Arc::new(RawPath::new(usc, scid, dcid, loss, retire)))
+        Self(Arc::new(RawPath::new(
+            usc, scid, dcid, algorithm, loss, retire,
+        )))
     }
 }
 
 #[derive(Deref, DerefMut)]
 pub struct Pathes {
     #[deref]
-    map: DashMap<Pathway, ArcPath>,
+    map: Arc<DashMap<Pathway, ArcPath>>,
     creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>,
 }
 
 impl Pathes {
     fn new(creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>) -> Self {
         Self {
-            map: DashMap::new(),
+            map: Arc::new(DashMap::new()),
             creator,
         }
     }
@@ -182,17 +207,25 @@ impl Pathes {
             .or_insert_with(|| {
                 let path = (self.creator)(pathway, usc);
                 let state = path.state.clone();
-                tokio::spawn({
+                // 收到新路径的包时创建路径，运行时关闭之后则不再计时
+                try_spawn_traced({
                     let state = state.clone();
                     let cc = path.cc.clone();
                     async move {
                         loop {
                             tokio::select! {
                                 _ = state.has_been_inactivated() => break,
-                                _ = tokio::time::sleep(std::time::Duration::from_millis(10)) => cc.do_tick(),
+                                _ = tokio::time::sleep(cc.max_wait().min(MAX_TICK_INTERVAL)) => cc.do_tick(),
+                            }
+                        }
+                        // 承载数据的路径失活了，改由剩下的路径承载
+                        if let Some((_, path)) = map_clone.remove(&pathway) {
+                            if !path.is_standby() {
+                                if let Some(entry) = map_clone.iter().next() {
+                                    entry.value().set_standby(false);
+                                }
                             }
                         }
-                        map_clone.remove(&pathway);
                     }
                 });
                 path
@@ -200,6 +233,22 @@ impl Pathes {
             .value()
             .clone()
     }
+
+    /// Make the path via `pathway` carry the data, and the others standby ones from then on,
+    /// see [`RawPath::set_standby`].
+    pub fn activate(&self, pathway: Pathway) {
+        for entry in self.map.iter() {
+            entry.value().set_standby(*entry.key() != pathway);
+        }
+    }
+
+    /// The pathway of the path carrying the data.
+    pub fn active_pathway(&self) -> Option<Pathway> {
+        self.map
+            .iter()
+            .find(|entry| !entry.value().is_standby())
+            .map(|entry| *entry.key())
+    }
 }
 
 #[derive(Clone, Deref, DerefMut)]
@@ -209,4 +258,45 @@ impl ArcPathes {
     pub fn new(creator: Box<dyn Fn(Pathway, ArcUsc) -> ArcPath + Send + Sync + 'static>) -> Self {
         Self(Arc::new(Pathes::new(creator)))
     }
+
+    /// Migrate the connection to the path via `pathway` once it's validated, the current
+    /// path keeps carrying the data until then. The new path has its own congestion control
+    /// state and RTT estimate, which start over as the RFC requires, see
+    /// [section 9.4](https://www.rfc-editor.org/rfc/rfc9000.html#section-9.4) of RFC 9000.
+    pub fn migrate(&self, pathway: Pathway) {
+        let Some(path) = self.get(&pathway).map(|entry| entry.value().clone()) else {
+            return;
+        };
+        let pathes = self.clone();
+        try_spawn_traced(async move {
+            if path.validated().await {
+                pathes.activate(pathway);
+                pathes.abandon_others(pathway).await;
+            }
+        });
+    }
+
+    /// Give up the paths other than the one via `pathway` after 3 PTOs, if it still carries
+    /// the data by then. The connection IDs they use are retired with the RETIRE_CONNECTION_ID
+    /// frames, so that the peer can't link the old paths to the new one, see
+    /// [section 9.5](https://www.rfc-editor.org/rfc/rfc9000.html#section-9.5) of RFC 9000.
+    ///
+    /// The PTOs leave time for the packets of the peer still in flight on the old paths.
+    pub async fn abandon_others(&self, pathway: Pathway) {
+        let Some(pto) = self
+            .get(&pathway)
+            .map(|entry| entry.value().cc.pto_time(Epoch::Data))
+        else {
+            return;
+        };
+        tokio::time::sleep(pto * 3).await;
+        if self.active_pathway() != Some(pathway) {
+            return;
+        }
+        for entry in self.map.iter() {
+            if *entry.key() != pathway {
+                entry.value().abandon();
+            }
+        }
+    }
 }
@@ -2,7 +2,7 @@ use std::{
     future::Future,
     pin::Pin,
     sync::{
-        atomic::{AtomicU8, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
         Arc,
     },
     task::{Context, Poll},
@@ -24,6 +24,8 @@ pub struct AntiAmplifier<const N: usize> {
     // new data is received before it can continue to send.
     waker: AtomicWaker,
     state: AtomicU8,
+    // Whether the sending is blocked since the credit was exhausted last time.
+    blocked: AtomicBool,
 }
 
 impl<const N: usize> AntiAmplifier<N> {
@@ -37,6 +39,7 @@ impl<const N: usize> AntiAmplifier<N> {
             return;
         }
         self.credit.fetch_add(amount * N, Ordering::AcqRel);
+        self.blocked.store(false, Ordering::Release);
         self.waker.wake();
     }
 
@@ -71,12 +74,23 @@ impl<const N: usize> AntiAmplifier<N> {
         }
     }
 
+    /// Called when the sending is blocked by [`AntiAmplifier::poll_balance`], returns true
+    /// only the first time after the credit is exhausted, until new data is received.
+    pub fn on_blocked(&self) -> bool {
+        !self.blocked.swap(true, Ordering::AcqRel)
+    }
+
     pub fn on_sent(&self, amount: usize) {
         if self.state.load(Ordering::Acquire) == Self::NORMAL {
             self.credit.fetch_sub(amount, Ordering::AcqRel);
         }
     }
 
+    /// Whether the path is validated, or the peer's address is validated otherwise.
+    pub fn is_granted(&self) -> bool {
+        self.state.load(Ordering::Acquire) == Self::GRANTED
+    }
+
     pub fn grant(&self) {
         if self
             .state
@@ -177,4 +191,14 @@ mod tests {
         anti_amplifier.on_sent(5);
         assert_eq!(anti_amplifier.credit.load(Ordering::Acquire), 1);
     }
+
+    #[test]
+    fn test_on_blocked() {
+        let anti_amplifier = ArcAntiAmplifier::<3>::default();
+        assert!(anti_amplifier.on_blocked());
+        assert!(!anti_amplifier.on_blocked());
+
+        anti_amplifier.on_rcvd(1);
+        assert!(anti_amplifier.on_blocked());
+    }
 }
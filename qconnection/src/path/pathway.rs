@@ -1,5 +1,7 @@
 use std::{hash::Hash, net::SocketAddr};
 
+use qbase::util::canonical_addr;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct RelayAddr {
     pub agent: SocketAddr, // 代理人
@@ -19,7 +21,39 @@ pub enum Pathway {
     },
 }
 
+impl RelayAddr {
+    fn canonical(self) -> Self {
+        RelayAddr {
+            agent: canonical_addr(self.agent),
+            addr: canonical_addr(self.addr),
+        }
+    }
+}
+
 impl Pathway {
+    pub fn direct(local: SocketAddr, remote: SocketAddr) -> Self {
+        Pathway::Direct { local, remote }
+    }
+
+    /// The pathway with the IPv4-mapped addresses received by the dual-stack sockets
+    /// canonicalized, see [`canonical_addr`].
+    ///
+    /// Whether a connection canonicalizes its pathways is decided when it's created,
+    /// see [`PacketEntries`](crate::router::PacketEntries), so that the same pathway
+    /// never changes its identity in the paths of the connection.
+    pub fn canonical(self) -> Self {
+        match self {
+            Pathway::Direct { local, remote } => Pathway::Direct {
+                local: canonical_addr(local),
+                remote: canonical_addr(remote),
+            },
+            Pathway::Relay { local, remote } => Pathway::Relay {
+                local: local.canonical(),
+                remote: remote.canonical(),
+            },
+        }
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         match self {
             Pathway::Direct { local, .. } => *local,
@@ -44,13 +78,20 @@ impl Pathway {
 
 impl PartialEq for Pathway {
     fn eq(&self, other: &Self) -> bool {
+        // 双栈socket绑定在[::]上，也会收到规范化成IPv4的地址
+        let same_family = |l: &SocketAddr, r: &SocketAddr| {
+            l.is_ipv4() == r.is_ipv4()
+                || l.ip().is_unspecified() && l.is_ipv6()
+                || r.ip().is_unspecified() && r.is_ipv6()
+        };
         let match_local = |l: &SocketAddr, r: &SocketAddr| {
             if l.ip().is_unspecified() | r.ip().is_unspecified() {
-                l.is_ipv4() == r.is_ipv4() && l.port() == r.port()
+                same_family(l, r) && l.port() == r.port()
             } else {
                 l == r
             }
         };
+        let match_remote = |l: &SocketAddr, r: &SocketAddr| l == r;
         match (self, other) {
             (
                 Self::Direct {
@@ -61,7 +102,7 @@ impl PartialEq for Pathway {
                     local: r_local,
                     remote: r_remote,
                 },
-            ) => l_remote == r_remote && match_local(l_local, r_local),
+            ) => match_remote(l_remote, r_remote) && match_local(l_local, r_local),
             (
                 Self::Relay {
                     local: l_local,
@@ -72,9 +113,10 @@ impl PartialEq for Pathway {
                     remote: r_remote,
                 },
             ) => {
-                l_remote == r_remote
+                match_remote(&l_remote.addr, &r_remote.addr)
+                    && match_remote(&l_remote.agent, &r_remote.agent)
                     && match_local(&l_local.addr, &r_local.addr)
-                    && l_local.agent == r_local.agent
+                    && match_remote(&l_local.agent, &r_local.agent)
             }
             _ => false,
         }
@@ -237,6 +279,55 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_mapped_canonical() {
+        let v4_local: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let v6_unspec: SocketAddr = "[::]:4433".parse().unwrap();
+        let mapped_local: SocketAddr = "[::ffff:127.0.0.1]:4433".parse().unwrap();
+        let v4_client: SocketAddr = "192.168.1.2:5678".parse().unwrap();
+        let mapped_client: SocketAddr = "[::ffff:192.168.1.2]:5678".parse().unwrap();
+
+        // 同一个IPv4客户端，分别经由IPv4 socket和双栈socket收到
+        let via_v4 = Pathway::direct(v4_local, v4_client).canonical();
+        let via_dual_stack = Pathway::direct(mapped_local, mapped_client).canonical();
+        assert_eq!(via_v4.remote_addr(), v4_client);
+        assert_eq!(via_dual_stack.local_addr(), v4_local);
+        assert_eq!(via_dual_stack.remote_addr(), v4_client);
+        assert_eq!(via_v4, via_dual_stack);
+        // 客户端用usc的地址[::]创建的路径，也是同一条
+        let bound = Pathway::direct(v6_unspec, mapped_client).canonical();
+        assert_eq!(bound, via_dual_stack);
+
+        let pathes = dashmap::DashMap::new();
+        for pathway in [via_v4, via_dual_stack, bound] {
+            *pathes.entry(pathway).or_insert(0) += 1;
+        }
+        assert_eq!(pathes.len(), 1);
+        assert_eq!(*pathes.get(&via_v4).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_mapped_strict() {
+        let v4_local: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let mapped_local: SocketAddr = "[::ffff:127.0.0.1]:4433".parse().unwrap();
+        let v4_client: SocketAddr = "192.168.1.2:5678".parse().unwrap();
+        let mapped_client: SocketAddr = "[::ffff:192.168.1.2]:5678".parse().unwrap();
+
+        // 不规范化的连接，两种形式是不同的路径
+        let via_v4 = Pathway::direct(v4_local, v4_client);
+        let via_dual_stack = Pathway::direct(mapped_local, mapped_client);
+        assert_ne!(via_v4, via_dual_stack);
+        assert_eq!(via_dual_stack.remote_addr(), mapped_client);
+
+        let pathes = dashmap::DashMap::new();
+        for pathway in [via_v4, via_dual_stack, via_v4] {
+            *pathes.entry(pathway).or_insert(0) += 1;
+        }
+        assert_eq!(pathes.len(), 2);
+        assert_eq!(*pathes.get(&via_v4).unwrap(), 2);
+        assert_eq!(*pathes.get(&via_dual_stack).unwrap(), 1);
+    }
+
     #[test]
     fn test_hash() {
         let mut map = HashMap::new();
@@ -1,31 +1,44 @@
 use std::{
+    io,
     ops::Deref,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time::{self, Duration},
 };
 
+use futures::FutureExt;
 use qbase::{
     cid::{ArcCidCell, ConnectionId},
     flow::FlowController,
-    frame::{PathChallengeFrame, PathResponseFrame},
+    frame::{BeFrame, ObservedAddressFrame, PathChallengeFrame, PathResponseFrame},
+    util::try_spawn_traced,
 };
 use qcongestion::{
-    congestion::{ArcCC, CongestionAlgorithm},
+    congestion::{ArcCC, CongestionAlgorithm, MSS},
     CongestionControl,
 };
 use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
 use qudp::ArcUsc;
-use tokio::time::timeout;
+use tokio::{sync::watch, time::timeout};
 
 use super::{
     anti_amplifier::{ArcAntiAmplifier, ANTI_FACTOR},
     read::ReadIntoDatagrams,
     state::ArcPathState,
     util::{RecvBuffer, SendBuffer},
-    Pathway, ViaPathWayExt,
+    Pathway, ViaPathWayExt, ViaPathway,
 };
-use crate::connection::transmit::{
-    data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+use crate::{
+    connection::{
+        activity::ArcActivity,
+        overhead::{ArcOverheadBudget, OverheadKind},
+        transmit::{
+            data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+        },
+    },
+    drops::ArcDropCounters,
 };
 
 #[derive(Clone)]
@@ -39,7 +52,20 @@ pub struct RawPath {
     pub(super) challenge_sndbuf: SendBuffer<PathChallengeFrame>,
     pub(super) response_sndbuf: SendBuffer<PathResponseFrame>,
     pub(super) response_rcvbuf: RecvBuffer<PathResponseFrame>,
+    // 向对端报告其在此路径上的地址的OBSERVED_ADDRESS帧
+    pub(super) observed_sndbuf: SendBuffer<ObservedAddressFrame>,
     pub(super) state: ArcPathState,
+    // 是否从对端收到过任何包，用于区分对端不可达和握手超时
+    pub(super) rcvd: Arc<AtomicBool>,
+    // 在此路径上收发的字节数，抗放大限制依此计算，验证地址之前发送不得超过接收的3倍
+    pub(super) bytes_rcvd: Arc<AtomicU64>,
+    pub(super) bytes_sent: Arc<AtomicU64>,
+    // 发送的数据报的最大长度，通常是MSS
+    pub(super) max_datagram_size: Arc<AtomicUsize>,
+    // 备用的路径只发探测帧与ACK，不承载数据，见RawPath::set_standby
+    pub(super) standby: Arc<AtomicBool>,
+    // 路径验证的结果，尚未验证完成时为None
+    pub(super) validation: Arc<watch::Sender<Option<bool>>>,
 }
 
 impl RawPath {
@@ -47,6 +73,7 @@ impl RawPath {
         usc: ArcUsc,
         scid: ConnectionId,
         dcid: ArcCidCell<ArcReliableFrameDeque>,
+        algorithm: CongestionAlgorithm,
         loss: Box<dyn Fn(Epoch, u64) + Send + Sync>,
         retire: Box<dyn Fn(Epoch, u64) + Send + Sync>,
     ) -> Self {
@@ -54,21 +81,29 @@ impl RawPath {
             usc,
             dcid: dcid.clone(),
             scid,
-            cc: ArcCC::new(
-                CongestionAlgorithm::Bbr,
-                Duration::from_micros(100),
-                loss,
-                retire,
-            ),
+            cc: ArcCC::new(algorithm, Duration::from_micros(100), loss, retire),
             anti_amplifier: ArcAntiAmplifier::<ANTI_FACTOR>::default(),
             spin: Arc::new(AtomicBool::new(false)),
             challenge_sndbuf: SendBuffer::default(),
             response_sndbuf: SendBuffer::default(),
             response_rcvbuf: RecvBuffer::default(),
+            observed_sndbuf: SendBuffer::default(),
             state: ArcPathState::new(dcid),
+            rcvd: Arc::new(AtomicBool::new(false)),
+            bytes_rcvd: Arc::new(AtomicU64::new(0)),
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+            max_datagram_size: Arc::new(AtomicUsize::new(MSS)),
+            standby: Arc::new(AtomicBool::new(false)),
+            validation: Arc::new(watch::channel(None).0),
         }
     }
 
+    /// Send a datagram on this path at once, out of the congestion control, such as the
+    /// packet carrying the CONNECTION_CLOSE frame when the connection enters the closing state.
+    pub fn send_datagram(&self, datagram: Vec<u8>, pathway: Pathway) -> io::Result<()> {
+        self.usc.clone().sync_send_via_path_way(datagram, pathway)
+    }
+
     pub fn recv_response(&self, frame: PathResponseFrame) {
         self.response_rcvbuf.write(frame);
     }
@@ -78,7 +113,8 @@ impl RawPath {
         self.response_sndbuf.write(frame.into());
     }
 
-    pub fn begin_validation(&self) {
+    pub fn begin_validation(&self, overhead: &ArcOverheadBudget) {
+        let overhead = overhead.clone();
         let anti_amplifier = self.anti_amplifier.clone();
         let challenge_sndbuf = self.challenge_sndbuf.clone();
         let response_rcvbuf = self.response_rcvbuf.clone();
@@ -86,14 +122,19 @@ impl RawPath {
         let congestion_ctrl = self.cc.clone();
         let state = self.state.clone();
         let cid = self.dcid.get_cid();
-        tokio::spawn(async move {
+        let validation = self.validation.clone();
+        try_spawn_traced(async move {
             let challenge = PathChallengeFrame::random();
             for _ in 0..3 {
                 let pto = congestion_ctrl.pto_time(Epoch::Data);
-                challenge_sndbuf.write(challenge);
+                // 超出了开销预算，这次不发Challenge，当作一次没有回应的尝试
+                if overhead.try_draw(OverheadKind::PathValidation, challenge.encoding_size()) {
+                    challenge_sndbuf.write(challenge);
+                }
                 match timeout(pto, response_rcvbuf.receive()).await {
                     Ok(Some(response)) if *response == *challenge => {
                         anti_amplifier.grant();
+                        validation.send_replace(Some(true));
                         return;
                     }
                     // 外部发生变化，导致路径验证任务作废
@@ -103,17 +144,26 @@ impl RawPath {
                 }
             }
             anti_amplifier.abort();
+            validation.send_replace(Some(false));
             state.to_inactive(cid);
         });
     }
 
-    pub fn begin_sending<G>(&self, pathway: Pathway, flow_ctrl: &FlowController, gen_readers: G)
-    where
+    pub fn begin_sending<G>(
+        &self,
+        pathway: Pathway,
+        flow_ctrl: &FlowController,
+        drops: &ArcDropCounters,
+        activity: &ArcActivity,
+        gen_readers: G,
+    ) where
         G: Fn(&RawPath) -> (InitialSpaceReader, HandshakeSpaceReader, DataSpaceReader),
     {
         let mut usc = self.usc.clone();
         let state = self.state.clone();
         let cid = self.dcid.get_cid();
+        let activity = activity.clone();
+        let bytes_sent = self.bytes_sent.clone();
         let space_readers = gen_readers(self);
         let read_into_datagram = ReadIntoDatagrams {
             scid: self.scid,
@@ -125,17 +175,24 @@ impl RawPath {
             initial_space_reader: space_readers.0.clone(),
             handshake_space_reader: space_readers.1.clone(),
             data_space_reader: space_readers.2.clone(),
+            pathway,
+            drops: drops.clone(),
+            max_datagram_size: self.max_datagram_size.clone(),
+            standby: self.standby.clone(),
         };
 
-        tokio::spawn(async move {
+        try_spawn_traced(async move {
             let mut datagrams = Vec::with_capacity(4);
 
-            while let Some(iovec) = read_into_datagram.read(&mut datagrams).await {
-                let send_result = usc.send_all_via_pathway(&iovec, pathway).await;
+            while let Some((iovec, ecn)) = read_into_datagram.read(&mut datagrams).await {
+                let send_result = usc.send_all_via_pathway(&iovec, pathway, ecn.bits()).await;
                 if send_result.is_err() {
                     state.to_inactive(cid);
                     return;
                 }
+                let sent: u64 = iovec.iter().map(|datagram| datagram.len() as u64).sum();
+                bytes_sent.fetch_add(sent, Ordering::Relaxed);
+                activity.on_sent();
             }
         });
     }
@@ -148,8 +205,107 @@ impl RawPath {
         self.response_sndbuf.clone()
     }
 
+    pub fn observed_sndbuf(&self) -> SendBuffer<ObservedAddressFrame> {
+        self.observed_sndbuf.clone()
+    }
+
+    /// The source and destination connection ID of this path, the destination one is
+    /// None if it has not been assigned yet.
+    pub fn cids(&self) -> (ConnectionId, Option<ConnectionId>) {
+        (self.scid, self.dcid.get_cid().now_or_never().flatten())
+    }
+
     /// Sets the receive time to the current instant.
     pub fn update_recv_time(&self) {
         *self.state.deref().lock().unwrap() = time::Instant::now();
+        self.rcvd.store(true, Ordering::Relaxed);
+    }
+
+    /// Called when a packet of `amount` bytes is received on this path, the packet is
+    /// credited to the anti-amplification limit before the path is validated, which may
+    /// unblock the sending.
+    pub fn on_rcvd(&self, amount: usize) {
+        self.update_recv_time();
+        self.bytes_rcvd.fetch_add(amount as u64, Ordering::Relaxed);
+        self.anti_amplifier.on_rcvd(amount);
+        self.cc.on_datagram_rcvd();
+    }
+
+    /// The bytes of the packets received on this path, and the bytes of the datagrams sent.
+    pub fn traffic(&self) -> (u64, u64) {
+        (
+            self.bytes_rcvd.load(Ordering::Relaxed),
+            self.bytes_sent.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Whether anything has ever been received from the peer on this path.
+    pub fn has_rcvd(&self) -> bool {
+        self.rcvd.load(Ordering::Relaxed)
+    }
+
+    /// When a packet was received on this path most recently, None if nothing is received.
+    pub fn last_recv_time(&self) -> Option<time::Instant> {
+        self.has_rcvd().then(|| *self.state.deref().lock().unwrap())
+    }
+
+    /// The maximum size of the datagrams sent on this path, [`MSS`] unless it's reduced by
+    /// [`RawPath::set_max_datagram_size`].
+    pub fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size.load(Ordering::Relaxed)
+    }
+
+    /// Send smaller datagrams than [`MSS`] on this path, including the Initial packets which
+    /// are padded to the size only. It's out of the spec, which requires the datagrams
+    /// carrying Initial packets to be at least 1200 bytes, and only meant for the private
+    /// deployments over the paths that can't carry 1200-byte datagrams.
+    ///
+    /// It takes effect from the next datagrams, the size is capped at [`MSS`].
+    pub fn set_max_datagram_size(&self, size: usize) {
+        self.max_datagram_size
+            .store(size.min(MSS), Ordering::Relaxed);
+    }
+
+    /// Whether the path is a standby one, see [`RawPath::set_standby`].
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Relaxed)
+    }
+
+    /// A standby path only sends the probing frames and the ACK frames, but no data, such as
+    /// a new path being validated, or the old path after the connection migrates, which still
+    /// answers the probes of the peer. It takes effect from the next packets.
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Relaxed);
+    }
+
+    /// Resolves to whether the path is validated by [`RawPath::begin_validation`], or the
+    /// address of the peer is validated otherwise already, e.g. during the handshake.
+    pub async fn validated(&self) -> bool {
+        if self.anti_amplifier.is_granted() {
+            return true;
+        }
+        self.challenge_answered().await
+    }
+
+    /// Resolves to whether the PATH_CHALLENGE sent by [`RawPath::begin_validation`] is answered,
+    /// unlike [`RawPath::validated`], it ignores the address validated otherwise.
+    pub async fn challenge_answered(&self) -> bool {
+        let mut validation = self.validation.subscribe();
+        tokio::select! {
+            result = validation.wait_for(Option::is_some) => {
+                result.is_ok_and(|validated| *validated == Some(true))
+            }
+            _ = self.inactivated() => false,
+        }
+    }
+
+    /// Give up this path, it will be removed from the connection.
+    pub fn abandon(&self) {
+        self.state.to_inactive(self.dcid.clone());
+    }
+
+    /// Resolves once the path has been inactivated.
+    pub async fn inactivated(&self) {
+        self.state.has_been_inactivated().await
     }
 }
@@ -1,7 +1,7 @@
 use std::{
     io::IoSlice,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     task::{ready, Context, Poll},
@@ -10,23 +10,36 @@ use std::{
 use qbase::{
     cid::{ArcCidCell, ConnectionId},
     flow::ArcSendControler,
-    packet::SpinBit,
+    packet::{
+        encrypt::{encode_short_first_byte, PacketBuf, SealKeys},
+        keys::ArcOneRttPacketKeys,
+        SpinBit,
+    },
 };
 use qcongestion::{
-    congestion::{ArcCC, MSS},
+    congestion::{ArcCC, PacketClass, MSS},
+    ecn::EcnCodepoint,
     CongestionControl,
 };
 use qrecovery::{reliable::ArcReliableFrameDeque, space::Epoch};
+use rustls::quic::HeaderProtectionKey;
 
 use super::{
     anti_amplifier::ANTI_FACTOR,
     util::{ApplyConstraints, Constraints},
-    ArcAntiAmplifier,
+    ArcAntiAmplifier, Pathway,
 };
-use crate::connection::transmit::{
-    data::DataSpaceReader, handshake::HandshakeSpaceReader, initial::InitialSpaceReader,
+use crate::{
+    connection::transmit::{
+        data::{DataSpaceReader, UnsealedPacket},
+        handshake::HandshakeSpaceReader,
+        initial::InitialSpaceReader,
+    },
+    drops::{ArcDropCounters, DropReason},
 };
 
+type OneRttKeys = (Arc<dyn HeaderProtectionKey>, ArcOneRttPacketKeys);
+
 pub struct ReadIntoDatagrams {
     pub(super) scid: ConnectionId,
     pub(super) dcid: ArcCidCell<ArcReliableFrameDeque>,
@@ -37,33 +50,58 @@ pub struct ReadIntoDatagrams {
     pub(super) initial_space_reader: InitialSpaceReader,
     pub(super) handshake_space_reader: HandshakeSpaceReader,
     pub(super) data_space_reader: DataSpaceReader,
+    pub(super) pathway: Pathway,
+    pub(super) drops: ArcDropCounters,
+    pub(super) max_datagram_size: Arc<AtomicUsize>,
+    pub(super) standby: Arc<AtomicBool>,
 }
 
 impl ReadIntoDatagrams {
+    /// Returns (datagram_size, fresh_bytes, unsealed 1-RTT packet in the datagram)
     fn read_into_datagram(
         &self,
         constraints: &mut Constraints,
         flow_limit: usize,
         datagram: &mut [u8],
         dcid: ConnectionId,
-    ) -> (usize, usize) {
+        ecn: EcnCodepoint,
+        one_rtt_keys: Option<&OneRttKeys>,
+    ) -> (usize, usize, Option<UnsealedPacket>) {
+        let datagram_size = datagram.len();
         let buffer = datagram.apply(constraints);
 
         let ack_pkt = self.cc.need_ack(Epoch::Initial);
+        let probe = self.cc.need_probe(Epoch::Initial);
         // 按顺序发，先发Initial空间的，到Initial数据包
         if let Some((padding, len, is_just_ack)) = self
             .initial_space_reader
-            .try_read(buffer, self.scid, dcid, ack_pkt)
+            .try_read(buffer, self.scid, dcid, ack_pkt, probe)
         {
             // 若真的只包含ack， 后续只会追加padding，追加的padding也可以看成是新的InitialPacket数据包
             constraints.commit(len, is_just_ack);
 
-            let (wrote, fresh_bytes) = {
+            let (wrote, fresh_bytes, mut unsealed) = {
                 let remain = &mut buffer[len..];
-                self.read_other_space(constraints, flow_limit, remain, dcid)
+                self.read_other_space(constraints, flow_limit, remain, dcid, ecn, one_rtt_keys)
             };
+            if let Some(packet) = unsealed.as_mut() {
+                packet.offset += len;
+            }
 
-            let padding_len = if wrote == 0 { 1200 } else { 0 };
+            // 携带CRYPTO数据的Initial包所在的数据报要填充到最大，其后合并了其他包的，比如0-RTT包，
+            // 已加密的它们挪到数据报末尾，空出来的位置填充到Initial包中，见RFC 9000 14.1
+            let padding_len = if wrote == 0 {
+                datagram_size
+            } else if !is_just_ack && len + wrote < buffer.len() {
+                let end = buffer.len();
+                buffer.copy_within(len..len + wrote, end - wrote);
+                if let Some(packet) = unsealed.as_mut() {
+                    packet.offset += end - wrote - len;
+                }
+                end - wrote
+            } else {
+                0
+            };
             let (pn, is_ack_eliciting, is_just_ack, sent_bytes, in_flight, sent_ack) =
                 padding(buffer, padding_len);
             self.cc.on_pkt_sent(
@@ -73,12 +111,14 @@ impl ReadIntoDatagrams {
                 sent_bytes,
                 in_flight,
                 sent_ack,
+                ecn,
+                PacketClass::Normal,
             );
             // 减除initial数据包已经commit的
             constraints.commit(sent_bytes - len, is_just_ack);
-            (wrote + sent_bytes, fresh_bytes)
+            (wrote + sent_bytes, fresh_bytes, unsealed)
         } else {
-            self.read_other_space(constraints, flow_limit, buffer, dcid)
+            self.read_other_space(constraints, flow_limit, buffer, dcid, ecn, one_rtt_keys)
         }
     }
 
@@ -88,11 +128,12 @@ impl ReadIntoDatagrams {
         flow_limit: usize,
         mut buffer: &mut [u8],
         dcid: ConnectionId,
-    ) -> (usize, usize) {
+        ecn: EcnCodepoint,
+        one_rtt_keys: Option<&OneRttKeys>,
+    ) -> (usize, usize, Option<UnsealedPacket>) {
         // 在发0Rtt数据包，但是0Rtt数据包要看有没有获取到1rtt的密钥o
         let mut written = 0;
         let mut fresh_bytes = 0;
-        let one_rtt_keys = self.data_space_reader.one_rtt_keys();
         if one_rtt_keys.is_none() {
             if let Some((pn, is_ack_eliciting, sent_bytes, fresh_len, in_flight)) = self
                 .data_space_reader
@@ -105,6 +146,8 @@ impl ReadIntoDatagrams {
                     sent_bytes,
                     in_flight,
                     None,
+                    ecn,
+                    PacketClass::Normal,
                 );
                 buffer = &mut buffer[sent_bytes..];
                 // 0Rtt数据包不会发送Ack
@@ -116,50 +159,49 @@ impl ReadIntoDatagrams {
 
         buffer = buffer.apply(constraints);
         if buffer.is_empty() {
-            return (written, fresh_bytes);
+            return (written, fresh_bytes, None);
         }
 
         // 再尝试写handshake空间的
-        let n = self.read_handshake_space(constraints, buffer, dcid);
+        let n = self.read_handshake_space(constraints, buffer, dcid, ecn);
         written += n;
         buffer = &mut buffer[n..];
         buffer = buffer.apply(constraints);
         if buffer.is_empty() {
-            return (written, fresh_bytes);
+            return (written, fresh_bytes, None);
         }
 
-        // 最后尝试写1rtt数据包
-        if let Some(keys) = one_rtt_keys {
+        // 最后尝试写1rtt数据包，1rtt包总是数据报中的最后一个包
+        let mut unsealed = None;
+        if let Some((_, pk)) = one_rtt_keys {
             let ack_pkt = self.cc.need_ack(Epoch::Data);
+            let probe = self.cc.need_probe(Epoch::Data);
             let spin = self.spin.load(Ordering::Relaxed);
             let spin = SpinBit::from(spin);
-            if let Some((
-                pn,
-                is_ack_eliciting,
-                is_just_ack,
-                sent_bytes,
-                fresh_len,
-                in_flight,
-                sent_ack,
-            )) = self
-                .data_space_reader
-                .try_read_1rtt(buffer, flow_limit, dcid, spin, ack_pkt, keys)
+            let standby = self.standby.load(Ordering::Relaxed);
+            if let Some((mut packet, is_ack_eliciting, is_just_ack, fresh_len, in_flight, class)) =
+                self.data_space_reader
+                    .try_read_1rtt(buffer, flow_limit, dcid, spin, ack_pkt, probe, standby, pk)
             {
                 self.cc.on_pkt_sent(
                     Epoch::Data,
-                    pn,
+                    packet.pn,
                     is_ack_eliciting,
-                    sent_bytes,
+                    packet.size,
                     in_flight,
-                    sent_ack,
+                    packet.acked,
+                    ecn,
+                    class,
                 );
-                constraints.commit(sent_bytes, is_just_ack);
-                written += sent_bytes;
+                constraints.commit(packet.size, is_just_ack);
+                packet.offset += written;
+                written += packet.size;
                 fresh_bytes += fresh_len;
+                unsealed = Some(packet);
             }
         }
 
-        (written, fresh_bytes)
+        (written, fresh_bytes, unsealed)
     }
 
     fn read_handshake_space(
@@ -167,12 +209,14 @@ impl ReadIntoDatagrams {
         constraints: &mut Constraints,
         buffer: &mut [u8],
         dcid: ConnectionId,
+        ecn: EcnCodepoint,
     ) -> usize {
         // 再尝试写handshake空间的
         let ack_pkt = self.cc.need_ack(Epoch::Handshake);
+        let probe = self.cc.need_probe(Epoch::Handshake);
         if let Some((pn, is_ack_eliciting, is_just_ack, sent_bytes, in_flight, sent_ack)) = self
             .handshake_space_reader
-            .try_read(buffer, self.scid, dcid, ack_pkt)
+            .try_read(buffer, self.scid, dcid, ack_pkt, probe)
         {
             self.cc.on_pkt_sent(
                 Epoch::Handshake,
@@ -181,6 +225,8 @@ impl ReadIntoDatagrams {
                 sent_bytes,
                 in_flight,
                 sent_ack,
+                ecn,
+                PacketClass::Normal,
             );
             constraints.commit(sent_bytes, is_just_ack);
             return sent_bytes;
@@ -192,12 +238,26 @@ impl ReadIntoDatagrams {
         &self,
         cx: &mut Context<'_>,
         buffers: &mut Vec<[u8; MSS]>,
-    ) -> Poll<Option<(usize, usize)>> {
-        let Poll::Ready(Some(dcid)) = self.dcid.poll_get_cid(cx) else {
+    ) -> Poll<Option<(usize, usize, usize, EcnCodepoint)>> {
+        // 对方尚未提供可用的连接ID时，等待之，而非结束发送任务；只有连接ID被淘汰了才结束
+        let Some(dcid) = ready!(self.dcid.poll_get_cid(cx)) else {
             return Poll::Ready(None);
         };
         let send_quota = ready!(self.cc.poll_send(cx));
-        let credit_limit = ready!(self.anti_amplifier.poll_balance(cx));
+        let credit_limit = match self.anti_amplifier.poll_balance(cx) {
+            Poll::Ready(credit_limit) => credit_limit,
+            Poll::Pending => {
+                // 每次信用耗尽只记一次，直到收到新数据恢复信用
+                if self.anti_amplifier.on_blocked() {
+                    let remote = self.pathway.remote_addr();
+                    self.drops
+                        .on_dropped(DropReason::AntiAmplification, remote, &[]);
+                    // 发不出探测包，暂停PTO以免死锁时白白耗尽PTO次数，收到新数据后再恢复
+                    self.cc.on_amplification_limited();
+                }
+                return Poll::Pending;
+            }
+        };
         let Some(credit_limit) = credit_limit else {
             return Poll::Pending;
         };
@@ -210,7 +270,15 @@ impl ReadIntoDatagrams {
             return Poll::Ready(None);
         };
         let flow_limit = send_flow_credit.available();
+        // 通常是MSS，路径不支持1200字节的数据报时可能被调小，见RawPath::set_max_datagram_size
+        let max_datagram_size = self.max_datagram_size.load(Ordering::Relaxed);
+        // 发送配额是pacer放行的一块额度，一次装填成一批数据报，借GSO一并发出
         let mut constraints = Constraints::new(credit_limit, send_quota);
+        // 一批数据报一次发出，共用一个ECN标记，逐包记录在cc中以验证对方报告的计数
+        let ecn = self.cc.ecn_codepoint();
+        // 一批数据报中的1rtt包，用同一代密钥一并加密
+        let one_rtt_keys = self.data_space_reader.one_rtt_keys();
+        let mut unsealed = Vec::new();
 
         // 遍历，填充每一个包
 
@@ -228,17 +296,30 @@ impl ReadIntoDatagrams {
                     &mut buffers[buffers_used]
                 }
             };
+            let datagram = &mut datagram[..max_datagram_size];
 
-            let (datagram_size, fresh_bytes) =
-                self.read_into_datagram(&mut constraints, flow_limit, datagram, dcid);
+            let (datagram_size, fresh_bytes, packet) = self.read_into_datagram(
+                &mut constraints,
+                // 同一批数据报共用流量控制的额度
+                flow_limit - total_fresh_bytes,
+                datagram,
+                dcid,
+                ecn,
+                one_rtt_keys.as_ref(),
+            );
             // 啥也没读到，就结束吧
             // TODO: 若因没有数据可发，将waker挂载到数据控制器上一份，包括帧数据、流数据，
             //       一旦有任何数据发送，唤醒该任务发一次
             if datagram_size == 0 {
+                // 拥塞控制允许发送却无数据可发，告知cc此后的带宽采样受限于应用
+                if constraints.is_quota_available(max_datagram_size) {
+                    self.cc.on_app_limited();
+                }
                 break;
             }
             total_bytes += datagram_size;
             total_fresh_bytes += fresh_bytes;
+            unsealed.push(packet);
             buffers_used += 1;
             last_buffer_written = datagram_size;
 
@@ -246,43 +327,96 @@ impl ReadIntoDatagrams {
             match remaining.len() {
                 0 => continue,
                 // 如果数据报没有没填满，需要填充padding帧，否则datagram会被之前的数据污染
-                len if len == MSS - datagram_size => {
+                len if len == max_datagram_size - datagram_size => {
                     /* use qbase::frame::io::WriteFrame;
                     use qbase::frame::PaddingFrame;
                     for _ in 0..remaining.remaining_mut() {
                         remaining.put_frame(&PaddingFrame);
                     } */
                     remaining.fill(0);
-                    constraints.commit(MSS - datagram_size, false);
+                    constraints.commit(max_datagram_size - datagram_size, false);
                 }
                 // 如果拥塞控制，抗放大限制不允许填充帧，那本次装填就此结结束
                 _ => break,
             }
         }
+        // 这一轮没能发出的探测包，比如密钥尚未到手的空间的，不再补发
+        self.cc.discard_probes();
 
         if buffers_used == 0 {
             // 就算Constraints允许发送，但也不一定真的有数据供发送
             return Poll::Pending;
         }
+        if let Some(keys) = one_rtt_keys {
+            seal_batch(buffers, &unsealed, keys);
+        }
 
         // 最终将要发送前，反馈给各个限制条件。除了拥塞控制的，在每个Epoch发包后，都已直接反馈给cc过了
         self.anti_amplifier.on_sent(total_bytes);
         send_flow_credit.post_sent(total_fresh_bytes);
         // 返回这个后，datagrams肯定等着被发送了
-        Poll::Ready(Some((buffers_used, last_buffer_written)))
+        Poll::Ready(Some((
+            buffers_used,
+            last_buffer_written,
+            max_datagram_size,
+            ecn,
+        )))
     }
 
-    pub async fn read<'ds>(&self, buffers: &'ds mut Vec<[u8; MSS]>) -> Option<Vec<IoSlice<'ds>>> {
-        let (buffers_used, last_buffer_written) =
+    /// Assemble the datagrams into `buffers`, return the slices to be sent, and the ECN
+    /// codepoint they must be sent with.
+    ///
+    /// The frames, including the payload of the streams and datagrams, are written
+    /// straight into the datagram buffers, and then encrypted in place. The payload is
+    /// copied only once, from the send buffer to the datagram, no staging buffer between.
+    pub async fn read<'ds>(
+        &self,
+        buffers: &'ds mut Vec<[u8; MSS]>,
+    ) -> Option<(Vec<IoSlice<'ds>>, EcnCodepoint)> {
+        let (buffers_used, last_buffer_written, max_datagram_size, ecn) =
             core::future::poll_fn(|cx| self.poll_read_inner(cx, buffers)).await?;
 
         debug_assert!(buffers_used > 0);
         let datagrams = (0..buffers_used - 1)
-            .map(|i| IoSlice::new(&buffers[i]))
+            .map(|i| IoSlice::new(&buffers[i][..max_datagram_size]))
             .chain(Some(IoSlice::new(
                 &buffers[buffers_used - 1][..last_buffer_written],
             )))
             .collect::<Vec<_>>();
-        Some(datagrams)
+        Some((datagrams, ecn))
+    }
+}
+
+/// Seal the 1-RTT packets of a batch in place, each datagram of `buffers` carries at most one
+/// of them, as `unsealed` tells.
+///
+/// The key phase and the keys are taken once for the whole batch, a key update never happens
+/// in the middle of it, see [`OneRttPacketKeys::seal_batch`](qbase::packet::keys::OneRttPacketKeys::seal_batch).
+fn seal_batch(
+    buffers: &mut [[u8; MSS]],
+    unsealed: &[Option<UnsealedPacket>],
+    (hpk, pk): OneRttKeys,
+) {
+    if unsealed.iter().all(Option::is_none) {
+        return;
     }
+    let (key_phase, pk) = pk
+        .lock_guard()
+        .seal_batch(unsealed.iter().flatten().map(|p| (p.pn, p.acked)));
+    let mut packets = buffers
+        .iter_mut()
+        .zip(unsealed)
+        .filter_map(|(datagram, packet)| {
+            let packet = packet.as_ref()?;
+            let buf = &mut datagram[packet.offset..packet.offset + packet.size];
+            encode_short_first_byte(&mut buf[0], packet.pn_len, key_phase);
+            Some(PacketBuf {
+                buf,
+                pn: packet.pn,
+                payload_offset: packet.hdr_len,
+                pn_len: packet.pn_len,
+            })
+        })
+        .collect::<Vec<_>>();
+    SealKeys::new(pk, hpk).seal_batch(&mut packets);
 }
@@ -7,7 +7,7 @@ use std::{
 };
 
 use deref_derive::Deref;
-use qbase::cid::ArcCidCell;
+use qbase::{cid::ArcCidCell, util::try_spawn_traced};
 use qrecovery::reliable::ArcReliableFrameDeque;
 
 #[derive(Debug, Clone, Default)]
@@ -37,7 +37,7 @@ impl ArcPathState {
             state: Default::default(),
         };
 
-        tokio::spawn({
+        try_spawn_traced({
             let state = state.clone();
             async move {
                 loop {
@@ -114,6 +114,11 @@ impl Constraints {
         self.credit_limit > 0
     }
 
+    /// 发送配额仍足以发送一个完整的数据报，此时无数据可发，即是受限于应用
+    pub fn is_quota_available(&self, datagram_size: usize) -> bool {
+        self.send_quota >= datagram_size
+    }
+
     pub fn constrain<'b>(&self, buf: &'b mut [u8]) -> &'b mut [u8] {
         let min_len = buf
             .remaining_mut()
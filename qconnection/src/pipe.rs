@@ -4,7 +4,7 @@ macro_rules! pipe {
         $input:ident |> $var:expr,$method:ident
     ) => {{
         #[allow(unused)]
-        ::tokio::spawn({
+        ::qbase::util::spawn_traced({
             let mut input = $input;
             let mut owned_capture = ::std::clone::Clone::clone(&$var);
             async move {
@@ -18,7 +18,7 @@ macro_rules! pipe {
         $input:ident |> $($lambda:tt)*
     ) => {{
         #[allow(unused)]
-        ::tokio::spawn({
+        ::qbase::util::spawn_traced({
             let mut input = $input;
             let mut lambda = $($lambda)*;
             async move {
@@ -33,7 +33,7 @@ macro_rules! pipe {
         $input:ident |> $var:expr,$method:ident
     ) => {{
         #[allow(unused)]
-        ::tokio::spawn({
+        ::qbase::util::spawn_traced({
             let mut input = $input;
             let mut owned_capture = ::std::clone::Clone::clone(&$var);
             let mut error = ::std::clone::Clone::clone($error);
@@ -53,7 +53,7 @@ macro_rules! pipe {
         $input:ident |> $($lambda:tt)*
     ) => {{
         #[allow(unused)]
-        ::tokio::spawn({
+        ::qbase::util::spawn_traced({
             let mut input = $input;
             let mut error = ::std::clone::Clone::clone($error);
             let mut lambda = $($lambda)*;
@@ -71,8 +71,11 @@ macro_rules! pipe {
 
 #[cfg(test)]
 mod tests {
-    use futures::{channel::mpsc, SinkExt};
-    use qbase::error::{Error, ErrorKind};
+    use futures::{channel::mpsc, SinkExt, StreamExt};
+    use qbase::{
+        error::{Error, ErrorKind},
+        util::{ArcTraceContext, Captured},
+    };
 
     use crate::error::ConnError;
 
@@ -153,4 +156,28 @@ mod tests {
         assert!(is_active);
         assert!(tx1.send(()).await.is_err());
     }
+
+    #[tokio::test]
+    async fn pipe_in_trace_context() {
+        let (captured, _guard) = Captured::capture(tracing::Level::INFO);
+
+        let conn = ArcTraceContext::default();
+        let (mut tx, rx) = mpsc::unbounded::<u32>();
+        let (done_tx, mut done_rx) = mpsc::unbounded::<()>();
+        {
+            let _enter = conn.enter();
+            pipe!(rx |> move |item: &u32| {
+                tracing::info!(item, "frame dispatched");
+                _ = done_tx.unbounded_send(());
+            });
+        }
+        // 管道任务派生之后，应用才设置连接的span
+        conn.set_span(tracing::info_span!("conn", id = 3));
+
+        assert!(tx.send(1).await.is_ok());
+        assert!(done_rx.next().await.is_some());
+        let output = captured.output();
+        assert!(output.contains("conn{id=3}"));
+        assert!(output.contains("frame dispatched"));
+    }
 }
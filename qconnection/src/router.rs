@@ -1,22 +1,71 @@
-use std::sync::{Arc, LazyLock};
+use std::sync::{Arc, LazyLock, Mutex};
 
 use dashmap::DashMap;
 use deref_derive::Deref;
 use qbase::{
-    cid::{ConnectionId, UniqueCid},
+    cid::{ConnectionId, GenResetToken, UniqueCid},
     error::Error,
     frame::{NewConnectionIdFrame, ReceiveFrame, RetireConnectionIdFrame, SendFrame},
     packet::{header::GetDcid, long, DataHeader, DataPacket},
+    token::{trailing_reset_token, ResetToken, StatelessResetKey},
 };
 use qudp::ArcUsc;
 
-use crate::{connection::PacketEntry, path::pathway::Pathway};
+use crate::{
+    connection::{ArcRemoteCids, PacketEntry},
+    error::ConnError,
+    path::pathway::Pathway,
+};
 
 /// Global Router for managing connections.
 pub static ROUTER: LazyLock<ArcRouter> = LazyLock::new(|| ArcRouter(Arc::new(DashMap::new())));
 
+/// Global registry of the stateless reset tokens of the connection IDs issued by the peers,
+/// to recognize the stateless resets, see [`ArcResetTokens::recv_stateless_reset`].
+pub static RESET_TOKENS: LazyLock<ArcResetTokens> = LazyLock::new(ArcResetTokens::default);
+
+/// The entries of a connection for the packets of each space.
+///
+/// Whether the IPv4-mapped addresses in the pathways of the packets are canonicalized, see
+/// [`Pathway::canonical`], is the setting of the connection decided when it's created. It
+/// never changes, so that a pathway keeps its identity in the paths of the connection.
+#[derive(Clone, Debug)]
+pub struct PacketEntries {
+    entries: [PacketEntry; 4],
+    canonical_mapped_addresses: bool,
+}
+
+impl PacketEntries {
+    pub fn new(entries: [PacketEntry; 4], canonical_mapped_addresses: bool) -> Self {
+        Self {
+            entries,
+            canonical_mapped_addresses,
+        }
+    }
+
+    /// Deliver the packet received via the pathway to the connection.
+    pub fn send(&self, packet: DataPacket, pathway: Pathway, usc: ArcUsc) {
+        let index = match packet.header {
+            DataHeader::Long(long::DataHeader::Initial(_)) => 0,
+            DataHeader::Long(long::DataHeader::ZeroRtt(_)) => 1,
+            DataHeader::Long(long::DataHeader::Handshake(_)) => 2,
+            DataHeader::Short(_) => 3,
+        };
+        _ = self.entries[index].unbounded_send((packet, self.pathway(pathway), usc));
+    }
+
+    /// The pathway in the form of the connection, canonicalized or as it is.
+    pub fn pathway(&self, pathway: Pathway) -> Pathway {
+        if self.canonical_mapped_addresses {
+            pathway.canonical()
+        } else {
+            pathway
+        }
+    }
+}
+
 #[derive(Clone, Deref, Debug)]
-pub struct ArcRouter(Arc<DashMap<ConnectionId, [PacketEntry; 4]>>);
+pub struct ArcRouter(Arc<DashMap<ConnectionId, PacketEntries>>);
 
 impl UniqueCid for ArcRouter {
     fn is_unique_cid(&self, cid: &ConnectionId) -> bool {
@@ -33,13 +82,7 @@ impl ArcRouter {
     ) -> Option<DataPacket> {
         let dcid = packet.header.get_dcid();
         if let Some(entries) = self.0.get(dcid) {
-            let index = match packet.header {
-                DataHeader::Long(long::DataHeader::Initial(_)) => 0,
-                DataHeader::Long(long::DataHeader::ZeroRtt(_)) => 1,
-                DataHeader::Long(long::DataHeader::Handshake(_)) => 2,
-                DataHeader::Short(_) => 3,
-            };
-            _ = entries[index].unbounded_send((packet, pathway, usc.clone()));
+            entries.send(packet, pathway, usc.clone());
             None
         } else {
             Some(packet)
@@ -50,7 +93,8 @@ impl ArcRouter {
         &self,
         scid: ConnectionId,
         issued_cids: ISSUED,
-        packet_entries: [PacketEntry; 4],
+        packet_entries: PacketEntries,
+        reset_key: StatelessResetKey,
     ) -> RouterRegistry<ISSUED>
     where
         ISSUED: SendFrame<NewConnectionIdFrame>,
@@ -60,6 +104,7 @@ impl ArcRouter {
             router: self.clone(),
             issued_cids,
             packet_entries,
+            reset_key,
         }
     }
 
@@ -75,7 +120,9 @@ impl ArcRouter {
 pub struct RouterRegistry<ISSUED> {
     router: ArcRouter,
     issued_cids: ISSUED,
-    packet_entries: [PacketEntry; 4],
+    packet_entries: PacketEntries,
+    // 派生发放的连接ID的无状态重置令牌
+    reset_key: StatelessResetKey,
 }
 
 impl<T> SendFrame<NewConnectionIdFrame> for RouterRegistry<T>
@@ -96,6 +143,12 @@ impl<T> UniqueCid for RouterRegistry<T> {
     }
 }
 
+impl<T> GenResetToken for RouterRegistry<T> {
+    fn gen_reset_token(&self, cid: &ConnectionId) -> ResetToken {
+        self.reset_key.reset_token(cid)
+    }
+}
+
 #[derive(Clone)]
 pub struct RevokeRouter<T> {
     router: ArcRouter,
@@ -115,3 +168,125 @@ where
         Ok(())
     }
 }
+
+#[derive(Clone)]
+struct ResetEntry {
+    remote_cids: ArcRemoteCids,
+    conn_error: ConnError,
+}
+
+#[derive(Clone, Default)]
+pub struct ArcResetTokens(Arc<DashMap<ResetToken, ResetEntry>>);
+
+impl ArcResetTokens {
+    /// Register the stateless reset tokens of the connection IDs the peer issues to a connection,
+    /// they're revoked by [`ResetTokenRegistry::revoke`] once the connection is closed.
+    pub fn registry(
+        &self,
+        remote_cids: ArcRemoteCids,
+        conn_error: ConnError,
+    ) -> ResetTokenRegistry {
+        ResetTokenRegistry {
+            tokens: self.clone(),
+            entry: ResetEntry {
+                remote_cids,
+                conn_error,
+            },
+            registered: Default::default(),
+        }
+    }
+
+    /// Check whether `datagram`, which can't be associated with any connection, is a stateless
+    /// reset, by its trailing 16 bytes. If it is, the connection it resets enters the draining
+    /// state, see [`ConnError::on_stateless_reset`].
+    ///
+    /// A stateless reset must never be responded to with another one, even if it is not
+    /// recognized, otherwise two endpoints could reset each other for ever.
+    pub fn recv_stateless_reset(&self, datagram: &[u8]) -> bool {
+        let Some(token) = trailing_reset_token(datagram) else {
+            return false;
+        };
+        // 候选的令牌以常数时间比较，见ResetToken的PartialEq
+        let Some(entry) = self.0.get(&token).map(|entry| entry.value().clone()) else {
+            return false;
+        };
+        if !entry.remote_cids.is_active_reset_token(&token) {
+            return false;
+        }
+        entry.conn_error.on_stateless_reset();
+        true
+    }
+}
+
+#[derive(Clone)]
+pub struct ResetTokenRegistry {
+    tokens: ArcResetTokens,
+    entry: ResetEntry,
+    // 该连接登记过的令牌，连接关闭时一并撤销
+    registered: Arc<Mutex<Vec<ResetToken>>>,
+}
+
+impl ResetTokenRegistry {
+    /// Register the stateless reset token of a connection ID issued by the peer, e.g. the
+    /// one in the stateless_reset_token transport parameter of the server.
+    pub fn register(&self, token: ResetToken) {
+        self.tokens.0.insert(token, self.entry.clone());
+        self.registered.lock().unwrap().push(token);
+    }
+
+    /// Revoke all the tokens registered by the connection, a stateless reset is no longer
+    /// recognized for it.
+    pub fn revoke(&self) {
+        for token in std::mem::take(&mut *self.registered.lock().unwrap()) {
+            self.tokens.0.remove(&token);
+        }
+    }
+}
+
+impl ReceiveFrame<NewConnectionIdFrame> for ResetTokenRegistry {
+    type Output = ();
+
+    fn recv_frame(&self, frame: &NewConnectionIdFrame) -> Result<Self::Output, Error> {
+        if let Some(token) = self.entry.remote_cids.recv_frame(frame)? {
+            self.register(token);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_entries_pathway() {
+        let entries = || std::array::from_fn(|_| mpsc::unbounded().0);
+        let via_v4 = Pathway::direct(
+            "127.0.0.1:4433".parse().unwrap(),
+            "192.168.1.2:5678".parse().unwrap(),
+        );
+        let via_dual_stack = Pathway::direct(
+            "[::ffff:127.0.0.1]:4433".parse().unwrap(),
+            "[::ffff:192.168.1.2]:5678".parse().unwrap(),
+        );
+
+        // 规范化的连接，同一个客户端的两种形式是同一条路径
+        let canonical = PacketEntries::new(entries(), true);
+        assert_eq!(canonical.pathway(via_dual_stack), via_v4);
+        assert_eq!(
+            canonical.pathway(via_dual_stack).remote_addr(),
+            via_v4.remote_addr()
+        );
+
+        // 不规范化的连接，两种形式是不同的路径，地址保持原样
+        let strict = PacketEntries::new(entries(), false);
+        assert_ne!(strict.pathway(via_dual_stack), via_v4);
+        assert_eq!(strict.pathway(via_dual_stack), via_dual_stack);
+        assert_eq!(
+            strict.pathway(via_dual_stack).remote_addr(),
+            via_dual_stack.remote_addr()
+        );
+    }
+}
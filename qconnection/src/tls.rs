@@ -9,12 +9,13 @@ use std::{
 use qbase::{
     cid::ConnectionId,
     config::{
-        ext::{be_parameters, WriteParameters},
-        Parameters,
+        ext::WriteParameters, ClientParameters, Parameters, RemoteParameters, ServerParameters,
     },
     error::{Error, ErrorKind},
     packet::keys::{ArcKeys, ArcOneRttKeys},
-    util::AsyncCell,
+    streamid::Role,
+    util::{sanitize, spawn_traced, AsyncCell, MAX_REASON_LEN},
+    version::VersionInformation,
 };
 use qrecovery::{space::Epoch, streams::crypto::CryptoStream};
 use rustls::{crypto::CryptoProvider, quic::Keys, Side};
@@ -23,6 +24,36 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::error::ConnError;
 
+/// The CRYPTO data the server can send in its first flight without waiting for the
+/// client, roughly.
+///
+/// The server can send no more than 3 times the bytes received before the client address
+/// is validated, which is 3 padded Initial packets of 1200 bytes, minus the overhead of
+/// the headers and the AEAD tags of each packet. A larger first flight costs an extra
+/// round trip, the certificate chain should be trimmed or compressed.
+pub const FIRST_FLIGHT_BUDGET: usize = 3 * (1200 - 80);
+
+/// The flights of the TLS handshake messages sent by this endpoint, see
+/// [`ArcConnection::handshake_stats`](crate::connection::ArcConnection::handshake_stats).
+///
+/// A flight is all the handshake messages sent before the next message from the peer.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HandshakeStats {
+    /// The size of each flight in bytes, in the order sent.
+    pub flights: Vec<usize>,
+    /// How many times the handshake waited for the peer after sending a flight.
+    pub round_trips: u32,
+}
+
+impl HandshakeStats {
+    /// Whether the first flight of the server exceeds the [`FIRST_FLIGHT_BUDGET`].
+    pub fn exceeds_first_flight_budget(&self) -> bool {
+        self.flights
+            .first()
+            .is_some_and(|&first| first > FIRST_FLIGHT_BUDGET)
+    }
+}
+
 /// write_tls_msg()，将明文数据写入tls_conn，同步的，可能会唤醒read数据发送
 /// poll_read_tls_msg()，从tls_conn读取数据，异步的，返回([`Vec<u8>`], [`Option<KeyChange>`])
 #[derive(Debug)]
@@ -30,6 +61,13 @@ use crate::error::ConnError;
 pub(crate) struct RawTlsSession {
     tls_conn: rustls::quic::Connection,
     waker: Option<Waker>,
+    stats: HandshakeStats,
+    // 上一次收到对方的消息之后，是否已经发出了数据，即当前飞行是否还在继续
+    is_flying: bool,
+    // 1-RTT密钥就绪之后的数据，比如NewSessionTicket，不属于握手的飞行
+    is_one_rtt: bool,
+    // 0-RTT密钥只取一次，客户端恢复会话时一创建就有，服务端则在接受了0-RTT的ClientHello之后
+    zero_rtt_taken: bool,
 }
 
 #[derive(Debug, Error)]
@@ -40,10 +78,12 @@ impl RawTlsSession {
     fn new_client(
         server_name: rustls::pki_types::ServerName<'static>,
         tls_config: Arc<rustls::ClientConfig>,
-        parameters: &Parameters,
+        parameters: &ClientParameters,
     ) -> Self {
         let mut params_bytes = Vec::new();
-        params_bytes.put_parameters(parameters);
+        params_bytes.put_client_parameters(parameters);
+        // 总是通告所支持的版本，以便服务端核对，也让经过版本协商的客户端能够防止降级
+        params_bytes.put_version_information_parameter(&VersionInformation::local());
 
         let connection = rustls::quic::Connection::Client(
             rustls::quic::ClientConnection::new(
@@ -57,12 +97,20 @@ impl RawTlsSession {
         Self {
             tls_conn: connection,
             waker: None,
+            stats: HandshakeStats::default(),
+            is_flying: false,
+            is_one_rtt: false,
+            zero_rtt_taken: false,
         }
     }
 
-    pub fn new_server(tls_config: Arc<rustls::ServerConfig>, server_params: &Parameters) -> Self {
+    pub fn new_server(
+        tls_config: Arc<rustls::ServerConfig>,
+        server_params: &ServerParameters,
+    ) -> Self {
         let mut params = Vec::new();
-        params.put_parameters(server_params);
+        params.put_server_parameters(server_params);
+        params.put_version_information_parameter(&VersionInformation::local());
 
         let connection = rustls::quic::Connection::Server(
             rustls::quic::ServerConnection::new(tls_config, rustls::quic::Version::V1, params)
@@ -71,11 +119,20 @@ impl RawTlsSession {
         Self {
             tls_conn: connection,
             waker: None,
+            stats: HandshakeStats::default(),
+            is_flying: false,
+            is_one_rtt: false,
+            zero_rtt_taken: false,
         }
     }
 
     // 将plaintext中的数据写入tls_conn供其处理
     fn write_tls_msg(&mut self, plaintext: &[u8]) -> Result<(), rustls::Error> {
+        // 发出一个飞行之后，收到了对方的消息，就是一个往返
+        if self.is_flying && self.tls_conn.is_handshaking() {
+            self.is_flying = false;
+            self.stats.round_trips += 1;
+        }
         // rusltls::quic::Connection::read_hs()，该函数即消费掉plaintext的数据给到tls_conn内部处理
         self.tls_conn.read_hs(plaintext)?;
         // want to read from tls_conn and then write into the crypto stream?
@@ -99,13 +156,49 @@ impl RawTlsSession {
             self.waker = Some(cx.waker().clone());
             return Poll::Pending;
         }
+        if !buf.is_empty() && !self.is_one_rtt {
+            self.on_flight_data(buf.len());
+        }
+        if let Some(rustls::quic::KeyChange::OneRtt { .. }) = key_change {
+            self.is_one_rtt = true;
+        }
 
         Poll::Ready(Some((buf, key_change)))
     }
 
+    fn on_flight_data(&mut self, len: usize) {
+        if !self.is_flying {
+            self.is_flying = true;
+            self.stats.flights.push(0);
+        }
+        let is_first = self.stats.flights.len() == 1;
+        let flight = self.stats.flights.last_mut().unwrap();
+        let exceeded = *flight <= FIRST_FLIGHT_BUDGET && *flight + len > FIRST_FLIGHT_BUDGET;
+        *flight += len;
+        if exceeded && is_first && matches!(self.tls_conn, rustls::quic::Connection::Server(_)) {
+            tracing::warn!(
+                first_flight = *flight,
+                budget = FIRST_FLIGHT_BUDGET,
+                "the first flight exceeds the anti-amplification budget, \
+                 trim or compress the certificate chain to save a round trip"
+            );
+        }
+    }
+
     fn alert(&self) -> Option<rustls::AlertDescription> {
         self.tls_conn.alert()
     }
+
+    fn take_zero_rtt_keys(&mut self) -> Option<Keys> {
+        if self.zero_rtt_taken {
+            return None;
+        }
+        // 0-RTT只有客户端到服务端一个方向，两端各取所需的一半，rustls每次都重新派生
+        let local = self.tls_conn.zero_rtt_keys()?;
+        let remote = self.tls_conn.zero_rtt_keys()?;
+        self.zero_rtt_taken = true;
+        Some(Keys { local, remote })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -115,7 +208,7 @@ impl ArcTlsSession {
     pub fn new_client(
         server_name: rustls::pki_types::ServerName<'static>,
         tls_config: Arc<rustls::ClientConfig>,
-        parameters: &Parameters,
+        parameters: &ClientParameters,
     ) -> Self {
         Self(Arc::new(Mutex::new(Ok(RawTlsSession::new_client(
             server_name,
@@ -124,7 +217,10 @@ impl ArcTlsSession {
         )))))
     }
 
-    pub fn new_server(tls_config: Arc<rustls::ServerConfig>, parameters: &Parameters) -> Self {
+    pub fn new_server(
+        tls_config: Arc<rustls::ServerConfig>,
+        parameters: &ServerParameters,
+    ) -> Self {
         Self(Arc::new(Mutex::new(Ok(RawTlsSession::new_server(
             tls_config, parameters,
         )))))
@@ -181,17 +277,24 @@ impl ArcTlsSession {
         &self,
         crypto_streams: [&CryptoStream; 3],
         handshake_keys: ArcKeys,
+        zero_rtt_keys: ArcKeys,
         one_rtt_keys: ArcOneRttKeys,
         conn_error: ConnError,
-    ) -> Arc<AsyncCell<Arc<Parameters>>> {
+    ) -> Arc<AsyncCell<Arc<RemoteParameters>>> {
         let remote_params = Arc::new(AsyncCell::new());
+        // 恢复会话的客户端，此时就能以0-RTT密钥发送数据了
+        if let Some(keys) = self.take_zero_rtt_keys() {
+            zero_rtt_keys.set_keys(keys);
+        }
+        let is_client = self.is_client();
 
         let for_each_epoch = |epoch: Epoch| {
             let mut crypto_stream_reader = crypto_streams[epoch].reader();
             let tls_session = self.clone();
             let remote_params = remote_params.clone();
+            let zero_rtt_keys = zero_rtt_keys.clone();
             let conn_error = conn_error.clone();
-            tokio::spawn(async move {
+            spawn_traced(async move {
                 // 不停地从crypto_stream_reader读取数据，读到就送给tls_conn
                 let mut buf = [0u8; 1500];
                 loop {
@@ -214,7 +317,16 @@ impl ArcTlsSession {
                         ));
                         break;
                     }
+                    // 服务端接受了ClientHello中的0-RTT，才能解密0-RTT包
+                    if let Some(keys) = tls_session.take_zero_rtt_keys() {
+                        zero_rtt_keys.set_keys(keys);
+                    }
 
+                    // 客户端在Initial密级读到的是恢复会话时记住的传输参数，服务端此番的传输参数
+                    // 在Handshake密级的EncryptedExtensions中
+                    if is_client && epoch == Epoch::Initial {
+                        continue;
+                    }
                     if let Some(params) = tls_session.get_transport_parameters() {
                         match params {
                             Ok(params) => _ = remote_params.write(params.into()),
@@ -230,7 +342,7 @@ impl ArcTlsSession {
 
         // 在此创建不停地检查tls_conn是否有数据要给到对方，或者产生了密钥升级
         // TODO: 处理错误，处理它们的异常终止
-        tokio::spawn({
+        spawn_traced({
             let tls_session = self.clone();
             let mut crypto_stream_writers = [
                 crypto_streams[0].writer(),
@@ -265,8 +377,8 @@ impl ArcTlsSession {
                             }
                             rustls::quic::KeyChange::OneRtt { keys, next } => {
                                 one_rtt_keys.set_keys(keys, next);
-                                // epoch = Epoch::Data;
-                                break;
+                                // 握手之后还有NewSessionTicket要在1-RTT密级发送，以便客户端恢复会话
+                                epoch = Epoch::Data;
                             }
                         }
                     }
@@ -280,11 +392,33 @@ impl ArcTlsSession {
         remote_params
     }
 
+    pub fn handshake_stats(&self) -> Option<HandshakeStats> {
+        let guard = self.0.lock().unwrap();
+        guard
+            .as_ref()
+            .ok()
+            .map(|tls_session| tls_session.stats.clone())
+    }
+
+    /// Whether the server accepted the 0-RTT data, only meaningful for the client after the
+    /// server's transport parameters are received. Returns None on the server side.
+    pub fn is_early_data_accepted(&self) -> Option<bool> {
+        let guard = self.0.lock().unwrap();
+        match guard.as_ref().ok()?.tls_conn {
+            rustls::quic::Connection::Client(ref client) => Some(client.is_early_data_accepted()),
+            rustls::quic::Connection::Server(_) => None,
+        }
+    }
+
+    /// The SNI sent by the client, on the server side. It's controlled by the peer, and
+    /// sanitized to be safe to display and log, see [`sanitize`].
     pub fn server_name(&self) -> Option<String> {
         let mut guard = self.0.lock().unwrap();
         if let Ok(ref mut tls_session) = guard.deref_mut() {
             if let rustls::quic::Connection::Server(server) = &tls_session.tls_conn {
-                return server.server_name().map(|s| s.to_string());
+                return server
+                    .server_name()
+                    .map(|s| sanitize(s.as_bytes(), MAX_REASON_LEN).into_owned());
             } else {
                 return None;
             }
@@ -292,26 +426,46 @@ impl ArcTlsSession {
         None
     }
 
-    fn get_transport_parameters(&self) -> Option<Result<Parameters, Error>> {
+    /// The transport parameters of the server remembered along with the session ticket, when
+    /// the client resumes a session with 0-RTT. None on the server side, or if the client
+    /// doesn't send 0-RTT data.
+    pub fn remembered_parameters(&self) -> Option<Parameters> {
+        let guard = self.0.lock().unwrap();
+        let tls_session = guard.as_ref().ok()?;
+        if !matches!(tls_session.tls_conn, rustls::quic::Connection::Client(_))
+            || tls_session.tls_conn.zero_rtt_keys().is_none()
+        {
+            return None;
+        }
+        // 在收到服务端的消息之前，rustls给出的就是记住的传输参数
+        let raw = tls_session.tls_conn.quic_transport_parameters()?;
+        let remembered = RemoteParameters::decode(raw, Role::Server).ok()?;
+        Some(*remembered)
+    }
+
+    fn is_client(&self) -> bool {
+        let guard = self.0.lock().unwrap();
+        matches!(
+            guard.as_ref().map(|tls_session| &tls_session.tls_conn),
+            Ok(rustls::quic::Connection::Client(_))
+        )
+    }
+
+    fn take_zero_rtt_keys(&self) -> Option<Keys> {
+        let mut guard = self.0.lock().unwrap();
+        guard.as_mut().ok()?.take_zero_rtt_keys()
+    }
+
+    fn get_transport_parameters(&self) -> Option<Result<RemoteParameters, Error>> {
         let mut guard = self.0.lock().unwrap();
         if let Ok(ref mut tls_session) = guard.deref_mut() {
-            let raw = tls_session.tls_conn.quic_transport_parameters()?;
-            let params = match be_parameters(raw) {
-                Ok((_, params)) => params,
-                Err(e) => {
-                    return Some(Err(Error::with_default_fty(
-                        ErrorKind::Internal,
-                        e.to_string(),
-                    )))
-                }
+            // 对端的角色与本端相反，据此校验对端不该发送的传输参数
+            let peer = match tls_session.tls_conn {
+                rustls::quic::Connection::Client(_) => Role::Server,
+                rustls::quic::Connection::Server(_) => Role::Client,
             };
-            if let Err(reason) = params.validate() {
-                return Some(Err(Error::with_default_fty(
-                    ErrorKind::TransportParameter,
-                    reason,
-                )));
-            }
-            Some(Ok(params))
+            let raw = tls_session.tls_conn.quic_transport_parameters()?;
+            Some(RemoteParameters::decode(raw, peer))
         } else {
             None
         }
@@ -332,3 +486,194 @@ impl Future for ReadTlsMsg {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rustls::{
+        compress::{
+            CertCompressor, CertDecompressor, CompressionFailed, CompressionLevel,
+            DecompressionFailed,
+        },
+        pki_types::{CertificateDer, PrivateKeyDer},
+        CertificateCompressionAlgorithm, RootCertStore,
+    };
+
+    use super::*;
+
+    const RUN_LENGTH: CertificateCompressionAlgorithm =
+        CertificateCompressionAlgorithm::Unknown(0xfe00);
+
+    // 测试用的压缩算法：游程编码，足以压缩膨胀的证书链
+    #[derive(Debug)]
+    struct RunLength;
+
+    impl CertCompressor for RunLength {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            _level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            let mut output = Vec::new();
+            for run in input.chunk_by(|a, b| a == b) {
+                for run in run.chunks(u8::MAX as usize) {
+                    output.extend([run.len() as u8, run[0]]);
+                }
+            }
+            Ok(output)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            RUN_LENGTH
+        }
+    }
+
+    impl CertDecompressor for RunLength {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            let mut filled = 0;
+            for pair in input.chunks(2) {
+                let &[len, byte] = pair else {
+                    return Err(DecompressionFailed);
+                };
+                let run = output
+                    .get_mut(filled..filled + len as usize)
+                    .ok_or(DecompressionFailed)?;
+                run.fill(byte);
+                filled += len as usize;
+            }
+            (filled == output.len())
+                .then_some(())
+                .ok_or(DecompressionFailed)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            RUN_LENGTH
+        }
+    }
+
+    // 带着大量冗长域名的证书链，远超第一个飞行的预算
+    fn bloated_chain() -> (
+        Vec<CertificateDer<'static>>,
+        PrivateKeyDer<'static>,
+        RootCertStore,
+    ) {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let label = "a".repeat(60);
+        let names = (0..32)
+            .map(|i| format!("{label}.{label}.{i}.test.net"))
+            .chain(["localhost".to_owned()])
+            .collect::<Vec<_>>();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let chain = vec![cert.der().clone(), ca.der().clone()];
+        (
+            chain,
+            PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            roots,
+        )
+    }
+
+    /// Run the handshake in memory, return the stats of the client and the server, and how
+    /// many times the server waited for the client before the handshake is completed.
+    ///
+    /// Like the transport, the server sends no more than [`FIRST_FLIGHT_BUDGET`] bytes before
+    /// the client responds and its address is validated, the rest waits for a round trip.
+    fn handshake(compression: bool) -> (HandshakeStats, HandshakeStats, u32) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let (chain, key, roots) = bloated_chain();
+
+        let mut server_config = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        server_config.cert_compressors = vec![&RunLength];
+        let mut client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.cert_decompressors = match compression {
+            true => vec![&RunLength],
+            false => vec![],
+        };
+
+        let mut client = RawTlsSession::new_client(
+            "localhost".try_into().unwrap(),
+            Arc::new(client_config),
+            &ClientParameters::default(),
+        );
+        let mut server =
+            RawTlsSession::new_server(Arc::new(server_config), &ServerParameters::default());
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut read = |from: &mut RawTlsSession| {
+            let mut buf = Vec::new();
+            while let Poll::Ready(Some((data, _key_change))) = from.poll_read_tls_msg(&mut cx) {
+                buf.extend(data);
+            }
+            buf
+        };
+
+        // 受抗放大限制，服务端还没能发出去的数据
+        let mut pending = Vec::new();
+        let mut validated = false;
+        let mut round_trips = 0;
+        let mut to_server = read(&mut client);
+        loop {
+            // 数据发不完时，客户端只是回个ACK，TLS没有什么要处理的
+            if !to_server.is_empty() {
+                server.write_tls_msg(&to_server).unwrap();
+            }
+            if !server.tls_conn.is_handshaking() {
+                break;
+            }
+            pending.extend(read(&mut server));
+            let len = match validated {
+                true => pending.len(),
+                false => pending.len().min(FIRST_FLIGHT_BUDGET),
+            };
+            let to_client = pending.drain(..len).collect::<Vec<_>>();
+            client.write_tls_msg(&to_client).unwrap();
+            to_server = read(&mut client);
+            // 服务端等到了客户端的回应，客户端的地址也由此验证了
+            round_trips += 1;
+            validated = true;
+        }
+
+        assert!(!client.tls_conn.is_handshaking());
+        assert!(!server.tls_conn.is_handshaking());
+        (client.stats, server.stats, round_trips)
+    }
+
+    #[test]
+    fn test_first_flight_budget() {
+        let (client, server, round_trips) = handshake(false);
+        // ClientHello，Finished
+        assert_eq!(client.flights.len(), 2);
+        assert_eq!(client.round_trips, 1);
+        assert_eq!(server.flights.len(), 1);
+        assert_eq!(server.round_trips, 1);
+        assert!(server.exceeds_first_flight_budget());
+        // 握手消息还是一个往返，传输层却要多等一个往返，才能发完第一个飞行
+        assert_eq!(round_trips, 2);
+        let uncompressed = server.flights[0];
+
+        let (client, server, round_trips) = handshake(true);
+        assert_eq!(client.round_trips, 1);
+        assert_eq!(server.round_trips, 1);
+        assert!(!server.exceeds_first_flight_budget());
+        assert!(server.flights[0] < uncompressed);
+        assert_eq!(round_trips, 1);
+    }
+}
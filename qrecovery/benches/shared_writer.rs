@@ -0,0 +1,97 @@
+//! Compare several producers writing records through a [`SharedWriter`] with the same
+//! producers sharing a [`Writer`] wrapped in a mutex.
+//!
+//! Run with `cargo bench -p qrecovery --bench shared_writer`.
+
+use std::{sync::Arc, time::Instant};
+
+use qbase::{
+    config::Parameters,
+    frame::StreamCtlFrame,
+    streamid::{Dir, Role},
+    util::ArcAsyncDeque,
+};
+use qrecovery::{
+    send::{SharedWriter, Writer},
+    streams::DataStreams,
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+const PRODUCERS: usize = 8;
+const RECORDS: usize = 20000;
+const RECORD_LEN: usize = 64;
+const TOTAL: usize = PRODUCERS * RECORDS * RECORD_LEN;
+
+async fn open_writer() -> Writer {
+    let streams = DataStreams::new(
+        Role::Client,
+        &Parameters::default(),
+        ArcAsyncDeque::<StreamCtlFrame>::new(),
+    );
+    streams.premit_max_sid(Dir::Uni, 1);
+    // 窗口足够容纳所有记录，只比较写端之间的竞争
+    streams.open_uni(TOTAL as u64).await.unwrap().unwrap()
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    let records = PRODUCERS * RECORDS;
+    println!(
+        "{name:>6}: {:>8.1} ns/record, {:>7.1} MB/s",
+        elapsed.as_nanos() as f64 / records as f64,
+        TOTAL as f64 / elapsed.as_secs_f64() / 1e6,
+    );
+}
+
+async fn bench_mutex() {
+    let writer = Arc::new(Mutex::new(open_writer().await));
+    let start = Instant::now();
+    let producers = (0..PRODUCERS)
+        .map(|tag| {
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                let record = [tag as u8; RECORD_LEN];
+                for _ in 0..RECORDS {
+                    writer.lock().await.write_all(&record).await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for producer in producers {
+        producer.await.unwrap();
+    }
+    report("mutex", start);
+    Arc::into_inner(writer).unwrap().into_inner().reset(0);
+}
+
+async fn bench_shared() {
+    let writer: SharedWriter = open_writer().await.into_shared();
+    let start = Instant::now();
+    let producers = (0..PRODUCERS)
+        .map(|tag| {
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                let record = [tag as u8; RECORD_LEN];
+                for _ in 0..RECORDS {
+                    writer.write_message(&record).await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for producer in producers {
+        producer.await.unwrap();
+    }
+    report("shared", start);
+    writer.reset(0);
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(PRODUCERS)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        bench_mutex().await;
+        bench_shared().await;
+    });
+}
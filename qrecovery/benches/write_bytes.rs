@@ -0,0 +1,85 @@
+//! Compare writing the buffers held in [`Bytes`] by copying them through `write_all` with
+//! handing them over by [`Writer::write_bytes`], each written buffer sent in STREAM frames
+//! and acknowledged right away.
+//!
+//! Run with `cargo bench -p qrecovery --bench write_bytes`.
+
+use std::time::Instant;
+
+use bytes::Bytes;
+use qbase::{
+    config::Parameters,
+    frame::StreamCtlFrame,
+    streamid::{Dir, Role},
+    util::ArcAsyncDeque,
+};
+use qrecovery::{send::Writer, streams::DataStreams};
+use tokio::io::AsyncWriteExt;
+
+const CHUNKS: usize = 4096;
+const CHUNK_LEN: usize = 16 * 1024;
+const TOTAL: usize = CHUNKS * CHUNK_LEN;
+
+type Streams = DataStreams<ArcAsyncDeque<StreamCtlFrame>>;
+
+async fn open_writer() -> (Streams, Writer) {
+    let streams = DataStreams::new(
+        Role::Client,
+        &Parameters::default(),
+        ArcAsyncDeque::<StreamCtlFrame>::new(),
+    );
+    streams.premit_max_sid(Dir::Uni, 1);
+    let writer = streams.open_uni(TOTAL as u64).await.unwrap().unwrap();
+    (streams, writer)
+}
+
+// 把缓冲的数据都打包成STREAM帧发出，并立即确认
+fn drain(streams: &Streams) {
+    let mut packet = [0u8; 1200];
+    while let Some((frame, _, _)) = streams.try_read_data(&mut packet, usize::MAX) {
+        streams.on_data_acked(frame);
+    }
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>6}: {:>8.1} ns/chunk, {:>7.1} MB/s",
+        elapsed.as_nanos() as f64 / CHUNKS as f64,
+        TOTAL as f64 / elapsed.as_secs_f64() / 1e6,
+    );
+}
+
+async fn bench_copy(chunk: &Bytes) {
+    let (streams, mut writer) = open_writer().await;
+    let start = Instant::now();
+    for _ in 0..CHUNKS {
+        writer.write_all(chunk).await.unwrap();
+        drain(&streams);
+    }
+    report("copy", start);
+    writer.reset(0);
+}
+
+async fn bench_bytes(chunk: &Bytes) {
+    let (streams, mut writer) = open_writer().await;
+    let start = Instant::now();
+    for _ in 0..CHUNKS {
+        writer.write_bytes(chunk.clone()).await.unwrap();
+        drain(&streams);
+    }
+    report("bytes", start);
+    writer.reset(0);
+}
+
+fn main() {
+    let chunk = Bytes::from((0..CHUNK_LEN).map(|i| i as u8).collect::<Vec<_>>());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        bench_copy(&chunk).await;
+        bench_bytes(&chunk).await;
+    });
+}
@@ -3,6 +3,7 @@ pub mod reliable;
 pub mod send;
 pub mod space;
 pub mod streams;
+pub mod trailer;
 
 #[derive(Debug)]
 pub enum QuicStream {
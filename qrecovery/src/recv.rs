@@ -1,13 +1,27 @@
+use qbase::util::ArcTraceContext;
+
 mod incoming;
+mod inspector;
 mod reader;
 mod recver;
+mod window;
 
 pub mod rcvbuf;
 
 pub use incoming::{Incoming, IsStopped, UpdateWindow};
-pub use reader::Reader;
-pub use recver::ArcRecver;
+pub use inspector::StreamInspector;
+pub use reader::{ReadTimedOut, Reader, StreamReset};
+pub use recver::{ArcRecver, RecvIntrospection, RecvState};
+pub use window::{ArcWindowTuning, DEFAULT_MAX_STREAM_WINDOW};
 
 pub fn new(buf_size: u64) -> ArcRecver {
     ArcRecver::new(buf_size)
 }
+
+pub fn with_trace(buf_size: u64, trace: ArcTraceContext) -> ArcRecver {
+    ArcRecver::with_trace(buf_size, trace)
+}
+
+pub fn with_tuning(buf_size: u64, tuning: ArcWindowTuning, trace: ArcTraceContext) -> ArcRecver {
+    ArcRecver::with_tuning(buf_size, tuning, trace)
+}
@@ -12,7 +12,7 @@ use qbase::{
     frame::{ResetStreamFrame, StreamFrame},
 };
 
-use super::recver::{ArcRecver, Recver};
+use super::recver::{ArcRecver, Broken, Recver};
 
 #[derive(Debug, Clone)]
 pub struct Incoming(pub(crate) ArcRecver);
@@ -26,8 +26,7 @@ impl Incoming {
             match receiving_state {
                 Recver::Recv(r) => {
                     if stream_frame.is_fin() {
-                        let final_size = stream_frame.offset() + stream_frame.len() as u64;
-                        let mut size_known = r.determin_size(final_size);
+                        let mut size_known = r.determin_size(stream_frame)?;
                         new_data_size = size_known.recv(stream_frame, body)?;
                         if size_known.is_all_rcvd() {
                             *receiving_state = Recver::DataRcvd(size_known.into());
@@ -52,41 +51,62 @@ impl Incoming {
         Ok(new_data_size)
     }
 
-    pub fn recv_reset(&self, reset_frame: &ResetStreamFrame) -> Result<(), QuicError> {
-        // TODO: ResetStream中还有错误信息，比如http3的错误码，看是否能用到
+    /// Return the bytes before the final size that are never received. The peer has
+    /// consumed the connection-level flow control for them, so they must be counted
+    /// as received as well, see RFC 9000 section 4.5.
+    pub fn recv_reset(&self, reset_frame: &ResetStreamFrame) -> Result<u64, QuicError> {
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
         if let Ok(receiving_state) = inner {
-            match receiving_state {
-                Recver::Recv(r) => {
-                    let final_size = r.recv_reset(reset_frame)?;
-                    *receiving_state = Recver::ResetRcvd(final_size);
-                }
-                Recver::SizeKnown(r) => {
-                    let final_size = r.recv_reset(reset_frame)?;
-                    *receiving_state = Recver::ResetRcvd(final_size);
-                }
+            let (final_size, unrcvd) = match receiving_state {
+                Recver::Recv(r) => r.recv_reset(reset_frame)?,
+                Recver::SizeKnown(r) => r.recv_reset(reset_frame)?,
                 _ => {
                     log::error!("there is sth wrong, ignored recv_reset");
                     unreachable!();
                 }
-            }
+            };
+            let error_code = reset_frame.app_error_code.into_inner();
+            *receiving_state = Recver::ResetRcvd(final_size, error_code);
+            return Ok(unrcvd);
         }
-        Ok(())
+        Ok(0)
     }
 
     pub fn on_conn_error(&self, err: &QuicError) {
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
+        let error = io::Error::from(err.clone());
         match inner {
-            Ok(receiving_state) => match receiving_state {
-                Recver::Recv(r) => r.wake_all(),
-                Recver::SizeKnown(r) => r.wake_all(),
-                _ => return,
-            },
+            Ok(receiving_state) => {
+                let rcvbuf = match receiving_state {
+                    Recver::Recv(r) => {
+                        r.wake_all();
+                        r.take_rcvbuf()
+                    }
+                    Recver::SizeKnown(r) => {
+                        r.wake_all();
+                        r.take_rcvbuf()
+                    }
+                    // 数据已全部收到的流不受影响，照常读到流的末尾
+                    _ => return,
+                };
+                // 已收到的连续数据仍然可读，读完之后才报告连接错误
+                if rcvbuf.is_readable() {
+                    *receiving_state = Recver::Broken(Broken::new(rcvbuf, error));
+                    return;
+                }
+            }
             Err(_) => return,
         };
-        *inner = Err(io::Error::new(io::ErrorKind::BrokenPipe, err.to_string()));
+        *inner = Err(error);
+    }
+
+    /// 对方发来STREAM_DATA_BLOCKED帧，受限于我方的窗口limit
+    pub fn on_data_blocked(&self, limit: u64) {
+        if let Ok(Recver::Recv(r)) = self.0.recver().deref_mut() {
+            r.on_peer_blocked(limit);
+        }
     }
 
     /// 应用层是否对流写入结束，如果是，那么应要发送STOP_SENDING
@@ -0,0 +1,70 @@
+use std::io;
+
+/// A hook observing the data of a receiving stream as it becomes readable, such as to
+/// hash a content-addressed blob incrementally or to report the progress, without
+/// buffering the whole stream or copying the data once more.
+///
+/// See [`Reader::set_inspector`](crate::recv::Reader::set_inspector). The data is handed
+/// to the inspector in order and exactly once, as the application reads it out of the
+/// stream, so the duplicated or overlapping data retransmitted by the peer never reaches
+/// it twice.
+///
+/// The callbacks are synchronous and are called within the reads of the stream, they must
+/// be fast. Heavy work belongs in the application.
+pub trait StreamInspector: Send {
+    /// Some contiguous data starting at the offset of the stream became readable.
+    ///
+    /// Returning an error fails the read with it, and stops the stream if
+    /// [`StreamInspector::stop_code`] is set.
+    fn on_data(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// All the data of the stream has been read, the final size is the length of the
+    /// stream. Not called if the stream is reset or the connection is broken.
+    fn on_finish(&mut self, final_size: u64) -> io::Result<()> {
+        _ = final_size;
+        Ok(())
+    }
+
+    /// The error code of the STOP_SENDING frame to send when a callback returns an error.
+    /// None means only failing the read, and leaving the stream to the application.
+    fn stop_code(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// 流的检查器，以及已经交给它的数据偏移
+pub(super) struct Inspection {
+    inspector: Box<dyn StreamInspector>,
+    offset: u64,
+}
+
+impl Inspection {
+    pub(super) fn new(inspector: Box<dyn StreamInspector>) -> Self {
+        Self {
+            inspector,
+            offset: 0,
+        }
+    }
+
+    pub(super) fn on_data(&mut self, data: &[u8]) -> io::Result<()> {
+        let offset = self.offset;
+        self.offset += data.len() as u64;
+        self.inspector.on_data(offset, data)
+    }
+
+    pub(super) fn on_finish(&mut self) -> io::Result<()> {
+        self.inspector.on_finish(self.offset)
+    }
+
+    pub(super) fn stop_code(&self) -> Option<u64> {
+        self.inspector.stop_code()
+    }
+}
+
+impl std::fmt::Debug for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inspection")
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
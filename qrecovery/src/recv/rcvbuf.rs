@@ -3,7 +3,7 @@ use std::{collections::VecDeque, fmt};
 use bytes::{BufMut, Bytes};
 
 /// 一段连续的数据片段，每个片段都是Bytes
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub(super) struct Segment {
     offset: u64,
     length: u64,
@@ -16,6 +16,13 @@ impl fmt::Display for Segment {
     }
 }
 
+// 同Display，不输出片段中的数据
+impl fmt::Debug for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 impl Segment {
     fn new_with_data(offset: u64, data: Bytes) -> Self {
         Segment {
@@ -47,7 +54,7 @@ impl Segment {
 /// that may not be continuous. It sequentially stores the received data
 /// fragments and then reassembles them into a continuous data stream for
 /// future reading by the application layer.
-#[derive(Default, Debug)]
+#[derive(Default)]
 pub struct RecvBuf {
     nread: u64,
     segments: VecDeque<Segment>,
@@ -63,6 +70,16 @@ impl fmt::Display for RecvBuf {
     }
 }
 
+// 只输出已读的偏移与各段的区间，绝不输出收到的数据
+impl fmt::Debug for RecvBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvBuf")
+            .field("offset", &self.nread)
+            .field("segments", &self.segments)
+            .finish()
+    }
+}
+
 impl RecvBuf {
     pub fn is_empty(&self) -> bool {
         self.segments.is_empty()
@@ -261,6 +278,7 @@ impl RecvBuf {
                 let n = buf.remaining_mut().min(frag.len());
                 buf.put_slice(&frag[..n]);
                 seg.offset += n as u64;
+                seg.length -= n as u64;
                 self.nread = seg.offset;
                 if n < frag.len() {
                     seg.fragments.push_front(frag.slice(n..));
@@ -285,6 +303,40 @@ impl RecvBuf {
         }
     }
 
+    /// Copy at most `len` bytes of the contiguous data from the read offset without
+    /// consuming them.
+    pub fn peek(&self, len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(seg) = self.segments.front().filter(|seg| seg.offset == self.nread) {
+            for frag in &seg.fragments {
+                let n = (len - data.len()).min(frag.len());
+                data.extend_from_slice(&frag[..n]);
+                if data.len() == len {
+                    break;
+                }
+            }
+        }
+        data
+    }
+
+    /// The end of the largest offset received, the data before it may not be contiguous.
+    pub fn largest(&self) -> u64 {
+        self.segments
+            .back()
+            .map_or(self.nread, |seg| seg.offset + seg.length)
+    }
+
+    /// The bytes received so far, excluding the duplicate ones, i.e. the sum of the new
+    /// data returned by [`RecvBuf::recv`].
+    pub fn rcvd(&self) -> u64 {
+        self.nread + self.buffered()
+    }
+
+    /// The bytes received but not read yet, including the ones not contiguous yet.
+    pub fn buffered(&self) -> u64 {
+        self.segments.iter().map(|seg| seg.length).sum()
+    }
+
     /// Once the received data becomes continuous, it becomes readable. If necessary (if the application
     /// layer is blocked on reading), it is necessary to notify the application layer to read.
     pub fn is_readable(&self) -> bool {
@@ -449,6 +501,25 @@ mod tests {
         assert_eq!(dst[..11], b"hello world"[..]);
     }
 
+    #[test]
+    fn test_recvbuf_partial_read() {
+        let mut rcvbuf = RecvBuf::default();
+        assert_eq!(rcvbuf.recv(0, Bytes::from("hello")), 5);
+
+        let mut dst = [0u8; 3];
+        rcvbuf.read(&mut &mut dst[..]);
+        assert_eq!(&dst, b"hel");
+        // 读了一部分的片段，剩余的长度随之减少
+        assert_eq!(rcvbuf.buffered(), 2);
+        assert_eq!(rcvbuf.available(), 5);
+
+        assert_eq!(rcvbuf.recv(5, Bytes::from(" world")), 6);
+        assert_eq!(rcvbuf.buffered(), 8);
+        let mut dst = Vec::new();
+        rcvbuf.read(&mut dst);
+        assert_eq!(dst, b"lo world");
+    }
+
     #[test]
     fn test_rcvbuf_recv_overlap_seg() {
         let mut buf = RecvBuf::default();
@@ -462,4 +533,20 @@ mod tests {
         assert_eq!(buf.recv(12, Bytes::from("00")), 2);
         assert_eq!(buf.recv(0, Bytes::from("hello world")), 7);
     }
+
+    #[test]
+    fn test_rcvbuf_peek() {
+        let mut buf = RecvBuf::default();
+        assert_eq!(buf.recv(6, Bytes::from("world")), 5);
+        // 开头的数据还没收到，没有可预览的
+        assert!(buf.peek(8).is_empty());
+
+        assert_eq!(buf.recv(0, Bytes::from("hello ")), 6);
+        assert_eq!(buf.peek(8), b"hello wo");
+        assert_eq!(buf.peek(20), b"hello world");
+        // 预览不消费数据
+        let mut dst = Vec::new();
+        buf.read(&mut dst);
+        assert_eq!(dst, b"hello world");
+    }
 }
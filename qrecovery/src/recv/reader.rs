@@ -1,31 +1,226 @@
 use std::{
+    fmt,
+    future::Future,
     io,
     ops::DerefMut,
     pin::Pin,
-    task::{Context, Poll},
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
 };
 
-use qbase::varint::VARINT_MAX;
-use tokio::io::{AsyncRead, ReadBuf};
+use bytes::Bytes;
+use qbase::{cid::ConnectionId, error::clone_io_error, streamid::StreamId, varint::VARINT_MAX};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use super::{
+    inspector::{Inspection, StreamInspector},
+    recver::{ArcRecver, Recver},
+};
+use crate::{streams::policy::InFlight, trailer};
+
+/// The error inside the [`io::Error`] of kind [`io::ErrorKind::TimedOut`], returned by
+/// reading a [`Reader`] when no new data became readable within the read timeout,
+/// see [`Reader::set_read_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadTimedOut {
+    in_repair: bool,
+}
 
-use super::recver::{ArcRecver, Recver};
+impl ReadTimedOut {
+    /// Whether some data after the read offset has been received, but the data before
+    /// it is lost and waiting for the retransmission. The peer isn't silent in this case,
+    /// the caller may prefer to extend the timeout.
+    pub fn in_repair(&self) -> bool {
+        self.in_repair
+    }
+}
 
+impl fmt::Display for ReadTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.in_repair {
+            write!(f, "read timed out while the lost data is being repaired")
+        } else {
+            write!(f, "read timed out, no data received")
+        }
+    }
+}
+
+impl std::error::Error for ReadTimedOut {}
+
+/// The error inside the [`io::Error`] of kind [`io::ErrorKind::BrokenPipe`], returned by
+/// reading a [`Reader`] after the peer reset the stream by a RESET_STREAM frame, see
+/// [`Writer::reset`](crate::send::Writer::reset). The data received but not read yet is
+/// abandoned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamReset {
+    /// The application error code carried by the RESET_STREAM frame.
+    pub error_code: u64,
+}
+
+impl fmt::Display for StreamReset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "reset by peer with error code {}", self.error_code)
+    }
+}
+
+impl std::error::Error for StreamReset {}
+
+/// 读超时的计时器，每个流只有一个，每读到新数据就重置
 #[derive(Debug)]
-pub struct Reader(pub(crate) ArcRecver);
+struct ReadDeadline {
+    timeout: Option<Duration>,
+    last_read: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Default for ReadDeadline {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            last_read: Instant::now(),
+            sleep: None,
+        }
+    }
+}
+
+impl ReadDeadline {
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.last_read = Instant::now();
+    }
+
+    fn on_read(&mut self) {
+        self.last_read = Instant::now();
+    }
+
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(timeout) = self.timeout else {
+            return Poll::Pending;
+        };
+        let deadline = self.last_read + timeout;
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+        if sleep.deadline() != deadline {
+            sleep.as_mut().reset(deadline);
+        }
+        let expired = sleep.as_mut().poll(cx);
+        if expired.is_ready() {
+            // 超时之后，下一次读取重新计时
+            self.last_read = Instant::now();
+        }
+        expired
+    }
+}
+
+#[derive(Debug)]
+pub struct Reader(
+    pub(crate) ArcRecver,
+    ReadDeadline,
+    Option<Inspection>,
+    Option<Arc<InFlight>>,
+    StreamId,
+    ConnectionId,
+);
 
 impl Reader {
+    pub(crate) fn new(sid: StreamId, recver: ArcRecver) -> Self {
+        Self(
+            recver,
+            ReadDeadline::default(),
+            None,
+            None,
+            sid,
+            ConnectionId::default(),
+        )
+    }
+
+    /// The id of this stream.
+    pub fn stream_id(&self) -> StreamId {
+        self.4
+    }
+
+    pub(crate) fn with_conn_id(mut self, conn_id: ConnectionId) -> Self {
+        self.5 = conn_id;
+        self
+    }
+
+    /// The stable id of the connection this stream belongs to, for correlating the logs,
+    /// see [`DataStreams::with_conn_id`](crate::streams::DataStreams::with_conn_id).
+    pub fn conn_id(&self) -> ConnectionId {
+        self.5
+    }
+
+    pub(crate) fn with_in_flight(mut self, in_flight: Option<Arc<InFlight>>) -> Self {
+        self.3 = in_flight;
+        self
+    }
+
+    /// Set the inspector observing the data as it's read, see [`StreamInspector`].
+    ///
+    /// The inspector sees the data read after it's set, and is dropped once the stream
+    /// is finished or it returns an error.
+    pub fn set_inspector(&mut self, inspector: Box<dyn StreamInspector>) {
+        self.2 = Some(Inspection::new(inspector));
+    }
+
+    /// Set the timeout of the subsequent reads, None means no timeout, which is the default.
+    ///
+    /// A read fails with [`ReadTimedOut`] if no new data becomes readable within the
+    /// timeout, the window restarts whenever some data is read. Unlike wrapping each read
+    /// in a timeout, the stream keeps only one timer, and the error tells whether the
+    /// stream is waiting for the retransmission of the lost data.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.1.set_timeout(timeout);
+    }
+
+    /// Set the span of this stream, the asynchronous work of this stream will be traced
+    /// within it, nested inside the span of the connection.
+    ///
+    /// For a bidirectional stream, the span is shared with the [`Writer`](crate::send::Writer).
+    pub fn set_trace_span(&self, span: tracing::Span) {
+        self.0.trace().set_span(span);
+    }
+
+    /// Read all the data until the end of the stream, and split the trailer written by
+    /// [`Writer::finish_with`](crate::send::Writer::finish_with) from the body.
+    ///
+    /// Return an error if the trailer is larger than max_trailer_len, see [`crate::trailer`].
+    pub async fn read_to_end_split_trailer(
+        &mut self,
+        max_trailer_len: usize,
+    ) -> io::Result<(Bytes, Option<Bytes>)> {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data).await?;
+        trailer::split(Bytes::from(data), max_trailer_len)
+    }
+
     /// Tell peer to stop sending data with the given error code.
     /// It meaning sending a STOP_SENDING frame to peer.
+    ///
+    /// The peer's [`Writer`](crate::send::Writer) fails with a [`StreamStopped`] carrying
+    /// the error code, and the peer resets the stream with the same code.
+    ///
+    /// [`StreamStopped`]: crate::send::StreamStopped
     pub fn stop(self, error_code: u64) {
+        self.stop_sending(error_code);
+    }
+
+    fn stop_sending(&self, error_code: u64) {
         debug_assert!(error_code <= VARINT_MAX);
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
         if let Ok(receiving_state) = inner {
+            // 检查器出错时可能已经停止了
             match receiving_state {
-                Recver::Recv(r) => {
+                Recver::Recv(r) if !r.is_stopped() => {
                     r.stop(error_code);
                 }
-                Recver::SizeKnown(r) => {
+                Recver::SizeKnown(r) if !r.is_stopped() => {
                     r.stop(error_code);
                 }
                 _ => (),
@@ -39,6 +234,74 @@ impl AsyncRead for Reader {
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let is_eof = buf.remaining() > 0;
+        match this.poll_read_data(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                if buf.filled().len() > filled {
+                    this.1.on_read();
+                }
+                Poll::Ready(this.inspect(&buf.filled()[filled..], is_eof))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => {
+                ready!(this.1.poll_expired(cx));
+                let in_repair = match this.0.recver().as_ref() {
+                    Ok(Recver::Recv(r)) => r.is_in_repair(),
+                    Ok(Recver::SizeKnown(r)) => r.is_in_repair(),
+                    _ => false,
+                };
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    ReadTimedOut { in_repair },
+                )))
+            }
+        }
+    }
+}
+
+/// The same as the [`tokio::io::AsyncRead`] implementation, reading 0 bytes at the end of the
+/// stream.
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncRead for Reader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(AsyncRead::poll_read(self, cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl Reader {
+    /// 把新读到的数据交给检查器，读到了流的末尾（没有新数据）则通知其结束
+    fn inspect(&mut self, data: &[u8], is_eof: bool) -> io::Result<()> {
+        let Some(inspection) = self.2.as_mut() else {
+            return Ok(());
+        };
+        let result = match (data.is_empty(), is_eof) {
+            (false, _) => inspection.on_data(data),
+            (true, true) => inspection.on_finish(),
+            (true, false) => return Ok(()),
+        };
+        // 流结束或者检查出错之后，就不再检查了，出错时按需告知对方停止发送
+        if data.is_empty() || result.is_err() {
+            let stop_code = self.2.take().and_then(|i| i.stop_code());
+            if let (Err(_), Some(error_code)) = (&result, stop_code) {
+                self.stop_sending(error_code);
+            }
+        }
+        result
+    }
+
+    fn poll_read_data(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let mut recver = self.0.recver();
         let inner = recver.deref_mut();
@@ -55,19 +318,23 @@ impl AsyncRead for Reader {
                     Poll::Ready(Ok(()))
                 }
                 Recver::DataRead => Poll::Ready(Ok(())),
-                Recver::ResetRcvd(_final_size) => {
-                    *receiving_state = Recver::ResetRead;
+                Recver::ResetRcvd(_final_size, error_code) => {
+                    let error_code = *error_code;
+                    *receiving_state = Recver::ResetRead(error_code);
                     Poll::Ready(Err(io::Error::new(
                         io::ErrorKind::BrokenPipe,
-                        "reset by peer",
+                        StreamReset { error_code },
                     )))
                 }
-                Recver::ResetRead => Poll::Ready(Err(io::Error::new(
+                Recver::ResetRead(error_code) => Poll::Ready(Err(io::Error::new(
                     io::ErrorKind::BrokenPipe,
-                    "you know, reset by peer",
+                    StreamReset {
+                        error_code: *error_code,
+                    },
                 ))),
+                Recver::Broken(r) => r.poll_read(buf),
             },
-            Err(e) => Poll::Ready(Err(io::Error::new(e.kind(), e.to_string()))),
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
         }
     }
 }
@@ -99,4 +366,316 @@ impl Drop for Reader {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use qbase::{
+        error::{Error as QuicError, ErrorKind},
+        frame::StreamFrame,
+        varint::VarInt,
+    };
+
+    use super::*;
+    use crate::recv::{Incoming, RecvState};
+
+    fn finished_stream(data: &[u8]) -> Reader {
+        let recver = ArcRecver::new(1000);
+        let mut frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), 0, data.len());
+        frame.set_eos_flag(true);
+        Incoming(recver.clone())
+            .recv_data(&frame, Bytes::copy_from_slice(data))
+            .unwrap();
+        Reader::new(StreamId::from(VarInt::from_u32(0)), recver)
+    }
+
+    #[tokio::test]
+    async fn test_read_to_end_split_trailer() {
+        let mut data = b"hello".to_vec();
+        data.extend(trailer::encode(b"ok").unwrap());
+
+        let mut reader = finished_stream(&data);
+        let (body, trailer) = reader.read_to_end_split_trailer(16).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"hello"));
+        assert_eq!(trailer, Some(Bytes::from_static(b"ok")));
+
+        let mut reader = finished_stream(&data);
+        let err = reader.read_to_end_split_trailer(1).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_plain_read_with_trailer() {
+        let mut data = b"hello".to_vec();
+        data.extend(trailer::encode(b"ok").unwrap());
+
+        let mut reader = finished_stream(&data);
+        let mut rcvd = Vec::new();
+        reader.read_to_end(&mut rcvd).await.unwrap();
+        assert_eq!(rcvd, b"hellook\x00\x02");
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[tokio::test]
+    async fn test_futures_io_read() {
+        let mut reader = finished_stream(b"hello");
+        let mut rcvd = Vec::new();
+        futures::io::AsyncReadExt::read_to_end(&mut reader, &mut rcvd)
+            .await
+            .unwrap();
+        assert_eq!(rcvd, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_read_before_conn_error() {
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let conn_error = QuicError::with_default_fty(ErrorKind::Internal, "connection broken");
+
+        // 数据、FIN和连接关闭同时到达，仍然读到全部数据和流的末尾
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(sid, recver.clone());
+        let mut frame = StreamFrame::new(sid, 0, 5);
+        frame.set_eos_flag(true);
+        let incoming = Incoming(recver);
+        incoming
+            .recv_data(&frame, Bytes::from_static(b"hello"))
+            .unwrap();
+        incoming.on_conn_error(&conn_error);
+        let mut rcvd = Vec::new();
+        reader.read_to_end(&mut rcvd).await.unwrap();
+        assert_eq!(rcvd, b"hello");
+
+        // 没收到FIN的流，先读完已收到的连续数据，再报告连接错误
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(sid, recver.clone());
+        let incoming = Incoming(recver.clone());
+        incoming
+            .recv_data(&StreamFrame::new(sid, 0, 5), Bytes::from_static(b"hello"))
+            .unwrap();
+        incoming
+            .recv_data(&StreamFrame::new(sid, 10, 5), Bytes::from_static(b"world"))
+            .unwrap();
+        incoming.on_conn_error(&conn_error);
+        assert_eq!(recver.introspect().buffered, 10);
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(recver.introspect().state, RecvState::Broken);
+    }
+
+    fn timed_out(err: io::Error) -> ReadTimedOut {
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        *err.get_ref()
+            .unwrap()
+            .downcast_ref::<ReadTimedOut>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(StreamId::from(VarInt::from_u32(0)), recver.clone());
+        reader.set_read_timeout(Some(Duration::from_millis(20)));
+
+        let frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), 0, 5);
+        Incoming(recver.clone())
+            .recv_data(&frame, Bytes::from_static(b"hello"))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 5);
+
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert!(!timed_out(err).in_repair());
+        reader.stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_in_repair() {
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(StreamId::from(VarInt::from_u32(0)), recver.clone());
+        reader.set_read_timeout(Some(Duration::from_millis(20)));
+
+        // 偏移0..5的数据丢失了，后面的数据先到
+        let frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), 5, 5);
+        Incoming(recver.clone())
+            .recv_data(&frame, Bytes::from_static(b"world"))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert!(timed_out(err).in_repair());
+        reader.stop(0);
+    }
+
+    /// 边检查边计算SHA256，并记录交给它的每段数据
+    struct Sha256Inspector {
+        context: Option<ring::digest::Context>,
+        ranges: Arc<Mutex<Vec<(u64, usize)>>>,
+        digest: Arc<Mutex<Option<(u64, ring::digest::Digest)>>>,
+    }
+
+    impl StreamInspector for Sha256Inspector {
+        fn on_data(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+            self.ranges.lock().unwrap().push((offset, data.len()));
+            self.context.as_mut().unwrap().update(data);
+            Ok(())
+        }
+
+        fn on_finish(&mut self, final_size: u64) -> io::Result<()> {
+            let digest = self.context.take().unwrap().finish();
+            *self.digest.lock().unwrap() = Some((final_size, digest));
+            Ok(())
+        }
+    }
+
+    fn stream_frame(offset: u64, len: usize, total: usize) -> StreamFrame {
+        let mut frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), offset, len);
+        frame.set_eos_flag(offset as usize + len == total);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_inspector_hash_lossy_transfer() {
+        let data = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let data = Bytes::from(data);
+        let chunk = 1200;
+
+        // 第一轮丢掉三分之一的包，第二轮重传，并夹杂着重复、重叠的数据
+        let mut frames = vec![];
+        let offsets = (0..data.len()).step_by(chunk).collect::<Vec<_>>();
+        for (i, &offset) in offsets.iter().enumerate() {
+            if i % 3 != 1 {
+                frames.push((offset, chunk.min(data.len() - offset)));
+            }
+        }
+        for (i, &offset) in offsets.iter().enumerate().rev() {
+            if i % 3 == 1 {
+                frames.push((offset, chunk.min(data.len() - offset)));
+            }
+            let overlap = offset + chunk / 2;
+            if overlap < data.len() {
+                frames.push((overlap, chunk.min(data.len() - overlap)));
+            }
+        }
+        frames.push((0, chunk));
+
+        let recver = ArcRecver::new(1_000_000);
+        let mut reader = Reader::new(StreamId::from(VarInt::from_u32(0)), recver.clone());
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let digest = Arc::new(Mutex::new(None));
+        reader.set_inspector(Box::new(Sha256Inspector {
+            context: Some(ring::digest::Context::new(&ring::digest::SHA256)),
+            ranges: ranges.clone(),
+            digest: digest.clone(),
+        }));
+
+        let deliver = async {
+            let incoming = Incoming(recver.clone());
+            for (offset, len) in frames {
+                let frame = stream_frame(offset as u64, len, data.len());
+                incoming
+                    .recv_data(&frame, data.slice(offset..offset + len))
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+        };
+        let read = async {
+            let mut rcvd = vec![];
+            let mut buf = [0u8; 500];
+            loop {
+                let n = reader.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break rcvd;
+                }
+                rcvd.extend_from_slice(&buf[..n]);
+            }
+        };
+        let ((), rcvd) = tokio::join!(deliver, read);
+        assert_eq!(rcvd, data);
+
+        let (final_size, digest) = digest.lock().unwrap().take().unwrap();
+        assert_eq!(final_size, data.len() as u64);
+        let expected = ring::digest::digest(&ring::digest::SHA256, &data);
+        assert_eq!(digest.as_ref(), expected.as_ref());
+
+        // 每个字节恰好检查一次，且按顺序
+        let mut next = 0;
+        for (offset, len) in ranges.lock().unwrap().iter() {
+            assert_eq!(*offset, next);
+            next += *len as u64;
+        }
+        assert_eq!(next, data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_inspector_duplicated_data() {
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(StreamId::from(VarInt::from_u32(0)), recver.clone());
+        let ranges = Arc::new(Mutex::new(vec![]));
+        reader.set_inspector(Box::new(Sha256Inspector {
+            context: Some(ring::digest::Context::new(&ring::digest::SHA256)),
+            ranges: ranges.clone(),
+            digest: Arc::default(),
+        }));
+
+        let data = Bytes::from_static(b"hello world");
+        let incoming = Incoming(recver.clone());
+        incoming
+            .recv_data(&stream_frame(0, 5, 11), data.slice(..5))
+            .unwrap();
+        incoming
+            .recv_data(&stream_frame(0, 5, 11), data.slice(..5))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 5);
+
+        // 已经读过的数据又重传了，与未读的数据重叠
+        incoming
+            .recv_data(&stream_frame(2, 6, 11), data.slice(2..8))
+            .unwrap();
+        incoming
+            .recv_data(&stream_frame(3, 8, 11), data.slice(3..))
+            .unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 6);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+
+        assert_eq!(*ranges.lock().unwrap(), vec![(0, 5), (5, 6)]);
+    }
+
+    struct RejectingInspector;
+
+    impl StreamInspector for RejectingInspector {
+        fn on_data(&mut self, _offset: u64, data: &[u8]) -> io::Result<()> {
+            if data.starts_with(b"\x7fELF") {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "no executables"));
+            }
+            Ok(())
+        }
+
+        fn stop_code(&self) -> Option<u64> {
+            Some(7)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inspector_error_stops_stream() {
+        let recver = ArcRecver::new(1000);
+        let mut reader = Reader::new(StreamId::from(VarInt::from_u32(0)), recver.clone());
+        reader.set_inspector(Box::new(RejectingInspector));
+
+        let frame = StreamFrame::new(StreamId::from(VarInt::from_u32(0)), 0, 8);
+        let incoming = Incoming(recver.clone());
+        incoming
+            .recv_data(&frame, Bytes::from_static(b"\x7fELF\x02\x01\x01\x00"))
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(incoming.is_stopped_by_app().await, Some(7));
+
+        // 已经因检查出错而停止，应用层再停止也无妨
+        reader.stop(0);
+    }
+}
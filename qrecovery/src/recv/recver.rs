@@ -2,15 +2,17 @@ use std::{
     io,
     sync::{Arc, Mutex, MutexGuard},
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
 use bytes::{BufMut, Bytes};
 use qbase::{
-    error::{Error, ErrorKind},
+    error::{clone_io_error, Error, ErrorKind},
     frame::{BeFrame, ResetStreamFrame, StreamFrame},
+    util::ArcTraceContext,
 };
 
-use super::rcvbuf;
+use super::{rcvbuf, window::ArcWindowTuning};
 
 #[derive(Debug)]
 pub(super) struct Recv {
@@ -21,10 +23,18 @@ pub(super) struct Recv {
     largest_data_offset: u64,
     max_data_size: u64,
     buf_exceeds_half_waker: Option<Waker>,
+    // 接收窗口，随读取的速度和RTT增长，见ArcWindowTuning
+    window: u64,
+    tuning: ArcWindowTuning,
+    // 上次更新窗口的时间，以及那时读到的位置，用于测量读取的速度
+    epoch: Option<(Instant, u64)>,
+    // 据对方的STREAM_DATA_BLOCKED帧，对方受限于我方窗口的起始时间，以及此前受限的总时长
+    peer_blocked_since: Option<Instant>,
+    peer_blocked_total: Duration,
 }
 
 impl Recv {
-    pub(super) fn with(buf_size: u64) -> Self {
+    pub(super) fn with(buf_size: u64, tuning: ArcWindowTuning) -> Self {
         Self {
             rcvbuf: rcvbuf::RecvBuf::default(),
             read_waker: None,
@@ -33,9 +43,19 @@ impl Recv {
             largest_data_offset: 0,
             max_data_size: buf_size,
             buf_exceeds_half_waker: None,
+            window: buf_size,
+            tuning,
+            epoch: None,
+            peer_blocked_since: None,
+            peer_blocked_total: Duration::ZERO,
         }
     }
 
+    /// 已读过了窗口的一半，需要更新MAX_STREAM_DATA
+    fn exceeds_half_window(&self) -> bool {
+        self.rcvbuf.offset() + self.window / 2 > self.max_data_size
+    }
+
     pub(super) fn recv(&mut self, stream_frame: &StreamFrame, body: Bytes) -> Result<usize, Error> {
         let begin = stream_frame.offset();
 
@@ -66,10 +86,13 @@ impl Recv {
         buf: &mut impl BufMut,
     ) -> Poll<io::Result<()>> {
         if self.rcvbuf.is_readable() {
+            // 从第一次读开始测量读取的速度
+            if self.epoch.is_none() {
+                self.epoch = Some((Instant::now(), self.rcvbuf.offset()));
+            }
             self.rcvbuf.read(buf);
 
-            let threshold = 1_000_000;
-            if self.rcvbuf.offset() + threshold > self.max_data_size {
+            if self.exceeds_half_window() {
                 if let Some(waker) = self.buf_exceeds_half_waker.take() {
                     waker.wake()
                 }
@@ -84,9 +107,17 @@ impl Recv {
 
     pub(super) fn poll_update_window(&mut self, cx: &mut Context<'_>) -> Poll<Option<u64>> {
         assert!(self.buf_exceeds_half_waker.is_none());
-        let threshold = 1_000_000;
-        if self.rcvbuf.offset() + threshold > self.max_data_size {
-            self.max_data_size = self.rcvbuf.offset() + threshold * 2;
+        if self.exceeds_half_window() {
+            let read = self.rcvbuf.offset();
+            let now = Instant::now();
+            if let Some((since, from)) = self.epoch.replace((now, read)) {
+                self.window = self.tuning.tune(self.window, read - from, now - since);
+            }
+            // 已通告的上限不会收回
+            self.max_data_size = self.max_data_size.max(read + self.window);
+            if let Some(since) = self.peer_blocked_since.take() {
+                self.peer_blocked_total += since.elapsed();
+            }
             Poll::Ready(Some(self.max_data_size))
         } else {
             self.buf_exceeds_half_waker = Some(cx.waker().clone());
@@ -94,6 +125,13 @@ impl Recv {
         }
     }
 
+    /// 对方告知受限于limit，只有仍是当前的窗口时才算数，过时的帧可能在窗口更新之后才到
+    pub(super) fn on_peer_blocked(&mut self, limit: u64) {
+        if limit >= self.max_data_size {
+            self.peer_blocked_since.get_or_insert_with(Instant::now);
+        }
+    }
+
     pub(super) fn poll_stop(&mut self, cx: &mut Context<'_>) -> Poll<Option<u64>> {
         if let Some(err_code) = self.stop_state {
             Poll::Ready(Some(err_code))
@@ -115,20 +153,53 @@ impl Recv {
         self.stop_state.is_some()
     }
 
-    pub(super) fn determin_size(&mut self, total_size: u64) -> SizeKnown {
+    /// Some data has been received but can't be read, because the data before it is
+    /// missing, and is waiting for the retransmission.
+    pub(super) fn is_in_repair(&self) -> bool {
+        !self.rcvbuf.is_empty() && !self.rcvbuf.is_readable()
+    }
+
+    pub(super) fn determin_size(&mut self, stream_frame: &StreamFrame) -> Result<SizeKnown, Error> {
+        // 携带FIN的帧不经过recv，即便是空帧，其offset也要受流量控制，且不能小于已收到的数据
+        let total_size = stream_frame.offset() + stream_frame.len() as u64;
+        if total_size > self.max_data_size {
+            return Err(Error::new(
+                ErrorKind::FlowControl,
+                stream_frame.frame_type(),
+                format!(
+                    "{} send {total_size} bytes which exceeds the stream data limit {}",
+                    stream_frame.id, self.max_data_size
+                ),
+            ));
+        }
+        if total_size < self.largest_data_offset {
+            return Err(Error::new(
+                ErrorKind::FinalSize,
+                stream_frame.frame_type(),
+                format!(
+                    "{} fin with a wrong smaller final size {total_size} than the largest rcvd data offset {}",
+                    stream_frame.id, self.largest_data_offset
+                ),
+            ));
+        }
         if let Some(waker) = self.buf_exceeds_half_waker.take() {
             waker.wake();
         }
         if let Some(waker) = self.read_waker.take() {
             waker.wake();
         }
-        SizeKnown {
+        Ok(SizeKnown {
             total_size,
             rcvbuf: std::mem::take(&mut self.rcvbuf),
             stop_state: self.stop_state.take(),
             read_waker: self.read_waker.take(),
             stop_waker: self.stop_waker.take(),
-        }
+        })
+    }
+
+    /// Take the data received but not read yet, when the connection is broken.
+    pub(super) fn take_rcvbuf(&mut self) -> rcvbuf::RecvBuf {
+        std::mem::take(&mut self.rcvbuf)
     }
 
     pub(super) fn wake_all(&mut self) {
@@ -143,8 +214,23 @@ impl Recv {
         }
     }
 
-    pub(super) fn recv_reset(&mut self, reset_frame: &ResetStreamFrame) -> Result<u64, Error> {
+    /// Return the final size, and the bytes before it that are never received, see
+    /// [`Incoming::recv_reset`](super::Incoming::recv_reset).
+    pub(super) fn recv_reset(
+        &mut self,
+        reset_frame: &ResetStreamFrame,
+    ) -> Result<(u64, u64), Error> {
         let final_size = reset_frame.final_size.into_inner();
+        if final_size > self.max_data_size {
+            return Err(Error::new(
+                ErrorKind::FlowControl,
+                reset_frame.frame_type(),
+                format!(
+                    "{} reset with the final size {final_size} which exceeds the stream data limit {}",
+                    reset_frame.stream_id, self.max_data_size
+                ),
+            ));
+        }
         if final_size < self.largest_data_offset {
             return Err(Error::new(
                 ErrorKind::FinalSize,
@@ -156,7 +242,7 @@ impl Recv {
             ));
         }
         self.wake_all();
-        Ok(final_size)
+        Ok((final_size, final_size - self.rcvbuf.rcvd()))
     }
 }
 
@@ -258,6 +344,17 @@ impl SizeKnown {
         self.stop_state.is_some()
     }
 
+    /// Some data has been received but can't be read, because the data before it is
+    /// missing, and is waiting for the retransmission.
+    pub(super) fn is_in_repair(&self) -> bool {
+        !self.rcvbuf.is_empty() && !self.rcvbuf.is_readable()
+    }
+
+    /// Take the data received but not read yet, when the connection is broken.
+    pub(super) fn take_rcvbuf(&mut self) -> rcvbuf::RecvBuf {
+        std::mem::take(&mut self.rcvbuf)
+    }
+
     pub(super) fn wake_all(&mut self) {
         if let Some(waker) = self.stop_waker.take() {
             waker.wake()
@@ -267,7 +364,11 @@ impl SizeKnown {
         }
     }
 
-    pub(super) fn recv_reset(&mut self, reset_frame: &ResetStreamFrame) -> Result<u64, Error> {
+    /// See [`Recv::recv_reset`].
+    pub(super) fn recv_reset(
+        &mut self,
+        reset_frame: &ResetStreamFrame,
+    ) -> Result<(u64, u64), Error> {
         let final_size = reset_frame.final_size.into_inner();
         if final_size != self.total_size {
             return Err(Error::new(
@@ -280,7 +381,7 @@ impl SizeKnown {
             ));
         }
         self.wake_all();
-        Ok(final_size)
+        Ok((final_size, final_size - self.rcvbuf.rcvd()))
     }
 }
 
@@ -334,6 +435,31 @@ impl DataRcvd {
     }
 }
 
+/// The connection is broken while some data received is not read yet. The data is still
+/// readable, but it can't be followed by any more: the connection error is returned once
+/// it runs out, rather than the end of the stream.
+#[derive(Debug)]
+pub(super) struct Broken {
+    rcvbuf: rcvbuf::RecvBuf,
+    error: io::Error,
+}
+
+impl Broken {
+    pub(super) fn new(rcvbuf: rcvbuf::RecvBuf, error: io::Error) -> Self {
+        Self { rcvbuf, error }
+    }
+
+    /// Never "Pending", as no more data would arrive.
+    pub(super) fn poll_read(&mut self, buf: &mut impl BufMut) -> Poll<io::Result<()>> {
+        if self.rcvbuf.is_readable() {
+            self.rcvbuf.read(buf);
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Ready(Err(clone_io_error(&self.error)))
+        }
+    }
+}
+
 /// Receiving stream state machine. In fact, here the state variables such as
 /// is_closed/is_reset are replaced by a state machine. This not only provides
 /// clearer semantics and aligns with the QUIC RFC specification but also
@@ -343,28 +469,202 @@ pub(super) enum Recver {
     Recv(Recv),
     SizeKnown(SizeKnown),
     DataRcvd(DataRcvd),
-    ResetRcvd(u64),
+    /// The final size and the application error code of the RESET_STREAM frame.
+    ResetRcvd(u64, u64),
     DataRead,
-    ResetRead,
+    /// The application error code of the RESET_STREAM frame.
+    ResetRead(u64),
+    Broken(Broken),
 }
 
 impl Recver {
-    pub(super) fn new(buf_size: u64) -> Self {
-        Self::Recv(Recv::with(buf_size))
+    pub(super) fn new(buf_size: u64, tuning: ArcWindowTuning) -> Self {
+        Self::Recv(Recv::with(buf_size, tuning))
+    }
+
+    fn introspect(&self) -> RecvIntrospection {
+        match self {
+            Recver::Recv(r) => RecvIntrospection {
+                received: r.largest_data_offset,
+                max_data: Some(r.max_data_size),
+                window: Some(r.window),
+                stop_code: r.stop_state,
+                read_waker: r.read_waker.is_some(),
+                stop_waker: r.stop_waker.is_some(),
+                window_waker: r.buf_exceeds_half_waker.is_some(),
+                peer_blocked: r.peer_blocked_since.map(|since| since.elapsed()),
+                peer_blocked_total: r.peer_blocked_total
+                    + r.peer_blocked_since
+                        .map(|since| since.elapsed())
+                        .unwrap_or_default(),
+                ..RecvIntrospection::with_rcvbuf(RecvState::Recv, &r.rcvbuf)
+            },
+            Recver::SizeKnown(r) => RecvIntrospection {
+                final_size: Some(r.total_size),
+                stop_code: r.stop_state,
+                read_waker: r.read_waker.is_some(),
+                stop_waker: r.stop_waker.is_some(),
+                ..RecvIntrospection::with_rcvbuf(RecvState::SizeKnown, &r.rcvbuf)
+            },
+            Recver::DataRcvd(r) => RecvIntrospection {
+                final_size: Some(r.rcvbuf.available()),
+                ..RecvIntrospection::with_rcvbuf(RecvState::DataRcvd, &r.rcvbuf)
+            },
+            Recver::ResetRcvd(final_size, _) => RecvIntrospection {
+                final_size: Some(*final_size),
+                ..RecvIntrospection::with_state(RecvState::ResetRcvd)
+            },
+            Recver::DataRead => RecvIntrospection::with_state(RecvState::DataRead),
+            Recver::ResetRead(_) => RecvIntrospection::with_state(RecvState::ResetRead),
+            Recver::Broken(r) => RecvIntrospection::with_rcvbuf(RecvState::Broken, &r.rcvbuf),
+        }
+    }
+}
+
+/// The state of the receiving part of a stream, see
+/// [section 3.2](https://www.rfc-editor.org/rfc/rfc9000.html#name-receiving-stream-states)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RecvState {
+    Recv,
+    SizeKnown,
+    DataRcvd,
+    ResetRcvd,
+    DataRead,
+    ResetRead,
+    /// The connection is broken, the stream is gone with it once the data received is read.
+    Broken,
+}
+
+/// A snapshot of the receiving part of a stream for diagnostics, which never carries any
+/// stream data.
+///
+/// The offsets and sizes are in bytes from the start of the stream. Some of them are lost
+/// once the data is read or the stream is reset, they are zero or `None` then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RecvIntrospection {
+    pub state: RecvState,
+    /// The end of the data read by the application.
+    pub read: u64,
+    /// The end of the data received contiguously from the start of the stream.
+    pub contiguous: u64,
+    /// The largest offset received, the data before it may be incomplete.
+    pub received: u64,
+    /// The bytes received but not read yet.
+    pub buffered: u64,
+    /// The flow control limit advertised to the peer, before the final size is known.
+    pub max_data: Option<u64>,
+    /// The receive window in use, which grows with the throughput, see [`ArcWindowTuning`].
+    pub window: Option<u64>,
+    pub final_size: Option<u64>,
+    /// The error code the application stopped the stream with.
+    pub stop_code: Option<u64>,
+    pub read_waker: bool,
+    pub stop_waker: bool,
+    /// Whether a task is waiting to update the receive window.
+    pub window_waker: bool,
+    /// How long the peer has been blocked on our MAX_STREAM_DATA, inferred from its
+    /// STREAM_DATA_BLOCKED frames, if it is now.
+    pub peer_blocked: Option<Duration>,
+    /// The time the peer spent blocked on our MAX_STREAM_DATA in total, before the final
+    /// size is known.
+    pub peer_blocked_total: Duration,
+}
+
+impl RecvIntrospection {
+    fn with_state(state: RecvState) -> Self {
+        Self {
+            state,
+            read: 0,
+            contiguous: 0,
+            received: 0,
+            buffered: 0,
+            max_data: None,
+            window: None,
+            final_size: None,
+            stop_code: None,
+            read_waker: false,
+            stop_waker: false,
+            window_waker: false,
+            peer_blocked: None,
+            peer_blocked_total: Duration::ZERO,
+        }
+    }
+
+    fn with_rcvbuf(state: RecvState, rcvbuf: &rcvbuf::RecvBuf) -> Self {
+        Self {
+            read: rcvbuf.offset(),
+            contiguous: rcvbuf.available(),
+            received: rcvbuf.largest(),
+            buffered: rcvbuf.buffered(),
+            ..Self::with_state(state)
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct ArcRecver(Arc<Mutex<io::Result<Recver>>>);
+#[derive(Clone)]
+pub struct ArcRecver(Arc<Mutex<io::Result<Recver>>>, ArcTraceContext);
 
 impl ArcRecver {
     pub fn new(buf_size: u64) -> Self {
-        ArcRecver(Arc::new(Mutex::new(Ok(Recver::new(buf_size)))))
+        Self::with_trace(buf_size, ArcTraceContext::default())
+    }
+
+    /// 流级别的追踪上下文，该流的异步任务都在其中执行
+    pub fn with_trace(buf_size: u64, trace: ArcTraceContext) -> Self {
+        Self::with_tuning(buf_size, ArcWindowTuning::default(), trace)
+    }
+
+    /// The receive window grows from `buf_size` as [`ArcWindowTuning`] tunes it.
+    pub fn with_tuning(buf_size: u64, tuning: ArcWindowTuning, trace: ArcTraceContext) -> Self {
+        let recver = Recver::new(buf_size, tuning);
+        ArcRecver(Arc::new(Mutex::new(Ok(recver))), trace)
+    }
+
+    pub fn trace(&self) -> &ArcTraceContext {
+        &self.1
     }
 
     pub(super) fn recver(&self) -> MutexGuard<io::Result<Recver>> {
         self.0.lock().unwrap()
     }
+
+    /// 预览流开头已收到的连续数据，至多len字节，不消费数据；同时返回流的数据是否已不会再增加，
+    /// 即已全部收到、被重置或连接出错
+    pub(crate) fn preview(&self, len: usize) -> (Vec<u8>, bool) {
+        match self.recver().as_ref() {
+            Ok(Recver::Recv(r)) => (r.rcvbuf.peek(len), false),
+            Ok(Recver::SizeKnown(r)) => (r.rcvbuf.peek(len), false),
+            Ok(Recver::DataRcvd(r)) => (r.rcvbuf.peek(len), true),
+            Ok(Recver::Broken(r)) => (r.rcvbuf.peek(len), true),
+            _ => (Vec::new(), true),
+        }
+    }
+
+    /// Take a snapshot of the receiving state for diagnostics, see [`RecvIntrospection`].
+    pub fn introspect(&self) -> RecvIntrospection {
+        match self.recver().as_ref() {
+            Ok(recver) => recver.introspect(),
+            Err(_) => RecvIntrospection::with_state(RecvState::Broken),
+        }
+    }
+}
+
+// 只输出接收状态的快照，不输出收到的数据；Debug可能在持有锁时被调用，不能阻塞
+impl std::fmt::Debug for ArcRecver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("ArcRecver");
+        match self.0.try_lock() {
+            Ok(recver) => match recver.as_ref() {
+                Ok(recver) => s.field("state", &recver.introspect()),
+                Err(e) => s.field("state", &RecvState::Broken).field("error", e),
+            },
+            Err(_) => s.field("state", &format_args!("<locked>")),
+        };
+        s.finish()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,102 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The default upper bound of the receive window of a stream, see [`ArcWindowTuning`].
+pub const DEFAULT_MAX_STREAM_WINDOW: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct WindowTuning {
+    rtt: Duration,
+    max_window: u64,
+    conn_window: u64,
+}
+
+impl Default for WindowTuning {
+    fn default() -> Self {
+        Self {
+            rtt: Duration::ZERO,
+            max_window: DEFAULT_MAX_STREAM_WINDOW,
+            conn_window: u64::MAX,
+        }
+    }
+}
+
+/// The auto-tuning of the receive windows, shared by the streams of a connection.
+///
+/// Every time a stream updates its MAX_STREAM_DATA, it measures how fast the data was read
+/// since the last update, and grows its window toward twice the bandwidth-delay product,
+/// at most doubling each time. The window is bounded by `max_window`, and the
+/// connection-level window, beyond which the stream can't be sent faster anyway. It never
+/// shrinks, neither does the limit advertised.
+///
+/// Nothing grows until the RTT is known, see [`ArcWindowTuning::update_rtt`].
+#[derive(Debug, Default, Clone)]
+pub struct ArcWindowTuning(Arc<Mutex<WindowTuning>>);
+
+impl ArcWindowTuning {
+    pub fn update_rtt(&self, rtt: Duration) {
+        self.0.lock().unwrap().rtt = rtt;
+    }
+
+    /// Set the upper bound of the receive window of a stream, [`DEFAULT_MAX_STREAM_WINDOW`]
+    /// by default. A window grown beyond it already stays.
+    pub fn set_max_window(&self, max_window: u64) {
+        self.0.lock().unwrap().max_window = max_window;
+    }
+
+    /// The bytes allocated for the tuning.
+    pub(crate) fn footprint(&self) -> usize {
+        std::mem::size_of_val(&*self.0)
+    }
+
+    /// Set the connection-level window of the MAX_DATA frames in use.
+    pub fn set_conn_window(&self, conn_window: u64) {
+        self.0.lock().unwrap().conn_window = conn_window;
+    }
+
+    /// The next window, given the current one and `read` bytes read in `elapsed`.
+    pub(super) fn tune(&self, window: u64, read: u64, elapsed: Duration) -> u64 {
+        let tuning = *self.0.lock().unwrap();
+        if tuning.rtt.is_zero() || elapsed.is_zero() {
+            return window;
+        }
+        // 两倍的带宽时延积，读得比窗口允许的慢时，窗口就不必再增长
+        let target = read as u128 * tuning.rtt.as_nanos() * 2 / elapsed.as_nanos();
+        u64::try_from(target)
+            .unwrap_or(u64::MAX)
+            .min(window.saturating_mul(2))
+            .min(tuning.max_window.min(tuning.conn_window))
+            .max(window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tune_window() {
+        let tuning = ArcWindowTuning::default();
+        let rtt = Duration::from_millis(100);
+        // RTT未知时不调整
+        assert_eq!(tuning.tune(1000, 1000, rtt), 1000);
+
+        tuning.update_rtt(rtt);
+        // 一个RTT读完了整个窗口，窗口受限，翻倍
+        assert_eq!(tuning.tune(1000, 1000, rtt), 2000);
+        // 读得再快，一次也至多翻倍
+        assert_eq!(tuning.tune(1000, 10_000, rtt), 2000);
+        // 读得慢，窗口不增长，也不收缩
+        assert_eq!(tuning.tune(1000, 100, rtt), 1000);
+        assert_eq!(tuning.tune(1000, 750, rtt), 1500);
+
+        tuning.set_max_window(1200);
+        assert_eq!(tuning.tune(1000, 1000, rtt), 1200);
+        tuning.set_conn_window(1100);
+        assert_eq!(tuning.tune(1000, 1000, rtt), 1100);
+        // 已经超过上限的窗口保持不变
+        assert_eq!(tuning.tune(5000, 5000, rtt), 5000);
+    }
+}
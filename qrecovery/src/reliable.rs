@@ -1,11 +1,21 @@
 use std::{
+    cmp::Reverse,
     collections::VecDeque,
     sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
 };
 
-use deref_derive::{Deref, DerefMut};
+use deref_derive::Deref;
 use enum_dispatch::enum_dispatch;
-use qbase::frame::{io::WriteFrame, BeFrame, CryptoFrame, ReliableFrame, SendFrame, StreamFrame};
+use qbase::{
+    frame::{
+        io::WriteFrame, BeFrame, CryptoFrame, DatagramFrame, FrameType, MaxStreamsFrame,
+        ReliableFrame, SendFrame, StreamCtlFrame, StreamFrame, StreamsBlockedFrame,
+    },
+    streamid::{Dir, StreamId},
+    util::Wakers,
+};
+use sentpkt::MergeFrame;
 
 pub mod rcvdpkt;
 pub mod sentpkt;
@@ -16,26 +26,172 @@ pub enum GuaranteedFrame {
     Stream(StreamFrame),
     Crypto(CryptoFrame),
     Reliable(ReliableFrame),
+    /// Never retransmitted, recorded only to tell the application whether it's acknowledged or lost.
+    Datagram(TrackedDatagram),
+}
+
+/// A datagram frame whose fate the application wants to know, with the sequence id of the
+/// datagram in the queue of the connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TrackedDatagram {
+    pub frame: DatagramFrame,
+    pub seq: u64,
+}
+
+impl BeFrame for TrackedDatagram {
+    fn frame_type(&self) -> FrameType {
+        self.frame.frame_type()
+    }
+
+    fn max_encoding_size(&self) -> usize {
+        self.frame.max_encoding_size()
+    }
+
+    fn encoding_size(&self) -> usize {
+        self.frame.encoding_size()
+    }
+}
+
+impl MergeFrame for GuaranteedFrame {
+    fn merge(&mut self, other: &Self) -> bool {
+        // 可靠帧各有各的语义，不合并
+        match (self, other) {
+            (Self::Stream(frame), Self::Stream(other)) => frame.merge(other),
+            (Self::Crypto(frame), Self::Crypto(other)) => frame.merge(other),
+            _ => false,
+        }
+    }
+}
+
+/// How urgently a reliable frame is sent. The more urgent frames are sent first, and the
+/// frames of the same urgency in the order they are queued.
+///
+/// When the connection is limited by the congestion for long, the frames pile up, and a
+/// RESET_STREAM shouldn't wait behind the routine window updates to release the buffer of
+/// the stream on the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FrameUrgency {
+    /// The *_BLOCKED frames, which are only informative.
+    Blocked,
+    Routine,
+    /// The RESET_STREAM and STOP_SENDING frames.
+    Urgent,
 }
 
-#[derive(Debug, Default, Deref, DerefMut)]
-pub struct RawReliableFrameDeque(VecDeque<ReliableFrame>);
+impl From<&ReliableFrame> for FrameUrgency {
+    fn from(frame: &ReliableFrame) -> Self {
+        match frame {
+            ReliableFrame::Stream(
+                StreamCtlFrame::ResetStream(_) | StreamCtlFrame::StopSending(_),
+            ) => Self::Urgent,
+            ReliableFrame::DataBlocked(_)
+            | ReliableFrame::Stream(
+                StreamCtlFrame::StreamDataBlocked(_) | StreamCtlFrame::StreamsBlocked(_),
+            ) => Self::Blocked,
+            _ => Self::Routine,
+        }
+    }
+}
+
+/// The frames of the same key supersede each other, only the one carrying the largest
+/// value is worth sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoalescingKey {
+    MaxData,
+    MaxStreamData(StreamId),
+    MaxStreams(Dir),
+    DataBlocked,
+    StreamDataBlocked(StreamId),
+    StreamsBlocked(Dir),
+}
+
+fn coalescing_key(frame: &ReliableFrame) -> Option<(CoalescingKey, u64)> {
+    Some(match frame {
+        ReliableFrame::MaxData(f) => (CoalescingKey::MaxData, f.max_data.into_inner()),
+        ReliableFrame::DataBlocked(f) => (CoalescingKey::DataBlocked, f.limit.into_inner()),
+        ReliableFrame::Stream(StreamCtlFrame::MaxStreamData(f)) => (
+            CoalescingKey::MaxStreamData(f.stream_id),
+            f.max_stream_data.into_inner(),
+        ),
+        ReliableFrame::Stream(StreamCtlFrame::MaxStreams(f)) => match f {
+            MaxStreamsFrame::Bi(max) => (CoalescingKey::MaxStreams(Dir::Bi), max.into_inner()),
+            MaxStreamsFrame::Uni(max) => (CoalescingKey::MaxStreams(Dir::Uni), max.into_inner()),
+        },
+        ReliableFrame::Stream(StreamCtlFrame::StreamDataBlocked(f)) => (
+            CoalescingKey::StreamDataBlocked(f.stream_id),
+            f.maximum_stream_data.into_inner(),
+        ),
+        ReliableFrame::Stream(StreamCtlFrame::StreamsBlocked(f)) => match f {
+            StreamsBlockedFrame::Bi(sid) => (CoalescingKey::StreamsBlocked(Dir::Bi), sid.id()),
+            StreamsBlockedFrame::Uni(sid) => (CoalescingKey::StreamsBlocked(Dir::Uni), sid.id()),
+        },
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Default, Deref)]
+pub struct RawReliableFrameDeque {
+    #[deref]
+    frames: VecDeque<ReliableFrame>,
+    // 等待所有可靠帧都发送出去的任务，可能有多个
+    drain_wakers: Wakers,
+}
 
 impl RawReliableFrameDeque {
     fn with_capacity(capacity: usize) -> Self {
-        Self(VecDeque::with_capacity(capacity))
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            drain_wakers: Wakers::default(),
+        }
+    }
+
+    /// Queue the frame, or merge it into the queued one superseded by it, e.g. the
+    /// MAX_STREAM_DATA frames of the same stream, which keeps its place in the queue. The
+    /// frames judged lost are queued again this way too, and are dropped if superseded.
+    pub fn push_back(&mut self, frame: ReliableFrame) {
+        if let Some((key, value)) = coalescing_key(&frame) {
+            let queued = self.frames.iter_mut().find_map(|queued| {
+                let (queued_key, queued_value) = coalescing_key(queued)?;
+                (queued_key == key).then_some((queued, queued_value))
+            });
+            if let Some((queued, queued_value)) = queued {
+                if queued_value < value {
+                    *queued = frame;
+                }
+                return;
+            }
+        }
+        self.frames.push_back(frame);
     }
 
     fn try_read(&mut self, mut buf: &mut [u8]) -> Option<(ReliableFrame, usize)> {
-        let frame = self.0.front()?;
+        // 最紧急的帧中最早入队的那个
+        let (idx, frame) = self
+            .frames
+            .iter()
+            .enumerate()
+            .max_by_key(|(idx, frame)| (FrameUrgency::from(*frame), Reverse(*idx)))?;
         if frame.max_encoding_size() <= buf.len() || frame.encoding_size() <= buf.len() {
             let buf_len = buf.len();
             buf.put_frame(frame);
-            Some((self.0.pop_front().unwrap(), buf_len - buf.len()))
+            let frame = self.frames.remove(idx).unwrap();
+            if self.frames.is_empty() {
+                self.drain_wakers.wake_all();
+            }
+            Some((frame, buf_len - buf.len()))
         } else {
             None
         }
     }
+
+    fn poll_drained(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.frames.is_empty() {
+            Poll::Ready(())
+        } else {
+            self.drain_wakers.register(cx.waker());
+            Poll::Pending
+        }
+    }
 }
 
 impl<T> Extend<T> for RawReliableFrameDeque
@@ -43,7 +199,9 @@ where
     T: Into<ReliableFrame>,
 {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
-        self.0.extend(iter.into_iter().map(Into::into));
+        for frame in iter {
+            self.push_back(frame.into());
+        }
     }
 }
 
@@ -79,6 +237,12 @@ impl ArcReliableFrameDeque {
     pub fn try_read(&self, buf: &mut [u8]) -> Option<(ReliableFrame, usize)> {
         self.lock_guard().try_read(buf)
     }
+
+    /// Polls whether all the queued frames have been sent once. The frames judged lost
+    /// will be queued again, and so will block the next poll.
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.lock_guard().poll_drained(cx)
+    }
 }
 
 impl<T> SendFrame<T> for ArcReliableFrameDeque
@@ -91,4 +255,90 @@ where
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use qbase::frame::HandshakeDoneFrame;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reliable_frames_drained() {
+        let frames = ArcReliableFrameDeque::with_capacity(2);
+        frames.send_frame([HandshakeDoneFrame, HandshakeDoneFrame]);
+
+        // 两个任务同时等待，都要被唤醒
+        let drained = [(); 2].map(|_| {
+            let frames = frames.clone();
+            tokio::spawn(async move { std::future::poll_fn(|cx| frames.poll_drained(cx)).await })
+        });
+
+        let mut buf = [0u8; 16];
+        assert!(frames.try_read(&mut buf).is_some());
+        tokio::task::yield_now().await;
+        assert!(drained.iter().all(|task| !task.is_finished()));
+
+        assert!(frames.try_read(&mut buf).is_some());
+        for task in drained {
+            task.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_urgent_frames_first() {
+        use qbase::{
+            frame::{MaxStreamDataFrame, ResetStreamFrame, StreamDataBlockedFrame},
+            varint::VarInt,
+        };
+
+        let sid = |id: u32| StreamId::from(VarInt::from_u32(id));
+        let frames = ArcReliableFrameDeque::with_capacity(256);
+        frames.send_frame([StreamCtlFrame::StreamDataBlocked(StreamDataBlockedFrame {
+            stream_id: sid(0),
+            maximum_stream_data: VarInt::from_u32(1000),
+        })]);
+        frames.send_frame((0..200).map(|i| {
+            StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                stream_id: sid(i * 4),
+                max_stream_data: VarInt::from_u32(1000),
+            })
+        }));
+        let reset = StreamCtlFrame::ResetStream(ResetStreamFrame {
+            stream_id: sid(800),
+            app_error_code: VarInt::from_u32(7),
+            final_size: VarInt::from_u32(100),
+        });
+        frames.send_frame([reset.clone()]);
+
+        // 每个RTT只能发出一个小包，RESET_STREAM仍在下一个包里
+        let mut packet = [0u8; 32];
+        let (frame, _) = frames.try_read(&mut packet).unwrap();
+        assert_eq!(frame, ReliableFrame::Stream(reset));
+        let (frame, _) = frames.try_read(&mut packet).unwrap();
+        assert!(matches!(
+            frame,
+            ReliableFrame::Stream(StreamCtlFrame::MaxStreamData(f)) if f.stream_id == sid(0)
+        ));
+
+        // 排队中的窗口更新被更大的取代，位置不变；更小的，比如判定丢失的旧帧，被丢弃
+        let update = |max_stream_data: u32| {
+            StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                stream_id: sid(4),
+                max_stream_data: VarInt::from_u32(max_stream_data),
+            })
+        };
+        frames.send_frame([update(2000), update(1500)]);
+        assert_eq!(frames.lock_guard().len(), 199 + 1);
+        let (frame, _) = frames.try_read(&mut packet).unwrap();
+        assert_eq!(frame, ReliableFrame::Stream(update(2000)));
+
+        // BLOCKED帧排在最后
+        let mut last = None;
+        while let Some((frame, _)) = frames.try_read(&mut packet) {
+            last = Some(frame);
+        }
+        assert_eq!(
+            last.map(|frame| FrameUrgency::from(&frame)),
+            Some(FrameUrgency::Blocked)
+        );
+        assert!(frames.lock_guard().is_empty());
+    }
+}
@@ -1,11 +1,56 @@
 use std::{
     collections::VecDeque,
+    mem,
     ops::DerefMut,
     sync::{Arc, Mutex, MutexGuard},
 };
 
 use deref_derive::{Deref, DerefMut};
-use qbase::{packet::PacketNumber, util::IndexDeque, varint::VARINT_MAX};
+use qbase::{
+    frame::{CryptoFrame, StreamFrame},
+    packet::PacketNumber,
+    util::IndexDeque,
+    varint::{VarInt, VARINT_MAX},
+};
+
+/// The default cap of the packets recorded one by one in a space, see
+/// [`ArcSentPktRecords::set_max_records`].
+pub const DEFAULT_MAX_SENT_RECORDS: usize = 1 << 16;
+
+/// The frames of the summarized packets merge into each other if possible, see
+/// [`ArcSentPktRecords::set_max_records`].
+pub trait MergeFrame {
+    /// Merge `other` sent after into self, return false if they can't be merged.
+    fn merge(&mut self, other: &Self) -> bool;
+}
+
+impl MergeFrame for StreamFrame {
+    fn merge(&mut self, other: &Self) -> bool {
+        // 同一个流前后相接的数据合并为一个区间，FIN之后不会再有数据
+        if self.id != other.id || self.is_fin() || self.range().end != other.offset() {
+            return false;
+        }
+        let mut merged = StreamFrame::new(self.id, self.offset(), self.len() + other.len());
+        merged.set_eos_flag(other.is_fin());
+        *self = merged;
+        true
+    }
+}
+
+impl MergeFrame for CryptoFrame {
+    fn merge(&mut self, other: &Self) -> bool {
+        if self.range().end != other.offset.into_inner() {
+            return false;
+        }
+        self.length = VarInt::from_u64(self.length.into_inner() + other.length.into_inner())
+            .expect("crypto stream offset never overflows");
+        true
+    }
+}
+
+fn never_merge<T>(_: &mut T, _: &T) -> bool {
+    false
+}
 
 /// 记录发送的数据包的状态，包括
 /// - Flighting: 数据包正在传输中
@@ -53,50 +98,144 @@ impl SentPktState {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct SentPkt {
+    state: SentPktState,
+    // 该包的第一个帧在queue中的序号，从连接开始计数，不随queue头部的移除而变
+    first_frame: u64,
+}
+
+/// The oldest packets beyond the cap of the records, whose frames are merged, and whose
+/// states are kept by a bit each, see [`ArcSentPktRecords::set_max_records`].
+#[derive(Debug)]
+struct Summary<T> {
+    // 被概括的包号从start开始，到records的offset为止
+    start: u64,
+    // 每个包一位，已确认或已丢失的置位
+    resolved: Vec<u64>,
+    unresolved: u64,
+    frames: Vec<T>,
+}
+
+impl<T> Summary<T> {
+    fn new(start: u64) -> Self {
+        Self {
+            start,
+            resolved: Vec::new(),
+            unresolved: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, pn: u64, resolved: bool) {
+        let idx = (pn - self.start) as usize;
+        if idx / 64 >= self.resolved.len() {
+            self.resolved.push(0);
+        }
+        if resolved {
+            self.resolved[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.unresolved += 1;
+        }
+    }
+
+    /// Return true if the packet is summarized and not resolved yet.
+    fn resolve(&mut self, pn: u64) -> bool {
+        let Some(idx) = pn.checked_sub(self.start).map(|idx| idx as usize) else {
+            return false;
+        };
+        match self.resolved.get_mut(idx / 64) {
+            Some(word) if *word & (1 << (idx % 64)) == 0 => {
+                *word |= 1 << (idx % 64);
+                self.unresolved -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The memory taken by the records of the sent packets in a space, see
+/// [`ArcSentPktRecords::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SentRecordsStats {
+    /// The packets recorded one by one.
+    pub records: usize,
+    /// The packets summarized beyond the cap of the records, which are neither acknowledged
+    /// nor lost yet.
+    pub summarized: u64,
+    /// The frames retained, including the merged ones of the summarized packets.
+    pub frames: usize,
+    /// An estimate of the bytes taken by the records, the frames and the summary.
+    pub memory: usize,
+}
+
 /// 记录已经发送的帧，尽最大努力省略内存分配。
 /// queue记录着所有发送过的帧，records记录着顺序发送的数据包包含几个帧，以及这些数据包的状态。
 /// 发送数据包的时候，往其中写入数据包的帧，
 /// 接收到确认的时候，更新数据包的状态，被确认就什么都不做；丢失的数据包，得重新发送
-#[derive(Debug, Default, Deref, DerefMut)]
+#[derive(Debug, Deref, DerefMut)]
 pub struct RawSentPktRecords<T> {
     #[deref]
     queue: VecDeque<T>,
-    // 记录着每个包的内容，其实是一个数字，该数字对应着queue中的record数量
-    records: IndexDeque<SentPktState, VARINT_MAX>,
+    // queue头部的帧的序号，即已从queue中移除的帧数
+    queue_base: u64,
+    // 记录着每个包的状态，以及它的帧在queue中的位置
+    records: IndexDeque<SentPkt, VARINT_MAX>,
     largest_acked_pktno: u64,
+    // 逐个记录的包数上限，超出的最早的包被概括
+    max_records: usize,
+    merge: fn(&mut T, &T) -> bool,
+    summary: Option<Summary<T>>,
+}
+
+impl<T> Default for RawSentPktRecords<T> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
 }
 
 impl<T: Clone> RawSentPktRecords<T> {
     fn on_pkt_acked(&mut self, pn: u64) -> impl Iterator<Item = T> + '_ {
-        let mut len = 0;
-        let offset = self
-            .records
-            .iter_with_idx()
-            .take_while(|(pkt_idx, _)| *pkt_idx < pn)
-            .map(|(_, s)| s.nframes())
-            .sum::<usize>();
-        if let Some(s) = self.records.get_mut(pn) {
-            len = s.be_acked();
+        let mut summarized = None;
+        if pn < self.records.offset() {
+            // 概括的包全部确认了，才确认其中的帧
+            if let Some(summary) = self.summary.as_mut() {
+                if summary.resolve(pn) && summary.unresolved == 0 {
+                    summarized = self.summary.take().map(|summary| summary.frames);
+                }
+            }
         }
-        self.queue
-            .range_mut(offset..offset + len)
-            .map(|f| f.clone())
+        let mut range = 0..0;
+        if let Some(pkt) = self.records.get_mut(pn) {
+            let offset = (pkt.first_frame - self.queue_base) as usize;
+            range = offset..offset + pkt.state.be_acked();
+        }
+        summarized
+            .into_iter()
+            .flatten()
+            .chain(self.queue.range(range).cloned())
     }
 
     fn may_loss_pkt(&mut self, pn: u64) -> impl Iterator<Item = T> + '_ {
-        let mut len = 0;
-        let offset = self
-            .records
-            .iter_with_idx()
-            .take_while(|(pkt_idx, _)| *pkt_idx < pn)
-            .map(|(_, s)| s.nframes())
-            .sum::<usize>();
-        if let Some(s) = self.records.get_mut(pn) {
-            len = s.maybe_loss();
+        let mut summarized = None;
+        if pn < self.records.offset() {
+            // 概括的包中任何一个丢失，其中的帧都判定丢失，其余的包再确认也无济于事
+            if let Some(summary) = self.summary.as_mut() {
+                if summary.resolve(pn) {
+                    summarized = self.summary.take().map(|summary| summary.frames);
+                }
+            }
+        }
+        let mut range = 0..0;
+        if let Some(pkt) = self.records.get_mut(pn) {
+            let offset = (pkt.first_frame - self.queue_base) as usize;
+            range = offset..offset + pkt.state.maybe_loss();
         }
-        self.queue
-            .range_mut(offset..offset + len)
-            .map(|f| f.clone())
+        summarized
+            .into_iter()
+            .flatten()
+            .chain(self.queue.range(range).cloned())
     }
 }
 
@@ -104,19 +243,77 @@ impl<T> RawSentPktRecords<T> {
     fn with_capacity(capacity: usize) -> Self {
         Self {
             queue: VecDeque::with_capacity(capacity * 4),
+            queue_base: 0,
             records: IndexDeque::with_capacity(capacity),
             largest_acked_pktno: 0,
+            max_records: DEFAULT_MAX_SENT_RECORDS,
+            merge: never_merge,
+            summary: None,
         }
     }
 
     fn auto_drain(&mut self) {
+        // 只能从头部连续地移除已确认或已丢失的包，否则包号与帧就对不上了
         let (n, f) = self
             .records
             .iter()
-            .filter(|s| !matches!(s, SentPktState::Flighting(_)))
-            .fold((0usize, 0usize), |(n, f), s| (n + 1, f + s.nframes()));
+            .take_while(|pkt| !matches!(pkt.state, SentPktState::Flighting(_)))
+            .fold((0usize, 0usize), |(n, f), pkt| {
+                (n + 1, f + pkt.state.nframes())
+            });
         self.records.advance(n);
         let _ = self.queue.drain(..f);
+        self.queue_base += f as u64;
+    }
+
+    /// Summarize the oldest packets beyond the cap.
+    fn summarize(&mut self) {
+        let merge = self.merge;
+        while self.records.len() > self.max_records {
+            let (pn, pkt) = self.records.pop_front().unwrap();
+            let summary = self.summary.get_or_insert_with(|| Summary::new(pn));
+            let nframes = pkt.state.nframes();
+            self.queue_base += nframes as u64;
+            let frames = self.queue.drain(..nframes);
+            if !matches!(pkt.state, SentPktState::Flighting(_)) {
+                // 已确认或已丢失的包，其帧已经交付过了
+                summary.push(pn, true);
+                continue;
+            }
+            summary.push(pn, false);
+            for frame in frames {
+                // 只与最近的几个帧尝试合并，以免概括的帧多了之后，合并越来越慢
+                let merged = summary
+                    .frames
+                    .iter_mut()
+                    .rev()
+                    .take(16)
+                    .any(|merged| merge(merged, &frame));
+                if !merged {
+                    summary.frames.push(frame);
+                }
+            }
+        }
+    }
+
+    fn stats(&self) -> SentRecordsStats {
+        let (summarized, summary_frames, summary_words) =
+            self.summary.as_ref().map_or((0, 0, 0), |summary| {
+                (
+                    summary.unresolved,
+                    summary.frames.len(),
+                    summary.resolved.len(),
+                )
+            });
+        let frames = self.queue.len() + summary_frames;
+        SentRecordsStats {
+            records: self.records.len(),
+            summarized,
+            frames,
+            memory: self.records.len() * mem::size_of::<SentPkt>()
+                + frames * mem::size_of::<T>()
+                + summary_words * mem::size_of::<u64>(),
+        }
     }
 }
 
@@ -129,6 +326,15 @@ impl<T> Clone for ArcSentPktRecords<T> {
     }
 }
 
+impl<T: MergeFrame> ArcSentPktRecords<T> {
+    /// The frames of the summarized packets are merged, see [`MergeFrame`].
+    pub fn with_merging(capacity: usize) -> Self {
+        let mut records = RawSentPktRecords::with_capacity(capacity);
+        records.merge = T::merge;
+        Self(Arc::new(Mutex::new(records)))
+    }
+}
+
 impl<T> ArcSentPktRecords<T> {
     pub fn with_capacity(capacity: usize) -> Self {
         Self(Arc::new(Mutex::new(RawSentPktRecords::with_capacity(
@@ -136,6 +342,33 @@ impl<T> ArcSentPktRecords<T> {
         ))))
     }
 
+    /// Set how many packets are recorded one by one at most, [`DEFAULT_MAX_SENT_RECORDS`]
+    /// by default, so that the memory is bounded on the paths with a huge congestion
+    /// window, or when the peer stops acknowledging.
+    ///
+    /// Beyond the cap, the oldest packets in flight are summarized: their frames are moved
+    /// into a summary, merged if possible, such as the adjacent ranges of a stream, and
+    /// each packet only takes a bit to tell whether it's acknowledged or lost. The
+    /// acknowledgments and losses get coarser for them:
+    /// - the frames are acknowledged only when all the summarized packets are;
+    /// - once any of them is lost, all the frames are lost, some retransmitted spuriously.
+    pub fn set_max_records(&self, max_records: usize) {
+        let mut inner = self.0.lock().unwrap();
+        inner.max_records = max_records.max(1);
+        inner.summarize();
+    }
+
+    /// The memory taken by the records, see [`SentRecordsStats`].
+    pub fn stats(&self) -> SentRecordsStats {
+        self.0.lock().unwrap().stats()
+    }
+
+    /// Whether the packet numbered `pn` has been sent in this space, no matter whether it's
+    /// acknowledged or lost since, or which key generation protected it.
+    pub fn has_sent(&self, pn: u64) -> bool {
+        pn < self.0.lock().unwrap().records.largest()
+    }
+
     pub fn receive(&self) -> RecvGuard<'_, T> {
         RecvGuard {
             inner: self.0.lock().unwrap(),
@@ -213,10 +446,111 @@ impl<T> Drop for SendGuard<'_, T> {
     fn drop(&mut self) {
         let nframes = self.inner.queue.len() - self.origin_len;
         if self.necessary || nframes > 0 {
+            let first_frame = self.inner.queue_base + self.origin_len as u64;
             self.inner
                 .records
-                .push_back(SentPktState::Flighting(nframes as u16))
+                .push_back(SentPkt {
+                    state: SentPktState::Flighting(nframes as u16),
+                    first_frame,
+                })
                 .expect("packet number never overflow");
+            self.inner.summarize();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use qbase::streamid::StreamId;
+
+    use super::*;
+
+    fn send(records: &ArcSentPktRecords<u32>, frames: &[u32]) {
+        let mut guard = records.send();
+        for &frame in frames {
+            guard.record_frame(frame);
+        }
+    }
+
+    #[test]
+    fn test_loss_reported_once() {
+        let records = ArcSentPktRecords::with_capacity(4);
+        send(&records, &[1, 2]);
+        send(&records, &[3]);
+        send(&records, &[4, 5]);
+
+        let lost = records.receive().may_loss_pkt(1).collect::<Vec<_>>();
+        assert_eq!(lost, vec![3]);
+        // 同一个包不会重复报告丢失，其后被确认也不会
+        assert_eq!(records.receive().may_loss_pkt(1).count(), 0);
+        let acked = records.receive().on_pkt_acked(1).collect::<Vec<_>>();
+        assert_eq!(acked, vec![3]);
+        assert_eq!(records.receive().may_loss_pkt(1).count(), 0);
+
+        // 中间的包被移除后，两侧的包仍对应着各自的帧
+        let acked = records.receive().on_pkt_acked(0).collect::<Vec<_>>();
+        assert_eq!(acked, vec![1, 2]);
+        let lost = records.receive().may_loss_pkt(2).collect::<Vec<_>>();
+        assert_eq!(lost, vec![4, 5]);
+    }
+
+    fn send_stream(records: &ArcSentPktRecords<StreamFrame>, sid: StreamId, npkts: u64) {
+        for pn in 0..npkts {
+            let mut guard = records.send();
+            guard.record_frame(StreamFrame::new(sid, pn * 1000, 1000));
+        }
+    }
+
+    #[test]
+    fn test_bounded_records() {
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let records = ArcSentPktRecords::with_merging(16);
+        records.set_max_records(1024);
+        send_stream(&records, sid, 100_000);
+
+        // 10万个未确认的包，逐个记录的不超过上限，其余的帧合并成了一个区间
+        let stats = records.stats();
+        assert_eq!(stats.records, 1024);
+        assert_eq!(stats.summarized, 100_000 - 1024);
+        assert_eq!(stats.frames, 1024 + 1);
+        assert!(stats.memory < 128 * 1024, "{stats:?}");
+
+        // 概括的包全部确认后，合并的区间才被确认
+        let mut guard = records.receive();
+        for pn in 0..100_000 - 1024 - 1 {
+            assert_eq!(guard.on_pkt_acked(pn).count(), 0);
+        }
+        let acked = guard.on_pkt_acked(100_000 - 1024 - 1).collect::<Vec<_>>();
+        assert_eq!(acked.len(), 1);
+        assert_eq!(acked[0].range(), 0..(100_000 - 1024) * 1000);
+        let acked = guard.on_pkt_acked(100_000 - 1024).collect::<Vec<_>>();
+        assert_eq!(
+            acked[0].range(),
+            (100_000 - 1024) * 1000..(100_000 - 1023) * 1000
+        );
+        drop(guard);
+        let stats = records.stats();
+        assert_eq!((stats.records, stats.summarized), (1023, 0));
+    }
+
+    #[test]
+    fn test_summarized_loss() {
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let records = ArcSentPktRecords::with_merging(16);
+        records.set_max_records(4);
+        send_stream(&records, sid, 10);
+
+        // 概括的包中一个丢失，合并的区间整个判定丢失，之后的确认不再报告
+        let acked = records.receive().on_pkt_acked(0).count();
+        assert_eq!(acked, 0);
+        let lost = records.receive().may_loss_pkt(3).collect::<Vec<_>>();
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].range(), 0..6000);
+        assert_eq!(records.receive().on_pkt_acked(5).count(), 0);
+        assert_eq!(records.stats().summarized, 0);
+
+        // 逐个记录的包不受影响
+        let lost = records.receive().may_loss_pkt(6).collect::<Vec<_>>();
+        assert_eq!(lost, vec![StreamFrame::new(sid, 6000, 1000)]);
+    }
+}
@@ -1,13 +1,37 @@
+use qbase::util::ArcTraceContext;
+
 pub mod sndbuf;
 
 mod outgoing;
 mod sender;
 mod writer;
 
+#[cfg(test)]
+mod model;
+
 pub use outgoing::{IsCancelled, Outgoing};
-pub use sender::ArcSender;
-pub use writer::Writer;
+pub use sender::{
+    ArcSender, Priority, SendIntrospection, SendState, DEFAULT_URGENCY, DEFAULT_WEIGHT,
+    MAX_URGENCY, MAX_WEIGHT,
+};
+pub use writer::{SharedWriter, StreamStopped, Writer};
+
+/// How much of the submitted data must have been handled before a flush completes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// Every byte has been sent at least once, the data waiting for retransmission
+    /// does not block the flush.
+    #[default]
+    SentOnce,
+    /// Every byte has been acknowledged by the peer, like [`Writer`]'s flush but for
+    /// the whole connection.
+    FullyAcked,
+}
 
 pub fn new(wnd_size: u64) -> ArcSender {
     ArcSender::with_wnd_size(wnd_size)
 }
+
+pub fn with_trace(wnd_size: u64, trace: ArcTraceContext) -> ArcSender {
+    ArcSender::with_trace(wnd_size, trace)
+}
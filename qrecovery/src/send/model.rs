@@ -0,0 +1,720 @@
+//! 发送端状态机的基于模型的测试。
+//!
+//! [`SenderModel`]是RFC9000 3.1节发送端状态机的简化参考模型，按字节记录数据的状态，
+//! 由纯函数[`SenderModel::step`]推进。proptest随机生成合法的事件交错序列，同时施加于
+//! 模型和真实的[`ArcSender`]（经由[`Writer`]和[`Outgoing`]），逐个事件比较可观察的结果：
+//! 状态类别、发出的帧、应用读写的成败。出现分歧时，proptest将其缩减为最小的失败用例。
+//!
+//! 观察等价的定义：
+//! - Ready与Sending对应用和对端都没有区别，合为[`Category::Open`]；
+//! - 发出的STREAM帧不要求与模型逐字节一致，只要求合法：从最靠前的待发送数据开始，
+//!   不为空、不超过令牌和流量额度、不跨越不同状态的数据，且恰在流末尾时携带FIN；
+//! - RESET_STREAM帧要求final size一致；
+//! - 应用的读写只比较是否就绪、成功与否，不比较错误的种类。
+
+use std::{
+    ops::Range,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{task::noop_waker_ref, Future};
+use proptest::{collection::vec, prelude::*};
+use qbase::{
+    error::{Error as QuicError, ErrorKind},
+    streamid::StreamId,
+    varint::VarInt,
+};
+use tokio::io::AsyncWrite;
+
+use super::{
+    sender::{ArcSender, Sender},
+    Outgoing, Writer,
+};
+
+/// 足够容纳任何令牌数的帧，使得帧的长度只受令牌和流量额度限制
+const BUF_SIZE: usize = 4096;
+
+/// 可观察的发送端状态类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Open,
+    DataSent,
+    DataRcvd,
+    ResetSent,
+    ResetRcvd,
+    /// 连接出错，流随之失效
+    Broken,
+}
+
+/// 施加于发送端的事件，Ack/Lose以发出的STREAM帧的序号（对帧数取模）来指代帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    /// 应用写入若干字节
+    Write(usize),
+    /// 对端通告了新的MAX_STREAM_DATA
+    UpdateWindow(u64),
+    /// 应用结束写入
+    Shutdown,
+    /// 应用取消流，此后应用不再持有Writer
+    Cancel(u64),
+    /// 传输层取数据发送
+    PickUp {
+        tokens: usize,
+        flow_limit: usize,
+    },
+    Ack(usize),
+    Lose(usize),
+    /// 收到对端的STOP_SENDING
+    StopSending,
+    /// 传输层检查应用是否取消了流
+    PollCancel,
+    /// RESET_STREAM被确认
+    AckReset,
+    ConnError,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Frame {
+    Stream { range: Range<u64>, fin: bool },
+    Reset { final_size: u64 },
+}
+
+/// 事件的可观察结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    /// 事件在当前情形下不会发生，如应用已经取消了流就不会再写，未发出的帧无从确认
+    Skipped,
+    Write(Poll<Result<usize, ()>>),
+    Shutdown(Poll<Result<(), ()>>),
+    Frame(Option<Frame>),
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Pending,
+    Flighting,
+    Lost,
+    Acked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fin {
+    NotSent,
+    Sent,
+    Rcvd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameStatus {
+    Flighting,
+    Lost,
+    Acked,
+}
+
+/// 发送端的参考模型
+#[derive(Debug, Clone)]
+struct SenderModel {
+    category: Category,
+    window: u64,
+    bytes: Vec<Byte>,
+    fin: Fin,
+    cancelled: bool,
+    has_writer: bool,
+    has_reset: bool,
+    frames: Vec<(Range<u64>, bool, FrameStatus)>,
+}
+
+impl SenderModel {
+    fn new(window: u64) -> Self {
+        Self {
+            category: Category::Open,
+            window,
+            bytes: Vec::new(),
+            fin: Fin::NotSent,
+            cancelled: false,
+            has_writer: true,
+            has_reset: false,
+            frames: Vec::new(),
+        }
+    }
+
+    fn written(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// 发送过的数据的末尾，数据按序发出，从未发送的数据都在其后
+    fn sent(&self) -> u64 {
+        self.bytes
+            .iter()
+            .position(|b| *b == Byte::Pending)
+            .unwrap_or(self.bytes.len()) as u64
+    }
+
+    fn is_sending(&self) -> bool {
+        matches!(self.category, Category::Open | Category::DataSent)
+    }
+
+    /// 事件是否会在当前情形下发生，以及它指代的帧
+    fn frame_of(&self, idx: usize) -> Option<usize> {
+        (!self.frames.is_empty()).then(|| idx % self.frames.len())
+    }
+
+    fn is_applicable(&self, event: &Event) -> bool {
+        match *event {
+            Event::Write(_) | Event::Shutdown | Event::Cancel(_) => self.has_writer,
+            Event::Ack(idx) => self
+                .frame_of(idx)
+                .is_some_and(|i| self.frames[i].2 != FrameStatus::Acked),
+            Event::Lose(idx) => self
+                .frame_of(idx)
+                .is_some_and(|i| self.frames[i].2 == FrameStatus::Flighting),
+            Event::AckReset => self.has_reset,
+            _ => true,
+        }
+    }
+
+    /// 第一段可发送的数据：最靠前的Lost或者Pending数据，Pending数据还要有流量额度
+    fn first_sendable(&self, flow_limit: usize) -> Option<(u64, Byte)> {
+        self.bytes
+            .iter()
+            .position(|b| *b == Byte::Lost || (*b == Byte::Pending && flow_limit > 0))
+            .map(|pos| (pos as u64, self.bytes[pos]))
+    }
+
+    /// 检查真实发出的STREAM帧在模型看来是否合法
+    fn check_pick_up(
+        &self,
+        tokens: usize,
+        flow_limit: usize,
+        real: &Option<Frame>,
+    ) -> Result<(), String> {
+        if !self.is_sending() || self.cancelled {
+            return match real {
+                None => Ok(()),
+                Some(frame) => Err(format!("{frame:?} sent in {:?}", self.category)),
+            };
+        }
+        // 数据都发过了，FIN却没有发过或者丢了，要单独发送FIN
+        let fin_pending = self.category == Category::DataSent
+            && self.fin == Fin::NotSent
+            && !self.bytes.contains(&Byte::Pending);
+        match (self.first_sendable(flow_limit), real) {
+            (Some((start, byte)), Some(Frame::Stream { range, fin })) => {
+                if range.start != start || range.is_empty() {
+                    return Err(format!("expect data from {start}, got {range:?}"));
+                }
+                let mut limit = tokens as u64;
+                if byte == Byte::Pending {
+                    limit = limit.min(flow_limit as u64);
+                }
+                if range.end - range.start > limit {
+                    return Err(format!("{range:?} exceeds the limit {limit}"));
+                }
+                if self.bytes[range.start as usize..range.end as usize]
+                    .iter()
+                    .any(|b| *b != byte)
+                {
+                    return Err(format!("{range:?} spans the data in different states"));
+                }
+                let expect_fin = self.category == Category::DataSent && range.end == self.written();
+                if *fin != expect_fin {
+                    return Err(format!("{range:?} with fin {fin}, expect {expect_fin}"));
+                }
+                Ok(())
+            }
+            (None, Some(Frame::Stream { range, fin: true }))
+                if fin_pending && *range == (self.written()..self.written()) =>
+            {
+                Ok(())
+            }
+            (None, None) if !fin_pending => Ok(()),
+            (expect, real) => Err(format!(
+                "expect sendable data {expect:?}, fin pending {fin_pending}, got {real:?}"
+            )),
+        }
+    }
+
+    /// 推进模型，`observed`是真实发送端的结果；模型对其做出判定，并吸收其中合法的选择，
+    /// 如STREAM帧实际的长度。返回新的模型，或者分歧的描述。
+    fn step(mut self, event: &Event, observed: &Outcome) -> Result<Self, String> {
+        if !self.is_applicable(event) {
+            return match observed {
+                Outcome::Skipped => Ok(self),
+                _ => Err(format!("{event:?} is not applicable, but {observed:?}")),
+            };
+        }
+        let expected = match *event {
+            Event::Write(n) => Outcome::Write(match self.category {
+                Category::Open if self.written() < self.window => {
+                    let n = n.min((self.window - self.written()) as usize);
+                    self.bytes.extend(std::iter::repeat_n(Byte::Pending, n));
+                    Poll::Ready(Ok(n))
+                }
+                Category::Open => Poll::Pending,
+                _ => Poll::Ready(Err(())),
+            }),
+            Event::UpdateWindow(max) => {
+                self.window = self.window.max(max);
+                Outcome::Done
+            }
+            Event::Shutdown => Outcome::Shutdown(match self.category {
+                Category::Open => {
+                    self.category = Category::DataSent;
+                    Poll::Pending
+                }
+                Category::DataSent => Poll::Pending,
+                Category::DataRcvd => Poll::Ready(Ok(())),
+                _ => Poll::Ready(Err(())),
+            }),
+            Event::Cancel(_) => {
+                self.has_writer = false;
+                self.cancelled = self.is_sending();
+                Outcome::Done
+            }
+            Event::PickUp { tokens, flow_limit } => {
+                let Outcome::Frame(real) = observed else {
+                    return Err(format!("{event:?} got {observed:?}"));
+                };
+                self.check_pick_up(tokens, flow_limit, real)?;
+                if let Some(Frame::Stream { range, fin }) = real {
+                    for b in &mut self.bytes[range.start as usize..range.end as usize] {
+                        *b = Byte::Flighting;
+                    }
+                    if *fin && self.fin != Fin::Rcvd {
+                        self.fin = Fin::Sent;
+                    }
+                    self.frames
+                        .push((range.clone(), *fin, FrameStatus::Flighting));
+                }
+                Outcome::Frame(real.clone())
+            }
+            Event::Ack(idx) => {
+                let i = self.frame_of(idx).unwrap();
+                let (range, fin, _) = self.frames[i].clone();
+                self.frames[i].2 = FrameStatus::Acked;
+                if self.is_sending() {
+                    for b in &mut self.bytes[range.start as usize..range.end as usize] {
+                        *b = Byte::Acked;
+                    }
+                    if fin {
+                        self.fin = Fin::Rcvd;
+                    }
+                    if self.category == Category::DataSent
+                        && self.fin == Fin::Rcvd
+                        && self.bytes.iter().all(|b| *b == Byte::Acked)
+                    {
+                        self.category = Category::DataRcvd;
+                    }
+                }
+                Outcome::Done
+            }
+            Event::Lose(idx) => {
+                let i = self.frame_of(idx).unwrap();
+                let (range, fin, _) = self.frames[i].clone();
+                self.frames[i].2 = FrameStatus::Lost;
+                if self.is_sending() {
+                    for b in &mut self.bytes[range.start as usize..range.end as usize] {
+                        if *b == Byte::Flighting {
+                            *b = Byte::Lost;
+                        }
+                    }
+                    // 携带FIN的帧丢了，FIN也要重传
+                    if fin && self.fin == Fin::Sent {
+                        self.fin = Fin::NotSent;
+                    }
+                }
+                Outcome::Done
+            }
+            Event::StopSending | Event::PollCancel => {
+                let resets = match event {
+                    Event::StopSending => self.is_sending(),
+                    _ => self.is_sending() && self.cancelled,
+                };
+                Outcome::Frame(resets.then(|| {
+                    self.category = Category::ResetSent;
+                    self.has_reset = true;
+                    // 写入而从未发出的数据不计入最终大小，对方也从未为它们消耗流量额度
+                    Frame::Reset {
+                        final_size: self.sent(),
+                    }
+                }))
+            }
+            Event::AckReset => {
+                if matches!(self.category, Category::ResetSent | Category::ResetRcvd) {
+                    self.category = Category::ResetRcvd;
+                }
+                Outcome::Done
+            }
+            Event::ConnError => {
+                if self.is_sending() {
+                    self.category = Category::Broken;
+                }
+                Outcome::Done
+            }
+        };
+        if *observed != expected {
+            return Err(format!("{event:?} expect {expected:?}, got {observed:?}"));
+        }
+        Ok(self)
+    }
+}
+
+/// 把事件施加于真实的发送端
+struct RealSender {
+    sid: StreamId,
+    sender: ArcSender,
+    writer: Option<Writer>,
+    frames: Vec<(Range<u64>, bool)>,
+}
+
+impl RealSender {
+    fn new(window: u64) -> Self {
+        let sender = ArcSender::with_wnd_size(window);
+        Self {
+            sid: VarInt::from_u32(0).into(),
+            writer: Some(Writer::new(VarInt::from_u32(0).into(), sender.clone())),
+            sender,
+            frames: Vec::new(),
+        }
+    }
+
+    fn outgoing(&self) -> Outgoing {
+        Outgoing(self.sender.clone())
+    }
+
+    fn category(&self) -> Category {
+        match self.sender.sender().as_ref() {
+            Ok(Sender::Ready(_) | Sender::Sending(_)) => Category::Open,
+            Ok(Sender::DataSent(_)) => Category::DataSent,
+            Ok(Sender::DataRcvd) => Category::DataRcvd,
+            Ok(Sender::ResetSent(..)) => Category::ResetSent,
+            Ok(Sender::ResetRcvd(_)) => Category::ResetRcvd,
+            Err(_) => Category::Broken,
+        }
+    }
+
+    fn apply(&mut self, model: &SenderModel, event: &Event) -> Outcome {
+        if !model.is_applicable(event) {
+            return Outcome::Skipped;
+        }
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match *event {
+            Event::Write(n) => {
+                let writer = Pin::new(self.writer.as_mut().unwrap());
+                Outcome::Write(writer.poll_write(&mut cx, &vec![0; n]).map_err(|_| ()))
+            }
+            Event::UpdateWindow(max) => {
+                self.outgoing().update_window(max);
+                Outcome::Done
+            }
+            Event::Shutdown => {
+                let writer = Pin::new(self.writer.as_mut().unwrap());
+                Outcome::Shutdown(writer.poll_shutdown(&mut cx).map_err(|_| ()))
+            }
+            Event::Cancel(code) => {
+                self.writer.take().unwrap().reset(code);
+                Outcome::Done
+            }
+            Event::PickUp { tokens, flow_limit } => {
+                let mut buf = [0; BUF_SIZE];
+                let frame = self
+                    .outgoing()
+                    .try_read(self.sid, &mut buf, tokens, flow_limit)
+                    .map(|(frame, ..)| {
+                        self.frames.push((frame.range(), frame.is_fin()));
+                        Frame::Stream {
+                            range: frame.range(),
+                            fin: frame.is_fin(),
+                        }
+                    });
+                Outcome::Frame(frame)
+            }
+            Event::Ack(idx) => {
+                let (range, fin) = self.frames[idx % self.frames.len()].clone();
+                self.outgoing().on_data_acked(&range, fin);
+                Outcome::Done
+            }
+            Event::Lose(idx) => {
+                let (range, fin) = self.frames[idx % self.frames.len()].clone();
+                self.outgoing().may_loss_data(&range, fin);
+                Outcome::Done
+            }
+            Event::StopSending => Outcome::Frame(
+                self.outgoing()
+                    .stop(0)
+                    .map(|final_size| Frame::Reset { final_size }),
+            ),
+            Event::PollCancel => {
+                let outgoing = self.outgoing();
+                let mut is_cancelled = outgoing.is_cancelled_by_app();
+                Outcome::Frame(match Pin::new(&mut is_cancelled).poll(&mut cx) {
+                    Poll::Ready(Some((final_size, _))) => Some(Frame::Reset { final_size }),
+                    _ => None,
+                })
+            }
+            Event::AckReset => {
+                self.outgoing().on_reset_acked();
+                Outcome::Done
+            }
+            Event::ConnError => {
+                let error = QuicError::with_default_fty(ErrorKind::Internal, "model");
+                self.outgoing().on_conn_error(&error);
+                Outcome::Done
+            }
+        }
+    }
+}
+
+impl Drop for RealSender {
+    fn drop(&mut self) {
+        // 应用丢弃未完成的流之前，必须取消它；真实的发送端panic了，则已无从取消
+        if let Some(writer) = self.writer.take() {
+            if std::thread::panicking() {
+                std::mem::forget(writer);
+            } else {
+                writer.reset(0);
+            }
+        }
+    }
+}
+
+/// 同时施加事件序列于模型和真实的发送端，返回第一处分歧
+fn run(window: u64, events: &[Event]) -> Result<(), String> {
+    let mut model = SenderModel::new(window);
+    let mut real = RealSender::new(window);
+    for (i, event) in events.iter().enumerate() {
+        let observed = real.apply(&model, event);
+        model = model
+            .step(event, &observed)
+            .map_err(|e| format!("event #{i}: {e}"))?;
+        if real.category() != model.category {
+            return Err(format!(
+                "event #{i} {event:?}: in {:?}, expect {:?}",
+                real.category(),
+                model.category
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn event() -> impl Strategy<Value = Event> {
+    prop_oneof![
+        4 => (1..48usize).prop_map(Event::Write),
+        1 => (0..256u64).prop_map(Event::UpdateWindow),
+        1 => Just(Event::Shutdown),
+        1 => (0..8u64).prop_map(Event::Cancel),
+        6 => (1..32usize, prop_oneof![Just(0), 1..32usize, Just(usize::MAX)])
+            .prop_map(|(tokens, flow_limit)| Event::PickUp { tokens, flow_limit }),
+        4 => any::<usize>().prop_map(Event::Ack),
+        3 => any::<usize>().prop_map(Event::Lose),
+        1 => Just(Event::StopSending),
+        1 => Just(Event::PollCancel),
+        1 => Just(Event::AckReset),
+        1 => Just(Event::ConnError),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1024))]
+
+    #[test]
+    fn sender_conforms_to_model(window in 0..128u64, events in vec(event(), 1..64)) {
+        if let Err(discrepancy) = run(window, &events) {
+            prop_assert!(false, "{}", discrepancy);
+        }
+    }
+}
+
+// 以下是模型曾发现的分歧，固定下来防止回归
+
+#[test]
+fn no_empty_frame_without_flow_credit() {
+    let events = [
+        Event::Write(5),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+    ];
+    run(20, &events).unwrap();
+}
+
+#[test]
+fn stop_sending_before_any_data() {
+    run(20, &[Event::StopSending]).unwrap();
+}
+
+#[test]
+fn fin_deferred_while_data_blocked() {
+    let events = [
+        Event::Write(1),
+        Event::Shutdown,
+        Event::PickUp {
+            tokens: 1,
+            flow_limit: 0,
+        },
+        Event::Ack(0),
+    ];
+    run(20, &events).unwrap();
+}
+
+#[test]
+fn lost_fin_only_frame_retransmitted() {
+    let events = [
+        Event::Shutdown,
+        Event::PickUp {
+            tokens: 1,
+            flow_limit: 0,
+        },
+        Event::Lose(0),
+        Event::PickUp {
+            tokens: 1,
+            flow_limit: 0,
+        },
+    ];
+    run(20, &events).unwrap();
+}
+
+#[test]
+fn lost_data_after_acked_prefix_retransmitted() {
+    let events = [
+        Event::UpdateWindow(11),
+        Event::Write(11),
+        Event::PickUp {
+            tokens: 11,
+            flow_limit: 11,
+        },
+        Event::Lose(0),
+        Event::PickUp {
+            tokens: 1,
+            flow_limit: 0,
+        },
+        Event::Lose(1),
+        Event::PickUp {
+            tokens: 2,
+            flow_limit: 0,
+        },
+        Event::Ack(1),
+        Event::Lose(2),
+        Event::PickUp {
+            tokens: 1,
+            flow_limit: 0,
+        },
+    ];
+    run(0, &events).unwrap();
+}
+
+#[test]
+fn acked_fin_not_downgraded_by_retransmission() {
+    let events = [
+        Event::Write(5),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 64,
+        },
+        Event::Shutdown,
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Lose(0),
+        Event::Ack(1),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Lose(2),
+        Event::Ack(0),
+    ];
+    run(20, &events).unwrap();
+}
+
+#[test]
+fn lost_fin_only_frame_repaired_until_data_rcvd() {
+    let events = [
+        Event::Write(5),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 64,
+        },
+        Event::Shutdown,
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Ack(0),
+        // 仅携带FIN的帧丢了，数据都已确认，只能单独重传FIN
+        Event::Lose(1),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Lose(2),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Ack(3),
+        Event::Shutdown,
+    ];
+    run(20, &events).unwrap();
+}
+
+#[test]
+fn fin_acked_apart_from_last_data() {
+    for (first, second) in [(0, 1), (1, 0)] {
+        let events = [
+            Event::Write(5),
+            Event::PickUp {
+                tokens: 64,
+                flow_limit: 64,
+            },
+            Event::Shutdown,
+            Event::PickUp {
+                tokens: 64,
+                flow_limit: 0,
+            },
+            // 数据帧恰到流末尾，但不带FIN，先于FIN确认不能算作FIN已确认
+            Event::Ack(first),
+            Event::Shutdown,
+            Event::Ack(second),
+            Event::Shutdown,
+        ];
+        run(20, &events).unwrap();
+    }
+}
+
+#[test]
+fn fin_carried_by_overlapping_retransmissions() {
+    let events = [
+        Event::Write(5),
+        Event::Shutdown,
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 64,
+        },
+        Event::Lose(0),
+        Event::PickUp {
+            tokens: 3,
+            flow_limit: 0,
+        },
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        Event::Lose(2),
+        Event::Ack(1),
+        Event::PickUp {
+            tokens: 64,
+            flow_limit: 0,
+        },
+        // 判定丢失的原帧迟到的确认，连同FIN确认了全部数据
+        Event::Ack(0),
+        Event::Lose(3),
+        Event::Shutdown,
+    ];
+    run(20, &events).unwrap();
+}
@@ -12,11 +12,14 @@ use qbase::{
     error::Error as QuicError,
     frame::{io::WriteDataFrame, ShouldCarryLength, StreamFrame},
     streamid::StreamId,
-    util::DescribeData,
+    util::{Chunks, DescribeData},
     varint::VARINT_MAX,
 };
 
-use super::sender::{ArcSender, DataSentSender, Sender, SendingSender};
+use super::{
+    sender::{ArcSender, DataSentSender, Sender, SendingSender},
+    FlushMode,
+};
 
 #[derive(Debug, Clone)]
 pub struct Outgoing(pub(crate) ArcSender);
@@ -40,7 +43,7 @@ impl Outgoing {
         flow_limit: usize,
     ) -> Option<(StreamFrame, usize, bool, usize)> {
         let capacity = buf.len();
-        let write = |(offset, is_fresh, data, is_eos): (u64, bool, (&[u8], &[u8]), bool)| {
+        let write = |(offset, is_fresh, data, is_eos): (u64, bool, Chunks, bool)| {
             let mut frame = StreamFrame::new(sid, offset, data.len());
 
             frame.set_eos_flag(is_eos);
@@ -48,8 +51,11 @@ impl Outgoing {
                 ShouldCarryLength::NoProblem => {
                     buf.put_data_frame(&frame, &data);
                 }
+                // 剩余的字节不够编码长度，在帧前填充PADDING，帧仍占满整个包。缓冲区未必
+                // 是零，须显式写入PADDING，且要推进buf，否则返回的写入字节数为0
                 ShouldCarryLength::PaddingFirst(n) => {
-                    (&mut buf[n..]).put_data_frame(&frame, &data);
+                    buf.put_bytes(0, n);
+                    buf.put_data_frame(&frame, &data);
                 }
                 ShouldCarryLength::ShouldAfter(_not_carry_len, _carry_len) => {
                     frame.carry_length();
@@ -65,7 +71,7 @@ impl Outgoing {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
 
-        match inner {
+        let result = match inner {
             Ok(sending_state) => match sending_state {
                 Sender::Ready(s) => {
                     let result;
@@ -85,7 +91,12 @@ impl Outgoing {
                 _ => None,
             },
             Err(_) => None,
+        };
+        // 窗口内的数据都发出了，写任务却还在等待窗口，流就受限于对方的MAX_STREAM_DATA
+        if let Ok(Sender::Sending(s)) = inner {
+            s.check_blocked();
         }
+        result
     }
 
     /// return true if all data has been rcvd
@@ -114,7 +125,19 @@ impl Outgoing {
         false
     }
 
-    pub fn may_loss_data(&self, range: &Range<u64>) {
+    /// Return true if the stream has nothing left to send, or to be acknowledged for
+    /// [`FlushMode::FullyAcked`]. A reset stream abandons its data, which counts as flushed.
+    pub fn is_flushed(&self, mode: FlushMode) -> bool {
+        let sender = self.0.sender();
+        match sender.as_ref() {
+            Ok(Sender::Ready(s)) => s.is_flushed(),
+            Ok(Sender::Sending(s)) => s.is_flushed(mode),
+            Ok(Sender::DataSent(s)) => s.is_flushed(mode),
+            _ => true,
+        }
+    }
+
+    pub fn may_loss_data(&self, range: &Range<u64>, is_fin: bool) {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
         if let Ok(sending_state) = inner {
@@ -126,7 +149,7 @@ impl Outgoing {
                     s.may_loss_data(range);
                 }
                 Sender::DataSent(s) => {
-                    s.may_loss_data(range);
+                    s.may_loss_data(range, is_fin);
                 }
                 // ignore loss
                 _ => (),
@@ -134,26 +157,56 @@ impl Outgoing {
         };
     }
 
-    /// 被动stop，返回true说明成功stop了；返回false则表明流没有必要stop，要么已经完成，要么已经reset
-    pub fn stop(&self) -> bool {
+    /// The urgency the stream competes with in the scheduler, see [`ArcSender::urgency`].
+    ///
+    /// The lost data rejoins the sendable data of its own stream, so the repairs of a stream
+    /// don't jump ahead of the more urgent streams. To keep the repairs from starving, a stream
+    /// with lost data is lifted by at most `retransmission_boost` levels.
+    pub fn urgency(&self, retransmission_boost: u8) -> u8 {
+        let urgency = self.0.urgency();
+        let has_lost = match self.0.sender().as_ref() {
+            Ok(Sender::Sending(s)) => s.has_lost(),
+            Ok(Sender::DataSent(s)) => s.has_lost(),
+            _ => false,
+        };
+        if has_lost {
+            urgency.saturating_sub(retransmission_boost)
+        } else {
+            urgency
+        }
+    }
+
+    /// The weight the stream takes turns with among the streams of the same urgency, see
+    /// [`ArcSender::weight`].
+    pub fn weight(&self) -> u16 {
+        self.0.weight()
+    }
+
+    /// 被动stop，返回RESET_STREAM帧要携带的final size；返回None则表明流没有必要stop，要么已经完成，要么已经reset
+    /// 写端此后的操作失败，错误中带有对方STOP_SENDING帧的错误码
+    pub fn stop(&self, error_code: u64) -> Option<u64> {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
         match inner {
             Ok(sending_state) => match sending_state {
-                Sender::Ready(_) => {
-                    unreachable!("never send data before recv data");
+                Sender::Ready(s) => {
+                    let final_size = s.stop();
+                    *sending_state = Sender::ResetSent(final_size, Some(error_code));
+                    Some(final_size)
                 }
                 Sender::Sending(s) => {
-                    *sending_state = Sender::ResetSent(s.stop());
-                    true
+                    let final_size = s.stop();
+                    *sending_state = Sender::ResetSent(final_size, Some(error_code));
+                    Some(final_size)
                 }
                 Sender::DataSent(s) => {
-                    *sending_state = Sender::ResetSent(s.stop());
-                    true
+                    let final_size = s.stop();
+                    *sending_state = Sender::ResetSent(final_size, Some(error_code));
+                    Some(final_size)
                 }
-                _ => false,
+                _ => None,
             },
-            Err(_) => false,
+            Err(_) => None,
         }
     }
 
@@ -162,9 +215,10 @@ impl Outgoing {
         let inner = sender.deref_mut();
         if let Ok(sending_state) = inner {
             match sending_state {
-                Sender::ResetSent(_) | Sender::ResetRcvd => {
-                    *sending_state = Sender::ResetRcvd;
+                Sender::ResetSent(_, stop_code) => {
+                    *sending_state = Sender::ResetRcvd(*stop_code);
                 }
+                Sender::ResetRcvd(_) => {}
                 _ => {
                     unreachable!(
                     "If no RESET_STREAM has been sent, how can there be a received acknowledgment?"
@@ -188,12 +242,37 @@ impl Outgoing {
             },
             Err(_) => return,
         };
-        *inner = Err(Error::new(std::io::ErrorKind::BrokenPipe, err.to_string()));
+        *inner = Err(Error::from(err.clone()));
     }
 
     pub fn is_cancelled_by_app(&self) -> IsCancelled {
         IsCancelled(&self.0)
     }
+
+    /// Resolves with the window once the stream is blocked on the peer's MAX_STREAM_DATA,
+    /// i.e. the application waits to write more while the data in the window are all sent,
+    /// a STREAM_DATA_BLOCKED frame should be sent then. Each window resolves only once.
+    ///
+    /// None if the stream can't be blocked anymore, when all the data are written, or the
+    /// stream is reset.
+    pub fn is_blocked(&self) -> IsBlocked {
+        IsBlocked(&self.0)
+    }
+}
+
+pub struct IsBlocked<'s>(&'s ArcSender);
+
+impl Future for IsBlocked<'_> {
+    type Output = Option<u64>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut sender = self.0.sender();
+        match sender.deref_mut() {
+            Ok(Sender::Ready(s)) if !s.is_cancelled() => s.poll_blocked(cx).map(Some),
+            Ok(Sender::Sending(s)) if !s.is_cancelled() => s.poll_blocked(cx).map(Some),
+            _ => Poll::Ready(None),
+        }
+    }
 }
 
 pub struct IsCancelled<'s>(&'s ArcSender);
@@ -209,17 +288,17 @@ impl Future for IsCancelled<'_> {
             Ok(sending_state) => match sending_state {
                 Sender::Ready(s) => {
                     let (final_size, err_code) = ready!(s.poll_cancel(cx));
-                    *sending_state = Sender::ResetSent(final_size);
+                    *sending_state = Sender::ResetSent(final_size, None);
                     Poll::Ready(Some((final_size, err_code)))
                 }
                 Sender::Sending(s) => {
                     let (final_size, err_code) = ready!(s.poll_cancel(cx));
-                    *sending_state = Sender::ResetSent(final_size);
+                    *sending_state = Sender::ResetSent(final_size, None);
                     Poll::Ready(Some((final_size, err_code)))
                 }
                 Sender::DataSent(s) => {
                     let (final_size, err_code) = ready!(s.poll_cancel(cx));
-                    *sending_state = Sender::ResetSent(final_size);
+                    *sending_state = Sender::ResetSent(final_size, None);
                     Poll::Ready(Some((final_size, err_code)))
                 }
                 _ => Poll::Ready(None),
@@ -229,3 +308,149 @@ impl Future for IsCancelled<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::task::noop_waker_ref;
+    use proptest::prelude::*;
+    use qbase::{
+        frame::{Frame, FrameReader},
+        packet::r#type::{short::OneRtt, Type},
+        varint::VarInt,
+    };
+    use tokio::io::AsyncWrite;
+
+    use super::*;
+    use crate::send::Writer;
+
+    fn pattern(offset: u64) -> u8 {
+        (offset % 251) as u8
+    }
+
+    /// 流上先发出`offset`字节，此时还有`available`字节待发，再从容量为`capacity`的缓冲区中
+    /// 取出一个STREAM帧，不受令牌和流量额度限制。缓冲区预先填满非零字节，未写入的部分不会
+    /// 侥幸被当作PADDING帧。返回缓冲区、帧和写入的字节数
+    fn pack(
+        sid: StreamId,
+        capacity: usize,
+        offset: u64,
+        available: usize,
+        fin: bool,
+    ) -> Option<(Vec<u8>, StreamFrame, usize)> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let total = offset + available as u64;
+        let sender = ArcSender::with_wnd_size(total);
+        let mut writer = Writer::new(sid, sender.clone());
+        let outgoing = Outgoing(sender);
+
+        let data = (0..total).map(pattern).collect::<Vec<_>>();
+        if !data.is_empty() {
+            assert!(matches!(
+                Pin::new(&mut writer).poll_write(&mut cx, &data),
+                Poll::Ready(Ok(n)) if n == data.len()
+            ));
+        }
+        let mut sent = 0;
+        while sent < offset {
+            let mut buf = vec![0; offset as usize + 32];
+            let tokens = (offset - sent) as usize;
+            let (frame, ..) = outgoing.try_read(sid, &mut buf, tokens, 1 << 30).unwrap();
+            sent += frame.len() as u64;
+        }
+        if fin {
+            let _ = Pin::new(&mut writer).poll_shutdown(&mut cx);
+        }
+
+        let mut buf = vec![0xff; capacity];
+        let packed = outgoing.try_read(sid, &mut buf, usize::MAX, 1 << 30);
+        writer.reset(0);
+        packed.map(|(frame, len, is_fresh, written)| {
+            assert_eq!(len, frame.len());
+            assert!(is_fresh);
+            (buf, frame, written)
+        })
+    }
+
+    /// 穷举一个STREAM帧所有合法的编码，即数据长度及是否携带长度，返回最多能带的数据量，
+    /// 以及带这么多数据时，最多能留给其后的帧的字节数。不带长度的帧只能是包中最后一个帧
+    fn optimal(
+        sid: StreamId,
+        capacity: usize,
+        offset: u64,
+        available: usize,
+    ) -> Option<(usize, usize)> {
+        let mut best = None;
+        for len in 1..=available.min(capacity) {
+            for carry_length in [false, true] {
+                let mut frame = StreamFrame::new(sid, offset, len);
+                if carry_length {
+                    frame.carry_length();
+                }
+                let size = frame.encoding_size();
+                if size <= capacity {
+                    let left = if carry_length { capacity - size } else { 0 };
+                    best = best.max(Some((len, left)));
+                }
+            }
+        }
+        best
+    }
+
+    // 集中在变长整数编码长度变化的64和16384附近
+    fn around_boundaries(max: u64) -> impl Strategy<Value = u64> {
+        prop_oneof![0..200u64, 16_300..16_450u64, 0..max]
+    }
+
+    proptest! {
+        #[test]
+        fn test_packing_efficiency(
+            capacity in around_boundaries(20_000),
+            sid in prop_oneof![0..64u64, 64..16_384u64, 16_384..1u64 << 30],
+            offset in around_boundaries(20_000),
+            available in around_boundaries(20_000),
+            fin in any::<bool>(),
+        ) {
+            let capacity = capacity as usize;
+            let available = available as usize;
+            let sid = StreamId::from(VarInt::from_u64(sid).unwrap());
+            // 没有数据可发时，FIN帧不在考察之列
+            let fin = fin && available > 0;
+            let packed = pack(sid, capacity, offset, available, fin);
+            let optimal = optimal(sid, capacity, offset, available);
+
+            let Some((buf, frame, written)) = packed else {
+                prop_assert_eq!(optimal, None);
+                return Ok(());
+            };
+            let Some((optimal_len, optimal_left)) = optimal else {
+                return Err(TestCaseError::fail("packed a frame that doesn't fit"));
+            };
+            // 不超出容量
+            prop_assert!(written <= capacity);
+
+            // 解码得到同样的帧和数据，其前只有PADDING
+            let payload = Bytes::copy_from_slice(&buf[..written]);
+            let mut frames = FrameReader::new(payload, Type::Short(OneRtt(Default::default())))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let Some((Frame::Stream(decoded, data), true)) = frames.pop() else {
+                return Err(TestCaseError::fail("the last frame is not a stream frame"));
+            };
+            prop_assert!(frames.iter().all(|(f, _)| matches!(f, Frame::Padding(_))));
+            prop_assert_eq!(decoded.id, sid);
+            prop_assert_eq!(decoded.range(), frame.range());
+            prop_assert_eq!(decoded.is_fin(), fin && frame.len() == available);
+            prop_assert!(data.iter().zip(frame.range()).all(|(b, o)| *b == pattern(o)));
+
+            // 流还有数据时，包被数据占满，没有PADDING
+            if frame.len() < available {
+                prop_assert_eq!(written, capacity);
+                prop_assert!(frames.is_empty());
+            }
+            // 与最优的编码相比，带的数据一样多，留给其后的帧的空间至多少1个字节
+            prop_assert_eq!(frame.len(), optimal_len);
+            prop_assert!(capacity - written + 1 >= optimal_left);
+        }
+    }
+}
@@ -1,13 +1,80 @@
 use std::{
     io,
     ops::Range,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU16, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use qbase::util::DescribeData;
+use bytes::Bytes;
+use qbase::util::{ArcTraceContext, Chunks, DescribeData, Wakers};
 
-use super::sndbuf::SendBuf;
+use super::{sndbuf::SendBuf, FlushMode};
+
+/// 写任务在等待发送窗口，且窗口内的数据都已发出，流就受限于对方的MAX_STREAM_DATA，
+/// 要告知对方STREAM_DATA_BLOCKED。每个窗口只告知一次
+#[derive(Debug, Default)]
+struct BlockedSignal {
+    // 已告知过的窗口
+    reported: Option<u64>,
+    waker: Option<Waker>,
+    // 本次受限于对方窗口的起始时间，以及此前受限的总时长，供诊断流控死锁
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl BlockedSignal {
+    fn is_blocked(&self, writers_waiting: bool, sndbuf: &SendBuf, max_data_size: u64) -> bool {
+        writers_waiting && !sndbuf.has_unsent() && self.reported != Some(max_data_size)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        writers_waiting: bool,
+        sndbuf: &SendBuf,
+        max_data_size: u64,
+    ) -> Poll<u64> {
+        if self.is_blocked(writers_waiting, sndbuf, max_data_size) {
+            self.reported = Some(max_data_size);
+            self.since.get_or_insert_with(Instant::now);
+            Poll::Ready(max_data_size)
+        } else {
+            self.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn check(&mut self, writers_waiting: bool, sndbuf: &SendBuf, max_data_size: u64) {
+        if self.is_blocked(writers_waiting, sndbuf, max_data_size) {
+            self.wake();
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// 窗口扩大了，或者流不再写入，都不再受限于对方的窗口
+    fn unblock(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.total += since.elapsed();
+        }
+    }
+
+    fn blocked_for(&self) -> Option<Duration> {
+        self.since.map(|since| since.elapsed())
+    }
+
+    fn blocked_total(&self) -> Duration {
+        self.total + self.blocked_for().unwrap_or_default()
+    }
+}
 
 /// The "Ready" state represents a newly created stream that is able to accept data from the application.
 /// Stream data might be buffered in this state in preparation for sending.
@@ -20,7 +87,9 @@ pub struct ReadySender {
     flush_waker: Option<Waker>,
     shutdown_waker: Option<Waker>,
     cancel_waker: Option<Waker>,
-    writable_waker: Option<Waker>,
+    // 等待发送窗口的写任务，共享的写端可能有多个任务同时等待，窗口更新时要全部唤醒
+    writable_wakers: Wakers,
+    blocked: BlockedSignal,
     max_data_size: u64,
 }
 
@@ -32,7 +101,8 @@ impl ReadySender {
             flush_waker: None,
             shutdown_waker: None,
             cancel_waker: None,
-            writable_waker: None,
+            writable_wakers: Wakers::default(),
+            blocked: BlockedSignal::default(),
             max_data_size: wnd_size,
         }
     }
@@ -61,12 +131,29 @@ impl ReadySender {
     pub(super) fn update_window(&mut self, max_data_size: u64) {
         if max_data_size > self.max_data_size {
             self.max_data_size = max_data_size;
-            if let Some(waker) = self.writable_waker.take() {
-                waker.wake();
-            }
+            self.blocked.unblock();
+            self.writable_wakers.wake_all();
         }
     }
 
+    fn wait_writable(&mut self, waker: &Waker) {
+        self.writable_wakers.register(waker);
+        self.check_blocked();
+    }
+
+    pub(super) fn check_blocked(&mut self) {
+        let writers_waiting = !self.writable_wakers.is_empty();
+        self.blocked
+            .check(writers_waiting, &self.sndbuf, self.max_data_size);
+    }
+
+    /// 传输层使用，流受限于对方的MAX_STREAM_DATA时，返回该窗口，每个窗口只返回一次
+    pub(super) fn poll_blocked(&mut self, cx: &mut Context<'_>) -> Poll<u64> {
+        let writers_waiting = !self.writable_wakers.is_empty();
+        self.blocked
+            .poll(cx, writers_waiting, &self.sndbuf, self.max_data_size)
+    }
+
     pub(super) fn poll_write(
         &mut self,
         cx: &mut Context<'_>,
@@ -83,7 +170,30 @@ impl ReadySender {
                 let n = std::cmp::min((self.max_data_size - range.end) as usize, buf.len());
                 Poll::Ready(Ok(self.sndbuf.write(&buf[..n])))
             } else {
-                self.writable_waker = Some(cx.waker().clone());
+                self.wait_writable(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// 同poll_write，但不拷贝，窗口容得下的前一部分data原样存入sndbuf
+    pub(super) fn poll_write_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+        data: &mut Bytes,
+    ) -> Poll<io::Result<usize>> {
+        if let Some(err_code) = self.cancel_state {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("cancelled by app with error code {err_code}"),
+            )))
+        } else {
+            let range = self.sndbuf.range();
+            if range.end < self.max_data_size {
+                let n = std::cmp::min((self.max_data_size - range.end) as usize, data.len());
+                Poll::Ready(Ok(self.sndbuf.write_bytes(data.split_to(n))))
+            } else {
+                self.wait_writable(cx.waker());
                 Poll::Pending
             }
         }
@@ -113,14 +223,40 @@ impl ReadySender {
         }
     }
 
+    /// 将data整体写入sndbuf，要么全部写入，要么不写，供共享的写端写入完整的记录。
+    /// 若发送窗口容不下全部data，则等待窗口更新，期间不写入任何数据。
+    pub(super) fn poll_write_all(
+        &mut self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<()>> {
+        if let Some(err_code) = self.cancel_state {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("cancelled by app with error code {err_code}"),
+            )))
+        } else if self.sndbuf.range().end + data.len() as u64 <= self.max_data_size {
+            self.sndbuf.write(data);
+            Poll::Ready(Ok(()))
+        } else {
+            self.wait_writable(cx.waker());
+            Poll::Pending
+        }
+    }
+
     pub(super) fn is_shutdown(&self) -> bool {
         self.shutdown_waker.is_some()
     }
 
+    /// Ready状态下写入的数据、以及结束标志，都还未发送过；被取消的流，其数据不会再发送
+    pub(super) fn is_flushed(&self) -> bool {
+        self.cancel_state.is_some() || (self.sndbuf.is_empty() && !self.is_shutdown())
+    }
+
     /// 传输层使用，用于发送RST_STREAM帧后，将Sender置为ResetSent状态
     pub(super) fn poll_cancel(&mut self, cx: &mut Context<'_>) -> Poll<(u64, u64)> {
         if let Some(err_code) = self.cancel_state {
-            Poll::Ready((self.sndbuf.len(), err_code))
+            Poll::Ready((self.sndbuf.sent(), err_code))
         } else {
             self.cancel_waker = Some(cx.waker().clone());
             Poll::Pending
@@ -131,6 +267,9 @@ impl ReadySender {
     pub(super) fn cancel(&mut self, err_code: u64) {
         assert!(self.cancel_state.is_none());
         self.cancel_state = Some(err_code);
+        self.writable_wakers.wake_all();
+        self.blocked.unblock();
+        self.blocked.wake();
         if let Some(waker) = self.cancel_waker.take() {
             waker.wake();
         }
@@ -141,9 +280,9 @@ impl ReadySender {
     }
 
     pub(super) fn wake_all(&mut self) {
-        if let Some(waker) = self.writable_waker.take() {
-            waker.wake();
-        }
+        self.writable_wakers.wake_all();
+        self.blocked.unblock();
+        self.blocked.wake();
         if let Some(waker) = self.flush_waker.take() {
             waker.wake();
         }
@@ -155,6 +294,12 @@ impl ReadySender {
             waker.wake();
         }
     }
+
+    /// 传输层使用，对方在我方发送任何数据之前，就可能发来STOP_SENDING
+    pub(super) fn stop(&mut self) -> u64 {
+        self.wake_all();
+        self.sndbuf.sent()
+    }
 }
 
 /// 状态转换，ReaderSender => SendingSender
@@ -166,7 +311,8 @@ impl From<&mut ReadySender> for SendingSender {
             flush_waker: value.flush_waker.take(),
             shutdown_waker: value.shutdown_waker.take(),
             cancel_waker: value.cancel_waker.take(),
-            writable_waker: value.writable_waker.take(),
+            writable_wakers: std::mem::take(&mut value.writable_wakers),
+            blocked: std::mem::take(&mut value.blocked),
             max_data_size: value.max_data_size,
         }
     }
@@ -175,6 +321,9 @@ impl From<&mut ReadySender> for SendingSender {
 /// 状态转换，ReaderSender => DataSentSender
 impl From<&mut ReadySender> for DataSentSender {
     fn from(value: &mut ReadySender) -> Self {
+        // 其他等待写入的任务，将得知流已结束
+        value.writable_wakers.wake_all();
+        value.blocked.wake();
         DataSentSender {
             sndbuf: std::mem::take(&mut value.sndbuf),
             cancel_state: value.cancel_state.take(),
@@ -193,11 +342,12 @@ pub struct SendingSender {
     flush_waker: Option<Waker>,
     shutdown_waker: Option<Waker>,
     cancel_waker: Option<Waker>,
-    writable_waker: Option<Waker>,
+    writable_wakers: Wakers,
+    blocked: BlockedSignal,
     max_data_size: u64,
 }
 
-type StreamData<'s> = (u64, bool, (&'s [u8], &'s [u8]), bool);
+type StreamData<'s> = (u64, bool, Chunks<'s>, bool);
 
 impl SendingSender {
     pub(super) fn poll_write(
@@ -216,7 +366,30 @@ impl SendingSender {
                 let n = std::cmp::min((self.max_data_size - range.end) as usize, buf.len());
                 Poll::Ready(Ok(self.sndbuf.write(&buf[..n])))
             } else {
-                self.writable_waker = Some(cx.waker().clone());
+                self.wait_writable(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// 同poll_write，但不拷贝，窗口容得下的前一部分data原样存入sndbuf
+    pub(super) fn poll_write_bytes(
+        &mut self,
+        cx: &mut Context<'_>,
+        data: &mut Bytes,
+    ) -> Poll<io::Result<usize>> {
+        if let Some(err_code) = self.cancel_state {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("cancelled by app with error code {err_code}"),
+            )))
+        } else {
+            let range = self.sndbuf.range();
+            if range.end < self.max_data_size {
+                let n = std::cmp::min((self.max_data_size - range.end) as usize, data.len());
+                Poll::Ready(Ok(self.sndbuf.write_bytes(data.split_to(n))))
+            } else {
+                self.wait_writable(cx.waker());
                 Poll::Pending
             }
         }
@@ -226,12 +399,29 @@ impl SendingSender {
     pub(super) fn update_window(&mut self, max_data_size: u64) {
         if max_data_size > self.max_data_size {
             self.max_data_size = max_data_size;
-            if let Some(waker) = self.writable_waker.take() {
-                waker.wake();
-            }
+            self.blocked.unblock();
+            self.writable_wakers.wake_all();
         }
     }
 
+    fn wait_writable(&mut self, waker: &Waker) {
+        self.writable_wakers.register(waker);
+        self.check_blocked();
+    }
+
+    pub(super) fn check_blocked(&mut self) {
+        let writers_waiting = !self.writable_wakers.is_empty();
+        self.blocked
+            .check(writers_waiting, &self.sndbuf, self.max_data_size);
+    }
+
+    /// 传输层使用，流受限于对方的MAX_STREAM_DATA时，返回该窗口，每个窗口只返回一次
+    pub(super) fn poll_blocked(&mut self, cx: &mut Context<'_>) -> Poll<u64> {
+        let writers_waiting = !self.writable_wakers.is_empty();
+        self.blocked
+            .poll(cx, writers_waiting, &self.sndbuf, self.max_data_size)
+    }
+
     pub(super) fn pick_up<P>(&mut self, predicate: P, flow_limit: usize) -> Option<StreamData>
     where
         P: Fn(u64) -> Option<usize>,
@@ -257,6 +447,18 @@ impl SendingSender {
         self.sndbuf.may_loss_data(range)
     }
 
+    pub(super) fn has_lost(&self) -> bool {
+        self.sndbuf.has_lost()
+    }
+
+    pub(super) fn is_flushed(&self, mode: FlushMode) -> bool {
+        self.cancel_state.is_some()
+            || match mode {
+                FlushMode::SentOnce => !self.sndbuf.has_unsent(),
+                FlushMode::FullyAcked => self.sndbuf.is_all_rcvd(),
+            }
+    }
+
     pub(super) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         if let Some(err_code) = self.cancel_state {
             Poll::Ready(Err(io::Error::new(
@@ -283,10 +485,31 @@ impl SendingSender {
         }
     }
 
+    /// 将data整体写入sndbuf，要么全部写入，要么不写，供共享的写端写入完整的记录。
+    /// 若发送窗口容不下全部data，则等待窗口更新，期间不写入任何数据。
+    pub(super) fn poll_write_all(
+        &mut self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<()>> {
+        if let Some(err_code) = self.cancel_state {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("cancelled by app with error code {err_code}"),
+            )))
+        } else if self.sndbuf.range().end + data.len() as u64 <= self.max_data_size {
+            self.sndbuf.write(data);
+            Poll::Ready(Ok(()))
+        } else {
+            self.wait_writable(cx.waker());
+            Poll::Pending
+        }
+    }
+
     /// 传输层使用
     pub(super) fn poll_cancel(&mut self, cx: &mut Context<'_>) -> Poll<(u64, u64)> {
         if let Some(err_code) = self.cancel_state {
-            Poll::Ready((self.sndbuf.len(), err_code))
+            Poll::Ready((self.sndbuf.sent(), err_code))
         } else {
             self.cancel_waker = Some(cx.waker().clone());
             Poll::Pending
@@ -296,6 +519,9 @@ impl SendingSender {
     pub(super) fn cancel(&mut self, err_code: u64) {
         assert!(self.cancel_state.is_none());
         self.cancel_state = Some(err_code);
+        self.writable_wakers.wake_all();
+        self.blocked.unblock();
+        self.blocked.wake();
         if let Some(waker) = self.cancel_waker.take() {
             waker.wake();
         }
@@ -306,9 +532,9 @@ impl SendingSender {
     }
 
     pub(super) fn wake_all(&mut self) {
-        if let Some(waker) = self.writable_waker.take() {
-            waker.wake();
-        }
+        self.writable_wakers.wake_all();
+        self.blocked.unblock();
+        self.blocked.wake();
         if let Some(waker) = self.flush_waker.take() {
             waker.wake();
         }
@@ -325,13 +551,16 @@ impl SendingSender {
     pub(super) fn stop(&mut self) -> u64 {
         self.wake_all();
         // Actually, these remaining data is not acked and will not be acked
-        self.sndbuf.len()
+        self.sndbuf.sent()
     }
 }
 
 /// 状态转换，SendingSender => DataSentSender
 impl From<&mut SendingSender> for DataSentSender {
     fn from(value: &mut SendingSender) -> Self {
+        // 其他等待写入的任务，将得知流已结束
+        value.writable_wakers.wake_all();
+        value.blocked.wake();
         DataSentSender {
             sndbuf: std::mem::take(&mut value.sndbuf),
             cancel_state: value.cancel_state.take(),
@@ -372,20 +601,24 @@ impl DataSentSender {
         }
 
         let final_size = self.sndbuf.len();
+        // 挑不出数据时，sndbuf不变，可以提前判断
+        let has_unsent = self.sndbuf.has_unsent();
         self.sndbuf
             .pick_up(&predicate, flow_limit)
             .map(|(offset, is_fresh, data)| {
                 let is_eos = offset + data.len() as u64 == final_size;
-                if is_eos {
+                // 重传的数据携带FIN，但FIN已被确认的，不能退回Sent
+                if is_eos && self.fin_state != FinState::Rcvd {
                     self.fin_state = FinState::Sent;
                 }
                 (offset, is_fresh, data, is_eos)
             })
             .or_else(|| {
-                if self.fin_state == FinState::None {
+                // 尚有数据因流量控制未发，FIN随最后的数据一起发
+                if self.fin_state == FinState::None && !has_unsent {
                     let _ = predicate(final_size)?;
                     self.fin_state = FinState::Sent;
-                    Some((final_size, false, (&[], &[]), true))
+                    Some((final_size, false, Chunks::default(), true))
                 } else {
                     None
                 }
@@ -394,6 +627,7 @@ impl DataSentSender {
 
     pub(super) fn on_data_acked(&mut self, range: &Range<u64>, is_fin: bool) {
         self.sndbuf.on_data_acked(range);
+        // FIN只看帧的标志位，不由区间推断：数据恰到流末尾但不带FIN的帧，确认了也不算
         if is_fin {
             self.fin_state = FinState::Rcvd;
         }
@@ -411,8 +645,26 @@ impl DataSentSender {
         self.sndbuf.is_all_rcvd() && self.fin_state == FinState::Rcvd
     }
 
-    pub(super) fn may_loss_data(&mut self, range: &Range<u64>) {
-        self.sndbuf.may_loss_data(range)
+    pub(super) fn may_loss_data(&mut self, range: &Range<u64>, is_fin: bool) {
+        self.sndbuf.may_loss_data(range);
+        // 携带FIN的帧丢了，FIN也要重传；若丢的帧有数据，FIN会随重传的数据再发，否则要单独再发
+        if is_fin && self.fin_state == FinState::Sent {
+            self.fin_state = FinState::None;
+        }
+    }
+
+    pub(super) fn has_lost(&self) -> bool {
+        self.sndbuf.has_lost()
+    }
+
+    pub(super) fn is_flushed(&self, mode: FlushMode) -> bool {
+        self.cancel_state.is_some()
+            || match mode {
+                FlushMode::SentOnce => {
+                    !self.sndbuf.has_unsent() && self.fin_state != FinState::None
+                }
+                FlushMode::FullyAcked => self.is_all_rcvd(),
+            }
     }
 
     pub(super) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
@@ -445,7 +697,7 @@ impl DataSentSender {
 
     pub(super) fn poll_cancel(&mut self, cx: &mut Context<'_>) -> Poll<(u64, u64)> {
         if let Some(err_code) = self.cancel_state {
-            Poll::Ready((self.sndbuf.len(), err_code))
+            Poll::Ready((self.sndbuf.sent(), err_code))
         } else {
             self.cancel_waker = Some(cx.waker().clone());
             Poll::Pending
@@ -480,7 +732,7 @@ impl DataSentSender {
     pub(super) fn stop(&mut self) -> u64 {
         self.wake_all();
         // Actually, these remaining data is not acked and will not be acked
-        self.sndbuf.len()
+        self.sndbuf.sent()
     }
 }
 
@@ -489,31 +741,298 @@ pub(super) enum Sender {
     Ready(ReadySender),
     Sending(SendingSender),
     DataSent(DataSentSender),
-    #[allow(unused)] // TODO
-    ResetSent(u64),
+    /// The final size, and the error code of the peer's STOP_SENDING if it's reset for
+    /// that, rather than by the application.
+    ResetSent(u64, Option<u64>),
     DataRcvd,
-    ResetRcvd,
+    /// The error code of the peer's STOP_SENDING, see [`Sender::ResetSent`].
+    ResetRcvd(Option<u64>),
 }
 
 impl Sender {
     pub fn with_wnd_size(wnd_size: u64) -> Self {
         Sender::Ready(ReadySender::with_wnd_size(wnd_size))
     }
+
+    fn introspect(&self) -> SendIntrospection {
+        match self {
+            Sender::Ready(s) => SendIntrospection {
+                max_data: Some(s.max_data_size),
+                cancel_code: s.cancel_state,
+                writable_wakers: s.writable_wakers.len(),
+                flush_waker: s.flush_waker.is_some(),
+                shutdown_waker: s.shutdown_waker.is_some(),
+                cancel_waker: s.cancel_waker.is_some(),
+                blocked: s.blocked.blocked_for(),
+                blocked_total: s.blocked.blocked_total(),
+                ..SendIntrospection::with_sndbuf(SendState::Ready, &s.sndbuf)
+            },
+            Sender::Sending(s) => SendIntrospection {
+                max_data: Some(s.max_data_size),
+                cancel_code: s.cancel_state,
+                writable_wakers: s.writable_wakers.len(),
+                flush_waker: s.flush_waker.is_some(),
+                shutdown_waker: s.shutdown_waker.is_some(),
+                cancel_waker: s.cancel_waker.is_some(),
+                blocked: s.blocked.blocked_for(),
+                blocked_total: s.blocked.blocked_total(),
+                ..SendIntrospection::with_sndbuf(SendState::Sending, &s.sndbuf)
+            },
+            Sender::DataSent(s) => SendIntrospection {
+                final_size: Some(s.sndbuf.len()),
+                fin_sent: s.fin_state != FinState::None,
+                fin_acked: s.fin_state == FinState::Rcvd,
+                cancel_code: s.cancel_state,
+                flush_waker: s.flush_waker.is_some(),
+                shutdown_waker: s.shutdown_waker.is_some(),
+                cancel_waker: s.cancel_waker.is_some(),
+                ..SendIntrospection::with_sndbuf(SendState::DataSent, &s.sndbuf)
+            },
+            Sender::ResetSent(final_size, _) => SendIntrospection {
+                final_size: Some(*final_size),
+                ..SendIntrospection::with_state(SendState::ResetSent)
+            },
+            // 数据已全部确认，缓冲区随之释放，偏移已无从得知
+            Sender::DataRcvd => SendIntrospection {
+                fin_sent: true,
+                fin_acked: true,
+                ..SendIntrospection::with_state(SendState::DataRcvd)
+            },
+            Sender::ResetRcvd(_) => SendIntrospection::with_state(SendState::ResetRcvd),
+        }
+    }
+}
+
+/// The state of the sending part of a stream, see
+/// [section 3.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-sending-stream-states)
+/// of [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SendState {
+    Ready,
+    Sending,
+    DataSent,
+    ResetSent,
+    DataRcvd,
+    ResetRcvd,
+    /// The connection is broken, the stream is gone with it.
+    Broken,
+}
+
+/// A snapshot of the sending part of a stream for diagnostics, which never carries any
+/// stream data.
+///
+/// The offsets and sizes are in bytes from the start of the stream. Some of them are lost
+/// once the data is acknowledged or the stream is reset, they are zero or `None` then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SendIntrospection {
+    pub state: SendState,
+    /// The end of the data written by the application.
+    pub written: u64,
+    /// The end of the data sent at least once.
+    pub sent: u64,
+    /// The end of the data acknowledged contiguously by the peer.
+    pub acked: u64,
+    /// The bytes written but not acknowledged yet.
+    pub buffered: u64,
+    /// The flow control limit set by the peer, before all data is written.
+    pub max_data: Option<u64>,
+    /// The final size of the stream, once it's finished or reset.
+    pub final_size: Option<u64>,
+    pub fin_sent: bool,
+    pub fin_acked: bool,
+    /// The error code the application cancelled the stream with, before the RESET_STREAM
+    /// frame is sent.
+    pub cancel_code: Option<u64>,
+    /// The number of write tasks waiting for the send window.
+    pub writable_wakers: usize,
+    pub flush_waker: bool,
+    pub shutdown_waker: bool,
+    pub cancel_waker: bool,
+    /// How long the stream has been blocked on the peer's MAX_STREAM_DATA, if it is now,
+    /// i.e. all the data within the window is sent, and the application waits to write more.
+    pub blocked: Option<Duration>,
+    /// The time spent blocked on the peer's MAX_STREAM_DATA in total, before all data is
+    /// written.
+    pub blocked_total: Duration,
+}
+
+impl SendIntrospection {
+    fn with_state(state: SendState) -> Self {
+        Self {
+            state,
+            written: 0,
+            sent: 0,
+            acked: 0,
+            buffered: 0,
+            max_data: None,
+            final_size: None,
+            fin_sent: false,
+            fin_acked: false,
+            cancel_code: None,
+            writable_wakers: 0,
+            flush_waker: false,
+            shutdown_waker: false,
+            cancel_waker: false,
+            blocked: None,
+            blocked_total: Duration::ZERO,
+        }
+    }
+
+    fn with_sndbuf(state: SendState, sndbuf: &SendBuf) -> Self {
+        Self {
+            written: sndbuf.len(),
+            sent: sndbuf.sent(),
+            acked: sndbuf.acked(),
+            buffered: sndbuf.buffered(),
+            ..Self::with_state(state)
+        }
+    }
+}
+
+/// The default urgency of a stream, see [`ArcSender::set_urgency`].
+pub const DEFAULT_URGENCY: u8 = 3;
+/// The least urgent level, the urgency is clamped to `0..=MAX_URGENCY`.
+pub const MAX_URGENCY: u8 = 7;
+/// The default weight of a stream, see [`ArcSender::set_weight`].
+pub const DEFAULT_WEIGHT: u16 = 16;
+/// The largest weight, the weight is clamped to `1..=MAX_WEIGHT`.
+pub const MAX_WEIGHT: u16 = 256;
+
+/// The priority of a stream, like the extensible priorities of
+/// [RFC 9218](https://www.rfc-editor.org/rfc/rfc9218.html), but with a weight in place of
+/// the incremental flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Priority {
+    /// 0 is the most urgent and [`MAX_URGENCY`] the least, the streams of a lower urgency
+    /// are always sent first, see [`ArcSender::urgency`].
+    pub urgency: u8,
+    /// The share of the bandwidth among the streams of the same urgency, see
+    /// [`ArcSender::weight`].
+    pub weight: u16,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self {
+            urgency: DEFAULT_URGENCY,
+            weight: DEFAULT_WEIGHT,
+        }
+    }
 }
 
 /// Sender是典型的一体两用，对应用层而言是Writer，对传输控制层而言是Outgoing。
 /// Writer/Outgoing分别有不同的接口，而且生命周期独立，应用层可以在close、reset后
 /// 直接丢弃不管；然而Outgoing还有DataRcvd、ResetRcvd两个状态，需要等待对端确认。
 /// 所以Writer/Outgoing内部共享同一个Sender。
-#[derive(Debug, Clone)]
-pub struct ArcSender(Arc<Mutex<io::Result<Sender>>>);
+#[derive(Clone)]
+pub struct ArcSender(
+    Arc<Mutex<io::Result<Sender>>>,
+    ArcTraceContext,
+    Arc<AtomicU8>,
+    Arc<AtomicU16>,
+    // 发送到这个偏移的包要请求对方立即确认，0表示没有，见Writer::flush_eager
+    Arc<AtomicU64>,
+);
 
 impl ArcSender {
     pub fn with_wnd_size(wnd_size: u64) -> Self {
-        ArcSender(Arc::new(Mutex::new(Ok(Sender::with_wnd_size(wnd_size)))))
+        Self::with_trace(wnd_size, ArcTraceContext::default())
+    }
+
+    /// 流级别的追踪上下文，该流的异步任务都在其中执行
+    pub fn with_trace(wnd_size: u64, trace: ArcTraceContext) -> Self {
+        ArcSender(
+            Arc::new(Mutex::new(Ok(Sender::with_wnd_size(wnd_size)))),
+            trace,
+            Arc::new(AtomicU8::new(DEFAULT_URGENCY)),
+            Arc::new(AtomicU16::new(DEFAULT_WEIGHT)),
+            Arc::new(AtomicU64::new(0)),
+        )
+    }
+
+    pub fn trace(&self) -> &ArcTraceContext {
+        &self.1
+    }
+
+    /// The urgency of the stream, like that of [RFC 9218](https://www.rfc-editor.org/rfc/rfc9218.html#name-urgency),
+    /// the lower the more urgent. The streams of a lower urgency are sent first, the streams of
+    /// the same urgency share the bandwidth in turn.
+    pub fn urgency(&self) -> u8 {
+        self.2.load(Ordering::Relaxed)
+    }
+
+    pub fn set_urgency(&self, urgency: u8) {
+        self.2.store(urgency.min(MAX_URGENCY), Ordering::Relaxed);
+    }
+
+    /// The weight of the stream among the streams of the same urgency, each takes turns to
+    /// send up to an amount of data in proportion to its weight.
+    pub fn weight(&self) -> u16 {
+        self.3.load(Ordering::Relaxed)
+    }
+
+    pub fn set_weight(&self, weight: u16) {
+        self.3.store(weight.clamp(1, MAX_WEIGHT), Ordering::Relaxed);
+    }
+
+    pub fn priority(&self) -> Priority {
+        Priority {
+            urgency: self.urgency(),
+            weight: self.weight(),
+        }
+    }
+
+    pub fn set_priority(&self, priority: Priority) {
+        self.set_urgency(priority.urgency);
+        self.set_weight(priority.weight);
+    }
+
+    /// Mark the data written so far, the packet carrying its end asks the peer to
+    /// acknowledge at once. Nothing is marked if all of it has been sent already.
+    pub(super) fn mark_eager(&self) {
+        let snapshot = self.introspect();
+        if snapshot.sent < snapshot.written {
+            self.4.store(snapshot.written, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the data sent up to `end` reaches the mark, the mark is cleared then.
+    pub(crate) fn take_eager(&self, end: u64) -> bool {
+        let mark = self.4.load(Ordering::Relaxed);
+        mark != 0
+            && end >= mark
+            && self
+                .4
+                .compare_exchange(mark, 0, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
     }
 
     pub(super) fn sender(&self) -> MutexGuard<io::Result<Sender>> {
         self.0.lock().unwrap()
     }
+
+    /// Take a snapshot of the sending state for diagnostics, see [`SendIntrospection`].
+    pub fn introspect(&self) -> SendIntrospection {
+        match self.sender().as_ref() {
+            Ok(sender) => sender.introspect(),
+            Err(_) => SendIntrospection::with_state(SendState::Broken),
+        }
+    }
+}
+
+// 只输出发送状态的快照，不输出缓冲的数据；Debug可能在持有锁时被调用，不能阻塞
+impl std::fmt::Debug for ArcSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("ArcSender");
+        match self.0.try_lock() {
+            Ok(sender) => match sender.as_ref() {
+                Ok(sender) => s.field("state", &sender.introspect()),
+                Err(e) => s.field("state", &SendState::Broken).field("error", e),
+            },
+            Err(_) => s.field("state", &format_args!("<locked>")),
+        };
+        s.field("priority", &self.priority()).finish()
+    }
 }
@@ -5,6 +5,9 @@ use std::{
     ops::Range,
 };
 
+use bytes::{Buf, Bytes, BytesMut};
+use qbase::util::Chunks;
+
 /// To indicate the state of a data segment, it is colored.
 #[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
 enum Color {
@@ -140,7 +143,8 @@ impl BufMap {
                 } else {
                     available.min(flow_limit)
                 };
-                Some((idx, allowance, state))
+                // 没有额度的区间，不能挑出空的数据来发送
+                (allowance > 0).then_some((idx, allowance, state))
             })
             .map(|(index, allowance, state)| {
                 let origin_state = *state; // 此处能归还self.0的可变借用
@@ -297,6 +301,24 @@ impl BufMap {
         }
     }
 
+    // 是否还有从未发送过的Pending数据，已发送但判定丢失待重传的Lost数据不算
+    fn has_pending(&self) -> bool {
+        self.0.iter().any(|s| s.color() == Color::Pending)
+    }
+
+    // 是否有判定丢失待重传的Lost数据
+    fn has_lost(&self) -> bool {
+        self.0.iter().any(|s| s.color() == Color::Lost)
+    }
+
+    // 发送过的数据的末尾；数据按顺序挑选发送，Pending的区间总在最后
+    fn sent(&self) -> u64 {
+        self.0
+            .iter()
+            .find(|s| s.color() == Color::Pending)
+            .map_or(self.1, |s| s.offset())
+    }
+
     // 判定某部分数据丢失，但不一定真的丢失，判定可能有误；丢失的数据需要优先重传。
     // 寻找到丢失区间覆盖的范围，其中若遇到Recved的区间，则忽略；只有Flighting/Lost的才可以丢失。
     // 然后检查Lost区间前后是否有需要合并的区间，合并之。
@@ -324,7 +346,13 @@ impl BufMap {
             }
             Err(idx) => {
                 if idx == 0 {
-                    (1, false, 0, Color::Recved)
+                    // 丢失区间的前段已被确认并移出，只需判定剩余的部分
+                    if let Some(start) = self.0.front().map(State::offset) {
+                        if start < range.end {
+                            self.may_loss(&(start..range.end));
+                        }
+                    }
+                    return;
                 } else {
                     let s = self.0.get(idx - 1).unwrap();
                     let pre_color = s.color();
@@ -501,20 +529,23 @@ impl BufMap {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default)]
 pub struct SendBuf {
     offset: u64,
-    // 写入数据的环形队列，与接收队列不同的是，它是连续的
-    data: VecDeque<u8>,
+    // 写入而未确认的数据，由一段段Bytes组成：应用交来的Bytes原样保存，不拷贝；拷贝写入的
+    // 数据先攒在tail中，挑选数据发送之前再封成一段，以免零碎的写入产生太多的段
+    chunks: VecDeque<Bytes>,
+    tail: BytesMut,
+    // 缓冲区的容量，Crypto流据此控制写入
+    capacity: usize,
     state: BufMap,
 }
 
 impl SendBuf {
     pub fn with_capacity(n: usize) -> Self {
         Self {
-            offset: 0,
-            data: VecDeque::with_capacity(n),
-            state: BufMap::default(),
+            capacity: n,
+            ..Self::default()
         }
     }
 
@@ -527,15 +558,34 @@ impl SendBuf {
         // 写的数据量受流量控制限制，Crypto流则受Crypto流自身控制
         let n = data.len();
         if n > 0 {
-            self.data.extend(data);
+            self.tail.extend_from_slice(data);
+            self.state.extend_to(self.len() + n as u64);
+        }
+
+        n
+    }
+
+    // invoked by application layer
+    // 原样保存data，直到它被确认，不拷贝
+    pub fn write_bytes(&mut self, data: Bytes) -> usize {
+        let n = data.len();
+        if n > 0 {
+            self.seal();
+            self.chunks.push_back(data);
             self.state.extend_to(self.len() + n as u64);
         }
 
         n
     }
 
+    fn seal(&mut self) {
+        if !self.tail.is_empty() {
+            self.chunks.push_back(self.tail.split().freeze());
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.offset == 0 && self.data.is_empty()
+        self.state.1 == 0
     }
 
     // invoked by application layer
@@ -545,7 +595,7 @@ impl SendBuf {
     }
 
     pub fn remaining_mut(&self) -> usize {
-        self.data.capacity() - self.data.len()
+        self.capacity.saturating_sub(self.buffered() as usize)
     }
 
     // 无需close：不在写入即可，具体到某个状态，才有close
@@ -553,37 +603,47 @@ impl SendBuf {
     // 无需clean：Sender上下文直接释放即可，
 }
 
-type Data<'s> = (u64, bool, (&'s [u8], &'s [u8]));
+type Data<'s> = (u64, bool, Chunks<'s>);
 
 impl SendBuf {
     // 挑选出可供发送的数据，限制长度不能超过len，以满足一个数据包能容的下一个完整的数据帧。
-    // 返回的是一个切片，该切片的生命周期必须不长于SendBuf的生命周期，该切片可以被缓存至数据包
+    // 返回的数据可能跨越多段，其生命周期必须不长于SendBuf的生命周期，该数据可以被缓存至数据包
     // 被确认或者被判定丢失。
     pub fn pick_up<P>(&mut self, predicate: P, flow_limit: usize) -> Option<Data>
     where
         P: Fn(u64) -> Option<usize>,
     {
+        self.seal();
         self.state
             .pick(predicate, flow_limit)
             .map(|(range, is_fresh)| {
                 let start = (range.start - self.offset) as usize;
-                let end = (range.end - self.offset) as usize;
-
-                let (l, r) = self.data.as_slices();
-                let s1 = &l[start.min(l.len())..l.len().min(end)];
-                let s2 = &r[start.saturating_sub(l.len())..end.saturating_sub(l.len())];
-                (range.start, is_fresh, (s1, s2))
+                let len = (range.end - range.start) as usize;
+                (range.start, is_fresh, Chunks::new(&self.chunks, start, len))
             })
     }
 
     // 通过传输层接收到的对方的ack帧，确认某些包已经被接收到，这些包携带的数据即被确认。
     // ack只能确认Flighting/Lost状态的区间；如果确认的是Lost区间，意味着之前的判定丢包是错误的。
+    // 空区间没有数据，只可能是仅携带FIN的帧，FIN由发送端单独记录，不能在此留下空的区间。
     pub fn on_data_acked(&mut self, range: &Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
         self.state.ack_rcvd(range);
         // 对于头部连续确认接收到的，还要前进，以免浪费空间
         let min_unrecved_pos = self.state.shift();
         if self.offset < min_unrecved_pos {
-            self.data.drain(..(min_unrecved_pos - self.offset) as usize);
+            self.seal();
+            let mut n = (min_unrecved_pos - self.offset) as usize;
+            while let Some(chunk) = self.chunks.front_mut() {
+                if n < chunk.len() {
+                    chunk.advance(n);
+                    break;
+                }
+                n -= chunk.len();
+                self.chunks.pop_front();
+            }
             self.offset = min_unrecved_pos;
         }
     }
@@ -591,17 +651,110 @@ impl SendBuf {
     // 通过传输层收到的ack帧，判定有些数据包丢失，因为它之后的数据包都被确认了，
     // 或者距离发送该段数据之后相当长一段时间都没收到它的确认。
     pub fn may_loss_data(&mut self, range: &Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
         self.state.may_loss(range);
     }
 
     pub fn is_all_rcvd(&self) -> bool {
-        self.data.is_empty()
+        self.offset == self.state.1
+    }
+
+    // 是否还有写入后从未发送过的数据，待重传的数据不算
+    pub fn has_unsent(&self) -> bool {
+        self.state.has_pending()
+    }
+
+    // 是否有判定丢失、等待重传的数据
+    pub fn has_lost(&self) -> bool {
+        self.state.has_lost()
+    }
+
+    /// The end of the data sent at least once.
+    pub fn sent(&self) -> u64 {
+        self.state.sent()
+    }
+
+    /// The end of the data acknowledged contiguously from the start of the stream.
+    pub fn acked(&self) -> u64 {
+        self.offset
+    }
+
+    /// The bytes written but not acknowledged yet, still held in the buffer.
+    pub fn buffered(&self) -> u64 {
+        self.state.1 - self.offset
+    }
+}
+
+// 只输出偏移与区间状态，绝不输出缓冲的数据，以免应用数据泄露到日志中
+impl Debug for SendBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendBuf")
+            .field("acked", &self.acked())
+            .field("sent", &self.sent())
+            .field("written", &self.len())
+            .field("state", &self.state)
+            .finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{BufMap, Color, State};
+    use bytes::Bytes;
+    use qbase::util::WriteData;
+
+    use super::{BufMap, Color, SendBuf, State};
+
+    #[test]
+    fn test_sndbuf_across_chunks() {
+        let mut sndbuf = SendBuf::default();
+        sndbuf.write(b"hello ");
+        sndbuf.write_bytes(Bytes::from_static(b"zero-copy "));
+        sndbuf.write(b"world");
+        assert_eq!(sndbuf.len(), 21);
+
+        let mut pick = |max: usize| {
+            sndbuf
+                .pick_up(|_| Some(max), usize::MAX)
+                .map(|(offset, is_fresh, data)| {
+                    let mut buf = Vec::new();
+                    buf.put_data(&data);
+                    (offset, is_fresh, buf)
+                })
+        };
+        assert_eq!(pick(8), Some((0, true, b"hello ze".to_vec())));
+        assert_eq!(pick(10), Some((8, true, b"ro-copy wo".to_vec())));
+        assert_eq!(pick(10), Some((18, true, b"rld".to_vec())));
+        assert_eq!(pick(10), None);
+
+        // 确认了第一段的一部分，以及第二段中间的一部分
+        sndbuf.on_data_acked(&(0..4));
+        sndbuf.on_data_acked(&(10..18));
+        assert_eq!(sndbuf.acked(), 4);
+        assert_eq!(sndbuf.buffered(), 17);
+
+        // 丢失的数据跨越前两段的边界，重传时仍从各段中取出原来的数据
+        sndbuf.may_loss_data(&(4..10));
+        let (offset, is_fresh, data) = sndbuf.pick_up(|_| Some(100), 0).unwrap();
+        let mut buf = Vec::new();
+        buf.put_data(&data);
+        assert_eq!((offset, is_fresh, buf), (4, false, b"o zero".to_vec()));
+
+        // 补上空洞之后，前两段都被释放，第三段从中间开始
+        sndbuf.on_data_acked(&(4..10));
+        assert_eq!(sndbuf.acked(), 18);
+        assert_eq!(sndbuf.buffered(), 3);
+        sndbuf.may_loss_data(&(18..21));
+        let (offset, _, data) = sndbuf.pick_up(|_| Some(100), 0).unwrap();
+        let mut buf = Vec::new();
+        buf.put_data(&data);
+        assert_eq!((offset, buf), (18, b"rld".to_vec()));
+
+        sndbuf.on_data_acked(&(18..21));
+        assert!(sndbuf.is_all_rcvd());
+        assert_eq!(sndbuf.buffered(), 0);
+    }
 
     #[test]
     fn test_bufmap_empty() {
@@ -1040,4 +1193,27 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_bufmap_lost_after_shift() {
+        let mut buf_map = BufMap::default();
+        buf_map.extend_to(11);
+        buf_map.pick(|_| Some(11), usize::MAX);
+        buf_map.may_loss(&(0..11));
+        buf_map.pick(|_| Some(2), 0);
+        assert_eq!(
+            buf_map.0,
+            vec![
+                State::encode(0, Color::Flighting),
+                State::encode(2, Color::Lost)
+            ]
+        );
+        buf_map.ack_rcvd(&(0..1));
+        assert_eq!(buf_map.shift(), 1);
+
+        // 丢失区间的前段已被确认并移出
+        buf_map.may_loss(&(0..2));
+        assert_eq!(buf_map.0, vec![State::encode(1, Color::Lost)]);
+        assert_eq!(buf_map.pick(|_| Some(20), 0), Some((1..11, false)));
+    }
 }
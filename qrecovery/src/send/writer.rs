@@ -1,24 +1,113 @@
 use std::{
-    io,
+    fmt, io,
     ops::DerefMut,
     pin::Pin,
-    task::{Context, Poll},
+    sync::Arc,
+    task::{ready, Context, Poll},
 };
 
-use tokio::io::AsyncWrite;
+use bytes::Bytes;
+use qbase::{cid::ConnectionId, error::clone_io_error, streamid::StreamId};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use super::sender::{ArcSender, Sender};
+use super::sender::{ArcSender, Priority, Sender};
+use crate::{streams::policy::InFlight, trailer};
+
+/// The error inside the [`io::Error`] of kind [`io::ErrorKind::BrokenPipe`], returned by
+/// writing a [`Writer`] after the peer asked to stop sending by a STOP_SENDING frame, see
+/// [`Reader::stop`](crate::recv::Reader::stop).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStopped {
+    /// The application error code carried by the STOP_SENDING frame.
+    pub error_code: u64,
+}
+
+impl fmt::Display for StreamStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stopped by peer with error code {}", self.error_code)
+    }
+}
+
+impl std::error::Error for StreamStopped {}
+
+// 流被重置之后，写端的操作都失败；因对方要求停止发送而重置的，错误中带有对方的错误码
+fn reset_error(stop_code: Option<u64>, reason: &'static str) -> io::Error {
+    match stop_code {
+        Some(error_code) => io::Error::new(io::ErrorKind::BrokenPipe, StreamStopped { error_code }),
+        None => io::Error::new(io::ErrorKind::BrokenPipe, reason),
+    }
+}
 
 #[derive(Debug)]
-pub struct Writer(pub(crate) ArcSender);
+pub struct Writer(
+    pub(crate) ArcSender,
+    Option<Arc<InFlight>>,
+    StreamId,
+    ConnectionId,
+);
 
 impl AsyncWrite for Writer {
-    /// 往sndbuf里面写数据，直到写满MAX_STREAM_DATA，等通告窗口更新再写
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
+        self.poll_write_ref(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored_ref(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_ref(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_shutdown_ref(cx)
+    }
+}
+
+/// The same as the [`tokio::io::AsyncWrite`] implementation, closing the writer sends the FIN
+/// and completes once all the data is acknowledged by the peer.
+#[cfg(feature = "futures-io")]
+impl futures::io::AsyncWrite for Writer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_ref(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored_ref(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_ref(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_shutdown_ref(cx)
+    }
+}
+
+impl Writer {
+    /// 往sndbuf里面写数据，直到写满MAX_STREAM_DATA，等通告窗口更新再写
+    fn poll_write_ref(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
         match inner {
@@ -33,20 +122,74 @@ impl AsyncWrite for Writer {
                     io::ErrorKind::Unsupported,
                     "all data has been received",
                 ))),
-                Sender::ResetSent(_) => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "reset by local",
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
+                    "reset msg has been received by peer",
                 ))),
-                Sender::ResetRcvd => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
+            },
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
+        }
+    }
+
+    /// 依次写入各段数据，直到窗口写满；已写入了一些数据，就不再等待或报错
+    fn poll_write_vectored_ref(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let mut written = 0;
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            match self.poll_write_ref(cx, buf) {
+                Poll::Ready(Ok(n)) => {
+                    written += n;
+                    if n < buf.len() {
+                        break;
+                    }
+                }
+                Poll::Ready(Err(e)) if written == 0 => return Poll::Ready(Err(e)),
+                Poll::Pending if written == 0 => return Poll::Pending,
+                _ => break,
+            }
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    /// 同poll_write_ref，但不拷贝，写入的部分从data中移走
+    fn poll_write_bytes_ref(
+        &self,
+        cx: &mut Context<'_>,
+        data: &mut Bytes,
+    ) -> Poll<io::Result<usize>> {
+        let mut sender = self.0.sender();
+        let inner = sender.deref_mut();
+        match inner {
+            Ok(sending_state) => match sending_state {
+                Sender::Ready(s) => s.poll_write_bytes(cx, data),
+                Sender::Sending(s) => s.poll_write_bytes(cx, data),
+                Sender::DataSent(_) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been written",
+                ))),
+                Sender::DataRcvd => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been received",
+                ))),
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
                     "reset msg has been received by peer",
                 ))),
             },
-            Err(e) => Poll::Ready(Err(io::Error::new(e.kind(), e.to_string()))),
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
         }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn poll_flush_ref(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
         match inner {
@@ -61,20 +204,19 @@ impl AsyncWrite for Writer {
                     result
                 }
                 Sender::DataRcvd => Poll::Ready(Ok(())),
-                Sender::ResetSent(_) => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "reset by local",
-                ))),
-                Sender::ResetRcvd => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
                     "reset msg has been received by peer",
                 ))),
             },
-            Err(e) => Poll::Ready(Err(io::Error::new(e.kind(), e.to_string()))),
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
         }
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn poll_shutdown_ref(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
         match inner {
@@ -106,39 +248,211 @@ impl AsyncWrite for Writer {
                     result
                 }
                 Sender::DataRcvd => Poll::Ready(Ok(())),
-                Sender::ResetSent(_) => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "reset by local",
-                ))),
-                Sender::ResetRcvd => Poll::Ready(Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
                     "reset msg has been received by peer",
                 ))),
             },
-            Err(e) => Poll::Ready(Err(io::Error::new(e.kind(), e.to_string()))),
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
         }
     }
 }
 
 impl Writer {
-    pub fn cancel(self, err_code: u64) {
+    pub(crate) fn new(sid: StreamId, sender: ArcSender) -> Self {
+        Self(sender, None, sid, ConnectionId::default())
+    }
+
+    pub(crate) fn with_in_flight(mut self, in_flight: Option<Arc<InFlight>>) -> Self {
+        self.1 = in_flight;
+        self
+    }
+
+    /// The id of this stream.
+    pub fn stream_id(&self) -> StreamId {
+        self.2
+    }
+
+    pub(crate) fn with_conn_id(mut self, conn_id: ConnectionId) -> Self {
+        self.3 = conn_id;
+        self
+    }
+
+    /// The stable id of the connection this stream belongs to, for correlating the logs,
+    /// see [`DataStreams::with_conn_id`](crate::streams::DataStreams::with_conn_id).
+    pub fn conn_id(&self) -> ConnectionId {
+        self.3
+    }
+
+    /// Set the span of this stream, the asynchronous work of this stream will be traced
+    /// within it, nested inside the span of the connection.
+    ///
+    /// For a bidirectional stream, the span is shared with the [`Reader`](crate::recv::Reader).
+    pub fn set_trace_span(&self, span: tracing::Span) {
+        self.0.trace().set_span(span);
+    }
+
+    /// Set the urgency of this stream, 0 is the most urgent and 7 the least, 3 by default.
+    /// The streams of a lower urgency are sent first, including the retransmissions of
+    /// their lost data, see [`ArcSender::urgency`].
+    pub fn set_urgency(&self, urgency: u8) {
+        self.0.set_urgency(urgency);
+    }
+
+    pub fn urgency(&self) -> u8 {
+        self.0.urgency()
+    }
+
+    /// Set the urgency and the weight of this stream at once, the streams of the same
+    /// urgency take turns to send in proportion to their weights, see [`Priority`].
+    pub fn set_priority(&self, priority: Priority) {
+        self.0.set_priority(priority);
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.0.priority()
+    }
+
+    /// Write all the data without copying it, like [`AsyncWriteExt::write_all`]. The
+    /// [`Bytes`] is kept in the send buffer as it is, until acknowledged by the peer.
+    ///
+    /// It waits for the send window like the other writes, the part of the data within
+    /// the window is buffered at once.
+    pub async fn write_bytes(&mut self, mut data: Bytes) -> io::Result<()> {
+        while !data.is_empty() {
+            core::future::poll_fn(|cx| self.poll_write_bytes_ref(cx, &mut data)).await?;
+        }
+        Ok(())
+    }
+
+    /// Flush the data written so far, like [`AsyncWriteExt::flush`], and ask the peer to
+    /// acknowledge the packet carrying its end at once by an IMMEDIATE_ACK frame, so that
+    /// the last packet of a response isn't held by the peer's delayed acknowledgment.
+    ///
+    /// It's the same as [`AsyncWriteExt::flush`] if the peer doesn't support the
+    /// ACK_FREQUENCY extension.
+    pub async fn flush_eager(&mut self) -> io::Result<()> {
+        self.0.mark_eager();
+        self.flush().await
+    }
+
+    /// Append the trailer and finish the stream, just like [`AsyncWriteExt::shutdown`].
+    ///
+    /// The trailer and the FIN are scheduled in one step, either both or neither, so
+    /// that the peer will never see a finished stream without the trailer. The trailer
+    /// is framed as described in [`crate::trailer`], and can be split by
+    /// [`Reader::read_to_end_split_trailer`](crate::recv::Reader::read_to_end_split_trailer).
+    pub async fn finish_with(&mut self, trailer: Bytes) -> io::Result<()> {
+        let data = trailer::encode(&trailer)?;
+        core::future::poll_fn(|cx| self.poll_finish_with(cx, &data)).await?;
+        self.shutdown().await
+    }
+
+    // 整体写入data之后随即标记结束，写不下就等待，期间既不写入也不结束
+    fn poll_finish_with(&mut self, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<()>> {
         let mut sender = self.0.sender();
         let inner = sender.deref_mut();
-        if let Ok(sending_state) = inner {
-            match sending_state {
+        match inner {
+            Ok(sending_state) => match sending_state {
                 Sender::Ready(s) => {
-                    s.cancel(err_code);
+                    ready!(s.poll_write_all(cx, data))?;
+                    s.shutdown(cx)?;
+                    *sending_state = Sender::DataSent(s.into());
+                    Poll::Ready(Ok(()))
                 }
                 Sender::Sending(s) => {
-                    s.cancel(err_code);
+                    ready!(s.poll_write_all(cx, data))?;
+                    s.shutdown(cx)?;
+                    *sending_state = Sender::DataSent(s.into());
+                    Poll::Ready(Ok(()))
                 }
-                Sender::DataSent(s) => {
-                    s.cancel(err_code);
+                Sender::DataSent(_) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been written",
+                ))),
+                Sender::DataRcvd => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been received",
+                ))),
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
+                    "reset msg has been received by peer",
+                ))),
+            },
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
+        }
+    }
+
+    /// Abandon the stream with the application error code, a RESET_STREAM frame carrying
+    /// it is sent to the peer. The data not yet received by the peer is abandoned.
+    ///
+    /// The peer's [`Reader`](crate::recv::Reader) fails with a [`StreamReset`] carrying
+    /// the error code.
+    ///
+    /// [`StreamReset`]: crate::recv::StreamReset
+    pub fn reset(self, error_code: u64) {
+        self.reset_ref(error_code);
+    }
+
+    /// 共享的写端可能被多次取消，只有第一次生效
+    fn reset_ref(&self, err_code: u64) {
+        let mut sender = self.0.sender();
+        let inner = sender.deref_mut();
+        if let Ok(sending_state) = inner {
+            match sending_state {
+                Sender::Ready(s) if !s.is_cancelled() => {
+                    s.reset(err_code);
+                }
+                Sender::Sending(s) if !s.is_cancelled() => {
+                    s.reset(err_code);
+                }
+                Sender::DataSent(s) if !s.is_cancelled() => {
+                    s.reset(err_code);
                 }
                 _ => (),
             }
         };
     }
+
+    /// 将一条记录整体写入，要么全部写入，要么等待窗口更新，不会与其他记录交错
+    fn poll_write_all(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<()>> {
+        let mut sender = self.0.sender();
+        let inner = sender.deref_mut();
+        match inner {
+            Ok(sending_state) => match sending_state {
+                Sender::Ready(s) => s.poll_write_all(cx, data),
+                Sender::Sending(s) => s.poll_write_all(cx, data),
+                Sender::DataSent(_) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been written",
+                ))),
+                Sender::DataRcvd => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "all data has been received",
+                ))),
+                Sender::ResetSent(_, stop_code) => {
+                    Poll::Ready(Err(reset_error(*stop_code, "reset by local")))
+                }
+                Sender::ResetRcvd(stop_code) => Poll::Ready(Err(reset_error(
+                    *stop_code,
+                    "reset msg has been received by peer",
+                ))),
+            },
+            Err(e) => Poll::Ready(Err(clone_io_error(e))),
+        }
+    }
+
+    /// Turn this writer into a [`SharedWriter`], which can be cloned and used by several
+    /// tasks at the same time to write whole records.
+    pub fn into_shared(self) -> SharedWriter {
+        SharedWriter(Arc::new(self))
+    }
 }
 
 impl Drop for Writer {
@@ -175,4 +489,241 @@ impl Drop for Writer {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use futures::FutureExt;
+    use qbase::varint::VarInt;
+
+    use super::*;
+    use crate::send::Outgoing;
+
+    #[test]
+    fn test_finish_with() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let sender = ArcSender::with_wnd_size(1000);
+        let mut writer = Writer::new(StreamId::from(VarInt::from_u32(0)), sender.clone());
+        let outgoing = Outgoing(sender);
+
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, b"hello")
+            .is_ready());
+        assert!(writer
+            .finish_with(Bytes::from_static(b"ok"))
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+
+        // The trailer and the fin are scheduled together
+        let mut buf = [0u8; 100];
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let (frame, len, is_fresh, _) = outgoing.try_read(sid, &mut buf, 100, 100).unwrap();
+        assert!(frame.is_fin());
+        assert!(is_fresh);
+        assert_eq!(len, 5 + 2 + trailer::TRAILER_LEN_SIZE);
+
+        assert!(outgoing.on_data_acked(&frame.range(), true));
+        assert!(matches!(
+            Pin::new(&mut writer).poll_shutdown(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn test_futures_io_write() {
+        use futures::io::AsyncWrite as FuturesAsyncWrite;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let sender = ArcSender::with_wnd_size(5);
+        let mut writer = Writer::new(StreamId::from(VarInt::from_u32(0)), sender.clone());
+        let outgoing = Outgoing(sender);
+
+        assert!(matches!(
+            FuturesAsyncWrite::poll_write(Pin::new(&mut writer), &mut cx, b"hello world"),
+            Poll::Ready(Ok(5))
+        ));
+        // 窗口用完了，等待而不是无限地缓冲
+        assert!(
+            FuturesAsyncWrite::poll_write(Pin::new(&mut writer), &mut cx, b" world").is_pending()
+        );
+        assert!(FuturesAsyncWrite::poll_close(Pin::new(&mut writer), &mut cx).is_pending());
+
+        let mut buf = [0u8; 100];
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let (frame, len, _, _) = outgoing.try_read(sid, &mut buf, 100, 100).unwrap();
+        assert!(frame.is_fin());
+        assert_eq!(len, 5);
+        assert!(outgoing.on_data_acked(&frame.range(), true));
+        assert!(matches!(
+            FuturesAsyncWrite::poll_close(Pin::new(&mut writer), &mut cx),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    #[test]
+    fn test_write_vectored_and_bytes() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let sid = StreamId::from(VarInt::from_u32(0));
+        let sender = ArcSender::with_wnd_size(18);
+        let mut writer = Writer::new(sid, sender.clone());
+        let outgoing = Outgoing(sender);
+
+        // 窗口只容得下前两段和第三段的一部分
+        let bufs = [
+            io::IoSlice::new(b"hello "),
+            io::IoSlice::new(b"vectored "),
+            io::IoSlice::new(b"world"),
+        ];
+        assert!(matches!(
+            Pin::new(&mut writer).poll_write_vectored(&mut cx, &bufs),
+            Poll::Ready(Ok(18))
+        ));
+        assert!(Pin::new(&mut writer)
+            .poll_write_vectored(&mut cx, &[io::IoSlice::new(b"ld")])
+            .is_pending());
+
+        outgoing.update_window(40);
+        let data = Bytes::from_static(b"ld, zero-copy");
+        assert!(matches!(
+            writer.write_bytes(data).now_or_never(),
+            Some(Ok(()))
+        ));
+
+        let mut buf = [0u8; 100];
+        let (frame, len, is_fresh, written) = outgoing.try_read(sid, &mut buf, 100, 100).unwrap();
+        assert!(is_fresh);
+        assert_eq!(frame.range(), 0..31);
+        assert_eq!(
+            &buf[written - len..written],
+            b"hello vectored world, zero-copy"
+        );
+        writer.reset(0);
+    }
+
+    #[test]
+    fn test_finish_with_in_small_window() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let sender = ArcSender::with_wnd_size(3);
+        let mut writer = Writer::new(StreamId::from(VarInt::from_u32(0)), sender.clone());
+        let outgoing = Outgoing(sender);
+
+        assert!(writer
+            .finish_with(Bytes::from_static(b"ok"))
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+
+        // Neither the trailer nor the fin is scheduled
+        let mut buf = [0u8; 100];
+        let sid = StreamId::from(VarInt::from_u32(0));
+        assert!(outgoing.try_read(sid, &mut buf, 100, 100).is_none());
+
+        outgoing.update_window(10);
+        assert!(writer
+            .finish_with(Bytes::from_static(b"ok"))
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+        let (frame, len, _, _) = outgoing.try_read(sid, &mut buf, 100, 100).unwrap();
+        assert!(frame.is_fin());
+        assert_eq!(len, 2 + trailer::TRAILER_LEN_SIZE);
+        writer.reset(0);
+    }
+
+    #[test]
+    fn test_reset_after_finish_with() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let sender = ArcSender::with_wnd_size(1000);
+        let mut writer = Writer::new(StreamId::from(VarInt::from_u32(0)), sender.clone());
+        let outgoing = Outgoing(sender);
+
+        assert!(writer
+            .finish_with(Bytes::from_static(b"ok"))
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+        writer.reset(7);
+
+        // 结尾的数据还未发出，不计入最终大小
+        assert_eq!(
+            outgoing.is_cancelled_by_app().now_or_never(),
+            Some(Some((0, 7)))
+        );
+        let mut buf = [0u8; 100];
+        let sid = StreamId::from(VarInt::from_u32(0));
+        assert!(outgoing.try_read(sid, &mut buf, 100, 100).is_none());
+    }
+}
+
+/// A cloneable handle of a [`Writer`], for the applications where several tasks write
+/// independent records to the same stream, created by [`Writer::into_shared`].
+///
+/// Each record written by [`SharedWriter::write_message`] or [`SharedWriter::write_bytes`]
+/// is atomic: its bytes are appended to the stream contiguously, never interleaved with
+/// the bytes of the other records. The order between the records written by different
+/// handles or tasks is unspecified, only the records written one after another by the
+/// same task keep their order.
+///
+/// A record is appended only when the flow control window can take it whole, so a
+/// record must not be larger than the window of the stream. Just like [`Writer`], the
+/// stream must be finished or cancelled before the last handle is dropped.
+#[derive(Debug, Clone)]
+pub struct SharedWriter(Arc<Writer>);
+
+impl SharedWriter {
+    /// Append a record to the stream atomically, see [`SharedWriter`] for the ordering.
+    pub async fn write_message(&self, record: &[u8]) -> io::Result<()> {
+        core::future::poll_fn(|cx| self.0.poll_write_all(cx, record)).await
+    }
+
+    /// Same as [`SharedWriter::write_message`], but takes the record as [`Bytes`].
+    pub async fn write_bytes(&self, record: Bytes) -> io::Result<()> {
+        self.write_message(&record).await
+    }
+
+    /// Wait until all the records written so far are acknowledged by the peer.
+    pub async fn flush(&self) -> io::Result<()> {
+        core::future::poll_fn(|cx| self.0.poll_flush_ref(cx)).await
+    }
+
+    /// Finish the stream after all the records written so far, and wait until they are
+    /// acknowledged by the peer. The records written afterwards by any handle fail.
+    pub async fn finish(&self) -> io::Result<()> {
+        core::future::poll_fn(|cx| self.0.poll_shutdown_ref(cx)).await
+    }
+
+    /// Reset the stream with the error code like [`Writer::reset`], only the first reset
+    /// by any handle takes effect.
+    pub fn reset(&self, error_code: u64) {
+        self.0.reset_ref(error_code);
+    }
+
+    /// See [`Writer::stream_id`].
+    pub fn stream_id(&self) -> StreamId {
+        self.0.stream_id()
+    }
+
+    /// See [`Writer::conn_id`].
+    pub fn conn_id(&self) -> ConnectionId {
+        self.0.conn_id()
+    }
+
+    /// See [`Writer::set_trace_span`].
+    pub fn set_trace_span(&self, span: tracing::Span) {
+        self.0.set_trace_span(span);
+    }
+
+    /// See [`Writer::set_urgency`].
+    pub fn set_urgency(&self, urgency: u8) {
+        self.0.set_urgency(urgency);
+    }
+
+    /// See [`Writer::set_priority`].
+    pub fn set_priority(&self, priority: Priority) {
+        self.0.set_priority(priority);
+    }
+}
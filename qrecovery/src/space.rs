@@ -2,7 +2,11 @@ use std::ops::{Index, IndexMut};
 
 use qbase::frame::CryptoFrame;
 
-use crate::reliable::{rcvdpkt::ArcRcvdPktRecords, sentpkt::ArcSentPktRecords, GuaranteedFrame};
+use crate::reliable::{
+    rcvdpkt::ArcRcvdPktRecords,
+    sentpkt::{ArcSentPktRecords, MergeFrame},
+    GuaranteedFrame,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Epoch {
@@ -48,14 +52,16 @@ pub struct RawSpace<T> {
     rcvd_pkt_records: ArcRcvdPktRecords,
 }
 
-impl<T> RawSpace<T> {
+impl<T: MergeFrame> RawSpace<T> {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            sent_pkt_records: ArcSentPktRecords::with_capacity(capacity),
+            sent_pkt_records: ArcSentPktRecords::with_merging(capacity),
             rcvd_pkt_records: ArcRcvdPktRecords::with_capacity(capacity),
         }
     }
+}
 
+impl<T> RawSpace<T> {
     pub fn sent_packets(&self) -> ArcSentPktRecords<T> {
         self.sent_pkt_records.clone()
     }
@@ -3,24 +3,29 @@ use std::{
     future::Future,
     pin::Pin,
     sync::Arc,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
 
 use bytes::Bytes;
 use deref_derive::Deref;
-use listener::{AcceptBiStream, AcceptUniStream};
+use futures::FutureExt;
+use listener::{AcceptBiStream, AcceptClass, AcceptUniStream};
 use qbase::{
+    cid::ConnectionId,
     config::Parameters,
     error::Error,
     frame::{ReceiveFrame, SendFrame, StreamCtlFrame, StreamFrame},
-    streamid::{Dir, Role},
+    streamid::{Dir, Role, StreamOpenRate},
 };
 
 use crate::{recv::Reader, send::Writer};
 
+pub mod classify;
 pub mod crypto;
 pub mod data;
 pub mod listener;
+pub mod policy;
+pub mod scheduler;
 
 #[derive(Debug, Clone, Deref)]
 pub struct DataStreams<T>(Arc<data::RawDataStreams<T>>)
@@ -32,7 +37,19 @@ where
     T: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
 {
     pub fn new(role: Role, local_params: &Parameters, ctrl_frames: T) -> Self {
-        let raw = data::RawDataStreams::new(role, local_params, ctrl_frames);
+        Self::with_conn_id(role, local_params, ctrl_frames, ConnectionId::default())
+    }
+
+    /// Same as [`DataStreams::new`], and the readers and writers of the streams carry the
+    /// given id of the connection, see [`Reader::conn_id`] and [`Writer::conn_id`]. An
+    /// empty id by default.
+    pub fn with_conn_id(
+        role: Role,
+        local_params: &Parameters,
+        ctrl_frames: T,
+        conn_id: ConnectionId,
+    ) -> Self {
+        let raw = data::RawDataStreams::new(role, local_params, ctrl_frames, conn_id);
 
         Self(Arc::new(raw))
     }
@@ -42,6 +59,8 @@ where
         OpenBiStream {
             inner: self,
             snd_wnd_size,
+            label: None,
+            waker: None,
         }
     }
 
@@ -50,6 +69,60 @@ where
         OpenUniStream {
             inner: self,
             snd_wnd_size,
+            label: None,
+            waker: None,
+        }
+    }
+
+    /// Open a bidirectional stream without waiting, None if it can't be opened right now,
+    /// because the peer's MAX_STREAMS is reached, the opens waiting before take the credit
+    /// first, or the [`StreamOpenRate`] is exceeded. Also None if the stream IDs are used up.
+    ///
+    /// Nothing is allocated if it gives up, the STREAMS_BLOCKED frame is sent to the peer
+    /// though.
+    #[inline]
+    pub fn try_open_bi(&self, snd_wnd_size: u64) -> Result<Option<(Reader, Writer)>, Error> {
+        // 放弃时，OpenBiStream被丢弃，随之退出排队
+        self.open_bi(snd_wnd_size)
+            .now_or_never()
+            .unwrap_or(Ok(None))
+    }
+
+    /// Open a unidirectional stream without waiting, see [`DataStreams::try_open_bi`].
+    #[inline]
+    pub fn try_open_uni(&self, snd_wnd_size: u64) -> Result<Option<Writer>, Error> {
+        self.open_uni(snd_wnd_size)
+            .now_or_never()
+            .unwrap_or(Ok(None))
+    }
+
+    /// Open a bidirectional stream on the lane, see [`RawDataStreams::register_lanes`].
+    ///
+    /// [`RawDataStreams::register_lanes`]: data::RawDataStreams::register_lanes
+    #[inline]
+    pub fn open_bi_labeled<'d>(&'d self, label: &'d str, snd_wnd_size: u64) -> OpenBiStream<'d, T> {
+        OpenBiStream {
+            inner: self,
+            snd_wnd_size,
+            label: Some(label),
+            waker: None,
+        }
+    }
+
+    /// Open a unidirectional stream on the lane, see [`RawDataStreams::register_lanes`].
+    ///
+    /// [`RawDataStreams::register_lanes`]: data::RawDataStreams::register_lanes
+    #[inline]
+    pub fn open_uni_labeled<'d>(
+        &'d self,
+        label: &'d str,
+        snd_wnd_size: u64,
+    ) -> OpenUniStream<'d, T> {
+        OpenUniStream {
+            inner: self,
+            snd_wnd_size,
+            label: Some(label),
+            waker: None,
         }
     }
 
@@ -63,6 +136,15 @@ where
         self.0.accept_uni()
     }
 
+    /// Accept a stream of the class, see [`StreamClassifier`]. The [`Writer`] is None for a
+    /// unidirectional stream.
+    ///
+    /// [`StreamClassifier`]: classify::StreamClassifier
+    #[inline]
+    pub fn accept_class(&self, class: classify::StreamClass, snd_wnd_size: u64) -> AcceptClass {
+        self.0.accept_class(class, snd_wnd_size)
+    }
+
     #[inline]
     pub fn listener(&self) -> listener::ArcListener {
         self.0.listener()
@@ -72,13 +154,45 @@ where
     pub fn premit_max_sid(&self, dir: Dir, val: u64) {
         self.0.premit_max_sid(dir, val);
     }
+
+    /// Limit how fast the local streams are opened, see [`RawDataStreams::set_stream_open_rate`].
+    ///
+    /// [`RawDataStreams::set_stream_open_rate`]: data::RawDataStreams::set_stream_open_rate
+    #[inline]
+    pub fn set_stream_open_rate(&self, rate: Option<StreamOpenRate>) {
+        self.0.set_stream_open_rate(rate);
+    }
+
+    /// Set the policy of accepting the streams created by peer, see [`IncomingStreamPolicy`].
+    ///
+    /// [`IncomingStreamPolicy`]: policy::IncomingStreamPolicy
+    #[inline]
+    pub fn set_incoming_stream_policy(&self, policy: policy::IncomingStreamPolicy) {
+        self.0.set_incoming_stream_policy(policy);
+    }
+
+    /// Classify the streams created by peer from now on, see [`StreamClassifier`].
+    ///
+    /// [`StreamClassifier`]: classify::StreamClassifier
+    #[inline]
+    pub fn set_stream_classifier(&self, classifier: classify::StreamClassifier) {
+        self.0.listener().set_classifier(classifier);
+    }
+
+    /// Bound the accept queue of the class, see [`ClassPolicy`].
+    ///
+    /// [`ClassPolicy`]: classify::ClassPolicy
+    #[inline]
+    pub fn set_class_policy(&self, class: classify::StreamClass, policy: classify::ClassPolicy) {
+        self.0.listener().set_class_policy(class, policy);
+    }
 }
 
 impl<T> ReceiveFrame<StreamCtlFrame> for DataStreams<T>
 where
     T: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
 {
-    type Output = ();
+    type Output = usize;
 
     fn recv_frame(&self, frame: &StreamCtlFrame) -> Result<Self::Output, Error> {
         self.0.recv_stream_control(frame)
@@ -102,6 +216,9 @@ where
 {
     inner: &'d data::RawDataStreams<T>,
     snd_wnd_size: u64,
+    label: Option<&'d str>,
+    // 排队等待时用的waker，被放弃时凭它退出队列
+    waker: Option<Waker>,
 }
 
 impl<T> Future for OpenBiStream<'_, T>
@@ -111,7 +228,23 @@ where
     type Output = Result<Option<(Reader, Writer)>, Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.inner.poll_open_bi_stream(cx, self.snd_wnd_size)
+        let this = self.get_mut();
+        let poll = this
+            .inner
+            .poll_open_bi_stream(cx, this.snd_wnd_size, this.label);
+        this.waker = poll.is_pending().then(|| cx.waker().clone());
+        poll
+    }
+}
+
+impl<T> Drop for OpenBiStream<'_, T>
+where
+    T: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            self.inner.cancel_open(Dir::Bi, &waker);
+        }
     }
 }
 
@@ -121,6 +254,9 @@ where
 {
     inner: &'d data::RawDataStreams<T>,
     snd_wnd_size: u64,
+    label: Option<&'d str>,
+    // 排队等待时用的waker，被放弃时凭它退出队列
+    waker: Option<Waker>,
 }
 
 impl<T> Future for OpenUniStream<'_, T>
@@ -130,9 +266,1500 @@ where
     type Output = Result<Option<Writer>, Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.inner.poll_open_uni_stream(cx, self.snd_wnd_size)
+        let this = self.get_mut();
+        let poll = this
+            .inner
+            .poll_open_uni_stream(cx, this.snd_wnd_size, this.label);
+        this.waker = poll.is_pending().then(|| cx.waker().clone());
+        poll
+    }
+}
+
+impl<T> Drop for OpenUniStream<'_, T>
+where
+    T: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            self.inner.cancel_open(Dir::Uni, &waker);
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::{io, sync::Arc};
+
+    use qbase::{
+        frame::StreamCtlFrame,
+        util::{ArcAsyncDeque, ArcTraceContext, Captured},
+        varint::VarInt,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_window_update_in_trace_context() {
+        use tokio::io::AsyncReadExt;
+
+        let (captured, _guard) = Captured::capture(tracing::Level::DEBUG);
+
+        let mut params = Parameters::default();
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1000));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let conn = ArcTraceContext::default();
+        let streams = {
+            let _enter = conn.enter();
+            DataStreams::new(Role::Server, &params, ctrl_frames.clone())
+        };
+        // 应用在连接创建之后才设置span
+        conn.set_span(tracing::info_span!("conn", id = 7));
+
+        // 读过窗口的一半，才会更新MAX_STREAM_DATA
+        let frame = StreamFrame::new(VarInt::from_u32(2).into(), 0, 600);
+        streams
+            .recv_frame(&(frame, Bytes::from(vec![0u8; 600])))
+            .unwrap();
+        let mut reader = streams.accept_uni().await.unwrap();
+        reader.set_trace_span({
+            let _enter = conn.enter();
+            tracing::info_span!("stream", sid = 2)
+        });
+        let mut buf = [0u8; 600];
+        reader.read_exact(&mut buf).await.unwrap();
+
+        assert!(matches!(
+            ctrl_frames.pop().await,
+            Some(StreamCtlFrame::MaxStreamData(_))
+        ));
+        let output = captured.output();
+        let line = output
+            .lines()
+            .find(|line| line.contains("update max stream data"))
+            .unwrap();
+        assert!(line.contains("conn{id=7}:stream{sid=2}"));
+        reader.stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_poll_flushed() {
+        use tokio::io::AsyncWriteExt;
+
+        use crate::send::FlushMode;
+
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.premit_max_sid(Dir::Uni, 1);
+        let mut writer = streams.open_uni(1000).await.unwrap().unwrap();
+        writer.write_all(b"hello").await.unwrap();
+
+        let streams = &streams;
+        let flushed =
+            |mode| std::future::poll_fn(move |cx| Poll::Ready(streams.poll_flushed(cx, mode)));
+        assert!(flushed(FlushMode::SentOnce).await.is_pending());
+
+        let mut buf = [0u8; 64];
+        let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(flushed(FlushMode::SentOnce).await.is_ready());
+        assert!(flushed(FlushMode::FullyAcked).await.is_pending());
+
+        // 判定丢失待重传的数据，不妨碍SentOnce
+        streams.may_loss_data(&frame);
+        assert!(flushed(FlushMode::SentOnce).await.is_ready());
+
+        let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        streams.on_data_acked(frame);
+        assert!(flushed(FlushMode::FullyAcked).await.is_ready());
+
+        writer.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_flushed() {
+        use tokio::io::AsyncWriteExt;
+
+        use crate::send::FlushMode;
+
+        let streams = Arc::new(DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        ));
+        streams.premit_max_sid(Dir::Uni, 1);
+        let mut writer = streams.open_uni(1000).await.unwrap().unwrap();
+        writer.write_all(b"hello").await.unwrap();
+
+        // 两个任务同时等待，后登记的不能顶掉先登记的
+        let waiters = [(); 2].map(|_| {
+            let streams = streams.clone();
+            tokio::spawn(async move {
+                std::future::poll_fn(|cx| streams.poll_flushed(cx, FlushMode::SentOnce)).await
+            })
+        });
+        tokio::task::yield_now().await;
+        assert!(waiters.iter().all(|waiter| !waiter.is_finished()));
+
+        let mut buf = [0u8; 64];
+        assert!(streams.try_read_data(&mut buf, usize::MAX).is_some());
+        for waiter in waiters {
+            waiter.await.unwrap().unwrap();
+        }
+
+        writer.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_incoming_stream_policy() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::MaxStreamsFrame;
+
+        use crate::streams::policy::IncomingStreamPolicy;
+
+        const SHED_CODE: u64 = 0x10;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(200));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Server, &params, ctrl_frames.clone());
+        streams.set_incoming_stream_policy(IncomingStreamPolicy {
+            max_in_flight: 32,
+            queue_depth: 8,
+            shed_code: SHED_CODE,
+            queue_timeout: Duration::from_millis(500),
+        });
+
+        // 客户端一口气创建200个双向流
+        for i in 0..200 {
+            let frame = StreamFrame::new(VarInt::from_u32(i * 4).into(), 0, 5);
+            streams
+                .recv_frame(&(frame, Bytes::from_static(b"hello")))
+                .unwrap();
+        }
+        assert_eq!(streams.listener().shed_streams(), 160);
+
+        // 让各个流的异步任务发出控制帧，然后取出所有的控制帧
+        let drain = || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let mut frames = Vec::new();
+            while let Some(frame) = ctrl_frames.pop().now_or_never() {
+                frames.push(frame.unwrap());
+            }
+            frames
+        };
+        // 被丢弃的流，都以SHED_CODE同时发送STOP_SENDING和RESET_STREAM
+        let count_shed = |frames: &[StreamCtlFrame]| {
+            let mut shed = 0;
+            for frame in frames {
+                match frame {
+                    StreamCtlFrame::StopSending(f) => {
+                        assert_eq!(f.app_err_code.into_inner(), SHED_CODE);
+                        shed += 1;
+                    }
+                    StreamCtlFrame::ResetStream(f) => {
+                        assert_eq!(f.app_error_code.into_inner(), SHED_CODE);
+                    }
+                    _ => {}
+                }
+            }
+            let others = frames
+                .iter()
+                .filter(|f| !matches!(f, StreamCtlFrame::MaxStreams(_)))
+                .count();
+            assert_eq!(shed * 2, others);
+            shed
+        };
+        let max_streams = |frames: &[StreamCtlFrame]| {
+            frames.iter().rev().find_map(|frame| match frame {
+                StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(max)) => Some(max.into_inner()),
+                _ => None,
+            })
+        };
+
+        let frames = drain().await;
+        assert_eq!(count_shed(&frames), 160);
+        // 额度已足够对方打满策略，不再通告
+        assert_eq!(max_streams(&frames), None);
+
+        // 至多同时处理32个流，其余的排队
+        let mut accepted = Vec::new();
+        for _ in 0..32 {
+            accepted.push(streams.accept_bi(1000).await.unwrap());
+        }
+        assert!(streams.accept_bi(1000).now_or_never().is_none());
+        assert!(drain().await.is_empty());
+
+        // 处理完一个流，才能接受下一个，并给对方增加一个流的额度
+        let (reader, writer) = accepted.pop().unwrap();
+        reader.stop(0);
+        writer.reset(0);
+        accepted.push(streams.accept_bi(1000).await.unwrap());
+        let frames = drain().await;
+        assert_eq!(max_streams(&frames), Some(201));
+
+        // 剩下7个排队的流，超时未被接受，也被丢弃
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(streams.listener().shed_streams(), 167);
+        let frames = drain().await;
+        assert_eq!(count_shed(&frames), 7);
+        assert_eq!(max_streams(&frames), Some(208));
+        assert!(streams.accept_bi(1000).now_or_never().is_none());
+
+        // 连接始终健康，后续的流照常被接受
+        let (reader, writer) = accepted.pop().unwrap();
+        reader.stop(0);
+        writer.reset(0);
+        let frame = StreamFrame::new(VarInt::from_u32(200 * 4).into(), 0, 5);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(b"hello")))
+            .unwrap();
+        accepted.push(streams.accept_bi(1000).await.unwrap());
+        assert_eq!(accepted.len(), 32);
+
+        for (reader, writer) in accepted {
+            reader.stop(0);
+            writer.reset(0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shedding() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::MaxStreamsFrame;
+        use tokio::io::AsyncReadExt;
+
+        use crate::streams::policy::IncomingStreamPolicy;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(8));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Server, &params, ctrl_frames.clone());
+        streams.set_incoming_stream_policy(IncomingStreamPolicy {
+            max_in_flight: 4,
+            queue_depth: 4,
+            shed_code: 0x10,
+            queue_timeout: Duration::from_secs(10),
+        });
+        let recv = |i: u32, offset: u64, data: &'static [u8]| {
+            let frame = StreamFrame::new(VarInt::from_u32(i * 4).into(), offset, data.len());
+            streams
+                .recv_frame(&(frame, Bytes::from_static(data)))
+                .unwrap();
+        };
+        let max_streams = || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let mut max_streams = None;
+            while let Some(frame) = ctrl_frames.pop().now_or_never() {
+                if let StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(max)) = frame.unwrap() {
+                    max_streams = Some(max.into_inner());
+                }
+            }
+            max_streams
+        };
+
+        for i in 0..6 {
+            recv(i, 0, b"hello");
+        }
+        let (mut reader, writer) = streams.accept_bi(1000).await.unwrap();
+        let (done_reader, done_writer) = streams.accept_bi(1000).await.unwrap();
+
+        // 过载时，排队的4个流和新来的流都被丢弃，处理完的流也不再换来额度
+        streams.listener().set_shedding(true);
+        assert_eq!(streams.listener().shed_streams(), 4);
+        done_reader.stop(0);
+        done_writer.reset(0);
+        recv(6, 0, b"hello");
+        assert_eq!(streams.listener().shed_streams(), 5);
+        assert!(streams.accept_bi(1000).now_or_never().is_none());
+        assert_eq!(max_streams().await, None);
+
+        // 已接受的流不受影响
+        recv(0, 5, b"world");
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"helloworld");
+
+        // 恢复之后，补上期间释放的额度
+        streams.listener().set_shedding(false);
+        assert_eq!(max_streams().await, Some(14));
+
+        reader.stop(0);
+        writer.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_extend_max_streams() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::MaxStreamsFrame;
+        use tokio::io::AsyncReadExt;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_uni(VarInt::from_u32(2));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Server, &params, ctrl_frames.clone());
+        let max_streams = || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let mut frames = Vec::new();
+            while let Some(frame) = ctrl_frames.pop().now_or_never() {
+                if let StreamCtlFrame::MaxStreams(MaxStreamsFrame::Uni(max)) = frame.unwrap() {
+                    frames.push(max.into_inner());
+                }
+            }
+            frames
+        };
+        let recv = |i: u32| {
+            let mut frame = StreamFrame::new(VarInt::from_u32(i * 4 + 2).into(), 0, 5);
+            frame.set_eos_flag(true);
+            streams
+                .recv_frame(&(frame, Bytes::from_static(b"hello")))
+                .unwrap();
+        };
+
+        // 未接受的流和读取中的流都占着额度
+        recv(0);
+        recv(1);
+        let mut reader = streams.accept_uni().await.unwrap();
+        assert_eq!(max_streams().await, Vec::<u64>::new());
+
+        // 读完并释放一个流，对方就可以再新建一个
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        drop(reader);
+        assert_eq!(max_streams().await, vec![3]);
+
+        // 窗口调大，立即补上额度
+        streams.set_remote_stream_window(Dir::Uni, 4);
+        assert_eq!(max_streams().await, vec![5]);
+
+        let mut reader = streams.accept_uni().await.unwrap();
+        reader.read_to_end(&mut buf).await.unwrap();
+        drop(reader);
+        assert_eq!(max_streams().await, vec![6]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_length_stream_frame_opens_stream() {
+        use tokio::io::AsyncReadExt;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1000));
+        let streams = DataStreams::new(
+            Role::Server,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+
+        // 不带FIN的空帧，也宣告了流的存在，跳过的流一并创建
+        let frame = StreamFrame::new(VarInt::from_u32(6).into(), 0, 0);
+        assert_eq!(streams.recv_frame(&(frame, Bytes::new())).unwrap(), 0);
+        let first = streams.accept_uni().await.unwrap();
+        let mut second = streams.accept_uni().await.unwrap();
+
+        let frame = StreamFrame::new(VarInt::from_u32(6).into(), 0, 5);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(b"hello")))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(second.read(&mut buf).await.unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+
+        first.stop(0);
+        second.stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_zero_length_stream_frame_at_high_offset() {
+        use qbase::error::ErrorKind;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1000));
+        let streams = DataStreams::new(
+            Role::Server,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        let empty = |offset: u64, fin: bool| {
+            let mut frame = StreamFrame::new(VarInt::from_u32(2).into(), offset, 0);
+            frame.set_eos_flag(fin);
+            streams.recv_frame(&(frame, Bytes::new()))
+        };
+
+        // 空帧的offset同样受流量控制，恰到上限是合法的
+        assert_eq!(empty(1000, false).unwrap(), 0);
+        assert_eq!(
+            empty(1001, false).unwrap_err().kind(),
+            ErrorKind::FlowControl
+        );
+        assert_eq!(
+            empty(1001, true).unwrap_err().kind(),
+            ErrorKind::FlowControl
+        );
+        // 空帧的offset也算作已收到的最大偏移，FIN不能声明比它更小的final size
+        assert_eq!(empty(500, true).unwrap_err().kind(), ErrorKind::FinalSize);
+        assert_eq!(empty(1000, true).unwrap(), 0);
+        assert_eq!(empty(999, false).unwrap(), 0);
+        assert_eq!(empty(1000, false).unwrap(), 0);
+
+        streams.accept_uni().await.unwrap().stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_stop_codes() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::{ResetStreamFrame, StopSendingFrame};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{recv::StreamReset, send::StreamStopped};
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(10));
+        params.set_initial_max_stream_data_bidi_remote(VarInt::from_u32(1000));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Server, &params, ctrl_frames.clone());
+        let frame = StreamFrame::new(VarInt::from_u32(0).into(), 0, 5);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(b"hello")))
+            .unwrap();
+        let (mut reader, mut writer) = streams.accept_bi(1000).await.unwrap();
+        let downcast = |error: io::Error| {
+            assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+            error.into_inner().unwrap()
+        };
+
+        // 对方重置了流，读端的错误中带有RESET_STREAM的错误码，再读也一样
+        streams
+            .recv_frame(&StreamCtlFrame::ResetStream(ResetStreamFrame {
+                stream_id: VarInt::from_u32(0).into(),
+                app_error_code: VarInt::from_u32(42),
+                final_size: VarInt::from_u32(5),
+            }))
+            .unwrap();
+        for _ in 0..2 {
+            let error = downcast(reader.read(&mut [0u8; 8]).await.unwrap_err());
+            let reset = error.downcast_ref::<StreamReset>().unwrap();
+            assert_eq!(reset.error_code, 42);
+        }
+
+        // 对方要求停止发送，以同样的错误码重置，写端的错误中带有该错误码
+        streams
+            .recv_frame(&StreamCtlFrame::StopSending(StopSendingFrame {
+                stream_id: VarInt::from_u32(0).into(),
+                app_err_code: VarInt::from_u32(7),
+            }))
+            .unwrap();
+        let frame = ctrl_frames.pop().now_or_never().unwrap().unwrap();
+        assert!(
+            matches!(frame, StreamCtlFrame::ResetStream(f) if f.app_error_code.into_inner() == 7)
+        );
+        let error = downcast(writer.write(b"world").await.unwrap_err());
+        let stopped = error.downcast_ref::<StreamStopped>().unwrap();
+        assert_eq!(stopped.error_code, 7);
+
+        // 应用层重置流，RESET_STREAM帧带有给定的错误码
+        let frame = StreamFrame::new(VarInt::from_u32(4).into(), 0, 5);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(b"hello")))
+            .unwrap();
+        let (reader, writer) = streams.accept_bi(1000).await.unwrap();
+        reader.stop(3);
+        writer.reset(9);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let mut codes = Vec::new();
+        while let Some(frame) = ctrl_frames.pop().now_or_never() {
+            match frame.unwrap() {
+                StreamCtlFrame::StopSending(f) => codes.push(("stop", f.app_err_code.into_inner())),
+                StreamCtlFrame::ResetStream(f) => {
+                    codes.push(("reset", f.app_error_code.into_inner()))
+                }
+                _ => {}
+            }
+        }
+        codes.sort();
+        assert_eq!(codes, [("reset", 9), ("stop", 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_frames() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::{MaxStreamDataFrame, StreamDataBlockedFrame, StreamsBlockedFrame};
+        use tokio::io::AsyncWriteExt;
+
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Client, &Parameters::default(), ctrl_frames.clone());
+        let blocked = || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            std::iter::from_fn(|| ctrl_frames.pop().now_or_never().flatten())
+                .filter(|frame| {
+                    matches!(
+                        frame,
+                        StreamCtlFrame::StreamsBlocked(_) | StreamCtlFrame::StreamDataBlocked(_)
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // 受限于MAX_STREAMS，打不开流，反复尝试也只告知一次
+        streams.premit_max_sid(Dir::Uni, 0);
+        let mut writer = streams.open_uni(100).await.unwrap().unwrap();
+        for _ in 0..3 {
+            assert!(streams.open_uni(100).now_or_never().is_none());
+        }
+        assert_eq!(
+            blocked().await,
+            [StreamCtlFrame::StreamsBlocked(StreamsBlockedFrame::Uni(
+                VarInt::from_u32(2).into()
+            ))]
+        );
+        streams.premit_max_sid(Dir::Uni, 1);
+        let other = streams.open_uni(100).await.unwrap().unwrap();
+        assert!(streams.open_uni(100).now_or_never().is_none());
+        assert_eq!(
+            blocked().await,
+            [StreamCtlFrame::StreamsBlocked(StreamsBlockedFrame::Uni(
+                VarInt::from_u32(6).into()
+            ))]
+        );
+
+        // 窗口内的数据尚未发出时，不算受限于MAX_STREAM_DATA
+        let mut buf = [0u8; 200];
+        writer.write_all(&[0u8; 100]).await.unwrap();
+        assert!(writer.write(&[0u8; 10]).now_or_never().is_none());
+        assert!(blocked().await.is_empty());
+
+        // 每个窗口只告知一次
+        for max_stream_data in [100u32, 200] {
+            streams.try_read_data(&mut buf, usize::MAX).unwrap();
+            assert!(writer.write(&[0u8; 10]).now_or_never().is_none());
+            assert!(streams.try_read_data(&mut buf, usize::MAX).is_none());
+            assert_eq!(
+                blocked().await,
+                [StreamCtlFrame::StreamDataBlocked(StreamDataBlockedFrame {
+                    stream_id: VarInt::from_u32(2).into(),
+                    maximum_stream_data: VarInt::from_u32(max_stream_data),
+                })]
+            );
+
+            streams
+                .recv_frame(&StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                    stream_id: VarInt::from_u32(2).into(),
+                    max_stream_data: VarInt::from_u32(max_stream_data + 100),
+                }))
+                .unwrap();
+            writer.write_all(&[0u8; 100]).await.unwrap();
+        }
+
+        writer.reset(0);
+        other.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_flow_control_stall() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use qbase::frame::{MaxStreamDataFrame, StreamDataBlockedFrame};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::streams::data::FlowControlStallSuspected;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_uni(VarInt::from_u32(1));
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(100));
+        let streams = DataStreams::new(
+            Role::Client,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+
+        // 我方的流写满了对方的窗口，数据都已发出，还要再写
+        streams.premit_max_sid(Dir::Uni, 1);
+        let mut writer = streams.open_uni(100).await.unwrap().unwrap();
+        writer.write_all(&[0u8; 100]).await.unwrap();
+        let mut buf = [0u8; 200];
+        streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(writer.write(&[0u8; 10]).now_or_never().is_none());
+
+        // 对方的流写满了我方的窗口，应用却不读，对方随之告知受限
+        let sid = VarInt::from_u32(3);
+        let frame = StreamFrame::new(sid.into(), 0, 100);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(&[0u8; 100])))
+            .unwrap();
+        streams
+            .recv_frame(&StreamCtlFrame::StreamDataBlocked(StreamDataBlockedFrame {
+                stream_id: sid.into(),
+                maximum_stream_data: VarInt::from_u32(100),
+            }))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            streams.flow_control_stall(Duration::from_millis(40)),
+            Some(FlowControlStallSuspected {
+                blocked: vec![2],
+                full: vec![3],
+            })
+        );
+        assert_eq!(streams.flow_control_stall(Duration::from_secs(5)), None);
+        let listing = streams.introspect();
+        let send = listing[0].send.clone().unwrap();
+        assert!(send.blocked.unwrap() >= Duration::from_millis(40));
+        let recv = listing[1].recv.clone().unwrap();
+        assert!(recv.peer_blocked.unwrap() >= Duration::from_millis(40));
+
+        // 读得慢但仍在读，窗口随之更新，就不是死锁
+        let mut reader = streams.accept_uni().await.unwrap();
+        reader.read_exact(&mut buf[..60]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(streams.flow_control_stall(Duration::from_millis(40)), None);
+        let recv = streams.introspect()[1].recv.clone().unwrap();
+        assert_eq!(recv.peer_blocked, None);
+        assert!(recv.peer_blocked_total >= Duration::from_millis(40));
+
+        // 对方更新了窗口，也不再受限
+        streams
+            .recv_frame(&StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                stream_id: VarInt::from_u32(2).into(),
+                max_stream_data: VarInt::from_u32(200),
+            }))
+            .unwrap();
+        let send = streams.introspect()[0].send.clone().unwrap();
+        assert_eq!(send.blocked, None);
+        assert!(send.blocked_total >= Duration::from_millis(40));
+
+        writer.reset(0);
+        reader.stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_try_open() {
+        use std::time::Duration;
+
+        use qbase::{frame::MaxStreamsFrame, streamid::StreamId};
+
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Client, &Parameters::default(), ctrl_frames.clone());
+        let sid = |id: u32| StreamId::from(VarInt::from_u32(id));
+        let open = |streams: DataStreams<_>| async move {
+            let (reader, writer) = streams.open_bi(100).await.unwrap().unwrap();
+            let sid = writer.stream_id();
+            reader.stop(0);
+            writer.reset(0);
+            sid
+        };
+
+        streams.premit_max_sid(Dir::Bi, 0);
+        let (reader, writer) = streams.try_open_bi(100).unwrap().unwrap();
+        assert_eq!(writer.stream_id(), sid(0));
+        reader.stop(0);
+        writer.reset(0);
+        // 额度用完，立即返回None，不占用流ID，也不排队
+        for _ in 0..3 {
+            assert!(streams.try_open_bi(100).unwrap().is_none());
+        }
+
+        // 排队等待的打开，对方提高MAX_STREAMS后被唤醒，放弃的尝试没有占用流ID
+        let waiting = tokio::spawn(open(streams.clone()));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        // 额度先给排队等待的
+        streams
+            .recv_frame(&StreamCtlFrame::MaxStreams(MaxStreamsFrame::Bi(
+                VarInt::from_u32(1),
+            )))
+            .unwrap();
+        assert!(streams.try_open_bi(100).unwrap().is_none());
+        let waited = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("the waiting open is not woken by MAX_STREAMS")
+            .unwrap();
+        assert_eq!(waited, sid(4));
+
+        // 放弃等待的打开，也不占用流ID
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), open(streams.clone()));
+        assert!(abandoned.await.is_err());
+        streams.premit_max_sid(Dir::Bi, 2);
+        let (reader, writer) = streams.try_open_bi(100).unwrap().unwrap();
+        assert_eq!(writer.stream_id(), sid(8));
+        reader.stop(0);
+        writer.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_final_size_of_stop_sending() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use tokio::io::AsyncWriteExt;
+
+        let find = |frames: &ArcAsyncDeque<StreamCtlFrame>,
+                    f: fn(StreamCtlFrame) -> Option<StreamCtlFrame>| {
+            std::iter::from_fn(|| frames.pop().now_or_never().flatten())
+                .find_map(f)
+                .unwrap()
+        };
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(10));
+        params.set_initial_max_stream_data_bidi_remote(VarInt::from_u32(4000));
+        let client_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let server_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let client = DataStreams::new(Role::Client, &params, client_frames.clone());
+        let server = DataStreams::new(Role::Server, &params, server_frames.clone());
+
+        client.premit_max_sid(Dir::Bi, 1);
+        let (client_reader, mut client_writer) = client.open_bi(4000).await.unwrap().unwrap();
+        client_writer.write_all(&[0u8; 3000]).await.unwrap();
+
+        // 发出两个帧，第二个丢了；剩下的数据还没来得及发
+        let mut buf = [0u8; 500];
+        let (frame, written, fresh) = client.try_read_data(&mut buf, usize::MAX).unwrap();
+        let data = Bytes::copy_from_slice(&buf[written - frame.len()..written]);
+        let (_lost, _, lost_fresh) = client.try_read_data(&mut buf, usize::MAX).unwrap();
+        let consumed = (fresh + lost_fresh) as u64;
+        let rcvd = server.recv_frame(&(frame, data)).unwrap() as u64;
+        assert!(rcvd < consumed && consumed < 3000);
+
+        let (server_reader, server_writer) = server.accept_bi(1000).await.unwrap();
+        server_reader.stop(5);
+        server_writer.reset(0);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let stop_sending = find(&server_frames, |frame| {
+            matches!(frame, StreamCtlFrame::StopSending(_)).then_some(frame)
+        });
+        assert_eq!(client.recv_frame(&stop_sending).unwrap(), 0);
+
+        // 最终大小是已发出的数据，而不是写入的数据，错误码沿用STOP_SENDING的
+        let reset = find(&client_frames, |frame| {
+            matches!(frame, StreamCtlFrame::ResetStream(_)).then_some(frame)
+        });
+        let StreamCtlFrame::ResetStream(reset_frame) = &reset else {
+            unreachable!()
+        };
+        assert_eq!(reset_frame.final_size.into_inner(), consumed);
+        assert_eq!(reset_frame.app_error_code.into_inner(), 5);
+
+        // 接收端把从未收到的数据也计入连接级的流量控制，两端消耗的额度一致
+        let unrcvd = server.recv_frame(&reset).unwrap() as u64;
+        assert_eq!(rcvd + unrcvd, consumed);
+        client_reader.stop(0);
+    }
+
+    #[test]
+    fn test_late_frames_after_runtime_shutdown() {
+        use std::time::Duration;
+
+        use qbase::{
+            error::{Error as QuicError, ErrorKind},
+            frame::{BeFrame, StopSendingFrame},
+        };
+
+        use crate::streams::policy::IncomingStreamPolicy;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (streams, reader, writer) = runtime.block_on(async {
+            let mut params = Parameters::default();
+            params.set_initial_max_streams_bidi(VarInt::from_u32(10));
+            let streams = DataStreams::new(
+                Role::Server,
+                &params,
+                ArcAsyncDeque::<StreamCtlFrame>::new(),
+            );
+            streams.set_incoming_stream_policy(IncomingStreamPolicy {
+                max_in_flight: 2,
+                queue_depth: 2,
+                shed_code: 0,
+                queue_timeout: Duration::from_secs(1),
+            });
+            let frame = StreamFrame::new(VarInt::from_u32(0).into(), 0, 5);
+            streams
+                .recv_frame(&(frame, Bytes::from_static(b"hello")))
+                .unwrap();
+            let (reader, writer) = streams.accept_bi(1000).await.unwrap();
+            (streams, reader, writer)
+        });
+        drop(runtime);
+
+        // 运行时已关闭，迟到的帧仍会创建流、触发控制帧，都不能panic
+        for sid in [4, 8, 12] {
+            let frame = StreamFrame::new(VarInt::from_u32(sid).into(), 0, 5);
+            streams
+                .recv_frame(&(frame, Bytes::from_static(b"hello")))
+                .unwrap();
+        }
+        let stop_sending = StreamCtlFrame::StopSending(StopSendingFrame {
+            stream_id: VarInt::from_u32(4).into(),
+            app_err_code: VarInt::from_u32(0),
+        });
+        streams.recv_frame(&stop_sending).unwrap();
+
+        reader.stop(0);
+        writer.reset(0);
+        let frame_type = stop_sending.frame_type();
+        streams.on_conn_error(&QuicError::new(ErrorKind::Internal, frame_type, "bye"));
+        drop(streams);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_writer_records() {
+        use futures::FutureExt;
+        use qbase::frame::MaxStreamDataFrame;
+        use tokio::io::AsyncReadExt;
+
+        const PRODUCERS: u8 = 8;
+        const RECORDS: u32 = 200;
+        const RECORD_LEN: usize = 64;
+        // 发送窗口只容得下几条记录，写任务要反复等待窗口更新
+        const WINDOW: u64 = RECORD_LEN as u64 * 3 + 7;
+
+        let client = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        let mut params = Parameters::default();
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1 << 20));
+        let server = DataStreams::new(
+            Role::Server,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+
+        client.premit_max_sid(Dir::Uni, 1);
+        let writer = client
+            .open_uni(WINDOW)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_shared();
+        let producers = (0..PRODUCERS)
+            .map(|tag| {
+                let writer = writer.clone();
+                tokio::spawn(async move {
+                    for seq in 0..RECORDS {
+                        let mut record = vec![tag; RECORD_LEN];
+                        record[1..5].copy_from_slice(&seq.to_be_bytes());
+                        writer.write_message(&record).await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // 充当传输层，把数据从client搬到server，server读取之后再给client更新窗口
+        let total = PRODUCERS as usize * RECORDS as usize * RECORD_LEN;
+        let mut rcvd = Vec::with_capacity(total);
+        let mut buf = [0u8; 1500];
+        let mut reader = None;
+        while rcvd.len() < total {
+            let Some((frame, written, _)) = client.try_read_data(&mut buf, usize::MAX) else {
+                tokio::task::yield_now().await;
+                continue;
+            };
+            let body = Bytes::copy_from_slice(&buf[written - frame.len()..written]);
+            let sid = frame.id;
+            server.recv_frame(&(frame.clone(), body)).unwrap();
+            client.on_data_acked(frame);
+
+            let reader = match reader.as_mut() {
+                Some(reader) => reader,
+                None => reader.insert(server.accept_uni().await.unwrap()),
+            };
+            let mut chunk = [0u8; 4096];
+            if let Some(n) = reader.read(&mut chunk).now_or_never() {
+                rcvd.extend_from_slice(&chunk[..n.unwrap()]);
+                let max_stream_data = VarInt::from_u64(rcvd.len() as u64 + WINDOW).unwrap();
+                let update = StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
+                    stream_id: sid,
+                    max_stream_data,
+                });
+                client.recv_frame(&update).unwrap();
+            }
+        }
+        for producer in producers {
+            producer.await.unwrap();
+        }
+
+        // 每条记录完整、不与其他记录交错，同一任务写的记录保持顺序
+        let mut next_seq = [0u32; PRODUCERS as usize];
+        for record in rcvd.chunks(RECORD_LEN) {
+            let tag = record[0];
+            let seq = u32::from_be_bytes(record[1..5].try_into().unwrap());
+            assert!(record[5..].iter().all(|&b| b == tag));
+            assert_eq!(seq, next_seq[tag as usize]);
+            next_seq[tag as usize] += 1;
+        }
+        assert_eq!(next_seq, [RECORDS; PRODUCERS as usize]);
+
+        writer.reset(0);
+        writer.reset(0);
+        reader.unwrap().stop(0);
+    }
+
+    /// 先发出一批低紧急级别的批量数据，有损时丢掉其中一半，然后高紧急级别的流写入少量数据，
+    /// 返回高紧急级别的流发完数据所用的包数
+    async fn urgent_latency(loss: bool, retransmission_boost: u8) -> usize {
+        use tokio::io::AsyncWriteExt;
+
+        use crate::send::FlushMode;
+
+        const URGENT_LEN: usize = 4096;
+
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.set_retransmission_boost(retransmission_boost);
+        streams.premit_max_sid(Dir::Uni, 1);
+        let mut bulk = streams.open_uni(1 << 20).await.unwrap().unwrap();
+        bulk.set_urgency(7);
+        bulk.write_all(&[0; 64 * 1024]).await.unwrap();
+
+        let mut buf = [0u8; 1200];
+        let mut lost = vec![];
+        let mut bulk_sid = None;
+        // 停在批量流的令牌桶额度用完之前
+        for i in 0..21 {
+            let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+            bulk_sid = Some(frame.id);
+            if loss && i % 2 == 0 {
+                lost.push(frame);
+            } else {
+                streams.on_data_acked(frame);
+            }
+        }
+        for frame in &lost {
+            streams.may_loss_data(frame);
+        }
+
+        let mut urgent = streams.open_uni(1 << 20).await.unwrap().unwrap();
+        urgent.set_urgency(0);
+        urgent.write_all(&[1; URGENT_LEN]).await.unwrap();
+
+        let mut packets = 0;
+        let mut urgent_sent = 0;
+        while urgent_sent < URGENT_LEN {
+            let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+            packets += 1;
+            if Some(frame.id) != bulk_sid {
+                urgent_sent += frame.len();
+            }
+            streams.on_data_acked(frame);
+        }
+
+        // 批量数据的重传没有被饿死，最终都能发完
+        while let Some((frame, ..)) = streams.try_read_data(&mut buf, usize::MAX) {
+            streams.on_data_acked(frame);
+        }
+        let flushed =
+            std::future::poll_fn(|cx| Poll::Ready(streams.poll_flushed(cx, FlushMode::FullyAcked)));
+        assert!(flushed.await.is_ready());
+
+        bulk.reset(0);
+        urgent.reset(0);
+        packets
+    }
+
+    #[tokio::test]
+    async fn test_retransmission_priority() {
+        let baseline = urgent_latency(false, 1).await;
+        // 低紧急级别的流的重传，不再插队到高紧急级别的流之前
+        assert_eq!(urgent_latency(true, 1).await, baseline);
+        assert_eq!(urgent_latency(true, 0).await, baseline);
+        // 重传提升到与高紧急级别的流同级时，重传又会抢占，出现优先级反转
+        assert!(urgent_latency(true, 7).await > baseline);
+    }
+
+    #[tokio::test]
+    async fn test_open_labeled() {
+        use qbase::streamid::StreamId;
+
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        let sid = |id: u32| StreamId::from(VarInt::from_u32(id));
+        let firsts = streams.register_lanes(Dir::Uni, ["control", "encoder", "decoder"]);
+        assert_eq!(firsts, [sid(2), sid(6), sid(10)]);
+
+        // 各个标签的首个流交错着打开，额度不够时一起等着，额度到来后仍按注册的顺序分配
+        let mut opens = std::pin::pin!(futures::future::join4(
+            streams.open_uni_labeled("decoder", 1000),
+            streams.open_uni(1000),
+            streams.open_uni_labeled("encoder", 1000),
+            streams.open_uni_labeled("control", 1000),
+        ));
+        assert!(futures::poll!(&mut opens).is_pending());
+        streams.premit_max_sid(Dir::Uni, 3);
+        let (decoder, plain, encoder, control) = opens.await;
+        // 流未发送过数据，丢弃之前须先重置
+        let stream_id = |writer: Result<Option<Writer>, Error>| {
+            let writer = writer.unwrap().unwrap();
+            let sid = writer.stream_id();
+            writer.reset(0);
+            sid
+        };
+        assert_eq!(stream_id(control), sid(2));
+        assert_eq!(stream_id(encoder), sid(6));
+        assert_eq!(stream_id(decoder), sid(10));
+        assert_eq!(stream_id(plain), sid(14));
+
+        // 之后同一标签的流依次分配
+        streams.premit_max_sid(Dir::Uni, 4);
+        let control = streams.open_uni_labeled("control", 1000).await;
+        assert_eq!(stream_id(control), sid(18));
+    }
+
+    #[tokio::test]
+    async fn test_conn_id() {
+        use qbase::{cid::ConnectionId, streamid::StreamId};
+
+        let conn_id = ConnectionId::random_gen(8);
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(10));
+        let streams = DataStreams::with_conn_id(
+            Role::Server,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+            conn_id,
+        );
+
+        // 本地打开的流和对方打开的流，读写端都带着连接的id
+        streams.premit_max_sid(Dir::Bi, 1);
+        let (reader, writer) = streams.open_bi(1000).await.unwrap().unwrap();
+        assert_eq!(reader.stream_id(), StreamId::from(VarInt::from_u32(1)));
+        assert_eq!((reader.conn_id(), writer.conn_id()), (conn_id, conn_id));
+        reader.stop(0);
+        writer.reset(0);
+
+        let frame = StreamFrame::new(VarInt::from_u32(0).into(), 0, 0);
+        streams.recv_frame(&(frame, Bytes::new())).unwrap();
+        let (reader, writer) = streams.accept_bi(1000).await.unwrap();
+        assert_eq!(reader.stream_id(), StreamId::from(VarInt::from_u32(0)));
+        assert_eq!(reader.conn_id(), conn_id);
+        let writer = writer.into_shared();
+        assert_eq!(writer.conn_id(), conn_id);
+        reader.stop(0);
+        writer.reset(0);
+    }
+
+    #[tokio::test]
+    async fn test_stream_priority() {
+        use qbase::streamid::StreamId;
+        use tokio::io::AsyncWriteExt;
+
+        use crate::send::{Priority, DEFAULT_WEIGHT};
+
+        const LEN: usize = 10_000;
+
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.premit_max_sid(Dir::Uni, 2);
+        // 每个流写满自己的编号，从帧的数据就能看出来自哪个流
+        let mut writers = Vec::new();
+        for tag in 0..3u8 {
+            let mut writer = streams.open_uni(1 << 20).await.unwrap().unwrap();
+            writer.write_all(&[tag; LEN]).await.unwrap();
+            writers.push(writer);
+        }
+        // 先打开的流反而更不紧急
+        for (n, urgency) in [7, 3, 0].into_iter().enumerate() {
+            let sid = StreamId::from(VarInt::from_u32(2 + 4 * n as u32));
+            let priority = Priority {
+                urgency,
+                weight: DEFAULT_WEIGHT,
+            };
+            assert!(streams.set_priority(sid, priority));
+        }
+        assert_eq!(writers[0].priority().urgency, 7);
+
+        let mut buf = [0u8; 1200];
+        let mut tags = Vec::new();
+        while let Some((frame, written, _)) = streams.try_read_data(&mut buf, usize::MAX) {
+            let data = &buf[written - frame.len()..written];
+            assert!(data.iter().all(|&b| b == data[0]));
+            tags.extend_from_slice(data);
+            streams.on_data_acked(frame);
+        }
+        // 紧急级别之间严格有序，更紧急的流发完之前，不紧急的流一个字节也不发
+        let mut expected = Vec::new();
+        for tag in [2, 1, 0] {
+            expected.extend_from_slice(&[tag; LEN]);
+        }
+        assert_eq!(tags, expected);
+
+        // 同一紧急级别的流，按权重的比例轮流发送
+        streams.premit_max_sid(Dir::Uni, 4);
+        let mut light = streams.open_uni(1 << 20).await.unwrap().unwrap();
+        let mut heavy = streams.open_uni(1 << 20).await.unwrap().unwrap();
+        light.set_priority(Priority::default());
+        heavy.set_priority(Priority {
+            weight: DEFAULT_WEIGHT * 3,
+            ..Priority::default()
+        });
+        light.write_all(&[0; 64 * 1024]).await.unwrap();
+        heavy.write_all(&[1; 64 * 1024]).await.unwrap();
+
+        let mut sent = [0usize; 2];
+        while sent.iter().sum::<usize>() < 2 * 4 * 4096 {
+            let (frame, written, _) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+            sent[buf[written - 1] as usize] += frame.len();
+            streams.on_data_acked(frame);
+        }
+        assert_eq!(sent, [2 * 4096, 2 * 3 * 4096]);
+
+        for writer in writers.into_iter().chain([light, heavy]) {
+            writer.reset(0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_scheduler() {
+        use qbase::streamid::StreamId;
+        use tokio::io::AsyncWriteExt;
+
+        use super::scheduler::{Candidate, RoundRobin, StreamScheduler};
+        use crate::send::Priority;
+
+        const LEN: usize = 10_000;
+
+        // 按流打开的顺序，一个流发完再发下一个，无视优先级
+        #[derive(Debug, Default)]
+        struct Fifo;
+
+        impl StreamScheduler for Fifo {
+            fn schedule(&mut self, candidates: &[Candidate]) -> Vec<(StreamId, usize)> {
+                let mut order = candidates
+                    .iter()
+                    .map(|candidate| (candidate.sid, usize::MAX))
+                    .collect::<Vec<_>>();
+                order.sort_unstable();
+                order
+            }
+
+            fn on_sent(&mut self, _sid: StreamId, _granted: usize, _sent: usize) {}
+        }
+
+        let streams = DataStreams::new(
+            Role::Client,
+            &Parameters::default(),
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.set_stream_scheduler(Box::new(Fifo));
+        streams.premit_max_sid(Dir::Uni, 2);
+        let mut writers = Vec::new();
+        for tag in 0..3u8 {
+            let mut writer = streams.open_uni(1 << 20).await.unwrap().unwrap();
+            // 后打开的流更紧急，但调度器说了算
+            writer.set_priority(Priority {
+                urgency: 7 - tag * 3,
+                ..Priority::default()
+            });
+            writer.write_all(&[tag; LEN]).await.unwrap();
+            writers.push(writer);
+        }
+
+        let mut buf = [0u8; 1200];
+        let mut tags = Vec::new();
+        while let Some((frame, written, _)) = streams.try_read_data(&mut buf, usize::MAX) {
+            let data = &buf[written - frame.len()..written];
+            assert!(data.iter().all(|&b| b == data[0]));
+            tags.extend_from_slice(data);
+            streams.on_data_acked(frame);
+        }
+        let mut expected = Vec::new();
+        for tag in [0, 1, 2] {
+            expected.extend_from_slice(&[tag; LEN]);
+        }
+        assert_eq!(tags, expected);
+
+        // 换回轮流发送，量子越小，流交错得越细
+        streams.set_stream_scheduler(Box::new(RoundRobin::with_quantum(512)));
+        for (tag, writer) in writers.iter_mut().enumerate().take(2) {
+            writer.set_priority(Priority::default());
+            writer.write_all(&[tag as u8; 4096]).await.unwrap();
+        }
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            let (frame, written, _) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+            frames.push((buf[written - 1], frame.len()));
+            streams.on_data_acked(frame);
+        }
+        assert_eq!(frames, [(0, 512), (1, 512), (0, 512), (1, 512)]);
+
+        for writer in writers {
+            writer.reset(0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_introspection() {
+        use futures::FutureExt;
+        use tokio::io::AsyncWriteExt;
+
+        use crate::{recv::RecvState, send::SendState};
+
+        const SENTINEL: &[u8] = b"SENTINEL-PAYLOAD";
+        let no_leak = |debug: String| {
+            assert!(!debug.contains("SENTINEL"));
+            // 也不能以字节数组的形式泄露
+            assert!(!debug.contains("83, 69, 78, 84"));
+        };
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_uni(VarInt::from_u32(1));
+        params.set_initial_max_stream_data_uni(VarInt::from_u32(1000));
+        let streams = DataStreams::new(
+            Role::Client,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.premit_max_sid(Dir::Uni, 1);
+        let mut writer = streams.open_uni(1000).await.unwrap().unwrap();
+        writer.write_all(SENTINEL).await.unwrap();
+
+        let send = |streams: &DataStreams<ArcAsyncDeque<StreamCtlFrame>>| {
+            streams.introspect().into_iter().find_map(|s| s.send)
+        };
+        let len = SENTINEL.len() as u64;
+        let snapshot = send(&streams).unwrap();
+        assert_eq!(snapshot.state, SendState::Ready);
+        assert_eq!(
+            (snapshot.written, snapshot.sent, snapshot.acked),
+            (len, 0, 0)
+        );
+        assert_eq!(snapshot.buffered, len);
+        assert_eq!(snapshot.max_data, Some(1000));
+
+        let mut buf = [0u8; 64];
+        let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        let snapshot = send(&streams).unwrap();
+        assert_eq!(snapshot.state, SendState::Sending);
+        assert_eq!((snapshot.sent, snapshot.acked), (len, 0));
+
+        streams.on_data_acked(frame);
+        let snapshot = send(&streams).unwrap();
+        assert_eq!((snapshot.acked, snapshot.buffered), (len, 0));
+
+        assert!(writer.shutdown().now_or_never().is_none());
+        let snapshot = send(&streams).unwrap();
+        assert_eq!(snapshot.state, SendState::DataSent);
+        assert_eq!(snapshot.final_size, Some(len));
+        assert!(!snapshot.fin_sent && snapshot.shutdown_waker);
+
+        let (frame, ..) = streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(frame.is_fin());
+        let snapshot = send(&streams).unwrap();
+        assert!(snapshot.fin_sent && !snapshot.fin_acked);
+        // 确认了fin之后，发送端的生命周期结束，不再列出
+        streams.on_data_acked(frame);
+        assert_eq!(send(&streams), None);
+        no_leak(format!("{writer:?}"));
+
+        // 对方的单向流，先收到后半段的数据
+        let sid = VarInt::from_u32(3);
+        let frame = StreamFrame::new(sid.into(), 4, SENTINEL.len() - 4);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(&SENTINEL[4..])))
+            .unwrap();
+        let listing = streams.introspect();
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].id, 3);
+        let snapshot = listing[0].recv.clone().unwrap();
+        assert_eq!(snapshot.state, RecvState::Recv);
+        assert_eq!((snapshot.read, snapshot.contiguous), (0, 0));
+        assert_eq!((snapshot.received, snapshot.buffered), (len, len - 4));
+        no_leak(format!("{streams:?}"));
+
+        let frame = StreamFrame::new(sid.into(), 0, 4);
+        streams
+            .recv_frame(&(frame, Bytes::from_static(&SENTINEL[..4])))
+            .unwrap();
+        let snapshot = streams.introspect()[0].recv.clone().unwrap();
+        assert_eq!((snapshot.contiguous, snapshot.buffered), (len, len));
+        no_leak(format!("{streams:?}"));
+
+        let mut frame = StreamFrame::new(sid.into(), len, 0);
+        frame.set_eos_flag(true);
+        streams.recv_frame(&(frame, Bytes::new())).unwrap();
+        let snapshot = streams.introspect()[0].recv.clone().unwrap();
+        // 数据已完整，随fin直接进入DataRcvd
+        assert_eq!(snapshot.state, RecvState::DataRcvd);
+        assert_eq!(snapshot.final_size, Some(len));
+
+        let reader = streams.accept_uni().await.unwrap();
+        no_leak(format!("{reader:?}"));
+        reader.stop(0);
+    }
+
+    #[tokio::test]
+    async fn test_stream_classifier() {
+        use std::time::Duration;
+
+        use futures::FutureExt;
+        use tokio::io::AsyncReadExt;
+
+        use qbase::streamid::StreamId;
+
+        use crate::streams::classify::{ClassPolicy, StreamClass, StreamClassifier};
+
+        const RPC: StreamClass = StreamClass(1);
+        const UPLOAD: StreamClass = StreamClass(2);
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(20));
+        params.set_initial_max_streams_uni(VarInt::from_u32(20));
+        let ctrl_frames = ArcAsyncDeque::<StreamCtlFrame>::new();
+        let streams = DataStreams::new(Role::Server, &params, ctrl_frames.clone());
+        streams.set_stream_classifier(StreamClassifier::new(4, |preview| match preview.data() {
+            b"RPC!" => RPC,
+            b"UPLD" => UPLOAD,
+            _ => StreamClass::DEFAULT,
+        }));
+        streams.set_class_policy(
+            RPC,
+            ClassPolicy {
+                backlog: 2,
+                shed_code: 0x11,
+            },
+        );
+        streams.set_class_policy(
+            UPLOAD,
+            ClassPolicy {
+                backlog: 3,
+                shed_code: 0x22,
+            },
+        );
+
+        let recv = |sid: u32, offset: u64, data: &'static [u8], fin: bool| {
+            let mut frame = StreamFrame::new(VarInt::from_u32(sid).into(), offset, data.len());
+            frame.set_eos_flag(fin);
+            streams
+                .recv_frame(&(frame, Bytes::from_static(data)))
+                .unwrap();
+        };
+        recv(0, 0, b"RPC!a", false);
+        // 开头的数据不够预览，等凑够了才分类
+        recv(4, 0, b"UP", false);
+        assert!(streams.accept_class(UPLOAD, 1000).now_or_never().is_none());
+        recv(4, 2, b"LD", false);
+        recv(8, 0, b"hello", false);
+        // 数据比预览的长度短，但流已经结束了，也会分类
+        recv(2, 0, b"x", true);
+        for sid in [12, 16] {
+            recv(sid, 0, b"RPC!b", false);
+        }
+        for sid in [20, 24, 28] {
+            recv(sid, 0, b"UPLD", false);
+        }
+        // 每一类各自超出了一个
+        assert_eq!(streams.listener().shed_streams(), 2);
+
+        let mut buf = [0u8; 16];
+        let mut accepted = Vec::new();
+        let (mut reader, writer) = streams.accept_class(RPC, 1000).await.unwrap();
+        // 分类时预览的数据没有被消费
+        let n = reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"RPC!a");
+        accepted.push((reader, writer));
+        accepted.push(streams.accept_class(RPC, 1000).await.unwrap());
+        assert!(streams.accept_class(RPC, 1000).now_or_never().is_none());
+
+        for _ in 0..3 {
+            accepted.push(streams.accept_class(UPLOAD, 1000).await.unwrap());
+        }
+        assert!(streams.accept_class(UPLOAD, 1000).now_or_never().is_none());
+        assert!(accepted.iter().all(|(_, writer)| writer.is_some()));
+
+        // 没有匹配的流，照常从默认的队列接受
+        let (mut reader, writer) = streams.accept_bi(1000).await.unwrap();
+        let n = reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        accepted.push((reader, Some(writer)));
+        let mut reader = streams.accept_uni().await.unwrap();
+        let n = reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"x");
+        accepted.push((reader, None));
+        for (reader, writer) in accepted {
+            reader.stop(0);
+            if let Some(writer) = writer {
+                writer.reset(0);
+            }
+        }
+
+        // 被丢弃的流，以各自类别的错误码发送STOP_SENDING
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let mut codes = Vec::new();
+        while let Some(frame) = ctrl_frames.pop().now_or_never() {
+            if let StreamCtlFrame::StopSending(f) = frame.unwrap() {
+                codes.push((f.stream_id, f.app_err_code.into_inner()));
+            }
+        }
+        codes.sort();
+        assert_eq!(
+            codes,
+            [
+                (StreamId::from(VarInt::from_u32(16)), 0x11),
+                (StreamId::from(VarInt::from_u32(28)), 0x22)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_eager() {
+        use futures::FutureExt;
+        use tokio::io::AsyncWriteExt;
+
+        let mut params = Parameters::default();
+        params.set_initial_max_streams_bidi(VarInt::from_u32(10));
+        params.set_initial_max_stream_data_bidi_remote(VarInt::from_u32(4000));
+        let streams = DataStreams::new(
+            Role::Client,
+            &params,
+            ArcAsyncDeque::<StreamCtlFrame>::new(),
+        );
+        streams.premit_max_sid(Dir::Bi, 1);
+        let (reader, mut writer) = streams.open_bi(4000).await.unwrap().unwrap();
+        let mut buf = [0u8; 500];
+
+        // 对方不支持IMMEDIATE_ACK帧，flush_eager同flush一样
+        writer.write_all(&[0u8; 100]).await.unwrap();
+        assert!(writer.flush_eager().now_or_never().is_none());
+        streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(!streams.take_immediate_ack());
+
+        // 只有带着flush_eager位置的最后一个包才请求立即确认
+        streams.enable_immediate_ack();
+        writer.write_all(&[0u8; 900]).await.unwrap();
+        assert!(writer.flush_eager().now_or_never().is_none());
+        streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(!streams.take_immediate_ack());
+        streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(streams.take_immediate_ack());
+        assert!(!streams.take_immediate_ack());
+
+        // 之后写入的数据照常发送
+        writer.write_all(&[0u8; 100]).await.unwrap();
+        streams.try_read_data(&mut buf, usize::MAX).unwrap();
+        assert!(!streams.take_immediate_ack());
+
+        reader.stop(0);
+        writer.reset(0);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, sync::Arc};
+
+use qbase::streamid::{Dir, StreamId};
+
+/// The class of a stream created by peer, which decides the accept queue the stream lands
+/// on, see [`StreamClassifier`].
+///
+/// The classes are defined by the application, [`StreamClass::DEFAULT`] is the queue of
+/// [`DataStreams::accept_bi`] and [`DataStreams::accept_uni`].
+///
+/// [`DataStreams::accept_bi`]: super::DataStreams::accept_bi
+/// [`DataStreams::accept_uni`]: super::DataStreams::accept_uni
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamClass(pub u32);
+
+impl StreamClass {
+    pub const DEFAULT: StreamClass = StreamClass(0);
+}
+
+/// What is known about a stream created by peer when it's classified.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamPreview<'a> {
+    sid: StreamId,
+    data: &'a [u8],
+    is_complete: bool,
+}
+
+impl<'a> StreamPreview<'a> {
+    pub(super) fn new(sid: StreamId, data: &'a [u8], is_complete: bool) -> Self {
+        Self {
+            sid,
+            data,
+            is_complete,
+        }
+    }
+
+    pub fn id(&self) -> StreamId {
+        self.sid
+    }
+
+    pub fn dir(&self) -> Dir {
+        self.sid.dir()
+    }
+
+    /// The first bytes of the stream, as many as the `peek_len` of the classifier, or
+    /// fewer if the stream is complete.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The stream won't receive more data, because all its data is received, or it's
+    /// reset by peer. The data may be shorter than the `peek_len` then.
+    pub fn is_complete(&self) -> bool {
+        self.is_complete
+    }
+}
+
+/// Classify the streams created by peer at the time they're accepted, so that different
+/// workloads can be accepted by different workers, see [`DataStreams::accept_class`].
+///
+/// A stream is classified once its first `peek_len` bytes are received contiguously, or it
+/// becomes complete before that. The classification runs within the processing of the
+/// received packets, it must be fast and must not block, the bytes are already buffered and
+/// are not consumed.
+///
+/// The streams waiting for the classification count as queued in the
+/// [`IncomingStreamPolicy`], and are shed if they're not classified and accepted within its
+/// `queue_timeout`.
+///
+/// [`DataStreams::accept_class`]: super::DataStreams::accept_class
+/// [`IncomingStreamPolicy`]: super::policy::IncomingStreamPolicy
+#[derive(Clone)]
+pub struct StreamClassifier {
+    peek_len: usize,
+    classify: Arc<dyn Fn(&StreamPreview) -> StreamClass + Send + Sync>,
+}
+
+impl StreamClassifier {
+    pub fn new(
+        peek_len: usize,
+        classify: impl Fn(&StreamPreview) -> StreamClass + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            peek_len,
+            classify: Arc::new(classify),
+        }
+    }
+
+    pub fn peek_len(&self) -> usize {
+        self.peek_len
+    }
+
+    pub(super) fn classify(&self, preview: &StreamPreview) -> StreamClass {
+        (self.classify)(preview)
+    }
+}
+
+impl fmt::Debug for StreamClassifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamClassifier")
+            .field("peek_len", &self.peek_len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The bound of the accept queue of a [`StreamClass`] other than the default one, the
+/// streams classified beyond `backlog` are shed with `shed_code`, independent of the other
+/// classes.
+///
+/// Shedding means sending STOP_SENDING, and RESET_STREAM as well for the bidirectional
+/// stream, like [`IncomingStreamPolicy`] does. The queued streams not accepted in time
+/// are shed with `shed_code` as well.
+///
+/// [`IncomingStreamPolicy`]: super::policy::IncomingStreamPolicy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassPolicy {
+    /// How many classified streams can wait to be accepted.
+    pub backlog: u64,
+    /// The application error code to shed the excess streams with.
+    pub shed_code: u64,
+}
@@ -145,29 +145,68 @@ mod recv {
 
     use bytes::{BufMut, Bytes};
     use qbase::{
-        error::Error,
-        frame::{CryptoFrame, ReceiveFrame},
+        error::{Error, ErrorKind},
+        frame::{CryptoFrame, FrameType, ReceiveFrame},
         varint::VARINT_MAX,
     };
     use tokio::io::{AsyncRead, ReadBuf};
 
+    use super::{CryptoLimits, CryptoRecvStats};
     use crate::recv::rcvbuf::RecvBuf;
 
     #[derive(Debug)]
     pub(super) struct Recver {
         rcvbuf: RecvBuf,
         read_waker: Option<Waker>,
+        pub(super) limits: CryptoLimits,
+        pub(super) stats: CryptoRecvStats,
     }
 
     impl Recver {
-        fn recv(&mut self, offset: u64, data: Bytes) {
-            assert!(offset + data.len() as u64 <= VARINT_MAX);
+        fn recv(&mut self, offset: u64, data: Bytes) -> Result<(), Error> {
+            let end = offset + data.len() as u64;
+            assert!(end <= VARINT_MAX);
+            self.stats.received += data.len() as u64;
+
+            // 先检查偏移，远在TLS读取位置之后的数据，不必缓存就可以断定超限
+            let exceeded = |reason: String| {
+                Err(Error::new(
+                    ErrorKind::CryptoBufferExceeded,
+                    FrameType::Crypto,
+                    reason,
+                ))
+            };
+            if end > self.limits.max_total {
+                return exceeded(format!(
+                    "CRYPTO data ends at {end}, beyond the total limit {}",
+                    self.limits.max_total
+                ));
+            }
+            let consumed = self.rcvbuf.offset();
+            if end > consumed + self.limits.max_offset_ahead {
+                return exceeded(format!(
+                    "CRYPTO data ends at {end}, {} bytes ahead of the consumed offset {consumed}",
+                    end - consumed
+                ));
+            }
+
             self.rcvbuf.recv(offset, data);
+            self.stats.largest_offset = self.stats.largest_offset.max(end);
+            let buffered = self.rcvbuf.buffered();
+            self.stats.peak_buffered = self.stats.peak_buffered.max(buffered);
+            if buffered > self.limits.max_buffered {
+                return exceeded(format!(
+                    "{buffered} bytes of CRYPTO data are buffered, beyond the limit {}",
+                    self.limits.max_buffered
+                ));
+            }
+
             if self.rcvbuf.is_readable() {
                 if let Some(waker) = self.read_waker.take() {
                     waker.wake()
                 }
             }
+            Ok(())
         }
 
         fn poll_read<T: BufMut>(
@@ -210,8 +249,7 @@ mod recv {
             self.0
                 .lock()
                 .unwrap()
-                .recv(frame.offset.into(), data.clone());
-            Ok(())
+                .recv(frame.offset.into(), data.clone())
         }
     }
 
@@ -219,6 +257,8 @@ mod recv {
         Arc::new(Mutex::new(Recver {
             rcvbuf: RecvBuf::default(),
             read_waker: None,
+            limits: CryptoLimits::default(),
+            stats: CryptoRecvStats::default(),
         }))
     }
 }
@@ -226,6 +266,44 @@ mod recv {
 pub use recv::{CryptoStreamIncoming, CryptoStreamReader};
 pub use send::{CryptoStreamOutgoing, CryptoStreamWriter};
 
+/// The limits of the data received in CRYPTO frames of an epoch, beyond which the
+/// connection is closed with CRYPTO_BUFFER_EXCEEDED, see
+/// [section 7.5](https://www.rfc-editor.org/rfc/rfc9000.html#name-cryptographic-message-buffe)
+/// of RFC 9000.
+///
+/// Without them, a peer could make us buffer an absurd "certificate chain" of megabytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoLimits {
+    /// The most bytes received but not consumed by TLS yet, including the out of order ones.
+    pub max_buffered: u64,
+    /// How far beyond the offset consumed by TLS the received data can end.
+    pub max_offset_ahead: u64,
+    /// How far the data of the epoch can end in total, a backstop for the whole handshake.
+    /// Raise it for the huge certificate chains, e.g. with post-quantum signatures.
+    pub max_total: u64,
+}
+
+impl Default for CryptoLimits {
+    fn default() -> Self {
+        Self {
+            max_buffered: 64 * 1024,
+            max_offset_ahead: 128 * 1024,
+            max_total: 256 * 1024,
+        }
+    }
+}
+
+/// The accounting of the data received in CRYPTO frames of an epoch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoRecvStats {
+    /// The bytes received in CRYPTO frames, including the duplicated ones.
+    pub received: u64,
+    /// The end of the data received, not counting the frames beyond the limits.
+    pub largest_offset: u64,
+    /// The most bytes buffered but not consumed by TLS at once.
+    pub peak_buffered: u64,
+}
+
 /// Crypto data stream
 #[derive(Debug, Clone)]
 pub struct CryptoStream {
@@ -256,17 +334,39 @@ impl CryptoStream {
     pub fn incoming(&self) -> recv::CryptoStreamIncoming {
         recv::CryptoStreamIncoming(self.recver.clone())
     }
+
+    /// Set the limits of the data received, it applies to the CRYPTO frames received
+    /// from now on.
+    pub fn set_recv_limits(&self, limits: CryptoLimits) {
+        self.recver.lock().unwrap().limits = limits;
+    }
+
+    pub fn recv_stats(&self) -> CryptoRecvStats {
+        self.recver.lock().unwrap().stats
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
     use qbase::{
+        error::ErrorKind,
         frame::{CryptoFrame, ReceiveFrame},
         varint::VarInt,
     };
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-    use super::CryptoStream;
+    use super::{CryptoLimits, CryptoStream};
+
+    fn crypto_frame(offset: u64, data: &[u8]) -> (CryptoFrame, Bytes) {
+        (
+            CryptoFrame {
+                offset: VarInt::from_u64(offset).unwrap(),
+                length: VarInt::try_from(data.len()).unwrap(),
+            },
+            Bytes::copy_from_slice(data),
+        )
+    }
 
     #[tokio::test]
     async fn test_read() {
@@ -291,4 +391,88 @@ mod tests {
         crypto_stream.reader().read_exact(&mut buf).await.unwrap();
         assert_eq!(&buf[..], b"hello world");
     }
+
+    #[test]
+    fn test_offset_beyond_limit() {
+        let crypto_stream = CryptoStream::new(0, 0);
+        crypto_stream.set_recv_limits(CryptoLimits {
+            max_buffered: 64 * 1024,
+            max_offset_ahead: 64 * 1024,
+            ..CryptoLimits::default()
+        });
+        let incoming = crypto_stream.incoming();
+        assert!(incoming.recv_frame(&crypto_frame(0, &[0; 1000])).is_ok());
+
+        let error = incoming
+            .recv_frame(&crypto_frame(1024 * 1024, &[0; 1000]))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+        // 超限的数据不会被缓存
+        let stats = crypto_stream.recv_stats();
+        assert_eq!(stats.received, 2000);
+        assert_eq!(stats.largest_offset, 1000);
+        assert_eq!(stats.peak_buffered, 1000);
+    }
+
+    #[test]
+    fn test_buffered_beyond_limit() {
+        let crypto_stream = CryptoStream::new(0, 0);
+        crypto_stream.set_recv_limits(CryptoLimits {
+            max_buffered: 4096,
+            ..CryptoLimits::default()
+        });
+        let incoming = crypto_stream.incoming();
+        // 缺少开头的数据，TLS无法消费，乱序的数据越积越多
+        for i in 1..4 {
+            assert!(incoming
+                .recv_frame(&crypto_frame(i * 1000, &[0; 1000]))
+                .is_ok());
+        }
+        let error = incoming
+            .recv_frame(&crypto_frame(4000, &[0; 1000]))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+    }
+
+    #[tokio::test]
+    async fn test_large_chain() {
+        const CHAIN_LEN: usize = 200 * 1024;
+        let chain = (0..CHAIN_LEN).map(|i| i as u8).collect::<Vec<_>>();
+        let receive = |crypto_stream: CryptoStream| {
+            let chain = chain.clone();
+            async move {
+                let incoming = crypto_stream.incoming();
+                let mut reader = crypto_stream.reader();
+                let mut received = Vec::new();
+                // 每次收到两个乱序的包，TLS随即消费掉
+                for (i, pair) in chain.chunks(2400).enumerate() {
+                    let offset = (i * 2400) as u64;
+                    let (first, second) = pair.split_at(pair.len().min(1200));
+                    if !second.is_empty() {
+                        incoming.recv_frame(&crypto_frame(offset + 1200, second))?;
+                    }
+                    incoming.recv_frame(&crypto_frame(offset, first))?;
+                    let mut buf = vec![0; pair.len()];
+                    reader.read_exact(&mut buf).await.unwrap();
+                    received.extend(buf);
+                }
+                assert_eq!(received, chain);
+                Ok::<_, qbase::error::Error>(crypto_stream.recv_stats())
+            }
+        };
+
+        let stats = receive(CryptoStream::new(0, 0)).await.unwrap();
+        assert_eq!(stats.received, CHAIN_LEN as u64);
+        assert_eq!(stats.largest_offset, CHAIN_LEN as u64);
+        assert_eq!(stats.peak_buffered, 2400);
+
+        // 更小的总量上限，同样的证书链会超限
+        let crypto_stream = CryptoStream::new(0, 0);
+        crypto_stream.set_recv_limits(CryptoLimits {
+            max_total: 128 * 1024,
+            ..CryptoLimits::default()
+        });
+        let error = receive(crypto_stream).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::CryptoBufferExceeded);
+    }
 }
@@ -1,32 +1,71 @@
 use std::{
     collections::{BTreeMap, HashMap},
+    future::poll_fn,
     sync::{Arc, Mutex, MutexGuard},
-    task::{ready, Context, Poll},
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
 };
 
 use deref_derive::{Deref, DerefMut};
 use qbase::{
+    cid::ConnectionId,
     config::Parameters,
     error::{Error as QuicError, ErrorKind},
     frame::{
         BeFrame, FrameType, MaxStreamDataFrame, MaxStreamsFrame, ResetStreamFrame, SendFrame,
-        StopSendingFrame, StreamCtlFrame, StreamFrame,
+        StopSendingFrame, StreamCtlFrame, StreamDataBlockedFrame, StreamFrame, StreamsBlockedFrame,
     },
-    streamid::{AcceptSid, Dir, ExceedLimitError, Role, StreamId, StreamIds},
+    streamid::{AcceptSid, Dir, ExceedLimitError, Role, StreamId, StreamIds, StreamOpenRate},
+    util::{spawn_traced, try_spawn_traced, ArcTraceContext, Wakers},
     varint::VarInt,
 };
 
-use super::listener::{AcceptBiStream, AcceptUniStream, ArcListener};
+use super::{
+    classify::StreamClass,
+    listener::{AcceptBiStream, AcceptClass, AcceptUniStream, ArcListener},
+    policy::IncomingStreamPolicy,
+    scheduler::{Candidate, RoundRobin, StreamScheduler},
+};
 use crate::{
-    recv::{self, ArcRecver, Incoming, Reader},
-    send::{self, ArcSender, Outgoing, Writer},
+    recv::{self, ArcRecver, ArcWindowTuning, Incoming, Reader, RecvIntrospection},
+    send::{self, ArcSender, FlushMode, Outgoing, Priority, SendIntrospection, Writer},
 };
 
-#[derive(Default, Debug, Clone, Deref, DerefMut)]
+/// The default levels a stream with lost data is lifted by, see [`Outgoing::urgency`].
+pub const DEFAULT_RETRANSMISSION_BOOST: u8 = 1;
+
+#[derive(Debug, Deref, DerefMut)]
 struct RawOutput {
     #[deref]
     outgoings: BTreeMap<StreamId, Outgoing>,
-    cur_sending_stream: Option<(StreamId, usize)>,
+    scheduler: Box<dyn StreamScheduler>,
+    // 等待所有流的数据都发送/确认完毕的任务，可能有多个，发送或确认数据后唤醒它们重新检查
+    flush_wakers: Wakers,
+    // 有丢失数据待重传的流，至多提升几个紧急级别
+    retransmission_boost: u8,
+    // 对方支持IMMEDIATE_ACK帧，才响应Writer::flush_eager的请求
+    immediate_ack_enabled: bool,
+    // 刚读出的数据到达了某个流flush_eager的位置，所在的包要请求对方立即确认
+    immediate_ack: bool,
+}
+
+impl Default for RawOutput {
+    fn default() -> Self {
+        Self {
+            outgoings: BTreeMap::new(),
+            scheduler: Box::new(RoundRobin::default()),
+            flush_wakers: Wakers::default(),
+            retransmission_boost: DEFAULT_RETRANSMISSION_BOOST,
+            immediate_ack_enabled: false,
+            immediate_ack: false,
+        }
+    }
+}
+
+impl RawOutput {
+    fn wake_flush(&mut self) {
+        self.flush_wakers.wake_all();
+    }
 }
 
 /// ArcOutput里面包含一个Result类型，一旦发生quic error，就会被替换为Err
@@ -64,8 +103,11 @@ impl ArcOutputGuard<'_> {
     }
 
     fn on_conn_error(&mut self, err: &QuicError) {
-        match self.inner.as_ref() {
-            Ok(set) => set.values().for_each(|o| o.on_conn_error(err)),
+        match self.inner.as_mut() {
+            Ok(set) => {
+                set.values().for_each(|o| o.on_conn_error(err));
+                set.wake_flush();
+            }
             // 已经遇到过conn error了，不需要再次处理。然而guard()时就已经返回了Err，不会再走到这里来
             Err(e) => unreachable!("output is invalid: {e}"),
         };
@@ -116,6 +158,61 @@ impl ArcInputGuard<'_> {
     }
 }
 
+/// A snapshot of a data stream for diagnostics, see [`RawDataStreams::introspect`].
+///
+/// A unidirectional stream has only one of the two parts, and a part is gone once it
+/// finishes its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StreamIntrospection {
+    /// The stream id as carried in the frames.
+    pub id: u64,
+    pub send: Option<SendIntrospection>,
+    pub recv: Option<RecvIntrospection>,
+}
+
+/// The streams of a connection that look like a flow control deadlock, see
+/// [`RawDataStreams::flow_control_stall`].
+///
+/// Both ends wait for each other: our streams are blocked on the peer's windows, while the
+/// data the peer sent on our other streams fills our windows but isn't read. Often the
+/// application reads only after it has written everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FlowControlStallSuspected {
+    /// The streams blocked on the peer's MAX_STREAM_DATA for longer than the threshold.
+    pub blocked: Vec<u64>,
+    /// The streams whose unread data fills the receive window advertised to the peer.
+    pub full: Vec<u64>,
+}
+
+impl FlowControlStallSuspected {
+    /// Detect the stall in a snapshot of the streams, None if any of the two lists is empty.
+    pub fn detect(streams: &[StreamIntrospection], threshold: Duration) -> Option<Self> {
+        let blocked = streams
+            .iter()
+            .filter(|s| {
+                s.send
+                    .as_ref()
+                    .and_then(|send| send.blocked)
+                    .is_some_and(|blocked| blocked >= threshold)
+            })
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        // 读得慢但仍在读的流，窗口会随读取更新，不会一直是满的
+        let full = streams
+            .iter()
+            .filter(|s| {
+                s.recv.as_ref().is_some_and(|recv| {
+                    recv.buffered > 0 && recv.max_data.is_some_and(|max| recv.received >= max)
+                })
+            })
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        (!blocked.is_empty() && !full.is_empty()).then_some(Self { blocked, full })
+    }
+}
+
 /// 专门根据Stream相关帧处理streams相关逻辑
 #[derive(Debug, Clone)]
 pub struct RawDataStreams<T>
@@ -133,12 +230,18 @@ where
     local_bi_stream_rcvbuf_size: u64,
     // the receive buffer size for the accpeted bidirectional stream created by peer
     remote_bi_stream_rcvbuf_size: u64,
+    // 各流的接收窗口从上面的大小开始，随吞吐和RTT增长
+    window_tuning: ArcWindowTuning,
     // 所有流的待写端，要发送数据，就得向这些流索取
     output: ArcOutput,
     // 所有流的待读端，收到了数据，交付给这些流
     input: ArcInput,
     // 对方主动创建的流
     listener: ArcListener,
+    // 连接的追踪上下文，各个流的上下文都是它的子上下文
+    trace: ArcTraceContext,
+    // 所属连接的id，各个流的读写端都带着它，以便关联日志
+    conn_id: ConnectionId,
 }
 
 fn wrapper_error(fty: FrameType) -> impl FnOnce(ExceedLimitError) -> QuicError {
@@ -157,40 +260,82 @@ where
         let guard = &mut self.output.0.lock().unwrap();
         let output = guard.as_mut().ok()?;
 
-        const DEFAULT_TOKENS: usize = 4096;
-
-        // 该tokens是令牌桶算法的token，为了多条Stream的公平性，给每个流定期地发放tokens，不累积
-        // 各流轮流按令牌桶算法发放的tokens来整理数据去发送
-        let (sid, outgoing, tokens) = output
-            .cur_sending_stream
-            .and_then(|(sid, tokens): (StreamId, usize)| {
-                if tokens == 0 {
-                    // 没有额度：下一个
-                    output
-                        .outgoings
-                        .range(sid..)
-                        .nth(1)
-                        .map(|(sid, outgoing)| (*sid, outgoing, DEFAULT_TOKENS))
-                } else {
-                    // 有额度：继续
-                    Some((sid, output.outgoings.get(&sid)?, tokens))
-                }
+        // 丢失待重传的数据回到各自的流中，随流（提升后）的紧急级别竞争，不再插队
+        let boost = output.retransmission_boost;
+        let candidates = output
+            .outgoings
+            .iter()
+            .map(|(sid, outgoing)| Candidate {
+                sid: *sid,
+                urgency: outgoing.urgency(boost),
+                weight: outgoing.weight(),
             })
-            .or_else(|| {
-                // 还没开始/没有下一个/该sid已经被移除：从头开始
-                output
-                    .outgoings
-                    .first_key_value()
-                    .map(|(sid, outgoing)| (*sid, outgoing, DEFAULT_TOKENS))
+            .collect::<Vec<_>>();
+        let schedule = output.scheduler.schedule(&candidates);
+
+        let (sid, granted, (frame, dat_len, is_fresh, written)) =
+            schedule.into_iter().find_map(|(sid, granted)| {
+                let outgoing = output.outgoings.get(&sid)?;
+                let read = outgoing.try_read(sid, buf, granted, flow_limit)?;
+                Some((sid, granted, read))
             })?;
-
-        let (frame, dat_len, is_fresh, written) =
-            outgoing.try_read(sid, buf, tokens, flow_limit)?;
-        output.cur_sending_stream = Some((sid, tokens - dat_len));
+        output.scheduler.on_sent(sid, granted, dat_len);
+        output.wake_flush();
+        if output
+            .get(&sid)
+            .is_some_and(|outgoing| outgoing.0.take_eager(frame.range().end))
+        {
+            output.immediate_ack |= output.immediate_ack_enabled;
+        }
 
         Some((frame, written, if is_fresh { dat_len } else { 0 }))
     }
 
+    /// Replace the scheduler deciding which stream sends next, [`RoundRobin`] by default.
+    pub fn set_stream_scheduler(&self, scheduler: Box<dyn StreamScheduler>) {
+        if let Ok(output) = self.output.0.lock().unwrap().as_mut() {
+            output.scheduler = scheduler;
+        }
+    }
+
+    /// Set the priority of the stream, see [`Writer::set_priority`]. Returns false if the
+    /// stream doesn't send, or is already done.
+    pub fn set_priority(&self, sid: StreamId, priority: Priority) -> bool {
+        match self.output.0.lock().unwrap().as_ref() {
+            Ok(output) => output
+                .get(&sid)
+                .map(|outgoing| outgoing.0.set_priority(priority))
+                .is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Enable the IMMEDIATE_ACK requests of [`Writer::flush_eager`], once the peer turns out
+    /// to support the ACK_FREQUENCY extension by its min_ack_delay transport parameter.
+    pub fn enable_immediate_ack(&self) {
+        if let Ok(output) = self.output.0.lock().unwrap().as_mut() {
+            output.immediate_ack_enabled = true;
+        }
+    }
+
+    /// Whether the data read since the last call reached the end of a [`Writer::flush_eager`],
+    /// the packet carrying it should ask the peer to acknowledge at once.
+    pub fn take_immediate_ack(&self) -> bool {
+        match self.output.0.lock().unwrap().as_mut() {
+            Ok(output) => std::mem::take(&mut output.immediate_ack),
+            Err(_) => false,
+        }
+    }
+
+    /// Set how many urgency levels a stream with lost data is lifted by at most, so that the
+    /// repairs of a less urgent stream don't starve, [`DEFAULT_RETRANSMISSION_BOOST`] by default.
+    /// 0 makes the repairs compete at the very urgency of their stream.
+    pub fn set_retransmission_boost(&self, boost: u8) {
+        if let Ok(output) = self.output.0.lock().unwrap().as_mut() {
+            output.retransmission_boost = boost;
+        }
+    }
+
     pub fn on_data_acked(&self, frame: StreamFrame) {
         if let Ok(set) = self.output.0.lock().unwrap().as_mut() {
             if set
@@ -200,6 +345,28 @@ where
             {
                 set.remove(&frame.id);
             }
+            set.wake_flush();
+        }
+    }
+
+    /// Poll whether all the streams have nothing left to send, or to be acknowledged for
+    /// [`FlushMode::FullyAcked`], see [`Outgoing::is_flushed`].
+    pub fn poll_flushed(
+        &self,
+        cx: &mut Context<'_>,
+        mode: FlushMode,
+    ) -> Poll<Result<(), QuicError>> {
+        let mut guard = self.output.0.lock().unwrap();
+        match guard.as_mut() {
+            Ok(output) => {
+                if output.values().all(|o| o.is_flushed(mode)) {
+                    Poll::Ready(Ok(()))
+                } else {
+                    output.flush_wakers.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+            Err(e) => Poll::Ready(Err(e.clone())),
         }
     }
 
@@ -213,7 +380,7 @@ where
             .ok()
             .and_then(|set| set.get(&stream_frame.id))
         {
-            o.may_loss_data(&stream_frame.range());
+            o.may_loss_data(&stream_frame.range(), stream_frame.is_fin());
         }
     }
 
@@ -222,6 +389,7 @@ where
             if let Some(o) = set.remove(&reset_frame.stream_id) {
                 o.on_reset_acked();
             }
+            set.wake_flush();
             // 如果流是双向的，接收部分的流独立地管理结束。其实是上层应用决定接收的部分是否同时结束
         }
     }
@@ -257,13 +425,26 @@ where
             .map(|incoming| incoming.recv_data(stream_frame, body.clone()));
 
         match ret {
-            Some(recv_ret) => recv_ret,
+            Some(recv_ret) => {
+                // 对方创建的流，收到开头的数据后可能可以分类了
+                if sid.role() != self.role {
+                    self.listener.on_stream_changed(sid);
+                }
+                recv_ret
+            }
             // 该流已结束，收到的数据将被忽略
             None => Ok(0),
         }
     }
 
-    pub fn recv_stream_control(&self, stream_ctl_frame: &StreamCtlFrame) -> Result<(), QuicError> {
+    /// Return the new data implied by the final size of a RESET_STREAM frame, which counts
+    /// against the connection-level flow control like the data of the STREAM frames, see
+    /// [`Incoming::recv_reset`]. 0 for the other frames.
+    pub fn recv_stream_control(
+        &self,
+        stream_ctl_frame: &StreamCtlFrame,
+    ) -> Result<usize, QuicError> {
+        let mut new_data_size = 0;
         match stream_ctl_frame {
             StreamCtlFrame::ResetStream(reset) => {
                 let sid = reset.stream_id;
@@ -283,9 +464,13 @@ where
                 }
                 if let Ok(set) = self.input.0.lock().unwrap().as_mut() {
                     if let Some(incoming) = set.remove(&sid) {
-                        incoming.recv_reset(reset)?;
+                        new_data_size = incoming.recv_reset(reset)? as usize;
                     }
                 }
+                // 被重置的流不会再有数据，等待分类的要按已有的数据分类
+                if sid.role() != self.role {
+                    self.listener.on_stream_changed(sid);
+                }
             }
             StreamCtlFrame::StopSending(stop_sending) => {
                 let sid = stop_sending.stream_id;
@@ -302,7 +487,7 @@ where
                     self.try_accept_sid(sid)
                         .map_err(wrapper_error(stop_sending.frame_type()))?;
                 }
-                if self
+                if let Some(final_size) = self
                     .output
                     .0
                     .lock()
@@ -310,14 +495,14 @@ where
                     .as_mut()
                     .ok()
                     .and_then(|set| set.get(&sid))
-                    .map(|outgoing| outgoing.stop())
-                    .unwrap_or(false)
+                    .and_then(|outgoing| outgoing.stop(stop_sending.app_err_code.into_inner()))
                 {
+                    // 沿用STOP_SENDING帧的错误码，见RFC 9000 3.5节
                     self.ctrl_frames
                         .send_frame([StreamCtlFrame::ResetStream(ResetStreamFrame {
                             stream_id: sid,
-                            app_error_code: VarInt::from_u32(0),
-                            final_size: VarInt::from_u32(0),
+                            app_error_code: stop_sending.app_err_code,
+                            final_size: unsafe { VarInt::from_u64_unchecked(final_size) },
                         })]);
                 }
             }
@@ -365,6 +550,18 @@ where
                     }
                 }
                 // 仅仅起到通知作用?主动更新窗口的，此帧没多大用，或许要进一步放大缓冲区大小；被动更新窗口的，此帧有用
+                // 至少记下对方受限于我方窗口的时长，供诊断
+                if let Some(incoming) = self
+                    .input
+                    .0
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .ok()
+                    .and_then(|set| set.get(&sid))
+                {
+                    incoming.on_data_blocked(stream_data_blocked.maximum_stream_data.into_inner());
+                }
             }
             StreamCtlFrame::MaxStreams(max_streams) => {
                 // 主要更新我方能创建的单双向流
@@ -385,7 +582,7 @@ where
                 // 仅仅起到通知作用?也分主动和被动
             }
         }
-        Ok(())
+        Ok(new_data_size)
     }
 
     pub fn on_conn_error(&self, err: &QuicError) {
@@ -407,37 +604,207 @@ where
         listener.on_conn_error(err);
     }
 
+    /// Take a snapshot of all the living streams, ordered by the stream id, for the
+    /// diagnostics like an admin listing. Nothing is listed after a connection error.
+    pub fn introspect(&self) -> Vec<StreamIntrospection> {
+        fn entry(
+            streams: &mut BTreeMap<StreamId, StreamIntrospection>,
+            sid: StreamId,
+        ) -> &mut StreamIntrospection {
+            streams.entry(sid).or_insert_with(|| StreamIntrospection {
+                id: VarInt::from(sid).into_inner(),
+                send: None,
+                recv: None,
+            })
+        }
+
+        let mut streams = BTreeMap::new();
+        // 依次加锁，不同时持有两把锁，以免与收发数据的路径形成锁序问题
+        if let Ok(output) = self.output.0.lock().unwrap().as_ref() {
+            for (sid, outgoing) in output.iter() {
+                entry(&mut streams, *sid).send = Some(outgoing.0.introspect());
+            }
+        }
+        if let Ok(input) = self.input.0.lock().unwrap().as_ref() {
+            for (sid, incoming) in input.iter() {
+                entry(&mut streams, *sid).recv = Some(incoming.0.introspect());
+            }
+        }
+        streams.into_values().collect()
+    }
+
+    /// Check whether the streams look like a flow control deadlock, where some streams have
+    /// been blocked on the peer's windows for at least `threshold`, see
+    /// [`FlowControlStallSuspected`].
+    pub fn flow_control_stall(&self, threshold: Duration) -> Option<FlowControlStallSuspected> {
+        FlowControlStallSuspected::detect(&self.introspect(), threshold)
+    }
+
+    /// The bytes allocated for the bookkeeping of the streams when they're created, without
+    /// the streams themselves, roughly what a connection without streams saves.
+    pub fn footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + std::mem::size_of_val(&*self.output.0)
+            + std::mem::size_of_val(&*self.input.0)
+            + self.listener.footprint()
+            + self.window_tuning.footprint()
+    }
+
     pub fn premit_max_sid(&self, dir: Dir, val: u64) {
         self.stream_ids.local.permit_max_sid(dir, val);
     }
+
+    /// Limit how fast the local streams are opened, see [`ArcLocalStreamIds::set_open_rate`].
+    ///
+    /// [`ArcLocalStreamIds::set_open_rate`]: qbase::streamid::ArcLocalStreamIds::set_open_rate
+    pub fn set_stream_open_rate(&self, rate: Option<StreamOpenRate>) {
+        self.stream_ids.local.set_open_rate(rate);
+    }
+
+    /// 流的打开限速按RTT补充令牌
+    pub fn update_rtt(&self, rtt: Duration) {
+        self.stream_ids.local.update_rtt(rtt);
+        self.window_tuning.update_rtt(rtt);
+    }
+
+    /// Set the upper bound the receive window of a stream grows to, see [`ArcWindowTuning`].
+    pub fn set_max_stream_window(&self, max_window: u64) {
+        self.window_tuning.set_max_window(max_window);
+    }
+
+    /// The connection-level receive window changed, beyond which the receive window of a
+    /// stream doesn't grow.
+    pub fn set_conn_recv_window(&self, conn_window: u64) {
+        self.window_tuning.set_conn_window(conn_window);
+    }
+
+    /// 等待中的打开被放弃了，让排在后面的打开接上
+    /// Reserve the first stream ID of each lane, the streams of a kind the application opens,
+    /// in the order the labels are given. Return the first stream ID of each label, see
+    /// [`ArcLocalStreamIds::register_lanes`].
+    ///
+    /// [`ArcLocalStreamIds::register_lanes`]: qbase::streamid::ArcLocalStreamIds::register_lanes
+    pub fn register_lanes(
+        &self,
+        dir: Dir,
+        labels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Vec<StreamId> {
+        self.stream_ids.local.register_lanes(dir, labels)
+    }
+
+    /// The first stream ID reserved for the lane, None if the label isn't registered.
+    pub fn lane(&self, dir: Dir, label: &str) -> Option<StreamId> {
+        self.stream_ids.local.lane(dir, label)
+    }
+
+    fn poll_alloc_sid(
+        &self,
+        cx: &mut Context<'_>,
+        dir: Dir,
+        label: Option<&str>,
+    ) -> Poll<Option<StreamId>> {
+        match label {
+            Some(label) => self.stream_ids.local.poll_alloc_lane_sid(cx, dir, label),
+            None => self.stream_ids.local.poll_alloc_sid(cx, dir),
+        }
+    }
+
+    /// 受限于对方的MAX_STREAMS而打不开流时，告知对方STREAMS_BLOCKED，每个额度只告知一次
+    fn notify_streams_blocked(&self, dir: Dir) {
+        if let Some(max_sid) = self.stream_ids.local.take_blocked(dir) {
+            tracing::debug!(?dir, %max_sid, "streams blocked");
+            self.ctrl_frames
+                .send_frame([StreamCtlFrame::StreamsBlocked(match dir {
+                    Dir::Bi => StreamsBlockedFrame::Bi(max_sid),
+                    Dir::Uni => StreamsBlockedFrame::Uni(max_sid),
+                })]);
+        }
+    }
+
+    pub(super) fn cancel_open(&self, dir: Dir, waker: &Waker) {
+        self.stream_ids.local.cancel_alloc(dir, waker);
+    }
+
+    pub fn set_incoming_stream_policy(&self, policy: IncomingStreamPolicy) {
+        self.listener.set_policy(policy);
+    }
+
+    /// Keep the peer able to create `window` more streams in the direction beyond the
+    /// streams released, the `initial_max_streams` of the local parameters by default, see
+    /// [`ArcListener::set_window`].
+    pub fn set_remote_stream_window(&self, dir: Dir, window: u64) {
+        self.listener.set_window(dir, window);
+    }
+
+    // MAX_STREAMS的额度随着对方创建的流的处理完成或被丢弃而增长，不通告注定要丢弃的流
+    fn spawn_credit_issuers(&self) {
+        for dir in [Dir::Bi, Dir::Uni] {
+            spawn_traced({
+                let listener = self.listener.clone();
+                let remote = self.stream_ids.remote.clone();
+                let ctrl_frames = self.ctrl_frames.clone();
+                async move {
+                    while let Some(max_streams) =
+                        poll_fn(|cx| listener.poll_extend_credit(cx, dir)).await
+                    {
+                        if !remote.extend_max_sid(dir, max_streams) {
+                            continue;
+                        }
+                        let max_streams = VarInt::from_u64(remote.max_sid(dir))
+                            .expect("max streams must not exceed VARINT_MAX");
+                        tracing::debug!(?dir, %max_streams, "extend max streams");
+                        ctrl_frames.send_frame([StreamCtlFrame::MaxStreams(match dir {
+                            Dir::Bi => MaxStreamsFrame::Bi(max_streams),
+                            Dir::Uni => MaxStreamsFrame::Uni(max_streams),
+                        })]);
+                    }
+                }
+            });
+        }
+    }
 }
 
 impl<T> RawDataStreams<T>
 where
     T: SendFrame<StreamCtlFrame> + Clone + Send + 'static,
 {
-    pub(super) fn new(role: Role, local_params: &Parameters, ctrl_frames: T) -> Self {
-        Self {
+    pub(super) fn new(
+        role: Role,
+        local_params: &Parameters,
+        ctrl_frames: T,
+        conn_id: ConnectionId,
+    ) -> Self {
+        let initial_max_streams = [
+            local_params.initial_max_streams_bidi().into_inner(),
+            local_params.initial_max_streams_uni().into_inner(),
+        ];
+        let streams = Self {
             role,
-            stream_ids: StreamIds::new(
-                role,
-                local_params.initial_max_streams_bidi().into(),
-                local_params.initial_max_streams_uni().into(),
-            ),
+            stream_ids: StreamIds::new(role, initial_max_streams[0], initial_max_streams[1]),
             uni_stream_rcvbuf_size: local_params.initial_max_stream_data_uni().into(),
             local_bi_stream_rcvbuf_size: local_params.initial_max_stream_data_bidi_local().into(),
             remote_bi_stream_rcvbuf_size: local_params.initial_max_stream_data_bidi_remote().into(),
+            window_tuning: {
+                let tuning = ArcWindowTuning::default();
+                tuning.set_conn_window(local_params.initial_max_data().into_inner());
+                tuning
+            },
             output: ArcOutput::default(),
             input: ArcInput::default(),
-            listener: ArcListener::default(),
+            listener: ArcListener::with_conn_id(conn_id, initial_max_streams),
             ctrl_frames,
-        }
+            trace: ArcTraceContext::current().unwrap_or_default(),
+            conn_id,
+        };
+        streams.spawn_credit_issuers();
+        streams
     }
 
     pub(super) fn poll_open_bi_stream(
         &self,
         cx: &mut Context<'_>,
         snd_wnd_size: u64,
+        label: Option<&str>,
     ) -> Poll<Result<Option<(Reader, Writer)>, QuicError>> {
         let mut output = match self.output.guard() {
             Ok(out) => out,
@@ -447,12 +814,20 @@ where
             Ok(input) => input,
             Err(e) => return Poll::Ready(Err(e)),
         };
-        if let Some(sid) = ready!(self.stream_ids.local.poll_alloc_sid(cx, Dir::Bi)) {
-            let arc_sender = self.create_sender(sid, snd_wnd_size);
-            let arc_recver = self.create_recver(sid, self.local_bi_stream_rcvbuf_size);
+        let alloc = self.poll_alloc_sid(cx, Dir::Bi, label);
+        if alloc.is_pending() {
+            self.notify_streams_blocked(Dir::Bi);
+        }
+        if let Some(sid) = ready!(alloc) {
+            let trace = self.trace.child();
+            let arc_sender = self.create_sender(sid, snd_wnd_size, &trace);
+            let arc_recver = self.create_recver(sid, self.local_bi_stream_rcvbuf_size, &trace);
             output.insert(sid, Outgoing(arc_sender.clone()));
             input.insert(sid, Incoming(arc_recver.clone()));
-            Poll::Ready(Ok(Some((Reader(arc_recver), Writer(arc_sender)))))
+            Poll::Ready(Ok(Some((
+                Reader::new(sid, arc_recver).with_conn_id(self.conn_id),
+                Writer::new(sid, arc_sender).with_conn_id(self.conn_id),
+            ))))
         } else {
             Poll::Ready(Ok(None))
         }
@@ -462,15 +837,22 @@ where
         &self,
         cx: &mut Context<'_>,
         snd_wnd_size: u64,
+        label: Option<&str>,
     ) -> Poll<Result<Option<Writer>, QuicError>> {
         let mut output = match self.output.guard() {
             Ok(out) => out,
             Err(e) => return Poll::Ready(Err(e)),
         };
-        if let Some(sid) = ready!(self.stream_ids.local.poll_alloc_sid(cx, Dir::Uni)) {
-            let arc_sender = self.create_sender(sid, snd_wnd_size);
+        let alloc = self.poll_alloc_sid(cx, Dir::Uni, label);
+        if alloc.is_pending() {
+            self.notify_streams_blocked(Dir::Uni);
+        }
+        if let Some(sid) = ready!(alloc) {
+            let arc_sender = self.create_sender(sid, snd_wnd_size, &self.trace.child());
             output.insert(sid, Outgoing(arc_sender.clone()));
-            Poll::Ready(Ok(Some(Writer(arc_sender))))
+            Poll::Ready(Ok(Some(
+                Writer::new(sid, arc_sender).with_conn_id(self.conn_id),
+            )))
         } else {
             Poll::Ready(Ok(None))
         }
@@ -486,6 +868,11 @@ where
         self.listener.accept_uni_stream()
     }
 
+    #[inline]
+    pub(super) fn accept_class(&self, class: StreamClass, snd_wnd_size: u64) -> AcceptClass {
+        self.listener.accept_class(class, snd_wnd_size)
+    }
+
     pub(super) fn listener(&self) -> ArcListener {
         self.listener.clone()
     }
@@ -517,11 +904,12 @@ where
             AcceptSid::New(need_create) => {
                 let rcv_buf_size = self.remote_bi_stream_rcvbuf_size;
                 for sid in need_create {
-                    let arc_recver = self.create_recver(sid, rcv_buf_size);
-                    let arc_sender = self.create_sender(sid, 0);
+                    let trace = self.trace.child();
+                    let arc_recver = self.create_recver(sid, rcv_buf_size, &trace);
+                    let arc_sender = self.create_sender(sid, 0, &trace);
                     input.insert(sid, Incoming(arc_recver.clone()));
                     output.insert(sid, Outgoing(arc_sender.clone()));
-                    listener.push_bi_stream((arc_recver, arc_sender));
+                    listener.push_bi_stream(sid, (arc_recver, arc_sender));
                 }
                 Ok(())
             }
@@ -544,21 +932,23 @@ where
                 let rcv_buf_size = self.uni_stream_rcvbuf_size;
 
                 for sid in need_create {
-                    let arc_receiver = self.create_recver(sid, rcv_buf_size);
+                    let arc_receiver = self.create_recver(sid, rcv_buf_size, &self.trace.child());
                     input.insert(sid, Incoming(arc_receiver.clone()));
-                    listener.push_uni_stream(arc_receiver);
+                    listener.push_uni_stream(sid, arc_receiver);
                 }
                 Ok(())
             }
         }
     }
 
-    fn create_sender(&self, sid: StreamId, wnd_size: u64) -> ArcSender {
-        let arc_sender = send::new(wnd_size);
+    // 收到的帧也会创建流，可能在运行时关闭之后迟到，此时流的这些任务不再派生
+    fn create_sender(&self, sid: StreamId, wnd_size: u64, trace: &ArcTraceContext) -> ArcSender {
+        let arc_sender = send::with_trace(wnd_size, trace.clone());
+        let _enter = trace.enter();
         // 创建异步轮询子，监听来自应用层的cancel
         // 一旦cancel，直接向对方发送reset_stream
         // 但要等ResetRecved才能真正释放该流
-        tokio::spawn({
+        try_spawn_traced({
             let outgoing = Outgoing(arc_sender.clone());
             let ctrl_frames = self.ctrl_frames.clone();
             async move {
@@ -572,17 +962,35 @@ where
                 }
             }
         });
+        // 受限于对方的MAX_STREAM_DATA时，告知对方STREAM_DATA_BLOCKED，以便对方排查
+        try_spawn_traced({
+            let outgoing = Outgoing(arc_sender.clone());
+            let ctrl_frames = self.ctrl_frames.clone();
+            async move {
+                while let Some(limit) = outgoing.is_blocked().await {
+                    tracing::debug!(%sid, limit, "stream data blocked");
+                    ctrl_frames.send_frame([StreamCtlFrame::StreamDataBlocked(
+                        StreamDataBlockedFrame {
+                            stream_id: sid,
+                            maximum_stream_data: unsafe { VarInt::from_u64_unchecked(limit) },
+                        },
+                    )]);
+                }
+            }
+        });
         arc_sender
     }
 
-    fn create_recver(&self, sid: StreamId, buf_size: u64) -> ArcRecver {
-        let arc_recver = recv::new(buf_size);
+    fn create_recver(&self, sid: StreamId, buf_size: u64, trace: &ArcTraceContext) -> ArcRecver {
+        let arc_recver = recv::with_tuning(buf_size, self.window_tuning.clone(), trace.clone());
+        let _enter = trace.enter();
         // Continuously check whether the MaxStreamData window needs to be updated.
-        tokio::spawn({
+        try_spawn_traced({
             let incoming = Incoming(arc_recver.clone());
             let ctrl_frames = self.ctrl_frames.clone();
             async move {
                 while let Some(max_data) = incoming.need_update_window().await {
+                    tracing::debug!(%sid, max_data, "update max stream data");
                     ctrl_frames.send_frame([StreamCtlFrame::MaxStreamData(MaxStreamDataFrame {
                         stream_id: sid,
                         max_stream_data: unsafe { VarInt::from_u64_unchecked(max_data) },
@@ -591,7 +999,7 @@ where
             }
         });
         // 监听是否被应用stop了。如果是，则要发送一个StopSendingFrame
-        tokio::spawn({
+        try_spawn_traced({
             let incoming = Incoming(arc_recver.clone());
             let ctrl_frames = self.ctrl_frames.clone();
             async move {
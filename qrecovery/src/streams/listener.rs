@@ -1,81 +1,486 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     future::Future,
     pin::Pin,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
     task::{Context, Poll, Waker},
 };
 
-use qbase::error::Error as QuicError;
+use qbase::{
+    cid::ConnectionId,
+    error::Error as QuicError,
+    streamid::{Dir, StreamId},
+    util::try_spawn_traced,
+};
 
+use super::{
+    classify::{ClassPolicy, StreamClass, StreamClassifier, StreamPreview},
+    policy::{InFlight, IncomingStreamPolicy},
+};
 use crate::{
     recv::{ArcRecver, Reader},
     send::{ArcSender, Outgoing, Writer},
 };
 
+/// 对方创建的流，单向流没有发送端
+type Stream = (StreamId, ArcRecver, Option<ArcSender>);
+
+/// 某一类流的接受队列
+#[derive(Debug, Default)]
+struct ClassQueue {
+    streams: VecDeque<Stream>,
+    policy: Option<ClassPolicy>,
+    waker: Option<Waker>,
+}
+
 #[derive(Debug, Default)]
 struct RawListener {
-    // 对方主动创建的流
-    bi_streams: VecDeque<(ArcRecver, ArcSender)>,
-    uni_streams: VecDeque<ArcRecver>,
+    // 对方主动创建的流，也是默认类别的流
+    bi_streams: VecDeque<(StreamId, ArcRecver, ArcSender)>,
+    uni_streams: VecDeque<(StreamId, ArcRecver)>,
     bi_waker: Option<Waker>,
     uni_waker: Option<Waker>,
+    // 按默认类别接受流的任务，单双向的流都可以
+    default_class_waker: Option<Waker>,
+    // 应用设置的分类器，以及等待分类的流和分好类的流
+    classifier: Option<StreamClassifier>,
+    unclassified: Vec<Stream>,
+    classes: HashMap<StreamClass, ClassQueue>,
+    // 应用设置的接受策略，以下的计数都按单双向分开
+    policy: Option<IncomingStreamPolicy>,
+    // 已被应用接受，正在处理的流
+    in_flight: [u64; 2],
+    // 处理完或被丢弃的流，对方可以据此新建同样多的流
+    released: [u64; 2],
+    // 已通告给对方的最大流数
+    advertised: [u64; 2],
+    // 没有接受策略时，对方除了已释放的流之外，始终可以再新建的流数
+    window: [u64; 2],
+    credit_wakers: [Option<Waker>; 2],
+    // 过载时丢弃排队的和新来的流，也不再通告新的额度，见ArcListener::set_shedding
+    shedding: bool,
+    shed: Arc<AtomicU64>,
+    // 接受的流都带上所属连接的id
+    conn_id: ConnectionId,
 }
 
 impl RawListener {
-    fn push_bi_stream(&mut self, stream: (ArcRecver, ArcSender)) {
-        self.bi_streams.push_back(stream);
-        if let Some(waker) = self.bi_waker.take() {
+    fn queued(&self, dir: Dir) -> u64 {
+        let default = match dir {
+            Dir::Bi => self.bi_streams.len(),
+            Dir::Uni => self.uni_streams.len(),
+        };
+        let others = self
+            .unclassified
+            .iter()
+            .chain(self.classes.values().flat_map(|queue| queue.streams.iter()))
+            .filter(|(sid, ..)| sid.dir() == dir)
+            .count();
+        (default + others) as u64
+    }
+
+    /// 正在处理的流和排队的流已达上限，新来的流要被丢弃
+    fn is_full(&self, dir: Dir) -> bool {
+        self.policy.as_ref().is_some_and(|policy| {
+            self.shedding || self.in_flight[dir as usize] + self.queued(dir) >= policy.capacity()
+        })
+    }
+
+    /// 正在处理的流已达上限，应用要等有流处理完才能接受新的流
+    fn is_busy(&self, dir: Dir) -> bool {
+        self.policy
+            .as_ref()
+            .is_some_and(|policy| self.in_flight[dir as usize] >= policy.max_in_flight)
+    }
+
+    fn wake_accept(&mut self, dir: Dir) {
+        let waker = match dir {
+            Dir::Bi => self.bi_waker.take(),
+            Dir::Uni => self.uni_waker.take(),
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        if let Some(waker) = self.default_class_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_classes(&mut self) {
+        for waker in self.classes.values_mut().filter_map(|q| q.waker.take()) {
             waker.wake();
         }
     }
 
-    fn push_recv_stream(&mut self, stream: ArcRecver) {
-        self.uni_streams.push_back(stream);
-        if let Some(waker) = self.uni_waker.take() {
+    fn release(&mut self, dir: Dir) {
+        self.released[dir as usize] += 1;
+        if let Some(waker) = self.credit_wakers[dir as usize].take() {
             waker.wake();
         }
     }
 
+    fn shed_bi_stream(
+        &mut self,
+        sid: StreamId,
+        (recver, sender): (ArcRecver, ArcSender),
+        code: u64,
+    ) {
+        Reader::new(sid, recver).stop(code);
+        Writer::new(sid, sender).reset(code);
+        self.shed.fetch_add(1, Ordering::Relaxed);
+        self.release(Dir::Bi);
+    }
+
+    fn shed_uni_stream(&mut self, sid: StreamId, recver: ArcRecver, code: u64) {
+        Reader::new(sid, recver).stop(code);
+        self.shed.fetch_add(1, Ordering::Relaxed);
+        self.release(Dir::Uni);
+    }
+
+    fn shed_stream(&mut self, (sid, recver, sender): Stream, code: u64) {
+        match sender {
+            Some(sender) => self.shed_bi_stream(sid, (recver, sender), code),
+            None => self.shed_uni_stream(sid, recver, code),
+        }
+    }
+
+    /// 返回是否排上了队，没排上的已被丢弃
+    fn push_bi_stream(&mut self, sid: StreamId, stream: (ArcRecver, ArcSender)) -> bool {
+        if let Some(policy) = self.policy.filter(|_| self.is_full(Dir::Bi)) {
+            tracing::debug!(%sid, "shed the excess stream");
+            self.shed_bi_stream(sid, stream, policy.shed_code);
+            return false;
+        }
+        if self.classifier.is_some() {
+            self.unclassified.push((sid, stream.0, Some(stream.1)));
+            self.try_classify(sid);
+            return true;
+        }
+        self.bi_streams.push_back((sid, stream.0, stream.1));
+        self.wake_accept(Dir::Bi);
+        true
+    }
+
+    fn push_recv_stream(&mut self, sid: StreamId, stream: ArcRecver) -> bool {
+        if let Some(policy) = self.policy.filter(|_| self.is_full(Dir::Uni)) {
+            tracing::debug!(%sid, "shed the excess stream");
+            self.shed_uni_stream(sid, stream, policy.shed_code);
+            return false;
+        }
+        if self.classifier.is_some() {
+            self.unclassified.push((sid, stream, None));
+            self.try_classify(sid);
+            return true;
+        }
+        self.uni_streams.push_back((sid, stream));
+        self.wake_accept(Dir::Uni);
+        true
+    }
+
+    /// 等待分类的流，开头的数据够预览了，或者不会再有更多数据了，就分类并放入相应的队列
+    fn try_classify(&mut self, sid: StreamId) {
+        let Some(classifier) = self.classifier.clone() else {
+            return;
+        };
+        let Some(idx) = self.unclassified.iter().position(|(s, ..)| *s == sid) else {
+            return;
+        };
+        let (data, is_complete) = self.unclassified[idx].1.preview(classifier.peek_len());
+        if data.len() < classifier.peek_len() && !is_complete {
+            return;
+        }
+
+        let stream = self.unclassified.swap_remove(idx);
+        let class = classifier.classify(&StreamPreview::new(sid, &data, is_complete));
+        tracing::debug!(%sid, ?class, "classify the stream");
+        if class == StreamClass::DEFAULT {
+            match stream {
+                (sid, recver, Some(sender)) => self.bi_streams.push_back((sid, recver, sender)),
+                (sid, recver, None) => self.uni_streams.push_back((sid, recver)),
+            }
+            self.wake_accept(sid.dir());
+            return;
+        }
+
+        let queue = self.classes.entry(class).or_default();
+        match queue.policy {
+            Some(policy) if queue.streams.len() as u64 >= policy.backlog => {
+                tracing::debug!(%sid, ?class, "shed the excess stream of the class");
+                self.shed_stream(stream, policy.shed_code);
+            }
+            _ => {
+                queue.streams.push_back(stream);
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// 排队超时的流若仍未被接受，则丢弃
+    fn expire(&mut self, sid: StreamId) {
+        let Some(policy) = self.policy else {
+            return;
+        };
+        match sid.dir() {
+            Dir::Bi => {
+                if let Some(idx) = self.bi_streams.iter().position(|(s, ..)| *s == sid) {
+                    let (_, recver, sender) = self.bi_streams.remove(idx).unwrap();
+                    tracing::debug!(%sid, "shed the stream not accepted in time");
+                    self.shed_bi_stream(sid, (recver, sender), policy.shed_code);
+                }
+            }
+            Dir::Uni => {
+                if let Some(idx) = self.uni_streams.iter().position(|(s, _)| *s == sid) {
+                    let (_, recver) = self.uni_streams.remove(idx).unwrap();
+                    tracing::debug!(%sid, "shed the stream not accepted in time");
+                    self.shed_uni_stream(sid, recver, policy.shed_code);
+                }
+            }
+        }
+        // 还在等待分类，或者在某一类的队列里
+        if let Some(idx) = self.unclassified.iter().position(|(s, ..)| *s == sid) {
+            let stream = self.unclassified.swap_remove(idx);
+            tracing::debug!(%sid, "shed the stream not classified in time");
+            self.shed_stream(stream, policy.shed_code);
+        }
+        let queued = self.classes.values_mut().find_map(|queue| {
+            let idx = queue.streams.iter().position(|(s, ..)| *s == sid)?;
+            let shed_code = queue.policy.map_or(policy.shed_code, |p| p.shed_code);
+            Some((queue.streams.remove(idx)?, shed_code))
+        });
+        if let Some((stream, shed_code)) = queued {
+            tracing::debug!(%sid, "shed the stream not accepted in time");
+            self.shed_stream(stream, shed_code);
+        }
+    }
+
+    // 被接受的流都占用一个名额，其Reader和Writer都释放后，才释放MAX_STREAMS的额度
+    fn in_flight(&mut self, listener: &ArcListener, dir: Dir) -> Arc<InFlight> {
+        self.in_flight[dir as usize] += 1;
+        Arc::new(InFlight::new(listener.clone(), dir))
+    }
+
+    fn on_in_flight_done(&mut self, dir: Dir) {
+        self.in_flight[dir as usize] = self.in_flight[dir as usize].saturating_sub(1);
+        self.release(dir);
+        self.wake_accept(dir);
+        self.wake_classes();
+    }
+
+    /// 流被应用接受，占用一个处理名额
+    fn accept(
+        &mut self,
+        listener: &ArcListener,
+        (sid, recver, sender): Stream,
+        send_wnd_size: u64,
+    ) -> (Reader, Option<Writer>) {
+        let in_flight = Some(self.in_flight(listener, sid.dir()));
+        let writer = sender.map(|sender| {
+            let outgoing = Outgoing(sender);
+            outgoing.update_window(send_wnd_size);
+            Writer::new(sid, outgoing.0)
+                .with_in_flight(in_flight.clone())
+                .with_conn_id(self.conn_id)
+        });
+        let reader = Reader::new(sid, recver)
+            .with_in_flight(in_flight)
+            .with_conn_id(self.conn_id);
+        (reader, writer)
+    }
+
     fn poll_accept_bi_stream(
         &mut self,
         cx: &mut Context<'_>,
         send_wnd_size: u64,
+        listener: &ArcListener,
     ) -> Poll<Result<(Reader, Writer), QuicError>> {
-        if let Some((recever, sender)) = self.bi_streams.pop_front() {
-            let outgoing = Outgoing(sender);
-            outgoing.update_window(send_wnd_size);
-            Poll::Ready(Ok((Reader(recever), Writer(outgoing.0))))
-        } else {
-            self.bi_waker = Some(cx.waker().clone());
-            Poll::Pending
+        if !self.is_busy(Dir::Bi) {
+            if let Some((sid, recver, sender)) = self.bi_streams.pop_front() {
+                let (reader, writer) =
+                    self.accept(listener, (sid, recver, Some(sender)), send_wnd_size);
+                return Poll::Ready(Ok((reader, writer.unwrap())));
+            }
         }
+        self.bi_waker = Some(cx.waker().clone());
+        Poll::Pending
     }
 
-    fn poll_accept_recv_stream(&mut self, cx: &mut Context<'_>) -> Poll<Result<Reader, QuicError>> {
-        if let Some(reader) = self.uni_streams.pop_front() {
-            Poll::Ready(Ok(Reader(reader)))
-        } else {
-            self.uni_waker = Some(cx.waker().clone());
-            Poll::Pending
+    fn poll_accept_recv_stream(
+        &mut self,
+        cx: &mut Context<'_>,
+        listener: &ArcListener,
+    ) -> Poll<Result<Reader, QuicError>> {
+        if !self.is_busy(Dir::Uni) {
+            if let Some((sid, recver)) = self.uni_streams.pop_front() {
+                let (reader, _) = self.accept(listener, (sid, recver, None), 0);
+                return Poll::Ready(Ok(reader));
+            }
         }
+        self.uni_waker = Some(cx.waker().clone());
+        Poll::Pending
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct ArcListener(Arc<Mutex<Result<RawListener, QuicError>>>);
+    fn poll_accept_class(
+        &mut self,
+        cx: &mut Context<'_>,
+        class: StreamClass,
+        send_wnd_size: u64,
+        listener: &ArcListener,
+    ) -> Poll<Result<(Reader, Option<Writer>), QuicError>> {
+        if class == StreamClass::DEFAULT {
+            if !self.is_busy(Dir::Bi) {
+                if let Some((sid, recver, sender)) = self.bi_streams.pop_front() {
+                    let stream = (sid, recver, Some(sender));
+                    return Poll::Ready(Ok(self.accept(listener, stream, send_wnd_size)));
+                }
+            }
+            if !self.is_busy(Dir::Uni) {
+                if let Some((sid, recver)) = self.uni_streams.pop_front() {
+                    let stream = (sid, recver, None);
+                    return Poll::Ready(Ok(self.accept(listener, stream, send_wnd_size)));
+                }
+            }
+            self.default_class_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // 按到达的顺序接受，排在前面的流所在方向的名额用完了，后面的也要等待
+        let front = self.classes.get(&class).and_then(|q| q.streams.front());
+        if let Some(dir) = front.map(|(sid, ..)| sid.dir()) {
+            if !self.is_busy(dir) {
+                let stream = self.classes.get_mut(&class).unwrap().streams.pop_front();
+                return Poll::Ready(Ok(self.accept(listener, stream.unwrap(), send_wnd_size)));
+            }
+        }
+        self.classes.entry(class).or_default().waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn set_classifier(&mut self, classifier: StreamClassifier) {
+        self.classifier = Some(classifier);
+    }
+
+    fn set_class_policy(&mut self, class: StreamClass, policy: ClassPolicy) {
+        self.classes.entry(class).or_default().policy = Some(policy);
+    }
+
+    fn set_policy(&mut self, policy: IncomingStreamPolicy) {
+        self.policy = Some(policy);
+        // 策略变了，可能可以接受更多的流、通告更多的额度了
+        self.wake_accept(Dir::Bi);
+        self.wake_accept(Dir::Uni);
+        self.wake_classes();
+        for waker in self.credit_wakers.iter_mut().filter_map(Option::take) {
+            waker.wake();
+        }
+    }
+
+    fn set_window(&mut self, dir: Dir, window: u64) {
+        self.window[dir as usize] = window;
+        if let Some(waker) = self.credit_wakers[dir as usize].take() {
+            waker.wake();
+        }
+    }
+
+    fn set_shedding(&mut self, shedding: bool) {
+        if std::mem::replace(&mut self.shedding, shedding) == shedding {
+            return;
+        }
+        if !shedding {
+            // 恢复通告额度，被丢弃的流所释放的额度也一并通告
+            for waker in self.credit_wakers.iter_mut().filter_map(Option::take) {
+                waker.wake();
+            }
+            return;
+        }
+        let Some(policy) = self.policy else {
+            return;
+        };
+        let bi_streams = std::mem::take(&mut self.bi_streams);
+        for (sid, recver, sender) in bi_streams {
+            tracing::debug!(%sid, "shed the queued stream under pressure");
+            self.shed_bi_stream(sid, (recver, sender), policy.shed_code);
+        }
+        let uni_streams = std::mem::take(&mut self.uni_streams);
+        for (sid, recver) in uni_streams {
+            tracing::debug!(%sid, "shed the queued stream under pressure");
+            self.shed_uni_stream(sid, recver, policy.shed_code);
+        }
+        for stream in std::mem::take(&mut self.unclassified) {
+            tracing::debug!(sid = %stream.0, "shed the unclassified stream under pressure");
+            self.shed_stream(stream, policy.shed_code);
+        }
+        let queued = self
+            .classes
+            .values_mut()
+            .flat_map(|queue| {
+                let shed_code = queue.policy.map_or(policy.shed_code, |p| p.shed_code);
+                queue
+                    .streams
+                    .drain(..)
+                    .map(move |stream| (stream, shed_code))
+            })
+            .collect::<Vec<_>>();
+        for (stream, shed_code) in queued {
+            tracing::debug!(sid = %stream.0, "shed the queued stream under pressure");
+            self.shed_stream(stream, shed_code);
+        }
+    }
 
-impl Default for ArcListener {
-    fn default() -> Self {
-        ArcListener(Arc::new(Mutex::new(Ok(RawListener::default()))))
+    fn poll_extend_credit(&mut self, cx: &mut Context<'_>, dir: Dir) -> Poll<u64> {
+        let idx = dir as usize;
+        if !self.shedding {
+            let capacity = self
+                .policy
+                .as_ref()
+                .map_or(self.window[idx], IncomingStreamPolicy::capacity);
+            let credit = self.released[idx].saturating_add(capacity);
+            if credit > self.advertised[idx] {
+                self.advertised[idx] = credit;
+                return Poll::Ready(credit);
+            }
+        }
+        self.credit_wakers[idx] = Some(cx.waker().clone());
+        Poll::Pending
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ArcListener(Arc<Mutex<Result<RawListener, QuicError>>>, Arc<AtomicU64>);
+
 impl ArcListener {
+    /// The streams accepted from the listener carry the id of the connection.
+    ///
+    /// `initial_max_streams` is the maximum number of the bidirectional and the
+    /// unidirectional streams that peer can create initially, which peer is kept able to
+    /// create beyond the streams released, see [`ArcListener::set_window`].
+    pub fn with_conn_id(conn_id: ConnectionId, initial_max_streams: [u64; 2]) -> Self {
+        let raw = RawListener {
+            conn_id,
+            advertised: initial_max_streams,
+            window: initial_max_streams,
+            ..Default::default()
+        };
+        let shed = raw.shed.clone();
+        ArcListener(Arc::new(Mutex::new(Ok(raw))), shed)
+    }
+
+    /// The bytes allocated for the listener, without the streams in it.
+    pub(crate) fn footprint(&self) -> usize {
+        std::mem::size_of_val(&*self.0) + std::mem::size_of_val(&*self.1)
+    }
+
     pub(crate) fn guard(&self) -> Result<ListenerGuard, QuicError> {
         let guard = self.0.lock().unwrap();
         match guard.as_ref() {
-            Ok(_) => Ok(ListenerGuard { inner: guard }),
+            Ok(_) => Ok(ListenerGuard {
+                inner: guard,
+                listener: self,
+            }),
             Err(e) => Err(e.clone()),
         }
     }
@@ -97,38 +502,172 @@ impl ArcListener {
         send_wnd_size: u64,
     ) -> Poll<Result<(Reader, Writer), QuicError>> {
         match self.0.lock().unwrap().as_mut() {
-            Ok(set) => set.poll_accept_bi_stream(cx, send_wnd_size),
+            Ok(set) => set.poll_accept_bi_stream(cx, send_wnd_size, self),
             Err(e) => Poll::Ready(Err(e.clone())),
         }
     }
 
     pub fn poll_accept_uni_stream(&self, cx: &mut Context<'_>) -> Poll<Result<Reader, QuicError>> {
         match self.0.lock().unwrap().as_mut() {
-            Ok(set) => set.poll_accept_recv_stream(cx),
+            Ok(set) => set.poll_accept_recv_stream(cx, self),
+            Err(e) => Poll::Ready(Err(e.clone())),
+        }
+    }
+
+    pub fn accept_class(&self, class: StreamClass, send_wnd_size: u64) -> AcceptClass {
+        AcceptClass {
+            inner: self,
+            class,
+            send_wnd_size,
+        }
+    }
+
+    /// Accept a stream of the class, the [`Writer`] is None for a unidirectional stream,
+    /// and the window of the [`Writer`] is `send_wnd_size`.
+    pub fn poll_accept_class(
+        &self,
+        cx: &mut Context<'_>,
+        class: StreamClass,
+        send_wnd_size: u64,
+    ) -> Poll<Result<(Reader, Option<Writer>), QuicError>> {
+        match self.0.lock().unwrap().as_mut() {
+            Ok(set) => set.poll_accept_class(cx, class, send_wnd_size, self),
             Err(e) => Poll::Ready(Err(e.clone())),
         }
     }
+
+    /// Set the classifier of the streams created by peer from now on, see
+    /// [`StreamClassifier`].
+    pub fn set_classifier(&self, classifier: StreamClassifier) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.set_classifier(classifier);
+        }
+    }
+
+    /// Bound the accept queue of the class, see [`ClassPolicy`].
+    pub fn set_class_policy(&self, class: StreamClass, policy: ClassPolicy) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.set_class_policy(class, policy);
+        }
+    }
+
+    /// Some data of the stream is received, or it's reset, it may be ready to be classified.
+    pub(crate) fn on_stream_changed(&self, sid: StreamId) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.try_classify(sid);
+        }
+    }
+
+    /// The number of the streams created by peer but shed by the [`IncomingStreamPolicy`],
+    /// or by the [`ClassPolicy`], it's kept after the connection error.
+    pub fn shed_streams(&self) -> u64 {
+        self.1.load(Ordering::Relaxed)
+    }
+
+    /// Shed the streams queued to be accepted and the new streams created by peer, and
+    /// withhold the new MAX_STREAMS credit while `shedding`, to relieve the pressure of the
+    /// application. The accepted streams are not affected.
+    ///
+    /// The streams are only shed with an [`IncomingStreamPolicy`], which gives the code to
+    /// shed them with. The credit released meanwhile is issued once it stops shedding.
+    pub fn set_shedding(&self, shedding: bool) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.set_shedding(shedding);
+        }
+    }
+
+    /// Whether the listener is shedding, see [`ArcListener::set_shedding`].
+    pub fn is_shedding(&self) -> bool {
+        match self.0.lock().unwrap().as_ref() {
+            Ok(set) => set.shedding,
+            Err(_) => false,
+        }
+    }
+
+    /// Set the policy, the MAX_STREAMS credit is issued according to it from now on, see
+    /// [`ArcListener::poll_extend_credit`].
+    pub(crate) fn set_policy(&self, policy: IncomingStreamPolicy) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.set_policy(policy);
+        }
+    }
+
+    /// Keep the peer able to create `window` more streams in the direction beyond the
+    /// streams released, the `initial_max_streams` by default. A stream created by peer is
+    /// released once it's accepted, and both its [`Reader`] and [`Writer`] are dropped, or
+    /// once it's shed. Without it, a peer opening the streams one after another would be
+    /// stuck at the initial limit.
+    ///
+    /// It's overridden by the capacity of the [`IncomingStreamPolicy`] if set. Shrinking the
+    /// window doesn't withdraw the credit already issued.
+    pub fn set_window(&self, dir: Dir, window: u64) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.set_window(dir, window);
+        }
+    }
+
+    /// Return the new maximum number of the streams in the direction that peer can create,
+    /// once some streams are released and the credit grows. None if the connection is broken.
+    pub(crate) fn poll_extend_credit(&self, cx: &mut Context<'_>, dir: Dir) -> Poll<Option<u64>> {
+        match self.0.lock().unwrap().as_mut() {
+            Ok(set) => set.poll_extend_credit(cx, dir).map(Some),
+            Err(_) => Poll::Ready(None),
+        }
+    }
+
+    pub(super) fn on_in_flight_done(&self, dir: Dir) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.on_in_flight_done(dir);
+        }
+    }
+
+    fn expire(&self, sid: StreamId) {
+        if let Ok(set) = self.0.lock().unwrap().as_mut() {
+            set.expire(sid);
+        }
+    }
 }
 
 pub(crate) struct ListenerGuard<'a> {
     inner: MutexGuard<'a, Result<RawListener, QuicError>>,
+    listener: &'a ArcListener,
 }
 
-impl<'a> ListenerGuard<'a> {
-    pub(crate) fn push_bi_stream(&mut self, stream: (ArcRecver, ArcSender)) {
+impl ListenerGuard<'_> {
+    pub(crate) fn push_bi_stream(&mut self, sid: StreamId, stream: (ArcRecver, ArcSender)) {
         match self.inner.as_mut() {
-            Ok(set) => set.push_bi_stream(stream),
+            Ok(set) => {
+                if set.push_bi_stream(sid, stream) {
+                    self.expire_later(sid);
+                }
+            }
             Err(e) => unreachable!("listener is invalid: {e}"),
         }
     }
 
-    pub(crate) fn push_uni_stream(&mut self, stream: ArcRecver) {
+    pub(crate) fn push_uni_stream(&mut self, sid: StreamId, stream: ArcRecver) {
         match self.inner.as_mut() {
-            Ok(set) => set.push_recv_stream(stream),
+            Ok(set) => {
+                if set.push_recv_stream(sid, stream) {
+                    self.expire_later(sid);
+                }
+            }
             Err(e) => unreachable!("listener is invalid: {e}"),
         }
     }
 
+    /// 有接受策略时，排队的流超时未被接受则丢弃，运行时关闭之后则不再计时
+    fn expire_later(&self, sid: StreamId) {
+        let Ok(Some(policy)) = self.inner.as_ref().map(|set| set.policy) else {
+            return;
+        };
+        let listener = self.listener.clone();
+        try_spawn_traced(async move {
+            tokio::time::sleep(policy.queue_timeout).await;
+            listener.expire(sid);
+        });
+    }
+
     pub(crate) fn on_conn_error(&mut self, e: &QuicError) {
         match self.inner.as_mut() {
             Ok(set) => {
@@ -138,6 +677,13 @@ impl<'a> ListenerGuard<'a> {
                 if let Some(waker) = set.uni_waker.take() {
                     waker.wake();
                 }
+                if let Some(waker) = set.default_class_waker.take() {
+                    waker.wake();
+                }
+                set.wake_classes();
+                for waker in set.credit_wakers.iter_mut().filter_map(Option::take) {
+                    waker.wake();
+                }
             }
             Err(e) => unreachable!("listener is invalid: {e}"),
         };
@@ -171,3 +717,19 @@ impl Future for AcceptUniStream<'_> {
         self.inner.poll_accept_uni_stream(cx)
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct AcceptClass<'l> {
+    inner: &'l ArcListener,
+    class: StreamClass,
+    send_wnd_size: u64,
+}
+
+impl Future for AcceptClass<'_> {
+    type Output = Result<(Reader, Option<Writer>), QuicError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner
+            .poll_accept_class(cx, self.class, self.send_wnd_size)
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use qbase::streamid::Dir;
+
+use super::listener::ArcListener;
+
+/// The policy of accepting the streams created by peer, on top of the MAX_STREAMS limit,
+/// which applies to the bidirectional and the unidirectional streams separately.
+///
+/// At most `max_in_flight` accepted streams are being processed at the same time, the
+/// stream accepted takes a slot until both its [`Reader`] and [`Writer`] are dropped.
+/// The streams waiting to be accepted are queued, and shed with `shed_code` if they are
+/// not accepted within `queue_timeout`. The streams beyond `max_in_flight + queue_depth`
+/// are shed immediately, without being ever seen by the application.
+///
+/// Shedding a stream means sending STOP_SENDING, and RESET_STREAM as well for the
+/// bidirectional stream, with `shed_code`, the connection is not affected. The credit of
+/// the MAX_STREAMS frames only grows as the streams are released, so that a well-behaved
+/// peer will never have more streams open than the policy accepts.
+///
+/// [`Reader`]: crate::recv::Reader
+/// [`Writer`]: crate::send::Writer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncomingStreamPolicy {
+    /// The maximum number of the accepted streams being processed at the same time.
+    pub max_in_flight: u64,
+    /// How many streams can wait to be accepted, beyond the free slots of `max_in_flight`.
+    pub queue_depth: u64,
+    /// The application error code to shed the excess streams with.
+    pub shed_code: u64,
+    /// How long a stream can wait to be accepted before it's shed.
+    pub queue_timeout: Duration,
+}
+
+impl IncomingStreamPolicy {
+    /// 对方最多可同时拥有的流数，超出的流将被丢弃
+    pub(super) fn capacity(&self) -> u64 {
+        self.max_in_flight.saturating_add(self.queue_depth)
+    }
+}
+
+/// 被应用接受的流所占用的处理名额，其Reader和Writer都释放后，归还给监听器
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    listener: ArcListener,
+    dir: Dir,
+}
+
+impl InFlight {
+    pub(super) fn new(listener: ArcListener, dir: Dir) -> Self {
+        Self { listener, dir }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.listener.on_in_flight_done(self.dir);
+    }
+}
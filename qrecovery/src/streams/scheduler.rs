@@ -0,0 +1,103 @@
+use std::fmt::Debug;
+
+use qbase::streamid::StreamId;
+
+use crate::send::DEFAULT_WEIGHT;
+
+/// The bytes a stream of [`DEFAULT_WEIGHT`] sends in its turn by default, see [`RoundRobin`].
+pub const DEFAULT_QUANTUM: usize = 4096;
+
+/// A stream that may have data to send, see [`StreamScheduler::schedule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub sid: StreamId,
+    /// The urgency of the stream, lifted if it has lost data to send again, see
+    /// [`Outgoing::urgency`](crate::send::Outgoing::urgency).
+    pub urgency: u8,
+    /// See [`ArcSender::weight`](crate::send::ArcSender::weight).
+    pub weight: u16,
+}
+
+/// Decides which stream the next STREAM frame comes from, and how many bytes it carries at
+/// most. The CRYPTO and the control frames are always sent before any STREAM frame.
+///
+/// It's consulted for every STREAM frame with the streams that may have data to send, in
+/// the ascending order of the stream ids. The streams are tried in the order returned,
+/// the first one having data to send within the flow control sends it, then the scheduler
+/// is told by [`StreamScheduler::on_sent`].
+///
+/// The urgency and the weight of the streams are only hints, a custom scheduler may ignore
+/// them, like a FIFO draining the streams in the order they are opened.
+pub trait StreamScheduler: Debug + Send {
+    /// The streams to try in order, each with the most bytes it may send in the frame. The
+    /// streams left out don't send this time.
+    fn schedule(&mut self, candidates: &[Candidate]) -> Vec<(StreamId, usize)>;
+
+    /// The stream `sid`, granted `granted` bytes by [`StreamScheduler::schedule`], sent
+    /// `sent` bytes of data.
+    fn on_sent(&mut self, sid: StreamId, granted: usize, sent: usize);
+}
+
+/// The default [`StreamScheduler`]: the streams of a lower urgency are sent first, the
+/// streams of the same urgency take turns to send.
+///
+/// In its turn, a stream sends up to a quantum in proportion to its weight, [`DEFAULT_QUANTUM`]
+/// for [`DEFAULT_WEIGHT`] by default. A small quantum interleaves the streams finely, while a
+/// large one drains a stream before the next.
+#[derive(Debug, Clone)]
+pub struct RoundRobin {
+    quantum: usize,
+    // 当前轮到的流，及其本轮剩余的额度
+    cur: Option<(StreamId, usize)>,
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::with_quantum(DEFAULT_QUANTUM)
+    }
+}
+
+impl RoundRobin {
+    /// The quantum of the streams of [`DEFAULT_WEIGHT`], at least 1 byte.
+    pub fn with_quantum(quantum: usize) -> Self {
+        Self {
+            quantum: quantum.max(1),
+            cur: None,
+        }
+    }
+
+    fn quantum_of(&self, weight: u16) -> usize {
+        (self.quantum.saturating_mul(weight as usize) / DEFAULT_WEIGHT as usize).max(1)
+    }
+}
+
+impl StreamScheduler for RoundRobin {
+    fn schedule(&mut self, candidates: &[Candidate]) -> Vec<(StreamId, usize)> {
+        // 紧急级别低的流先发；同一紧急级别的流，还有额度的当前流继续，其后的流次之，其前的流
+        // （包括额度用完的当前流）最后，从头开始。额度每轮发放，不累积
+        let turn = |candidate: &Candidate| {
+            let quantum = self.quantum_of(candidate.weight);
+            match self.cur {
+                Some((cur, left)) if cur == candidate.sid && left > 0 => (0, left),
+                Some((cur, _)) if candidate.sid <= cur => (2, quantum),
+                _ => (1, quantum),
+            }
+        };
+        let mut order = candidates
+            .iter()
+            .map(|candidate| {
+                let (order, granted) = turn(candidate);
+                (candidate.urgency, order, candidate.sid, granted)
+            })
+            .collect::<Vec<_>>();
+        order.sort_unstable();
+        order
+            .into_iter()
+            .map(|(_urgency, _order, sid, granted)| (sid, granted))
+            .collect()
+    }
+
+    fn on_sent(&mut self, sid: StreamId, granted: usize, sent: usize) {
+        self.cur = Some((sid, granted.saturating_sub(sent)));
+    }
+}
@@ -0,0 +1,109 @@
+//! A tiny framing convention for carrying a trailer at the end of a stream, such as the
+//! status of a RPC response.
+//!
+//! The trailer is written as the last bytes of the stream, followed by its length encoded
+//! as a 2-byte big-endian integer:
+//!
+//! ```text
+//! Stream {
+//!   Body (..),
+//!   Trailer (..),
+//!   Trailer Length (16),
+//! }
+//! ```
+//!
+//! It is optional sugar, see [`Writer::finish_with`] and [`Reader::read_to_end_split_trailer`].
+//! The peer using plain reads just sees the trailer and its length as the final bytes.
+//!
+//! [`Writer::finish_with`]: crate::send::Writer::finish_with
+//! [`Reader::read_to_end_split_trailer`]: crate::recv::Reader::read_to_end_split_trailer
+use std::io;
+
+use bytes::{BufMut, Bytes};
+
+/// The size of the Trailer Length field.
+pub const TRAILER_LEN_SIZE: usize = 2;
+
+/// Encode the trailer followed by its length, which will be appended to the stream.
+pub fn encode(trailer: &[u8]) -> io::Result<Vec<u8>> {
+    let len = u16::try_from(trailer.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("trailer length {} exceeds {}", trailer.len(), u16::MAX),
+        )
+    })?;
+    let mut data = Vec::with_capacity(trailer.len() + TRAILER_LEN_SIZE);
+    data.put_slice(trailer);
+    data.put_u16(len);
+    Ok(data)
+}
+
+/// Split the whole data of the stream into the body and the trailer.
+///
+/// Return None as the trailer if the data is too short to carry the Trailer Length.
+/// Return an error if the trailer is larger than max_trailer_len, or than the data
+/// preceding its length.
+pub fn split(mut data: Bytes, max_trailer_len: usize) -> io::Result<(Bytes, Option<Bytes>)> {
+    let Some(body_len) = data.len().checked_sub(TRAILER_LEN_SIZE) else {
+        return Ok((data, None));
+    };
+    let trailer_len = u16::from_be_bytes([data[body_len], data[body_len + 1]]) as usize;
+    if trailer_len > max_trailer_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("trailer length {trailer_len} exceeds the limit {max_trailer_len}"),
+        ));
+    }
+    let Some(body_len) = body_len.checked_sub(trailer_len) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("trailer length {trailer_len} exceeds the data of {body_len} bytes"),
+        ));
+    };
+    let mut trailer = data.split_off(body_len);
+    trailer.truncate(trailer_len);
+    Ok((data, Some(trailer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_split() {
+        let mut data = b"hello world".to_vec();
+        data.extend(encode(b"status: ok").unwrap());
+
+        let (body, trailer) = split(Bytes::from(data), 16).unwrap();
+        assert_eq!(body, Bytes::from_static(b"hello world"));
+        assert_eq!(trailer, Some(Bytes::from_static(b"status: ok")));
+    }
+
+    #[test]
+    fn test_empty_body_and_trailer() {
+        let (body, trailer) = split(Bytes::from(encode(b"").unwrap()), 16).unwrap();
+        assert!(body.is_empty());
+        assert_eq!(trailer, Some(Bytes::new()));
+
+        let (body, trailer) = split(Bytes::from_static(b"x"), 16).unwrap();
+        assert_eq!(body, Bytes::from_static(b"x"));
+        assert_eq!(trailer, None);
+    }
+
+    #[test]
+    fn test_trailer_exceeds_limit() {
+        let mut data = b"hello world".to_vec();
+        data.extend(encode(b"status: ok").unwrap());
+
+        let err = split(Bytes::from(data), 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(encode(&[0; u16::MAX as usize + 1]).is_err());
+    }
+
+    #[test]
+    fn test_trailer_exceeds_data() {
+        // 声明的长度是5，前面却只有3个字节
+        let err = split(Bytes::from_static(b"abc\x00\x05"), 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -76,6 +76,8 @@ impl UdpSocketController {
             log::error!("Failed to bind socket: {}", e);
             return Err(io::Error::new(io::ErrorKind::AddrInUse, e));
         }
+        // tokio要求交给它的socket是非阻塞的
+        socket.set_nonblocking(true)?;
 
         let io =
             tokio::net::UdpSocket::from_std(socket.into()).expect("Failed to create tokio socket");
@@ -194,9 +196,16 @@ impl ArcUsc {
         if guard.bufs.len() >= BUFFER_CAPACITY {
             return Err(io::Error::new(io::ErrorKind::WouldBlock, "buffer full"));
         }
-        guard.bufs.push_back((packet, *hdr));
-        if guard.bufs.len() == 1 {
-            tokio::spawn({
+        if guard.bufs.is_empty() {
+            // 运行时正在关闭，无法派生发送任务，缓存的数据包将永远发不出去
+            let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+                log::debug!("no runtime available, the packet is not sent");
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "no runtime available",
+                ));
+            };
+            runtime.spawn({
                 let usc = self.clone();
                 async move {
                     let sync_guard = SyncGuard(usc);
@@ -204,6 +213,7 @@ impl ArcUsc {
                 }
             });
         }
+        guard.bufs.push_back((packet, *hdr));
         Ok(())
     }
 
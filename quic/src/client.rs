@@ -1,22 +1,38 @@
 use std::{
-    fs::File,
-    io::{self, BufReader},
+    io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::Path,
     sync::Arc,
+    time::SystemTime,
 };
 
 use qbase::{
     cid::ConnectionId,
-    config::{ClientParameters, Parameters},
-    token::{ArcTokenRegistry, TokenSink},
+    config::{ClientParameters, CommonParameters},
+    token::{ArcTokenRegistry, StatelessResetKey, TokenSink, TokenStore},
 };
-use qconnection::{connection::ArcConnection, path::Pathway};
+use qcongestion::{
+    congestion::{CongestionAlgorithm, LossDetectionConfig},
+    pacing::PacingConfig,
+};
+use qconnection::{
+    connection::{
+        profile::ConnectionProfile,
+        telemetry::{NetworkReport, NetworkTelemetry, PrefixLengths},
+        ArcConnection,
+    },
+    path::Pathway,
+};
+use qrecovery::streams::{crypto::CryptoLimits, scheduler::DEFAULT_QUANTUM};
 use rustls::{
     client::WantsClientCert, ClientConfig as TlsClientConfig, ConfigBuilder, WantsVerifier,
 };
 
-use crate::{get_usc_or_create, ConnKey, QuicConnection, CONNECTIONS};
+use crate::{
+    get_usc_or_create,
+    validate::{self, ConfigDiagnostic, DiagnosticKind, Validation, Validator},
+    ConnKey, QuicConnection, CONNECTIONS,
+};
 
 type TlsClientConfigBuilder<T> = ConfigBuilder<TlsClientConfig, T>;
 
@@ -25,10 +41,22 @@ pub struct QuicClient {
     addresses: Vec<SocketAddr>,
     _reuse_connection: bool,
     _enable_happy_eyepballs: bool,
-    _prefered_versions: Vec<u32>,
-    parameters: Parameters,
+    prefered_versions: Vec<u32>,
+    parameters: ClientParameters,
     tls_config: Arc<TlsClientConfig>,
-    token_sink: Option<Arc<dyn TokenSink>>,
+    token_sink: Arc<dyn TokenSink>,
+    max_initial_pto_count: Option<u32>,
+    undersized_initial: Option<usize>,
+    congestion_algorithm: CongestionAlgorithm,
+    pacing: PacingConfig,
+    loss_detection: LossDetectionConfig,
+    overhead_budget: Option<u64>,
+    crypto_limits: CryptoLimits,
+    stream_quantum: usize,
+    connection_profile: ConnectionProfile,
+    network_telemetry: Option<NetworkTelemetry>,
+    canonical_mapped_addresses: bool,
+    stateless_reset_key: StatelessResetKey,
 }
 
 impl QuicClient {
@@ -72,12 +100,34 @@ impl QuicClient {
             reuse_connection: true,
             enable_happy_eyepballs: false,
             preferred_versions: vec![1],
-            parameters: Parameters::default(),
+            parameters: ClientParameters::default(),
             tls_config: TlsClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS13]),
             token_sink: None,
+            max_initial_pto_count: None,
+            undersized_initial: None,
+            congestion_algorithm: CongestionAlgorithm::default(),
+            pacing: PacingConfig::default(),
+            loss_detection: LossDetectionConfig::default(),
+            overhead_budget: None,
+            crypto_limits: CryptoLimits::default(),
+            stream_quantum: DEFAULT_QUANTUM,
+            connection_profile: ConnectionProfile::default(),
+            network_telemetry: None,
+            canonical_mapped_addresses: true,
+            stateless_reset_key: StatelessResetKey::random(),
+            validator: Validator::default(),
         }
     }
 
+    /// 按服务端所在网段汇总的连接统计，自上次取走之后关闭的连接，取走后重新累计，
+    /// 见[`QuicClientBuilder::with_network_telemetry`]。未开启时总是空的。
+    pub fn network_report(&self) -> NetworkReport {
+        self.network_telemetry
+            .as_ref()
+            .map(NetworkTelemetry::take_report)
+            .unwrap_or_default()
+    }
+
     /// 重新绑定地址，其后创建的连接，会使用新的绑定地址
     pub fn rebind(&mut self, addresses: impl IntoIterator<Item = SocketAddr>) {
         self.addresses.clear();
@@ -103,35 +153,57 @@ impl QuicClient {
         server_name: impl Into<String>,
         server_addr: SocketAddr,
     ) -> io::Result<QuicConnection> {
-        let server_name = server_name.into();
-        let bind_addr = self
-            .addresses
-            .iter()
-            .find(|addr| addr.is_ipv4() == server_addr.is_ipv4())
-            .unwrap();
-
-        let usc = get_usc_or_create(bind_addr);
+        self.connect_any(server_name, [server_addr])
+    }
 
-        let pathway = Pathway::Direct {
-            local: usc.local_addr(),
-            remote: server_addr,
-        };
+    /// 同[`connect`]，但同时向server_name解析出的多个地址发起初始尝试，哪个地址先响应就用哪个，
+    /// 一个不可达的地址不会拖慢其他地址的尝试。
+    /// 没有绑定同族本地地址的server_addr会被跳过，若全部被跳过，则返回错误。
+    ///
+    /// 配合[`with_max_initial_pto_count`]，所有地址都不可达时，连接会尽快失败。
+    ///
+    /// [`connect`]: QuicClient::connect
+    /// [`with_max_initial_pto_count`]: QuicClientBuilder::with_max_initial_pto_count
+    pub fn connect_any(
+        &self,
+        server_name: impl Into<String>,
+        server_addrs: impl IntoIterator<Item = SocketAddr>,
+    ) -> io::Result<QuicConnection> {
+        let server_name = server_name.into();
+        let pathways = server_addrs
+            .into_iter()
+            .filter_map(|server_addr| {
+                let bind_addr = self
+                    .addresses
+                    .iter()
+                    .find(|addr| addr.is_ipv4() == server_addr.is_ipv4())?;
+                let usc = get_usc_or_create(bind_addr);
+                Some((Pathway::direct(usc.local_addr(), server_addr), usc))
+            })
+            .collect::<Vec<_>>();
+        if pathways.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no bound address of the same family as the server addresses",
+            ));
+        }
 
         let scid = std::iter::repeat_with(Self::gen_cid)
             .find(|cid| !CONNECTIONS.contains_key(&ConnKey::Client(*cid)))
             .unwrap();
 
-        let token_registry = match &self.token_sink {
-            Some(sink) => ArcTokenRegistry::with_sink(server_name.clone(), sink.clone()),
-            None => ArcTokenRegistry::default_sink(server_name.clone()),
-        };
+        let token_registry =
+            ArcTokenRegistry::with_sink(server_name.clone(), self.token_sink.clone());
 
         let inner = ArcConnection::new_client(
             scid,
             server_name,
             self.parameters,
+            self.connection_profile,
             self.tls_config.clone(),
             token_registry,
+            self.stateless_reset_key.clone(),
+            self.canonical_mapped_addresses,
         );
         let conn = QuicConnection {
             key: ConnKey::Client(scid),
@@ -139,7 +211,19 @@ impl QuicClient {
         };
 
         CONNECTIONS.insert(ConnKey::Client(scid), conn.clone());
-        inner.add_initial_path(pathway, usc);
+        inner.set_preferred_versions(self.prefered_versions.iter().copied());
+        inner.set_max_initial_pto_count(self.max_initial_pto_count);
+        inner.set_undersized_initial(self.undersized_initial);
+        inner.switch_congestion(self.congestion_algorithm);
+        inner.set_pacing(self.pacing);
+        inner.set_loss_detection(self.loss_detection);
+        inner.set_overhead_budget(self.overhead_budget);
+        inner.set_crypto_limits(self.crypto_limits);
+        inner.set_stream_quantum(self.stream_quantum);
+        inner.set_network_telemetry(self.network_telemetry.clone());
+        for (pathway, usc) in pathways {
+            inner.add_initial_path(pathway, usc);
+        }
         Ok(conn)
     }
 }
@@ -149,9 +233,22 @@ pub struct QuicClientBuilder<T> {
     reuse_connection: bool,
     enable_happy_eyepballs: bool,
     preferred_versions: Vec<u32>,
-    parameters: Parameters,
+    parameters: ClientParameters,
     tls_config: T,
     token_sink: Option<Arc<dyn TokenSink>>,
+    max_initial_pto_count: Option<u32>,
+    undersized_initial: Option<usize>,
+    congestion_algorithm: CongestionAlgorithm,
+    pacing: PacingConfig,
+    loss_detection: LossDetectionConfig,
+    overhead_budget: Option<u64>,
+    crypto_limits: CryptoLimits,
+    stream_quantum: usize,
+    connection_profile: ConnectionProfile,
+    network_telemetry: Option<PrefixLengths>,
+    canonical_mapped_addresses: bool,
+    stateless_reset_key: StatelessResetKey,
+    validator: Validator,
 }
 
 impl<T> QuicClientBuilder<T> {
@@ -170,6 +267,11 @@ impl<T> QuicClientBuilder<T> {
 
     /// 当服务端发来版本协商包，其中包含了支持的版本号，那么客户端可以选择使用哪个版本
     /// 将按照客户端设定的versions的顺序优先选择
+    ///
+    /// 首个Initial包使用其中第一个支持的或者保留的版本，比如0x?a?a?a?a，以保留版本试探服务端，
+    /// 借版本协商得知服务端支持的版本；不支持的版本会被略过，都不支持则用版本1，见[`Versions`]。
+    ///
+    /// [`Versions`]: qbase::version::Versions
     pub fn prefer_versions(mut self, versions: impl IntoIterator<Item = u32>) -> Self {
         self.preferred_versions.clear();
         self.preferred_versions.extend(versions);
@@ -180,28 +282,146 @@ impl<T> QuicClientBuilder<T> {
     /// 后续使用该QuicClient创建新连接，会直接使用这些参数。
     /// 可以多次调用该函数，覆盖上一次设置的参数。
     pub fn with_parameters(mut self, parameters: ClientParameters) -> Self {
-        self.parameters = parameters.into();
+        self.parameters = parameters;
         self
     }
 
     /// 设置客户端的证书，用于传输给服务端验证客户端身份
     /// 一般情况下，客户端都无需设置证书，只有特别的安全需求，才需要客户端提交证书
     /// 设置TokenRegisty的方法，当收到服务端的NewToken，客户端自行决定如何保存。
-    /// 如不设置，则会丢弃这些NewToken
+    /// 如不设置，则保存在内存中的[`TokenStore`]里，同一个QuicClient之后的连接会用上，
+    /// 但不会跨进程保留
     /// TokenSink会在创建新连接时，尝试根据server_name获取可用Token
     /// TokenSink还需保存服务端颁发的关联Token，以便未来连接时使用
     pub fn with_token_sink(mut self, sink: Arc<dyn TokenSink>) -> Self {
         self.token_sink = Some(sink);
         self
     }
+
+    /// 握手期间，连续探测超时count次仍未收到确认，就放弃这次尝试，而不是长时间地指数退避。
+    /// 从未收到过服务端任何包的连接，会以[`ConnectError::Unreachable`]失败；
+    /// 对端响应过却不再响应的，则是[`ConnectError::HandshakeTimeout`]。
+    /// 默认不设置，即不会提前放弃。
+    ///
+    /// [`ConnectError::Unreachable`]: qconnection::error::ConnectError::Unreachable
+    /// [`ConnectError::HandshakeTimeout`]: qconnection::error::ConnectError::HandshakeTimeout
+    pub fn with_max_initial_pto_count(mut self, count: u32) -> Self {
+        self.max_initial_pto_count = Some(count);
+        self
+    }
+
+    /// 违背协议规范的选项，仅用于私有部署，服务端也须开启，默认关闭。
+    /// 有的路径连1200字节的UDP数据报都会丢弃，握手永远无法完成。开启后，握手期间的探测超时
+    /// 耗尽（见[`with_max_initial_pto_count`]）仍无回应时，改发只填充到size字节的Initial包重试。
+    ///
+    /// [`with_max_initial_pto_count`]: QuicClientBuilder::with_max_initial_pto_count
+    pub fn allow_undersized_initial(mut self, size: u16) -> Self {
+        self.undersized_initial = Some(size as usize);
+        self
+    }
+
+    /// 新连接的各条路径使用的拥塞控制算法，缺省为[`CongestionAlgorithm::Bbr`]。
+    /// 连接建立后，仍可以通过[`ArcConnection::switch_congestion`]切换。
+    pub fn with_congestion_algorithm(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = algorithm;
+        self
+    }
+
+    /// 新连接的各条路径的发包节奏，缺省一次突发至多128个包，空闲超过一个RTO后拥塞窗口回到初始窗口。
+    /// 连接建立后，仍可以通过[`ArcConnection::set_pacing`]调整。
+    pub fn with_pacing(mut self, config: PacingConfig) -> Self {
+        self.pacing = config;
+        self
+    }
+
+    /// 新连接的各条路径判定丢包的包序阈值和时间阈值，乱序严重的路径上可以调高以避免虚假重传；
+    /// 以及探测超时指数退避的上限。
+    /// 连接建立后，仍可以通过[`ArcConnection::set_loss_detection`]调整。
+    pub fn with_loss_detection(mut self, config: LossDetectionConfig) -> Self {
+        self.loss_detection = config;
+        self
+    }
+
+    /// 新连接非应用发起的ack-eliciting流量（如路径验证）每分钟的字节数上限，缺省不限。
+    /// 应用数据和丢包恢复的探测包不受其限制，连接建立后，仍可以通过[`ArcConnection::set_overhead_budget`]调整。
+    pub fn with_overhead_budget(mut self, bytes_per_minute: Option<u64>) -> Self {
+        self.overhead_budget = bytes_per_minute;
+        self
+    }
+
+    /// 新连接每个Epoch的CRYPTO帧数据的上限，超出则以CRYPTO_BUFFER_EXCEEDED关闭连接，
+    /// 缺省允许至多256KB的证书链，见[`ArcConnection::set_crypto_limits`]。
+    pub fn with_crypto_limits(mut self, limits: CryptoLimits) -> Self {
+        self.crypto_limits = limits;
+        self
+    }
+
+    /// 新连接的流轮流发送时，缺省权重的流每轮发送的字节数，缺省为[`DEFAULT_QUANTUM`]。
+    /// 越小则各流交错得越细，越大则越倾向于发完一个流再发下一个。连接建立后，
+    /// 仍可以通过[`ArcConnection::set_stream_quantum`]调整，或以[`ArcConnection::set_stream_scheduler`]换用自定义的调度器。
+    ///
+    /// [`ArcConnection::set_stream_quantum`]: qconnection::connection::ArcConnection::set_stream_quantum
+    /// [`ArcConnection::set_stream_scheduler`]: qconnection::connection::ArcConnection::set_stream_scheduler
+    pub fn with_stream_quantum(mut self, quantum: usize) -> Self {
+        self.stream_quantum = quantum;
+        self
+    }
+
+    /// 新连接的用途，在连接创建时选定，缺省为[`ConnectionProfile::Full`]。
+    /// [`ConnectionProfile::DatagramOnly`]的连接只收发数据报，向对方通告的流数量为0，也不分配流的状态，
+    /// 打开或接受流都返回[`StreamsDisabled`]。
+    ///
+    /// [`StreamsDisabled`]: qconnection::error::StreamsDisabled
+    pub fn with_connection_profile(mut self, profile: ConnectionProfile) -> Self {
+        self.connection_profile = profile;
+        self
+    }
+
+    /// 开启按网段汇总的连接统计，连接关闭时向客户端汇报其摘要，见[`QuicClient::network_report`]。
+    /// 服务端的地址按`prefix_lengths`截为网段后才汇报，完整的地址从不保存，缺省不开启。
+    pub fn with_network_telemetry(mut self, prefix_lengths: PrefixLengths) -> Self {
+        self.network_telemetry = Some(prefix_lengths);
+        self
+    }
+
+    /// 双栈socket收到的IPv4服务端的地址形如`::ffff:a.b.c.d`，缺省规范化为IPv4地址，同一个服务端
+    /// 经由IPv4 socket和双栈socket到来，或者迁移之后，都是同一条路径。只有两种形式确实代表不同的
+    /// 对端时才关闭。这一设置在创建连接时即已决定，连接存续期间不变。
+    pub fn canonicalize_mapped_addresses(mut self, enabled: bool) -> Self {
+        self.canonical_mapped_addresses = enabled;
+        self
+    }
+
+    /// 派生无状态重置令牌的密钥，为发出的每个连接ID生成NEW_CONNECTION_ID帧中的令牌，缺省随机生成。
+    /// 同一个密钥对同一个连接ID总生成同一个令牌，见[`StatelessResetKey`]。
+    pub fn with_stateless_reset_key(mut self, key: StatelessResetKey) -> Self {
+        self.stateless_reset_key = key;
+        self
+    }
+
+    /// 如何校验配置，校验在[`build`]时进行，缺省拒绝错误、记录警告，
+    /// 见[`QuicClientBuilder::validate`]。
+    ///
+    /// [`build`]: QuicClientBuilder::build
+    pub fn with_validation(mut self, validation: Validation) -> Self {
+        self.validator.validation = validation;
+        self
+    }
 }
 
 impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
     /// 验证服务端证书，是否正常的方法
     pub fn with_root_certificates(
-        self,
+        mut self,
         root_store: impl Into<Arc<rustls::RootCertStore>>,
     ) -> QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
+        let root_store = root_store.into();
+        if root_store.is_empty() {
+            self.validator.report(ConfigDiagnostic::new(
+                DiagnosticKind::NoTrustAnchors,
+                "the root store is empty, no server certificate can be verified",
+            ));
+        }
         QuicClientBuilder {
             addresses: self.addresses,
             reuse_connection: self.reuse_connection,
@@ -210,6 +430,19 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_root_certificates(root_store),
             token_sink: self.token_sink,
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            validator: self.validator,
         }
     }
     pub fn with_webpki_verifier(
@@ -224,27 +457,51 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsVerifier>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_webpki_verifier(verifier),
             token_sink: self.token_sink,
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            validator: self.validator,
         }
     }
 }
 
 impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
+    /// 证书、私钥读取失败或不匹配，不会立即panic，而是留待[`QuicClientBuilder::validate`]报告
     pub fn with_cert(
-        self,
+        mut self,
         cert_file: impl AsRef<Path>,
         key_file: impl AsRef<Path>,
     ) -> QuicClientBuilder<TlsClientConfig> {
-        let cert_chain = rustls_pemfile::certs(&mut BufReader::new(
-            File::open(cert_file).expect("Failed to open cert file"),
-        ))
-        .collect::<Result<Vec<_>, _>>()
-        .expect("Failed to read and extract cert from the cert file");
-
-        let key_der = rustls_pemfile::private_key(&mut BufReader::new(
-            File::open(key_file).expect("Failed to open private key file"),
-        ))
-        .expect("Failed to read PEM sections from the private key file")
-        .unwrap();
+        let provider = self.tls_config.crypto_provider().clone();
+        let loaded = self
+            .validator
+            .load_certified_key("client", cert_file.as_ref(), key_file.as_ref(), &provider)
+            .map(|(cert_chain, key_der)| {
+                self.tls_config
+                    .clone()
+                    .with_client_auth_cert(cert_chain, key_der)
+            });
+        // 证书、私钥有问题的话，就不提交证书，由校验拒绝
+        let tls_config = match loaded {
+            Some(Ok(tls_config)) => tls_config,
+            Some(Err(e)) => {
+                self.validator.report(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidPrivateKey,
+                    format!("client: {e}"),
+                ));
+                self.tls_config.with_no_client_auth()
+            }
+            None => self.tls_config.with_no_client_auth(),
+        };
 
         QuicClientBuilder {
             addresses: self.addresses,
@@ -252,11 +509,21 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
             enable_happy_eyepballs: self.enable_happy_eyepballs,
             preferred_versions: self.preferred_versions,
             parameters: self.parameters,
-            tls_config: self
-                .tls_config
-                .with_client_auth_cert(cert_chain, key_der)
-                .expect("The private key was wrong encoded or failed validation"),
+            tls_config,
             token_sink: self.token_sink,
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            validator: self.validator,
         }
     }
 
@@ -269,6 +536,19 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_no_client_auth(),
             token_sink: self.token_sink,
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            validator: self.validator,
         }
     }
 
@@ -284,6 +564,19 @@ impl QuicClientBuilder<TlsClientConfigBuilder<WantsClientCert>> {
             parameters: self.parameters,
             tls_config: self.tls_config.with_client_cert_resolver(cert_resolver),
             token_sink: self.token_sink,
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            validator: self.validator,
         }
     }
 }
@@ -303,15 +596,98 @@ impl QuicClientBuilder<TlsClientConfig> {
         self
     }
 
-    pub fn build(self) -> QuicClient {
-        QuicClient {
+    /// 恢复会话时，在握手完成之前就以0-RTT包发送应用数据，缺省关闭。
+    ///
+    /// 此后对同一服务端的连接，以此前连接中服务端颁发的会话票据恢复会话，握手完成之前打开的流
+    /// 和发送的数据，受限于随票据一同记住的服务端传输参数。服务端拒绝0-RTT的话，这些数据透明地
+    /// 在1-RTT包中重传。0-RTT数据可能被攻击者重放，只应在握手完成之前发出幂等的请求。
+    pub fn enable_0rtt(mut self, flag: bool) -> Self {
+        self.tls_config.enable_early_data = flag;
+        self
+    }
+
+    /// Accept the certificate chain of the server compressed (RFC 8879) with brotli, compiled
+    /// in by the `cert-compression` feature, with which it is on by default. Without the
+    /// feature there is no algorithm to decompress with, enabling it only logs a warning; the
+    /// algorithms of your own go to [`with_cert_decompressors`].
+    ///
+    /// [`with_cert_decompressors`]: Self::with_cert_decompressors
+    pub fn enable_cert_compression(mut self, flag: bool) -> Self {
+        if flag && rustls::compress::default_cert_decompressors().is_empty() {
+            log::warn!(
+                "no certificate decompression algorithm, enable the `cert-compression` feature"
+            );
+        }
+        self.tls_config.cert_decompressors = match flag {
+            true => rustls::compress::default_cert_decompressors().to_vec(),
+            false => vec![],
+        };
+        self
+    }
+
+    /// Accept the certificate chain compressed with the given algorithms.
+    pub fn with_cert_decompressors(
+        mut self,
+        decompressors: impl IntoIterator<Item = &'static dyn rustls::compress::CertDecompressor>,
+    ) -> Self {
+        self.tls_config.cert_decompressors = decompressors.into_iter().collect();
+        self
+    }
+
+    /// 校验配置，返回发现的所有问题，包括警告，见[`DiagnosticKind`]。
+    ///
+    /// [`build`]时会自动校验，按[`with_validation`]设置的严格程度，拒绝或记录这些问题。
+    ///
+    /// [`build`]: Self::build
+    /// [`with_validation`]: QuicClientBuilder::with_validation
+    pub fn validate(&self) -> Result<(), Vec<ConfigDiagnostic>> {
+        match self.diagnose() {
+            diagnostics if diagnostics.is_empty() => Ok(()),
+            diagnostics => Err(diagnostics),
+        }
+    }
+
+    fn diagnose(&self) -> Vec<ConfigDiagnostic> {
+        let parameters = CommonParameters::from(self.parameters);
+        self.validator
+            .diagnose([("client", parameters)], false, SystemTime::now())
+    }
+
+    /// 校验配置，然后构建QuicClient。配置被校验拒绝的话，记录所有问题并返回
+    /// [`io::ErrorKind::InvalidInput`]错误，要逐一检查这些问题见[`try_build`]。
+    ///
+    /// [`try_build`]: Self::try_build
+    pub fn build(self) -> io::Result<QuicClient> {
+        self.try_build().map_err(validate::rejected)
+    }
+
+    /// 同[`build`]，但配置被校验拒绝时，在绑定任何地址之前返回所有问题，供逐一检查。
+    ///
+    /// [`build`]: Self::build
+    pub fn try_build(self) -> Result<QuicClient, Vec<ConfigDiagnostic>> {
+        self.validator.enforce(self.diagnose())?;
+        Ok(QuicClient {
             addresses: self.addresses,
             _reuse_connection: self.reuse_connection,
             _enable_happy_eyepballs: self.enable_happy_eyepballs,
-            _prefered_versions: self.preferred_versions,
+            prefered_versions: self.preferred_versions,
             parameters: self.parameters,
             tls_config: Arc::new(self.tls_config),
-            token_sink: self.token_sink,
-        }
+            token_sink: self
+                .token_sink
+                .unwrap_or_else(|| Arc::new(TokenStore::default())),
+            max_initial_pto_count: self.max_initial_pto_count,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry.map(NetworkTelemetry::new),
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+        })
     }
 }
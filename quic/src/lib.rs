@@ -1,4 +1,5 @@
 use std::{
+    io,
     net::SocketAddr,
     sync::{LazyLock, RwLock},
 };
@@ -8,13 +9,23 @@ use dashmap::DashMap;
 use deref_derive::Deref;
 use qbase::{
     cid::ConnectionId,
-    packet::{header::GetDcid, Packet, PacketReader, RetryHeader, VersionNegotiationHeader},
+    packet::{
+        error::Error as PacketError,
+        header::{Encode, GetDcid},
+        DataHeader, Packet, PacketReader, RetryHeader, VersionNegotiationHeader,
+    },
+};
+use qconnection::{
+    connection::ArcConnection,
+    drops::{DropReason, DROPS},
+    path::Pathway,
+    router::{RESET_TOKENS, ROUTER},
 };
-use qconnection::{connection::ArcConnection, path::Pathway, router::ROUTER};
 use qudp::ArcUsc;
 
 pub mod client;
 pub mod server;
+pub mod validate;
 
 pub use client::QuicClient;
 pub use server::QuicServer;
@@ -42,17 +53,35 @@ pub struct QuicConnection {
 }
 
 impl QuicConnection {
-    pub fn recv_version_negotiation(&self, _vn: &VersionNegotiationHeader) {
-        // self.inner.recv_version_negotiation(vn);
+    pub fn recv_version_negotiation(&self, vn: &VersionNegotiationHeader) -> bool {
+        self.inner.recv_version_negotiation(vn)
     }
 
-    pub fn recv_retry_packet(&self, retry: &RetryHeader) {
-        self.inner.recv_retry_packet(retry);
+    pub fn recv_retry_packet(&self, retry: &RetryHeader, packet: &[u8]) -> bool {
+        self.inner.recv_retry_packet(retry, packet)
     }
 
     pub fn update_path_recv_time(&self, pathway: Pathway) {
         self.inner.update_path_recv_time(pathway);
     }
+
+    /// 把连接迁移到新绑定的本地地址上，比如移动客户端切换了网络，服务端验证新路径后随之迁移，
+    /// 见[`ArcConnection::migrate`]。连接正在关闭或者已经关闭时，返回错误；服务端禁止了主动迁移，
+    /// 或者没有未用过的连接ID时，返回内含[`MigrationError`]的错误，连接仍留在原路径上
+    ///
+    /// [`MigrationError`]: qconnection::error::MigrationError
+    pub fn rebind(&self, bind_addr: SocketAddr) -> io::Result<()> {
+        let Some(active) = self.inner.active_pathway() else {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closing or closed",
+            ));
+        };
+        let usc = get_usc_or_create(&bind_addr);
+        let pathway = Pathway::direct(usc.local_addr(), active.remote_addr());
+        self.inner.migrate(pathway, usc)?;
+        Ok(())
+    }
 }
 
 impl Drop for QuicConnection {
@@ -72,39 +101,81 @@ pub fn get_usc_or_create(bind_addr: &SocketAddr) -> ArcUsc {
                     .zip(receive.iovecs.iter())
                     .take(msg_count)
                 {
-                    let data: BytesMut = buf[0..hdr.seg_size as usize].into();
-                    let pathway = Pathway::Direct {
-                        local: hdr.dst,
-                        remote: hdr.src,
-                    };
+                    let datagram = &buf[0..hdr.seg_size as usize];
+                    let data = BytesMut::from(datagram);
+                    let pathway = Pathway::direct(hdr.dst, hdr.src);
 
                     let reader = PacketReader::new(data, 8);
-                    for pkt in reader.flatten() {
+                    for pkt in reader {
+                        let pkt = match pkt {
+                            Ok(pkt) => pkt,
+                            // 解析失败后，数据报剩余部分都不再解析
+                            Err(e) => {
+                                let source = Some(pathway.remote_addr());
+                                DROPS.record(DropReason::from(&e), source, datagram);
+                                // 不支持的版本，服务端回应版本协商包
+                                if matches!(e, PacketError::UnsupportedVersion(_)) {
+                                    if let Some(server) = SERVER.read().unwrap().as_ref() {
+                                        server.send_version_negotiation(datagram, pathway, &usc);
+                                    }
+                                }
+                                break;
+                            }
+                        };
                         match pkt {
                             Packet::VN(vn) => {
                                 let key = ConnKey::Client(*vn.get_dcid());
                                 if let Some(conn) = CONNECTIONS.get(&key) {
-                                    conn.recv_version_negotiation(&vn);
-                                    conn.update_path_recv_time(pathway);
+                                    if conn.recv_version_negotiation(&vn) {
+                                        conn.update_path_recv_time(pathway);
+                                    } else {
+                                        let source = Some(pathway.remote_addr());
+                                        DROPS.record(
+                                            DropReason::UnsupportedVersion,
+                                            source,
+                                            datagram,
+                                        );
+                                    }
                                 } else {
-                                    log::error!("No connection found for VN packet");
+                                    let source = Some(pathway.remote_addr());
+                                    DROPS.record(DropReason::UnknownDcid, source, datagram);
                                 }
                             }
                             Packet::Retry(retry) => {
                                 let key = ConnKey::Client(*retry.get_dcid());
                                 if let Some(conn) = CONNECTIONS.get(&key) {
-                                    conn.recv_retry_packet(&retry);
-                                    conn.update_path_recv_time(pathway);
+                                    // Retry包没有长度字段，总是数据报中的最后一个包
+                                    let packet = &datagram[datagram.len() - retry.size()..];
+                                    if conn.recv_retry_packet(&retry, packet) {
+                                        conn.update_path_recv_time(pathway);
+                                    } else {
+                                        let source = Some(pathway.remote_addr());
+                                        DROPS.record(DropReason::Undecryptable, source, packet);
+                                    }
                                 } else {
-                                    log::error!("No connection found for Retry packet");
+                                    let source = Some(pathway.remote_addr());
+                                    DROPS.record(DropReason::UnknownDcid, source, datagram);
                                 }
                             }
                             Packet::Data(packet) => {
                                 if let Some(packet) =
                                     ROUTER.recv_packet_via_pathway(packet, pathway, &usc)
                                 {
+                                    // 无状态重置伪装成短包头的包，识别出来的从不回应，以免两端互相重置
+                                    if matches!(packet.header, DataHeader::Short(_))
+                                        && RESET_TOKENS.recv_stateless_reset(datagram)
+                                    {
+                                        break;
+                                    }
                                     if let Some(server) = SERVER.read().unwrap().as_ref() {
                                         server.recv_unmatched_packet(packet, pathway, &usc);
+                                    } else {
+                                        let source = Some(pathway.remote_addr());
+                                        DROPS.record(
+                                            DropReason::UnknownDcid,
+                                            source,
+                                            &packet.bytes,
+                                        );
                                     }
                                 }
                             }
@@ -1,32 +1,68 @@
 use std::{
-    fs::File,
-    io::{self, BufReader},
+    borrow::Cow,
+    io,
     net::SocketAddr,
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
 };
 
 use dashmap::DashMap;
 use deref_derive::Deref;
-use futures::SinkExt;
 use qbase::{
     cid::ConnectionId,
-    config::{Parameters, ServerParameters},
-    packet::{header::GetScid, long, DataHeader, DataPacket, InitialHeader, RetryHeader},
-    token::{ArcTokenRegistry, TokenProvider},
+    config::{CommonParameters, ServerParameters},
+    packet::{
+        header::{GetDcid, GetScid, WriteLongHeader},
+        long, DataHeader, DataPacket, InitialHeader, LongHeaderBuilder, RetryHeader,
+    },
+    token::{
+        be_retry_token, stateless_reset, AeadTokenProvider, ArcTokenRegistry, StatelessResetKey,
+        TokenAction, TokenFallback, TokenKind, TokenOutcome, TokenPolicy, TokenProvider,
+        TokenStats, TokenValidator,
+    },
     util::ArcAsyncDeque,
+    version::{self, version_negotiation, SUPPORTED_VERSIONS},
 };
-use qconnection::{connection::ArcConnection, path::Pathway, router::ROUTER};
+use qcongestion::{
+    congestion::{CongestionAlgorithm, LossDetectionConfig},
+    pacing::PacingConfig,
+};
+use qconnection::{
+    connection::{
+        pressure::PressureLevel,
+        profile::ConnectionProfile,
+        telemetry::{NetworkReport, NetworkTelemetry, PrefixLengths},
+        ArcConnection,
+    },
+    drops::{DropReason, DROPS},
+    path::{Pathway, ViaPathway},
+    router::ROUTER,
+};
+use qrecovery::streams::{crypto::CryptoLimits, scheduler::DEFAULT_QUANTUM};
 use qudp::ArcUsc;
 use rustls::{
     server::{danger::ClientCertVerifier, NoClientAuth, ResolvesServerCert, WantsServerCert},
     ConfigBuilder, ServerConfig as TlsServerConfig, WantsVerifier,
 };
 
-use crate::{get_usc_or_create, ConnKey, QuicConnection, CONNECTIONS, SERVER};
+use crate::{
+    get_usc_or_create,
+    validate::{self, ConfigDiagnostic, DiagnosticKind, Validation, Validator},
+    ConnKey, QuicConnection, CONNECTIONS, SERVER,
+};
+
+mod early_data;
+mod limits;
+use early_data::RememberingSessions;
+use limits::ConnectionTable;
+pub use limits::{
+    CandidateId, ConnectionLimits, EvictLongestIdle, EvictionCandidate, EvictionPolicy, RefuseNew,
+};
 
 type TlsServerConfigBuilder<T> = ConfigBuilder<TlsServerConfig, T>;
 type QuicListner = ArcAsyncDeque<(QuicConnection, SocketAddr)>;
+type ZeroRttPolicy = Arc<dyn Fn(SocketAddr) -> bool + Send + Sync + 'static>;
 
 #[derive(Debug, Default)]
 pub struct VirtualHosts(Arc<DashMap<String, Host>>);
@@ -49,14 +85,34 @@ impl ResolvesServerCert for VirtualHosts {
 /// 要想有服务端的功能，得至少有一个usc可以收包。
 /// 如果不创建QuicServer，那意味着不接收新连接
 pub struct RawQuicServer {
+    // 实际绑定的地址，绑定端口0的，已替换为分配到的端口
     addresses: Vec<SocketAddr>,
     listener: QuicListner,
-    _restrict: bool,
-    _supported_versions: Vec<u32>,
+    restrict: bool,
+    supported_versions: Vec<u32>,
     _load_balance: Arc<dyn Fn(InitialHeader) -> Option<RetryHeader> + Send + Sync + 'static>,
-    _parameters: DashMap<String, Parameters>,
+    parameters: DashMap<String, ServerParameters>,
     tls_config: Arc<TlsServerConfig>,
-    token_provider: Option<Arc<dyn TokenProvider + Send + Sync + 'static>>,
+    token_validator: TokenValidator,
+    accept_0rtt_datagrams: bool,
+    // 决定是否接受某客户端的0-RTT数据，接受的连接改用允许0-RTT的TLS配置
+    zero_rtt: Option<(ZeroRttPolicy, Arc<TlsServerConfig>)>,
+    undersized_initial: Option<usize>,
+    congestion_algorithm: CongestionAlgorithm,
+    pacing: PacingConfig,
+    loss_detection: LossDetectionConfig,
+    overhead_budget: Option<u64>,
+    crypto_limits: CryptoLimits,
+    stream_quantum: usize,
+    connection_profile: ConnectionProfile,
+    network_telemetry: Option<NetworkTelemetry>,
+    canonical_mapped_addresses: bool,
+    stateless_reset_key: StatelessResetKey,
+    // 在保持的连接，从准入直到彻底终结
+    connections: Arc<Mutex<ConnectionTable<ArcConnection>>>,
+    eviction_reason: Cow<'static, str>,
+    // 应用告知的负载压力，新连接也要沿用
+    pressure: Mutex<PressureLevel>,
 }
 
 #[derive(Clone, Deref)]
@@ -67,6 +123,13 @@ impl QuicServer {
     /// 监听地址可以有多个，但必须都得是本地能绑定成功的，否则会panic
     /// 监听地址若为空，则会默认创建一个
     /// 严格模式是指，只有在这些地址上收到并创建的新连接，才会被接受
+    ///
+    /// 多个监听地址，比如多个端口、分别绑定的v4和v6地址，每个地址各有一个收包任务，但共用
+    /// 同一个路由、同一个[`accept`]队列和同一套[`ConnectionLimits`]。连接从哪个地址到来，就经由
+    /// 哪个地址回包，见[`ArcConnection::local_addr`]；客户端换用另一个监听地址，只是连接的路径迁移。
+    ///
+    /// [`accept`]: RawQuicServer::accept
+    /// [`ArcConnection::local_addr`]: qconnection::connection::ArcConnection::local_addr
     pub fn bind(
         addresses: impl IntoIterator<Item = SocketAddr>,
         restrict: bool,
@@ -83,6 +146,24 @@ impl QuicServer {
             .with_protocol_versions(&[&rustls::version::TLS13])
             .unwrap(),
             token_provider: None,
+            token_policy: TokenPolicy::default(),
+            accept_0rtt_datagrams: false,
+            zero_rtt_policy: None,
+            undersized_initial: None,
+            congestion_algorithm: CongestionAlgorithm::default(),
+            pacing: PacingConfig::default(),
+            loss_detection: LossDetectionConfig::default(),
+            overhead_budget: None,
+            crypto_limits: CryptoLimits::default(),
+            stream_quantum: DEFAULT_QUANTUM,
+            connection_profile: ConnectionProfile::default(),
+            network_telemetry: None,
+            canonical_mapped_addresses: true,
+            stateless_reset_key: StatelessResetKey::random(),
+            connection_limits: ConnectionLimits::default(),
+            eviction_policy: Arc::new(RefuseNew),
+            eviction_reason: Cow::Borrowed("evicted for new connections"),
+            validator: Validator::default(),
         }
     }
 }
@@ -111,38 +192,248 @@ impl RawQuicServer {
     }
 
     pub fn recv_unmatched_packet(&self, packet: DataPacket, pathway: Pathway, usc: &ArcUsc) {
-        let (index, initial_dcid) = match &packet.header {
-            DataHeader::Long(hdr @ long::DataHeader::Initial(_)) => (0, *hdr.get_scid()),
-            DataHeader::Long(hdr @ long::DataHeader::ZeroRtt(_)) => (1, *hdr.get_scid()),
-            _ => return,
+        // 新连接沿用服务端的设置，令牌、准入以及接受时报告的都是连接所用形式的地址
+        let pathway = if self.canonical_mapped_addresses {
+            pathway.canonical()
+        } else {
+            pathway
         };
+        let initial_dcid = match &packet.header {
+            DataHeader::Long(hdr @ long::DataHeader::Initial(_)) => *hdr.get_scid(),
+            DataHeader::Long(hdr @ long::DataHeader::ZeroRtt(_)) => *hdr.get_scid(),
+            DataHeader::Short(hdr) => {
+                let source = Some(pathway.remote_addr());
+                DROPS.record(DropReason::UnknownDcid, source, &packet.bytes);
+                self.send_stateless_reset(hdr.get_dcid(), packet.bytes.len(), pathway, usc);
+                return;
+            }
+            _ => {
+                let source = Some(pathway.remote_addr());
+                DROPS.record(DropReason::UnknownDcid, source, &packet.bytes);
+                return;
+            }
+        };
+        // 严格模式下，客户端的usc等其他地址上收到的包，不能创建新连接
+        if self.restrict && !self.addresses.contains(&usc.local_addr()) {
+            let source = Some(pathway.remote_addr());
+            DROPS.record(DropReason::UnknownDcid, source, &packet.bytes);
+            return;
+        }
         let initial_scid =
             std::iter::repeat_with(|| ConnectionId::random_gen_with_mark(8, 0, 0x7F))
                 .find(|cid| !CONNECTIONS.contains_key(&ConnKey::Server(*cid)))
                 .unwrap();
 
-        let token_provider = match &self.token_provider {
-            Some(provider) => ArcTokenRegistry::with_provider(provider.clone()),
-            None => ArcTokenRegistry::default_provider(),
+        // 客户端首个Initial包的目的连接ID，经过Retry的话则由令牌带回
+        let packet_dcid = *packet.header.get_dcid();
+        let (mut odcid, mut retry_scid) = (packet_dcid, None);
+        // 严格地处置新连接的Initial包中的Token，见RFC 9000 8.1
+        if let DataHeader::Long(long::DataHeader::Initial(initial)) = &packet.header {
+            let (outcome, action) = self.token_validator.validate(
+                &initial.token,
+                pathway.remote_addr(),
+                initial.get_dcid(),
+            );
+            if outcome == TokenOutcome::Valid(TokenKind::Retry) {
+                if let Some((retry_odcid, _)) = be_retry_token(&initial.token) {
+                    (odcid, retry_scid) = (retry_odcid, Some(packet_dcid));
+                }
+            }
+            match action {
+                TokenAction::Discard => {
+                    let source = Some(pathway.remote_addr());
+                    DROPS.record(DropReason::InvalidToken, source, &packet.bytes);
+                    return;
+                }
+                TokenAction::Retry => {
+                    self.send_retry(initial, pathway, usc);
+                    return;
+                }
+                // Accept、Proceed、Close都交由连接自己处理
+                _ => {}
+            }
+        }
+        let token_provider = ArcTokenRegistry::with_validator(self.token_validator.clone());
+
+        // 超出连接数的限制，又腾不出位置，则丢弃新连接的包，客户端的重传也将同样被丢弃
+        let mut connections = self.connections.lock().unwrap();
+        let Some(admission) = connections.admit(
+            pathway.remote_addr(),
+            Instant::now(),
+            ArcConnection::activity,
+        ) else {
+            let source = Some(pathway.remote_addr());
+            DROPS.record(DropReason::ConnectionLimit, source, &packet.bytes);
+            return;
         };
 
-        let initial_keys = self.initial_server_keys(initial_dcid);
+        // Initial包的密钥由其目的连接ID派生
+        let initial_keys = self.initial_server_keys(packet_dcid);
+        // 收到Initial包时还不知道SNI，只能用泛域名的参数
+        let mut parameters = self
+            .parameters
+            .get("*")
+            .map(|parameters| *parameters)
+            .unwrap_or_default();
+        parameters.set_original_destination_connection_id(Some(odcid));
+        parameters.set_retry_source_connection_id(retry_scid);
+        // 按策略接受其0-RTT数据的连接，改用允许0-RTT的配置，否则rustls拒绝之
+        let tls_config = match &self.zero_rtt {
+            Some((policy, tls_config)) if policy(pathway.remote_addr()) => tls_config.clone(),
+            _ => self.tls_config.clone(),
+        };
         let inner = ArcConnection::new_server(
             initial_scid,
             initial_dcid,
-            Parameters::default(), // &self.parameters,
+            parameters,
+            self.connection_profile,
             initial_keys,
-            self.tls_config.clone(),
+            tls_config,
             token_provider,
+            self.stateless_reset_key.clone(),
+            self.canonical_mapped_addresses,
         );
+        inner.set_accept_0rtt_datagrams(self.accept_0rtt_datagrams);
+        inner.set_undersized_initial(self.undersized_initial);
+        inner.switch_congestion(self.congestion_algorithm);
+        inner.set_pacing(self.pacing);
+        inner.set_loss_detection(self.loss_detection);
+        inner.set_overhead_budget(self.overhead_budget);
+        inner.set_crypto_limits(self.crypto_limits);
+        inner.set_stream_quantum(self.stream_quantum);
+        inner.set_pressure_level(*self.pressure.lock().unwrap());
+        inner.set_network_telemetry(self.network_telemetry.clone());
+        connections.attach(admission.id, inner.clone());
+        drop(connections);
+
+        for victim in admission.victims {
+            victim.close(0, self.eviction_reason.clone());
+        }
+        // 客户端收到服务端的Initial包之前，仍以原来的目的连接ID发送Initial包和0-RTT包，
+        // 这些包也要路由到该连接，而不是再创建新的连接
+        if let Some(entries) = ROUTER.get(&initial_scid).map(|entries| entries.clone()) {
+            ROUTER.insert(packet_dcid, entries);
+        }
+        // 无论握手是否成功，连接走完closing或draining状态后，才释放其名额
+        tokio::spawn({
+            let connections = self.connections.clone();
+            let inner = inner.clone();
+            async move {
+                inner.terminated().await;
+                ROUTER.remove(&packet_dcid);
+                connections.lock().unwrap().release(admission.id);
+            }
+        });
         let conn = QuicConnection {
             key: ConnKey::Server(initial_scid),
             inner,
         };
         self.listener.push((conn.clone(), pathway.remote_addr()));
-        if let Some(mut entry) = ROUTER.get_mut(&initial_scid) {
-            _ = entry[index].send((packet, pathway, usc.clone()));
+        _ = ROUTER.recv_packet_via_pathway(packet, pathway, usc);
+    }
+
+    /// 回复Retry包，让客户端携带新的Token重新发起连接，以验证客户端的地址
+    fn send_retry(&self, initial: &InitialHeader, pathway: Pathway, usc: &ArcUsc) {
+        let retry_scid = std::iter::repeat_with(|| ConnectionId::random_gen_with_mark(8, 0, 0x7F))
+            .find(|cid| !CONNECTIONS.contains_key(&ConnKey::Server(*cid)))
+            .unwrap();
+        // 令牌绑定客户端的地址和原始的目的连接ID，服务端无需为此保存任何状态
+        let token = self
+            .token_validator
+            .retry_token(pathway.remote_addr(), &initial.dcid);
+        let retry =
+            LongHeaderBuilder::with_cid(initial.scid, retry_scid).retry(&initial.dcid, token);
+
+        let mut packet = Vec::new();
+        packet.put_long_header(&retry);
+        if let Err(e) = usc.clone().sync_send_via_path_way(packet, pathway) {
+            log::warn!("failed to send Retry packet: {e}");
+        }
+    }
+
+    /// 客户端的Initial包用了不支持的版本，回复版本协商包，列出所支持的版本以及一个保留版本，
+    /// 见RFC 9000 6.1。配置的版本中本实现并不支持的不会列出，未配置则列出所有支持的版本；
+    /// 太小的数据报不回应，以免被用于放大攻击。
+    pub fn send_version_negotiation(&self, datagram: &[u8], pathway: Pathway, usc: &ArcUsc) {
+        // 严格模式下，客户端的usc等其他地址上收到的包，不能创建新连接，也就无需协商版本
+        if self.restrict && !self.addresses.contains(&usc.local_addr()) {
+            return;
+        }
+        let mut versions = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|v| version::is_supported(*v))
+            .collect::<Vec<_>>();
+        if versions.is_empty() {
+            versions.extend(SUPPORTED_VERSIONS);
+        }
+        let Some(packet) = version_negotiation(datagram, &versions) else {
+            return;
+        };
+        if let Err(e) = usc.clone().sync_send_via_path_way(packet, pathway) {
+            log::warn!("failed to send Version Negotiation packet: {e}");
+        }
+    }
+
+    /// 对方可能丢失了连接的状态，比如服务端重启过，以无状态重置告知对方，见RFC 9000 10.3。
+    /// 无状态重置总比收到的包小，以免两端无休止地互相重置；收到的包太小就不回应。
+    fn send_stateless_reset(
+        &self,
+        dcid: &ConnectionId,
+        received: usize,
+        pathway: Pathway,
+        usc: &ArcUsc,
+    ) {
+        let token = self.stateless_reset_key.reset_token(dcid);
+        let Some(reset) = stateless_reset(&token, received) else {
+            return;
         };
+        if let Err(e) = usc.clone().sync_send_via_path_way(reset, pathway) {
+            log::warn!("failed to send stateless reset: {e}");
+        }
+    }
+
+    /// The count of each classification of the tokens in the Initial packets of new connections.
+    pub fn token_stats(&self) -> &Arc<TokenStats> {
+        self.token_validator.stats()
+    }
+
+    /// 告知服务端应用的负载压力，比如CPU已饱和，作用于所有已有的和新来的连接，见
+    /// [`ArcConnection::set_pressure_level`]。压力之下优先保障进行中的传输，压力解除后一切恢复。
+    ///
+    /// [`ArcConnection::set_pressure_level`]: qconnection::connection::ArcConnection::set_pressure_level
+    pub fn set_pressure_level(&self, level: PressureLevel) {
+        *self.pressure.lock().unwrap() = level;
+        for conn in self.connections.lock().unwrap().connections() {
+            conn.set_pressure_level(level);
+        }
+    }
+
+    /// 当前的负载压力，见[`QuicServer::set_pressure_level`]
+    pub fn pressure_level(&self) -> PressureLevel {
+        *self.pressure.lock().unwrap()
+    }
+
+    /// 按客户端所在网段汇总的连接统计，自上次取走之后关闭的连接，取走后重新累计，
+    /// 见[`QuicServerBuilder::with_network_telemetry`]。未开启时总是空的。
+    pub fn network_report(&self) -> NetworkReport {
+        self.network_telemetry
+            .as_ref()
+            .map(NetworkTelemetry::take_report)
+            .unwrap_or_default()
+    }
+
+    /// The count of the connections kept by the listener, including the ones still
+    /// handshaking and the ones closing or draining.
+    pub fn active_connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// The count of the connections kept from the client of `addr`, see
+    /// [`ConnectionLimits::ipv6_prefix_len`].
+    pub fn active_connections_of(&self, addr: SocketAddr) -> usize {
+        self.connections.lock().unwrap().len_of(addr)
     }
 
     /// 监听新连接的到来
@@ -171,9 +462,27 @@ pub struct QuicServerBuilder<T> {
     restrict: bool,
     supported_versions: Vec<u32>,
     load_balance: Arc<dyn Fn(InitialHeader) -> Option<RetryHeader> + Send + Sync + 'static>,
-    parameters: DashMap<String, Parameters>,
+    parameters: DashMap<String, ServerParameters>,
     tls_config: T,
     token_provider: Option<Arc<dyn TokenProvider + Send + Sync + 'static>>,
+    token_policy: TokenPolicy,
+    accept_0rtt_datagrams: bool,
+    zero_rtt_policy: Option<ZeroRttPolicy>,
+    undersized_initial: Option<usize>,
+    congestion_algorithm: CongestionAlgorithm,
+    pacing: PacingConfig,
+    loss_detection: LossDetectionConfig,
+    overhead_budget: Option<u64>,
+    crypto_limits: CryptoLimits,
+    stream_quantum: usize,
+    connection_profile: ConnectionProfile,
+    network_telemetry: Option<PrefixLengths>,
+    canonical_mapped_addresses: bool,
+    stateless_reset_key: StatelessResetKey,
+    connection_limits: ConnectionLimits,
+    eviction_policy: Arc<dyn EvictionPolicy>,
+    eviction_reason: Cow<'static, str>,
+    validator: Validator,
 }
 
 pub struct QuicServerSniBuilder<T> {
@@ -182,13 +491,31 @@ pub struct QuicServerSniBuilder<T> {
     supported_versions: Vec<u32>,
     load_balance: Arc<dyn Fn(InitialHeader) -> Option<RetryHeader> + Send + Sync + 'static>,
     hosts: Arc<DashMap<String, Host>>,
-    parameters: DashMap<String, Parameters>,
+    parameters: DashMap<String, ServerParameters>,
     tls_config: T,
     token_provider: Option<Arc<dyn TokenProvider + Send + Sync + 'static>>,
+    token_policy: TokenPolicy,
+    accept_0rtt_datagrams: bool,
+    zero_rtt_policy: Option<ZeroRttPolicy>,
+    undersized_initial: Option<usize>,
+    congestion_algorithm: CongestionAlgorithm,
+    pacing: PacingConfig,
+    loss_detection: LossDetectionConfig,
+    overhead_budget: Option<u64>,
+    crypto_limits: CryptoLimits,
+    stream_quantum: usize,
+    connection_profile: ConnectionProfile,
+    network_telemetry: Option<PrefixLengths>,
+    canonical_mapped_addresses: bool,
+    stateless_reset_key: StatelessResetKey,
+    connection_limits: ConnectionLimits,
+    eviction_policy: Arc<dyn EvictionPolicy>,
+    eviction_reason: Cow<'static, str>,
+    validator: Validator,
 }
 
 impl<T> QuicServerBuilder<T> {
-    pub fn with_supported_versions(mut self, versions: impl IntoIterator<Item = u32>) -> Self {
+    pub fn withsupported_versions(mut self, versions: impl IntoIterator<Item = u32>) -> Self {
         self.supported_versions.clear();
         self.supported_versions.extend(versions);
         self
@@ -207,18 +534,228 @@ impl<T> QuicServerBuilder<T> {
     /// TokenProvider有2个功能：
     /// TokenProvider需要向客户端颁发新Token
     /// 同时，收到新连接，TokenProvider也要验证客户端的Initial包中的Token
+    ///
+    /// 缺省为随机密钥的[`AeadTokenProvider`]，重启之后此前颁发的令牌都不再有效。
     pub fn with_token_provider(mut self, token_provider: Arc<dyn TokenProvider>) -> Self {
         self.token_provider = Some(token_provider);
         self
     }
+
+    /// 如何处置新连接的Initial包中的Token，包括空Token，以及各种原因验证失败的Token，
+    /// 缺省策略见[`TokenPolicy`]
+    pub fn with_token_policy(mut self, token_policy: TokenPolicy) -> Self {
+        self.token_policy = token_policy;
+        self
+    }
+
+    /// 是否要求新连接先经过Retry验证客户端的地址，缺省不要求，见RFC 9000 8.1.2。
+    ///
+    /// 要求时，不带有效令牌的Initial包都以Retry包回应，客户端携带其中的令牌重新发送Initial包，
+    /// 握手多花一个往返，但伪造源地址的Initial包不能再让服务端创建连接的状态。
+    /// 只改变[`TokenPolicy`]对空令牌的处置，之后再设置[`with_token_policy`]则以其为准。
+    ///
+    /// [`with_token_policy`]: QuicServerBuilder::with_token_policy
+    pub fn require_retry(mut self, required: bool) -> Self {
+        let action = match required {
+            true => TokenFallback::Retry,
+            false => TokenFallback::Proceed,
+        };
+        self.token_policy = self.token_policy.on_empty(action);
+        self
+    }
+
+    /// 握手确认之后，通过NEW_TOKEN帧向客户端颁发多少个令牌，缺省1个，0则不颁发。
+    ///
+    /// 客户端之后的连接在Initial包中携带其中一个，服务端验证有效即视其地址已验证，
+    /// 即便[`require_retry`]也不必再Retry；令牌过期或无效时，按[`TokenPolicy`]退回空令牌的处置。
+    /// 只改变[`TokenPolicy`]颁发令牌的数量，之后再设置[`with_token_policy`]则以其为准。
+    ///
+    /// [`require_retry`]: QuicServerBuilder::require_retry
+    /// [`with_token_policy`]: QuicServerBuilder::with_token_policy
+    pub fn issue_new_tokens(mut self, count: usize) -> Self {
+        self.token_policy = self.token_policy.new_tokens(count);
+        self
+    }
+
+    /// 是否接受0-RTT数据包中的Datagram，缺省不接受。
+    /// 0-RTT数据可能被攻击者重放，只有应用能容忍Datagram被重复投递时，才应开启
+    pub fn accept_0rtt_datagrams(mut self, accept: bool) -> Self {
+        self.accept_0rtt_datagrams = accept;
+        self
+    }
+
+    /// 是否接受恢复会话的客户端在握手完成之前发来的0-RTT数据，缺省不接受。
+    ///
+    /// 开启后颁发的会话票据允许客户端发送0-RTT数据，会话缓存一并记下服务端的额度，恢复会话时
+    /// 当前的额度比记住的小，就不再恢复该会话，见RFC 9000 7.4.1。0-RTT数据可能被攻击者重放，
+    /// 应用应只在握手完成之前处理幂等的请求。逐个客户端决定见[`with_0rtt_policy`]。
+    ///
+    /// [`with_0rtt_policy`]: QuicServerBuilder::with_0rtt_policy
+    pub fn enable_0rtt(mut self, enable: bool) -> Self {
+        self.zero_rtt_policy = enable.then(|| Arc::new(|_: SocketAddr| true) as ZeroRttPolicy);
+        self
+    }
+
+    /// 同[`enable_0rtt`]，但由policy按客户端的地址决定是否接受其0-RTT数据。
+    ///
+    /// 拒绝的连接仍然可以恢复会话，只是0-RTT数据被丢弃，客户端随后在1-RTT包中重传。
+    ///
+    /// [`enable_0rtt`]: QuicServerBuilder::enable_0rtt
+    pub fn with_0rtt_policy(
+        mut self,
+        policy: impl Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.zero_rtt_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// 违背协议规范的选项，仅用于私有部署，客户端也须开启，默认关闭。
+    /// 收到不足1200字节的Initial包的路径，其后只发送size字节的数据报，
+    /// 以配合客户端的[`allow_undersized_initial`]。
+    ///
+    /// [`allow_undersized_initial`]: crate::client::QuicClientBuilder::allow_undersized_initial
+    pub fn allow_undersized_initial(mut self, size: u16) -> Self {
+        self.undersized_initial = Some(size as usize);
+        self
+    }
+
+    /// 新连接的各条路径使用的拥塞控制算法，缺省为[`CongestionAlgorithm::Bbr`]。
+    /// 连接建立后，仍可以通过[`ArcConnection::switch_congestion`]切换。
+    ///
+    /// [`ArcConnection::switch_congestion`]: qconnection::connection::ArcConnection::switch_congestion
+    pub fn with_congestion_algorithm(mut self, algorithm: CongestionAlgorithm) -> Self {
+        self.congestion_algorithm = algorithm;
+        self
+    }
+
+    /// 新连接的各条路径的发包节奏，缺省一次突发至多128个包，空闲超过一个RTO后拥塞窗口回到初始窗口。
+    /// 连接建立后，仍可以通过[`ArcConnection::set_pacing`]调整。
+    ///
+    /// [`ArcConnection::set_pacing`]: qconnection::connection::ArcConnection::set_pacing
+    pub fn with_pacing(mut self, config: PacingConfig) -> Self {
+        self.pacing = config;
+        self
+    }
+
+    /// 新连接的各条路径判定丢包的包序阈值和时间阈值，乱序严重的路径上可以调高以避免虚假重传；
+    /// 以及探测超时指数退避的上限。
+    /// 连接建立后，仍可以通过[`ArcConnection::set_loss_detection`]调整。
+    ///
+    /// [`ArcConnection::set_loss_detection`]: qconnection::connection::ArcConnection::set_loss_detection
+    pub fn with_loss_detection(mut self, config: LossDetectionConfig) -> Self {
+        self.loss_detection = config;
+        self
+    }
+
+    /// 新连接非应用发起的ack-eliciting流量（如路径验证）每分钟的字节数上限，缺省不限。
+    /// 应用数据和丢包恢复的探测包不受其限制，连接建立后，仍可以通过[`ArcConnection::set_overhead_budget`]调整。
+    ///
+    /// [`ArcConnection::set_overhead_budget`]: qconnection::connection::ArcConnection::set_overhead_budget
+    pub fn with_overhead_budget(mut self, bytes_per_minute: Option<u64>) -> Self {
+        self.overhead_budget = bytes_per_minute;
+        self
+    }
+
+    /// 新连接每个Epoch的CRYPTO帧数据的上限，超出则以CRYPTO_BUFFER_EXCEEDED关闭连接，
+    /// 缺省允许至多256KB的证书链，见[`ArcConnection::set_crypto_limits`]。
+    ///
+    /// [`ArcConnection::set_crypto_limits`]: qconnection::connection::ArcConnection::set_crypto_limits
+    pub fn with_crypto_limits(mut self, limits: CryptoLimits) -> Self {
+        self.crypto_limits = limits;
+        self
+    }
+
+    /// 新连接的流轮流发送时，缺省权重的流每轮发送的字节数，缺省为[`DEFAULT_QUANTUM`]。
+    /// 越小则各流交错得越细，越大则越倾向于发完一个流再发下一个。连接建立后，
+    /// 仍可以通过[`ArcConnection::set_stream_quantum`]调整，或以[`ArcConnection::set_stream_scheduler`]换用自定义的调度器。
+    ///
+    /// [`ArcConnection::set_stream_quantum`]: qconnection::connection::ArcConnection::set_stream_quantum
+    /// [`ArcConnection::set_stream_scheduler`]: qconnection::connection::ArcConnection::set_stream_scheduler
+    pub fn with_stream_quantum(mut self, quantum: usize) -> Self {
+        self.stream_quantum = quantum;
+        self
+    }
+
+    /// 新连接的用途，在连接创建时选定，缺省为[`ConnectionProfile::Full`]。
+    /// [`ConnectionProfile::DatagramOnly`]的连接只收发数据报，向对方通告的流数量为0，也不分配流的状态，
+    /// 打开或接受流都返回[`StreamsDisabled`]。
+    ///
+    /// [`StreamsDisabled`]: qconnection::error::StreamsDisabled
+    pub fn with_connection_profile(mut self, profile: ConnectionProfile) -> Self {
+        self.connection_profile = profile;
+        self
+    }
+
+    /// 开启按网段汇总的连接统计，连接关闭时向服务端汇报其摘要，见[`QuicServer::network_report`]。
+    /// 客户端的地址按`prefix_lengths`截为网段后才汇报，完整的地址从不保存，缺省不开启。
+    ///
+    /// [`QuicServer::network_report`]: RawQuicServer::network_report
+    pub fn with_network_telemetry(mut self, prefix_lengths: PrefixLengths) -> Self {
+        self.network_telemetry = Some(prefix_lengths);
+        self
+    }
+
+    /// 双栈socket收到的IPv4客户端的地址形如`::ffff:a.b.c.d`，缺省规范化为IPv4地址，同一个客户端
+    /// 经由IPv4 socket和双栈socket到来，或者迁移之后，都是同一条路径。只有两种形式确实代表不同的
+    /// 对端时才关闭。这一设置在创建连接时即已决定，连接存续期间不变。
+    pub fn canonicalize_mapped_addresses(mut self, enabled: bool) -> Self {
+        self.canonical_mapped_addresses = enabled;
+        self
+    }
+
+    /// 派生无状态重置令牌的密钥，缺省随机生成。同一个密钥对同一个连接ID总生成同一个令牌，
+    /// 因此重启的服务端沿用原来的密钥，即可以无状态重置告知客户端旧的连接已不复存在，见[`StatelessResetKey`]。
+    pub fn with_stateless_reset_key(mut self, key: StatelessResetKey) -> Self {
+        self.stateless_reset_key = key;
+        self
+    }
+
+    /// 同时保持的连接数的上限，包括总数和每个客户端地址的连接数，缺省不限制。
+    /// 超出每个地址的上限的新连接总被拒绝，超出总数的新连接由[`with_eviction_policy`]决定。
+    ///
+    /// [`with_eviction_policy`]: QuicServerBuilder::with_eviction_policy
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// 新连接超出[`ConnectionLimits::max_connections`]时，驱逐哪些连接为其腾出位置，
+    /// 缺省为[`RefuseNew`]，即拒绝新连接。
+    /// 被驱逐的连接以错误码0和reason优雅地关闭，即发送应用层的CONNECTION_CLOSE帧。
+    pub fn with_eviction_policy(
+        mut self,
+        policy: Arc<dyn EvictionPolicy>,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.eviction_policy = policy;
+        self.eviction_reason = reason.into();
+        self
+    }
+
+    /// 如何校验配置，校验在[`listen`]绑定地址之前进行，缺省拒绝错误、记录警告，
+    /// 见[`QuicServerBuilder::validate`]。
+    ///
+    /// [`listen`]: QuicServerBuilder::listen
+    pub fn with_validation(mut self, validation: Validation) -> Self {
+        self.validator.validation = validation;
+        self
+    }
 }
 
 impl QuicServerBuilder<TlsServerConfigBuilder<WantsVerifier>> {
     /// Choose how to verify client certificates.
     pub fn with_cert_verifier(
-        self,
+        mut self,
         client_cert_verifier: Arc<dyn ClientCertVerifier>,
     ) -> QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
+        if client_cert_verifier.client_auth_mandatory()
+            && client_cert_verifier.root_hint_subjects().is_empty()
+        {
+            self.validator.report(ConfigDiagnostic::new(
+                DiagnosticKind::ClientAuthWithoutRoots,
+                "client certificates are mandatory, but the verifier names no root subject",
+            ));
+        }
         QuicServerBuilder {
             addresses: self.addresses,
             restrict: self.restrict,
@@ -229,6 +766,24 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsVerifier>> {
                 .tls_config
                 .with_client_cert_verifier(client_cert_verifier),
             token_provider: self.token_provider,
+            token_policy: self.token_policy,
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt_policy: self.zero_rtt_policy,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connection_limits: self.connection_limits,
+            eviction_policy: self.eviction_policy,
+            eviction_reason: self.eviction_reason,
+            validator: self.validator,
         }
     }
 
@@ -246,6 +801,24 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsVerifier>> {
                 .tls_config
                 .with_client_cert_verifier(Arc::new(NoClientAuth)),
             token_provider: self.token_provider,
+            token_policy: self.token_policy,
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt_policy: self.zero_rtt_policy,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connection_limits: self.connection_limits,
+            eviction_policy: self.eviction_policy,
+            eviction_reason: self.eviction_reason,
+            validator: self.validator,
         }
     }
 }
@@ -259,27 +832,25 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
     /// [`with_single_cert`]: QuicServerBuilder::with_single_cert
     /// [`with_single_cert_with_ocsp`]: QuicServerBuilder::with_single_cert_with_ocsp
     pub fn with_parameters(self, parameters: ServerParameters) -> Self {
-        self.parameters.insert("*".to_owned(), parameters.into());
+        self.parameters.insert("*".to_owned(), parameters);
         self
     }
 
     /// 所有的Host都符合泛域名证书的话，可以用该函数
+    ///
+    /// 证书、私钥读取失败或不匹配，不会立即panic，而是留待[`QuicServerBuilder::validate`]报告
     pub fn with_single_cert(
-        self,
+        mut self,
         cert_file: impl AsRef<Path>,
         key_file: impl AsRef<Path>,
     ) -> QuicServerBuilder<TlsServerConfig> {
-        let cert_chain = rustls_pemfile::certs(&mut BufReader::new(
-            File::open(cert_file).expect("Failed to open cert file"),
-        ))
-        .collect::<Result<Vec<_>, _>>()
-        .expect("Failed to read and extract cert from the cert file");
-
-        let key_der = rustls_pemfile::private_key(&mut BufReader::new(
-            File::open(key_file).expect("Failed to open private key file"),
-        ))
-        .expect("Failed to read PEM sections from the private key file")
-        .unwrap();
+        let tls_config = single_cert(
+            self.tls_config,
+            &mut self.validator,
+            cert_file.as_ref(),
+            key_file.as_ref(),
+            None,
+        );
 
         QuicServerBuilder {
             addresses: self.addresses,
@@ -287,31 +858,42 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
             supported_versions: self.supported_versions,
             load_balance: self.load_balance,
             parameters: self.parameters,
-            tls_config: self
-                .tls_config
-                .with_single_cert(cert_chain, key_der)
-                .expect("The private key was wrong encoded or failed validation"),
+            tls_config,
             token_provider: self.token_provider,
+            token_policy: self.token_policy,
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt_policy: self.zero_rtt_policy,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connection_limits: self.connection_limits,
+            eviction_policy: self.eviction_policy,
+            eviction_reason: self.eviction_reason,
+            validator: self.validator,
         }
     }
 
     pub fn with_single_cert_with_ocsp(
-        self,
+        mut self,
         cert_file: impl AsRef<Path>,
         key_file: impl AsRef<Path>,
         ocsp: Vec<u8>,
     ) -> QuicServerBuilder<TlsServerConfig> {
-        let cert_chain = rustls_pemfile::certs(&mut BufReader::new(
-            File::open(cert_file).expect("Failed to open cert file"),
-        ))
-        .collect::<Result<Vec<_>, _>>()
-        .expect("Failed to read and extract cert from the cert file");
-
-        let key_der = rustls_pemfile::private_key(&mut BufReader::new(
-            std::fs::File::open(key_file).expect("Failed to open private key file"),
-        ))
-        .expect("Failed to read PEM sections from the private key file")
-        .unwrap();
+        let tls_config = single_cert(
+            self.tls_config,
+            &mut self.validator,
+            cert_file.as_ref(),
+            key_file.as_ref(),
+            Some(ocsp),
+        );
 
         QuicServerBuilder {
             addresses: self.addresses,
@@ -319,11 +901,26 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
             supported_versions: self.supported_versions,
             load_balance: self.load_balance,
             parameters: self.parameters,
-            tls_config: self
-                .tls_config
-                .with_single_cert_with_ocsp(cert_chain, key_der, ocsp)
-                .expect("The private key was wrong encoded or failed validation"),
+            tls_config,
             token_provider: self.token_provider,
+            token_policy: self.token_policy,
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt_policy: self.zero_rtt_policy,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connection_limits: self.connection_limits,
+            eviction_policy: self.eviction_policy,
+            eviction_reason: self.eviction_reason,
+            validator: self.validator,
         }
     }
 
@@ -341,6 +938,24 @@ impl QuicServerBuilder<TlsServerConfigBuilder<WantsServerCert>> {
                 .with_cert_resolver(Arc::new(VirtualHosts(hosts.clone()))),
             hosts,
             token_provider: self.token_provider,
+            token_policy: self.token_policy,
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt_policy: self.zero_rtt_policy,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry,
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connection_limits: self.connection_limits,
+            eviction_policy: self.eviction_policy,
+            eviction_reason: self.eviction_reason,
+            validator: self.validator,
         }
     }
 }
@@ -349,34 +964,30 @@ impl QuicServerSniBuilder<TlsServerConfig> {
     /// 添加服务器，包括证书链、私钥、参数
     /// 可以调用多次，支持多服务器，支持TLS SNI
     /// 若是新连接的server_name没有对应的配置，则会被拒绝
+    ///
+    /// 证书、私钥读取失败或不匹配的Host不会被添加，留待[`QuicServerSniBuilder::validate`]报告
     pub fn add_host(
         &mut self,
         server_name: impl Into<String>,
         cert_file: impl AsRef<Path>,
         key_file: impl AsRef<Path>,
-        parameters: Parameters,
+        parameters: ServerParameters,
     ) -> &mut Self {
-        let cert_chain = rustls_pemfile::certs(&mut BufReader::new(
-            File::open(cert_file).expect("Failed to open cert file"),
-        ))
-        .collect::<Result<Vec<_>, _>>()
-        .expect("Failed to read and extract cert from the cert file");
-
-        let key_der = rustls_pemfile::private_key(&mut BufReader::new(
-            std::fs::File::open(key_file).expect("Failed to open private key file"),
-        ))
-        .expect("Failed to read PEM sections from the private key file")
-        .unwrap();
-
-        let private_key = self
-            .tls_config
-            .crypto_provider()
-            .key_provider
-            .load_private_key(key_der)
-            .unwrap();
-
         let server_name = server_name.into();
         self.parameters.insert(server_name.clone(), parameters);
+
+        let provider = self.tls_config.crypto_provider();
+        let Some((cert_chain, key_der)) = self.validator.load_certified_key(
+            &server_name,
+            cert_file.as_ref(),
+            key_file.as_ref(),
+            provider,
+        ) else {
+            return self;
+        };
+        // 上面已经加载过一次，不会失败
+        let private_key = provider.key_provider.load_private_key(key_der).unwrap();
+
         self.hosts.insert(
             server_name,
             Host {
@@ -389,41 +1000,277 @@ impl QuicServerSniBuilder<TlsServerConfig> {
 }
 
 impl QuicServerBuilder<TlsServerConfig> {
-    pub fn listen(self) -> QuicServer {
-        for addr in &self.addresses {
-            _ = get_usc_or_create(addr);
+    /// Compress the certificate chain (RFC 8879) with brotli, compiled in by the
+    /// `cert-compression` feature, with which it is on by default. Without the feature
+    /// there is no algorithm to compress with, enabling it only logs a warning; the
+    /// algorithms of your own go to [`with_cert_compressors`].
+    ///
+    /// A large certificate chain may exceed the anti-amplification budget of the first
+    /// flight, costing an extra round trip, see [`HandshakeStats`].
+    ///
+    /// [`with_cert_compressors`]: Self::with_cert_compressors
+    /// [`HandshakeStats`]: qconnection::tls::HandshakeStats
+    pub fn enable_cert_compression(mut self, flag: bool) -> Self {
+        self.tls_config.cert_compressors = cert_compressors(flag);
+        self
+    }
+
+    /// Compress the certificate chain with the given algorithms, in the order of preference.
+    pub fn with_cert_compressors(
+        mut self,
+        compressors: impl IntoIterator<Item = &'static dyn rustls::compress::CertCompressor>,
+    ) -> Self {
+        self.tls_config.cert_compressors = compressors.into_iter().collect();
+        self
+    }
+
+    /// 校验配置，返回发现的所有问题，包括警告，见[`DiagnosticKind`]。
+    ///
+    /// [`listen`]时会自动校验，按[`with_validation`]设置的严格程度，拒绝或记录这些问题。
+    ///
+    /// [`listen`]: Self::listen
+    /// [`with_validation`]: QuicServerBuilder::with_validation
+    pub fn validate(&self) -> Result<(), Vec<ConfigDiagnostic>> {
+        match diagnose(&self.validator, &self.parameters) {
+            diagnostics if diagnostics.is_empty() => Ok(()),
+            diagnostics => Err(diagnostics),
         }
+    }
+
+    /// 同[`listen`]，但配置被校验拒绝时，在绑定任何地址之前返回所有问题。
+    ///
+    /// [`listen`]: Self::listen
+    pub fn try_listen(self) -> Result<QuicServer, Vec<ConfigDiagnostic>> {
+        let diagnostics = diagnose(&self.validator, &self.parameters);
+        self.validator.enforce(diagnostics)?;
+
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|addr| get_usc_or_create(addr).local_addr())
+            .collect();
+        let (tls_config, zero_rtt) =
+            early_data_configs(self.tls_config, &self.parameters, self.zero_rtt_policy);
         let quic_server = QuicServer(Arc::new(RawQuicServer {
-            addresses: self.addresses,
+            addresses,
             listener: Default::default(),
-            _restrict: self.restrict,
-            _supported_versions: self.supported_versions,
+            restrict: self.restrict,
+            supported_versions: self.supported_versions,
             _load_balance: self.load_balance,
-            _parameters: self.parameters,
-            tls_config: Arc::new(self.tls_config),
-            token_provider: self.token_provider,
+            parameters: self.parameters,
+            tls_config,
+            token_validator: TokenValidator::new(
+                self.token_provider
+                    .unwrap_or_else(|| Arc::new(AeadTokenProvider::random())),
+                self.token_policy,
+            ),
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry.map(NetworkTelemetry::new),
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connections: Arc::new(Mutex::new(ConnectionTable::new(
+                self.connection_limits,
+                self.eviction_policy,
+            ))),
+            eviction_reason: self.eviction_reason,
+            pressure: Mutex::new(PressureLevel::Normal),
         }));
         *SERVER.write().unwrap() = Some(quic_server.clone());
-        quic_server
+        Ok(quic_server)
+    }
+
+    /// 校验配置，然后绑定地址开始监听。配置被校验拒绝的话，记录所有问题并返回
+    /// [`io::ErrorKind::InvalidInput`]错误，要逐一检查这些问题见[`try_listen`]。
+    ///
+    /// [`try_listen`]: Self::try_listen
+    pub fn listen(self) -> io::Result<QuicServer> {
+        self.try_listen().map_err(validate::rejected)
     }
 }
 
 impl QuicServerSniBuilder<TlsServerConfig> {
-    pub fn listen(self) -> QuicServer {
-        for addr in &self.addresses {
-            _ = get_usc_or_create(addr);
+    /// Compress the certificate chains of all the hosts, see
+    /// [`QuicServerBuilder::enable_cert_compression`].
+    pub fn enable_cert_compression(mut self, flag: bool) -> Self {
+        self.tls_config.cert_compressors = cert_compressors(flag);
+        self
+    }
+
+    /// Compress the certificate chain with the given algorithms, in the order of preference.
+    pub fn with_cert_compressors(
+        mut self,
+        compressors: impl IntoIterator<Item = &'static dyn rustls::compress::CertCompressor>,
+    ) -> Self {
+        self.tls_config.cert_compressors = compressors.into_iter().collect();
+        self
+    }
+
+    /// 校验配置，返回发现的所有问题，包括警告，见[`DiagnosticKind`]。
+    ///
+    /// [`listen`]时会自动校验，按[`with_validation`]设置的严格程度，拒绝或记录这些问题。
+    ///
+    /// [`listen`]: Self::listen
+    /// [`with_validation`]: QuicServerBuilder::with_validation
+    pub fn validate(&self) -> Result<(), Vec<ConfigDiagnostic>> {
+        match diagnose(&self.validator, &self.parameters) {
+            diagnostics if diagnostics.is_empty() => Ok(()),
+            diagnostics => Err(diagnostics),
         }
+    }
+
+    /// 同[`listen`]，但配置被校验拒绝时，在绑定任何地址之前返回所有问题。
+    ///
+    /// [`listen`]: Self::listen
+    pub fn try_listen(self) -> Result<QuicServer, Vec<ConfigDiagnostic>> {
+        let diagnostics = diagnose(&self.validator, &self.parameters);
+        self.validator.enforce(diagnostics)?;
+
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|addr| get_usc_or_create(addr).local_addr())
+            .collect();
+        let (tls_config, zero_rtt) =
+            early_data_configs(self.tls_config, &self.parameters, self.zero_rtt_policy);
         let quic_server = QuicServer(Arc::new(RawQuicServer {
-            addresses: self.addresses,
+            addresses,
             listener: Default::default(),
-            _restrict: self.restrict,
-            _supported_versions: self.supported_versions,
+            restrict: self.restrict,
+            supported_versions: self.supported_versions,
             _load_balance: self.load_balance,
-            _parameters: self.parameters,
-            tls_config: Arc::new(self.tls_config),
-            token_provider: self.token_provider,
+            parameters: self.parameters,
+            tls_config,
+            token_validator: TokenValidator::new(
+                self.token_provider
+                    .unwrap_or_else(|| Arc::new(AeadTokenProvider::random())),
+                self.token_policy,
+            ),
+            accept_0rtt_datagrams: self.accept_0rtt_datagrams,
+            zero_rtt,
+            undersized_initial: self.undersized_initial,
+            congestion_algorithm: self.congestion_algorithm,
+            pacing: self.pacing,
+            loss_detection: self.loss_detection,
+            overhead_budget: self.overhead_budget,
+            crypto_limits: self.crypto_limits,
+            stream_quantum: self.stream_quantum,
+            connection_profile: self.connection_profile,
+            network_telemetry: self.network_telemetry.map(NetworkTelemetry::new),
+            canonical_mapped_addresses: self.canonical_mapped_addresses,
+            stateless_reset_key: self.stateless_reset_key,
+            connections: Arc::new(Mutex::new(ConnectionTable::new(
+                self.connection_limits,
+                self.eviction_policy,
+            ))),
+            eviction_reason: self.eviction_reason,
+            pressure: Mutex::new(PressureLevel::Normal),
         }));
         *SERVER.write().unwrap() = Some(quic_server.clone());
-        quic_server
+        Ok(quic_server)
+    }
+
+    /// 校验配置，然后绑定地址开始监听。配置被校验拒绝的话，记录所有问题并返回
+    /// [`io::ErrorKind::InvalidInput`]错误，要逐一检查这些问题见[`try_listen`]。
+    ///
+    /// [`try_listen`]: Self::try_listen
+    pub fn listen(self) -> io::Result<QuicServer> {
+        self.try_listen().map_err(validate::rejected)
+    }
+}
+
+// 开启0-RTT的，会话缓存一并记下服务端的额度，另备一份允许0-RTT的配置供接受0-RTT的连接使用，
+// 两份配置共用同一个会话缓存。QUIC要求max_early_data_size要么是0，要么是u32::MAX
+fn early_data_configs(
+    mut tls_config: TlsServerConfig,
+    parameters: &DashMap<String, ServerParameters>,
+    policy: Option<ZeroRttPolicy>,
+) -> (
+    Arc<TlsServerConfig>,
+    Option<(ZeroRttPolicy, Arc<TlsServerConfig>)>,
+) {
+    let Some(policy) = policy else {
+        return (Arc::new(tls_config), None);
+    };
+    // 新连接用的是泛域名的参数，见RawQuicServer::recv_unmatched_packet
+    let current = parameters
+        .get("*")
+        .map(|parameters| **parameters)
+        .unwrap_or_default();
+    tls_config.session_storage = Arc::new(RememberingSessions::new(
+        tls_config.session_storage.clone(),
+        current,
+    ));
+    let mut early_data_config = tls_config.clone();
+    early_data_config.max_early_data_size = u32::MAX;
+    (
+        Arc::new(tls_config),
+        Some((policy, Arc::new(early_data_config))),
+    )
+}
+
+// 没有编译进压缩算法的话，开启了也没法压缩，只能提醒一下
+fn cert_compressors(flag: bool) -> Vec<&'static dyn rustls::compress::CertCompressor> {
+    if flag && rustls::compress::default_cert_compressors().is_empty() {
+        log::warn!("no certificate compression algorithm, enable the `cert-compression` feature");
+    }
+    match flag {
+        true => rustls::compress::default_cert_compressors().to_vec(),
+        false => vec![],
     }
 }
+
+// 证书、私钥有问题的话，没有可用的证书，握手都会失败，由校验拒绝
+fn single_cert(
+    tls_config: TlsServerConfigBuilder<WantsServerCert>,
+    validator: &mut Validator,
+    cert_file: &Path,
+    key_file: &Path,
+    ocsp: Option<Vec<u8>>,
+) -> TlsServerConfig {
+    let provider = tls_config.crypto_provider().clone();
+    let loaded = validator
+        .load_certified_key("*", cert_file, key_file, &provider)
+        .map(|(cert_chain, key_der)| match ocsp {
+            Some(ocsp) => tls_config
+                .clone()
+                .with_single_cert_with_ocsp(cert_chain, key_der, ocsp),
+            None => tls_config.clone().with_single_cert(cert_chain, key_der),
+        });
+    match loaded {
+        Some(Ok(tls_config)) => tls_config,
+        Some(Err(e)) => {
+            validator.report(ConfigDiagnostic::new(
+                DiagnosticKind::InvalidPrivateKey,
+                format!("*: {e}"),
+            ));
+            tls_config.with_cert_resolver(Arc::new(VirtualHosts::default()))
+        }
+        None => tls_config.with_cert_resolver(Arc::new(VirtualHosts::default())),
+    }
+}
+
+fn diagnose(
+    validator: &Validator,
+    parameters: &DashMap<String, ServerParameters>,
+) -> Vec<ConfigDiagnostic> {
+    let parameters = parameters
+        .iter()
+        .map(|entry| (entry.key().clone(), CommonParameters::from(*entry.value())))
+        .collect::<Vec<_>>();
+    validator.diagnose(
+        parameters
+            .iter()
+            .map(|(label, parameters)| (label.as_str(), *parameters)),
+        true,
+        SystemTime::now(),
+    )
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use qbase::{
+    config::{Parameters, RememberedField},
+    varint::{be_varint, WriteVarInt},
+};
+use rustls::server::StoresServerSessions;
+
+/// The session cache of the server, which remembers the limits of the server's transport
+/// parameters along with each session it issues.
+///
+/// The client resuming a session sends 0-RTT data within the limits remembered with it, a
+/// server that accepts 0-RTT data MUST NOT lower any of them, see
+/// [RFC 9000 section 7.4.1](https://www.rfc-editor.org/rfc/rfc9000.html#name-values-of-transport-parameters-for-0-rtt).
+/// If any of the current limits is lower than the remembered one, the session is not resumed,
+/// so the 0-RTT data is rejected, and the client replays it within the current limits.
+#[derive(Debug)]
+pub(crate) struct RememberingSessions {
+    sessions: Arc<dyn StoresServerSessions>,
+    parameters: Parameters,
+}
+
+impl RememberingSessions {
+    pub(crate) fn new(sessions: Arc<dyn StoresServerSessions>, parameters: Parameters) -> Self {
+        Self {
+            sessions,
+            parameters,
+        }
+    }
+
+    // 核对记住的额度，剥离之后交还给rustls
+    fn restore(&self, value: Vec<u8>) -> Option<Vec<u8>> {
+        let mut remain = value.as_slice();
+        for field in RememberedField::ALL {
+            let (rest, remembered) = be_varint(remain).ok()?;
+            if field.value_of(&self.parameters) < remembered {
+                log::debug!("{field} is lowered since the session was issued, refuse to resume");
+                return None;
+            }
+            remain = rest;
+        }
+        Some(remain.to_vec())
+    }
+}
+
+impl StoresServerSessions for RememberingSessions {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let mut remembered = Vec::with_capacity(RememberedField::ALL.len() * 8 + value.len());
+        for field in RememberedField::ALL {
+            remembered.put_varint(&field.value_of(&self.parameters));
+        }
+        remembered.extend(value);
+        self.sessions.put(key, remembered)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.sessions.get(key).and_then(|value| self.restore(value))
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.sessions
+            .take(key)
+            .and_then(|value| self.restore(value))
+    }
+
+    fn can_cache(&self) -> bool {
+        self.sessions.can_cache()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qbase::varint::VarInt;
+    use rustls::server::ServerSessionMemoryCache;
+
+    use super::*;
+
+    #[test]
+    fn refuse_lowered_limits() {
+        let cache: Arc<dyn StoresServerSessions> = ServerSessionMemoryCache::new(4);
+        let issued = RememberingSessions::new(cache.clone(), Parameters::default());
+        assert!(issued.put(b"a".to_vec(), b"session a".to_vec()));
+        assert!(issued.put(b"b".to_vec(), b"session b".to_vec()));
+        assert_eq!(issued.get(b"a").as_deref(), Some(&b"session a"[..]));
+
+        // 放宽的额度不影响恢复会话
+        let mut relaxed = Parameters::default();
+        relaxed.set_initial_max_data(VarInt::from_u32(1 << 24));
+        let sessions = RememberingSessions::new(cache.clone(), relaxed);
+        assert_eq!(sessions.take(b"a").as_deref(), Some(&b"session a"[..]));
+        assert_eq!(sessions.take(b"a"), None);
+
+        let mut lowered = Parameters::default();
+        lowered.set_initial_max_streams_bidi(VarInt::from_u32(1));
+        let sessions = RememberingSessions::new(cache, lowered);
+        assert_eq!(sessions.take(b"b"), None);
+    }
+}
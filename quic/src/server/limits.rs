@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use qconnection::connection::activity::Activity;
+
+/// The limits on the connections a listener keeps at the same time.
+///
+/// A connection takes its slot from the first packet accepted, until it's completely
+/// terminated, which is after the closing or draining state, no matter whether the
+/// handshake succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// The most connections the listener keeps, unlimited if None. When a new connection
+    /// would exceed it, the [`EvictionPolicy`] decides to refuse it or to evict some others.
+    pub max_connections: Option<usize>,
+    /// The most connections from one client address, unlimited if None. The new connections
+    /// exceeding it are always refused.
+    pub max_connections_per_addr: Option<usize>,
+    /// How many leading bits of an IPv6 address identify a client, for example 64 to treat
+    /// a whole /64 as one client. IPv4 addresses are always counted one by one.
+    pub ipv6_prefix_len: u8,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_connections_per_addr: None,
+            ipv6_prefix_len: 128,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    // 连接按此计数到客户端，IPv6地址只保留前缀
+    fn client_of(&self, addr: SocketAddr) -> IpAddr {
+        match addr.ip().to_canonical() {
+            IpAddr::V6(ip) => {
+                let prefix_len = self.ipv6_prefix_len.min(128) as u32;
+                let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+            ip => ip,
+        }
+    }
+}
+
+/// Identifies a connection kept by the listener, only meaningful to the listener which
+/// offered it to the [`EvictionPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CandidateId(u64);
+
+/// A snapshot of a connection kept by the listener, which the [`EvictionPolicy`] may evict.
+#[derive(Debug, Clone, Copy)]
+pub struct EvictionCandidate {
+    pub id: CandidateId,
+    pub remote_addr: SocketAddr,
+    /// How long since the connection was accepted.
+    pub age: Duration,
+    /// How long since the last packet was received from the peer.
+    pub idle: Duration,
+    /// The payload bytes received from the peer within the last minute.
+    pub rcvd_last_minute: u64,
+}
+
+/// Decide what to do when a new connection would exceed
+/// [`ConnectionLimits::max_connections`].
+pub trait EvictionPolicy: Send + Sync {
+    /// Select the connections to evict for the new connection from `new_addr`.
+    ///
+    /// The new connection is admitted only if enough victims are selected to make room for
+    /// it, then the victims are closed gracefully. Otherwise, nothing is evicted and the new
+    /// connection is refused. The unknown ids are ignored.
+    fn select_victims(
+        &self,
+        new_addr: SocketAddr,
+        candidates: &mut dyn Iterator<Item = EvictionCandidate>,
+    ) -> Vec<CandidateId>;
+}
+
+/// The default [`EvictionPolicy`], which never evicts and refuses the new connections.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RefuseNew;
+
+impl EvictionPolicy for RefuseNew {
+    fn select_victims(
+        &self,
+        _new_addr: SocketAddr,
+        _candidates: &mut dyn Iterator<Item = EvictionCandidate>,
+    ) -> Vec<CandidateId> {
+        vec![]
+    }
+}
+
+/// An [`EvictionPolicy`] evicting the connection idle for the longest time, if it has been
+/// idle for at least `min_idle`, otherwise the new connection is refused.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EvictLongestIdle {
+    pub min_idle: Duration,
+}
+
+impl EvictionPolicy for EvictLongestIdle {
+    fn select_victims(
+        &self,
+        _new_addr: SocketAddr,
+        candidates: &mut dyn Iterator<Item = EvictionCandidate>,
+    ) -> Vec<CandidateId> {
+        candidates
+            .filter(|candidate| candidate.idle >= self.min_idle)
+            .max_by_key(|candidate| candidate.idle)
+            .map(|candidate| candidate.id)
+            .into_iter()
+            .collect()
+    }
+}
+
+struct Entry<C> {
+    remote_addr: SocketAddr,
+    client: IpAddr,
+    admitted_at: Instant,
+    // 准入后才创建连接，创建前不参与驱逐
+    conn: Option<C>,
+}
+
+/// The admission of a new connection, with the connections evicted for it, which are to be
+/// closed by the caller.
+pub(crate) struct Admission<C> {
+    pub id: CandidateId,
+    pub victims: Vec<C>,
+}
+
+/// The connections kept by a listener, counted globally and by client address.
+pub(crate) struct ConnectionTable<C> {
+    limits: ConnectionLimits,
+    policy: Arc<dyn EvictionPolicy>,
+    next_id: u64,
+    entries: HashMap<CandidateId, Entry<C>>,
+    per_client: HashMap<IpAddr, usize>,
+}
+
+impl<C> ConnectionTable<C> {
+    pub(crate) fn new(limits: ConnectionLimits, policy: Arc<dyn EvictionPolicy>) -> Self {
+        Self {
+            limits,
+            policy,
+            next_id: 0,
+            entries: HashMap::new(),
+            per_client: HashMap::new(),
+        }
+    }
+
+    /// Try to admit a new connection from `remote_addr`, None if it's refused.
+    ///
+    /// `activity` tells how active a connection is, None if it's already closing, which is
+    /// not a candidate for eviction though it still takes a slot.
+    pub(crate) fn admit(
+        &mut self,
+        remote_addr: SocketAddr,
+        now: Instant,
+        activity: impl Fn(&C) -> Option<Activity>,
+    ) -> Option<Admission<C>> {
+        let client = self.limits.client_of(remote_addr);
+        if let Some(max) = self.limits.max_connections_per_addr {
+            if self.per_client.get(&client).copied().unwrap_or(0) >= max {
+                return None;
+            }
+        }
+
+        let mut victims = vec![];
+        if let Some(max) = self.limits.max_connections {
+            let excess = (self.entries.len() + 1).saturating_sub(max);
+            if excess > 0 {
+                let mut candidates = self.entries.iter().filter_map(|(id, entry)| {
+                    let activity = activity(entry.conn.as_ref()?)?;
+                    Some(EvictionCandidate {
+                        id: *id,
+                        remote_addr: entry.remote_addr,
+                        age: now.saturating_duration_since(entry.admitted_at),
+                        idle: activity.idle,
+                        rcvd_last_minute: activity.rcvd_last_minute,
+                    })
+                });
+                let mut selected = self.policy.select_victims(remote_addr, &mut candidates);
+                selected.sort_unstable_by_key(|id| id.0);
+                selected.dedup();
+                selected.retain(|id| self.entries.get(id).is_some_and(|e| e.conn.is_some()));
+                // 腾不出足够的位置，就一个也不驱逐
+                if selected.len() < excess {
+                    return None;
+                }
+                victims = selected
+                    .into_iter()
+                    .filter_map(|id| self.remove(id)?.conn)
+                    .collect();
+            }
+        }
+
+        let id = CandidateId(self.next_id);
+        self.next_id += 1;
+        self.entries.insert(
+            id,
+            Entry {
+                remote_addr,
+                client,
+                admitted_at: now,
+                conn: None,
+            },
+        );
+        *self.per_client.entry(client).or_default() += 1;
+        Some(Admission { id, victims })
+    }
+
+    /// Attach the connection created for the admission.
+    pub(crate) fn attach(&mut self, id: CandidateId, conn: C) {
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.conn = Some(conn);
+        }
+    }
+
+    /// Release the slot of a terminated connection, return false if it's already released,
+    /// for example because it was evicted.
+    pub(crate) fn release(&mut self, id: CandidateId) -> bool {
+        self.remove(id).is_some()
+    }
+
+    fn remove(&mut self, id: CandidateId) -> Option<Entry<C>> {
+        let entry = self.entries.remove(&id)?;
+        if let Some(count) = self.per_client.get_mut(&entry.client) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_client.remove(&entry.client);
+            }
+        }
+        Some(entry)
+    }
+
+    /// The connections kept, except the ones admitted but not created yet.
+    pub(crate) fn connections(&self) -> impl Iterator<Item = &C> {
+        self.entries
+            .values()
+            .filter_map(|entry| entry.conn.as_ref())
+    }
+
+    /// The count of the connections kept.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The count of the connections kept from the client of `addr`, aggregated by
+    /// [`ConnectionLimits::ipv6_prefix_len`].
+    pub(crate) fn len_of(&self, addr: SocketAddr) -> usize {
+        let client = self.limits.client_of(addr);
+        self.per_client.get(&client).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn limits(max_connections: Option<usize>, max_per_addr: Option<usize>) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections,
+            max_connections_per_addr: max_per_addr,
+            ..Default::default()
+        }
+    }
+
+    // 测试中的连接直接就是其活跃情况
+    fn activity(conn: &Activity) -> Option<Activity> {
+        Some(*conn)
+    }
+
+    fn idle(secs: u64) -> Activity {
+        Activity {
+            idle: Duration::from_secs(secs),
+            rcvd_last_minute: 0,
+            rcvd_total: 0,
+        }
+    }
+
+    #[test]
+    fn test_per_addr_limit() {
+        let mut table = ConnectionTable::new(limits(None, Some(2)), Arc::new(RefuseNew));
+        let now = Instant::now();
+        let a = table.admit(addr("192.0.2.1:1000"), now, activity).unwrap();
+        table.admit(addr("192.0.2.1:1001"), now, activity).unwrap();
+        // 同一IP的不同端口也算同一客户端
+        assert!(table.admit(addr("192.0.2.1:1002"), now, activity).is_none());
+        assert!(table.admit(addr("192.0.2.2:1000"), now, activity).is_some());
+        assert_eq!(table.len_of(addr("192.0.2.1:0")), 2);
+
+        assert!(table.release(a.id));
+        assert!(table.admit(addr("192.0.2.1:1002"), now, activity).is_some());
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn test_ipv6_prefix() {
+        let limits = ConnectionLimits {
+            max_connections_per_addr: Some(1),
+            ipv6_prefix_len: 64,
+            ..Default::default()
+        };
+        let mut table = ConnectionTable::new(limits, Arc::new(RefuseNew));
+        let now = Instant::now();
+        table
+            .admit(addr("[2001:db8:0:1::1]:443"), now, activity)
+            .unwrap();
+        assert!(table
+            .admit(addr("[2001:db8:0:1::2]:443"), now, activity)
+            .is_none());
+        assert!(table
+            .admit(addr("[2001:db8:0:2::1]:443"), now, activity)
+            .is_some());
+        // IPv4映射的地址按IPv4计数
+        table
+            .admit(addr("[::ffff:192.0.2.1]:443"), now, activity)
+            .unwrap();
+        assert!(table.admit(addr("192.0.2.1:443"), now, activity).is_none());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let policy = Arc::new(EvictLongestIdle {
+            min_idle: Duration::from_secs(10),
+        });
+        let mut table = ConnectionTable::new(limits(Some(2), None), policy);
+        let now = Instant::now();
+        let busy = table.admit(addr("192.0.2.1:1000"), now, activity).unwrap();
+        table.attach(busy.id, idle(1));
+        let lazy = table.admit(addr("192.0.2.2:1000"), now, activity).unwrap();
+        table.attach(lazy.id, idle(5));
+
+        // 都不够空闲，拒绝新连接
+        assert!(table.admit(addr("192.0.2.3:1000"), now, activity).is_none());
+        assert_eq!(table.len(), 2);
+
+        table.attach(lazy.id, idle(30));
+        let admission = table.admit(addr("192.0.2.3:1000"), now, activity).unwrap();
+        assert_eq!(admission.victims, vec![idle(30)]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.len_of(addr("192.0.2.2:1000")), 0);
+        // 被驱逐的连接终结时不再重复释放
+        assert!(!table.release(lazy.id));
+        assert_eq!(table.len(), 2);
+
+        // 尚未创建完的连接不会被驱逐
+        assert!(table.admit(addr("192.0.2.4:1000"), now, activity).is_none());
+    }
+
+    #[test]
+    fn test_refuse_by_default() {
+        let mut table = ConnectionTable::new(limits(Some(1), None), Arc::new(RefuseNew));
+        let now = Instant::now();
+        let first = table.admit(addr("192.0.2.1:1000"), now, activity).unwrap();
+        table.attach(first.id, idle(3600));
+        assert!(table.admit(addr("192.0.2.2:1000"), now, activity).is_none());
+        assert!(table.release(first.id));
+        assert!(table.admit(addr("192.0.2.2:1000"), now, activity).is_some());
+    }
+
+    #[test]
+    fn test_accounting_under_churn() {
+        let policy = Arc::new(EvictLongestIdle::default());
+        let mut table = ConnectionTable::new(limits(Some(8), Some(3)), policy);
+        let now = Instant::now();
+        let mut alive = vec![];
+        let mut released = 0;
+        // 用一个简单的线性同余序列模拟连接的来去
+        let mut seed = 7u64;
+        for round in 0..2000 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let client = (seed >> 33) % 5;
+            if (seed >> 20) % 3 == 0 && !alive.is_empty() {
+                // 握手失败或者draining结束，连接终结
+                let id = alive.swap_remove((seed >> 40) as usize % alive.len());
+                if table.release(id) {
+                    released += 1;
+                }
+                continue;
+            }
+            let remote = SocketAddr::new(IpAddr::from([192, 0, 2, client as u8]), round);
+            if let Some(admission) = table.admit(remote, now, activity) {
+                released += admission.victims.len();
+                table.attach(admission.id, idle(round as u64));
+                alive.push(admission.id);
+            }
+
+            assert!(table.len() <= 8);
+            assert_eq!(table.per_client.values().sum::<usize>(), table.len());
+            assert!(table
+                .per_client
+                .values()
+                .all(|&count| 0 < count && count <= 3));
+        }
+        for id in alive.drain(..) {
+            if table.release(id) {
+                released += 1;
+            }
+        }
+        assert_eq!(table.len(), 0);
+        assert!(table.per_client.is_empty());
+        assert_eq!(released as u64, table.next_id);
+    }
+}
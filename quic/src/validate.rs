@@ -0,0 +1,547 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use qbase::config::CommonParameters;
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    sign::CertifiedKey,
+    InconsistentKeys,
+};
+
+/// What is wrong with the configuration, found by the validation before binding, see
+/// [`ConfigDiagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    /// A certificate or private key file can't be opened or read.
+    UnreadableFile,
+    /// A certificate file holds no certificate, or a certificate can't be parsed.
+    InvalidCertificate,
+    /// A private key file holds no private key, or the key is not supported.
+    InvalidPrivateKey,
+    /// The private key doesn't match the leaf certificate.
+    KeyMismatch,
+    /// A certificate of the chain is not issued by the next one, the leaf must come first.
+    ChainOutOfOrder,
+    /// A certificate of the chain has expired.
+    CertificateExpired,
+    /// A certificate of the chain is not valid yet.
+    CertificateNotYetValid,
+    /// A certificate of the chain expires within the warning threshold, see
+    /// [`Validation::expiry_warning`].
+    CertificateExpiringSoon,
+    /// The transport parameters are invalid, see [`CommonParameters::validate`].
+    InvalidParameters,
+    /// The max idle timeout is zero, the idle connections are never closed.
+    ZeroIdleTimeout,
+    /// Neither bidirectional nor unidirectional streams may be opened by the peer.
+    ZeroStreamLimits,
+    /// No root certificate to verify the peer's certificate with.
+    NoTrustAnchors,
+    /// The client certificates are mandatory, but the verifier names no root subject.
+    ClientAuthWithoutRoots,
+}
+
+impl DiagnosticKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::CertificateExpiringSoon
+            | Self::ZeroIdleTimeout
+            | Self::ZeroStreamLimits
+            | Self::ClientAuthWithoutRoots => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Likely a mistake, but the endpoint still works.
+    Warning,
+    /// The handshakes will fail, or the endpoint is unusable.
+    Error,
+}
+
+/// A problem of the configuration, with a machine-readable kind and a human message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+impl ConfigDiagnostic {
+    pub fn new(kind: DiagnosticKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {:?}: {}", self.severity(), self.kind, self.message)
+    }
+}
+
+/// Which diagnostics refuse to build the endpoint, the rest are only logged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Refuse nothing, log all the diagnostics.
+    Lenient,
+    /// Refuse the errors, log the warnings.
+    #[default]
+    Normal,
+    /// Refuse the warnings too.
+    Strict,
+}
+
+impl Strictness {
+    fn refuses(self, severity: Severity) -> bool {
+        match self {
+            Strictness::Lenient => false,
+            Strictness::Normal => severity == Severity::Error,
+            Strictness::Strict => true,
+        }
+    }
+}
+
+/// How the configuration of a builder is validated, when the endpoint is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validation {
+    pub strictness: Strictness,
+    /// Warn about the certificates expiring within it, 30 days by default.
+    pub expiry_warning: Duration,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self {
+            strictness: Strictness::default(),
+            expiry_warning: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+}
+
+/// Collect the diagnostics of a builder, while the certificates and keys are being loaded,
+/// and check the rest when validated.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Validator {
+    pub(crate) validation: Validation,
+    // 加载证书、私钥时就发现的问题
+    found: Vec<ConfigDiagnostic>,
+    // 有效期要以校验的时刻为准，先留着证书链
+    chains: Vec<(String, Vec<CertificateDer<'static>>)>,
+}
+
+impl Validator {
+    pub(crate) fn report(&mut self, diagnostic: ConfigDiagnostic) {
+        self.found.push(diagnostic);
+    }
+
+    /// Load the certificate chain and the private key of `label`, and check that they
+    /// match. Returns None if anything is wrong, which is reported.
+    pub(crate) fn load_certified_key(
+        &mut self,
+        label: &str,
+        cert_file: &Path,
+        key_file: &Path,
+        provider: &Arc<CryptoProvider>,
+    ) -> Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let cert_chain = self.load_cert_chain(label, cert_file);
+        let key_der = self.load_private_key(label, key_file)?;
+        let cert_chain = cert_chain?;
+
+        let signing_key = match provider.key_provider.load_private_key(key_der.clone_key()) {
+            Ok(signing_key) => signing_key,
+            Err(e) => {
+                self.report(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidPrivateKey,
+                    format!("{label}: the private key in {}: {e}", key_file.display()),
+                ));
+                return None;
+            }
+        };
+        // 无法从私钥得出公钥的，无从比对，就不报告了
+        if let Err(rustls::Error::InconsistentKeys(InconsistentKeys::KeyMismatch)) =
+            CertifiedKey::new(cert_chain.clone(), signing_key).keys_match()
+        {
+            self.report(ConfigDiagnostic::new(
+                DiagnosticKind::KeyMismatch,
+                format!(
+                    "{label}: the private key in {} doesn't match the certificate in {}",
+                    key_file.display(),
+                    cert_file.display()
+                ),
+            ));
+            return None;
+        }
+        Some((cert_chain, key_der))
+    }
+
+    /// Load the certificate chain of `label`, check that it parses and is in order.
+    fn load_cert_chain(
+        &mut self,
+        label: &str,
+        cert_file: &Path,
+    ) -> Option<Vec<CertificateDer<'static>>> {
+        let file = match File::open(cert_file) {
+            Ok(file) => file,
+            Err(e) => {
+                self.report(unreadable(label, cert_file, e));
+                return None;
+            }
+        };
+        let cert_chain =
+            match rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>() {
+                Ok(cert_chain) => cert_chain,
+                Err(e) => {
+                    self.report(unreadable(label, cert_file, e));
+                    return None;
+                }
+            };
+        if cert_chain.is_empty() {
+            self.report(ConfigDiagnostic::new(
+                DiagnosticKind::InvalidCertificate,
+                format!("{label}: no certificate in {}", cert_file.display()),
+            ));
+            return None;
+        }
+
+        let mut certs = Vec::with_capacity(cert_chain.len());
+        for (i, cert) in cert_chain.iter().enumerate() {
+            match Certificate::parse(cert) {
+                Some(cert) => certs.push(cert),
+                None => {
+                    self.report(ConfigDiagnostic::new(
+                        DiagnosticKind::InvalidCertificate,
+                        format!(
+                            "{label}: the certificate #{i} in {} can't be parsed",
+                            cert_file.display()
+                        ),
+                    ));
+                    return None;
+                }
+            }
+        }
+        // 叶子证书在前，每张证书都由其后一张签发
+        if let Some(i) = certs
+            .windows(2)
+            .position(|pair| pair[0].issuer != pair[1].subject)
+        {
+            self.report(ConfigDiagnostic::new(
+                DiagnosticKind::ChainOutOfOrder,
+                format!(
+                    "{label}: the certificate #{i} in {} is not issued by the certificate #{}",
+                    cert_file.display(),
+                    i + 1
+                ),
+            ));
+            return None;
+        }
+
+        self.chains.push((label.to_owned(), cert_chain.clone()));
+        Some(cert_chain)
+    }
+
+    fn load_private_key(&mut self, label: &str, key_file: &Path) -> Option<PrivateKeyDer<'static>> {
+        let file = match File::open(key_file) {
+            Ok(file) => file,
+            Err(e) => {
+                self.report(unreadable(label, key_file, e));
+                return None;
+            }
+        };
+        match rustls_pemfile::private_key(&mut BufReader::new(file)) {
+            Ok(Some(key_der)) => Some(key_der),
+            Ok(None) => {
+                self.report(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidPrivateKey,
+                    format!("{label}: no private key in {}", key_file.display()),
+                ));
+                None
+            }
+            Err(e) => {
+                self.report(unreadable(label, key_file, e));
+                None
+            }
+        }
+    }
+
+    /// All the diagnostics, of the loading, of the validity of the certificates at `now`, and
+    /// of the transport parameters of `label`.
+    pub(crate) fn diagnose<'a>(
+        &self,
+        parameters: impl IntoIterator<Item = (&'a str, CommonParameters)>,
+        is_server: bool,
+        now: SystemTime,
+    ) -> Vec<ConfigDiagnostic> {
+        let mut diagnostics = self.found.clone();
+        for (label, cert_chain) in &self.chains {
+            for (i, cert) in cert_chain.iter().enumerate() {
+                let Some(cert) = Certificate::parse(cert) else {
+                    continue;
+                };
+                if now > cert.not_after {
+                    diagnostics.push(ConfigDiagnostic::new(
+                        DiagnosticKind::CertificateExpired,
+                        format!("{label}: the certificate #{i} has expired"),
+                    ));
+                } else if now < cert.not_before {
+                    diagnostics.push(ConfigDiagnostic::new(
+                        DiagnosticKind::CertificateNotYetValid,
+                        format!("{label}: the certificate #{i} is not valid yet"),
+                    ));
+                } else if now + self.validation.expiry_warning > cert.not_after {
+                    let left = cert.not_after.duration_since(now).unwrap_or_default();
+                    diagnostics.push(ConfigDiagnostic::new(
+                        DiagnosticKind::CertificateExpiringSoon,
+                        format!(
+                            "{label}: the certificate #{i} expires in {} hours",
+                            left.as_secs() / 3600
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for (label, parameters) in parameters {
+            if let Err(reason) = parameters.validate() {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidParameters,
+                    format!("{label}: {reason}"),
+                ));
+            }
+            if parameters.max_idle_timeout().is_zero() {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::ZeroIdleTimeout,
+                    format!("{label}: max_idle_timeout is zero, idle connections never time out"),
+                ));
+            }
+            // 客户端通常不接受对端开的流，只检查服务端
+            if is_server
+                && parameters.initial_max_streams_bidi().into_inner() == 0
+                && parameters.initial_max_streams_uni().into_inner() == 0
+            {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::ZeroStreamLimits,
+                    format!("{label}: the clients may open no stream"),
+                ));
+            }
+        }
+        diagnostics
+    }
+
+    /// Log the diagnostics, and return them if any is refused by the strictness.
+    pub(crate) fn enforce(
+        &self,
+        diagnostics: Vec<ConfigDiagnostic>,
+    ) -> Result<(), Vec<ConfigDiagnostic>> {
+        let strictness = self.validation.strictness;
+        if diagnostics
+            .iter()
+            .any(|diagnostic| strictness.refuses(diagnostic.severity()))
+        {
+            return Err(diagnostics);
+        }
+        for diagnostic in &diagnostics {
+            log::warn!("{diagnostic}");
+        }
+        Ok(())
+    }
+}
+
+/// Log the diagnostics that refused to build the endpoint, and turn them into an error.
+pub(crate) fn rejected(diagnostics: Vec<ConfigDiagnostic>) -> std::io::Error {
+    for diagnostic in &diagnostics {
+        log::error!("{diagnostic}");
+    }
+    let diagnostics = diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid configuration:\n{}", diagnostics.join("\n")),
+    )
+}
+
+fn unreadable(label: &str, path: &Path, e: std::io::Error) -> ConfigDiagnostic {
+    ConfigDiagnostic::new(
+        DiagnosticKind::UnreadableFile,
+        format!("{label}: {}: {e}", path.display()),
+    )
+}
+
+/// The fields of a X.509 certificate that the validation needs.
+struct Certificate<'a> {
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    not_before: SystemTime,
+    not_after: SystemTime,
+}
+
+impl<'a> Certificate<'a> {
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+    // TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber, signature, issuer,
+    //                               validity, subject, ... }
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let (SEQUENCE, cert, _) = der_tlv(der)? else {
+            return None;
+        };
+        let (SEQUENCE, tbs, _) = der_tlv(cert)? else {
+            return None;
+        };
+        let (tag, _, rest) = der_tlv(tbs)?;
+        let tbs = if tag == VERSION { rest } else { tbs };
+        let (INTEGER, _serial, tbs) = der_tlv(tbs)? else {
+            return None;
+        };
+        let (SEQUENCE, _signature, tbs) = der_tlv(tbs)? else {
+            return None;
+        };
+        let (SEQUENCE, issuer, tbs) = der_tlv(tbs)? else {
+            return None;
+        };
+        let (SEQUENCE, validity, tbs) = der_tlv(tbs)? else {
+            return None;
+        };
+        let (SEQUENCE, subject, _) = der_tlv(tbs)? else {
+            return None;
+        };
+        let (tag, not_before, validity) = der_tlv(validity)?;
+        let not_before = der_time(tag, not_before)?;
+        let (tag, not_after, _) = der_tlv(validity)?;
+        let not_after = der_time(tag, not_after)?;
+        Some(Self {
+            issuer,
+            subject,
+            not_before,
+            not_after,
+        })
+    }
+}
+
+const INTEGER: u8 = 0x02;
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// Split a DER encoded TLV, returns the tag, the value and the rest.
+fn der_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+    let (len, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let (len, input) = input.split_at(n);
+        (len.iter().fold(0, |len, &b| (len << 8) | b as usize), input)
+    };
+    if input.len() < len {
+        return None;
+    }
+    let (value, rest) = input.split_at(len);
+    Some((tag, value, rest))
+}
+
+/// Parse the UTCTime `YYMMDDHHMMSSZ` or the GeneralizedTime `YYYYMMDDHHMMSSZ` of DER.
+fn der_time(tag: u8, value: &[u8]) -> Option<SystemTime> {
+    let (year, rest) = match tag {
+        UTC_TIME if value.len() == 13 => (digits(&value[..2])?, &value[2..]),
+        GENERALIZED_TIME if value.len() == 15 => (digits(&value[..4])?, &value[4..]),
+        _ => return None,
+    };
+    // RFC 5280 4.1.2.5.1：UTCTime的年份，小于50的是20xx年
+    let year = match tag {
+        UTC_TIME if year < 50 => 2000 + year,
+        UTC_TIME => 1900 + year,
+        _ => year,
+    };
+    if rest[10] != b'Z' {
+        return None;
+    }
+    let [month, day, hour, minute, second] = [0, 2, 4, 6, 8].map(|i| digits(&rest[i..i + 2]));
+    let (month, day) = (month?, day?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // 公历日期到1970-01-01的天数
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + hour? * 3600 + minute? * 60 + second?;
+    match secs >= 0 {
+        true => Some(UNIX_EPOCH + Duration::from_secs(secs as u64)),
+        false => Some(UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())),
+    }
+}
+
+fn digits(ascii: &[u8]) -> Option<i64> {
+    ascii.iter().try_fold(0, |n, &b| {
+        b.is_ascii_digit().then(|| n * 10 + (b - b'0') as i64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_der_time() {
+        let utc = |text: &str| der_time(UTC_TIME, text.as_bytes());
+        let generalized = |text: &str| der_time(GENERALIZED_TIME, text.as_bytes());
+        let secs = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(utc("700101000000Z"), secs(0));
+        assert_eq!(utc("250829083602Z"), secs(1756456562));
+        assert_eq!(generalized("20340827073852Z"), secs(2040277132));
+        // 闰年的2月29日
+        assert_eq!(generalized("20240229000000Z"), secs(1709164800));
+        assert_eq!(utc("491231235959Z"), secs(2524607999));
+        assert_eq!(
+            utc("500101000000Z"),
+            Some(UNIX_EPOCH - Duration::from_secs(631152000))
+        );
+
+        assert_eq!(utc("250829083602"), None);
+        assert_eq!(utc("2508290836+2Z"), None);
+        assert_eq!(utc("251329083602Z"), None);
+        assert_eq!(generalized("250829083602Z"), None);
+        assert_eq!(der_time(INTEGER, b"250829083602Z"), None);
+    }
+
+    #[test]
+    fn test_der_tlv() {
+        assert_eq!(
+            der_tlv(&[0x02, 0x01, 0x05, 0xff]),
+            Some((0x02, &[0x05][..], &[0xff][..]))
+        );
+        let mut long = vec![0x04, 0x82, 0x01, 0x00];
+        long.extend([0xaa; 256]);
+        let (tag, value, rest) = der_tlv(&long).unwrap();
+        assert_eq!((tag, value.len(), rest.len()), (0x04, 256, 0));
+        // 长度超出了剩余的字节
+        assert_eq!(der_tlv(&long[..100]), None);
+        assert_eq!(der_tlv(&[0x30, 0x80]), None);
+        assert_eq!(der_tlv(&[0x30]), None);
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
     io,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
 };
 
 use qbase::{
@@ -10,7 +14,7 @@ use qbase::{
 
 use super::{
     reader::{DatagramReader, RawDatagramReader},
-    writer::{DatagramWriter, RawDatagramWriter},
+    writer::{DatagramQueueCapacity, DatagramWriter, RawDatagramWriter},
 };
 use crate::{DatagramIncoming, DatagramOutgoing};
 
@@ -21,6 +25,8 @@ pub struct DatagramFlow {
     incoming: DatagramIncoming,
     /// The outgoing datagram frame, see type's doc for more details.
     outgoing: DatagramOutgoing,
+    /// Whether to accept the datagram frames received in 0-RTT packets, see [`DatagramFlow::accept_0rtt`].
+    accept_0rtt: Arc<AtomicBool>,
 }
 
 impl DatagramFlow {
@@ -33,20 +39,116 @@ impl DatagramFlow {
     #[inline]
     pub fn new(local_max_datagram_frame_size: u64) -> Self {
         let reader = RawDatagramReader::new(local_max_datagram_frame_size as _);
-        let writer = RawDatagramWriter::new();
+        let writer = RawDatagramWriter::new(DatagramQueueCapacity::default());
 
         Self {
             incoming: DatagramIncoming(Arc::new(Mutex::new(Ok(reader)))),
             outgoing: DatagramOutgoing(Arc::new(Mutex::new(Ok(writer)))),
+            accept_0rtt: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Set whether to accept the datagram frames received in 0-RTT packets, disabled by default.
+    ///
+    /// 0-RTT data can be replayed by an attacker, so is a datagram in it. Only enable it when the
+    /// application can tolerate the datagrams being delivered more than once. When disabled, the
+    /// datagram frames in 0-RTT packets are silently dropped, as datagrams are unreliable anyway.
+    #[inline]
+    pub fn accept_0rtt(&self, accept: bool) {
+        self.accept_0rtt.store(accept, Ordering::Relaxed);
+    }
+
+    /// Returns whether the datagram frames received in 0-RTT packets are accepted.
+    #[inline]
+    pub fn is_0rtt_accepted(&self) -> bool {
+        self.accept_0rtt.load(Ordering::Relaxed)
+    }
+
+    /// See [`DatagramIncoming::set_queue_limit`] for more details.
+    #[inline]
+    pub fn set_queue_limit(&self, limit: Option<usize>) {
+        self.incoming.set_queue_limit(limit)
+    }
+
+    /// See [`DatagramIncoming::dropped`] for more details.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.incoming.dropped()
+    }
+
+    /// See [`DatagramOutgoing::set_capacity`] for more details.
+    #[inline]
+    pub fn set_send_capacity(&self, capacity: DatagramQueueCapacity) {
+        self.outgoing.set_capacity(capacity)
+    }
+
     /// See [`DatagramOutgoing::try_read_datagram`] for more details.
     #[inline]
-    pub fn try_read_datagram(&self, buf: &mut [u8]) -> Option<(DatagramFrame, usize)> {
+    pub fn try_read_datagram(&self, buf: &mut [u8]) -> Option<(DatagramFrame, usize, Option<u64>)> {
         self.outgoing.try_read_datagram(buf)
     }
 
+    /// See [`DatagramOutgoing::try_read_0rtt_datagram`] for more details.
+    #[inline]
+    pub fn try_read_0rtt_datagram(
+        &self,
+        buf: &mut [u8],
+    ) -> Option<(DatagramFrame, usize, Option<u64>)> {
+        self.outgoing.try_read_0rtt_datagram(buf)
+    }
+
+    /// See [`DatagramOutgoing::try_read_datagrams`] for more details.
+    #[inline]
+    pub fn try_read_datagrams(&self, buf: &mut [u8]) -> Vec<(DatagramFrame, usize, Option<u64>)> {
+        self.outgoing.try_read_datagrams(buf)
+    }
+
+    /// See [`DatagramOutgoing::try_read_0rtt_datagrams`] for more details.
+    #[inline]
+    pub fn try_read_0rtt_datagrams(
+        &self,
+        buf: &mut [u8],
+    ) -> Vec<(DatagramFrame, usize, Option<u64>)> {
+        self.outgoing.try_read_0rtt_datagrams(buf)
+    }
+
+    /// See [`DatagramOutgoing::on_0rtt_accepted`] for more details.
+    #[inline]
+    pub fn on_0rtt_accepted(&self) {
+        self.outgoing.on_0rtt_accepted()
+    }
+
+    /// See [`DatagramOutgoing::on_0rtt_rejected`] for more details.
+    #[inline]
+    pub fn on_0rtt_rejected(&self) {
+        self.outgoing.on_0rtt_rejected()
+    }
+
+    /// See [`DatagramOutgoing::on_datagram_acked`] for more details.
+    #[inline]
+    pub fn on_datagram_acked(&self, seq: u64) {
+        self.outgoing.on_datagram_acked(seq)
+    }
+
+    /// See [`DatagramOutgoing::on_datagram_lost`] for more details.
+    #[inline]
+    pub fn on_datagram_lost(&self, seq: u64) {
+        self.outgoing.on_datagram_lost(seq)
+    }
+
+    /// See [`DatagramOutgoing::on_max_frame_size_reduced`] for more details.
+    #[inline]
+    pub fn on_max_frame_size_reduced(&self, max_datagram_frame_size: u64) -> usize {
+        self.outgoing
+            .on_max_frame_size_reduced(max_datagram_frame_size)
+    }
+
+    /// See [`DatagramOutgoing::poll_drained`] for more details.
+    #[inline]
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.outgoing.poll_drained(cx)
+    }
+
     /// Create a new **unique** instance of [`DatagramReader`].
     ///
     /// Return an error if the connection is closing or already closed, or there is already a reader exist.
@@ -1,11 +1,12 @@
 use std::{
     collections::VecDeque,
-    future::Future,
+    future::{poll_fn, Future},
     io,
+    num::NonZeroUsize,
     ops::DerefMut,
     pin::Pin,
     sync::{Arc, Mutex},
-    task::{Context, Poll, Waker},
+    task::{ready, Context, Poll, Waker},
 };
 
 use bytes::{BufMut, Bytes};
@@ -14,6 +15,11 @@ use qbase::{
     frame::{BeFrame, DatagramFrame},
 };
 
+use crate::{
+    subscription::{DatagramSubscription, Subscriptions},
+    DatagramQueueCapacity,
+};
+
 /// The [`RawDatagramReader`] struct represents a queue for receiving [`DatagramFrame`] frames from peer.
 ///
 /// The transport layer will push the received datagrams into the internal FIFO queue or set the internal queue to an error state
@@ -32,6 +38,14 @@ pub struct RawDatagramReader {
     local_max_size: usize,
     /// The internal queue for caching the received datagrams.
     queue: VecDeque<Bytes>,
+    /// The most datagrams the queue holds, unbounded if None, see [`DatagramIncoming::set_queue_limit`].
+    queue_limit: Option<usize>,
+    /// The backlog set by the application, see [`DatagramReader::set_backlog`].
+    backlog: DatagramQueueCapacity,
+    /// The total size of the datagrams in the queue.
+    queued_bytes: usize,
+    /// The number of datagrams dropped from the full queue.
+    dropped: u64,
     /// The waker for waking up the task that is waiting for the data to be read.
     ///
     /// When a datagram is received, the waker will be used to wake up the task.
@@ -40,6 +54,12 @@ pub struct RawDatagramReader {
     ///
     /// See [`DatagramReader`] for more.
     reader_exist: bool,
+    /// The subscriptions in broadcast mode, see [`DatagramReader::subscribe`].
+    subscriptions: Subscriptions,
+    /// The connection error occurred while some datagrams are still in the queue.
+    ///
+    /// The queued datagrams are still readable, the error is returned once they run out.
+    error: Option<Error>,
 }
 
 impl RawDatagramReader {
@@ -47,8 +67,64 @@ impl RawDatagramReader {
         Self {
             local_max_size,
             queue: Default::default(),
+            queue_limit: None,
+            backlog: DatagramQueueCapacity::default(),
+            queued_bytes: 0,
+            dropped: 0,
             waker: Default::default(),
             reader_exist: false,
+            subscriptions: Default::default(),
+            error: None,
+        }
+    }
+
+    /// Pop the next datagram, or return the connection error once the queue runs out.
+    ///
+    /// None if the queue is empty and the connection is fine.
+    fn try_pop(&mut self) -> Option<io::Result<Bytes>> {
+        match (self.queue.pop_front(), &self.error) {
+            (Some(bytes), _) => {
+                self.queued_bytes -= bytes.len();
+                Some(Ok(bytes))
+            }
+            (None, Some(e)) => Some(Err(io::Error::from(e.clone()))),
+            (None, None) => None,
+        }
+    }
+
+    fn poll_pop(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        match self.try_pop() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn push_back(&mut self, data: Bytes) {
+        self.queued_bytes += data.len();
+        self.queue.push_back(data);
+        self.drop_excess();
+    }
+
+    // 超出了压力等级的上限，或者应用设置的积压，丢弃最旧的数据报，见RFC 9221第5节
+    fn drop_excess(&mut self) {
+        let max_count = match (self.queue_limit, self.backlog.max_count) {
+            (Some(limit), Some(max)) => Some(limit.min(max)),
+            (limit, max) => limit.or(max),
+        };
+        while max_count.is_some_and(|max| self.queue.len() > max)
+            || self
+                .backlog
+                .max_bytes
+                .is_some_and(|max| self.queued_bytes > max)
+        {
+            let Some(bytes) = self.queue.pop_front() else {
+                break;
+            };
+            self.queued_bytes -= bytes.len();
+            self.dropped += 1;
         }
     }
 }
@@ -94,7 +170,7 @@ impl DatagramIncoming {
     /// Receives a datagram and pushes it into the internal FIFO queue for the application to read.
     ///
     /// If the size of the received datagram exceeds the maximum size set by the local transport parameters `max_datagram_frame_size`,
-    /// a connection error occurs.
+    /// a connection error occurs. So does any datagram if it's 0, where the datagrams are not supported at all.
     ///
     /// If the connection is closing or closed, the new datagram will be ignored.
     ///
@@ -105,6 +181,17 @@ impl DatagramIncoming {
         let Ok(reader) = inner else {
             return Ok(());
         };
+        if reader.error.is_some() {
+            return Ok(());
+        }
+        // 本端没有通告支持数据报，见RFC 9221第3节
+        if reader.local_max_size == 0 {
+            return Err(Error::new(
+                ErrorKind::ProtocolViolation,
+                frame.frame_type(),
+                "datagrams are not supported",
+            ));
+        }
         if (frame.encoding_size() + data.len()) > reader.local_max_size {
             return Err(Error::new(
                 ErrorKind::ProtocolViolation,
@@ -117,7 +204,11 @@ impl DatagramIncoming {
             ));
         }
 
-        reader.queue.push_back(data);
+        // 广播模式下，数据报只投递给各个订阅
+        if reader.subscriptions.deliver(&data) {
+            return Ok(());
+        }
+        reader.push_back(data);
         if let Some(waker) = reader.waker.take() {
             waker.wake();
         }
@@ -125,20 +216,52 @@ impl DatagramIncoming {
         Ok(())
     }
 
+    /// Bound the queue of the received datagrams to `limit` datagrams, or unbound it with
+    /// None, which is the default. The oldest datagrams are dropped from the full queue,
+    /// including the ones beyond a new limit right away.
+    ///
+    /// It doesn't apply to the subscriptions, which are bounded by their own capacities.
+    pub fn set_queue_limit(&self, limit: Option<usize>) {
+        if let Ok(reader) = self.0.lock().unwrap().deref_mut() {
+            reader.queue_limit = limit;
+            reader.drop_excess();
+        }
+    }
+
+    /// The number of the received datagrams dropped from the full queue, see
+    /// [`DatagramIncoming::set_queue_limit`] and [`DatagramReader::set_backlog`].
+    pub fn dropped(&self) -> u64 {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(reader) => reader.dropped,
+            Err(_) => 0,
+        }
+    }
+
     /// When a connection error occurs, the error will be set to the reader.
     ///
-    /// Any subsequent calls to [`DatagramIncoming::new_reader`], [`DatagramReader::recv`] and [`DatagramReader::recv_buf`] will return an error.
+    /// The datagrams received before are still readable by [`DatagramReader::recv`] and the like,
+    /// which return the error once the queue runs out. If the queue is empty, any subsequent calls to
+    /// [`DatagramIncoming::new_reader`], [`DatagramReader::recv`] and the like will return an error.
     ///
     /// If there is a task waiting for the data to be read, the task will be woken up and return an error immediately.
     ///
-    /// All the received datagrams will be discarded, and subsequent calls to [`DatagramIncoming::recv_datagram`] will be ignored.
+    /// Subsequent calls to [`DatagramIncoming::recv_datagram`] will be ignored.
     pub fn on_conn_error(&self, error: &Error) {
         let reader = &mut self.0.lock().unwrap();
         let inner = reader.deref_mut();
         if let Ok(reader) = inner {
+            if reader.error.is_some() {
+                return;
+            }
             if let Some(waker) = reader.waker.take() {
                 waker.wake();
             }
+            reader.subscriptions.on_conn_error(error);
+            // 队列中的数据报仍然可读，读完之后才报告连接错误
+            if !reader.queue.is_empty() {
+                reader.error = Some(error.clone());
+                return;
+            }
             *inner = Err(error.clone());
         }
     }
@@ -150,30 +273,64 @@ impl DatagramIncoming {
 /// Because the internal datagram queue is a mpsc queue, the reader (consumer) is unique, only one reader can exist at the same time.
 /// See [`DatagramIncoming::new_reader`] for more.
 ///
-/// The application can read the received datagrams from the reader by calling the [`DatagramReader::recv`] or [`DatagramReader::recv_buf`] method.
+/// The application can read the received datagrams from the reader by calling the [`DatagramReader::recv`],
+/// [`DatagramReader::recv_into`] or [`DatagramReader::recv_buf`] method, or [`DatagramReader::try_recv`] which never blocks.
 ///
-/// These methods are asynchronous, they return a future that resolves to the number of bytes read into the buffer.
+/// These methods are asynchronous, they return a future that resolves to the datagram, or the number of bytes read into the buffer.
 /// If the connection is closing or already closed, the future will yield an error.
 ///
+/// The received but unread datagrams are queued without limit by default. A reader falling behind can bound them by
+/// [`DatagramReader::set_backlog`], the oldest ones are dropped then, counted by [`DatagramReader::dropped_count`].
+///
 /// Read their docs for more.
 #[derive(Debug)]
 pub struct DatagramReader(ArcDatagramReader);
 
 impl DatagramReader {
+    /// Receives the next datagram.
+    ///
+    /// If the connection is closing or already closed, an error is returned, once the
+    /// datagrams queued before are received.
+    pub async fn recv(&mut self) -> io::Result<Bytes> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls for the next datagram, for the futures written by hand, see [`DatagramReader::recv`].
+    ///
+    /// Only the waker of the last poll is woken up when a datagram is received.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(reader) => reader.poll_pop(cx),
+            Err(e) => Poll::Ready(Err(io::Error::from(e.clone()))),
+        }
+    }
+
+    /// Receives the next datagram without blocking, an error of [`io::ErrorKind::WouldBlock`]
+    /// is returned if none is queued.
+    pub fn try_recv(&mut self) -> io::Result<Bytes> {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(reader) => reader
+                .try_pop()
+                .unwrap_or_else(|| Err(io::ErrorKind::WouldBlock.into())),
+            Err(e) => Err(io::Error::from(e.clone())),
+        }
+    }
+
     /// Reads the received data into a mutable slice.
     ///
     /// This method is asynchronous and returns a future that resolves to the number of bytes read.
     ///
     /// ``` rust, ignore
-    /// pub async fn recv(&self, buf: & mut [u8]) -> io::Result<usize>
+    /// pub async fn recv_into(&mut self, buf: &mut [u8]) -> io::Result<usize>
     /// ```
     ///
     /// The future will yield the size of bytes read from the received datagram as [`Ok`].
     ///
     /// If the buffer is not large enough to hold the received data, the received data will be truncated.
     ///
-    /// If the connection is closing or already closed, the future will yield an error as [`Err`].
-    pub fn recv<'b>(&'b mut self, buf: &'b mut [u8]) -> ReadIntoSlice<'b> {
+    /// If the connection is closing or already closed, the future will yield an error as [`Err`],
+    /// once the datagrams queued before are read.
+    pub fn recv_into<'b>(&'b mut self, buf: &'b mut [u8]) -> ReadIntoSlice<'b> {
         let reader = &mut self.0;
         ReadIntoSlice { reader, buf }
     }
@@ -183,18 +340,69 @@ impl DatagramReader {
     /// This method is asynchronous and returns a future that resolves to the number of bytes read.
     ///
     /// ``` rust, ignore
-    /// pub async fn recv(&self, buf: & mut [u8]) -> io::Result<usize>
+    /// pub async fn recv_buf<B: BufMut>(&mut self, buf: &mut B) -> io::Result<usize>
     /// ```
     ///
     /// The future will yield the size of bytes read from the received datagram as [`Ok`].
     ///
     /// If the buffer is not large enough to hold the received data, the behavior is defined by the [`bytes::BufMut::put`] implementation.
     ///
-    /// If the connection is closing or already closed, the future will yield an error as [`Err`].
+    /// If the connection is closing or already closed, the future will yield an error as [`Err`],
+    /// once the datagrams queued before are read.
     pub fn recv_buf<'b, B: BufMut>(&'b mut self, buf: &'b mut B) -> ReadInfoBuf<'b, B> {
         let reader = &mut self.0;
         ReadInfoBuf { reader, buf }
     }
+
+    /// Bound the received but unread datagrams by `backlog`, unbounded by default. The
+    /// oldest datagrams are dropped to make room for the new ones, including the ones
+    /// beyond a new backlog right away, see [`DatagramReader::dropped_count`].
+    ///
+    /// Under the pressure, the connection may bound the queue tighter, see
+    /// [`DatagramIncoming::set_queue_limit`].
+    pub fn set_backlog(&self, backlog: DatagramQueueCapacity) {
+        if let Ok(reader) = self.0.lock().unwrap().deref_mut() {
+            reader.backlog = backlog;
+            reader.drop_excess();
+        }
+    }
+
+    /// The number of the received datagrams dropped before being read, because the reader
+    /// fell behind, see [`DatagramReader::set_backlog`].
+    pub fn dropped_count(&self) -> u64 {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(reader) => reader.dropped,
+            Err(_) => 0,
+        }
+    }
+
+    /// Subscribes the received datagrams, turning the reader into broadcast mode.
+    ///
+    /// Each subscription gets every datagram received afterwards, into its own queue
+    /// holding at most `capacity` datagrams, see [`DatagramSubscription`] for more.
+    ///
+    /// While any subscription is alive, the datagrams are delivered to the subscriptions
+    /// only, not to this reader. Once all of them are dropped, this reader receives the
+    /// datagrams as usual.
+    ///
+    /// Return an error if the connection is closing or already closed, or an
+    /// [`io::ErrorKind::InvalidInput`] error if the `capacity` is 0.
+    pub fn subscribe(&self, capacity: usize) -> io::Result<DatagramSubscription> {
+        let capacity = NonZeroUsize::new(capacity).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the capacity of a subscription must be positive",
+            )
+        })?;
+        let mut reader = self.0.lock().unwrap();
+        match reader.deref_mut() {
+            Ok(reader) => match &reader.error {
+                Some(e) => Err(io::Error::from(e.clone())),
+                None => Ok(reader.subscriptions.subscribe(capacity)),
+            },
+            Err(e) => Err(io::Error::from(e.clone())),
+        }
+    }
 }
 
 /// Releases the reader when it is dropped, so that a new reader can be created.
@@ -208,7 +416,7 @@ impl Drop for DatagramReader {
     }
 }
 
-/// the [`Future`] created by [`DatagramReader::recv`], see [`DatagramReader::recv`] for more.
+/// the [`Future`] created by [`DatagramReader::recv_into`], see [`DatagramReader::recv_into`] for more.
 pub struct ReadIntoSlice<'a> {
     reader: &'a mut ArcDatagramReader,
     buf: &'a mut [u8],
@@ -222,17 +430,12 @@ impl Future for ReadIntoSlice<'_> {
 
         let mut reader = s.reader.lock().unwrap();
         match reader.deref_mut() {
-            Ok(reader) => match reader.queue.pop_front() {
-                Some(bytes) => {
-                    let len = bytes.len().min(s.buf.len());
-                    s.buf[..len].copy_from_slice(&bytes[..len]);
-                    Poll::Ready(Ok(len))
-                }
-                None => {
-                    reader.waker = Some(cx.waker().clone());
-                    Poll::Pending
-                }
-            },
+            Ok(reader) => {
+                let bytes = ready!(reader.poll_pop(cx))?;
+                let len = bytes.len().min(s.buf.len());
+                s.buf[..len].copy_from_slice(&bytes[..len]);
+                Poll::Ready(Ok(len))
+            }
             Err(e) => Poll::Ready(Err(io::Error::from(e.clone()))),
         }
     }
@@ -254,17 +457,12 @@ where
         let s = self.get_mut();
         let mut reader = s.reader.lock().unwrap();
         match reader.deref_mut() {
-            Ok(reader) => match reader.queue.pop_front() {
-                Some(bytes) => {
-                    let len = bytes.len();
-                    s.buf.put(bytes);
-                    Poll::Ready(Ok(len))
-                }
-                None => {
-                    reader.waker = Some(cx.waker().clone());
-                    Poll::Pending
-                }
-            },
+            Ok(reader) => {
+                let bytes = ready!(reader.poll_pop(cx))?;
+                let len = bytes.len();
+                s.buf.put(bytes);
+                Poll::Ready(Ok(len))
+            }
             Err(e) => Poll::Ready(Err(io::Error::from(e.clone()))),
         }
     }
@@ -283,7 +481,7 @@ mod tests {
         let recv = tokio::spawn({
             let mut reader = incoming.new_reader().unwrap();
             async move {
-                let n = reader.recv(&mut [0u8; 1024]).await.unwrap();
+                let n = reader.recv_into(&mut [0u8; 1024]).await.unwrap();
                 assert_eq!(n, 11);
             }
         });
@@ -298,6 +496,16 @@ mod tests {
         recv.await.unwrap();
     }
 
+    #[test]
+    fn test_datagram_reader_local_disabled() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(0)))));
+        let error = incoming
+            .recv_datagram(&DatagramFrame::new(None), Bytes::from_static(b""))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProtocolViolation);
+        assert_eq!(error.frame_type(), FrameType::Datagram(0));
+    }
+
     #[tokio::test]
     async fn test_datagram_reader_on_conn_error() {
         let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
@@ -312,4 +520,182 @@ mod tests {
         assert!(new_reader.is_err());
         assert_eq!(new_reader.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
     }
+
+    #[tokio::test]
+    async fn test_datagram_reader_drain_before_conn_error() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let mut reader = incoming.new_reader().unwrap();
+        let recv = |data| incoming.recv_datagram(&DatagramFrame::new(None), Bytes::from(data));
+        recv("hello").unwrap();
+        recv("world").unwrap();
+        incoming.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "protocol violation",
+        ));
+        // 出错之后收到的数据报被忽略
+        recv("ignored").unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.recv_into(&mut buf).await.unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        let mut buf = Vec::new();
+        assert_eq!(reader.recv_buf(&mut buf).await.unwrap(), 5);
+        assert_eq!(buf, b"world");
+        let error = reader.recv_into(&mut [0u8; 16]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert!(reader.subscribe(4).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_datagram_queue_limit() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let mut reader = incoming.new_reader().unwrap();
+        let recv = |i: u8| incoming.recv_datagram(&DatagramFrame::new(None), Bytes::from(vec![i]));
+        for i in 0..6 {
+            recv(i).unwrap();
+        }
+        // 收紧的上限立即生效，丢弃最旧的
+        incoming.set_queue_limit(Some(4));
+        assert_eq!(incoming.dropped(), 2);
+        recv(6).unwrap();
+        assert_eq!(incoming.dropped(), 3);
+
+        let mut buf = [0u8; 16];
+        for i in 3..7 {
+            assert_eq!(reader.recv_into(&mut buf).await.unwrap(), 1);
+            assert_eq!(buf[0], i);
+        }
+
+        incoming.set_queue_limit(None);
+        for i in 0..6 {
+            recv(i).unwrap();
+        }
+        assert_eq!(incoming.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_reader_backlog() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let mut reader = incoming.new_reader().unwrap();
+        reader.set_backlog(DatagramQueueCapacity {
+            max_count: Some(16),
+            max_bytes: None,
+        });
+        for i in 0..1000u32 {
+            incoming
+                .recv_datagram(
+                    &DatagramFrame::new(None),
+                    Bytes::from(i.to_be_bytes().to_vec()),
+                )
+                .unwrap();
+        }
+        assert_eq!(reader.dropped_count(), 984);
+
+        // 留下的是最新的16个，依序读出
+        for i in 984..1000u32 {
+            assert_eq!(
+                reader.try_recv().unwrap(),
+                Bytes::from(i.to_be_bytes().to_vec())
+            );
+        }
+        let error = reader.try_recv().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+
+        // 按字节数积压，收紧时立即丢弃最旧的
+        let recv = |data| incoming.recv_datagram(&DatagramFrame::new(None), Bytes::from(data));
+        recv("hello").unwrap();
+        recv("world").unwrap();
+        recv("!").unwrap();
+        reader.set_backlog(DatagramQueueCapacity {
+            max_count: None,
+            max_bytes: Some(6),
+        });
+        assert_eq!(reader.dropped_count(), 985);
+        assert_eq!(reader.recv().await.unwrap(), Bytes::from("world"));
+        assert_eq!(reader.recv().await.unwrap(), Bytes::from("!"));
+    }
+
+    #[tokio::test]
+    async fn test_datagram_reader_poll_recv() {
+        use std::future::poll_fn;
+
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let mut reader = incoming.new_reader().unwrap();
+        let recv = tokio::spawn(async move {
+            let first = poll_fn(|cx| reader.poll_recv(cx)).await.unwrap();
+            let error = reader.recv().await.unwrap_err();
+            (first, error)
+        });
+
+        tokio::task::yield_now().await;
+        incoming
+            .recv_datagram(&DatagramFrame::new(None), Bytes::from_static(b"hello"))
+            .unwrap();
+        tokio::task::yield_now().await;
+        incoming.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "protocol violation",
+        ));
+        let (first, error) = recv.await.unwrap();
+        assert_eq!(first, Bytes::from_static(b"hello"));
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_subscriptions() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let mut reader = incoming.new_reader().unwrap();
+        let recv = |i: u8| incoming.recv_datagram(&DatagramFrame::new(None), Bytes::from(vec![i]));
+
+        let error = reader.subscribe(0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let mut fast = reader.subscribe(16).unwrap();
+        let mut slow = reader.subscribe(4).unwrap();
+        let mut dropped = reader.subscribe(16).unwrap();
+        recv(0).unwrap();
+        assert_eq!(dropped.recv().await.unwrap(), Bytes::from(vec![0]));
+        drop(dropped);
+
+        for i in 1..10 {
+            recv(i).unwrap();
+            // 快的订阅者每个都及时收取
+            assert_eq!(fast.recv().await.unwrap(), Bytes::from(vec![i - 1]));
+        }
+        assert_eq!(fast.recv().await.unwrap(), Bytes::from(vec![9]));
+        assert_eq!(fast.missed(), 0);
+
+        // 慢的订阅者只能收到最后4个
+        assert_eq!(slow.missed(), 6);
+        for i in 6..10 {
+            assert_eq!(slow.recv().await.unwrap(), Bytes::from(vec![i]));
+        }
+
+        // 订阅都被丢弃之后，恢复单一读者
+        drop(fast);
+        drop(slow);
+        recv(10).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.recv_into(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 10);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_subscription_on_conn_error() {
+        let incoming = DatagramIncoming(Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024)))));
+        let reader = incoming.new_reader().unwrap();
+        let mut subscription = reader.subscribe(4).unwrap();
+        let recv = tokio::spawn(async move { subscription.recv().await });
+
+        tokio::task::yield_now().await;
+        incoming.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "protocol violation",
+        ));
+        let result = recv.await.unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
 }
@@ -0,0 +1,153 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    num::NonZeroUsize,
+    ops::DerefMut,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+};
+
+use bytes::Bytes;
+use qbase::error::Error;
+
+/// The bounded queue of a [`DatagramSubscription`].
+#[derive(Debug)]
+pub struct RawDatagramSubscription {
+    /// The datagrams not yet received by the subscriber, at most `capacity` of them.
+    queue: VecDeque<Bytes>,
+    capacity: usize,
+    /// The number of datagrams dropped because the subscriber lagged behind.
+    missed: u64,
+    waker: Option<Waker>,
+    /// The connection error occurred while some datagrams are still in the queue, it's
+    /// returned once they run out.
+    error: Option<Error>,
+}
+
+/// If a connection error occurs, the subscription will be set to an error state.
+pub type ArcDatagramSubscription = Arc<Mutex<Result<RawDatagramSubscription, Error>>>;
+
+/// The subscriptions of the received datagrams, each of them gets every datagram.
+///
+/// The subscriptions are held weakly, dropping a [`DatagramSubscription`] stops
+/// its deliveries, and it will be removed on the next delivery.
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions(Vec<Weak<Mutex<Result<RawDatagramSubscription, Error>>>>);
+
+impl Subscriptions {
+    pub(crate) fn subscribe(&mut self, capacity: NonZeroUsize) -> DatagramSubscription {
+        let capacity = capacity.get();
+        let subscription = Arc::new(Mutex::new(Ok(RawDatagramSubscription {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            missed: 0,
+            waker: None,
+            error: None,
+        })));
+        self.0.push(Arc::downgrade(&subscription));
+        DatagramSubscription(subscription)
+    }
+
+    /// Deliver the datagram to every subscription, which only clones the [`Bytes`].
+    ///
+    /// A full subscription drops its oldest datagram to make room for the new one.
+    ///
+    /// Return false if there is no alive subscription, in which case the datagram should
+    /// be delivered to the [`DatagramReader`](crate::DatagramReader) as usual.
+    pub(crate) fn deliver(&mut self, data: &Bytes) -> bool {
+        self.0.retain(|subscription| {
+            let Some(subscription) = subscription.upgrade() else {
+                return false;
+            };
+            let mut guard = subscription.lock().unwrap();
+            if let Ok(raw) = guard.deref_mut() {
+                if raw.queue.len() == raw.capacity {
+                    raw.queue.pop_front();
+                    raw.missed += 1;
+                }
+                raw.queue.push_back(data.clone());
+                if let Some(waker) = raw.waker.take() {
+                    waker.wake();
+                }
+            }
+            true
+        });
+        !self.0.is_empty()
+    }
+
+    pub(crate) fn on_conn_error(&mut self, error: &Error) {
+        for subscription in self.0.drain(..) {
+            let Some(subscription) = subscription.upgrade() else {
+                continue;
+            };
+            let mut guard = subscription.lock().unwrap();
+            if let Ok(raw) = guard.deref_mut() {
+                if let Some(waker) = raw.waker.take() {
+                    waker.wake();
+                }
+                // 队列中的数据报仍然可读，读完之后才报告连接错误
+                if !raw.queue.is_empty() {
+                    raw.error = Some(error.clone());
+                    continue;
+                }
+                *guard = Err(error.clone());
+            }
+        }
+    }
+}
+
+/// A subscription of the received datagrams, created by [`DatagramReader::subscribe`].
+///
+/// Each subscription gets every received datagram into its own bounded queue. A slow
+/// subscriber drops the oldest datagrams when its queue is full, the number of the
+/// dropped datagrams can be learned by [`DatagramSubscription::missed`].
+///
+/// [`DatagramReader::subscribe`]: crate::DatagramReader::subscribe
+#[derive(Debug)]
+pub struct DatagramSubscription(ArcDatagramSubscription);
+
+impl DatagramSubscription {
+    /// Receives the next datagram.
+    ///
+    /// ``` rust, ignore
+    /// pub async fn recv(&mut self) -> io::Result<Bytes>
+    /// ```
+    ///
+    /// If the connection is closing or already closed, the future will yield an error as [`Err`],
+    /// once the datagrams queued before are received.
+    pub fn recv(&mut self) -> RecvSubscription<'_> {
+        RecvSubscription(&self.0)
+    }
+
+    /// The number of datagrams missed so far, because this subscriber lagged behind.
+    pub fn missed(&self) -> u64 {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(raw) => raw.missed,
+            Err(_) => 0,
+        }
+    }
+}
+
+/// the [`Future`] created by [`DatagramSubscription::recv`], see [`DatagramSubscription::recv`] for more.
+pub struct RecvSubscription<'a>(&'a ArcDatagramSubscription);
+
+impl Future for RecvSubscription<'_> {
+    type Output = io::Result<Bytes>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut subscription = self.0.lock().unwrap();
+        match subscription.deref_mut() {
+            Ok(raw) => match (raw.queue.pop_front(), &raw.error) {
+                (Some(bytes), _) => Poll::Ready(Ok(bytes)),
+                (None, Some(e)) => Poll::Ready(Err(io::Error::from(e.clone()))),
+                (None, None) => {
+                    raw.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            },
+            Err(e) => Poll::Ready(Err(io::Error::from(e.clone()))),
+        }
+    }
+}
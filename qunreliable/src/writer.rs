@@ -1,16 +1,22 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
+    future::Future,
     io,
     ops::DerefMut,
+    pin::Pin,
     sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 
 use bytes::Bytes;
 use qbase::{
     error::Error,
     frame::{io::WriteDataFrame, BeFrame, DatagramFrame},
+    util::Wakers,
     varint::VarInt,
 };
+use tokio::{sync::oneshot, time::Instant};
 
 /// The [`RawDatagramWriter`] struct represents a queue for sending [`DatagramFrame`].
 ///
@@ -23,21 +29,239 @@ use qbase::{
 /// [`DatagramWriter`] is created by [`DatagramOutgoing::new_writer`], and they share the same [`RawDatagramWriter`](wrapped in [`ArcDatagramWriter`]).
 #[derive(Debug)]
 pub struct RawDatagramWriter {
-    /// The queue for storing the datagram frame to send.
-    queue: VecDeque<Bytes>,
+    /// The queue for storing the datagram frame to send, with their sequence ids, and the
+    /// deadlines to send them if any, see [`DatagramWriter::send_bytes_with_ttl`].
+    queue: VecDeque<(u64, Bytes, Option<Instant>)>,
+    /// The sequence id of the next datagram pushed into the queue, see [`DatagramWriter::send_bytes_tracked`].
+    next_seq: u64,
+    /// How many queued datagrams to retain when a connection error occurs, 0 drops them all,
+    /// see [`DatagramWriter::retain_unsent_on_close`].
+    retain_unsent: usize,
+    /// The wakers of the tasks waiting for the queue to be drained, see [`DatagramOutgoing::poll_drained`].
+    drain_wakers: Wakers,
+    /// The number of datagrams sent in 0-RTT packets, whose fate is unknown until the
+    /// server accepts or rejects the 0-RTT data.
+    unconfirmed_0rtt: usize,
+    /// The number of datagrams sent in 0-RTT packets that were rejected by the server.
+    rejected_0rtt: usize,
+    /// The lowered `max_datagram_frame_size` of the peer, caps the [`DatagramWriter`]s created
+    /// with the value remembered from the previous connection, see [`DatagramOutgoing::on_max_frame_size_reduced`].
+    reduced_max_frame_size: Option<usize>,
+    /// The capacity of the queue, see [`DatagramOutgoing::set_capacity`].
+    capacity: DatagramQueueCapacity,
+    /// The total size of the datagrams in the queue.
+    queued_bytes: usize,
+    /// The wakers of the tasks waiting for the space of the full queue, see [`DatagramWriter::send_bytes_wait`].
+    space_wakers: Vec<Waker>,
+    /// The number of datagrams dropped from the full queue by the writers with
+    /// [`QueueOverflow::DropOldest`].
+    dropped_oldest: u64,
+    /// The number of datagrams dropped for having expired in the queue.
+    expired: u64,
+    /// The datagrams sent by [`DatagramWriter::send_bytes_with_handle`] by their sequence ids,
+    /// from being queued until they're acknowledged or lost. Dropping one resolves its
+    /// [`DatagramHandle`] as lost.
+    tracked: HashMap<u64, oneshot::Sender<DatagramOutcome>>,
 }
 
 impl RawDatagramWriter {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(capacity: DatagramQueueCapacity) -> Self {
         Self {
             queue: Default::default(),
+            next_seq: 0,
+            retain_unsent: 0,
+            drain_wakers: Wakers::default(),
+            unconfirmed_0rtt: 0,
+            rejected_0rtt: 0,
+            reduced_max_frame_size: None,
+            capacity,
+            queued_bytes: 0,
+            space_wakers: Vec::new(),
+            dropped_oldest: 0,
+            expired: 0,
+            tracked: HashMap::new(),
+        }
+    }
+
+    /// Whether a datagram of `len` bytes can be pushed into the queue without exceeding the capacity.
+    fn has_space_for(&self, len: usize) -> bool {
+        self.capacity
+            .max_count
+            .map_or(true, |max| self.queue.len() < max)
+            && self
+                .capacity
+                .max_bytes
+                .map_or(true, |max| self.queued_bytes + len <= max)
+    }
+
+    fn push_back(&mut self, data: Bytes, ttl: Option<Duration>) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queued_bytes += data.len();
+        let deadline = ttl.map(|ttl| Instant::now() + ttl);
+        self.queue.push_back((seq, data, deadline));
+        seq
+    }
+
+    fn pop_front(&mut self) -> Option<(u64, Bytes)> {
+        let (seq, data, _) = self.queue.pop_front()?;
+        self.queued_bytes -= data.len();
+        Some((seq, data))
+    }
+
+    // 从未发出就被丢弃的数据报，被跟踪的话，判为丢失
+    fn drop_front(&mut self) -> bool {
+        let Some((seq, _)) = self.pop_front() else {
+            return false;
+        };
+        self.tracked.remove(&seq);
+        true
+    }
+
+    // 队首过期的数据报不再发送，直接丢弃；过期时间不一的，排在后面的轮到队首时再丢弃
+    fn drop_expired_front(&mut self, now: Instant) {
+        let mut expired = 0;
+        while self
+            .queue
+            .front()
+            .is_some_and(|(_, _, deadline)| deadline.is_some_and(|deadline| deadline < now))
+        {
+            self.drop_front();
+            expired += 1;
+        }
+        self.on_expired(expired);
+    }
+
+    // 队列满了，丢弃所有过期的数据报，腾出空间
+    fn drop_expired(&mut self, now: Instant) {
+        let queued = self.queue.len();
+        let tracked = &mut self.tracked;
+        self.queue.retain(|(seq, _, deadline)| {
+            let retained = deadline.map_or(true, |deadline| deadline >= now);
+            if !retained {
+                tracked.remove(seq);
+            }
+            retained
+        });
+        self.queued_bytes = self.queue.iter().map(|(_, data, _)| data.len()).sum();
+        self.on_expired(queued - self.queue.len());
+    }
+
+    fn on_expired(&mut self, expired: usize) {
+        if expired == 0 {
+            return;
+        }
+        self.expired += expired as u64;
+        self.wake_senders();
+        if self.queue.is_empty() {
+            self.drain_wakers.wake_all();
+        }
+    }
+
+    /// Encode the datagram at the front of the queue into the buffer, see
+    /// [`DatagramOutgoing::try_read_datagram`] for the encoding.
+    fn encode_front(&mut self, mut buf: &mut [u8]) -> Option<(DatagramFrame, usize, Option<u64>)> {
+        let (_, datagram, _) = self.queue.front()?;
+        let max_encoding_size = buf.len().saturating_sub(datagram.len());
+        if max_encoding_size == 0 {
+            return None;
+        }
+
+        let (seq, datagram) = self.pop_front()?;
+        // 未被跟踪的数据报，不必查表
+        let tracked = (!self.tracked.is_empty() && self.tracked.contains_key(&seq)).then_some(seq);
+        let frame_without_len = DatagramFrame::new(None);
+        let frame_with_len = DatagramFrame::new(Some(VarInt::try_from(datagram.len()).unwrap()));
+        match max_encoding_size {
+            // Encode length
+            n if n >= frame_with_len.encoding_size() => {
+                buf.put_data_frame(&frame_with_len, &datagram);
+                let written = frame_with_len.encoding_size() + datagram.len();
+                Some((frame_with_len, written, tracked))
+            }
+            // Do not encode length, may need padding
+            n => {
+                debug_assert_eq!(frame_without_len.encoding_size(), 1);
+                let (padding, rest) = buf.split_at_mut(n - frame_without_len.encoding_size());
+                padding.fill(0);
+                buf = rest;
+                buf.put_data_frame(&frame_without_len, &datagram);
+                let written = n + datagram.len();
+                Some((frame_without_len, written, tracked))
+            }
+        }
+    }
+
+    // 数据报出队后，唤醒等待空间的写者；队列空了，唤醒等待发完的任务
+    fn on_popped(&mut self) {
+        self.wake_senders();
+        if self.queue.is_empty() {
+            self.drain_wakers.wake_all();
+        }
+    }
+
+    /// The queue got some space, wake up all the waiting tasks to try again.
+    fn wake_senders(&mut self) {
+        for waker in self.space_wakers.drain(..) {
+            waker.wake();
         }
     }
 }
 
+/// The capacity of the queue of the datagrams to send, unbounded by default.
+///
+/// A fast producer on a slow path would otherwise queue the datagrams without limit, see
+/// [`QueueOverflow`] for what happens when the queue is full.
+///
+/// It also bounds the backlog of the received datagrams, see [`DatagramReader::set_backlog`].
+///
+/// [`DatagramReader::set_backlog`]: crate::DatagramReader::set_backlog
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DatagramQueueCapacity {
+    /// The most datagrams the queue holds, unbounded if None.
+    pub max_count: Option<usize>,
+    /// The most bytes of the datagrams the queue holds, unbounded if None.
+    pub max_bytes: Option<usize>,
+}
+
+/// What a [`DatagramWriter`] does when the queue is full, see [`DatagramQueueCapacity`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOverflow {
+    /// [`DatagramWriter::send_bytes`] returns an error of [`io::ErrorKind::WouldBlock`],
+    /// the application can wait for the space by [`DatagramWriter::send_bytes_wait`].
+    #[default]
+    Reject,
+    /// The oldest datagrams are dropped from the queue to make room for the new one, for the
+    /// real-time media whose stale datagrams are useless.
+    DropOldest,
+}
+
+/// The error state of the writer after a connection error, see [`DatagramOutgoing::on_conn_error`].
+#[derive(Debug)]
+pub struct ClosedDatagramWriter {
+    error: Error,
+    /// The datagrams never encoded into a packet when the connection error occurred, with
+    /// their sequence ids, kept only if [`DatagramWriter::retain_unsent_on_close`] is set.
+    unsent: VecDeque<(u64, Bytes)>,
+}
+
+impl ClosedDatagramWriter {
+    fn io_error(&self) -> io::Error {
+        io::Error::from(self.error.clone())
+    }
+}
+
+/// The error of sending to a peer whose `max_datagram_frame_size` is 0.
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the peer doesn't support datagrams",
+    )
+}
+
 /// If a connection error occurs, the internal writer will be set to an error state.
 /// See [`DatagramOutgoing::on_conn_error`] for more details.
-pub type ArcDatagramWriter = Arc<Mutex<Result<RawDatagramWriter, Error>>>;
+pub type ArcDatagramWriter = Arc<Mutex<Result<RawDatagramWriter, ClosedDatagramWriter>>>;
 
 #[derive(Debug, Clone)]
 pub struct DatagramOutgoing(pub(crate) ArcDatagramWriter);
@@ -47,18 +271,34 @@ impl DatagramOutgoing {
     ///
     /// This method takes the remote transport parameters `max_datagram_frame_size`.
     ///
-    /// Returns an error when the connection is closing or already closed.
+    /// Returns an error when the connection is closing or already closed, or an error of kind
+    /// [`io::ErrorKind::Unsupported`] when the `max_datagram_frame_size` is 0, that is the peer
+    /// doesn't support the datagrams, either by advertising 0 or by not advertising it at all.
     ///
     /// Be different from [`DatagramReader`], there can be multiple [`DatagramWriter`]s at the same time.
     ///
     /// [`DatagramReader`]: crate::reader::DatagramReader
     pub fn new_writer(&self, max_datagram_frame_size: u64) -> io::Result<DatagramWriter> {
         match self.0.lock().unwrap().deref_mut() {
+            Ok(..) if max_datagram_frame_size == 0 => Err(unsupported()),
             Ok(..) => Ok(DatagramWriter {
                 writer: self.0.clone(),
                 max_datagram_frame_size: max_datagram_frame_size as _,
+                overflow: QueueOverflow::default(),
+                ttl: None,
             }),
-            Err(e) => Err(io::Error::from(e.clone())),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Bound the queue of the datagrams to send by `capacity`, unbounded by default.
+    ///
+    /// The datagrams already queued are kept even if they exceed the new capacity, the new
+    /// ones have to wait for them to be sent.
+    pub fn set_capacity(&self, capacity: DatagramQueueCapacity) {
+        if let Ok(writer) = self.0.lock().unwrap().deref_mut() {
+            writer.capacity = capacity;
+            writer.wake_senders();
         }
     }
 
@@ -90,36 +330,167 @@ impl DatagramOutgoing {
     /// If the buffer is not enough to encode the length, it will encode the [`DatagramFrame`] without the data's length (frame type `0x30`).
     /// Because no frame can be put after the datagram frame without length, this method will put padding frames before to fill the buffer.
     /// In this case, the buffer will be filled.
-    pub fn try_read_datagram(&self, mut buf: &mut [u8]) -> Option<(DatagramFrame, usize)> {
+    ///
+    /// To pack several small datagrams into one packet, see [`DatagramOutgoing::try_read_datagrams`].
+    ///
+    /// The last item is the sequence id of the datagram if it was sent by
+    /// [`DatagramWriter::send_bytes_with_handle`], the packet carrying it should report it by
+    /// [`DatagramOutgoing::on_datagram_acked`] or [`DatagramOutgoing::on_datagram_lost`].
+    pub fn try_read_datagram(&self, buf: &mut [u8]) -> Option<(DatagramFrame, usize, Option<u64>)> {
         let mut guard = self.0.lock().unwrap();
         let writer = guard.as_mut().ok()?;
-        let datagram = writer.queue.front()?;
+        writer.drop_expired_front(Instant::now());
+        let read = writer.encode_front(buf)?;
+        writer.on_popped();
+        Some(read)
+    }
 
-        let available = buf.len();
+    /// Attempts to encode as many datagram frames from the queue as fit into the buffer, one after another, in the order
+    /// they were sent, so that a packet isn't wasted on a small datagram.
+    ///
+    /// Each frame is encoded the same as [`DatagramOutgoing::try_read_datagram`] does. They carry the length (frame type
+    /// `0x31`), except the last one which is encoded without it (frame type `0x30`) only if the length no longer fits.
+    /// It stops at the first datagram that doesn't fit, the ones behind it wait for the next packet.
+    ///
+    /// Returns the frames with the number of bytes each of them takes and their sequence ids if tracked, in the order
+    /// written from the start of the buffer. Empty if nothing is written.
+    pub fn try_read_datagrams(&self, buf: &mut [u8]) -> Vec<(DatagramFrame, usize, Option<u64>)> {
+        let mut guard = self.0.lock().unwrap();
+        let Ok(writer) = guard.as_mut() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let mut read = Vec::new();
+        let mut written = 0;
+        loop {
+            writer.drop_expired_front(now);
+            let Some((frame, n, tracked)) = writer.encode_front(&mut buf[written..]) else {
+                break;
+            };
+            written += n;
+            read.push((frame, n, tracked));
+        }
+        if !read.is_empty() {
+            writer.on_popped();
+        }
+        read
+    }
 
-        let max_encoding_size = available.saturating_sub(datagram.len());
-        if max_encoding_size == 0 {
-            return None;
+    /// Same as [`DatagramOutgoing::try_read_datagram`], but the datagram is encoded into a 0-RTT packet.
+    ///
+    /// The datagram is counted as unconfirmed, until [`DatagramOutgoing::on_0rtt_accepted`] or
+    /// [`DatagramOutgoing::on_0rtt_rejected`] is called.
+    pub fn try_read_0rtt_datagram(
+        &self,
+        buf: &mut [u8],
+    ) -> Option<(DatagramFrame, usize, Option<u64>)> {
+        let read = self.try_read_datagram(buf)?;
+        if let Ok(writer) = self.0.lock().unwrap().deref_mut() {
+            writer.unconfirmed_0rtt += 1;
         }
+        Some(read)
+    }
 
-        let datagram = writer.queue.pop_front()?;
-        let frame_without_len = DatagramFrame::new(None);
-        let frame_with_len = DatagramFrame::new(Some(VarInt::try_from(datagram.len()).unwrap()));
-        match max_encoding_size {
-            // Encode length
-            n if n >= frame_with_len.encoding_size() => {
-                buf.put_data_frame(&frame_with_len, &datagram);
-                let written = frame_with_len.encoding_size() + datagram.len();
-                Some((frame_with_len, written))
+    /// Same as [`DatagramOutgoing::try_read_datagrams`], but the datagrams are encoded into a 0-RTT packet, and
+    /// counted as unconfirmed as [`DatagramOutgoing::try_read_0rtt_datagram`] does.
+    pub fn try_read_0rtt_datagrams(
+        &self,
+        buf: &mut [u8],
+    ) -> Vec<(DatagramFrame, usize, Option<u64>)> {
+        let read = self.try_read_datagrams(buf);
+        if let Ok(writer) = self.0.lock().unwrap().deref_mut() {
+            writer.unconfirmed_0rtt += read.len();
+        }
+        read
+    }
+
+    /// The server accepted the 0-RTT data, the datagrams sent in 0-RTT packets are no longer unconfirmed.
+    pub fn on_0rtt_accepted(&self) {
+        if let Ok(writer) = self.0.lock().unwrap().deref_mut() {
+            writer.unconfirmed_0rtt = 0;
+        }
+    }
+
+    /// The server rejected the 0-RTT data, the datagrams sent in 0-RTT packets are lost.
+    ///
+    /// Datagrams are unreliable, so they will never be sent again in 1-RTT packets.
+    pub fn on_0rtt_rejected(&self) {
+        if let Ok(writer) = self.0.lock().unwrap().deref_mut() {
+            writer.rejected_0rtt += std::mem::take(&mut writer.unconfirmed_0rtt);
+        }
+    }
+
+    /// The peer lowered the `max_datagram_frame_size` below the value remembered from the previous
+    /// connection, which is legal only if it rejected the 0-RTT data.
+    ///
+    /// The writers created with the remembered value are capped by the new one, and the queued
+    /// datagrams that no longer fit are dropped, as if they were lost. Returns the number of the
+    /// dropped datagrams.
+    pub fn on_max_frame_size_reduced(&self, max_datagram_frame_size: u64) -> usize {
+        let mut guard = self.0.lock().unwrap();
+        let Ok(writer) = guard.deref_mut() else {
+            return 0;
+        };
+        let max = max_datagram_frame_size as usize;
+        writer.reduced_max_frame_size = Some(max);
+        let queued = writer.queue.len();
+        let tracked = &mut writer.tracked;
+        writer.queue.retain(|(seq, datagram, _)| {
+            let retained = 1 + datagram.len() <= max;
+            if !retained {
+                tracked.remove(seq);
             }
-            // Do not encode length, may need padding
-            n => {
-                debug_assert_eq!(frame_without_len.encoding_size(), 1);
-                buf = &mut buf[n - frame_without_len.encoding_size()..];
-                buf.put_data_frame(&frame_without_len, &datagram);
-                let written = n + datagram.len();
-                Some((frame_without_len, written))
+            retained
+        });
+        writer.queued_bytes = writer
+            .queue
+            .iter()
+            .map(|(_, datagram, _)| datagram.len())
+            .sum();
+        if writer.queue.len() < queued {
+            writer.wake_senders();
+        }
+        if writer.queue.is_empty() {
+            writer.drain_wakers.wake_all();
+        }
+        queued - writer.queue.len()
+    }
+
+    /// The packet carrying the tracked datagram `seq` is acknowledged, resolves its
+    /// [`DatagramHandle`] as [`DatagramOutcome::Acked`].
+    pub fn on_datagram_acked(&self, seq: u64) {
+        self.resolve(seq, DatagramOutcome::Acked);
+    }
+
+    /// The packet carrying the tracked datagram `seq` is declared lost, resolves its
+    /// [`DatagramHandle`] as [`DatagramOutcome::Lost`]. The datagram is never sent again.
+    pub fn on_datagram_lost(&self, seq: u64) {
+        self.resolve(seq, DatagramOutcome::Lost);
+    }
+
+    fn resolve(&self, seq: u64, outcome: DatagramOutcome) {
+        let sender = match self.0.lock().unwrap().deref_mut() {
+            Ok(writer) => writer.tracked.remove(&seq),
+            Err(_) => None,
+        };
+        if let Some(sender) = sender {
+            // 应用已不再关心结果
+            _ = sender.send(outcome);
+        }
+    }
+
+    /// Polls whether all the datagrams in the internal queue have been sent.
+    ///
+    /// Datagrams are never retransmitted, so once the queue is empty there is nothing
+    /// left to send. Returns an error if the connection is closing or already closed.
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.0.lock().unwrap().deref_mut() {
+            Ok(writer) if writer.queue.is_empty() => Poll::Ready(Ok(())),
+            Ok(writer) => {
+                writer.drain_wakers.register(cx.waker());
+                Poll::Pending
             }
+            Err(closed) => Poll::Ready(Err(closed.io_error())),
         }
     }
 
@@ -127,26 +498,81 @@ impl DatagramOutgoing {
     ///
     /// Any subsequent calls to [`DatagramWriter::send`] or [`DatagramWriter::send_bytes`] will return an error.
     ///
-    /// All datagrams in the internal queue will be dropped.
+    /// The datagrams in the internal queue are never sent. They will be dropped, unless
+    /// [`DatagramWriter::retain_unsent_on_close`] is set, then the first ones of them are
+    /// retained for the application to send again over a new connection, see
+    /// [`DatagramWriter::drain_unsent`].
     pub fn on_conn_error(&self, error: &Error) {
         let writer = &mut self.0.lock().unwrap();
-        if writer.is_ok() {
-            **writer = Err(error.clone());
+        if let Ok(raw) = writer.deref_mut() {
+            raw.drain_wakers.wake_all();
+            raw.wake_senders();
+            let unsent = std::mem::take(&mut raw.queue)
+                .into_iter()
+                .take(raw.retain_unsent)
+                .map(|(seq, data, _)| (seq, data))
+                .collect();
+            **writer = Err(ClosedDatagramWriter {
+                error: error.clone(),
+                unsent,
+            });
         }
     }
 }
 
+/// What happened to a datagram sent by [`DatagramWriter::send_bytes_with_handle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatagramOutcome {
+    /// The packet carrying the datagram was acknowledged by the peer.
+    Acked,
+    /// The packet carrying the datagram was declared lost, or the datagram was never sent.
+    Lost,
+}
+
+/// Resolves to the [`DatagramOutcome`] of a datagram, see [`DatagramWriter::send_bytes_with_handle`].
+///
+/// Dropping the handle doesn't affect the datagram.
+#[derive(Debug)]
+pub struct DatagramHandle {
+    seq: u64,
+    outcome: oneshot::Receiver<DatagramOutcome>,
+}
+
+impl DatagramHandle {
+    /// The sequence id of the datagram, see [`DatagramWriter::send_bytes_tracked`].
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Future for DatagramHandle {
+    type Output = DatagramOutcome;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 发送端被丢弃，说明数据报没有发出，或连接已关闭
+        Pin::new(&mut self.outcome)
+            .poll(cx)
+            .map(|outcome| outcome.unwrap_or(DatagramOutcome::Lost))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DatagramWriter {
     writer: ArcDatagramWriter,
     /// The maximum size of the datagram frame that can be sent to the peer.
     ///
     /// The value is set by the remote peer, and the transport layer will use this value to limit the size of the datagram frame.
+    /// Before the handshake completes, it is the value remembered from the previous connection, which allows
+    /// the datagrams to be sent in 0-RTT packets.
     ///
     /// If the size of the datagram frame exceeds this value, the transport layer will return an error.
     ///
     /// See [RFC](https://www.rfc-editor.org/rfc/rfc9221.html#name-transport-parameter) for more details.
     max_datagram_frame_size: usize,
+    /// What to do when the queue is full, see [`DatagramWriter::with_overflow`].
+    overflow: QueueOverflow,
+    /// The default time to live of the datagrams in the queue, see [`DatagramWriter::with_ttl`].
+    ttl: Option<Duration>,
 }
 
 impl DatagramWriter {
@@ -156,21 +582,206 @@ impl DatagramWriter {
     /// The transport layer will read the datagram from the queue and send it to the peer.
     ///
     /// Returns [`Ok`] when the data is successfully pushed into the internal queue.
-    /// Returns [`Err`] when the connection is closing or already closed.
+    /// Returns [`Err`] when the connection is closing or already closed, or the queue is full
+    /// and the writer rejects the datagram, see [`QueueOverflow`].
     pub fn send_bytes(&self, data: Bytes) -> io::Result<()> {
+        self.send_bytes_tracked(data).map(|_| ())
+    }
+
+    /// Same as [`DatagramWriter::send_bytes`], but returns the sequence id of the datagram.
+    ///
+    /// The sequence ids increase by 1 from 0 for all the writers of a connection. The ids of
+    /// the datagrams never sent due to a connection error can be retrieved by
+    /// [`DatagramWriter::unsent_after_close`].
+    ///
+    /// The datagrams dropped from the full queue by [`QueueOverflow::DropOldest`] are never
+    /// sent, their sequence ids are skipped.
+    pub fn send_bytes_tracked(&self, data: Bytes) -> io::Result<u64> {
+        self.push(data, self.ttl, None)
+    }
+
+    /// Same as [`DatagramWriter::send_bytes`], but returns a [`DatagramHandle`] that resolves
+    /// once the packet carrying the datagram is acknowledged or declared lost by the peer.
+    ///
+    /// The datagram is never sent again even if lost, it's up to the application to do so.
+    /// The datagrams never sent, being expired, dropped from the full queue or left in it when
+    /// the connection closes, are lost as well.
+    ///
+    /// Only the datagrams sent by this method are tracked, the others cost nothing more.
+    pub fn send_bytes_with_handle(&self, data: Bytes) -> io::Result<DatagramHandle> {
+        let (sender, outcome) = oneshot::channel();
+        let seq = self.push(data, self.ttl, Some(sender))?;
+        Ok(DatagramHandle { seq, outcome })
+    }
+
+    /// Same as [`DatagramWriter::send_bytes`], but the datagram is dropped rather than sent
+    /// once it has been in the queue for longer than `ttl`, overriding the default one of the
+    /// writer, see [`DatagramWriter::with_ttl`].
+    ///
+    /// The expired datagrams no longer count against the capacity of the queue, see
+    /// [`DatagramWriter::expired_count`].
+    pub fn send_bytes_with_ttl(&self, data: Bytes, ttl: Duration) -> io::Result<()> {
+        self.push(data, Some(ttl), None).map(|_| ())
+    }
+
+    fn push(
+        &self,
+        data: Bytes,
+        ttl: Option<Duration>,
+        handle: Option<oneshot::Sender<DatagramOutcome>>,
+    ) -> io::Result<u64> {
         match self.writer.lock().unwrap().deref_mut() {
             Ok(writer) => {
-                // Only consider the smallest encoding method: 1 byte
-                if (1 + data.len()) > self.max_datagram_frame_size {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "datagram frame size exceeds the limit",
-                    ));
+                self.check_size(writer, &data)?;
+                if !writer.has_space_for(data.len()) {
+                    writer.drop_expired(Instant::now());
                 }
-                writer.queue.push_back(data.clone());
-                Ok(())
+                if !writer.has_space_for(data.len()) {
+                    match self.overflow {
+                        QueueOverflow::Reject => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::WouldBlock,
+                                "the queue of datagrams is full",
+                            ))
+                        }
+                        QueueOverflow::DropOldest => {
+                            while !writer.has_space_for(data.len()) {
+                                if !writer.drop_front() {
+                                    break;
+                                }
+                                writer.dropped_oldest += 1;
+                            }
+                        }
+                    }
+                }
+                let seq = writer.push_back(data, ttl);
+                if let Some(handle) = handle {
+                    writer.tracked.insert(seq, handle);
+                }
+                Ok(seq)
             }
-            Err(e) => Err(io::Error::from(e.clone())),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Same as [`DatagramWriter::send_bytes`], but waits for the space when the queue is full,
+    /// until the transport layer sends some of the queued datagrams, whatever the
+    /// [`QueueOverflow`] of the writer is.
+    ///
+    /// Returns [`Err`] when the connection is closing or already closed.
+    pub async fn send_bytes_wait(&self, data: Bytes) -> io::Result<()> {
+        let mut data = Some(data);
+        std::future::poll_fn(|cx| {
+            let mut guard = self.writer.lock().unwrap();
+            let writer = match guard.deref_mut() {
+                Ok(writer) => writer,
+                Err(closed) => return Poll::Ready(Err(closed.io_error())),
+            };
+            let bytes = data.as_ref().expect("polled after completion");
+            if let Err(e) = self.check_size(writer, bytes) {
+                return Poll::Ready(Err(e));
+            }
+            if !writer.has_space_for(bytes.len()) {
+                writer.drop_expired(Instant::now());
+            }
+            if !writer.has_space_for(bytes.len()) {
+                writer.space_wakers.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            writer.push_back(data.take().unwrap(), self.ttl);
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Check the datagram against the limit of the peer, and the capacity of the queue that
+    /// it would never fit in.
+    fn check_size(&self, writer: &RawDatagramWriter, data: &Bytes) -> io::Result<()> {
+        let max_datagram_frame_size = writer
+            .reduced_max_frame_size
+            .map_or(self.max_datagram_frame_size, |max| {
+                max.min(self.max_datagram_frame_size)
+            });
+        // 对方禁用了数据报，见DatagramOutgoing::on_max_frame_size_reduced
+        if max_datagram_frame_size == 0 {
+            return Err(unsupported());
+        }
+        // Only consider the smallest encoding method: 1 byte
+        if (1 + data.len()) > max_datagram_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram frame size exceeds the limit",
+            ));
+        }
+        if writer
+            .capacity
+            .max_bytes
+            .is_some_and(|max| data.len() > max)
+            || writer.capacity.max_count == Some(0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram exceeds the capacity of the queue",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set what to do when the queue is full, [`QueueOverflow::Reject`] by default.
+    ///
+    /// It applies to this writer and the clones made from it afterwards, the other writers of
+    /// the connection keep their own.
+    pub fn with_overflow(mut self, overflow: QueueOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Set the default time to live of the datagrams sent by this writer, they're dropped
+    /// rather than sent once in the queue for longer than it. None by default, which never
+    /// expires.
+    ///
+    /// It applies to this writer and the clones made from it afterwards, the same as
+    /// [`DatagramWriter::with_overflow`].
+    pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Returns the number of the datagrams in the queue, sent by all the writers of the connection.
+    /// Returns an error when the connection is closing or already closed.
+    pub fn queued_count(&self) -> io::Result<usize> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.queue.len()),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Returns the total size of the datagrams in the queue, sent by all the writers of the connection.
+    /// Returns an error when the connection is closing or already closed.
+    pub fn queued_bytes(&self) -> io::Result<usize> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.queued_bytes),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Returns the number of datagrams dropped for having expired in the queue, see
+    /// [`DatagramWriter::send_bytes_with_ttl`].
+    /// Returns an error when the connection is closing or already closed.
+    pub fn expired_count(&self) -> io::Result<u64> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.expired),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Returns the number of datagrams dropped from the full queue by the writers with
+    /// [`QueueOverflow::DropOldest`].
+    /// Returns an error when the connection is closing or already closed.
+    pub fn dropped_oldest(&self) -> io::Result<u64> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.dropped_oldest),
+            Err(closed) => Err(closed.io_error()),
         }
     }
 
@@ -185,12 +796,79 @@ impl DatagramWriter {
         self.send_bytes(data.to_vec().into())
     }
 
+    /// Retain at most `max` queued datagrams when a connection error occurs, rather than
+    /// dropping them, so that the application can send them again over a new connection,
+    /// see [`DatagramWriter::drain_unsent`]. 0 drops them all, which is the default.
+    ///
+    /// It applies to all the writers of the connection.
+    /// Returns an error when the connection is closing or already closed.
+    pub fn retain_unsent_on_close(&self, max: usize) -> io::Result<()> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => {
+                writer.retain_unsent = max;
+                Ok(())
+            }
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Returns the sequence ids of the datagrams never encoded into a packet due to the
+    /// connection error, see [`DatagramWriter::send_bytes_tracked`]. Only the ones retained
+    /// by [`DatagramWriter::retain_unsent_on_close`] and not drained yet are returned.
+    ///
+    /// Returns an error when the connection is still open.
+    pub fn unsent_after_close(&self) -> io::Result<Vec<u64>> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "connection is still open",
+            )),
+            Err(closed) => Ok(closed.unsent.iter().map(|(seq, _)| *seq).collect()),
+        }
+    }
+
+    /// Takes the payloads of the datagrams never encoded into a packet due to the connection
+    /// error, in the order they were sent, see [`DatagramWriter::unsent_after_close`].
+    ///
+    /// Returns nothing when the connection is still open, or they're drained already.
+    pub fn drain_unsent(&self) -> Vec<Bytes> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(_) => vec![],
+            Err(closed) => closed.unsent.drain(..).map(|(_, data)| data).collect(),
+        }
+    }
+
+    /// Returns the number of datagrams sent in 0-RTT packets, whose fate is still unknown.
+    ///
+    /// If the server rejects the 0-RTT data, these datagrams are lost and never sent again,
+    /// see [`DatagramWriter::rejected_0rtt`].
+    /// Returns an error when the connection is closing or already closed.
+    pub fn unconfirmed_0rtt(&self) -> io::Result<usize> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.unconfirmed_0rtt),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
+    /// Returns the number of datagrams sent in 0-RTT packets that were rejected by the server.
+    /// Returns an error when the connection is closing or already closed.
+    pub fn rejected_0rtt(&self) -> io::Result<usize> {
+        match self.writer.lock().unwrap().deref_mut() {
+            Ok(writer) => Ok(writer.rejected_0rtt),
+            Err(closed) => Err(closed.io_error()),
+        }
+    }
+
     /// Returns the maximum size of the datagram frame that can be sent to the peer.
     /// Returns an error when the connection is closing or already closed.
     pub fn max_datagram_frame_size(&self) -> io::Result<usize> {
         match self.writer.lock().unwrap().deref_mut() {
-            Ok(..) => Ok(self.max_datagram_frame_size),
-            Err(e) => Err(io::Error::from(e.clone())),
+            Ok(writer) => Ok(writer
+                .reduced_max_frame_size
+                .map_or(self.max_datagram_frame_size, |max| {
+                    max.min(self.max_datagram_frame_size)
+                })),
+            Err(closed) => Err(closed.io_error()),
         }
     }
 }
@@ -206,7 +884,7 @@ mod tests {
 
     #[test]
     fn test_datagram_writer_with_length() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
         let writer = outgoing.new_writer(1024).unwrap();
 
@@ -217,7 +895,7 @@ mod tests {
         let expected_frame = DatagramFrame::new(Some(VarInt::try_from(data.len()).unwrap()));
         assert_eq!(
             outgoing.try_read_datagram(&mut buffer),
-            Some((expected_frame, 1 + 1 + data.len()))
+            Some((expected_frame, 1 + 1 + data.len(), None))
         );
 
         let mut expected_buffer = [0; 1024];
@@ -230,7 +908,7 @@ mod tests {
 
     #[test]
     fn test_datagram_writer_without_length() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
         let writer = outgoing.new_writer(1024).unwrap();
 
@@ -240,7 +918,7 @@ mod tests {
         let mut buffer = [0; 1024];
         assert_eq!(
             outgoing.try_read_datagram(&mut buffer[0..12]),
-            Some((DatagramFrame::new(None), 12))
+            Some((DatagramFrame::new(None), 12, None))
         );
 
         let mut expected_buffer = [0; 1024];
@@ -251,9 +929,66 @@ mod tests {
         assert_eq!(buffer, expected_buffer);
     }
 
+    #[test]
+    fn test_datagram_writer_coalesce() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+
+        let data = [[b'a'; 100], [b'b'; 100], [b'c'; 100]];
+        for datagram in &data {
+            writer.send(datagram).unwrap();
+        }
+        // 长度100的varint占2字节，三个都带长度，写进同一个包
+        let frame = DatagramFrame::new(Some(VarInt::from_u32(100)));
+        let mut buffer = [0xffu8; 400];
+        assert_eq!(
+            outgoing.try_read_datagrams(&mut buffer),
+            vec![(frame, 103, None), (frame, 103, None), (frame, 103, None)]
+        );
+        let mut expected_buffer = [0xffu8; 400];
+        {
+            let mut expected_buffer = &mut expected_buffer[..];
+            for datagram in &data {
+                expected_buffer.put_data_frame(&frame, datagram);
+            }
+        }
+        assert_eq!(buffer, expected_buffer);
+        assert!(outgoing.try_read_datagrams(&mut buffer).is_empty());
+
+        // 最后一个放不下长度，才省去长度，并在前面填充
+        for datagram in &data {
+            writer.send(datagram).unwrap();
+        }
+        let mut buffer = [0xffu8; 308];
+        assert_eq!(
+            outgoing.try_read_datagrams(&mut buffer),
+            vec![
+                (frame, 103, None),
+                (frame, 103, None),
+                (DatagramFrame::new(None), 102, None)
+            ]
+        );
+        let mut expected_buffer = [0xffu8; 308];
+        {
+            let mut expected_buffer = &mut expected_buffer[..];
+            expected_buffer.put_data_frame(&frame, &data[0]);
+            expected_buffer.put_data_frame(&frame, &data[1]);
+            expected_buffer.put_frame(&PaddingFrame);
+            expected_buffer.put_data_frame(&DatagramFrame::new(None), &data[2]);
+        }
+        assert_eq!(buffer, expected_buffer);
+
+        // 队首的放不下，就不越过它发后面的
+        writer.send(&[b'd'; 300]).unwrap();
+        writer.send(&[b'e'; 10]).unwrap();
+        assert!(outgoing.try_read_datagrams(&mut buffer[..200]).is_empty());
+        assert_eq!(outgoing.try_read_0rtt_datagrams(&mut buffer).len(), 1);
+    }
+
     #[test]
     fn test_datagram_writer_unwritten() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
         let writer = outgoing.new_writer(1024).unwrap();
 
@@ -269,7 +1004,7 @@ mod tests {
 
     #[test]
     fn test_datagram_writer_padding_first() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
         let writer = outgoing.new_writer(1024).unwrap();
 
@@ -280,7 +1015,7 @@ mod tests {
         let mut buffer = [0; 1024];
         assert_eq!(
             outgoing.try_read_datagram(&mut buffer[..data.len() + 2]),
-            Some((DatagramFrame::new(None), data.len() + 2))
+            Some((DatagramFrame::new(None), data.len() + 2, None))
         );
 
         let mut expected_buffer = [0; 1024];
@@ -295,18 +1030,27 @@ mod tests {
 
     #[test]
     fn test_datagram_writer_exceeds_limit() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
-        let writer = outgoing.new_writer(0).unwrap();
+        let writer = outgoing.new_writer(8).unwrap();
 
         let data = Bytes::from_static(b"hello world");
         let result = writer.send_bytes(data);
-        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_datagram_writer_remote_disabled() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        // 对方通告了0，或者根本没有通告
+        let error = outgoing.new_writer(0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
     }
 
     #[test]
     fn test_datagram_writer_on_conn_error() {
-        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new())));
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
         let outgoing = DatagramOutgoing(writer);
         let writer = outgoing.new_writer(1024).unwrap();
 
@@ -318,4 +1062,402 @@ mod tests {
         let writer_guard = writer.writer.lock().unwrap();
         assert!(writer_guard.as_ref().is_err());
     }
+
+    #[test]
+    fn test_datagram_writer_0rtt_rejected() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+        writer.send(b"world").unwrap();
+
+        let mut buffer = [0; 1024];
+        assert!(outgoing.try_read_0rtt_datagram(&mut buffer).is_some());
+        assert!(outgoing.try_read_0rtt_datagram(&mut buffer).is_some());
+        assert_eq!(writer.unconfirmed_0rtt().unwrap(), 2);
+
+        outgoing.on_0rtt_rejected();
+        assert_eq!(writer.unconfirmed_0rtt().unwrap(), 0);
+        assert_eq!(writer.rejected_0rtt().unwrap(), 2);
+        // never replayed in 1-RTT packets
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn test_datagram_writer_0rtt_accepted() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+
+        let mut buffer = [0; 1024];
+        assert!(outgoing.try_read_0rtt_datagram(&mut buffer).is_some());
+        assert!(outgoing.try_read_0rtt_datagram(&mut buffer).is_none());
+        assert_eq!(writer.unconfirmed_0rtt().unwrap(), 1);
+
+        outgoing.on_0rtt_accepted();
+        outgoing.on_0rtt_rejected();
+        assert_eq!(writer.unconfirmed_0rtt().unwrap(), 0);
+        assert_eq!(writer.rejected_0rtt().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_datagram_writer_max_frame_size_reduced() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        // 沿用上次连接记住的限制
+        let writer = outgoing.new_writer(1200).unwrap();
+        writer.send(&[0; 100]).unwrap();
+        writer.send(&[0; 10]).unwrap();
+
+        assert_eq!(outgoing.on_max_frame_size_reduced(64), 1);
+        assert_eq!(writer.max_datagram_frame_size().unwrap(), 64);
+        assert!(writer.send(&[0; 100]).is_err());
+
+        let mut buffer = [0; 1024];
+        let (_, written, _) = outgoing.try_read_datagram(&mut buffer).unwrap();
+        assert_eq!(written, 1 + 1 + 10);
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+
+        // 服务端禁用了数据报
+        assert_eq!(outgoing.on_max_frame_size_reduced(0), 0);
+        let error = writer.send(&[]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_datagram_writer_unsent_on_close() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.retain_unsent_on_close(16).unwrap();
+        assert!(writer.unsent_after_close().is_err());
+
+        for i in 0..15u8 {
+            assert_eq!(
+                writer.send_bytes_tracked(Bytes::from(vec![i])).unwrap(),
+                i as u64
+            );
+        }
+        let mut buffer = [0; 1024];
+        for _ in 0..5 {
+            assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        }
+
+        outgoing.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "test",
+        ));
+        assert!(writer.send(b"late").is_err());
+        assert_eq!(
+            writer.unsent_after_close().unwrap(),
+            (5..15).collect::<Vec<_>>()
+        );
+        let unsent = writer.drain_unsent();
+        assert_eq!(
+            unsent,
+            (5..15u8).map(|i| Bytes::from(vec![i])).collect::<Vec<_>>()
+        );
+        assert!(writer.drain_unsent().is_empty());
+        assert!(writer.unsent_after_close().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_datagram_writer_unsent_bounded() {
+        let error = Error::new(ErrorKind::ProtocolViolation, FrameType::Datagram(0), "test");
+        // 默认不保留
+        let outgoing = DatagramOutgoing(Arc::new(Mutex::new(Ok(RawDatagramWriter::new(
+            Default::default(),
+        )))));
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+        outgoing.on_conn_error(&error);
+        assert!(writer.unsent_after_close().unwrap().is_empty());
+
+        let outgoing = DatagramOutgoing(Arc::new(Mutex::new(Ok(RawDatagramWriter::new(
+            Default::default(),
+        )))));
+        let writer = outgoing.new_writer(1024).unwrap();
+        for _ in 0..4 {
+            writer.send(b"hello").unwrap();
+        }
+        writer.retain_unsent_on_close(2).unwrap();
+        outgoing.on_conn_error(&error);
+        assert_eq!(writer.unsent_after_close().unwrap(), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_outgoing_drained() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+        writer.send(b"world").unwrap();
+
+        // 两个任务同时等待，都要被唤醒
+        let drained = [(); 2].map(|_| {
+            let outgoing = outgoing.clone();
+            tokio::spawn(async move { std::future::poll_fn(|cx| outgoing.poll_drained(cx)).await })
+        });
+
+        let mut buffer = [0; 1024];
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        tokio::task::yield_now().await;
+        assert!(drained.iter().all(|task| !task.is_finished()));
+
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        for task in drained {
+            task.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_datagram_writer_bounded() {
+        let capacity = DatagramQueueCapacity {
+            max_count: Some(3),
+            max_bytes: Some(12),
+        };
+        let outgoing = DatagramOutgoing(Arc::new(Mutex::new(Ok(RawDatagramWriter::new(capacity)))));
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+        writer.send(b"world").unwrap();
+        assert_eq!(writer.queued_count().unwrap(), 2);
+        assert_eq!(writer.queued_bytes().unwrap(), 10);
+
+        // 字节数超出容量
+        let error = writer.send(b"!!!").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        writer.send(b"!!").unwrap();
+        // 个数超出容量
+        assert_eq!(
+            writer.send(&[]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        // 永远放不下的数据报，等待也无济于事
+        assert_eq!(
+            writer.send(&[0; 13]).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let waiting = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.send_bytes_wait(Bytes::from_static(b"later!")).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        // 传输层发出一个数据报，空出的字节数还不够
+        let mut buffer = [0; 1024];
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        assert!(outgoing.try_read_datagram(&mut buffer).is_some());
+        waiting.await.unwrap().unwrap();
+        assert_eq!(writer.queued_count().unwrap(), 2);
+        assert_eq!(writer.queued_bytes().unwrap(), 8);
+
+        let mut sent = vec![];
+        while let Some((_, written, _)) = outgoing.try_read_datagram(&mut buffer) {
+            sent.push(buffer[2..written].to_vec());
+        }
+        assert_eq!(sent, [b"!!".to_vec(), b"later!".to_vec()]);
+        assert_eq!(writer.queued_bytes().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_writer_wait_closed() {
+        let capacity = DatagramQueueCapacity {
+            max_count: Some(1),
+            max_bytes: None,
+        };
+        let outgoing = DatagramOutgoing(Arc::new(Mutex::new(Ok(RawDatagramWriter::new(capacity)))));
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"hello").unwrap();
+        let waiting = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.send_bytes_wait(Bytes::from_static(b"world")).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        outgoing.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "test",
+        ));
+        assert!(waiting.await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_datagram_writer_drop_oldest() {
+        let capacity = DatagramQueueCapacity {
+            max_count: Some(3),
+            max_bytes: Some(8),
+        };
+        let outgoing = DatagramOutgoing(Arc::new(Mutex::new(Ok(RawDatagramWriter::new(capacity)))));
+        let writer = outgoing
+            .new_writer(1024)
+            .unwrap()
+            .with_overflow(QueueOverflow::DropOldest);
+        let seqs = [&b"a"[..], b"b", b"c", b"d", b"efghijk"]
+            .into_iter()
+            .map(|data| writer.send_bytes_tracked(Bytes::from(data)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, [0, 1, 2, 3, 4]);
+        // d挤掉了a，efghijk又挤掉了b和c
+        assert_eq!(writer.dropped_oldest().unwrap(), 3);
+        assert_eq!(writer.queued_count().unwrap(), 2);
+        assert_eq!(writer.queued_bytes().unwrap(), 8);
+
+        let mut buffer = [0; 1024];
+        let mut sent = vec![];
+        while let Some((_, written, _)) = outgoing.try_read_datagram(&mut buffer) {
+            sent.push(buffer[2..written].to_vec());
+        }
+        assert_eq!(sent, [b"d".to_vec(), b"efghijk".to_vec()]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_datagram_writer_ttl() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        writer.send(b"first").unwrap();
+        writer
+            .send_bytes_with_ttl(Bytes::from_static(b"stale"), Duration::from_millis(50))
+            .unwrap();
+        let snapshots = writer.clone().with_ttl(Some(Duration::from_millis(200)));
+        snapshots.send(b"third").unwrap();
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+        let mut buffer = [0; 1024];
+        let mut sent = vec![];
+        while let Some((_, written, _)) = outgoing.try_read_datagram(&mut buffer) {
+            sent.push(buffer[2..written].to_vec());
+        }
+        assert_eq!(sent, [b"first".to_vec(), b"third".to_vec()]);
+        assert_eq!(writer.expired_count().unwrap(), 1);
+
+        // 过期的数据报不再占用队列的容量
+        outgoing.set_capacity(DatagramQueueCapacity {
+            max_count: Some(1),
+            max_bytes: None,
+        });
+        snapshots.send(b"snapshot 1").unwrap();
+        assert_eq!(
+            snapshots.send(b"snapshot 2").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        tokio::time::advance(Duration::from_millis(300)).await;
+        snapshots.send(b"snapshot 2").unwrap();
+        assert_eq!(writer.expired_count().unwrap(), 2);
+        assert_eq!(writer.queued_count().unwrap(), 1);
+        let (_, written, _) = outgoing.try_read_datagram(&mut buffer).unwrap();
+        assert_eq!(&buffer[2..written], b"snapshot 2");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_datagram_writer_handle() {
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+
+        // 有损的管道：每个数据报单独成包，第2个包丢失，其余被确认
+        let handles = (0..4u8)
+            .map(|i| {
+                writer
+                    .send_bytes_with_handle(Bytes::from(vec![i; 10]))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        writer.send(b"untracked").unwrap();
+        let mut buffer = [0; 12];
+        let mut packets = vec![];
+        while let Some((_, _, tracked)) = outgoing.try_read_datagram(&mut buffer) {
+            packets.push(tracked);
+        }
+        let seqs = handles.iter().map(|h| Some(h.seq())).collect::<Vec<_>>();
+        assert_eq!(packets[..4], seqs[..]);
+        assert_eq!(packets[4], None);
+        for (pn, tracked) in packets.into_iter().enumerate() {
+            match tracked {
+                Some(seq) if pn == 1 => outgoing.on_datagram_lost(seq),
+                Some(seq) => outgoing.on_datagram_acked(seq),
+                None => {}
+            }
+        }
+        let mut outcomes = vec![];
+        for handle in handles {
+            outcomes.push(handle.await);
+        }
+        use DatagramOutcome::*;
+        assert_eq!(outcomes, [Acked, Lost, Acked, Acked]);
+
+        // 过期而从未发出的，同样判为丢失
+        let expired = writer
+            .clone()
+            .with_ttl(Some(Duration::from_millis(10)))
+            .send_bytes_with_handle(Bytes::from_static(b"stale"))
+            .unwrap();
+        tokio::time::advance(Duration::from_millis(20)).await;
+        assert!(outgoing.try_read_datagram(&mut buffer).is_none());
+        assert_eq!(expired.await, Lost);
+
+        // 连接关闭时，排队中和在途的都判为丢失
+        let in_flight = writer
+            .send_bytes_with_handle(Bytes::from_static(b"flight"))
+            .unwrap();
+        let queued = writer
+            .send_bytes_with_handle(Bytes::from_static(b"queued"))
+            .unwrap();
+        let mut buffer = [0; 1024];
+        let read = outgoing.try_read_datagrams(&mut buffer[..10]);
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].2, Some(in_flight.seq()));
+        outgoing.on_conn_error(&Error::new(
+            ErrorKind::ProtocolViolation,
+            FrameType::Datagram(0),
+            "test",
+        ));
+        assert_eq!(queued.await, Lost);
+        assert_eq!(in_flight.await, Lost);
+    }
+
+    #[tokio::test]
+    async fn test_empty_datagram_round_trip() {
+        use qbase::{
+            frame::{io::be_frame, Frame},
+            packet::r#type::{short::OneRtt, Type},
+        };
+
+        use crate::reader::{DatagramIncoming, RawDatagramReader};
+
+        let writer = Arc::new(Mutex::new(Ok(RawDatagramWriter::new(Default::default()))));
+        let outgoing = DatagramOutgoing(writer);
+        let writer = outgoing.new_writer(1024).unwrap();
+        let reader = Arc::new(Mutex::new(Ok(RawDatagramReader::new(1024))));
+        let incoming = DatagramIncoming(reader);
+        let mut reader = incoming.new_reader().unwrap();
+
+        // 空间充足时携带长度，只剩1字节时不携带长度，都能编码空的数据报
+        for capacity in [1024, 1] {
+            writer.send(&[]).unwrap();
+            let mut buffer = [0; 1024];
+            let (_, written, _) = outgoing.try_read_datagram(&mut buffer[..capacity]).unwrap();
+
+            let packet = Bytes::copy_from_slice(&buffer[..written]);
+            let one_rtt = Type::Short(OneRtt::from(0x00));
+            let (consumed, frame, _) = be_frame(&packet, one_rtt).unwrap();
+            assert_eq!(consumed, written);
+            let Frame::Datagram(frame, data) = frame else {
+                panic!("expect datagram frame");
+            };
+            assert!(data.is_empty());
+            incoming.recv_datagram(&frame, data).unwrap();
+
+            let mut buf = [0xff; 8];
+            assert_eq!(reader.recv(&mut buf).await.unwrap(), 0);
+        }
+    }
 }